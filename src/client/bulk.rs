@@ -0,0 +1,86 @@
+use chrono::{DateTime, Utc};
+
+use crate::Asset;
+
+use super::state::ConnectionState;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BulkOperation {
+    PurgeMessages,
+    RemoveUser,
+    ReparseAssets,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BulkProgress {
+    pub operation: BulkOperation,
+    pub connection_id: String,
+    pub completed: usize,
+    pub total: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BulkReport {
+    pub connections: usize,
+    pub channels: usize,
+    pub affected: usize,
+}
+
+/// What a bulk operation did to one connection, kept so journals can replay it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BulkChange {
+    PurgeBefore(DateTime<Utc>),
+    /// Holds the user id as the connection normalizes it.
+    RemoveUser(String),
+    ReparseAssets,
+}
+
+impl BulkChange {
+    /// Applies the change, returning the channels touched and the items affected.
+    pub fn apply(&self, state: &mut ConnectionState) -> (usize, usize) {
+        match self {
+            BulkChange::PurgeBefore(before) => {
+                let mut channels = 0;
+                let mut purged = 0;
+                for channel in state.channels.values_mut() {
+                    let removed = channel.purge_before(*before);
+                    if removed > 0 {
+                        channels += 1;
+                        purged += removed;
+                    }
+                }
+                (channels, purged)
+            }
+            BulkChange::RemoveUser(user_id) => {
+                let mut channels = 0;
+                let mut removed = usize::from(state.global_users.remove(user_id).is_some());
+                state.presence.remove(user_id);
+                state.profile_history.remove(user_id);
+                for channel in state.channels.values_mut() {
+                    channel.typing.remove(user_id);
+                    channel.read_markers.remove(user_id);
+                    if channel.remove_user(user_id).is_some() {
+                        channels += 1;
+                        removed += 1;
+                    }
+                }
+                (channels, removed)
+            }
+            BulkChange::ReparseAssets => {
+                let global: Vec<Asset> = state.global_assets.values().cloned().collect();
+                let mut channels = 0;
+                let mut updated = 0;
+                for channel in state.channels.values_mut() {
+                    let mut assets = global.clone();
+                    assets.extend(channel.assets.values().cloned());
+                    let changed = channel.reparse_assets(&assets);
+                    if changed > 0 {
+                        channels += 1;
+                        updated += changed;
+                    }
+                }
+                (channels, updated)
+            }
+        }
+    }
+}
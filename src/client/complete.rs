@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    connection::{AssetEvent, ChannelEvent, ConnectionEvent, Scope, UserEvent},
+    utils::assets::{get_id, pattern_literals},
+    Asset, Profile,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum CompletionKind {
+    Asset,
+    User,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Completion {
+    pub kind: CompletionKind,
+    pub id: String,
+    pub text: String,
+    pub channel_id: Option<String>,
+}
+
+type CompletionKey = (CompletionKind, String, Option<String>);
+
+#[derive(Clone, Debug, Default)]
+struct TrieNode {
+    children: BTreeMap<char, TrieNode>,
+    entries: Vec<Completion>,
+}
+
+impl TrieNode {
+    fn remove(&mut self, mut chars: std::str::Chars, key: &CompletionKey) -> bool {
+        match chars.next() {
+            Some(c) => {
+                if let Some(child) = self.children.get_mut(&c) {
+                    if child.remove(chars, key) {
+                        self.children.remove(&c);
+                    }
+                }
+            }
+            None => self
+                .entries
+                .retain(|e| (e.kind, e.id.clone(), e.channel_id.clone()) != *key),
+        }
+        self.children.is_empty() && self.entries.is_empty()
+    }
+
+    fn collect(&self, out: &mut Vec<Completion>) {
+        out.extend(self.entries.iter().cloned());
+        for child in self.children.values() {
+            child.collect(out);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CompletionIndex {
+    root: TrieNode,
+    keys: HashMap<CompletionKey, Vec<String>>,
+}
+
+impl CompletionIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, completion: Completion) {
+        let key = (
+            completion.kind,
+            completion.id.clone(),
+            completion.channel_id.clone(),
+        );
+        self.remove(key.0, &key.1, key.2.as_deref());
+        self.add(key, completion);
+    }
+
+    fn add(&mut self, key: CompletionKey, completion: Completion) {
+        let folded = completion.text.to_lowercase();
+        let mut node = &mut self.root;
+        for c in folded.chars() {
+            node = node.children.entry(c).or_default();
+        }
+        node.entries.push(completion);
+        self.keys.entry(key).or_default().push(folded);
+    }
+
+    pub fn remove(&mut self, kind: CompletionKind, id: &str, channel_id: Option<&str>) {
+        let key = (kind, id.to_string(), channel_id.map(str::to_string));
+        for folded in self.keys.remove(&key).unwrap_or_default() {
+            self.root.remove(folded.chars(), &key);
+        }
+    }
+
+    pub fn retain(&mut self, keep: impl Fn(&CompletionKind, Option<&str>) -> bool) {
+        let dropped: Vec<CompletionKey> = self
+            .keys
+            .keys()
+            .filter(|(kind, _, channel_id)| !keep(kind, channel_id.as_deref()))
+            .cloned()
+            .collect();
+        for (kind, id, channel_id) in dropped {
+            self.remove(kind, &id, channel_id.as_deref());
+        }
+    }
+
+    pub fn complete(&self, prefix: &str, channel_id: Option<&str>) -> Vec<Completion> {
+        let mut node = &self.root;
+        for c in prefix.to_lowercase().chars() {
+            match node.children.get(&c) {
+                Some(child) => node = child,
+                None => return Vec::new(),
+            }
+        }
+
+        let mut found = Vec::new();
+        node.collect(&mut found);
+        found.retain(|c| c.channel_id.is_none() || c.channel_id.as_deref() == channel_id);
+        found.sort_by(|a, b| a.text.cmp(&b.text));
+        found.dedup_by(|a, b| a.kind == b.kind && a.text == b.text);
+        found
+    }
+
+    pub(crate) fn index_event(&mut self, event: &ConnectionEvent) {
+        match event {
+            ConnectionEvent::User { event } => match event {
+                UserEvent::New { scope, user } => {
+                    if let Some(id) = &user.id {
+                        self.index_user(id, scope, user);
+                    }
+                }
+                UserEvent::Update {
+                    scope,
+                    user_id,
+                    new_user,
+                } => self.index_user(user_id, scope, new_user),
+                UserEvent::Remove { scope, user_id } => {
+                    self.remove(CompletionKind::User, user_id, scope.channel_id())
+                }
+                UserEvent::ClearList { scope } => self
+                    .retain(|kind, cid| *kind != CompletionKind::User || cid != scope.channel_id()),
+                UserEvent::Batch { events } => {
+                    for event in events {
+                        self.index_event(&ConnectionEvent::User {
+                            event: event.clone(),
+                        });
+                    }
+                }
+                _ => {}
+            },
+            ConnectionEvent::Asset { event } => match event {
+                AssetEvent::New { scope, asset } => {
+                    if let Some(id) = get_id(asset) {
+                        self.index_asset(&id, scope, asset);
+                    }
+                }
+                AssetEvent::Update {
+                    scope,
+                    asset_id,
+                    new_asset,
+                } => self.index_asset(asset_id, scope, new_asset),
+                AssetEvent::Remove { scope, asset_id } => {
+                    self.remove(CompletionKind::Asset, asset_id, scope.channel_id())
+                }
+                AssetEvent::ClearList { scope } => self.retain(|kind, cid| {
+                    *kind != CompletionKind::Asset || cid != scope.channel_id()
+                }),
+            },
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Remove { channel_id },
+            } => self.retain(|_, cid| cid != Some(channel_id.as_str())),
+            ConnectionEvent::Channel {
+                event: ChannelEvent::ClearList,
+            } => self.retain(|_, cid| cid.is_none()),
+            _ => {}
+        }
+    }
+
+    fn index_user(&mut self, user_id: &str, scope: &Scope, user: &Profile) {
+        match &user.username {
+            Some(username) => self.insert(Completion {
+                kind: CompletionKind::User,
+                id: user_id.to_string(),
+                text: username.clone(),
+                channel_id: scope.channel_id().map(str::to_string),
+            }),
+            None => self.remove(CompletionKind::User, user_id, scope.channel_id()),
+        }
+    }
+
+    fn index_asset(&mut self, asset_id: &str, scope: &Scope, asset: &Asset) {
+        let pattern = match asset {
+            Asset::Emote { pattern, .. }
+            | Asset::Sticker { pattern, .. }
+            | Asset::Audio { pattern, .. }
+            | Asset::Command { pattern, .. } => pattern,
+        };
+        let channel_id = scope.channel_id().map(str::to_string);
+        self.remove(CompletionKind::Asset, asset_id, channel_id.as_deref());
+        // Patterns are regexes, so each spelling they accept gets its own entry.
+        for text in pattern_literals(pattern) {
+            let completion = Completion {
+                kind: CompletionKind::Asset,
+                id: asset_id.to_string(),
+                text,
+                channel_id: channel_id.clone(),
+            };
+            self.add(
+                (
+                    completion.kind,
+                    completion.id.clone(),
+                    completion.channel_id.clone(),
+                ),
+                completion,
+            );
+        }
+    }
+}
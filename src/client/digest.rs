@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{Channel, ChannelType, Message};
+
+use super::state::ChannelState;
+
+pub const DIGEST_CHANNEL_CAPACITY: usize = 64;
+pub const DIGEST_HISTORY_LIMIT: usize = 32;
+
+/// Batches messages in `Broadcast` channels into digests covering `window` from the first
+/// message, keeping up to `max_previews` of them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DigestPolicy {
+    pub enabled: bool,
+    pub window: Duration,
+    pub max_previews: usize,
+}
+
+impl Default for DigestPolicy {
+    fn default() -> Self {
+        DigestPolicy {
+            enabled: false,
+            window: Duration::from_secs(60),
+            max_previews: 3,
+        }
+    }
+}
+
+impl DigestPolicy {
+    pub fn new(window: Duration) -> Self {
+        DigestPolicy {
+            enabled: true,
+            window,
+            ..Self::default()
+        }
+    }
+
+    pub fn max_previews(mut self, max_previews: usize) -> Self {
+        self.max_previews = max_previews;
+        self
+    }
+
+    pub fn applies_to(&self, channel: &Channel) -> bool {
+        self.enabled && channel.channel_type == ChannelType::Broadcast
+    }
+
+    fn is_open(&self, digest: &Digest, at: DateTime<Utc>) -> bool {
+        chrono::Duration::from_std(self.window)
+            .is_ok_and(|window| at.signed_duration_since(digest.started_at) < window)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Digest {
+    pub channel_id: String,
+    pub count: usize,
+    pub previews: Vec<Message>,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DigestNotification {
+    pub connection_id: String,
+    pub digest: Digest,
+}
+
+/// Adds `message` to the channel's open digest, or starts a new one. Returns the digest it
+/// closed, if the previous one's window had elapsed.
+pub(crate) fn add_to_digest(
+    channel: &mut ChannelState,
+    message: Message,
+    policy: &DigestPolicy,
+    at: DateTime<Utc>,
+) -> Option<Digest> {
+    if let Some(digest) = channel
+        .pending_digest
+        .as_mut()
+        .filter(|digest| policy.is_open(digest, at))
+    {
+        digest.count += 1;
+        digest.ended_at = at;
+        if digest.previews.len() < policy.max_previews {
+            digest.previews.push(message);
+        }
+        return None;
+    }
+
+    let closed = close_digest(channel);
+    channel.pending_digest = Some(Digest {
+        channel_id: channel.channel.id.clone(),
+        count: 1,
+        previews: std::iter::once(message).take(policy.max_previews).collect(),
+        started_at: at,
+        ended_at: at,
+    });
+    closed
+}
+
+/// Closes the channel's open digest if its window has elapsed by `now`.
+pub(crate) fn flush_digest(
+    channel: &mut ChannelState,
+    policy: &DigestPolicy,
+    now: DateTime<Utc>,
+) -> Option<Digest> {
+    if channel
+        .pending_digest
+        .as_ref()
+        .is_some_and(|digest| !policy.is_open(digest, now))
+    {
+        return close_digest(channel);
+    }
+    None
+}
+
+fn close_digest(channel: &mut ChannelState) -> Option<Digest> {
+    let digest = channel.pending_digest.take()?;
+    channel.digests.push(digest.clone());
+    if channel.digests.len() > DIGEST_HISTORY_LIMIT {
+        channel
+            .digests
+            .drain(..channel.digests.len() - DIGEST_HISTORY_LIMIT);
+    }
+    Some(digest)
+}
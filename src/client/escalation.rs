@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{connection::Scope, ChannelType, Message, MessageFragment, MessageType};
+
+use super::state::ConnectionState;
+
+pub const PRIORITY_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum EscalationReason {
+    DirectMessage,
+    Mention,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct EscalationPolicy {
+    pub direct_messages: bool,
+    pub mentions: bool,
+}
+
+impl Default for EscalationPolicy {
+    fn default() -> Self {
+        EscalationPolicy {
+            direct_messages: true,
+            mentions: true,
+        }
+    }
+}
+
+impl EscalationPolicy {
+    pub fn disabled() -> Self {
+        EscalationPolicy {
+            direct_messages: false,
+            mentions: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.direct_messages || self.mentions
+    }
+
+    pub fn reason_for(
+        &self,
+        state: &ConnectionState,
+        scope: &Scope,
+        message: &Message,
+    ) -> Option<EscalationReason> {
+        if message.message_type == MessageType::CurrentUser
+            || (message.sender_id.is_some() && message.sender_id == state.current_user_id)
+        {
+            return None;
+        }
+
+        let direct = scope
+            .channel_id()
+            .and_then(|cid| state.channels.get(cid))
+            .is_some_and(|c| c.channel.channel_type == ChannelType::Direct);
+        if self.direct_messages && direct {
+            return Some(EscalationReason::DirectMessage);
+        }
+        if self.mentions && mentions_current_user(state, message) {
+            return Some(EscalationReason::Mention);
+        }
+        None
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PriorityNotification {
+    pub connection_id: String,
+    pub scope: Scope,
+    pub message: Message,
+    pub reason: EscalationReason,
+}
+
+fn mentions_current_user(state: &ConnectionState, message: &Message) -> bool {
+    let Some(user_id) = &state.current_user_id else {
+        return false;
+    };
+    if message.content.iter().any(|fragment| {
+        matches!(fragment, MessageFragment::Mention { user_id: id, .. } if id == user_id)
+    }) {
+        return true;
+    }
+    let Some(user) = state
+        .global_users
+        .get(user_id)
+        .or_else(|| state.channels.values().find_map(|c| c.users.get(user_id)))
+    else {
+        return false;
+    };
+    let names: Vec<String> = [&user.username, &user.display_name]
+        .into_iter()
+        .flatten()
+        .filter(|name| !name.is_empty())
+        .map(|name| format!("@{}", name.to_lowercase()))
+        .collect();
+
+    message.content.iter().any(|fragment| {
+        let Some(text) = fragment.text() else {
+            return false;
+        };
+        let text = text.to_lowercase();
+        names.iter().any(|name| {
+            text.match_indices(name.as_str()).any(|(start, _)| {
+                text[start + name.len()..]
+                    .chars()
+                    .next()
+                    .is_none_or(|c| !c.is_alphanumeric() && c != '_')
+            })
+        })
+    })
+}
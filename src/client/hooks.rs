@@ -0,0 +1,54 @@
+use async_trait::async_trait;
+
+use crate::connection::{ConnectionEvent, StatusEvent};
+
+/// Async callbacks a `StateClient` runs at connection lifecycle points, after the triggering
+/// status event has been applied. Every method defaults to doing nothing.
+///
+/// `on_first_sync_complete` runs for backends that report `StatusEvent::Synced` once their
+/// initial state is delivered. `on_fatal_error` runs on failures a reconnect will not fix,
+/// such as rejected credentials.
+#[async_trait]
+pub trait LifecycleHook: Send + Sync {
+    async fn on_connect(&self, _connection_id: &str) {}
+
+    async fn on_disconnect(&self, _connection_id: &str, _reason: Option<&str>) {}
+
+    async fn on_first_sync_complete(&self, _connection_id: &str) {}
+
+    async fn on_fatal_error(&self, _connection_id: &str, _error: &str) {}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Lifecycle {
+    Connect,
+    Disconnect(Option<String>),
+    FirstSync,
+    FatalError(String),
+}
+
+impl Lifecycle {
+    pub(crate) fn of(event: &ConnectionEvent) -> Option<Self> {
+        let ConnectionEvent::Status { event } = event else {
+            return None;
+        };
+        match event {
+            StatusEvent::Connected { .. } => Some(Lifecycle::Connect),
+            StatusEvent::Disconnected { artifact } => Some(Lifecycle::Disconnect(artifact.clone())),
+            StatusEvent::Synced => Some(Lifecycle::FirstSync),
+            StatusEvent::AuthFailed { reason } => Some(Lifecycle::FatalError(reason.clone())),
+            _ => None,
+        }
+    }
+
+    pub(crate) async fn run(&self, hook: &dyn LifecycleHook, connection_id: &str) {
+        match self {
+            Lifecycle::Connect => hook.on_connect(connection_id).await,
+            Lifecycle::Disconnect(reason) => {
+                hook.on_disconnect(connection_id, reason.as_deref()).await
+            }
+            Lifecycle::FirstSync => hook.on_first_sync_complete(connection_id).await,
+            Lifecycle::FatalError(error) => hook.on_fatal_error(connection_id, error).await,
+        }
+    }
+}
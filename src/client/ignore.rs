@@ -0,0 +1,88 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    connection::{ChatEvent, ConnectionEvent},
+    utils::compose::strip_media,
+    Message,
+};
+
+/// Senders whose media is collapsed into text placeholders as their messages are processed.
+/// Serializable so applications can keep it with their other settings.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MediaIgnoreList {
+    pub users: BTreeSet<String>,
+}
+
+impl MediaIgnoreList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_user(mut self, user_id: &str) -> Self {
+        self.insert(user_id);
+        self
+    }
+
+    /// Returns whether the user was newly added.
+    pub fn insert(&mut self, user_id: &str) -> bool {
+        self.users.insert(user_id.to_string())
+    }
+
+    /// Returns whether the user was listed.
+    pub fn remove(&mut self, user_id: &str) -> bool {
+        self.users.remove(user_id)
+    }
+
+    pub fn contains(&self, user_id: &str) -> bool {
+        self.users.contains(user_id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    pub(crate) fn apply(&self, event: ConnectionEvent) -> ConnectionEvent {
+        if self.is_empty() {
+            return event;
+        }
+        match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } => ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope,
+                    message: self.collapse(message),
+                },
+            },
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::Update {
+                        scope,
+                        message_id,
+                        new_message,
+                    },
+            } => ConnectionEvent::Chat {
+                event: ChatEvent::Update {
+                    scope,
+                    message_id,
+                    new_message: self.collapse(new_message),
+                },
+            },
+            other => other,
+        }
+    }
+
+    fn collapse(&self, mut message: Message) -> Message {
+        if message
+            .sender_id
+            .as_deref()
+            .is_some_and(|sender| self.contains(sender))
+        {
+            message.content = strip_media(&message.content);
+        }
+        message
+    }
+}
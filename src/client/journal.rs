@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+
+use crate::connection::ConnectionEvent;
+
+use super::{
+    bulk::BulkChange, retention::RetentionPolicy, state::ConnectionState,
+    stateclient::process_event_at,
+};
+
+#[derive(Clone, Debug)]
+pub struct JournalEntry {
+    pub received_at: DateTime<Utc>,
+    pub event: ConnectionEvent,
+}
+
+#[derive(Clone, Debug)]
+pub struct Journal {
+    base: ConnectionState,
+    base_time: Option<DateTime<Utc>>,
+    entries: VecDeque<JournalEntry>,
+    max_entries: Option<usize>,
+    /// Retention policies with the time each took effect, oldest first.
+    retention: Vec<(DateTime<Utc>, RetentionPolicy)>,
+    /// Bulk changes with the number of entries recorded before each and when it ran.
+    bulk: Vec<(usize, DateTime<Utc>, BulkChange)>,
+    /// How many entries have been folded into `base`.
+    folded: usize,
+}
+
+impl Journal {
+    pub fn new(
+        base: ConnectionState,
+        max_entries: Option<usize>,
+        retention: RetentionPolicy,
+    ) -> Self {
+        Journal {
+            base,
+            base_time: None,
+            entries: VecDeque::new(),
+            max_entries,
+            retention: vec![(Utc::now(), retention)],
+            bulk: Vec::new(),
+            folded: 0,
+        }
+    }
+
+    pub fn record(&mut self, event: ConnectionEvent) {
+        self.record_at(event, Utc::now());
+    }
+
+    pub fn record_at(&mut self, event: ConnectionEvent, received_at: DateTime<Utc>) {
+        self.entries.push_back(JournalEntry { received_at, event });
+
+        if let Some(max) = self.max_entries {
+            while self.entries.len() > max {
+                if let Some(entry) = self.entries.pop_front() {
+                    let due = self
+                        .bulk
+                        .iter()
+                        .take_while(|(position, _, _)| *position <= self.folded)
+                        .count();
+                    for (_, _, change) in self.bulk.drain(..due) {
+                        change.apply(&mut self.base);
+                    }
+                    self.folded += 1;
+                    self.base_time = Some(entry.received_at);
+                    let retention = self.retention_at(entry.received_at).clone();
+                    process_event_at(&mut self.base, entry.event, &retention, entry.received_at);
+                }
+            }
+            if let Some(base_time) = self.base_time {
+                while self.retention.len() > 1 && self.retention[1].0 <= base_time {
+                    self.retention.remove(0);
+                }
+            }
+        }
+    }
+
+    /// Notes a retention policy change so replays prune the way the live state did.
+    pub fn record_retention(&mut self, policy: RetentionPolicy, changed_at: DateTime<Utc>) {
+        self.retention.push((changed_at, policy));
+    }
+
+    /// Notes a bulk change made to the live state so replays make it at the same point.
+    pub fn record_bulk(&mut self, change: BulkChange, applied_at: DateTime<Utc>) {
+        self.bulk
+            .push((self.folded + self.entries.len(), applied_at, change));
+    }
+
+    fn retention_index(&self, timestamp: DateTime<Utc>) -> usize {
+        self.retention
+            .iter()
+            .rposition(|(changed_at, _)| *changed_at <= timestamp)
+            .unwrap_or(0)
+    }
+
+    fn retention_at(&self, timestamp: DateTime<Utc>) -> &RetentionPolicy {
+        &self.retention[self.retention_index(timestamp)].1
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &JournalEntry> {
+        self.entries.iter()
+    }
+
+    /// Replays the journal up to `timestamp` under the retention policies in force at the
+    /// time, judging message age against `timestamp` rather than the current time.
+    pub fn state_at(&self, timestamp: DateTime<Utc>) -> Option<ConnectionState> {
+        if self
+            .base_time
+            .is_some_and(|base_time| base_time > timestamp)
+        {
+            return None;
+        }
+
+        let mut state = self.base.clone();
+        let mut policy = self.retention_index(self.base_time.unwrap_or(timestamp));
+        let mut switch_policy = |state: &mut ConnectionState, at: DateTime<Utc>| {
+            let current = self.retention_index(at);
+            if current != policy {
+                policy = current;
+                for channel in state.channels.values_mut() {
+                    self.retention[policy].1.apply_at(channel, timestamp);
+                }
+            }
+            &self.retention[policy].1
+        };
+        let mut bulk = self.bulk.iter().peekable();
+        let mut apply_bulk = |state: &mut ConnectionState, position: usize| {
+            while let Some((_, applied_at, change)) =
+                bulk.next_if(|(at_position, _, _)| *at_position <= position)
+            {
+                if *applied_at <= timestamp {
+                    change.apply(state);
+                }
+            }
+        };
+        let mut position = self.folded;
+        for entry in self
+            .entries
+            .iter()
+            .take_while(|entry| entry.received_at <= timestamp)
+        {
+            apply_bulk(&mut state, position);
+            let retention = switch_policy(&mut state, entry.received_at);
+            process_event_at(&mut state, entry.event.clone(), retention, timestamp);
+            position += 1;
+        }
+        apply_bulk(&mut state, position);
+        switch_policy(&mut state, timestamp);
+        Some(state)
+    }
+}
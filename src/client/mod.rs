@@ -1,7 +1,46 @@
+pub mod bulk;
+pub mod complete;
+pub mod digest;
+pub mod escalation;
+pub mod hooks;
+pub mod ignore;
+pub mod journal;
+pub mod requests;
+pub mod retention;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod snapshot;
 pub mod state;
 pub mod stateclient;
 pub mod storage;
+#[cfg(feature = "sync")]
+pub mod sync;
+pub mod watch;
+#[cfg(feature = "webhooks")]
+pub mod webhook;
 
-pub use state::{ChannelState, ConnectionState, ConnectionStatus};
+pub use bulk::{BulkChange, BulkOperation, BulkProgress, BulkReport};
+pub use complete::{Completion, CompletionIndex, CompletionKind};
+pub use digest::{Digest, DigestNotification, DigestPolicy, DIGEST_HISTORY_LIMIT};
+pub use escalation::{EscalationPolicy, EscalationReason, PriorityNotification};
+pub use hooks::LifecycleHook;
+pub use ignore::MediaIgnoreList;
+pub use journal::{Journal, JournalEntry};
+pub use requests::{DirectRequest, DirectRequestPolicy};
+pub use retention::{Retention, RetentionPolicy};
+#[cfg(feature = "scripting")]
+pub use script::{ScriptAction, ScriptError, ScriptHost};
+pub use snapshot::{
+    ChannelSnapshot, ConnectionSummary, QueryLimits, SnapshotField, SnapshotQuery, SummaryStatus,
+};
+pub use state::{
+    channel_scope, scope_channel_id, Ban, ChannelState, ConnectionState, ConnectionStatus,
+    Maintenance, ProfileVersion, LOBBY_CHANNEL_ID, PROFILE_HISTORY_LIMIT,
+};
 pub use stateclient::StateClient;
 pub use storage::{InMemoryStorage, StateStorage};
+#[cfg(feature = "sync")]
+pub use sync::{BundleChannel, SyncBundle, SyncError};
+pub use watch::{Watch, WatchMatch};
+#[cfg(feature = "webhooks")]
+pub use webhook::{event_kind, WebhookError, WebhookTemplates};
@@ -0,0 +1,37 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{Channel, Profile};
+
+use super::state::ConnectionState;
+
+pub const AUTO_REPLY_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DirectRequest {
+    pub channel: Channel,
+    pub from: Profile,
+    pub received_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DirectRequestPolicy {
+    /// Accepts requests from users already seen on the connection without queuing them.
+    pub auto_accept_known: bool,
+}
+
+impl DirectRequestPolicy {
+    pub fn auto_accepts(&self, state: &ConnectionState, from: &Profile) -> bool {
+        let Some(user_id) = &from.id else {
+            return false;
+        };
+        self.auto_accept_known
+            && (state.global_users.contains_key(user_id)
+                || state
+                    .channels
+                    .values()
+                    .any(|c| c.users.contains_key(user_id)))
+    }
+}
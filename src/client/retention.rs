@@ -0,0 +1,63 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{Channel, ChannelType};
+
+use super::state::ChannelState;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Retention {
+    #[default]
+    KeepAll,
+    MaxMessages(usize),
+    MaxAge(Duration),
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RetentionPolicy {
+    pub default: Retention,
+    pub by_type: HashMap<ChannelType, Retention>,
+    pub by_channel: HashMap<String, Retention>,
+}
+
+impl RetentionPolicy {
+    pub fn retention_for(&self, channel: &Channel) -> &Retention {
+        self.by_channel
+            .get(&channel.id)
+            .or_else(|| self.by_type.get(&channel.channel_type))
+            .unwrap_or(&self.default)
+    }
+
+    pub fn apply(&self, channel_state: &mut ChannelState) {
+        self.apply_at(channel_state, Utc::now());
+    }
+
+    /// Like `apply`, judging `MaxAge` against `now` instead of the current time.
+    pub fn apply_at(&self, channel_state: &mut ChannelState, now: DateTime<Utc>) {
+        match self.retention_for(&channel_state.channel) {
+            Retention::KeepAll => {}
+            Retention::MaxMessages(max) => {
+                let len = channel_state.messages.len();
+                if len > *max {
+                    channel_state.messages.drain(..len - max);
+                    channel_state.forget_evicted();
+                }
+            }
+            Retention::MaxAge(age) => {
+                let Ok(age) = chrono::Duration::from_std(*age) else {
+                    return;
+                };
+                let cutoff = now - age;
+                let len = channel_state.messages.len();
+                channel_state.messages.retain(|m| m.timestamp >= cutoff);
+                if channel_state.messages.len() < len {
+                    channel_state.forget_evicted();
+                }
+            }
+        }
+    }
+}
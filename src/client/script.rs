@@ -0,0 +1,314 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use rhai::{module_resolvers::DummyModuleResolver, Array, Dynamic, Engine, Map, AST};
+
+use crate::{
+    connection::{ChatEvent, ConnectionError, ConnectionEvent, Scope},
+    Connection, Message, MessageFragment, MessageStatus, MessageType, Profile,
+};
+
+use super::{state::ConnectionState, stateclient::StateClient, storage::StateStorage};
+
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_COLLECTION_SIZE: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptError {
+    Compile { script: String, reason: String },
+    Runtime { script: String, reason: String },
+    Send(ConnectionError),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Compile { script, reason } => {
+                write!(f, "failed to compile script {}: {}", script, reason)
+            }
+            ScriptError::Runtime { script, reason } => {
+                write!(f, "script {} failed: {}", script, reason)
+            }
+            ScriptError::Send(e) => write!(f, "script send failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptAction {
+    Send { scope: Scope, text: String },
+    Drop,
+}
+
+#[derive(Default)]
+struct ScriptContext {
+    state: Option<ConnectionState>,
+    scope: Scope,
+    actions: Vec<ScriptAction>,
+}
+
+pub struct ScriptHost {
+    engine: Engine,
+    scripts: Vec<(String, AST)>,
+    context: Arc<Mutex<ScriptContext>>,
+}
+
+impl ScriptHost {
+    pub fn new() -> Self {
+        let context = Arc::new(Mutex::new(ScriptContext::default()));
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        engine.set_max_string_size(MAX_STRING_SIZE);
+        engine.set_max_array_size(MAX_COLLECTION_SIZE);
+        engine.set_max_map_size(MAX_COLLECTION_SIZE);
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.on_print(|_| {});
+        engine.on_debug(|_, _, _| {});
+
+        let ctx = context.clone();
+        engine.register_fn("send", move |channel_id: &str, text: &str| {
+            ctx.lock().unwrap().actions.push(ScriptAction::Send {
+                scope: Scope::channel(channel_id),
+                text: text.to_string(),
+            });
+        });
+        let ctx = context.clone();
+        engine.register_fn("reply", move |text: &str| {
+            let mut ctx = ctx.lock().unwrap();
+            let scope = ctx.scope.clone();
+            ctx.actions.push(ScriptAction::Send {
+                scope,
+                text: text.to_string(),
+            });
+        });
+        let ctx = context.clone();
+        engine.register_fn("drop_event", move || {
+            ctx.lock().unwrap().actions.push(ScriptAction::Drop);
+        });
+        let ctx = context.clone();
+        engine.register_fn("user_name", move |user_id: &str| -> Dynamic {
+            let ctx = ctx.lock().unwrap();
+            ctx.state
+                .as_ref()
+                .and_then(|state| find_user(state, user_id))
+                .and_then(|user| user.display_name.clone().or(user.username.clone()))
+                .map(Dynamic::from)
+                .unwrap_or(Dynamic::UNIT)
+        });
+        let ctx = context.clone();
+        engine.register_fn("channel_users", move |channel_id: &str| -> Array {
+            let ctx = ctx.lock().unwrap();
+            ctx.state
+                .as_ref()
+                .and_then(|state| state.channels.get(channel_id))
+                .map(|channel| channel.users.keys().cloned().map(Dynamic::from).collect())
+                .unwrap_or_default()
+        });
+        let ctx = context.clone();
+        engine.register_fn("current_channel", move || -> Dynamic {
+            let ctx = ctx.lock().unwrap();
+            ctx.state
+                .as_ref()
+                .and_then(|state| state.current_channel.clone())
+                .map(Dynamic::from)
+                .unwrap_or(Dynamic::UNIT)
+        });
+
+        ScriptHost {
+            engine,
+            scripts: Vec::new(),
+            context,
+        }
+    }
+
+    pub fn load(&mut self, name: &str, source: &str) -> Result<(), ScriptError> {
+        let ast = self
+            .engine
+            .compile(source)
+            .map_err(|e| ScriptError::Compile {
+                script: name.to_string(),
+                reason: e.to_string(),
+            })?;
+        self.unload(name);
+        self.scripts.push((name.to_string(), ast));
+        Ok(())
+    }
+
+    pub fn unload(&mut self, name: &str) -> bool {
+        let before = self.scripts.len();
+        self.scripts.retain(|(n, _)| n != name);
+        self.scripts.len() != before
+    }
+
+    pub fn scripts(&self) -> Vec<String> {
+        self.scripts.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    pub async fn handle<S: StateStorage + 'static>(
+        &mut self,
+        client: &StateClient<S>,
+        connection_id: &str,
+        event: &ConnectionEvent,
+    ) -> Result<Vec<ScriptAction>, ScriptError> {
+        if self.scripts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let state = client.get_connection(connection_id).await;
+        let own_id = state.as_ref().and_then(|s| s.current_user_id.clone());
+        let message = match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } if own_id.is_none() || own_id != message.sender_id => {
+                Some(message_map(connection_id, scope, message))
+            }
+            _ => None,
+        };
+        let event_value = rhai::serde::to_dynamic(event).map_err(|e| ScriptError::Runtime {
+            script: String::new(),
+            reason: e.to_string(),
+        })?;
+
+        *self.context.lock().unwrap() = ScriptContext {
+            state,
+            scope: event_scope(event),
+            actions: Vec::new(),
+        };
+
+        let result = self.run(event_value, message);
+        let actions = std::mem::take(&mut self.context.lock().unwrap().actions);
+        *self.context.lock().unwrap() = ScriptContext::default();
+        result.map(|_| actions)
+    }
+
+    pub async fn dispatch<S: StateStorage + 'static, C: Connection + ?Sized>(
+        &mut self,
+        client: &StateClient<S>,
+        connection_id: &str,
+        event: &ConnectionEvent,
+        connection: &mut C,
+    ) -> Result<bool, ScriptError> {
+        let mut keep = true;
+        for action in self.handle(client, connection_id, event).await? {
+            match action {
+                ScriptAction::Send { scope, text } => {
+                    let message = Message {
+                        id: None,
+                        sender_id: None,
+                        content: vec![MessageFragment::Text(text)],
+                        timestamp: Utc::now(),
+                        message_type: MessageType::CurrentUser,
+                        status: MessageStatus::Sent,
+                        reactions: Vec::new(),
+                        reply_to: None,
+                        thread_id: None,
+                        extra: HashMap::new(),
+                    };
+                    connection
+                        .send(ConnectionEvent::Chat {
+                            event: ChatEvent::New { scope, message },
+                        })
+                        .await
+                        .map_err(ScriptError::Send)?;
+                }
+                ScriptAction::Drop => keep = false,
+            }
+        }
+        Ok(keep)
+    }
+
+    fn run(&self, event: Dynamic, message: Option<Map>) -> Result<(), ScriptError> {
+        for (name, ast) in &self.scripts {
+            let runtime = |e: Box<rhai::EvalAltResult>| ScriptError::Runtime {
+                script: name.clone(),
+                reason: e.to_string(),
+            };
+            if has_handler(ast, "on_event") {
+                let _ = self
+                    .engine
+                    .call_fn::<Dynamic>(&mut rhai::Scope::new(), ast, "on_event", (event.clone(),))
+                    .map_err(runtime)?;
+            }
+            if let Some(message) = &message {
+                if has_handler(ast, "on_message") {
+                    let _ = self
+                        .engine
+                        .call_fn::<Dynamic>(
+                            &mut rhai::Scope::new(),
+                            ast,
+                            "on_message",
+                            (message.clone(),),
+                        )
+                        .map_err(runtime)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for ScriptHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn has_handler(ast: &AST, name: &str) -> bool {
+    ast.iter_functions()
+        .any(|f| f.name == name && f.params.len() == 1)
+}
+
+fn find_user<'a>(state: &'a ConnectionState, user_id: &str) -> Option<&'a Profile> {
+    state
+        .global_users
+        .get(user_id)
+        .or_else(|| state.channels.values().find_map(|c| c.users.get(user_id)))
+}
+
+fn event_scope(event: &ConnectionEvent) -> Scope {
+    match event {
+        ConnectionEvent::Chat {
+            event:
+                ChatEvent::New { scope, .. }
+                | ChatEvent::Update { scope, .. }
+                | ChatEvent::Remove { scope, .. }
+                | ChatEvent::ReactionAdd { scope, .. }
+                | ChatEvent::ReactionRemove { scope, .. },
+        } => scope.clone(),
+        ConnectionEvent::Chat {
+            event: ChatEvent::ReadMarker { channel_id, .. },
+        } => Scope::channel(channel_id.as_str()),
+        _ => Scope::Global,
+    }
+}
+
+fn message_map(connection_id: &str, scope: &Scope, message: &Message) -> Map {
+    let text: String = message
+        .content
+        .iter()
+        .filter_map(MessageFragment::text)
+        .collect();
+    let optional =
+        |value: Option<&str>| value.map_or(Dynamic::UNIT, |v| Dynamic::from(v.to_string()));
+
+    let mut map = Map::new();
+    map.insert(
+        "connection".into(),
+        Dynamic::from(connection_id.to_string()),
+    );
+    map.insert("channel".into(), optional(scope.channel_id()));
+    map.insert("id".into(), optional(message.id.as_deref()));
+    map.insert("sender".into(), optional(message.sender_id.as_deref()));
+    map.insert("text".into(), Dynamic::from(text));
+    map
+}
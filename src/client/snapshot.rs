@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Asset, Channel, Message, Profile};
+
+use super::state::{ChannelState, ConnectionState, ConnectionStatus};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ChannelSnapshot {
+    pub channel: Channel,
+    pub users: Vec<Profile>,
+    pub messages: Vec<Message>,
+    pub assets: Vec<Asset>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl From<&ChannelState> for ChannelSnapshot {
+    fn from(state: &ChannelState) -> Self {
+        let mut users: Vec<(&String, &Profile)> = state.users.iter().collect();
+        users.sort_by(|a, b| a.0.cmp(b.0));
+        let mut assets: Vec<(&String, &Asset)> = state.assets.iter().collect();
+        assets.sort_by(|a, b| a.0.cmp(b.0));
+
+        ChannelSnapshot {
+            channel: state.channel.clone(),
+            users: users.into_iter().map(|(_, u)| u.clone()).collect(),
+            messages: state.messages.clone(),
+            assets: assets.into_iter().map(|(_, a)| a.clone()).collect(),
+            annotations: state
+                .annotations
+                .iter()
+                .map(|(id, notes)| (id.clone(), notes.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// A part of a `ChannelSnapshot` a client can ask to leave out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum SnapshotField {
+    Users,
+    UserPictures,
+    Messages,
+    MessageContent,
+    Reactions,
+    Assets,
+    Annotations,
+}
+
+/// A field mask and size limits for a partial `ChannelSnapshot`, so clients on slow links only
+/// fetch what they render. Message limits keep the most recent messages.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SnapshotQuery {
+    #[serde(default)]
+    pub exclude: BTreeSet<SnapshotField>,
+    #[serde(default)]
+    pub max_messages: Option<usize>,
+    #[serde(default)]
+    pub max_users: Option<usize>,
+}
+
+impl SnapshotQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn without(mut self, field: SnapshotField) -> Self {
+        self.exclude.insert(field);
+        self
+    }
+
+    pub fn max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = Some(max_messages);
+        self
+    }
+
+    pub fn max_users(mut self, max_users: usize) -> Self {
+        self.max_users = Some(max_users);
+        self
+    }
+
+    pub fn includes(&self, field: SnapshotField) -> bool {
+        !self.exclude.contains(&field)
+    }
+
+    /// Tightens this query's limits to the server's, whatever the client asked for.
+    pub fn clamp(mut self, limits: &QueryLimits) -> Self {
+        let clamp = |asked: Option<usize>, max: Option<usize>| match (asked, max) {
+            (Some(asked), Some(max)) => Some(asked.min(max)),
+            (asked, max) => asked.or(max),
+        };
+        self.max_messages = clamp(self.max_messages, limits.max_messages);
+        self.max_users = clamp(self.max_users, limits.max_users);
+        self
+    }
+}
+
+/// Server-side caps on partial queries, applied on top of each `SnapshotQuery`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct QueryLimits {
+    pub max_messages: Option<usize>,
+    pub max_users: Option<usize>,
+}
+
+impl ChannelSnapshot {
+    /// Strips everything `query` excludes and trims to its limits.
+    pub fn apply_query(&mut self, query: &SnapshotQuery) {
+        if !query.includes(SnapshotField::Users) {
+            self.users.clear();
+        }
+        if let Some(max_users) = query.max_users {
+            self.users.truncate(max_users);
+        }
+        if !query.includes(SnapshotField::UserPictures) {
+            for user in &mut self.users {
+                user.picture = None;
+            }
+        }
+
+        if !query.includes(SnapshotField::Messages) {
+            self.messages.clear();
+        }
+        if let Some(max_messages) = query.max_messages {
+            let excess = self.messages.len().saturating_sub(max_messages);
+            self.messages.drain(..excess);
+        }
+        for message in &mut self.messages {
+            if !query.includes(SnapshotField::MessageContent) {
+                message.content.clear();
+            }
+            if !query.includes(SnapshotField::Reactions) {
+                message.reactions.clear();
+            }
+        }
+
+        if !query.includes(SnapshotField::Assets) {
+            self.assets.clear();
+        }
+        if !query.includes(SnapshotField::Annotations) {
+            self.annotations.clear();
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum SummaryStatus {
+    Disconnected,
+    Connecting,
+    Connected,
+    Reconnecting { attempt: u32 },
+    AuthFailed { reason: String },
+}
+
+impl From<&ConnectionStatus> for SummaryStatus {
+    fn from(status: &ConnectionStatus) -> Self {
+        match status {
+            ConnectionStatus::Disconnected => SummaryStatus::Disconnected,
+            ConnectionStatus::Connecting => SummaryStatus::Connecting,
+            ConnectionStatus::Connected => SummaryStatus::Connected,
+            ConnectionStatus::Reconnecting { attempt } => {
+                SummaryStatus::Reconnecting { attempt: *attempt }
+            }
+            ConnectionStatus::AuthFailed { reason } => SummaryStatus::AuthFailed {
+                reason: reason.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ConnectionSummary {
+    pub connection_id: String,
+    pub protocol_name: String,
+    pub status: SummaryStatus,
+    pub current_channel: Option<String>,
+    pub current_user_id: Option<String>,
+    pub channels: Vec<Channel>,
+    pub user_count: usize,
+}
+
+impl From<&ConnectionState> for ConnectionSummary {
+    fn from(state: &ConnectionState) -> Self {
+        let mut channels: Vec<Channel> =
+            state.channels.values().map(|c| c.channel.clone()).collect();
+        channels.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut user_ids: HashSet<&String> = state.global_users.keys().collect();
+        for channel in state.channels.values() {
+            user_ids.extend(channel.users.keys());
+        }
+
+        ConnectionSummary {
+            connection_id: state.connection_id.clone(),
+            protocol_name: state.protocol_name.clone(),
+            status: SummaryStatus::from(&state.status),
+            current_channel: state.current_channel.clone(),
+            current_user_id: state.current_user_id.clone(),
+            channels,
+            user_count: user_ids.len(),
+        }
+    }
+}
@@ -1,6 +1,38 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use crate::{Asset, Channel, Message, Profile};
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    connection::Scope, utils::assets::parse_assets, Asset, Channel, ChannelType, CommandSpec,
+    Message, MessageFragment, Presence, Profile,
+};
+
+use super::{complete::CompletionIndex, digest::Digest, requests::DirectRequest, watch::Watch};
+
+pub use crate::connection::ConnectionStatus;
+
+pub const TYPING_TIMEOUT_SECS: i64 = 10;
+pub const LOBBY_CHANNEL_ID: &str = "@lobby";
+pub const ACTIVITY_RETENTION_SECS: i64 = 3600;
+pub const PROFILE_HISTORY_LIMIT: usize = 16;
+
+/// The id of the channel holding state for `scope`. Global events belong to no channel, so
+/// they are kept in the lobby.
+pub fn scope_channel_id(scope: &Scope) -> &str {
+    match scope {
+        Scope::Global => LOBBY_CHANNEL_ID,
+        Scope::Channel(channel_id) => channel_id,
+    }
+}
+
+/// The inverse of [`scope_channel_id`]: the lobby stands for the global scope.
+pub fn channel_scope(channel_id: &str) -> Scope {
+    if channel_id == LOBBY_CHANNEL_ID {
+        Scope::Global
+    } else {
+        Scope::channel(channel_id)
+    }
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct ChannelState {
@@ -8,6 +40,17 @@ pub struct ChannelState {
     pub users: HashMap<String, Profile>,
     pub messages: Vec<Message>,
     pub assets: HashMap<String, Asset>,
+    pub typing: HashMap<String, DateTime<Utc>>,
+    pub read_markers: HashMap<String, String>,
+    pub watches: Vec<Watch>,
+    pub annotations: HashMap<String, BTreeMap<String, String>>,
+    pub activity: HashMap<String, Vec<DateTime<Utc>>>,
+    pub bans: HashMap<String, Ban>,
+    pub mutes: HashMap<String, Option<DateTime<Utc>>>,
+    pub threads: HashMap<String, Vec<String>>,
+    pub pending_digest: Option<Digest>,
+    pub digests: Vec<Digest>,
+    online: HashSet<String>,
 }
 
 impl ChannelState {
@@ -17,16 +60,303 @@ impl ChannelState {
             users: HashMap::new(),
             messages: Vec::new(),
             assets: HashMap::new(),
+            typing: HashMap::new(),
+            read_markers: HashMap::new(),
+            watches: Vec::new(),
+            annotations: HashMap::new(),
+            activity: HashMap::new(),
+            bans: HashMap::new(),
+            mutes: HashMap::new(),
+            threads: HashMap::new(),
+            pending_digest: None,
+            digests: Vec::new(),
+            online: HashSet::new(),
+        }
+    }
+
+    pub fn online_count(&self) -> usize {
+        self.online.len()
+    }
+
+    pub fn offline_count(&self) -> usize {
+        self.users.len().saturating_sub(self.online.len())
+    }
+
+    pub fn total_count(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_online(&self, user_id: &str) -> bool {
+        self.online.contains(user_id)
+    }
+
+    pub fn online_users(&self) -> Vec<&Profile> {
+        self.users
+            .iter()
+            .filter(|(id, _)| self.online.contains(*id))
+            .map(|(_, user)| user)
+            .collect()
+    }
+
+    pub fn offline_users(&self) -> Vec<&Profile> {
+        self.users
+            .iter()
+            .filter(|(id, _)| !self.online.contains(*id))
+            .map(|(_, user)| user)
+            .collect()
+    }
+
+    pub fn is_banned(&self, user_id: &str) -> bool {
+        self.bans.get(user_id).is_some_and(Ban::is_active)
+    }
+
+    pub fn is_muted(&self, user_id: &str) -> bool {
+        self.mutes
+            .get(user_id)
+            .is_some_and(|until| until.is_none_or(|until| until > Utc::now()))
+    }
+
+    pub(crate) fn insert_user(&mut self, user_id: String, user: Profile) {
+        if counts_as_online(user.presence.as_ref()) {
+            self.online.insert(user_id.clone());
+        } else {
+            self.online.remove(&user_id);
+        }
+        self.users.insert(user_id, user);
+    }
+
+    pub(crate) fn remove_user(&mut self, user_id: &str) -> Option<Profile> {
+        self.online.remove(user_id);
+        self.users.remove(user_id)
+    }
+
+    pub(crate) fn clear_users(&mut self) {
+        self.online.clear();
+        self.users.clear();
+    }
+
+    pub(crate) fn set_presence(&mut self, user_id: &str, presence: &Presence) {
+        let Some(user) = self.users.get_mut(user_id) else {
+            return;
+        };
+        user.presence = Some(presence.clone());
+        if counts_as_online(Some(presence)) {
+            self.online.insert(user_id.to_string());
+        } else {
+            self.online.remove(user_id);
+        }
+    }
+
+    pub fn typing_users(&self) -> Vec<String> {
+        let cutoff = Utc::now() - Duration::seconds(TYPING_TIMEOUT_SECS);
+        self.typing
+            .iter()
+            .filter(|(_, started)| **started >= cutoff)
+            .map(|(user_id, _)| user_id.clone())
+            .collect()
+    }
+
+    pub fn seen_by(&self, message_id: &str) -> Vec<String> {
+        let Some(index) = self.message_index(message_id) else {
+            return Vec::new();
+        };
+        self.read_markers
+            .iter()
+            .filter(|(_, marker)| self.message_index(marker).is_some_and(|i| i >= index))
+            .map(|(user_id, _)| user_id.clone())
+            .collect()
+    }
+
+    pub(crate) fn message_index(&self, message_id: &str) -> Option<usize> {
+        self.messages
+            .iter()
+            .position(|m| m.id.as_deref() == Some(message_id))
+    }
+
+    pub fn thread(&self, root_id: &str) -> Vec<Message> {
+        let root = self
+            .message_index(root_id)
+            .map(|i| self.messages[i].clone());
+        let children = self
+            .threads
+            .get(root_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.message_index(id).map(|i| self.messages[i].clone()));
+        root.into_iter().chain(children).collect()
+    }
+
+    pub(crate) fn index_thread(&mut self, message: &Message) {
+        let (Some(id), Some(root_id)) = (&message.id, &message.thread_id) else {
+            return;
+        };
+        if id == root_id {
+            return;
+        }
+        let children = self.threads.entry(root_id.clone()).or_default();
+        if !children.contains(id) {
+            children.push(id.clone());
+        }
+    }
+
+    pub(crate) fn reindex_threads(&mut self) {
+        let messages = std::mem::take(&mut self.messages);
+        self.threads.clear();
+        for message in &messages {
+            self.index_thread(message);
+        }
+        self.messages = messages;
+    }
+
+    pub(crate) fn unindex_thread(&mut self, message_id: &str) {
+        for children in self.threads.values_mut() {
+            children.retain(|id| id != message_id);
+        }
+        self.threads.retain(|_, children| !children.is_empty());
+    }
+
+    pub fn trending(&self, window: Duration, limit: usize) -> Vec<(Message, usize)> {
+        let cutoff = Utc::now() - window;
+        let mut scored: Vec<(Message, usize)> = self
+            .activity
+            .iter()
+            .filter_map(|(message_id, hits)| {
+                let count = hits.iter().filter(|at| **at >= cutoff).count();
+                let index = self.message_index(message_id)?;
+                (count > 0).then(|| (self.messages[index].clone(), count))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.timestamp.cmp(&a.0.timestamp)));
+        scored.truncate(limit);
+        scored
+    }
+
+    pub(crate) fn record_activity(&mut self, message_id: &str, at: DateTime<Utc>) {
+        let cutoff = at - Duration::seconds(ACTIVITY_RETENTION_SECS);
+        self.activity
+            .entry(message_id.to_string())
+            .or_default()
+            .push(at);
+        // Messages that went quiet drop out entirely rather than keeping an empty entry.
+        self.activity.retain(|_, hits| {
+            hits.retain(|at| *at >= cutoff);
+            !hits.is_empty()
+        });
+    }
+
+    pub(crate) fn purge_before(&mut self, before: DateTime<Utc>) -> usize {
+        let len = self.messages.len();
+        self.messages.retain(|m| m.timestamp >= before);
+        let purged = len - self.messages.len();
+        if purged > 0 {
+            self.forget_evicted();
+        }
+        purged
+    }
+
+    /// Drops the bookkeeping kept for messages that are no longer in the channel.
+    pub(crate) fn forget_evicted(&mut self) {
+        let ids: HashSet<&String> = self.messages.iter().filter_map(|m| m.id.as_ref()).collect();
+        self.activity.retain(|id, _| ids.contains(id));
+        self.annotations.retain(|id, _| ids.contains(id));
+        self.reindex_threads();
+    }
+
+    pub(crate) fn reparse_assets(&mut self, assets: &[Asset]) -> usize {
+        let changed = self.reparsed_messages(assets, self.messages.len());
+        let count = changed.len();
+        for (index, message) in changed {
+            self.messages[index] = message;
         }
+        count
+    }
+
+    pub(crate) fn reparsed_messages(
+        &self,
+        assets: &[Asset],
+        recent: usize,
+    ) -> Vec<(usize, Message)> {
+        let skip = self.messages.len().saturating_sub(recent);
+        self.messages
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .filter(|(_, message)| {
+                message
+                    .content
+                    .iter()
+                    .any(|f| matches!(f, MessageFragment::Text(_)))
+            })
+            .filter_map(|(index, message)| {
+                let content: Vec<MessageFragment> = message
+                    .content
+                    .iter()
+                    .flat_map(|fragment| match fragment {
+                        MessageFragment::Text(text) => parse_assets(text, assets),
+                        other => vec![other.clone()],
+                    })
+                    .collect();
+                (content != message.content).then(|| {
+                    let mut message = message.clone();
+                    message.content = content;
+                    (index, message)
+                })
+            })
+            .collect()
+    }
+
+    pub fn expire_typing(&mut self) {
+        let cutoff = Utc::now() - Duration::seconds(TYPING_TIMEOUT_SECS);
+        self.typing.retain(|_, started| *started >= cutoff);
+    }
+}
+
+// Protocols without presence only list connected users, so an unknown presence counts as online.
+fn counts_as_online(presence: Option<&Presence>) -> bool {
+    !matches!(presence, Some(Presence::Offline))
+}
+
+fn same_identity(a: &Profile, b: &Profile) -> bool {
+    a.username == b.username
+        && a.display_name == b.display_name
+        && a.color == b.color
+        && a.picture == b.picture
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ban {
+    pub channel_id: Option<String>,
+    pub reason: Option<String>,
+    pub issued_at: DateTime<Utc>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// The last maintenance window a server announced, cleared once the connection is back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Maintenance {
+    pub message: Option<String>,
+    pub announced_at: DateTime<Utc>,
+    pub scheduled_at: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Maintenance {
+    /// Whether the announced window has not ended yet. Windows without an end stay pending.
+    pub fn is_pending(&self) -> bool {
+        self.until.is_none_or(|until| until > Utc::now())
     }
 }
 
-#[derive(Clone, Debug, Default, PartialEq)]
-pub enum ConnectionStatus {
-    #[default]
-    Disconnected,
-    Connecting,
-    Connected,
+impl Ban {
+    pub fn is_active(&self) -> bool {
+        self.until.is_none_or(|until| until > Utc::now())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProfileVersion {
+    pub since: DateTime<Utc>,
+    pub profile: Profile,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -39,6 +369,15 @@ pub struct ConnectionState {
     pub global_users: HashMap<String, Profile>,
     pub global_assets: HashMap<String, Asset>,
     pub current_user_id: Option<String>,
+    pub presence: HashMap<String, Presence>,
+    pub profile_history: HashMap<String, Vec<ProfileVersion>>,
+    pub pending_requests: HashMap<String, DirectRequest>,
+    pub last_seq: Option<u64>,
+    pub ban: Option<Ban>,
+    pub maintenance: Option<Maintenance>,
+    pub commands: Vec<CommandSpec>,
+    pub completions: CompletionIndex,
+    pub last_error: Option<String>,
 }
 
 impl ConnectionState {
@@ -52,16 +391,97 @@ impl ConnectionState {
             global_users: HashMap::new(),
             global_assets: HashMap::new(),
             current_user_id: None,
+            presence: HashMap::new(),
+            profile_history: HashMap::new(),
+            pending_requests: HashMap::new(),
+            last_seq: None,
+            ban: None,
+            maintenance: None,
+            commands: Vec::new(),
+            completions: CompletionIndex::new(),
+            last_error: None,
         }
     }
 
     pub fn get_or_create_channel(&mut self, channel_id: &str) -> &mut ChannelState {
-        self.channels.entry(channel_id.to_string()).or_insert_with(|| {
-            ChannelState::new(Channel {
-                id: channel_id.to_string(),
-                name: None,
-                channel_type: crate::ChannelType::Group,
+        self.channels
+            .entry(channel_id.to_string())
+            .or_insert_with(|| {
+                ChannelState::new(Channel {
+                    id: channel_id.to_string(),
+                    name: None,
+                    channel_type: ChannelType::Group,
+                    topic: None,
+                    extra: HashMap::new(),
+                })
+            })
+    }
+
+    /// Forgets the presence of users no longer listed anywhere on the connection.
+    pub(crate) fn prune_presence(&mut self) {
+        let (channels, global_users) = (&self.channels, &self.global_users);
+        self.presence.retain(|user_id, _| {
+            global_users.contains_key(user_id)
+                || channels
+                    .values()
+                    .any(|channel| channel.users.contains_key(user_id))
+        });
+    }
+
+    pub fn lobby(&mut self) -> &mut ChannelState {
+        self.channels
+            .entry(LOBBY_CHANNEL_ID.to_string())
+            .or_insert_with(|| {
+                ChannelState::new(Channel {
+                    id: LOBBY_CHANNEL_ID.to_string(),
+                    name: Some("Lobby".to_string()),
+                    channel_type: ChannelType::Broadcast,
+                    topic: None,
+                    extra: HashMap::new(),
+                })
             })
-        })
+    }
+
+    /// Records `seq` as applied, returning false when it is not newer than the last one.
+    pub(crate) fn advance_seq(&mut self, seq: u64) -> bool {
+        if self.last_seq.is_some_and(|last| seq <= last) {
+            return false;
+        }
+        self.last_seq = Some(seq);
+        true
+    }
+
+    pub fn profile_at(&self, user_id: &str, at: DateTime<Utc>) -> Option<&Profile> {
+        let versions = self.profile_history.get(user_id)?;
+        versions
+            .iter()
+            .rev()
+            .find(|version| version.since <= at)
+            .or(versions.first())
+            .map(|version| &version.profile)
+    }
+
+    pub(crate) fn record_profile(&mut self, user_id: &str, profile: &Profile) {
+        let versions = self.profile_history.entry(user_id.to_string()).or_default();
+        if versions
+            .last()
+            .is_some_and(|version| same_identity(&version.profile, profile))
+        {
+            return;
+        }
+        versions.push(ProfileVersion {
+            since: Utc::now(),
+            profile: profile.clone(),
+        });
+        if versions.len() > PROFILE_HISTORY_LIMIT {
+            versions.drain(..versions.len() - PROFILE_HISTORY_LIMIT);
+        }
+    }
+
+    pub fn scoped_channel(&mut self, scope: &Scope) -> &mut ChannelState {
+        match scope {
+            Scope::Global => self.lobby(),
+            Scope::Channel(channel_id) => self.get_or_create_channel(channel_id),
+        }
     }
 }
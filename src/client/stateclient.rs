@@ -1,29 +1,99 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Duration, Utc};
 
 use tokio::{
-    sync::{mpsc, RwLock},
+    sync::{broadcast, mpsc, RwLock},
     task::JoinHandle,
 };
-use uuid::Uuid;
 
 use crate::{
-    connection::{AssetEvent, ChannelEvent, ChatEvent, ConnectionEvent, StatusEvent, UserEvent},
-    Asset, Message, Profile,
+    connection::{
+        AssetEvent, ChannelEvent, ChatEvent, ConnectionEvent, Envelope, EventMiddleware,
+        MiddlewareChain, ModerationEvent, Scope, StatusEvent, UserEvent,
+    },
+    utils::{
+        ids::{normalize_event, IdGenerator, IdNormalizer, UuidGenerator},
+        trace::event,
+    },
+    Asset, CommandSpec, Connection, ConnectionError, Message, MessageStatus, MessageType, Presence,
+    Profile, Reaction,
 };
 
+#[cfg(feature = "sync")]
+use super::sync::{merge_messages, BundleChannel, SyncBundle, SyncError};
 use super::{
-    state::{ChannelState, ConnectionState, ConnectionStatus},
+    bulk::{BulkChange, BulkOperation, BulkProgress, BulkReport},
+    complete::Completion,
+    digest::{
+        add_to_digest, flush_digest, Digest, DigestNotification, DigestPolicy,
+        DIGEST_CHANNEL_CAPACITY,
+    },
+    escalation::{EscalationPolicy, PriorityNotification, PRIORITY_CHANNEL_CAPACITY},
+    hooks::{Lifecycle, LifecycleHook},
+    ignore::MediaIgnoreList,
+    journal::Journal,
+    requests::{DirectRequest, DirectRequestPolicy, AUTO_REPLY_CHANNEL_CAPACITY},
+    retention::RetentionPolicy,
+    snapshot::{ChannelSnapshot, ConnectionSummary, QueryLimits, SnapshotQuery},
+    state::{
+        channel_scope, scope_channel_id, Ban, ChannelState, ConnectionState, ConnectionStatus,
+        Maintenance, LOBBY_CHANNEL_ID,
+    },
     storage::{InMemoryStorage, StateStorage},
+    watch::{Watch, WatchMatch},
 };
 
+const REPARSE_CHANNEL_CAPACITY: usize = 256;
+
 pub struct StateClient<S: StateStorage = InMemoryStorage> {
     storage: Arc<RwLock<S>>,
+    retention: Arc<RwLock<RetentionPolicy>>,
+    journals: Arc<RwLock<Option<Journals>>>,
+    ids: Arc<dyn IdGenerator>,
+    middleware: Arc<RwLock<MiddlewareChain>>,
+    escalation: Arc<RwLock<EscalationPolicy>>,
+    priority_tx: broadcast::Sender<PriorityNotification>,
+    asset_reparse: Arc<RwLock<Option<usize>>>,
+    reparse_tx: broadcast::Sender<(String, ConnectionEvent)>,
+    direct_requests: Arc<RwLock<DirectRequestPolicy>>,
+    auto_reply_tx: broadcast::Sender<(String, ConnectionEvent)>,
+    digest: Arc<RwLock<DigestPolicy>>,
+    digest_tx: broadcast::Sender<DigestNotification>,
+    media_ignores: Arc<RwLock<MediaIgnoreList>>,
+    query_limits: Arc<RwLock<QueryLimits>>,
+    hooks: Arc<RwLock<Vec<Arc<dyn LifecycleHook>>>>,
+    normalizers: Arc<RwLock<HashMap<String, Arc<dyn IdNormalizer>>>>,
+}
+
+struct Journals {
+    max_entries: Option<usize>,
+    connections: HashMap<String, Journal>,
 }
 
 impl StateClient<InMemoryStorage> {
     pub fn new() -> Self {
         StateClient {
             storage: Arc::new(RwLock::new(InMemoryStorage::new())),
+            retention: Arc::new(RwLock::new(RetentionPolicy::default())),
+            journals: Arc::new(RwLock::new(None)),
+            ids: Arc::new(UuidGenerator),
+            middleware: Arc::new(RwLock::new(MiddlewareChain::new())),
+            escalation: Arc::new(RwLock::new(EscalationPolicy::default())),
+            priority_tx: broadcast::channel(PRIORITY_CHANNEL_CAPACITY).0,
+            asset_reparse: Arc::new(RwLock::new(None)),
+            reparse_tx: broadcast::channel(REPARSE_CHANNEL_CAPACITY).0,
+            direct_requests: Arc::new(RwLock::new(DirectRequestPolicy::default())),
+            auto_reply_tx: broadcast::channel(AUTO_REPLY_CHANNEL_CAPACITY).0,
+            digest: Arc::new(RwLock::new(DigestPolicy::default())),
+            digest_tx: broadcast::channel(DIGEST_CHANNEL_CAPACITY).0,
+            media_ignores: Arc::new(RwLock::new(MediaIgnoreList::default())),
+            query_limits: Arc::new(RwLock::new(QueryLimits::default())),
+            hooks: Arc::new(RwLock::new(Vec::new())),
+            normalizers: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 }
@@ -32,12 +102,43 @@ impl<S: StateStorage + 'static> StateClient<S> {
     pub fn with_storage(storage: S) -> Self {
         StateClient {
             storage: Arc::new(RwLock::new(storage)),
+            retention: Arc::new(RwLock::new(RetentionPolicy::default())),
+            journals: Arc::new(RwLock::new(None)),
+            ids: Arc::new(UuidGenerator),
+            middleware: Arc::new(RwLock::new(MiddlewareChain::new())),
+            escalation: Arc::new(RwLock::new(EscalationPolicy::default())),
+            priority_tx: broadcast::channel(PRIORITY_CHANNEL_CAPACITY).0,
+            asset_reparse: Arc::new(RwLock::new(None)),
+            reparse_tx: broadcast::channel(REPARSE_CHANNEL_CAPACITY).0,
+            direct_requests: Arc::new(RwLock::new(DirectRequestPolicy::default())),
+            auto_reply_tx: broadcast::channel(AUTO_REPLY_CHANNEL_CAPACITY).0,
+            digest: Arc::new(RwLock::new(DigestPolicy::default())),
+            digest_tx: broadcast::channel(DIGEST_CHANNEL_CAPACITY).0,
+            media_ignores: Arc::new(RwLock::new(MediaIgnoreList::default())),
+            query_limits: Arc::new(RwLock::new(QueryLimits::default())),
+            hooks: Arc::new(RwLock::new(Vec::new())),
+            normalizers: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
+    pub fn with_id_generator(mut self, ids: impl IdGenerator + 'static) -> Self {
+        self.ids = Arc::new(ids);
+        self
+    }
+
     pub async fn track(&self, protocol_name: &str) -> String {
-        let connection_id = Uuid::new_v4().to_string();
+        let connection_id = self.ids.next_id();
         let state = ConnectionState::new(connection_id.clone(), protocol_name.to_string());
+        if let Some(journals) = self.journals.write().await.as_mut() {
+            journals.connections.insert(
+                connection_id.clone(),
+                Journal::new(
+                    state.clone(),
+                    journals.max_entries,
+                    self.retention.read().await.clone(),
+                ),
+            );
+        }
         self.storage
             .write()
             .await
@@ -45,233 +146,440 @@ impl<S: StateStorage + 'static> StateClient<S> {
         connection_id
     }
 
+    /// Tracks `connection` under its protocol name, keying its state by the ids its
+    /// `Connection::id_normalizer` produces.
+    pub async fn track_connection<C: Connection + ?Sized>(&self, connection: &C) -> String {
+        let connection_id = self.track(&connection.protocol_spec().name).await;
+        self.set_id_normalizer(&connection_id, connection.id_normalizer())
+            .await;
+        connection_id
+    }
+
+    /// Normalizes every channel and user id of `connection_id`, in processed events and in
+    /// lookups, before it is used as a key.
+    pub async fn set_id_normalizer(&self, connection_id: &str, normalizer: Arc<dyn IdNormalizer>) {
+        self.normalizers
+            .write()
+            .await
+            .insert(connection_id.to_string(), normalizer);
+    }
+
+    async fn channel_key(&self, connection_id: &str, channel_id: &str) -> String {
+        match self.normalizers.read().await.get(connection_id) {
+            Some(ids) => ids.normalize_channel_id(channel_id),
+            None => channel_id.to_string(),
+        }
+    }
+
+    async fn user_key(&self, connection_id: &str, user_id: &str) -> String {
+        match self.normalizers.read().await.get(connection_id) {
+            Some(ids) => ids.normalize_user_id(user_id),
+            None => user_id.to_string(),
+        }
+    }
+
     pub async fn untrack(&self, connection_id: &str) {
         self.storage.write().await.remove(connection_id);
+        self.normalizers.write().await.remove(connection_id);
+        if let Some(journals) = self.journals.write().await.as_mut() {
+            journals.connections.remove(connection_id);
+        }
     }
 
     pub async fn process(&self, connection_id: &str, event: ConnectionEvent) {
+        self.apply(connection_id, event, Utc::now(), None).await;
+    }
+
+    /// Processes an event stamped by `connection::stamp`. Envelopes whose `seq` is not newer
+    /// than the last one applied for their connection are ignored as duplicates or stragglers.
+    pub async fn process_envelope(&self, envelope: Envelope) {
+        self.apply(
+            &envelope.connection_id,
+            envelope.event,
+            envelope.received_at,
+            Some(envelope.seq),
+        )
+        .await;
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "state.apply", level = "trace", skip_all, fields(connection_id = %connection_id, seq = ?seq))
+    )]
+    async fn apply(
+        &self,
+        connection_id: &str,
+        event: ConnectionEvent,
+        received_at: DateTime<Utc>,
+        seq: Option<u64>,
+    ) {
+        let Some(event) = self.middleware.read().await.inbound(event) else {
+            event!(trace, "event dropped by middleware");
+            return;
+        };
+        let mut event = self.media_ignores.read().await.apply(event);
+        if let Some(ids) = self.normalizers.read().await.get(connection_id) {
+            normalize_event(&mut event, ids.as_ref());
+        }
+        event!(trace, "applying {:?}", event);
         let mut storage = self.storage.write().await;
         let Some(state) = storage.get_mut(connection_id) else {
             return;
         };
+        if seq.is_some_and(|seq| !state.advance_seq(seq)) {
+            event!(debug, "skipping stale envelope");
+            return;
+        }
+        let lifecycle = Lifecycle::of(&event);
 
-        match event {
-            ConnectionEvent::Status { event } => {
-                self.process_status(state, event);
+        let retention = self.retention.read().await;
+        record_event(
+            &self.journals,
+            connection_id,
+            received_at,
+            state,
+            &event,
+            &retention,
+        )
+        .await;
+        let escalation = self.escalation.read().await;
+        let candidate = match &event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } if escalation.is_enabled() && self.priority_tx.receiver_count() > 0 => {
+                Some((scope.clone(), message.clone()))
             }
-            ConnectionEvent::Channel { event } => {
-                self.process_channel(state, event);
+            _ => None,
+        };
+        let asset_scope = match &event {
+            ConnectionEvent::Asset {
+                event: AssetEvent::New { scope, .. } | AssetEvent::Update { scope, .. },
+            } => Some(scope.clone()),
+            _ => None,
+        };
+        let request = match &event {
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Request { channel, from },
+            } => Some((channel.id.clone(), from.clone())),
+            _ => None,
+        };
+        let digested = match &event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } if message.message_type != MessageType::CurrentUser => {
+                Some((scope_channel_id(scope).to_string(), message.clone()))
             }
-            ConnectionEvent::User { event } => {
-                self.process_user(state, event);
+            _ => None,
+        };
+        process_event(state, event, &retention);
+
+        if let Some((channel_id, message)) = digested {
+            let policy = self.digest.read().await;
+            if let Some(channel) = state
+                .channels
+                .get_mut(&channel_id)
+                .filter(|channel| policy.applies_to(&channel.channel))
+            {
+                if let Some(digest) = add_to_digest(channel, message, &policy, received_at) {
+                    let _ = self.digest_tx.send(DigestNotification {
+                        connection_id: connection_id.to_string(),
+                        digest,
+                    });
+                }
             }
-            ConnectionEvent::Chat { event } => {
-                self.process_chat(state, event);
+        }
+
+        if let Some((channel_id, from)) = request {
+            if state.pending_requests.contains_key(&channel_id)
+                && self.direct_requests.read().await.auto_accepts(state, &from)
+            {
+                let accept = ConnectionEvent::Channel {
+                    event: ChannelEvent::Accept { channel_id },
+                };
+                record_event(
+                    &self.journals,
+                    connection_id,
+                    received_at,
+                    state,
+                    &accept,
+                    &retention,
+                )
+                .await;
+                process_event(state, accept.clone(), &retention);
+                let _ = self.auto_reply_tx.send((connection_id.to_string(), accept));
             }
-            ConnectionEvent::Asset { event } => {
-                self.process_asset(state, event);
+        }
+
+        if let (Some(scope), Some(recent)) = (asset_scope, *self.asset_reparse.read().await) {
+            for update in reparse_updates(state, &scope, recent) {
+                record_event(
+                    &self.journals,
+                    connection_id,
+                    received_at,
+                    state,
+                    &update,
+                    &retention,
+                )
+                .await;
+                process_event(state, update.clone(), &retention);
+                let _ = self.reparse_tx.send((connection_id.to_string(), update));
             }
         }
-    }
 
-    fn process_status(&self, state: &mut ConnectionState, event: StatusEvent) {
-        match event {
-            StatusEvent::Connected { .. } => {
-                state.status = ConnectionStatus::Connected;
+        if let Some((scope, message)) = candidate {
+            if let Some(reason) = escalation.reason_for(state, &scope, &message) {
+                let _ = self.priority_tx.send(PriorityNotification {
+                    connection_id: connection_id.to_string(),
+                    scope,
+                    message,
+                    reason,
+                });
             }
-            StatusEvent::Disconnected { .. } => {
-                state.status = ConnectionStatus::Disconnected;
+        }
+
+        if let Some(lifecycle) = lifecycle {
+            // Hooks may call back into the client, so run them without holding any locks.
+            drop((escalation, retention, storage));
+            let hooks = self.hooks.read().await.clone();
+            for hook in hooks {
+                lifecycle.run(hook.as_ref(), connection_id).await;
             }
-            StatusEvent::Ping { .. } => {}
         }
     }
 
-    fn process_channel(&self, state: &mut ConnectionState, event: ChannelEvent) {
-        match event {
-            ChannelEvent::New { channel } => {
-                state
-                    .channels
-                    .entry(channel.id.clone())
-                    .or_insert_with(|| ChannelState::new(channel));
-            }
-            ChannelEvent::Update {
-                channel_id,
-                new_channel,
-            } => {
-                if let Some(channel_state) = state.channels.get_mut(&channel_id) {
-                    channel_state.channel = new_channel;
-                }
-            }
-            ChannelEvent::Remove { channel_id } => {
-                state.channels.remove(&channel_id);
-            }
-            ChannelEvent::Join { channel_id } => {
-                state.get_or_create_channel(&channel_id);
-            }
-            ChannelEvent::Leave { channel_id } => {
-                if state.current_channel.as_ref() == Some(&channel_id) {
-                    state.current_channel = None;
-                }
-            }
-            ChannelEvent::Switch { channel_id } => {
-                state.current_channel = Some(channel_id);
-            }
-            ChannelEvent::Kick { .. } => {
-                state.current_channel = None;
-            }
-            ChannelEvent::Wipe { channel_id } => {
-                if let Some(cid) = channel_id {
-                    if let Some(channel_state) = state.channels.get_mut(&cid) {
-                        channel_state.messages.clear();
-                    }
-                }
-            }
-            ChannelEvent::ClearList => {
-                state.channels.clear();
-            }
+    pub fn priority_notifications(&self) -> broadcast::Receiver<PriorityNotification> {
+        self.priority_tx.subscribe()
+    }
+
+    pub async fn set_escalation_policy(&self, policy: EscalationPolicy) {
+        *self.escalation.write().await = policy;
+    }
+
+    pub async fn escalation_policy(&self) -> EscalationPolicy {
+        self.escalation.read().await.clone()
+    }
+
+    pub async fn set_asset_reparse(&self, recent: Option<usize>) {
+        *self.asset_reparse.write().await = recent;
+    }
+
+    pub async fn asset_reparse(&self) -> Option<usize> {
+        *self.asset_reparse.read().await
+    }
+
+    pub fn reparsed_messages(&self) -> broadcast::Receiver<(String, ConnectionEvent)> {
+        self.reparse_tx.subscribe()
+    }
+
+    pub async fn set_direct_request_policy(&self, policy: DirectRequestPolicy) {
+        *self.direct_requests.write().await = policy;
+    }
+
+    pub async fn direct_request_policy(&self) -> DirectRequestPolicy {
+        self.direct_requests.read().await.clone()
+    }
+
+    /// Outbound events produced by policies, such as auto-accepted direct requests, for the
+    /// caller to forward to the connection.
+    pub fn auto_replies(&self) -> broadcast::Receiver<(String, ConnectionEvent)> {
+        self.auto_reply_tx.subscribe()
+    }
+
+    pub async fn set_media_ignores(&self, ignores: MediaIgnoreList) {
+        *self.media_ignores.write().await = ignores;
+    }
+
+    pub async fn media_ignores(&self) -> MediaIgnoreList {
+        self.media_ignores.read().await.clone()
+    }
+
+    /// Starts or stops collapsing media from `user_id`. Only messages processed afterwards are
+    /// affected.
+    pub async fn set_media_ignored(&self, user_id: &str, ignored: bool) {
+        let mut ignores = self.media_ignores.write().await;
+        if ignored {
+            ignores.insert(user_id);
+        } else {
+            ignores.remove(user_id);
         }
     }
 
-    fn process_user(&self, state: &mut ConnectionState, event: UserEvent) {
-        match event {
-            UserEvent::New { channel_id, user } => {
-                let user_id = user.id.clone().unwrap_or_default();
-                if let Some(cid) = channel_id {
-                    let channel = state.get_or_create_channel(&cid);
-                    channel.users.insert(user_id, user);
-                } else {
-                    state.global_users.insert(user_id, user);
-                }
-            }
-            UserEvent::Update {
-                channel_id,
-                user_id,
-                new_user,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(channel) = state.channels.get_mut(&cid) {
-                        channel.users.insert(user_id, new_user);
-                    }
-                } else {
-                    state.global_users.insert(user_id, new_user);
-                }
-            }
-            UserEvent::Remove {
-                channel_id,
-                user_id,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(channel) = state.channels.get_mut(&cid) {
-                        channel.users.remove(&user_id);
-                    }
-                } else {
-                    state.global_users.remove(&user_id);
-                }
-            }
-            UserEvent::ClearList { channel_id } => {
-                if let Some(cid) = channel_id {
-                    if let Some(channel) = state.channels.get_mut(&cid) {
-                        channel.users.clear();
-                    }
-                } else {
-                    state.global_users.clear();
+    pub async fn set_query_limits(&self, limits: QueryLimits) {
+        *self.query_limits.write().await = limits;
+    }
+
+    pub async fn query_limits(&self) -> QueryLimits {
+        self.query_limits.read().await.clone()
+    }
+
+    pub async fn set_digest_policy(&self, policy: DigestPolicy) {
+        *self.digest.write().await = policy;
+    }
+
+    pub async fn digest_policy(&self) -> DigestPolicy {
+        self.digest.read().await.clone()
+    }
+
+    /// Digests of broadcast channels, sent once their window has closed.
+    pub fn digests(&self) -> broadcast::Receiver<DigestNotification> {
+        self.digest_tx.subscribe()
+    }
+
+    /// Closes every digest whose window has elapsed by now, broadcasts them and returns them.
+    /// Digests otherwise only close when the next message in their channel arrives.
+    pub async fn flush_digests(&self) -> Vec<DigestNotification> {
+        let policy = self.digest.read().await.clone();
+        let now = Utc::now();
+        let mut storage = self.storage.write().await;
+        let mut flushed = Vec::new();
+        for connection_id in storage.list_connections() {
+            let Some(state) = storage.get_mut(&connection_id) else {
+                continue;
+            };
+            for channel in state.channels.values_mut() {
+                if let Some(digest) = flush_digest(channel, &policy, now) {
+                    flushed.push(DigestNotification {
+                        connection_id: connection_id.clone(),
+                        digest,
+                    });
                 }
             }
-            UserEvent::Identify { user_id } => {
-                state.current_user_id = Some(user_id);
-            }
         }
+        for notification in &flushed {
+            let _ = self.digest_tx.send(notification.clone());
+        }
+        flushed
     }
 
-    fn process_chat(&self, state: &mut ConnectionState, event: ChatEvent) {
-        match event {
-            ChatEvent::New {
-                channel_id,
-                message,
-            } => {
-                if let Some(cid) = channel_id {
-                    let channel = state.get_or_create_channel(&cid);
-                    channel.messages.push(message);
-                }
-            }
-            ChatEvent::Update {
-                channel_id,
-                message_id,
-                new_message,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(channel) = state.channels.get_mut(&cid) {
-                        if let Some(msg) = channel
-                            .messages
-                            .iter_mut()
-                            .find(|m| m.id.as_ref() == Some(&message_id))
-                        {
-                            *msg = new_message;
-                        }
-                    }
-                }
-            }
-            ChatEvent::Remove {
-                channel_id,
-                message_id,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(channel) = state.channels.get_mut(&cid) {
-                        channel
-                            .messages
-                            .retain(|m| m.id.as_ref() != Some(&message_id));
-                    }
-                }
-            }
+    /// Closed digests for a channel followed by the open one, if any.
+    pub async fn get_digests(&self, connection_id: &str, channel_id: &str) -> Vec<Digest> {
+        let Some(mut channel) = self.get_channel(connection_id, channel_id).await else {
+            return Vec::new();
+        };
+        channel.digests.extend(channel.pending_digest);
+        channel.digests
+    }
+
+    pub async fn pending_requests(&self, connection_id: &str) -> Vec<DirectRequest> {
+        let storage = self.storage.read().await;
+        let Some(state) = storage.get(connection_id) else {
+            return Vec::new();
+        };
+        let mut requests: Vec<DirectRequest> = state.pending_requests.values().cloned().collect();
+        requests.sort_by_key(|request| request.received_at);
+        requests
+    }
+
+    pub async fn accept_request<C: Connection + ?Sized>(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        connection: &mut C,
+    ) -> Result<(), ConnectionError> {
+        let event = ChannelEvent::Accept {
+            channel_id: channel_id.to_string(),
+        };
+        self.respond_to_request(connection_id, event, connection)
+            .await
+    }
+
+    pub async fn decline_request<C: Connection + ?Sized>(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        connection: &mut C,
+    ) -> Result<(), ConnectionError> {
+        let event = ChannelEvent::Decline {
+            channel_id: channel_id.to_string(),
+        };
+        self.respond_to_request(connection_id, event, connection)
+            .await
+    }
+
+    async fn respond_to_request<C: Connection + ?Sized>(
+        &self,
+        connection_id: &str,
+        event: ChannelEvent,
+        connection: &mut C,
+    ) -> Result<(), ConnectionError> {
+        let event = ConnectionEvent::Channel { event };
+        connection.send(event.clone()).await?;
+        self.process(connection_id, event).await;
+        Ok(())
+    }
+
+    pub async fn enable_journal(&self, max_entries: Option<usize>) {
+        let retention = self.retention.read().await;
+        let storage = self.storage.read().await;
+        let mut journals = self.journals.write().await;
+        if journals.is_some() {
+            return;
         }
+        let connections = storage
+            .list_connections()
+            .into_iter()
+            .filter_map(|id| {
+                let state = storage.get(&id)?;
+                Some((id, Journal::new(state, max_entries, retention.clone())))
+            })
+            .collect();
+        *journals = Some(Journals {
+            max_entries,
+            connections,
+        });
     }
 
-    fn process_asset(&self, state: &mut ConnectionState, event: AssetEvent) {
-        match event {
-            AssetEvent::New { channel_id, asset } => {
-                let asset_id = get_asset_id(&asset).unwrap_or_default();
-                if let Some(cid) = channel_id {
-                    let channel = state.get_or_create_channel(&cid);
-                    channel.assets.insert(asset_id, asset);
-                } else {
-                    state.global_assets.insert(asset_id, asset);
-                }
-            }
-            AssetEvent::Update {
-                channel_id,
-                asset_id,
-                new_asset,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(channel) = state.channels.get_mut(&cid) {
-                        channel.assets.insert(asset_id, new_asset);
-                    }
-                } else {
-                    state.global_assets.insert(asset_id, new_asset);
-                }
-            }
-            AssetEvent::Remove {
-                channel_id,
-                asset_id,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(channel) = state.channels.get_mut(&cid) {
-                        channel.assets.remove(&asset_id);
-                    }
-                } else {
-                    state.global_assets.remove(&asset_id);
+    pub async fn journal(&self, connection_id: &str) -> Option<Journal> {
+        self.journals
+            .read()
+            .await
+            .as_ref()?
+            .connections
+            .get(connection_id)
+            .cloned()
+    }
+
+    pub async fn state_at(
+        &self,
+        connection_id: &str,
+        timestamp: DateTime<Utc>,
+    ) -> Option<ConnectionState> {
+        let journals = self.journals.read().await;
+        journals
+            .as_ref()?
+            .connections
+            .get(connection_id)?
+            .state_at(timestamp)
+    }
+
+    pub async fn set_retention_policy(&self, policy: RetentionPolicy) {
+        let mut storage = self.storage.write().await;
+        for connection_id in storage.list_connections() {
+            if let Some(state) = storage.get_mut(&connection_id) {
+                for channel in state.channels.values_mut() {
+                    policy.apply(channel);
                 }
             }
-            AssetEvent::ClearList { channel_id } => {
-                if let Some(cid) = channel_id {
-                    if let Some(channel) = state.channels.get_mut(&cid) {
-                        channel.assets.clear();
-                    }
-                } else {
-                    state.global_assets.clear();
-                }
+        }
+        if let Some(journals) = self.journals.write().await.as_mut() {
+            let changed_at = Utc::now();
+            for journal in journals.connections.values_mut() {
+                journal.record_retention(policy.clone(), changed_at);
             }
         }
+        *self.retention.write().await = policy;
+    }
+
+    pub async fn add_middleware(&self, middleware: impl EventMiddleware + 'static) {
+        self.middleware.write().await.push(middleware);
+    }
+
+    pub async fn add_lifecycle_hook(&self, hook: impl LifecycleHook + 'static) {
+        self.hooks.write().await.push(Arc::new(hook));
+    }
+
+    pub async fn retention_policy(&self) -> RetentionPolicy {
+        self.retention.read().await.clone()
     }
 
     pub fn spawn_processor(
@@ -279,28 +587,64 @@ impl<S: StateStorage + 'static> StateClient<S> {
         connection_id: String,
         mut rx: mpsc::UnboundedReceiver<ConnectionEvent>,
     ) -> JoinHandle<()> {
-        let storage = self.storage.clone();
+        let client = self.handle();
         tokio::spawn(async move {
             while let Some(event) = rx.recv().await {
-                let mut storage = storage.write().await;
-                if let Some(state) = storage.get_mut(&connection_id) {
-                    process_event(state, event);
-                }
+                client.apply(&connection_id, event, Utc::now(), None).await;
+            }
+        })
+    }
+
+    /// Like `spawn_processor`, for envelopes merged from any number of tracked connections.
+    pub fn spawn_envelope_processor(
+        &self,
+        mut rx: mpsc::UnboundedReceiver<Envelope>,
+    ) -> JoinHandle<()> {
+        let client = self.handle();
+        tokio::spawn(async move {
+            while let Some(envelope) = rx.recv().await {
+                client.process_envelope(envelope).await;
             }
         })
     }
 
+    /// Another handle onto the same shared state, so spawned processors go through `apply`
+    /// like every other entry point.
+    fn handle(&self) -> Self {
+        StateClient {
+            storage: self.storage.clone(),
+            retention: self.retention.clone(),
+            journals: self.journals.clone(),
+            ids: self.ids.clone(),
+            middleware: self.middleware.clone(),
+            escalation: self.escalation.clone(),
+            priority_tx: self.priority_tx.clone(),
+            asset_reparse: self.asset_reparse.clone(),
+            reparse_tx: self.reparse_tx.clone(),
+            direct_requests: self.direct_requests.clone(),
+            auto_reply_tx: self.auto_reply_tx.clone(),
+            digest: self.digest.clone(),
+            digest_tx: self.digest_tx.clone(),
+            media_ignores: self.media_ignores.clone(),
+            query_limits: self.query_limits.clone(),
+            hooks: self.hooks.clone(),
+            normalizers: self.normalizers.clone(),
+        }
+    }
+
     pub async fn get_connection(&self, connection_id: &str) -> Option<ConnectionState> {
         self.storage.read().await.get(connection_id)
     }
 
     pub async fn get_channel(&self, connection_id: &str, channel_id: &str) -> Option<ChannelState> {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
         let storage = self.storage.read().await;
         let state = storage.get(connection_id)?;
         state.channels.get(channel_id).cloned()
     }
 
     pub async fn get_user(&self, connection_id: &str, user_id: &str) -> Option<Profile> {
+        let user_id = &self.user_key(connection_id, user_id).await;
         let storage = self.storage.read().await;
         let state = storage.get(connection_id)?;
 
@@ -317,7 +661,308 @@ impl<S: StateStorage + 'static> StateClient<S> {
         None
     }
 
+    pub async fn sender_profile(&self, connection_id: &str, message: &Message) -> Option<Profile> {
+        let sender_id = &self
+            .user_key(connection_id, message.sender_id.as_deref()?)
+            .await;
+        {
+            let storage = self.storage.read().await;
+            let state = storage.get(connection_id)?;
+            if let Some(profile) = state.profile_at(sender_id, message.timestamp) {
+                return Some(profile.clone());
+            }
+        }
+        self.get_user(connection_id, sender_id).await
+    }
+
+    pub async fn resolve_user<C: Connection + ?Sized>(
+        &self,
+        connection_id: &str,
+        user_id: &str,
+        connection: &mut C,
+    ) -> Result<Profile, ConnectionError> {
+        if let Some(user) = self.get_user(connection_id, user_id).await {
+            return Ok(user);
+        }
+        let user = connection.fetch_profile(user_id).await?;
+        let user_id = self.user_key(connection_id, user_id).await;
+        let mut storage = self.storage.write().await;
+        if let Some(state) = storage.get_mut(connection_id) {
+            state.global_users.insert(user_id, user.clone());
+        }
+        Ok(user)
+    }
+
+    pub async fn get_presence(&self, connection_id: &str, user_id: &str) -> Option<Presence> {
+        let user_id = &self.user_key(connection_id, user_id).await;
+        let storage = self.storage.read().await;
+        let state = storage.get(connection_id)?;
+        state.presence.get(user_id).cloned()
+    }
+
+    pub async fn register_commands(&self, connection_id: &str, commands: Vec<CommandSpec>) {
+        let mut storage = self.storage.write().await;
+        if let Some(state) = storage.get_mut(connection_id) {
+            state.commands = commands;
+        }
+    }
+
+    pub async fn complete(
+        &self,
+        connection_id: &str,
+        channel_id: Option<&str>,
+        prefix: &str,
+    ) -> Vec<Completion> {
+        let channel_id = match channel_id {
+            Some(channel_id) => Some(self.channel_key(connection_id, channel_id).await),
+            None => None,
+        };
+        let storage = self.storage.read().await;
+        let Some(state) = storage.get(connection_id) else {
+            return Vec::new();
+        };
+        state.completions.complete(prefix, channel_id.as_deref())
+    }
+
+    pub async fn get_commands(&self, connection_id: &str, prefix: &str) -> Vec<CommandSpec> {
+        let storage = self.storage.read().await;
+        let Some(state) = storage.get(connection_id) else {
+            return Vec::new();
+        };
+        let prefix = prefix.trim_start_matches('/');
+        let mut commands: Vec<CommandSpec> = state
+            .commands
+            .iter()
+            .filter(|c| c.name.starts_with(prefix))
+            .cloned()
+            .collect();
+        commands.sort_by(|a, b| a.name.cmp(&b.name));
+        commands
+    }
+
+    pub async fn find_commands(&self, prefix: &str) -> Vec<(String, CommandSpec)> {
+        let mut found = Vec::new();
+        for connection_id in self.list_connections().await {
+            for command in self.get_commands(&connection_id, prefix).await {
+                found.push((connection_id.clone(), command));
+            }
+        }
+        found.sort_by(|a, b| a.1.name.cmp(&b.1.name).then_with(|| a.0.cmp(&b.0)));
+        found
+    }
+
+    pub async fn load_older_messages<C: Connection + ?Sized>(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        connection: &mut C,
+        limit: usize,
+    ) -> Result<usize, ConnectionError> {
+        let before = self
+            .get_channel(connection_id, channel_id)
+            .await
+            .and_then(|c| c.messages.first().and_then(|m| m.id.clone()));
+        let older = connection.fetch_history(channel_id, before, limit).await?;
+
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
+        let mut storage = self.storage.write().await;
+        let Some(state) = storage.get_mut(connection_id) else {
+            return Ok(0);
+        };
+        let channel = state.get_or_create_channel(channel_id);
+        let older: Vec<Message> = older
+            .into_iter()
+            .filter(|m| m.id.is_none() || !channel.messages.iter().any(|e| e.id == m.id))
+            .collect();
+        let loaded = older.len();
+        channel.messages.splice(0..0, older);
+        channel.reindex_threads();
+        Ok(loaded)
+    }
+
+    pub async fn annotate(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        message_id: &str,
+        key: &str,
+        value: &str,
+    ) -> bool {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
+        let mut storage = self.storage.write().await;
+        let Some(channel) = storage
+            .get_mut(connection_id)
+            .and_then(|state| state.channels.get_mut(channel_id))
+        else {
+            return false;
+        };
+        if !channel
+            .messages
+            .iter()
+            .any(|m| m.id.as_deref() == Some(message_id))
+        {
+            return false;
+        }
+        channel
+            .annotations
+            .entry(message_id.to_string())
+            .or_default()
+            .insert(key.to_string(), value.to_string());
+        true
+    }
+
+    pub async fn remove_annotation(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        message_id: &str,
+        key: &str,
+    ) {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
+        let mut storage = self.storage.write().await;
+        let Some(channel) = storage
+            .get_mut(connection_id)
+            .and_then(|state| state.channels.get_mut(channel_id))
+        else {
+            return;
+        };
+        if let Some(notes) = channel.annotations.get_mut(message_id) {
+            notes.remove(key);
+            if notes.is_empty() {
+                channel.annotations.remove(message_id);
+            }
+        }
+    }
+
+    pub async fn get_annotations(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        message_id: &str,
+    ) -> BTreeMap<String, String> {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
+        let storage = self.storage.read().await;
+        storage
+            .get(connection_id)
+            .and_then(|state| {
+                state
+                    .channels
+                    .get(channel_id)?
+                    .annotations
+                    .get(message_id)
+                    .cloned()
+            })
+            .unwrap_or_default()
+    }
+
+    pub async fn add_watch(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        pattern: &str,
+    ) -> Result<Option<String>, regex::Error> {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
+        let mut watch = Watch::new(self.ids.next_id(), pattern)?;
+        let mut storage = self.storage.write().await;
+        let Some(state) = storage.get_mut(connection_id) else {
+            return Ok(None);
+        };
+        let channel = state.get_or_create_channel(channel_id);
+        for message in &channel.messages {
+            watch.scan(message);
+        }
+        let watch_id = watch.id.clone();
+        channel.watches.push(watch);
+        Ok(Some(watch_id))
+    }
+
+    pub async fn remove_watch(&self, connection_id: &str, channel_id: &str, watch_id: &str) {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
+        let mut storage = self.storage.write().await;
+        if let Some(channel) = storage
+            .get_mut(connection_id)
+            .and_then(|state| state.channels.get_mut(channel_id))
+        {
+            channel.watches.retain(|w| w.id != watch_id);
+        }
+    }
+
+    pub async fn get_watch_matches(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        watch_id: &str,
+    ) -> Vec<WatchMatch> {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
+        let storage = self.storage.read().await;
+        let Some(state) = storage.get(connection_id) else {
+            return Vec::new();
+        };
+        state
+            .channels
+            .get(channel_id)
+            .and_then(|c| c.watches.iter().find(|w| w.id == watch_id))
+            .map(|w| w.matches.clone())
+            .unwrap_or_default()
+    }
+
+    pub async fn seen_by(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        message_id: &str,
+    ) -> Vec<String> {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
+        let storage = self.storage.read().await;
+        let Some(state) = storage.get(connection_id) else {
+            return Vec::new();
+        };
+        state
+            .channels
+            .get(channel_id)
+            .map(|c| c.seen_by(message_id))
+            .unwrap_or_default()
+    }
+
+    pub async fn get_thread(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        root_id: &str,
+    ) -> Vec<Message> {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
+        let storage = self.storage.read().await;
+        let Some(state) = storage.get(connection_id) else {
+            return Vec::new();
+        };
+        state
+            .channels
+            .get(channel_id)
+            .map(|c| c.thread(root_id))
+            .unwrap_or_default()
+    }
+
+    pub async fn trending_messages(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        window: Duration,
+        limit: usize,
+    ) -> Vec<(Message, usize)> {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
+        let storage = self.storage.read().await;
+        let Some(state) = storage.get(connection_id) else {
+            return Vec::new();
+        };
+        state
+            .channels
+            .get(channel_id)
+            .map(|c| c.trending(window, limit))
+            .unwrap_or_default()
+    }
+
     pub async fn get_messages(&self, connection_id: &str, channel_id: &str) -> Vec<Message> {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
         let storage = self.storage.read().await;
         let Some(state) = storage.get(connection_id) else {
             return Vec::new();
@@ -330,6 +975,10 @@ impl<S: StateStorage + 'static> StateClient<S> {
     }
 
     pub async fn get_assets(&self, connection_id: &str, channel_id: Option<&str>) -> Vec<Asset> {
+        let channel_id = match channel_id {
+            Some(channel_id) => Some(self.channel_key(connection_id, channel_id).await),
+            None => None,
+        };
         let storage = self.storage.read().await;
         let Some(state) = storage.get(connection_id) else {
             return Vec::new();
@@ -338,7 +987,7 @@ impl<S: StateStorage + 'static> StateClient<S> {
         match channel_id {
             Some(cid) => state
                 .channels
-                .get(cid)
+                .get(&cid)
                 .map(|c| c.assets.values().cloned().collect())
                 .unwrap_or_default(),
             None => state.global_assets.values().cloned().collect(),
@@ -348,6 +997,194 @@ impl<S: StateStorage + 'static> StateClient<S> {
     pub async fn list_connections(&self) -> Vec<String> {
         self.storage.read().await.list_connections()
     }
+
+    #[cfg(feature = "sync")]
+    pub async fn export_bundle(
+        &self,
+        connection_id: &str,
+        channel_ids: &[&str],
+        since: DateTime<Utc>,
+    ) -> Option<SyncBundle> {
+        let storage = self.storage.read().await;
+        let state = storage.get(connection_id)?;
+        let mut bundle = SyncBundle::new(state.protocol_name.clone(), since);
+        let ids = self.normalizers.read().await.get(connection_id).cloned();
+        for channel_id in channel_ids {
+            let channel_id = match &ids {
+                Some(ids) => ids.normalize_channel_id(channel_id),
+                None => channel_id.to_string(),
+            };
+            if let Some(channel) = state.channels.get(&channel_id) {
+                let messages: Vec<Message> = channel
+                    .messages
+                    .iter()
+                    .filter(|m| m.timestamp >= since)
+                    .cloned()
+                    .collect();
+                let annotations = messages
+                    .iter()
+                    .filter_map(|m| m.id.as_ref())
+                    .filter_map(|id| Some((id.clone(), channel.annotations.get(id)?.clone())))
+                    .collect();
+                bundle.channels.push(BundleChannel {
+                    channel: channel.channel.clone(),
+                    messages,
+                    annotations,
+                });
+            }
+        }
+        Some(bundle)
+    }
+
+    #[cfg(feature = "sync")]
+    pub async fn import_bundle(
+        &self,
+        connection_id: &str,
+        bundle: SyncBundle,
+    ) -> Result<usize, SyncError> {
+        let ids = self.normalizers.read().await.get(connection_id).cloned();
+        let mut storage = self.storage.write().await;
+        let Some(state) = storage.get_mut(connection_id) else {
+            return Ok(0);
+        };
+        if bundle.protocol_name != state.protocol_name {
+            return Err(SyncError::ProtocolMismatch {
+                expected: state.protocol_name.clone(),
+                found: bundle.protocol_name,
+            });
+        }
+        let mut added = 0;
+        for mut entry in bundle.channels {
+            if let Some(ids) = &ids {
+                entry.channel.id = ids.normalize_channel_id(&entry.channel.id);
+            }
+            let channel = state
+                .channels
+                .entry(entry.channel.id.clone())
+                .or_insert_with(|| ChannelState::new(entry.channel));
+            added += merge_messages(&mut channel.messages, entry.messages);
+            channel.reindex_threads();
+            for (message_id, notes) in entry.annotations {
+                channel
+                    .annotations
+                    .entry(message_id)
+                    .or_default()
+                    .extend(notes);
+            }
+        }
+        Ok(added)
+    }
+
+    pub async fn purge_messages_before(
+        &self,
+        before: DateTime<Utc>,
+        progress: impl FnMut(BulkProgress) + Send,
+    ) -> BulkReport {
+        self.bulk(BulkOperation::PurgeMessages, progress, |_| {
+            BulkChange::PurgeBefore(before)
+        })
+        .await
+    }
+
+    pub async fn remove_user_everywhere(
+        &self,
+        user_id: &str,
+        progress: impl FnMut(BulkProgress) + Send,
+    ) -> BulkReport {
+        let normalizers = self.normalizers.read().await.clone();
+        self.bulk(BulkOperation::RemoveUser, progress, |state| {
+            BulkChange::RemoveUser(match normalizers.get(&state.connection_id) {
+                Some(ids) => ids.normalize_user_id(user_id),
+                None => user_id.to_string(),
+            })
+        })
+        .await
+    }
+
+    pub async fn reparse_assets(&self, progress: impl FnMut(BulkProgress) + Send) -> BulkReport {
+        self.bulk(BulkOperation::ReparseAssets, progress, |_| {
+            BulkChange::ReparseAssets
+        })
+        .await
+    }
+
+    /// Applies `change` to every connection, journaling it wherever it changed something.
+    async fn bulk(
+        &self,
+        operation: BulkOperation,
+        mut progress: impl FnMut(BulkProgress) + Send,
+        change: impl Fn(&ConnectionState) -> BulkChange + Send,
+    ) -> BulkReport {
+        let mut storage = self.storage.write().await;
+        let mut journals = self.journals.write().await;
+        let connection_ids = storage.list_connections();
+        let total = connection_ids.len();
+        let mut report = BulkReport::default();
+        for (index, connection_id) in connection_ids.into_iter().enumerate() {
+            if let Some(state) = storage.get_mut(&connection_id) {
+                let change = change(state);
+                let (channels, affected) = change.apply(state);
+                if affected > 0 {
+                    report.connections += 1;
+                    report.channels += channels;
+                    report.affected += affected;
+                    if let Some(journal) = journals
+                        .as_mut()
+                        .and_then(|journals| journals.connections.get_mut(&connection_id))
+                    {
+                        journal.record_bulk(change, Utc::now());
+                    }
+                }
+            }
+            progress(BulkProgress {
+                operation: operation.clone(),
+                connection_id,
+                completed: index + 1,
+                total,
+            });
+        }
+        report
+    }
+
+    pub async fn connection_summary(&self, connection_id: &str) -> Option<ConnectionSummary> {
+        let storage = self.storage.read().await;
+        let state = storage.get(connection_id)?;
+        Some(ConnectionSummary::from(&state))
+    }
+
+    pub async fn list_summaries(&self) -> Vec<ConnectionSummary> {
+        let storage = self.storage.read().await;
+        storage
+            .list_connections()
+            .iter()
+            .filter_map(|id| storage.get(id))
+            .map(|state| ConnectionSummary::from(&state))
+            .collect()
+    }
+
+    pub async fn channel_snapshot(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+    ) -> Option<ChannelSnapshot> {
+        let channel_id = &self.channel_key(connection_id, channel_id).await;
+        let storage = self.storage.read().await;
+        let state = storage.get(connection_id)?;
+        state.channels.get(channel_id).map(ChannelSnapshot::from)
+    }
+
+    /// A `channel_snapshot` cut down to `query`, within the limits from `set_query_limits`.
+    pub async fn partial_snapshot(
+        &self,
+        connection_id: &str,
+        channel_id: &str,
+        query: SnapshotQuery,
+    ) -> Option<ChannelSnapshot> {
+        let query = query.clamp(&*self.query_limits.read().await);
+        let mut snapshot = self.channel_snapshot(connection_id, channel_id).await?;
+        snapshot.apply_query(&query);
+        Some(snapshot)
+    }
 }
 
 impl Default for StateClient<InMemoryStorage> {
@@ -365,183 +1202,532 @@ fn get_asset_id(asset: &Asset) -> Option<String> {
     }
 }
 
-fn process_event(state: &mut ConnectionState, event: ConnectionEvent) {
+fn reparse_updates(state: &ConnectionState, scope: &Scope, recent: usize) -> Vec<ConnectionEvent> {
+    let global: Vec<&Asset> = state.global_assets.values().collect();
+    state
+        .channels
+        .iter()
+        .filter(|(cid, _)| scope.channel_id().is_none_or(|id| id == cid.as_str()))
+        .flat_map(|(cid, channel)| {
+            let assets: Vec<Asset> = global
+                .iter()
+                .copied()
+                .chain(channel.assets.values())
+                .cloned()
+                .collect();
+            let scope = channel_scope(cid);
+            channel
+                .reparsed_messages(&assets, recent)
+                .into_iter()
+                .filter_map(move |(_, message)| {
+                    Some(ConnectionEvent::Chat {
+                        event: ChatEvent::Update {
+                            scope: scope.clone(),
+                            message_id: message.id.clone()?,
+                            new_message: message,
+                        },
+                    })
+                })
+        })
+        .collect()
+}
+
+async fn record_event(
+    journals: &RwLock<Option<Journals>>,
+    connection_id: &str,
+    received_at: DateTime<Utc>,
+    state: &ConnectionState,
+    event: &ConnectionEvent,
+    retention: &RetentionPolicy,
+) {
+    let mut journals = journals.write().await;
+    let Some(journals) = journals.as_mut() else {
+        return;
+    };
+    let max_entries = journals.max_entries;
+    journals
+        .connections
+        .entry(connection_id.to_string())
+        .or_insert_with(|| Journal::new(state.clone(), max_entries, retention.clone()))
+        .record_at(event.clone(), received_at);
+}
+
+pub(crate) fn process_event(
+    state: &mut ConnectionState,
+    event: ConnectionEvent,
+    retention: &RetentionPolicy,
+) {
+    process_event_at(state, event, retention, Utc::now());
+}
+
+/// Like `process_event`, applying retention as of `now`.
+pub(crate) fn process_event_at(
+    state: &mut ConnectionState,
+    event: ConnectionEvent,
+    retention: &RetentionPolicy,
+    now: DateTime<Utc>,
+) {
+    state.completions.index_event(&event);
+    match event {
+        ConnectionEvent::Status { event } => process_status(state, event),
+        ConnectionEvent::Channel { event } => process_channel(state, event),
+        ConnectionEvent::User { event } => process_user(state, event),
+        ConnectionEvent::Chat { event } => process_chat(state, event, retention, now),
+        ConnectionEvent::Asset { event } => process_asset(state, event),
+        ConnectionEvent::Moderation { event } => process_moderation(state, event),
+    }
+}
+
+fn process_status(state: &mut ConnectionState, event: StatusEvent) {
     match event {
-        ConnectionEvent::Status { event } => match event {
-            StatusEvent::Connected { .. } => state.status = ConnectionStatus::Connected,
-            StatusEvent::Disconnected { .. } => state.status = ConnectionStatus::Disconnected,
-            StatusEvent::Ping { .. } => {}
-        },
-        ConnectionEvent::Channel { event } => match event {
-            ChannelEvent::New { channel } => {
-                state
-                    .channels
-                    .entry(channel.id.clone())
-                    .or_insert_with(|| ChannelState::new(channel));
-            }
-            ChannelEvent::Update {
-                channel_id,
-                new_channel,
-            } => {
-                if let Some(cs) = state.channels.get_mut(&channel_id) {
-                    cs.channel = new_channel;
-                }
+        StatusEvent::Connecting => {
+            state.status = ConnectionStatus::Connecting;
+        }
+        StatusEvent::Connected { .. } => {
+            state.status = ConnectionStatus::Connected;
+            state.last_error = None;
+            state.maintenance = None;
+        }
+        StatusEvent::Reconnecting { attempt } => {
+            state.status = ConnectionStatus::Reconnecting { attempt };
+        }
+        StatusEvent::Disconnected { .. } => {
+            if !matches!(state.status, ConnectionStatus::AuthFailed { .. }) {
+                state.status = ConnectionStatus::Disconnected;
             }
-            ChannelEvent::Remove { channel_id } => {
-                state.channels.remove(&channel_id);
+        }
+        StatusEvent::AuthFailed { reason } => {
+            state.last_error = Some(reason.clone());
+            state.status = ConnectionStatus::AuthFailed { reason };
+        }
+        StatusEvent::Error { message } => {
+            state.last_error = Some(message);
+        }
+        StatusEvent::Maintenance {
+            message,
+            scheduled_at,
+            until,
+        } => {
+            state.maintenance = Some(Maintenance {
+                message,
+                announced_at: Utc::now(),
+                scheduled_at,
+                until,
+            });
+        }
+        StatusEvent::Ping { .. } | StatusEvent::Synced | StatusEvent::Throttled { .. } => {}
+    }
+}
+
+fn process_channel(state: &mut ConnectionState, event: ChannelEvent) {
+    match event {
+        ChannelEvent::New { channel } => {
+            state
+                .channels
+                .entry(channel.id.clone())
+                .or_insert_with(|| ChannelState::new(channel));
+        }
+        ChannelEvent::Update {
+            channel_id,
+            new_channel,
+        } => {
+            if let Some(channel_state) = state.channels.get_mut(&channel_id) {
+                channel_state.channel = new_channel;
             }
-            ChannelEvent::Join { channel_id } => {
-                state.get_or_create_channel(&channel_id);
+        }
+        ChannelEvent::Remove { channel_id } => {
+            state.channels.remove(&channel_id);
+        }
+        ChannelEvent::Join { channel_id } => {
+            state.get_or_create_channel(&channel_id);
+        }
+        ChannelEvent::Leave { channel_id } => {
+            if state.current_channel.as_ref() == Some(&channel_id) {
+                state.current_channel = None;
             }
-            ChannelEvent::Leave { channel_id } => {
-                if state.current_channel.as_ref() == Some(&channel_id) {
-                    state.current_channel = None;
-                }
+        }
+        ChannelEvent::Switch { channel_id } => {
+            state.current_channel = Some(channel_id);
+        }
+        ChannelEvent::Kick {
+            scope,
+            reason,
+            ban,
+            until,
+        } => {
+            state.current_channel = None;
+            if ban {
+                state.ban = Some(Ban {
+                    channel_id: scope.into(),
+                    reason,
+                    issued_at: Utc::now(),
+                    until,
+                });
             }
-            ChannelEvent::Switch { channel_id } => {
-                state.current_channel = Some(channel_id);
+        }
+        ChannelEvent::Wipe { scope } => {
+            if let Some(channel_state) = state.channels.get_mut(scope_channel_id(&scope)) {
+                channel_state.messages.clear();
+                channel_state.pending_digest = None;
+                channel_state.digests.clear();
+                channel_state.forget_evicted();
             }
-            ChannelEvent::Kick { .. } => {
-                state.current_channel = None;
+        }
+        ChannelEvent::TopicChanged {
+            channel_id, topic, ..
+        } => {
+            state.get_or_create_channel(&channel_id).channel.topic = topic;
+        }
+        ChannelEvent::Request { channel, from } => {
+            if !state.channels.contains_key(&channel.id) {
+                state.pending_requests.insert(
+                    channel.id.clone(),
+                    DirectRequest {
+                        channel,
+                        from,
+                        received_at: Utc::now(),
+                    },
+                );
             }
-            ChannelEvent::Wipe { channel_id } => {
-                if let Some(cid) = channel_id {
-                    if let Some(cs) = state.channels.get_mut(&cid) {
-                        cs.messages.clear();
-                    }
-                }
+        }
+        ChannelEvent::Accept { channel_id } => {
+            if let Some(request) = state.pending_requests.remove(&channel_id) {
+                state
+                    .channels
+                    .entry(channel_id)
+                    .or_insert_with(|| ChannelState::new(request.channel));
             }
-            ChannelEvent::ClearList => {
-                state.channels.clear();
-            }
-        },
-        ConnectionEvent::User { event } => match event {
-            UserEvent::New { channel_id, user } => {
-                let uid = user.id.clone().unwrap_or_default();
-                if let Some(cid) = channel_id {
-                    state.get_or_create_channel(&cid).users.insert(uid, user);
-                } else {
-                    state.global_users.insert(uid, user);
-                }
+        }
+        ChannelEvent::Decline { channel_id } => {
+            state.pending_requests.remove(&channel_id);
+        }
+        ChannelEvent::ClearList => {
+            state.channels.retain(|id, _| id == LOBBY_CHANNEL_ID);
+            state.prune_presence();
+        }
+    }
+}
+
+fn process_user(state: &mut ConnectionState, event: UserEvent) {
+    match event {
+        UserEvent::New { scope, user } => {
+            let user_id = user.id.clone().unwrap_or_default();
+            state.record_profile(&user_id, &user);
+            if let Some(presence) = &user.presence {
+                state.presence.insert(user_id.clone(), presence.clone());
             }
-            UserEvent::Update {
-                channel_id,
-                user_id,
-                new_user,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(cs) = state.channels.get_mut(&cid) {
-                        cs.users.insert(user_id, new_user);
-                    }
-                } else {
-                    state.global_users.insert(user_id, new_user);
+            if let Scope::Channel(cid) = scope {
+                let channel = state.get_or_create_channel(&cid);
+                channel.insert_user(user_id, user);
+            } else {
+                state.global_users.insert(user_id, user);
+            }
+        }
+        UserEvent::Update {
+            scope,
+            user_id,
+            new_user,
+        } => {
+            state.record_profile(&user_id, &new_user);
+            if let Some(presence) = &new_user.presence {
+                state.presence.insert(user_id.clone(), presence.clone());
+            }
+            if let Scope::Channel(cid) = scope {
+                if let Some(channel) = state.channels.get_mut(&cid) {
+                    channel.insert_user(user_id, new_user);
                 }
+            } else {
+                state.global_users.insert(user_id, new_user);
             }
-            UserEvent::Remove {
-                channel_id,
-                user_id,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(cs) = state.channels.get_mut(&cid) {
-                        cs.users.remove(&user_id);
-                    }
-                } else {
-                    state.global_users.remove(&user_id);
+        }
+        UserEvent::Remove { scope, user_id } => {
+            if let Scope::Channel(cid) = scope {
+                if let Some(channel) = state.channels.get_mut(&cid) {
+                    channel.remove_user(&user_id);
                 }
+            } else {
+                state.global_users.remove(&user_id);
             }
-            UserEvent::ClearList { channel_id } => {
-                if let Some(cid) = channel_id {
-                    if let Some(cs) = state.channels.get_mut(&cid) {
-                        cs.users.clear();
-                    }
-                } else {
-                    state.global_users.clear();
+            state.prune_presence();
+        }
+        UserEvent::ClearList { scope } => {
+            if let Scope::Channel(cid) = scope {
+                if let Some(channel) = state.channels.get_mut(&cid) {
+                    channel.clear_users();
                 }
+            } else {
+                state.global_users.clear();
+            }
+            state.prune_presence();
+        }
+        UserEvent::Identify { user_id } => {
+            state.current_user_id = Some(user_id);
+        }
+        UserEvent::PresenceChanged { user_id, presence } => {
+            if let Some(user) = state.global_users.get_mut(&user_id) {
+                user.presence = Some(presence.clone());
             }
-            UserEvent::Identify { user_id } => {
-                state.current_user_id = Some(user_id);
+            for channel in state.channels.values_mut() {
+                channel.set_presence(&user_id, &presence);
             }
-        },
-        ConnectionEvent::Chat { event } => match event {
-            ChatEvent::New {
-                channel_id,
-                message,
-            } => {
-                if let Some(cid) = channel_id {
-                    state.get_or_create_channel(&cid).messages.push(message);
+            state.presence.insert(user_id, presence);
+        }
+        UserEvent::TypingStart {
+            channel_id,
+            user_id,
+        } => {
+            let channel = state.get_or_create_channel(&channel_id);
+            channel.expire_typing();
+            channel.typing.insert(user_id, Utc::now());
+        }
+        UserEvent::TypingStop {
+            channel_id,
+            user_id,
+        } => {
+            if let Some(channel) = state.channels.get_mut(&channel_id) {
+                channel.typing.remove(&user_id);
+                channel.expire_typing();
+            }
+        }
+        UserEvent::Batch { events } => {
+            for event in events {
+                process_user(state, event);
+            }
+        }
+    }
+}
+
+fn process_chat(
+    state: &mut ConnectionState,
+    event: ChatEvent,
+    retention: &RetentionPolicy,
+    now: DateTime<Utc>,
+) {
+    match event {
+        ChatEvent::New { scope, message } => {
+            let channel = state.scoped_channel(&scope);
+            if let Some(sender_id) = &message.sender_id {
+                channel.typing.remove(sender_id);
+            }
+            for watch in channel.watches.iter_mut() {
+                watch.scan(&message);
+            }
+            channel.index_thread(&message);
+            if let Some(parent_id) = &message.reply_to {
+                if channel.message_index(parent_id).is_some() {
+                    channel.record_activity(parent_id, now);
                 }
             }
-            ChatEvent::Update {
-                channel_id,
-                message_id,
-                new_message,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(cs) = state.channels.get_mut(&cid) {
-                        if let Some(m) = cs
-                            .messages
-                            .iter_mut()
-                            .find(|m| m.id.as_ref() == Some(&message_id))
-                        {
-                            *m = new_message;
-                        }
+            channel.messages.push(message);
+            retention.apply_at(channel, now);
+        }
+        ChatEvent::Update {
+            scope,
+            message_id,
+            new_message,
+        } => {
+            let Some(channel) = state.channels.get_mut(scope_channel_id(&scope)) else {
+                return;
+            };
+            let Some(index) = channel.message_index(&message_id) else {
+                return;
+            };
+            let msg = &mut channel.messages[index];
+            let rethreaded =
+                new_message.thread_id.is_some() && new_message.thread_id != msg.thread_id;
+            merge_update(msg, new_message);
+            if rethreaded {
+                let msg = channel.messages[index].clone();
+                channel.unindex_thread(&message_id);
+                channel.index_thread(&msg);
+            }
+        }
+        ChatEvent::Remove { scope, message_id } => {
+            if let Some(channel) = state.channels.get_mut(scope_channel_id(&scope)) {
+                channel
+                    .messages
+                    .retain(|m| m.id.as_ref() != Some(&message_id));
+                channel.annotations.remove(&message_id);
+                channel.activity.remove(&message_id);
+                channel.unindex_thread(&message_id);
+            }
+        }
+        ChatEvent::ReactionAdd {
+            scope,
+            message_id,
+            user_id,
+            key,
+        } => {
+            let cid = scope_channel_id(&scope).to_string();
+            let Some(message) = find_message_mut(state, scope, &message_id) else {
+                return;
+            };
+            match message.reactions.iter_mut().find(|r| r.key == key) {
+                Some(reaction) => {
+                    if reaction.user_ids.contains(&user_id) {
+                        return;
                     }
+                    reaction.user_ids.push(user_id);
                 }
+                None => message.reactions.push(Reaction {
+                    key,
+                    user_ids: vec![user_id],
+                }),
+            }
+            if let Some(channel) = state.channels.get_mut(&cid) {
+                channel.record_activity(&message_id, now);
+            }
+        }
+        ChatEvent::ReactionRemove {
+            scope,
+            message_id,
+            user_id,
+            key,
+        } => {
+            let Some(message) = find_message_mut(state, scope, &message_id) else {
+                return;
+            };
+            if let Some(reaction) = message.reactions.iter_mut().find(|r| r.key == key) {
+                reaction.user_ids.retain(|id| id != &user_id);
             }
-            ChatEvent::Remove {
-                channel_id,
-                message_id,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(cs) = state.channels.get_mut(&cid) {
-                        cs.messages.retain(|m| m.id.as_ref() != Some(&message_id));
+            message.reactions.retain(|r| !r.user_ids.is_empty());
+        }
+        ChatEvent::DeliveryAck { message_id } => {
+            for channel in state.channels.values_mut() {
+                if let Some(message) = channel
+                    .messages
+                    .iter_mut()
+                    .find(|m| m.id.as_ref() == Some(&message_id))
+                {
+                    if message.status == MessageStatus::Sent {
+                        message.status = MessageStatus::Delivered;
                     }
+                    break;
                 }
             }
-        },
-        ConnectionEvent::Asset { event } => match event {
-            AssetEvent::New { channel_id, asset } => {
-                let aid = get_asset_id(&asset).unwrap_or_default();
-                if let Some(cid) = channel_id {
-                    state.get_or_create_channel(&cid).assets.insert(aid, asset);
-                } else {
-                    state.global_assets.insert(aid, asset);
-                }
+        }
+        ChatEvent::ReadMarker {
+            channel_id,
+            user_id,
+            message_id,
+        } => {
+            let channel = state.get_or_create_channel(&channel_id);
+            channel.read_markers.insert(user_id, message_id);
+        }
+    }
+}
+
+/// Applies an edit without dropping what backends leave out of edit payloads: reactions, reply
+/// and thread links, and a status more specific than `Sent`.
+fn merge_update(msg: &mut Message, mut new_message: Message) {
+    if new_message.reactions.is_empty() {
+        new_message.reactions = std::mem::take(&mut msg.reactions);
+    }
+    if new_message.reply_to.is_none() {
+        new_message.reply_to = msg.reply_to.take();
+    }
+    if new_message.thread_id.is_none() {
+        new_message.thread_id = msg.thread_id.take();
+    }
+    if new_message.status == MessageStatus::Sent {
+        new_message.status = msg.status.clone();
+    }
+    *msg = new_message;
+}
+
+fn find_message_mut<'a>(
+    state: &'a mut ConnectionState,
+    scope: Scope,
+    message_id: &str,
+) -> Option<&'a mut Message> {
+    state
+        .channels
+        .get_mut(scope_channel_id(&scope))?
+        .messages
+        .iter_mut()
+        .find(|m| m.id.as_deref() == Some(message_id))
+}
+
+fn process_moderation(state: &mut ConnectionState, event: ModerationEvent) {
+    match event {
+        ModerationEvent::Kick { .. } => {}
+        ModerationEvent::Ban {
+            scope,
+            user_id,
+            reason,
+            until,
+        } => {
+            let channel = state.scoped_channel(&scope);
+            channel.bans.insert(
+                user_id,
+                Ban {
+                    channel_id: scope.into(),
+                    reason,
+                    issued_at: Utc::now(),
+                    until,
+                },
+            );
+        }
+        ModerationEvent::Unban { scope, user_id } => {
+            let channel = state.scoped_channel(&scope);
+            channel.bans.remove(&user_id);
+        }
+        ModerationEvent::Mute {
+            scope,
+            user_id,
+            until,
+        } => {
+            let channel = state.scoped_channel(&scope);
+            channel.mutes.insert(user_id, until);
+        }
+        ModerationEvent::Unmute { scope, user_id } => {
+            let channel = state.scoped_channel(&scope);
+            channel.mutes.remove(&user_id);
+        }
+    }
+}
+
+fn process_asset(state: &mut ConnectionState, event: AssetEvent) {
+    match event {
+        AssetEvent::New { scope, asset } => {
+            let asset_id = get_asset_id(&asset).unwrap_or_default();
+            if let Scope::Channel(cid) = scope {
+                let channel = state.get_or_create_channel(&cid);
+                channel.assets.insert(asset_id, asset);
+            } else {
+                state.global_assets.insert(asset_id, asset);
             }
-            AssetEvent::Update {
-                channel_id,
-                asset_id,
-                new_asset,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(cs) = state.channels.get_mut(&cid) {
-                        cs.assets.insert(asset_id, new_asset);
-                    }
-                } else {
-                    state.global_assets.insert(asset_id, new_asset);
+        }
+        AssetEvent::Update {
+            scope,
+            asset_id,
+            new_asset,
+        } => {
+            if let Scope::Channel(cid) = scope {
+                if let Some(channel) = state.channels.get_mut(&cid) {
+                    channel.assets.insert(asset_id, new_asset);
                 }
+            } else {
+                state.global_assets.insert(asset_id, new_asset);
             }
-            AssetEvent::Remove {
-                channel_id,
-                asset_id,
-            } => {
-                if let Some(cid) = channel_id {
-                    if let Some(cs) = state.channels.get_mut(&cid) {
-                        cs.assets.remove(&asset_id);
-                    }
-                } else {
-                    state.global_assets.remove(&asset_id);
+        }
+        AssetEvent::Remove { scope, asset_id } => {
+            if let Scope::Channel(cid) = scope {
+                if let Some(channel) = state.channels.get_mut(&cid) {
+                    channel.assets.remove(&asset_id);
                 }
+            } else {
+                state.global_assets.remove(&asset_id);
             }
-            AssetEvent::ClearList { channel_id } => {
-                if let Some(cid) = channel_id {
-                    if let Some(cs) = state.channels.get_mut(&cid) {
-                        cs.assets.clear();
-                    }
-                } else {
-                    state.global_assets.clear();
+        }
+        AssetEvent::ClearList { scope } => {
+            if let Scope::Channel(cid) = scope {
+                if let Some(channel) = state.channels.get_mut(&cid) {
+                    channel.assets.clear();
                 }
+            } else {
+                state.global_assets.clear();
             }
-        },
+        }
     }
 }
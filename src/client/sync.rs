@@ -0,0 +1,171 @@
+use std::{collections::BTreeMap, fmt};
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Nonce,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{Channel, Message};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const BUNDLE_VERSION: u32 = 1;
+const FLAG_PLAIN: u8 = 0;
+const FLAG_ENCRYPTED: u8 = 1;
+const NONCE_LEN: usize = 12;
+const MAC_LEN: usize = 32;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BundleChannel {
+    pub channel: Channel,
+    pub messages: Vec<Message>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SyncBundle {
+    pub version: u32,
+    pub protocol_name: String,
+    pub exported_at: DateTime<Utc>,
+    pub since: DateTime<Utc>,
+    pub channels: Vec<BundleChannel>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SyncError {
+    Malformed,
+    BadSignature,
+    Encryption,
+    Decryption,
+    UnsupportedVersion(u32),
+    Serialization(String),
+    ProtocolMismatch { expected: String, found: String },
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Malformed => write!(f, "malformed sync bundle"),
+            SyncError::BadSignature => write!(f, "sync bundle signature mismatch"),
+            SyncError::Encryption => write!(f, "failed to encrypt sync bundle"),
+            SyncError::Decryption => write!(f, "failed to decrypt sync bundle"),
+            SyncError::UnsupportedVersion(v) => write!(f, "unsupported sync bundle version {}", v),
+            SyncError::Serialization(e) => write!(f, "sync bundle serialization error: {}", e),
+            SyncError::ProtocolMismatch { expected, found } => write!(
+                f,
+                "sync bundle is for protocol {}, expected {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+impl SyncBundle {
+    pub fn new(protocol_name: String, since: DateTime<Utc>) -> Self {
+        SyncBundle {
+            version: BUNDLE_VERSION,
+            protocol_name,
+            exported_at: Utc::now(),
+            since,
+            channels: Vec::new(),
+        }
+    }
+
+    pub fn seal(&self, key: &[u8], encrypt: bool) -> Result<Vec<u8>, SyncError> {
+        let payload =
+            serde_json::to_vec(self).map_err(|e| SyncError::Serialization(e.to_string()))?;
+
+        let mut out = Vec::new();
+        if encrypt {
+            let mut nonce_bytes = [0; NONCE_LEN];
+            getrandom::getrandom(&mut nonce_bytes).map_err(|_| SyncError::Encryption)?;
+            let cipher = ChaCha20Poly1305::new_from_slice(&derive_key(key, b"encrypt"))
+                .map_err(|_| SyncError::Encryption)?;
+            let ciphertext = cipher
+                .encrypt(Nonce::from_slice(&nonce_bytes), payload.as_slice())
+                .map_err(|_| SyncError::Encryption)?;
+            out.push(FLAG_ENCRYPTED);
+            out.extend_from_slice(&nonce_bytes);
+            out.extend_from_slice(&ciphertext);
+        } else {
+            out.push(FLAG_PLAIN);
+            out.extend_from_slice(&payload);
+        }
+
+        let mut mac = signer(key);
+        mac.update(&out);
+        out.extend_from_slice(&mac.finalize().into_bytes());
+        Ok(out)
+    }
+
+    pub fn open(data: &[u8], key: &[u8]) -> Result<SyncBundle, SyncError> {
+        if data.len() < 1 + MAC_LEN {
+            return Err(SyncError::Malformed);
+        }
+        let (body, signature) = data.split_at(data.len() - MAC_LEN);
+        let mut mac = signer(key);
+        mac.update(body);
+        mac.verify_slice(signature)
+            .map_err(|_| SyncError::BadSignature)?;
+
+        let payload = match body[0] {
+            FLAG_PLAIN => body[1..].to_vec(),
+            FLAG_ENCRYPTED => {
+                if body.len() < 1 + NONCE_LEN {
+                    return Err(SyncError::Malformed);
+                }
+                let (nonce, ciphertext) = body[1..].split_at(NONCE_LEN);
+                let cipher = ChaCha20Poly1305::new_from_slice(&derive_key(key, b"encrypt"))
+                    .map_err(|_| SyncError::Decryption)?;
+                cipher
+                    .decrypt(Nonce::from_slice(nonce), ciphertext)
+                    .map_err(|_| SyncError::Decryption)?
+            }
+            _ => return Err(SyncError::Malformed),
+        };
+
+        let bundle: SyncBundle = serde_json::from_slice(&payload)
+            .map_err(|e| SyncError::Serialization(e.to_string()))?;
+        if bundle.version != BUNDLE_VERSION {
+            return Err(SyncError::UnsupportedVersion(bundle.version));
+        }
+        Ok(bundle)
+    }
+}
+
+pub(crate) fn merge_messages(existing: &mut Vec<Message>, incoming: Vec<Message>) -> usize {
+    let mut added = 0;
+    for message in incoming {
+        let duplicate = existing.iter().any(|m| match (&m.id, &message.id) {
+            (Some(a), Some(b)) => a == b,
+            _ => m.timestamp == message.timestamp && m.sender_id == message.sender_id,
+        });
+        if !duplicate {
+            existing.push(message);
+            added += 1;
+        }
+    }
+    existing.sort_by_key(|m| m.timestamp);
+    added
+}
+
+fn signer(key: &[u8]) -> HmacSha256 {
+    <HmacSha256 as Mac>::new_from_slice(&derive_key(key, b"sign"))
+        .expect("hmac accepts any key length")
+}
+
+fn derive_key(key: &[u8], purpose: &[u8]) -> [u8; 32] {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(b"oshatori-sync-");
+    mac.update(purpose);
+    mac.finalize().into_bytes().into()
+}
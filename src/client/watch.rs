@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use regex::Regex;
+
+use crate::Message;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchMatch {
+    pub message_id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub fragment: usize,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Watch {
+    pub id: String,
+    pub regex: Regex,
+    pub matches: Vec<WatchMatch>,
+}
+
+impl Watch {
+    pub fn new(id: String, pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Watch {
+            id,
+            regex: Regex::new(pattern)?,
+            matches: Vec::new(),
+        })
+    }
+
+    pub fn scan(&mut self, message: &Message) {
+        for (index, fragment) in message.content.iter().enumerate() {
+            let Some(text) = fragment.text() else {
+                continue;
+            };
+            for found in self.regex.find_iter(text) {
+                self.matches.push(WatchMatch {
+                    message_id: message.id.clone(),
+                    timestamp: message.timestamp,
+                    fragment: index,
+                    start: found.start(),
+                    end: found.end(),
+                    text: found.as_str().to_string(),
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::{collections::HashMap, fmt};
+
+use handlebars::{handlebars_helper, Handlebars};
+use serde_json::{json, Value};
+
+use crate::{
+    connection::{ChatEvent, ConnectionEvent},
+    utils::compose::plain_text,
+};
+
+/// Matches every event kind without a template of its own.
+pub const FALLBACK_KIND: &str = "*";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WebhookError {
+    Template { kind: String, reason: String },
+    Render { kind: String, reason: String },
+    InvalidJson { kind: String, reason: String },
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::Template { kind, reason } => {
+                write!(f, "invalid webhook template for {}: {}", kind, reason)
+            }
+            WebhookError::Render { kind, reason } => {
+                write!(f, "failed to render webhook for {}: {}", kind, reason)
+            }
+            WebhookError::InvalidJson { kind, reason } => {
+                write!(
+                    f,
+                    "webhook template for {} produced invalid JSON: {}",
+                    kind, reason
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for WebhookError {}
+
+handlebars_helper!(json_helper: |value: Json| value.to_string());
+
+/// Handlebars templates turning `ConnectionEvent`s into webhook payloads, keyed by event kind
+/// such as `chat.new` or `user.typing_start`, with `*` as a fallback.
+///
+/// Templates see `connection_id`, `kind`, `event` (the variant's fields as serialized),
+/// `channel_id` and, for chat messages, `sender_id` and `text`. Interpolated values are escaped
+/// for use inside JSON strings; `{{{json value}}}` embeds a value as raw JSON.
+pub struct WebhookTemplates {
+    registry: Handlebars<'static>,
+    kinds: HashMap<String, String>,
+}
+
+impl WebhookTemplates {
+    pub fn new() -> Self {
+        let mut registry = Handlebars::new();
+        registry.set_strict_mode(false);
+        registry.register_escape_fn(|text| {
+            let quoted = Value::String(text.to_string()).to_string();
+            quoted[1..quoted.len() - 1].to_string()
+        });
+        registry.register_helper("json", Box::new(json_helper));
+        WebhookTemplates {
+            registry,
+            kinds: HashMap::new(),
+        }
+    }
+
+    /// Posts new chat messages as Slack-compatible incoming-webhook payloads.
+    pub fn slack() -> Self {
+        Self::new()
+            .with_template(
+                "chat.new",
+                r#"{"text": "*{{sender_id}}* in {{channel_id}}: {{text}}"}"#,
+            )
+            .expect("built-in template is valid")
+    }
+
+    pub fn with_template(mut self, kind: &str, template: &str) -> Result<Self, WebhookError> {
+        self.insert(kind, template)?;
+        Ok(self)
+    }
+
+    pub fn insert(&mut self, kind: &str, template: &str) -> Result<(), WebhookError> {
+        self.registry
+            .register_template_string(kind, template)
+            .map_err(|e| WebhookError::Template {
+                kind: kind.to_string(),
+                reason: e.to_string(),
+            })?;
+        self.kinds.insert(kind.to_string(), template.to_string());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, kind: &str) -> bool {
+        self.registry.unregister_template(kind);
+        self.kinds.remove(kind).is_some()
+    }
+
+    pub fn template(&self, kind: &str) -> Option<&str> {
+        self.kinds.get(kind).map(String::as_str)
+    }
+
+    /// Renders the payload for `event`, or `None` if no template covers its kind.
+    pub fn render(
+        &self,
+        connection_id: &str,
+        event: &ConnectionEvent,
+    ) -> Result<Option<Value>, WebhookError> {
+        let kind = event_kind(event);
+        let name = if self.kinds.contains_key(&kind) {
+            kind.as_str()
+        } else if self.kinds.contains_key(FALLBACK_KIND) {
+            FALLBACK_KIND
+        } else {
+            return Ok(None);
+        };
+
+        let rendered = self
+            .registry
+            .render(name, &template_context(connection_id, &kind, event))
+            .map_err(|e| WebhookError::Render {
+                kind: kind.clone(),
+                reason: e.to_string(),
+            })?;
+        serde_json::from_str(&rendered)
+            .map(Some)
+            .map_err(|e| WebhookError::InvalidJson {
+                kind,
+                reason: e.to_string(),
+            })
+    }
+}
+
+impl Default for WebhookTemplates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The variant path of `event` as `category.variant`, e.g. `chat.new` or `status.connected`.
+pub fn event_kind(event: &ConnectionEvent) -> String {
+    let (category, inner) = split_variant(&serde_json::to_value(event).unwrap_or_default());
+    let (variant, _) = split_variant(&inner["event"]);
+    format!("{}.{}", snake_case(&category), snake_case(&variant))
+}
+
+fn split_variant(value: &Value) -> (String, Value) {
+    match value {
+        Value::String(name) => (name.clone(), Value::Null),
+        Value::Object(map) if map.len() == 1 => map
+            .iter()
+            .next()
+            .map(|(name, inner)| (name.clone(), inner.clone()))
+            .unwrap_or_default(),
+        _ => Default::default(),
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+fn template_context(connection_id: &str, kind: &str, event: &ConnectionEvent) -> Value {
+    let (_, inner) = split_variant(&serde_json::to_value(event).unwrap_or_default());
+    let (_, fields) = split_variant(&inner["event"]);
+    let channel_id = fields["channel_id"]
+        .as_str()
+        .or(fields["scope"]["Channel"].as_str())
+        .map(str::to_string);
+    let message = match event {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. },
+        }
+        | ConnectionEvent::Chat {
+            event:
+                ChatEvent::Update {
+                    new_message: message,
+                    ..
+                },
+        } => Some(message),
+        _ => None,
+    };
+    json!({
+        "connection_id": connection_id,
+        "kind": kind,
+        "event": fields,
+        "channel_id": channel_id,
+        "sender_id": message.and_then(|message| message.sender_id.clone()),
+        "text": message.map(|message| plain_text(&message.content)),
+    })
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+use crate::{
+    client::ChannelSnapshot, connection::ConnectionStatus, AuthField, Capabilities, Connection,
+    Message, Protocol,
+};
+
+use super::{
+    history_page, AssetEvent, ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, Scope,
+    StatusEvent, UserEvent,
+};
+
+/// A read-only connection backed by exported data. Connecting replays the archive as ordinary
+/// events, so it can be tracked by a `StateClient` like a live connection; every send is
+/// rejected.
+pub struct ArchiveConnection {
+    name: String,
+    events: Vec<ConnectionEvent>,
+    history: HashMap<String, Vec<Message>>,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+}
+
+impl ArchiveConnection {
+    /// An archive replaying `events` in order.
+    pub fn from_events(name: &str, events: Vec<ConnectionEvent>) -> Self {
+        let mut history: HashMap<String, Vec<Message>> = HashMap::new();
+        for event in &events {
+            if let ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } = event
+            {
+                history
+                    .entry(archive_channel_id(scope))
+                    .or_default()
+                    .push(message.clone());
+            }
+        }
+
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        ArchiveConnection {
+            name: name.to_string(),
+            events,
+            history,
+            event_tx,
+            event_rx: Some(event_rx),
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+        }
+    }
+
+    /// An archive of channel snapshots, replayed as each channel followed by its users, assets
+    /// and messages.
+    pub fn from_snapshots(name: &str, snapshots: Vec<ChannelSnapshot>) -> Self {
+        let events = snapshots.into_iter().flat_map(snapshot_events).collect();
+        Self::from_events(name, events)
+    }
+
+    /// Parses an export: either a JSON array of channel snapshots, or JSON lines where each line
+    /// is a channel snapshot or a connection event.
+    pub fn parse(name: &str, text: &str) -> Result<Self, ConnectionError> {
+        let invalid = |e: serde_json::Error| ConnectionError::Protocol(e.to_string());
+        if text.trim_start().starts_with('[') {
+            let snapshots = serde_json::from_str(text).map_err(invalid)?;
+            return Ok(Self::from_snapshots(name, snapshots));
+        }
+
+        let mut events = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<ChannelSnapshot>(line) {
+                Ok(snapshot) => events.extend(snapshot_events(snapshot)),
+                Err(_) => events.push(serde_json::from_str(line).map_err(invalid)?),
+            }
+        }
+        Ok(Self::from_events(name, events))
+    }
+
+    /// Reads and parses an export file, named after the file stem.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ConnectionError> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).map_err(|e| ConnectionError::Other(e.to_string()))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "archive".to_string());
+        Self::parse(&name, &text)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+}
+
+fn snapshot_events(snapshot: ChannelSnapshot) -> Vec<ConnectionEvent> {
+    let scope = Scope::channel(snapshot.channel.id.clone());
+    let mut events = vec![ConnectionEvent::Channel {
+        event: ChannelEvent::New {
+            channel: snapshot.channel,
+        },
+    }];
+    events.extend(
+        snapshot
+            .users
+            .into_iter()
+            .map(|user| ConnectionEvent::User {
+                event: UserEvent::New {
+                    scope: scope.clone(),
+                    user,
+                },
+            }),
+    );
+    events.extend(
+        snapshot
+            .assets
+            .into_iter()
+            .map(|asset| ConnectionEvent::Asset {
+                event: AssetEvent::New {
+                    scope: scope.clone(),
+                    asset,
+                },
+            }),
+    );
+    events.extend(
+        snapshot
+            .messages
+            .into_iter()
+            .map(|message| ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope: scope.clone(),
+                    message,
+                },
+            }),
+    );
+    events
+}
+
+fn archive_channel_id(scope: &Scope) -> String {
+    crate::client::state::scope_channel_id(scope).to_string()
+}
+
+#[async_trait]
+impl Connection for ArchiveConnection {
+    fn set_auth(&mut self, _auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        Ok(())
+    }
+
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        self.set_status(ConnectionStatus::Connected);
+        let send = |event| {
+            self.event_tx
+                .send(event)
+                .map_err(|_| ConnectionError::Closed)
+        };
+        send(ConnectionEvent::Status {
+            event: StatusEvent::Connected {
+                artifact: Some(self.name.clone()),
+            },
+        })?;
+        for event in &self.events {
+            send(event.clone())?;
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, _event: ConnectionEvent) -> Result<(), ConnectionError> {
+        Err(ConnectionError::Unsupported(
+            "Archives are read-only".to_string(),
+        ))
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        Ok(self
+            .history
+            .get(channel_id)
+            .map(|messages| history_page(messages, before, limit))
+            .unwrap_or_default())
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        Protocol {
+            name: "archive".to_string(),
+            auth: None,
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or(ConnectionStatus::Disconnected)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            history: true,
+            multiple_channels: true,
+            ..Capabilities::default()
+        }
+    }
+}
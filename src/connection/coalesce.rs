@@ -0,0 +1,195 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{sync::mpsc, task::JoinHandle, time::Instant};
+
+use crate::{
+    connection::ConnectionStatus, utils::ids::IdNormalizer, AuthField, Capabilities, Channel,
+    CommandSpec, Connection, Message, MessageFragment, Profile, Protocol,
+};
+
+use super::{ConnectionError, ConnectionEvent, PreflightReport, SendHandle, UserEvent};
+
+pub struct CoalescingConnection<C: Connection + 'static> {
+    inner: C,
+    window: Duration,
+    inner_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl<C: Connection + 'static> CoalescingConnection<C> {
+    pub fn new(mut inner: C, window: Duration) -> Self {
+        let inner_rx = inner.subscribe();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        CoalescingConnection {
+            inner,
+            window,
+            inner_rx: Some(inner_rx),
+            event_tx,
+            event_rx: Some(event_rx),
+            task: None,
+        }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    fn spawn_forwarder(&mut self) {
+        let Some(mut rx) = self.inner_rx.take() else {
+            return;
+        };
+        let tx = self.event_tx.clone();
+        let window = self.window;
+        self.task = Some(tokio::spawn(async move {
+            let mut pending: Vec<UserEvent> = Vec::new();
+            let mut deadline = Instant::now();
+            loop {
+                let event = if pending.is_empty() {
+                    rx.recv().await
+                } else {
+                    match tokio::time::timeout_at(deadline, rx.recv()).await {
+                        Ok(event) => event,
+                        Err(_) => {
+                            flush(&tx, &mut pending);
+                            continue;
+                        }
+                    }
+                };
+                let Some(event) = event else {
+                    flush(&tx, &mut pending);
+                    break;
+                };
+                match event {
+                    ConnectionEvent::User { event } if is_list_change(&event) => {
+                        if pending.is_empty() {
+                            deadline = Instant::now() + window;
+                        }
+                        pending.push(event);
+                    }
+                    other => {
+                        flush(&tx, &mut pending);
+                        let _ = tx.send(other);
+                    }
+                }
+            }
+        }));
+    }
+}
+
+fn is_list_change(event: &UserEvent) -> bool {
+    matches!(
+        event,
+        UserEvent::New { .. }
+            | UserEvent::Update { .. }
+            | UserEvent::Remove { .. }
+            | UserEvent::ClearList { .. }
+            | UserEvent::PresenceChanged { .. }
+            | UserEvent::Batch { .. }
+    )
+}
+
+fn flush(tx: &mpsc::UnboundedSender<ConnectionEvent>, pending: &mut Vec<UserEvent>) {
+    let event = match pending.len() {
+        0 => return,
+        1 => pending.remove(0),
+        _ => UserEvent::Batch {
+            events: std::mem::take(pending),
+        },
+    };
+    let _ = tx.send(ConnectionEvent::User { event });
+}
+
+#[async_trait]
+impl<C: Connection + 'static> Connection for CoalescingConnection<C> {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.inner.set_auth(auth)
+    }
+
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        self.spawn_forwarder();
+        self.inner.connect().await
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.inner.disconnect().await
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        self.spawn_forwarder();
+        self.inner.send(event).await
+    }
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        self.spawn_forwarder();
+        self.inner.send_tracked(event).await
+    }
+
+    async fn fetch_profile(&mut self, user_id: &str) -> Result<Profile, ConnectionError> {
+        self.inner.fetch_profile(user_id).await
+    }
+
+    async fn search_users(&mut self, query: &str) -> Result<Vec<Profile>, ConnectionError> {
+        self.inner.search_users(query).await
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        self.inner.fetch_history(channel_id, before, limit).await
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        self.inner.open_direct(user_id).await
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        self.inner.protocol_spec()
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.inner.status()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn commands(&self) -> Vec<CommandSpec> {
+        self.inner.commands()
+    }
+
+    fn id_normalizer(&self) -> Arc<dyn IdNormalizer> {
+        self.inner.id_normalizer()
+    }
+
+    fn render_text(&self, content: &[MessageFragment]) -> String {
+        self.inner.render_text(content)
+    }
+
+    async fn preflight(&self, check_reachability: bool) -> PreflightReport {
+        self.inner.preflight(check_reachability).await
+    }
+}
+
+impl<C: Connection + 'static> Drop for CoalescingConnection<C> {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
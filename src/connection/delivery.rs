@@ -0,0 +1,172 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, Mutex};
+
+use crate::{
+    utils::ids::{IdGenerator, UuidGenerator},
+    Message, MessageStatus,
+};
+
+use super::{ChatEvent, ConnectionEvent, Scope};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum SendOutcome {
+    Delivered { message_id: Option<String> },
+    Failed(String),
+}
+
+#[derive(Debug)]
+pub struct SendHandle {
+    correlation_id: String,
+    rx: oneshot::Receiver<SendOutcome>,
+}
+
+impl SendHandle {
+    pub fn new() -> (Self, oneshot::Sender<SendOutcome>) {
+        Self::with_id(UuidGenerator.next_id())
+    }
+
+    pub fn with_id(correlation_id: String) -> (Self, oneshot::Sender<SendOutcome>) {
+        let (tx, rx) = oneshot::channel();
+        (SendHandle { correlation_id, rx }, tx)
+    }
+
+    pub fn completed(outcome: SendOutcome) -> Self {
+        let (handle, tx) = Self::new();
+        let _ = tx.send(outcome);
+        handle
+    }
+
+    pub fn correlation_id(&self) -> &str {
+        &self.correlation_id
+    }
+}
+
+impl Future for SendHandle {
+    type Output = SendOutcome;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.rx).poll(cx).map(|outcome| {
+            outcome.unwrap_or_else(|_| SendOutcome::Failed("Send was abandoned".to_string()))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct OutboundEntry {
+    id: String,
+    scope: Scope,
+    message: Message,
+    echo: String,
+    sent_at: Instant,
+    tx: Option<oneshot::Sender<SendOutcome>>,
+}
+
+impl OutboundEntry {
+    pub(crate) fn new(
+        id: String,
+        scope: Scope,
+        message: Message,
+        echo: String,
+        tx: Option<oneshot::Sender<SendOutcome>>,
+    ) -> Self {
+        OutboundEntry {
+            id,
+            scope,
+            message,
+            echo,
+            sent_at: Instant::now(),
+            tx,
+        }
+    }
+
+    pub(crate) fn scope(&self) -> &Scope {
+        &self.scope
+    }
+
+    /// The text the server is expected to echo back once it accepts the message.
+    pub(crate) fn echo(&self) -> &str {
+        &self.echo
+    }
+
+    pub(crate) fn fail(mut self, reason: &str) -> ConnectionEvent {
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(SendOutcome::Failed(reason.to_string()));
+        }
+        let mut message = self.message;
+        message.id = Some(self.id.clone());
+        message.status = MessageStatus::Failed;
+        ConnectionEvent::Chat {
+            event: ChatEvent::Update {
+                scope: self.scope,
+                message_id: self.id,
+                new_message: message,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct OutboundQueue {
+    entries: Mutex<VecDeque<OutboundEntry>>,
+}
+
+impl OutboundQueue {
+    pub(crate) async fn push(&self, entry: OutboundEntry) {
+        self.entries.lock().await.push_back(entry);
+    }
+
+    pub(crate) async fn remove(&self, id: &str) -> Option<OutboundEntry> {
+        let mut entries = self.entries.lock().await;
+        let index = entries.iter().position(|e| e.id == id)?;
+        entries.remove(index)
+    }
+
+    /// Resolves the oldest pending entry the server's echo `matches` as delivered, returning
+    /// the scope it was sent to, or `None` when the echo belongs to none of them.
+    pub(crate) async fn acknowledge(
+        &self,
+        message_id: String,
+        matches: impl Fn(&OutboundEntry) -> bool,
+    ) -> Option<Scope> {
+        let mut entries = self.entries.lock().await;
+        let index = entries.iter().position(matches)?;
+        let mut entry = entries.remove(index)?;
+        if let Some(tx) = entry.tx.take() {
+            let _ = tx.send(SendOutcome::Delivered {
+                message_id: Some(message_id),
+            });
+        }
+        Some(entry.scope)
+    }
+
+    /// Fails the most recently sent entry, for server errors that answer the last command.
+    pub(crate) async fn fail_latest(&self, reason: &str) -> Option<ConnectionEvent> {
+        let mut entries = self.entries.lock().await;
+        let index = (0..entries.len()).max_by_key(|&index| entries[index].sent_at)?;
+        entries.remove(index).map(|entry| entry.fail(reason))
+    }
+
+    /// Takes the entries that went unacknowledged for longer than `ack_timeout`. They are not
+    /// resent, since the server may have accepted them without the echo reaching us.
+    pub(crate) async fn due(&self, ack_timeout: Duration) -> VecDeque<OutboundEntry> {
+        let mut entries = self.entries.lock().await;
+        let (failed, pending) = std::mem::take(&mut *entries)
+            .into_iter()
+            .partition(|entry| entry.sent_at.elapsed() >= ack_timeout);
+        *entries = pending;
+        failed
+    }
+
+    pub(crate) async fn drain(&self) -> Vec<OutboundEntry> {
+        self.entries.lock().await.drain(..).collect()
+    }
+}
@@ -0,0 +1,1101 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use mail_parser::{Address, HeaderValue, MessageParser, MimeHeaders, PartType};
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+};
+use uuid::Uuid;
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{compose::plain_text, html::html_fragments},
+    AuthField, Capabilities, Channel, ChannelType, Connection, FieldValue, Message,
+    MessageFragment, MessageStatus, MessageType, Profile, Protocol,
+};
+
+use super::{
+    preflight::{probe_reachability, validate_auth},
+    ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, ConnectionOptions, PreflightReport,
+    Scope, StatusEvent, Supervisor, TlsConfig, UserEvent,
+};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many of the newest messages are read at connect to find the open threads.
+const HISTORY: u32 = 50;
+const MAX_LITERAL_LENGTH: usize = 64 * 1024 * 1024;
+const NO_SUBJECT: &str = "(no subject)";
+
+trait MailStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> MailStream for T {}
+
+/// Where to connect, parsed from `<scheme>://host[:port]`, the TLS scheme
+/// (`imaps`/`smtps`) or a bare `host[:port]`, which also uses TLS.
+#[derive(Clone, Debug, PartialEq)]
+struct ServerAddress {
+    host: String,
+    port: u16,
+    tls: bool,
+}
+
+impl ServerAddress {
+    fn parse(
+        server: &str,
+        scheme: &str,
+        tls_port: u16,
+        plain_port: u16,
+    ) -> Result<Self, ConnectionError> {
+        let (tls, rest) = match server.split_once("://") {
+            Some((given, rest)) if given == scheme => (false, rest),
+            Some((given, rest)) if given.strip_suffix('s') == Some(scheme) => (true, rest),
+            Some((given, _)) => {
+                return Err(ConnectionError::Auth(format!(
+                    "Unknown scheme {}, expected {} or {}s",
+                    given, scheme, scheme
+                )))
+            }
+            None => (true, server),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| ConnectionError::Auth(format!("Invalid port: {}", port)))?,
+            ),
+            None => (rest, if tls { tls_port } else { plain_port }),
+        };
+        if host.is_empty() {
+            return Err(ConnectionError::Auth("Server has no host".to_string()));
+        }
+        Ok(ServerAddress {
+            host: host.to_string(),
+            port,
+            tls,
+        })
+    }
+
+    fn imap(server: &str) -> Result<Self, ConnectionError> {
+        Self::parse(server, "imap", 993, 143)
+    }
+
+    fn smtp(server: &str) -> Result<Self, ConnectionError> {
+        Self::parse(server, "smtp", 465, 587)
+    }
+}
+
+fn tls_connector(tls: &TlsConfig) -> Result<native_tls::TlsConnector, ConnectionError> {
+    let tls_error = |e: native_tls::Error| ConnectionError::Other(e.to_string());
+    let mut builder = native_tls::TlsConnector::builder();
+    builder.danger_accept_invalid_certs(tls.accept_invalid_certs);
+    for pem in &tls.root_certificates {
+        builder.add_root_certificate(native_tls::Certificate::from_pem(pem).map_err(tls_error)?);
+    }
+    if let Some(identity) = &tls.client_identity {
+        builder.identity(
+            native_tls::Identity::from_pkcs8(&identity.certificate_pem, &identity.key_pem)
+                .map_err(tls_error)?,
+        );
+    }
+    builder.build().map_err(tls_error)
+}
+
+async fn open_stream(
+    address: &ServerAddress,
+    options: &ConnectionOptions,
+) -> Result<BufReader<Box<dyn MailStream>>, ConnectionError> {
+    if options.proxy.is_some() {
+        return Err(ConnectionError::Unsupported(
+            "Proxies for email connections".to_string(),
+        ));
+    }
+    let network = |e: std::io::Error| ConnectionError::Network(e.to_string());
+    let connect = TcpStream::connect((address.host.as_str(), address.port));
+    let tcp = match options.connect_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, connect)
+            .await
+            .map_err(|_| ConnectionError::Timeout)?,
+        None => connect.await,
+    }
+    .map_err(network)?;
+    if !address.tls {
+        return Ok(BufReader::new(Box::new(tcp)));
+    }
+    let connector = tokio_native_tls::TlsConnector::from(tls_connector(&options.tls)?);
+    let tls = connector
+        .connect(&address.host, tcp)
+        .await
+        .map_err(|e| ConnectionError::Network(e.to_string()))?;
+    Ok(BufReader::new(Box::new(tls)))
+}
+
+/// An IMAP quoted string.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// One server response line, with any literals it carried.
+#[derive(Debug, Default)]
+struct ImapResponse {
+    text: String,
+    literals: Vec<Vec<u8>>,
+}
+
+impl ImapResponse {
+    /// The number before `keyword` in an untagged `* <n> <keyword>` response.
+    fn count(&self, keyword: &str) -> Option<u32> {
+        let rest = self.text.strip_prefix("* ")?;
+        let (count, rest) = rest.split_once(' ')?;
+        rest.trim_end()
+            .eq_ignore_ascii_case(keyword)
+            .then(|| count.parse().ok())?
+    }
+
+    /// The number after `key` anywhere in the text, like `UID 12` or `[UIDNEXT 13]`.
+    fn number(&self, key: &str) -> Option<u32> {
+        let at = self.text.find(key)? + key.len();
+        let digits: String = self.text[at..]
+            .trim_start()
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        digits.parse().ok()
+    }
+}
+
+/// A minimal IMAP4rev1 client: one command in flight, no IDLE.
+struct Imap {
+    stream: BufReader<Box<dyn MailStream>>,
+    tag: u32,
+}
+
+impl Imap {
+    async fn read_response(&mut self) -> std::io::Result<ImapResponse> {
+        let mut response = ImapResponse::default();
+        loop {
+            let mut line = Vec::new();
+            if self.stream.read_until(b'\n', &mut line).await? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            let literal = line
+                .strip_suffix('}')
+                .and_then(|rest| rest.rsplit_once('{'))
+                .and_then(|(_, length)| length.parse::<usize>().ok());
+            response.text.push_str(line);
+            let Some(length) = literal else {
+                return Ok(response);
+            };
+            if length > MAX_LITERAL_LENGTH {
+                return Err(std::io::Error::other("oversized literal"));
+            }
+            let mut literal = vec![0; length];
+            self.stream.read_exact(&mut literal).await?;
+            response.literals.push(literal);
+        }
+    }
+
+    /// Runs a command and returns its untagged responses, or the server's reason when it
+    /// answers `NO` or `BAD`.
+    async fn command(&mut self, command: &str) -> Result<Vec<ImapResponse>, ConnectionError> {
+        let network = |e: std::io::Error| ConnectionError::Network(e.to_string());
+        self.tag += 1;
+        let tag = format!("a{}", self.tag);
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{} {}\r\n", tag, command).as_bytes())
+            .await
+            .map_err(network)?;
+        stream.flush().await.map_err(network)?;
+        let mut responses = Vec::new();
+        loop {
+            let response = tokio::time::timeout(COMMAND_TIMEOUT, self.read_response())
+                .await
+                .map_err(|_| ConnectionError::Timeout)?
+                .map_err(network)?;
+            let Some(status) = response
+                .text
+                .strip_prefix(&tag)
+                .and_then(|rest| rest.strip_prefix(' '))
+            else {
+                responses.push(response);
+                continue;
+            };
+            let (state, reason) = status.split_once(' ').unwrap_or((status, ""));
+            return match state.to_ascii_uppercase().as_str() {
+                "OK" => Ok(responses),
+                _ => Err(ConnectionError::Protocol(reason.to_string())),
+            };
+        }
+    }
+
+    /// Fetches whole messages as `(uid, raw)` pairs.
+    async fn fetch(&mut self, command: &str) -> Result<Vec<(u32, Vec<u8>)>, ConnectionError> {
+        Ok(self
+            .command(command)
+            .await?
+            .into_iter()
+            .filter(|response| response.text.contains("FETCH"))
+            .filter_map(|mut response| {
+                let uid = response.number("UID")?;
+                Some((uid, response.literals.pop()?))
+            })
+            .collect())
+    }
+}
+
+/// A minimal SMTP submission client for one message at a time.
+struct Smtp {
+    stream: BufReader<Box<dyn MailStream>>,
+}
+
+impl Smtp {
+    async fn reply(&mut self) -> Result<(u16, String), ConnectionError> {
+        let network = |e: std::io::Error| ConnectionError::Network(e.to_string());
+        let mut text = String::new();
+        loop {
+            let mut line = String::new();
+            let read = tokio::time::timeout(COMMAND_TIMEOUT, self.stream.read_line(&mut line))
+                .await
+                .map_err(|_| ConnectionError::Timeout)?
+                .map_err(network)?;
+            if read == 0 {
+                return Err(ConnectionError::Network(
+                    "Server closed the connection".to_string(),
+                ));
+            }
+            let line = line.trim_end();
+            let code = line
+                .get(..3)
+                .and_then(|code| code.parse::<u16>().ok())
+                .ok_or_else(|| ConnectionError::Protocol(format!("Bad reply: {}", line)))?;
+            text.push_str(line.get(4..).unwrap_or_default());
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok((code, text));
+            }
+            text.push('\n');
+        }
+    }
+
+    /// Sends `command` (or nothing, for the greeting) and checks the reply class.
+    async fn expect(&mut self, command: Option<&str>, class: u16) -> Result<(), ConnectionError> {
+        if let Some(command) = command {
+            let stream = self.stream.get_mut();
+            stream
+                .write_all(format!("{}\r\n", command).as_bytes())
+                .await
+                .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        }
+        let (code, text) = self.reply().await?;
+        match code / 100 {
+            found if found == class => Ok(()),
+            _ if code == 535 => Err(ConnectionError::Auth(text)),
+            _ => Err(ConnectionError::Protocol(format!("{} {}", code, text))),
+        }
+    }
+}
+
+/// A message ready to go out over SMTP.
+struct Outgoing {
+    from: String,
+    display_name: Option<String>,
+    recipients: Vec<String>,
+    subject: String,
+    message_id: String,
+    references: Vec<String>,
+    body: String,
+}
+
+/// Encodes a header value as an RFC 2047 word when it is not plain ASCII.
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?utf-8?B?{}?=", STANDARD.encode(value))
+    }
+}
+
+impl Outgoing {
+    fn render(&self) -> String {
+        let from = match &self.display_name {
+            Some(name) => format!("{} <{}>", encode_header(name), self.from),
+            None => format!("<{}>", self.from),
+        };
+        let mut headers = vec![
+            format!("From: {}", from),
+            format!("To: {}", self.recipients.join(", ")),
+            format!("Subject: {}", encode_header(&self.subject)),
+            format!("Date: {}", Utc::now().to_rfc2822()),
+            format!("Message-ID: <{}>", self.message_id),
+        ];
+        if let Some(parent) = self.references.last() {
+            headers.push(format!("In-Reply-To: <{}>", parent));
+            let references: Vec<String> = self
+                .references
+                .iter()
+                .map(|id| format!("<{}>", id))
+                .collect();
+            headers.push(format!("References: {}", references.join(" ")));
+        }
+        headers.push("MIME-Version: 1.0".to_string());
+        headers.push("Content-Type: text/plain; charset=utf-8".to_string());
+        headers.push("Content-Transfer-Encoding: base64".to_string());
+        // Base64 lines never start with a dot, so the body needs no dot-stuffing.
+        let body = STANDARD.encode(self.body.replace('\n', "\r\n"));
+        let lines: Vec<&str> = body
+            .as_bytes()
+            .chunks(76)
+            .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+            .collect();
+        format!("{}\r\n\r\n{}\r\n", headers.join("\r\n"), lines.join("\r\n"))
+    }
+}
+
+async fn submit(
+    address: &ServerAddress,
+    options: &ConnectionOptions,
+    credentials: (&str, &str),
+    mail: &Outgoing,
+) -> Result<(), ConnectionError> {
+    let mut smtp = Smtp {
+        stream: open_stream(address, options).await?,
+    };
+    let domain = mail
+        .from
+        .rsplit_once('@')
+        .map_or("localhost", |(_, domain)| domain);
+    smtp.expect(None, 2).await?;
+    smtp.expect(Some(&format!("EHLO {}", domain)), 2).await?;
+    let (username, password) = credentials;
+    let token = STANDARD.encode(format!("\0{}\0{}", username, password));
+    smtp.expect(Some(&format!("AUTH PLAIN {}", token)), 2)
+        .await?;
+    smtp.expect(Some(&format!("MAIL FROM:<{}>", mail.from)), 2)
+        .await?;
+    for recipient in &mail.recipients {
+        smtp.expect(Some(&format!("RCPT TO:<{}>", recipient)), 2)
+            .await?;
+    }
+    smtp.expect(Some("DATA"), 3).await?;
+    smtp.expect(Some(&format!("{}.", mail.render())), 2).await?;
+    let _ = smtp.expect(Some("QUIT"), 2).await;
+    Ok(())
+}
+
+fn addresses(address: Option<&Address>) -> Vec<(String, Option<String>)> {
+    address
+        .into_iter()
+        .flat_map(|address| address.iter())
+        .filter_map(|addr| {
+            Some((
+                addr.address()?.to_lowercase(),
+                addr.name().map(|name| name.to_string()),
+            ))
+        })
+        .collect()
+}
+
+fn ids(value: &HeaderValue) -> Vec<String> {
+    value
+        .as_text_list()
+        .into_iter()
+        .flatten()
+        .map(|id| id.to_string())
+        .collect()
+}
+
+/// Drops any number of leading `Re:`/`Fwd:` markers.
+fn clean_subject(subject: &str) -> String {
+    let mut subject = subject.trim();
+    loop {
+        let lower = subject.to_ascii_lowercase();
+        let Some(marker) = ["re:", "fwd:", "fw:"]
+            .into_iter()
+            .find(|marker| lower.starts_with(marker))
+        else {
+            return subject.to_string();
+        };
+        subject = subject[marker.len()..].trim_start();
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Thread {
+    channel: Channel,
+    subject: String,
+    /// Everyone on the thread but us, in the order they showed up.
+    participants: Vec<String>,
+    /// Message ids on the thread, oldest first.
+    references: Vec<String>,
+    messages: Vec<Message>,
+}
+
+#[derive(Debug, Default)]
+struct Mailbox {
+    threads: HashMap<String, Thread>,
+    thread_of: HashMap<String, String>,
+    last_uid: u32,
+}
+
+#[derive(Clone)]
+struct EmailMapper {
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    address: String,
+    state: Arc<StdMutex<Mailbox>>,
+}
+
+impl EmailMapper {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn content(mail: &mail_parser::Message) -> Vec<MessageFragment> {
+        let text = mail.text_part(0).and_then(|part| match &part.body {
+            PartType::Text(text) => Some(text.trim_end().to_string()),
+            _ => None,
+        });
+        match text {
+            Some(text) => vec![MessageFragment::Text(text)],
+            None => mail
+                .html_part(0)
+                .and_then(|part| match &part.body {
+                    PartType::Html(html) => Some(html_fragments(html)),
+                    _ => None,
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Files one fetched message under its thread. Threads, and the people on them, are
+    /// announced as they are found; the message itself only when `live`.
+    fn ingest(&self, uid: u32, raw: &[u8], live: bool) {
+        let Some(mail) = MessageParser::default().parse(raw) else {
+            return;
+        };
+        let message_id = mail
+            .message_id()
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| format!("uid-{}", uid));
+        let mut references = ids(mail.references());
+        for parent in ids(mail.in_reply_to()) {
+            if !references.contains(&parent) {
+                references.push(parent);
+            }
+        }
+        let from = addresses(mail.from());
+        let sender = from.first().cloned();
+        let mut people = from;
+        people.extend(addresses(mail.to()));
+        people.extend(addresses(mail.cc()));
+        let attachments: Vec<String> = mail
+            .attachments()
+            .filter_map(|part| part.attachment_name().map(|name| name.to_string()))
+            .collect();
+        let mut extra = HashMap::new();
+        if !attachments.is_empty() {
+            extra.insert("attachments".to_string(), json!(attachments));
+        }
+        let message = Message {
+            id: Some(message_id.clone()),
+            sender_id: sender.as_ref().map(|(address, _)| address.clone()),
+            content: Self::content(&mail),
+            timestamp: mail
+                .date()
+                .and_then(|date| DateTime::from_timestamp(date.to_timestamp(), 0))
+                .unwrap_or_else(Utc::now),
+            message_type: match &sender {
+                Some((address, _)) if *address == self.address => MessageType::CurrentUser,
+                _ => MessageType::Normal,
+            },
+            status: MessageStatus::Delivered,
+            reactions: Vec::new(),
+            reply_to: references.last().cloned(),
+            thread_id: None,
+            extra,
+        };
+
+        let mut events = Vec::new();
+        let thread_id = {
+            let Ok(mut state) = self.state.lock() else {
+                return;
+            };
+            state.last_uid = state.last_uid.max(uid);
+            if state.thread_of.contains_key(&message_id) {
+                return;
+            }
+            let known = references
+                .iter()
+                .rev()
+                .find_map(|id| state.thread_of.get(id).cloned());
+            let thread_id = known.unwrap_or_else(|| {
+                references
+                    .first()
+                    .cloned()
+                    .unwrap_or_else(|| message_id.clone())
+            });
+            state
+                .thread_of
+                .insert(message_id.clone(), thread_id.clone());
+            let thread = state.threads.entry(thread_id.clone()).or_insert_with(|| {
+                let subject = clean_subject(mail.subject().unwrap_or_default());
+                let thread = Thread {
+                    channel: Channel {
+                        id: thread_id.clone(),
+                        name: Some(subject.clone()).filter(|subject| !subject.is_empty()),
+                        channel_type: ChannelType::Direct,
+                        topic: None,
+                        extra: HashMap::new(),
+                    },
+                    subject,
+                    participants: Vec::new(),
+                    references: Vec::new(),
+                    messages: Vec::new(),
+                };
+                events.push(ConnectionEvent::Channel {
+                    event: ChannelEvent::New {
+                        channel: thread.channel.clone(),
+                    },
+                });
+                thread
+            });
+            for (address, name) in people {
+                if address == self.address || thread.participants.contains(&address) {
+                    continue;
+                }
+                thread.participants.push(address.clone());
+                events.push(ConnectionEvent::User {
+                    event: UserEvent::New {
+                        scope: Scope::channel(thread_id.clone()),
+                        user: Profile {
+                            id: Some(address.clone()),
+                            username: Some(address),
+                            display_name: name,
+                            color: None,
+                            picture: None,
+                            presence: None,
+                            role: None,
+                            extra: HashMap::new(),
+                        },
+                    },
+                });
+            }
+            thread.references.push(message_id);
+            thread.messages.push(message.clone());
+            thread_id
+        };
+        if live {
+            events.push(ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope: Scope::channel(thread_id),
+                    message,
+                },
+            });
+        }
+        for event in events {
+            self.emit(event);
+        }
+    }
+
+    fn thread(&self, thread_id: &str) -> Option<Thread> {
+        self.state.lock().ok()?.threads.get(thread_id).cloned()
+    }
+
+    fn last_uid(&self) -> u32 {
+        self.state
+            .lock()
+            .map(|state| state.last_uid)
+            .unwrap_or_default()
+    }
+}
+
+/// Mail conversations over IMAP and SMTP. Each thread, found through `References` and
+/// `In-Reply-To`, is a `Direct` channel keyed by the id of its first message, and the mailbox
+/// is polled for new mail. Replies go out over SMTP to everyone else on the thread.
+///
+/// Servers are given as `imaps://`/`smtps://` URLs or bare hosts, which use TLS, or as
+/// `imap://`/`smtp://` for plaintext, such as a local bridge.
+pub struct EmailConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    poll_interval: Duration,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    mapper: Option<EmailMapper>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl EmailConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        EmailConnection {
+            auth: Vec::new(),
+            options,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            event_tx,
+            event_rx: Some(event_rx),
+            mapper: None,
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    /// How often the mailbox is checked for new mail, a minute by default.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    fn mapper(&self) -> Result<&EmailMapper, ConnectionError> {
+        self.mapper.as_ref().ok_or(ConnectionError::Closed)
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn fail_auth(&self, reason: String) -> ConnectionError {
+        self.set_status(ConnectionStatus::AuthFailed {
+            reason: reason.clone(),
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed {
+                reason: reason.clone(),
+            },
+        });
+        ConnectionError::Auth(reason)
+    }
+
+    /// Our own address: the `address` field, or the username when that is one.
+    fn address(&self) -> Option<String> {
+        field_text(&self.auth, "address")
+            .or_else(|| field_text(&self.auth, "username").filter(|name| name.contains('@')))
+            .map(|address| address.to_lowercase())
+    }
+
+    fn new_message_id(&self) -> String {
+        let address = self.address().unwrap_or_default();
+        let domain = address
+            .rsplit_once('@')
+            .map_or("localhost", |(_, domain)| domain);
+        format!("{}@{}", Uuid::new_v4(), domain)
+    }
+
+    /// Logs in and selects the mailbox, returning the session and how many messages it holds.
+    async fn open_mailbox(&self) -> Result<(Imap, u32), ConnectionError> {
+        let server = field_text(&self.auth, "imap_server")
+            .ok_or_else(|| ConnectionError::Auth("Missing IMAP server".to_string()))?;
+        let username = field_text(&self.auth, "username")
+            .ok_or_else(|| ConnectionError::Auth("Missing username".to_string()))?;
+        let password = field_text(&self.auth, "password")
+            .ok_or_else(|| ConnectionError::Auth("Missing password".to_string()))?;
+        let mailbox = field_text(&self.auth, "mailbox").unwrap_or_else(|| "INBOX".to_string());
+        let mut imap = Imap {
+            stream: open_stream(&ServerAddress::imap(&server)?, &self.options).await?,
+            tag: 0,
+        };
+        let greeting = tokio::time::timeout(COMMAND_TIMEOUT, imap.read_response())
+            .await
+            .map_err(|_| ConnectionError::Timeout)?
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        if !greeting.text.starts_with("* OK") && !greeting.text.starts_with("* PREAUTH") {
+            return Err(ConnectionError::Network(greeting.text));
+        }
+        if !greeting.text.starts_with("* PREAUTH") {
+            let login = format!("LOGIN {} {}", quote(&username), quote(&password));
+            match imap.command(&login).await {
+                Err(ConnectionError::Protocol(reason)) => {
+                    return Err(ConnectionError::Auth(reason))
+                }
+                result => result?,
+            };
+        }
+        let selected = imap.command(&format!("SELECT {}", quote(&mailbox))).await?;
+        let exists = selected
+            .iter()
+            .find_map(|response| response.count("EXISTS"))
+            .unwrap_or_default();
+        Ok((imap, exists))
+    }
+}
+
+impl Default for EmailConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+#[async_trait]
+impl Connection for EmailConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "email.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let address = self
+            .address()
+            .ok_or_else(|| ConnectionError::Auth("Missing address".to_string()))?;
+        let smtp = field_text(&self.auth, "smtp_server")
+            .ok_or_else(|| ConnectionError::Auth("Missing SMTP server".to_string()))?;
+        ServerAddress::smtp(&smtp)?;
+
+        self.tasks.shutdown().await;
+        self.mapper = None;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+        let (mut imap, exists) = match self.open_mailbox().await {
+            Ok(opened) => opened,
+            Err(ConnectionError::Auth(reason)) => return Err(self.fail_auth(reason)),
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+        let recent = match exists {
+            0 => Ok(Vec::new()),
+            _ => {
+                let first = exists.saturating_sub(HISTORY - 1).max(1);
+                imap.fetch(&format!("FETCH {}:* (UID BODY.PEEK[])", first))
+                    .await
+            }
+        };
+        let recent = match recent {
+            Ok(recent) => recent,
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+
+        let mapper = EmailMapper {
+            event_tx: self.event_tx.clone(),
+            address: address.clone(),
+            state: Arc::new(StdMutex::new(Mailbox::default())),
+        };
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected {
+                artifact: field_text(&self.auth, "mailbox"),
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::User {
+            event: UserEvent::Identify { user_id: address },
+        });
+        for (uid, raw) in &recent {
+            mapper.ingest(*uid, raw, false);
+        }
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Synced,
+        });
+        self.mapper = Some(mapper.clone());
+
+        let status = self.status.clone();
+        let poll_interval = self.poll_interval;
+        self.tasks
+            .spawn_essential("poll", self.status.clone(), async move {
+                let mut poll = tokio::time::interval(poll_interval);
+                poll.tick().await;
+                let reason = loop {
+                    poll.tick().await;
+                    let since = mapper.last_uid() + 1;
+                    match imap
+                        .fetch(&format!("UID FETCH {}:* (UID BODY.PEEK[])", since))
+                        .await
+                    {
+                        // `n:*` always matches the newest message, even when it is older than n.
+                        Ok(fetched) => fetched
+                            .into_iter()
+                            .filter(|(uid, _)| *uid >= since)
+                            .for_each(|(uid, raw)| mapper.ingest(uid, &raw, true)),
+                        Err(e) => break e.to_string(),
+                    }
+                };
+                if let Ok(mut current) = status.lock() {
+                    if *current == ConnectionStatus::Connected {
+                        *current = ConnectionStatus::Disconnected;
+                    }
+                }
+                mapper.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Disconnected {
+                        artifact: Some(reason),
+                    },
+                });
+            });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.mapper = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        let ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } = event
+        else {
+            return Err(ConnectionError::Unsupported(
+                "Event not supported over email".to_string(),
+            ));
+        };
+        let thread_id = scope.channel_id().ok_or_else(|| {
+            ConnectionError::Unsupported("Mail needs a thread channel".to_string())
+        })?;
+        let thread = self
+            .mapper()?
+            .thread(thread_id)
+            .ok_or_else(|| ConnectionError::Other(format!("Unknown thread {}", thread_id)))?;
+        if thread.participants.is_empty() {
+            return Err(ConnectionError::Other(
+                "Thread has no one to reply to".to_string(),
+            ));
+        }
+        let subject = match message
+            .extra
+            .get("subject")
+            .and_then(|subject| subject.as_str())
+        {
+            Some(subject) => subject.to_string(),
+            None if thread.subject.is_empty() => NO_SUBJECT.to_string(),
+            None if thread.references.is_empty() => thread.subject.clone(),
+            None => format!("Re: {}", thread.subject),
+        };
+        // A thread opened with `open_direct` starts with the message its id was made for.
+        let message_id = if thread.references.is_empty() {
+            thread.channel.id.clone()
+        } else {
+            self.new_message_id()
+        };
+        let from = self.address().ok_or(ConnectionError::Closed)?;
+        let mail = Outgoing {
+            from: from.clone(),
+            display_name: field_text(&self.auth, "display_name"),
+            recipients: thread.participants.clone(),
+            subject,
+            message_id: message_id.clone(),
+            references: thread.references.clone(),
+            body: plain_text(&message.content),
+        };
+        let smtp = field_text(&self.auth, "smtp_server")
+            .ok_or_else(|| ConnectionError::Auth("Missing SMTP server".to_string()))?;
+        let username = field_text(&self.auth, "username").unwrap_or_default();
+        let password = field_text(&self.auth, "password").unwrap_or_default();
+        submit(
+            &ServerAddress::smtp(&smtp)?,
+            &self.options,
+            (&username, &password),
+            &mail,
+        )
+        .await?;
+
+        let sent = Message {
+            id: Some(message_id.clone()),
+            sender_id: Some(from),
+            reply_to: thread.references.last().cloned(),
+            message_type: MessageType::CurrentUser,
+            status: MessageStatus::Delivered,
+            ..message
+        };
+        if let Ok(mut state) = self.mapper()?.state.lock() {
+            state
+                .thread_of
+                .insert(message_id.clone(), thread_id.to_string());
+            if let Some(thread) = state.threads.get_mut(thread_id) {
+                thread.references.push(message_id);
+                thread.messages.push(sent.clone());
+            }
+        }
+        let _ = self.event_tx.send(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope,
+                message: sent,
+            },
+        });
+        Ok(())
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        let thread = self
+            .mapper()?
+            .thread(channel_id)
+            .ok_or_else(|| ConnectionError::Other(format!("Unknown thread {}", channel_id)))?;
+        let end = before
+            .and_then(|before| {
+                thread
+                    .messages
+                    .iter()
+                    .position(|message| message.id.as_deref() == Some(before.as_str()))
+            })
+            .unwrap_or(thread.messages.len());
+        Ok(thread.messages[end.saturating_sub(limit)..end].to_vec())
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        let address = user_id.to_lowercase();
+        let thread_id = self.new_message_id();
+        let channel = Channel {
+            id: thread_id.clone(),
+            name: Some(address.clone()),
+            channel_type: ChannelType::Direct,
+            topic: None,
+            extra: HashMap::new(),
+        };
+        if let Ok(mut state) = self.mapper()?.state.lock() {
+            state.threads.insert(
+                thread_id,
+                Thread {
+                    channel: channel.clone(),
+                    subject: String::new(),
+                    participants: vec![address],
+                    references: Vec::new(),
+                    messages: Vec::new(),
+                },
+            );
+        }
+        let _ = self.event_tx.send(ConnectionEvent::Channel {
+            event: ChannelEvent::New {
+                channel: channel.clone(),
+            },
+        });
+        Ok(channel)
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        let field = |name: &str, display: &str, value: FieldValue, required: bool| AuthField {
+            name: name.to_string(),
+            display: Some(display.to_string()),
+            value,
+            required,
+        };
+        Protocol {
+            name: "email".to_string(),
+            auth: Some(vec![
+                field(
+                    "imap_server",
+                    "IMAP server, as host[:port] or imap(s)://host[:port]",
+                    FieldValue::Text(None),
+                    true,
+                ),
+                field(
+                    "smtp_server",
+                    "SMTP server, as host[:port] or smtp(s)://host[:port]",
+                    FieldValue::Text(None),
+                    true,
+                ),
+                field("username", "Username", FieldValue::Text(None), true),
+                field("password", "Password", FieldValue::Password(None), true),
+                field(
+                    "address",
+                    "Email address, if not the username",
+                    FieldValue::Text(None),
+                    false,
+                ),
+                field(
+                    "display_name",
+                    "Display name",
+                    FieldValue::Text(None),
+                    false,
+                ),
+                field("mailbox", "Mailbox (INBOX)", FieldValue::Text(None), false),
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            history: true,
+            multiple_channels: true,
+            ..Capabilities::default()
+        }
+    }
+
+    async fn preflight(&self, check_reachability: bool) -> PreflightReport {
+        let mut report = validate_auth(&self.protocol_spec(), &self.auth);
+        if field_text(&self.auth, "username").is_some() && self.address().is_none() {
+            report.error(
+                Some("address"),
+                "The username is not an email address, so one is needed",
+            );
+        }
+        let imap = field_text(&self.auth, "imap_server").and_then(|server| {
+            ServerAddress::imap(&server)
+                .map_err(|e| report.error(Some("imap_server"), e.to_string()))
+                .ok()
+        });
+        if let Some(server) = field_text(&self.auth, "smtp_server") {
+            if let Err(e) = ServerAddress::smtp(&server) {
+                report.error(Some("smtp_server"), e.to_string());
+            }
+        }
+        if check_reachability {
+            if let Some(imap) = imap {
+                let timeout = self
+                    .options
+                    .connect_timeout
+                    .unwrap_or(Duration::from_secs(5));
+                report.reachability = probe_reachability(&imap.host, imap.port, timeout).await;
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,55 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::utils::trace::event;
+
+use super::ConnectionEvent;
+
+/// A `ConnectionEvent` stamped with where and when it arrived. `seq` increases by one per event
+/// from the same connection, so envelopes merged from several connections can be ordered and
+/// checked for gaps.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Envelope {
+    pub received_at: DateTime<Utc>,
+    pub connection_id: String,
+    pub seq: u64,
+    pub event: ConnectionEvent,
+}
+
+impl Envelope {
+    pub fn new(connection_id: &str, seq: u64, event: ConnectionEvent) -> Self {
+        Envelope {
+            received_at: Utc::now(),
+            connection_id: connection_id.to_string(),
+            seq,
+            event,
+        }
+    }
+
+    /// Time elapsed since the event was received.
+    pub fn latency(&self) -> Duration {
+        Utc::now() - self.received_at
+    }
+}
+
+/// Wraps every event from `rx` in an `Envelope` for `connection_id`, numbering them from 1.
+pub fn stamp(
+    connection_id: &str,
+    mut rx: mpsc::UnboundedReceiver<ConnectionEvent>,
+) -> (mpsc::UnboundedReceiver<Envelope>, JoinHandle<()>) {
+    let connection_id = connection_id.to_string();
+    let (tx, envelopes) = mpsc::unbounded_channel();
+    let task = tokio::spawn(async move {
+        let mut seq = 0;
+        while let Some(event) = rx.recv().await {
+            seq += 1;
+            event!(trace, "dispatching event {} from {}", seq, connection_id);
+            if tx.send(Envelope::new(&connection_id, seq, event)).is_err() {
+                break;
+            }
+        }
+    });
+    (envelopes, task)
+}
@@ -0,0 +1,40 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ConnectionError {
+    Auth(String),
+    /// A setting the connection was given cannot be used, such as a malformed pattern.
+    Config(String),
+    Network(String),
+    Protocol(String),
+    Timeout,
+    RateLimited(Duration),
+    Unsupported(String),
+    NotFound(String),
+    Closed,
+    Other(String),
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::Auth(reason) => write!(f, "authentication error: {}", reason),
+            ConnectionError::Config(reason) => write!(f, "configuration error: {}", reason),
+            ConnectionError::Network(reason) => write!(f, "network error: {}", reason),
+            ConnectionError::Protocol(reason) => write!(f, "protocol error: {}", reason),
+            ConnectionError::Timeout => write!(f, "operation timed out"),
+            ConnectionError::RateLimited(wait) => {
+                write!(f, "rate limited, retry after {}ms", wait.as_millis())
+            }
+            ConnectionError::Unsupported(what) => write!(f, "unsupported: {}", what),
+            ConnectionError::NotFound(what) => write!(f, "not found: {}", what),
+            ConnectionError::Closed => write!(f, "connection closed"),
+            ConnectionError::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::{Connection, Message};
+
+use super::{ChatEvent, ConnectionError, ConnectionEvent, Scope};
+
+#[derive(Clone)]
+pub struct GroupMember {
+    pub name: String,
+    pub scope: Scope,
+    pub connection: Arc<Mutex<dyn Connection>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupSendResult {
+    pub name: String,
+    pub scope: Scope,
+    pub result: Result<(), ConnectionError>,
+}
+
+#[derive(Clone, Default)]
+pub struct ConnectionGroup {
+    pub name: String,
+    members: Vec<GroupMember>,
+}
+
+impl ConnectionGroup {
+    pub fn new(name: &str) -> Self {
+        ConnectionGroup {
+            name: name.to_string(),
+            members: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: &str, scope: Scope, connection: Arc<Mutex<dyn Connection>>) {
+        self.members.push(GroupMember {
+            name: name.to_string(),
+            scope,
+            connection,
+        });
+    }
+
+    pub fn remove(&mut self, name: &str, scope: &Scope) {
+        self.members.retain(|m| m.name != name || &m.scope != scope);
+    }
+
+    pub fn members(&self) -> &[GroupMember] {
+        &self.members
+    }
+
+    pub async fn send(&self, message: Message) -> Vec<GroupSendResult> {
+        let sends = self.members.iter().map(|member| {
+            let event = ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope: member.scope.clone(),
+                    message: message.clone(),
+                },
+            };
+            async move {
+                let result = member.connection.lock().await.send(event).await;
+                GroupSendResult {
+                    name: member.name.clone(),
+                    scope: member.scope.clone(),
+                    result,
+                }
+            }
+        });
+        futures::future::join_all(sends).await
+    }
+}
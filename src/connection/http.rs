@@ -0,0 +1,38 @@
+use reqwest::Url;
+
+use super::{ConnectionError, ConnectionOptions, Proxy, ProxyKind};
+
+pub(crate) fn http_client(options: &ConnectionOptions) -> Result<reqwest::Client, ConnectionError> {
+    let tls_error = |e: reqwest::Error| ConnectionError::Other(e.to_string());
+    let tls = &options.tls;
+    let mut builder =
+        reqwest::Client::builder().danger_accept_invalid_certs(tls.accept_invalid_certs);
+    for pem in &tls.root_certificates {
+        builder =
+            builder.add_root_certificate(reqwest::Certificate::from_pem(pem).map_err(tls_error)?);
+    }
+    if let Some(identity) = &tls.client_identity {
+        builder = builder.identity(
+            reqwest::Identity::from_pkcs8_pem(&identity.certificate_pem, &identity.key_pem)
+                .map_err(tls_error)?,
+        );
+    }
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(reqwest_proxy(proxy)?);
+    }
+    builder.build().map_err(tls_error)
+}
+
+fn reqwest_proxy(proxy: &Proxy) -> Result<reqwest::Proxy, ConnectionError> {
+    let scheme = match proxy.kind {
+        ProxyKind::Http => "http",
+        ProxyKind::Socks5 => "socks5h",
+    };
+    let mut proxy_url = Url::parse(&format!("{}://{}:{}", scheme, proxy.host, proxy.port))
+        .map_err(|e| ConnectionError::Other(e.to_string()))?;
+    if let Some((username, password)) = &proxy.credentials {
+        let _ = proxy_url.set_username(username);
+        let _ = proxy_url.set_password(Some(password));
+    }
+    reqwest::Proxy::all(proxy_url.as_str()).map_err(|e| ConnectionError::Other(e.to_string()))
+}
@@ -0,0 +1,1106 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{compose::plain_text, ids::IdNormalizer, trace::event},
+    AuthField, Capabilities, Channel, ChannelType, Connection, FieldValue, Message,
+    MessageFragment, MessageStatus, MessageType, Permissions, Presence, Profile, Protocol, Role,
+};
+
+use super::{
+    ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, ConnectionOptions, Scope,
+    StatusEvent, Supervisor, TlsConfig, UserEvent,
+};
+
+const DEFAULT_PORT: u16 = 6667;
+const DEFAULT_TLS_PORT: u16 = 6697;
+const MAX_MESSAGE_LENGTH: usize = 400;
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// IRCv3 capabilities requested when the server offers them.
+pub const REQUESTED_CAPS: &[&str] = &[
+    "multi-prefix",
+    "server-time",
+    "message-tags",
+    "away-notify",
+    "echo-message",
+];
+
+/// One IRC protocol line: optional IRCv3 tags and prefix, a command and its parameters.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IrcMessage {
+    pub tags: HashMap<String, String>,
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    pub fn new(command: &str, params: &[&str]) -> Self {
+        IrcMessage {
+            command: command.to_string(),
+            params: params.iter().map(|param| param.to_string()).collect(),
+            ..Self::default()
+        }
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+        let mut message = IrcMessage::default();
+
+        if let Some(tagged) = rest.strip_prefix('@') {
+            let (tags, tail) = tagged.split_once(' ')?;
+            for tag in tags.split(';').filter(|tag| !tag.is_empty()) {
+                let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+                message
+                    .tags
+                    .insert(key.to_string(), unescape_tag_value(value));
+            }
+            rest = tail.trim_start();
+        }
+        if let Some(prefixed) = rest.strip_prefix(':') {
+            let (prefix, tail) = prefixed.split_once(' ')?;
+            message.prefix = Some(prefix.to_string());
+            rest = tail.trim_start();
+        }
+
+        let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        if command.is_empty() {
+            return None;
+        }
+        message.command = command.to_ascii_uppercase();
+        while !rest.is_empty() {
+            if let Some(trailing) = rest.strip_prefix(':') {
+                message.params.push(trailing.to_string());
+                break;
+            }
+            let (param, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+            if !param.is_empty() {
+                message.params.push(param.to_string());
+            }
+            rest = tail;
+        }
+        Some(message)
+    }
+
+    /// The nickname part of the prefix, if it came from a user.
+    pub fn nick(&self) -> Option<&str> {
+        let prefix = self.prefix.as_deref()?;
+        match prefix.split_once('!') {
+            Some((nick, _)) => Some(nick),
+            None => (!prefix.contains('.')).then_some(prefix),
+        }
+    }
+
+    pub fn param(&self, index: usize) -> Option<&str> {
+        self.params.get(index).map(String::as_str)
+    }
+
+    pub fn to_line(&self) -> String {
+        let mut line = String::new();
+        if let Some(prefix) = &self.prefix {
+            line.push(':');
+            line.push_str(prefix);
+            line.push(' ');
+        }
+        line.push_str(&self.command);
+        for (i, param) in self.params.iter().enumerate() {
+            line.push(' ');
+            let last = i + 1 == self.params.len();
+            if last && (param.is_empty() || param.contains(' ') || param.starts_with(':')) {
+                line.push(':');
+            }
+            line.push_str(param);
+        }
+        line
+    }
+}
+
+fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+/// Removes mIRC bold, color, italic, underline, reverse and reset codes.
+pub fn strip_formatting(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x02' | '\x0f' | '\x11' | '\x16' | '\x1d' | '\x1e' | '\x1f' => {}
+            '\x03' => {
+                for _ in 0..2 {
+                    chars.next_if(char::is_ascii_digit);
+                }
+                if chars.peek() == Some(&',') {
+                    let mut lookahead = chars.clone();
+                    lookahead.next();
+                    if lookahead.peek().is_some_and(char::is_ascii_digit) {
+                        chars.next();
+                        for _ in 0..2 {
+                            chars.next_if(char::is_ascii_digit);
+                        }
+                    }
+                }
+            }
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+fn is_channel_name(target: &str) -> bool {
+    target.starts_with(['#', '&', '+', '!'])
+}
+
+fn irc_role(prefix: char) -> Option<Role> {
+    let rank = match prefix {
+        '~' => 5,
+        '&' => 4,
+        '@' => 3,
+        '%' => 2,
+        '+' => 1,
+        _ => return None,
+    };
+    Some(Role {
+        rank,
+        permissions: Permissions {
+            can_moderate: rank >= 2,
+            ..Permissions::default()
+        },
+        ..Role::default()
+    })
+}
+
+fn irc_profile(nick: &str, role: Option<Role>) -> Profile {
+    Profile {
+        id: Some(nick.to_string()),
+        username: Some(nick.to_string()),
+        presence: Some(Presence::Online),
+        role,
+        ..Profile::default()
+    }
+}
+
+fn irc_channel(name: &str, channel_type: ChannelType) -> Channel {
+    Channel {
+        id: name.to_string(),
+        name: Some(name.to_string()),
+        channel_type,
+        topic: None,
+        extra: HashMap::new(),
+    }
+}
+
+trait IrcStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> IrcStream for T {}
+
+/// Where to connect, parsed from `irc://host[:port]`, `ircs://host[:port]` or a bare
+/// `host[:port]`.
+#[derive(Clone, Debug, PartialEq)]
+struct ServerAddress {
+    host: String,
+    port: u16,
+    tls: bool,
+}
+
+impl ServerAddress {
+    fn parse(server: &str) -> Result<Self, ConnectionError> {
+        let (tls, rest) = if let Some(rest) = server.strip_prefix("ircs://") {
+            (true, rest)
+        } else {
+            (false, server.strip_prefix("irc://").unwrap_or(server))
+        };
+        let rest = rest.trim_end_matches('/');
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| ConnectionError::Auth(format!("Invalid port: {}", port)))?,
+            ),
+            None => (rest, if tls { DEFAULT_TLS_PORT } else { DEFAULT_PORT }),
+        };
+        if host.is_empty() {
+            return Err(ConnectionError::Auth("Server has no host".to_string()));
+        }
+        Ok(ServerAddress {
+            host: host.to_string(),
+            port,
+            tls,
+        })
+    }
+}
+
+/// IRC's default `rfc1459` casemapping: ASCII letters fold to lowercase and `[]\\~` fold to
+/// `{}|^`, for both channel names and nicknames.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IrcIds;
+
+impl IrcIds {
+    fn fold(id: &str) -> String {
+        id.chars()
+            .map(|c| match c {
+                '[' => '{',
+                ']' => '}',
+                '\\' => '|',
+                '~' => '^',
+                c => c.to_ascii_lowercase(),
+            })
+            .collect()
+    }
+}
+
+impl IdNormalizer for IrcIds {
+    fn normalize_channel_id(&self, channel_id: &str) -> String {
+        Self::fold(channel_id)
+    }
+
+    fn normalize_user_id(&self, user_id: &str) -> String {
+        Self::fold(user_id)
+    }
+}
+
+/// An IRC client connection with IRCv3 capability negotiation. Channels and users are keyed by
+/// their names and nicknames; a private message opens a `Direct` channel named after the other
+/// user. `/me` actions arrive and are sent as `MessageType::Action`.
+#[derive(Debug)]
+pub struct IrcConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    outbound: Option<mpsc::UnboundedSender<String>>,
+    writer: Option<JoinHandle<()>>,
+    nick: Arc<StdMutex<String>>,
+    caps: Arc<StdMutex<Vec<String>>>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl IrcConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        IrcConnection {
+            auth: Vec::new(),
+            options,
+            event_tx,
+            event_rx: Some(event_rx),
+            outbound: None,
+            writer: None,
+            nick: Arc::new(StdMutex::new(String::new())),
+            caps: Arc::new(StdMutex::new(Vec::new())),
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    /// The nickname the server knows us by, once registered.
+    pub fn nick(&self) -> String {
+        self.nick
+            .lock()
+            .map(|nick| nick.clone())
+            .unwrap_or_default()
+    }
+
+    /// Capabilities the server acknowledged during negotiation.
+    pub fn enabled_caps(&self) -> Vec<String> {
+        self.caps
+            .lock()
+            .map(|caps| caps.clone())
+            .unwrap_or_default()
+    }
+
+    fn send_line(&self, message: IrcMessage) -> Result<(), ConnectionError> {
+        self.outbound
+            .as_ref()
+            .ok_or(ConnectionError::Closed)?
+            .send(message.to_line())
+            .map_err(|_| ConnectionError::Closed)
+    }
+
+    async fn open_stream(
+        &self,
+        address: &ServerAddress,
+    ) -> Result<Box<dyn IrcStream>, ConnectionError> {
+        if self.options.proxy.is_some() {
+            return Err(ConnectionError::Unsupported(
+                "Proxies for IRC connections".to_string(),
+            ));
+        }
+        let network = |e: std::io::Error| ConnectionError::Network(e.to_string());
+        let connect = TcpStream::connect((address.host.as_str(), address.port));
+        let tcp = match self.options.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect)
+                .await
+                .map_err(|_| ConnectionError::Timeout)?,
+            None => connect.await,
+        }
+        .map_err(network)?;
+        if !address.tls {
+            return Ok(Box::new(tcp));
+        }
+
+        let connector = tokio_native_tls::TlsConnector::from(tls_connector(&self.options.tls)?);
+        let tls = connector
+            .connect(&address.host, tcp)
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        Ok(Box::new(tls))
+    }
+
+    /// Stops the reader, then gives the writer up to `CLOSE_TIMEOUT` to flush what is queued,
+    /// such as a final QUIT.
+    async fn stop_tasks(&mut self) {
+        self.tasks.shutdown().await;
+        self.outbound = None;
+        if let Some(mut writer) = self.writer.take() {
+            if tokio::time::timeout(CLOSE_TIMEOUT, &mut writer)
+                .await
+                .is_err()
+            {
+                writer.abort();
+            }
+        }
+    }
+}
+
+impl Default for IrcConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn tls_connector(tls: &TlsConfig) -> Result<native_tls::TlsConnector, ConnectionError> {
+    let tls_error = |e: native_tls::Error| ConnectionError::Other(e.to_string());
+    let mut builder = native_tls::TlsConnector::builder();
+    builder.danger_accept_invalid_certs(tls.accept_invalid_certs);
+    for pem in &tls.root_certificates {
+        builder.add_root_certificate(native_tls::Certificate::from_pem(pem).map_err(tls_error)?);
+    }
+    if let Some(identity) = &tls.client_identity {
+        builder.identity(
+            native_tls::Identity::from_pkcs8(&identity.certificate_pem, &identity.key_pem)
+                .map_err(tls_error)?,
+        );
+    }
+    builder.build().map_err(tls_error)
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+/// Reader-side protocol state: negotiation progress, channel membership and pending NAMES
+/// replies.
+struct Session {
+    nick: Arc<StdMutex<String>>,
+    caps: Arc<StdMutex<Vec<String>>>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    out_tx: mpsc::UnboundedSender<String>,
+    autojoin: Vec<String>,
+    offered_caps: Vec<String>,
+    registered: bool,
+    members: HashMap<String, HashSet<String>>,
+    names: HashMap<String, Vec<UserEvent>>,
+    direct: HashSet<String>,
+}
+
+impl Session {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn reply(&self, message: IrcMessage) {
+        let _ = self.out_tx.send(message.to_line());
+    }
+
+    fn current_nick(&self) -> String {
+        self.nick
+            .lock()
+            .map(|nick| nick.clone())
+            .unwrap_or_default()
+    }
+
+    fn is_self(&self, nick: &str) -> bool {
+        nick.eq_ignore_ascii_case(&self.current_nick())
+    }
+
+    fn handle(&mut self, message: IrcMessage) {
+        let nick = message.nick().unwrap_or_default().to_string();
+        match message.command.as_str() {
+            "PING" => self.reply(IrcMessage::new(
+                "PONG",
+                &message
+                    .params
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>(),
+            )),
+            "PONG" => self.emit(ConnectionEvent::Status {
+                event: StatusEvent::Ping {
+                    artifact: message.params.last().cloned(),
+                },
+            }),
+            "CAP" => self.handle_cap(&message),
+            "001" => {
+                if let Some(nick) = message.param(0) {
+                    if let Ok(mut current) = self.nick.lock() {
+                        *current = nick.to_string();
+                    }
+                }
+                self.registered = true;
+                set_status(&self.status, ConnectionStatus::Connected);
+                self.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Connected {
+                        artifact: message.prefix.clone(),
+                    },
+                });
+                self.emit(ConnectionEvent::User {
+                    event: UserEvent::Identify {
+                        user_id: self.current_nick(),
+                    },
+                });
+                if !self.autojoin.is_empty() {
+                    self.reply(IrcMessage::new("JOIN", &[&self.autojoin.join(",")]));
+                }
+            }
+            "376" | "422" => self.emit(ConnectionEvent::Status {
+                event: StatusEvent::Synced,
+            }),
+            "433" if !self.registered => {
+                let retry = format!("{}_", self.current_nick());
+                if let Ok(mut current) = self.nick.lock() {
+                    current.clone_from(&retry);
+                }
+                self.reply(IrcMessage::new("NICK", &[&retry]));
+            }
+            "464" | "465" => {
+                let reason = message.params.last().cloned().unwrap_or_default();
+                set_status(
+                    &self.status,
+                    ConnectionStatus::AuthFailed {
+                        reason: reason.clone(),
+                    },
+                );
+                self.emit(ConnectionEvent::Status {
+                    event: StatusEvent::AuthFailed { reason },
+                });
+            }
+            "JOIN" => {
+                let Some(channel_id) = message.param(0).map(str::to_string) else {
+                    return;
+                };
+                self.members
+                    .entry(channel_id.clone())
+                    .or_default()
+                    .insert(nick.clone());
+                if self.is_self(&nick) {
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::New {
+                            channel: irc_channel(&channel_id, ChannelType::Group),
+                        },
+                    });
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::Join { channel_id },
+                    });
+                } else {
+                    self.emit(ConnectionEvent::User {
+                        event: UserEvent::New {
+                            scope: Scope::channel(channel_id),
+                            user: irc_profile(&nick, None),
+                        },
+                    });
+                }
+            }
+            "PART" => {
+                if let Some(channel_id) = message.param(0) {
+                    self.leave(channel_id, &nick, None);
+                }
+            }
+            "KICK" => {
+                if let (Some(channel_id), Some(victim)) = (message.param(0), message.param(1)) {
+                    self.leave(channel_id, victim, message.param(2).map(str::to_string));
+                }
+            }
+            "QUIT" => {
+                for (channel_id, members) in self.members.iter_mut() {
+                    if members.remove(&nick) {
+                        let _ = self.event_tx.send(ConnectionEvent::User {
+                            event: UserEvent::Remove {
+                                scope: Scope::channel(channel_id.clone()),
+                                user_id: nick.clone(),
+                            },
+                        });
+                    }
+                }
+            }
+            "NICK" => {
+                let Some(new_nick) = message.param(0).map(str::to_string) else {
+                    return;
+                };
+                if self.is_self(&nick) {
+                    if let Ok(mut current) = self.nick.lock() {
+                        current.clone_from(&new_nick);
+                    }
+                    self.emit(ConnectionEvent::User {
+                        event: UserEvent::Identify {
+                            user_id: new_nick.clone(),
+                        },
+                    });
+                }
+                for (channel_id, members) in self.members.iter_mut() {
+                    if !members.remove(&nick) {
+                        continue;
+                    }
+                    members.insert(new_nick.clone());
+                    let scope = Scope::channel(channel_id.clone());
+                    let _ = self.event_tx.send(ConnectionEvent::User {
+                        event: UserEvent::Batch {
+                            events: vec![
+                                UserEvent::Remove {
+                                    scope: scope.clone(),
+                                    user_id: nick.clone(),
+                                },
+                                UserEvent::New {
+                                    scope,
+                                    user: irc_profile(&new_nick, None),
+                                },
+                            ],
+                        },
+                    });
+                }
+            }
+            "AWAY" => {
+                let presence = if message.params.is_empty() {
+                    Presence::Online
+                } else {
+                    Presence::Away
+                };
+                self.emit(ConnectionEvent::User {
+                    event: UserEvent::PresenceChanged {
+                        user_id: nick,
+                        presence,
+                    },
+                });
+            }
+            "TOPIC" | "332" => {
+                let (channel_index, set_by) = if message.command == "TOPIC" {
+                    (0, Some(nick))
+                } else {
+                    (1, None)
+                };
+                if let Some(channel_id) = message.param(channel_index) {
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::TopicChanged {
+                            channel_id: channel_id.to_string(),
+                            topic: message
+                                .param(channel_index + 1)
+                                .map(strip_formatting)
+                                .filter(|topic| !topic.is_empty()),
+                            set_by,
+                        },
+                    });
+                }
+            }
+            "353" => {
+                let (Some(channel_id), Some(names)) = (message.param(2), message.param(3)) else {
+                    return;
+                };
+                let members = self.members.entry(channel_id.to_string()).or_default();
+                let batch = self.names.entry(channel_id.to_string()).or_default();
+                for name in names.split_whitespace() {
+                    let nick = name.trim_start_matches(['~', '&', '@', '%', '+']);
+                    members.insert(nick.to_string());
+                    batch.push(UserEvent::New {
+                        scope: Scope::channel(channel_id),
+                        user: irc_profile(nick, name.chars().next().and_then(irc_role)),
+                    });
+                }
+            }
+            "366" => {
+                let Some(channel_id) = message.param(1) else {
+                    return;
+                };
+                let mut events = vec![UserEvent::ClearList {
+                    scope: Scope::channel(channel_id),
+                }];
+                events.extend(self.names.remove(channel_id).unwrap_or_default());
+                self.emit(ConnectionEvent::User {
+                    event: UserEvent::Batch { events },
+                });
+            }
+            "PRIVMSG" | "NOTICE" => self.handle_message(&message, nick),
+            "ERROR" => {
+                set_status(&self.status, ConnectionStatus::Disconnected);
+                self.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Disconnected {
+                        artifact: message.params.last().cloned(),
+                    },
+                });
+            }
+            command if command.starts_with(['4', '5']) && command.len() == 3 => {
+                self.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Error {
+                        message: message.params[1..].join(" "),
+                    },
+                });
+            }
+            _ => event!(trace, "unhandled IRC command {}", message.command),
+        }
+    }
+
+    fn handle_cap(&mut self, message: &IrcMessage) {
+        match message.param(1) {
+            Some("LS") => {
+                let more = message.param(2) == Some("*");
+                if let Some(caps) = message.params.last() {
+                    self.offered_caps.extend(
+                        caps.split_whitespace()
+                            .map(|cap| cap.split('=').next().unwrap_or(cap).to_string()),
+                    );
+                }
+                if more {
+                    return;
+                }
+                let wanted: Vec<&str> = REQUESTED_CAPS
+                    .iter()
+                    .copied()
+                    .filter(|cap| self.offered_caps.iter().any(|offered| offered == cap))
+                    .collect();
+                if wanted.is_empty() {
+                    self.reply(IrcMessage::new("CAP", &["END"]));
+                } else {
+                    self.reply(IrcMessage::new("CAP", &["REQ", &wanted.join(" ")]));
+                }
+            }
+            Some("ACK") => {
+                if let (Some(acked), Ok(mut caps)) = (message.params.last(), self.caps.lock()) {
+                    for cap in acked.split_whitespace() {
+                        match cap.strip_prefix('-') {
+                            Some(removed) => caps.retain(|enabled| enabled != removed),
+                            None => caps.push(cap.to_string()),
+                        }
+                    }
+                }
+                if !self.registered {
+                    self.reply(IrcMessage::new("CAP", &["END"]));
+                }
+            }
+            Some("NAK") if !self.registered => self.reply(IrcMessage::new("CAP", &["END"])),
+            _ => {}
+        }
+    }
+
+    fn handle_message(&mut self, message: &IrcMessage, nick: String) {
+        let (Some(target), Some(text)) = (message.param(0), message.param(1)) else {
+            return;
+        };
+        let from_self = self.is_self(&nick);
+        let scope = if is_channel_name(target) {
+            Scope::channel(target)
+        } else if nick.is_empty() || message.prefix.as_deref().is_some_and(|p| p.contains('.')) {
+            Scope::Global
+        } else {
+            let peer = if from_self { target } else { nick.as_str() };
+            if self.direct.insert(peer.to_ascii_lowercase()) {
+                self.emit(ConnectionEvent::Channel {
+                    event: ChannelEvent::New {
+                        channel: irc_channel(peer, ChannelType::Direct),
+                    },
+                });
+            }
+            Scope::channel(peer)
+        };
+
+        let (text, action) = match text
+            .strip_prefix("\x01ACTION ")
+            .map(|action| action.trim_end_matches('\x01'))
+        {
+            Some(action) => (action, true),
+            None if text.starts_with('\x01') => return,
+            None => (text, false),
+        };
+        let message_type = if action {
+            MessageType::Action
+        } else if message.command == "NOTICE" {
+            MessageType::Server
+        } else if from_self {
+            MessageType::CurrentUser
+        } else {
+            MessageType::Normal
+        };
+
+        self.emit(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope,
+                message: Message {
+                    id: message.tags.get("msgid").cloned(),
+                    sender_id: (!nick.is_empty()).then_some(nick),
+                    content: vec![MessageFragment::Text(strip_formatting(text))],
+                    timestamp: message
+                        .tags
+                        .get("time")
+                        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                        .map(|time| time.with_timezone(&Utc))
+                        .unwrap_or_else(Utc::now),
+                    message_type,
+                    status: MessageStatus::Delivered,
+                    reactions: Vec::new(),
+                    reply_to: None,
+                    thread_id: None,
+                    extra: HashMap::new(),
+                },
+            },
+        });
+    }
+
+    fn leave(&mut self, channel_id: &str, nick: &str, reason: Option<String>) {
+        if self.is_self(nick) {
+            self.members.remove(channel_id);
+            let event = match reason {
+                Some(reason) => ChannelEvent::Kick {
+                    scope: Scope::channel(channel_id),
+                    reason: Some(reason),
+                    ban: false,
+                    until: None,
+                },
+                None => ChannelEvent::Leave {
+                    channel_id: channel_id.to_string(),
+                },
+            };
+            self.emit(ConnectionEvent::Channel { event });
+            return;
+        }
+        if let Some(members) = self.members.get_mut(channel_id) {
+            members.remove(nick);
+        }
+        self.emit(ConnectionEvent::User {
+            event: UserEvent::Remove {
+                scope: Scope::channel(channel_id),
+                user_id: nick.to_string(),
+            },
+        });
+    }
+}
+
+fn set_status(cell: &StdMutex<ConnectionStatus>, status: ConnectionStatus) {
+    if let Ok(mut current) = cell.lock() {
+        *current = status;
+    }
+}
+
+#[async_trait]
+impl Connection for IrcConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "irc.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let server = field_text(&self.auth, "server")
+            .ok_or_else(|| ConnectionError::Auth("Missing server".to_string()))?;
+        let nick = field_text(&self.auth, "nick")
+            .ok_or_else(|| ConnectionError::Auth("Missing nick".to_string()))?;
+        let address = ServerAddress::parse(&server)?;
+        let username = field_text(&self.auth, "username").unwrap_or_else(|| nick.clone());
+        let realname = field_text(&self.auth, "realname").unwrap_or_else(|| nick.clone());
+        let autojoin: Vec<String> = field_text(&self.auth, "channels")
+            .map(|channels| {
+                channels
+                    .split([',', ' '])
+                    .filter(|channel| !channel.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        self.stop_tasks().await;
+        set_status(&self.status, ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+
+        let stream = match self.open_stream(&address).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                set_status(&self.status, ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+        let (read, mut write) = tokio::io::split(stream);
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel::<String>();
+
+        if let Ok(mut current) = self.nick.lock() {
+            current.clone_from(&nick);
+        }
+        if let Ok(mut caps) = self.caps.lock() {
+            caps.clear();
+        }
+        let mut registration = vec![IrcMessage::new("CAP", &["LS", "302"])];
+        if let Some(password) = field_text(&self.auth, "password") {
+            registration.push(IrcMessage::new("PASS", &[&password]));
+        }
+        registration.push(IrcMessage::new("NICK", &[&nick]));
+        registration.push(IrcMessage::new("USER", &[&username, "0", "*", &realname]));
+        for message in registration {
+            let _ = out_tx.send(message.to_line());
+        }
+
+        self.writer = Some(tokio::spawn(async move {
+            while let Some(line) = out_rx.recv().await {
+                event!(trace, "sending {}", line);
+                let quit = line.starts_with("QUIT");
+                if write
+                    .write_all(format!("{}\r\n", line).as_bytes())
+                    .await
+                    .is_err()
+                    || quit
+                {
+                    break;
+                }
+            }
+            let _ = write.shutdown().await;
+        }));
+
+        let mut session = Session {
+            nick: self.nick.clone(),
+            caps: self.caps.clone(),
+            status: self.status.clone(),
+            event_tx: self.event_tx.clone(),
+            out_tx: out_tx.clone(),
+            autojoin,
+            offered_caps: Vec::new(),
+            registered: false,
+            members: HashMap::new(),
+            names: HashMap::new(),
+            direct: HashSet::new(),
+        };
+        self.tasks
+            .spawn_essential("reader", self.status.clone(), async move {
+                let mut lines = BufReader::new(read).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    match IrcMessage::parse(&line) {
+                        Some(message) => {
+                            event!(trace, "received {:?}", message);
+                            session.handle(message);
+                        }
+                        None => event!(debug, "unparsed IRC line {:?}", line),
+                    }
+                }
+                event!(debug, "IRC reader closed");
+                let was_connected = session
+                    .status
+                    .lock()
+                    .is_ok_and(|status| *status == ConnectionStatus::Connected);
+                if was_connected {
+                    set_status(&session.status, ConnectionStatus::Disconnected);
+                    session.emit(ConnectionEvent::Status {
+                        event: StatusEvent::Disconnected {
+                            artifact: Some("Connection closed".to_string()),
+                        },
+                    });
+                }
+            });
+
+        self.outbound = Some(out_tx);
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        if let Some(outbound) = &self.outbound {
+            let _ = outbound.send(IrcMessage::new("QUIT", &["Leaving"]).to_line());
+        }
+        self.stop_tasks().await;
+        set_status(&self.status, ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } => {
+                let target = scope.channel_id().ok_or_else(|| {
+                    ConnectionError::Unsupported("IRC messages without a channel".to_string())
+                })?;
+                let text = plain_text(&message.content);
+                for line in text.lines().filter(|line| !line.is_empty()) {
+                    let line = match message.message_type {
+                        MessageType::Action => format!("\x01ACTION {}\x01", line),
+                        _ => line.to_string(),
+                    };
+                    self.send_line(IrcMessage::new("PRIVMSG", &[target, &line]))?;
+                }
+                if !self.enabled_caps().iter().any(|cap| cap == "echo-message") {
+                    let message_type = match message.message_type {
+                        MessageType::Action => MessageType::Action,
+                        _ => MessageType::CurrentUser,
+                    };
+                    let _ = self.event_tx.send(ConnectionEvent::Chat {
+                        event: ChatEvent::New {
+                            scope,
+                            message: Message {
+                                sender_id: Some(self.nick()),
+                                message_type,
+                                status: MessageStatus::Delivered,
+                                ..message
+                            },
+                        },
+                    });
+                }
+                Ok(())
+            }
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Join { channel_id },
+            } => self.send_line(IrcMessage::new("JOIN", &[&channel_id])),
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Leave { channel_id },
+            } => self.send_line(IrcMessage::new("PART", &[&channel_id])),
+            ConnectionEvent::Channel {
+                event:
+                    ChannelEvent::TopicChanged {
+                        channel_id, topic, ..
+                    },
+            } => self.send_line(IrcMessage::new(
+                "TOPIC",
+                &[&channel_id, topic.as_deref().unwrap_or_default()],
+            )),
+            ConnectionEvent::Status {
+                event: StatusEvent::Ping { artifact },
+            } => self.send_line(IrcMessage::new(
+                "PING",
+                &[artifact.as_deref().unwrap_or("oshatori")],
+            )),
+            _ => Err(ConnectionError::Unsupported(
+                "Event not supported over IRC".to_string(),
+            )),
+        }
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        if user_id.is_empty() || is_channel_name(user_id) || user_id.contains(' ') {
+            return Err(ConnectionError::Other(format!(
+                "Invalid nickname: {}",
+                user_id
+            )));
+        }
+        let channel = irc_channel(user_id, ChannelType::Direct);
+        self.event_tx
+            .send(ConnectionEvent::Channel {
+                event: ChannelEvent::New {
+                    channel: channel.clone(),
+                },
+            })
+            .map_err(|_| ConnectionError::Closed)?;
+        Ok(channel)
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        let field = |name: &str, display: &str, value, required| AuthField {
+            name: name.to_string(),
+            display: Some(display.to_string()),
+            value,
+            required,
+        };
+        Protocol {
+            name: "irc".to_string(),
+            auth: Some(vec![
+                field(
+                    "server",
+                    "Server, as host[:port], irc://host[:port] or ircs://host[:port]",
+                    FieldValue::Text(None),
+                    true,
+                ),
+                field("nick", "Nickname", FieldValue::Text(None), true),
+                field(
+                    "password",
+                    "Server password",
+                    FieldValue::Password(None),
+                    false,
+                ),
+                field("username", "Username", FieldValue::Text(None), false),
+                field("realname", "Real name", FieldValue::Text(None), false),
+                field(
+                    "channels",
+                    "Comma-separated channels to join",
+                    FieldValue::Text(None),
+                    false,
+                ),
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            multiple_channels: true,
+            topics: true,
+            max_message_length: Some(MAX_MESSAGE_LENGTH),
+            ..Capabilities::default()
+        }
+    }
+
+    fn id_normalizer(&self) -> Arc<dyn IdNormalizer> {
+        Arc::new(IrcIds)
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{utils::trace::event, Message};
+
+use super::{ChatEvent, ConnectionEvent, EventMiddleware, Scope};
+
+/// The `extra` key a tagged send carries, holding the scope the content was first sent to.
+pub const LOOP_TAG: &str = "loop_first_scope";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum LoopMode {
+    /// Let the send through, tagged with [`LOOP_TAG`].
+    #[default]
+    Tag,
+    /// Drop the send; tracked sends resolve as failed.
+    Reject,
+}
+
+/// Outbound middleware that catches cross-posting loops: the same content sent to a second
+/// scope within `window` of the first send. Repeating content in the same scope is never a
+/// loop, and a caught send does not extend the window.
+#[derive(Debug)]
+pub struct LoopGuard {
+    pub window: Duration,
+    pub mode: LoopMode,
+    recent: Mutex<HashMap<u64, SentFingerprint>>,
+}
+
+#[derive(Debug)]
+struct SentFingerprint {
+    scope: Scope,
+    sent_at: Instant,
+}
+
+impl LoopGuard {
+    pub fn new(window: Duration, mode: LoopMode) -> Self {
+        LoopGuard {
+            window,
+            mode,
+            recent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The scope this content was first sent to, when sending it to `scope` now would loop.
+    fn first_scope(&self, scope: &Scope, message: &Message) -> Option<Scope> {
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        recent.retain(|_, sent| now.duration_since(sent.sent_at) < self.window);
+
+        let key = fingerprint(message);
+        match recent.get(&key) {
+            Some(sent) if sent.scope != *scope => Some(sent.scope.clone()),
+            _ => {
+                recent.insert(
+                    key,
+                    SentFingerprint {
+                        scope: scope.clone(),
+                        sent_at: now,
+                    },
+                );
+                None
+            }
+        }
+    }
+}
+
+impl Default for LoopGuard {
+    fn default() -> Self {
+        LoopGuard::new(Duration::from_secs(5), LoopMode::default())
+    }
+}
+
+impl EventMiddleware for LoopGuard {
+    fn outbound(&self, mut event: ConnectionEvent) -> Option<ConnectionEvent> {
+        let ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } = &mut event
+        else {
+            return Some(event);
+        };
+        let Some(first_scope) = self.first_scope(scope, message) else {
+            return Some(event);
+        };
+
+        event!(
+            warn,
+            "loop detected: {:?} already went to {:?}",
+            scope,
+            first_scope
+        );
+        match self.mode {
+            LoopMode::Reject => None,
+            LoopMode::Tag => {
+                message.extra.insert(
+                    LOOP_TAG.to_string(),
+                    serde_json::to_value(first_scope).unwrap_or_default(),
+                );
+                Some(event)
+            }
+        }
+    }
+}
+
+fn fingerprint(message: &Message) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(&message.content)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
+}
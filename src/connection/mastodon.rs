@@ -0,0 +1,781 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use url::Url;
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{compose::plain_text, html::html_fragments, trace::event},
+    AuthField, Capabilities, Channel, ChannelType, Connection, FieldValue, Message,
+    MessageFragment, MessageStatus, MessageType, Profile, Protocol,
+};
+
+use super::{
+    http::http_client, transport::connect_websocket, ChannelEvent, ChatEvent, ConnectionError,
+    ConnectionEvent, ConnectionOptions, Scope, SendHandle, SendOutcome, StatusEvent, Supervisor,
+    UserEvent,
+};
+
+const CONVERSATION_PAGE_SIZE: usize = 40;
+const DEFAULT_MAX_CHARACTERS: usize = 500;
+
+/// An authenticated handle on an instance's REST API.
+#[derive(Clone, Debug)]
+struct MastodonApi {
+    http: reqwest::Client,
+    instance: Url,
+    access_token: String,
+}
+
+impl MastodonApi {
+    fn url(&self, path: &str) -> Url {
+        let mut url = self.instance.clone();
+        url.set_path(&format!("/api/{}", path));
+        url
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<Value>,
+    ) -> Result<Value, ConnectionError> {
+        let mut request = self
+            .http
+            .request(method, self.url(path))
+            .bearer_auth(&self.access_token)
+            .query(query);
+        if let Some(body) = body {
+            request = request
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string());
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        let status = response.status();
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        let body: Value = serde_json::from_slice(&bytes).unwrap_or_default();
+        if status.is_success() {
+            return Ok(body);
+        }
+
+        let error = body["error"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| status.to_string());
+        Err(match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ConnectionError::Auth(error),
+            StatusCode::TOO_MANY_REQUESTS => ConnectionError::RateLimited(Duration::from_secs(60)),
+            _ => ConnectionError::Protocol(error),
+        })
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> Result<Value, ConnectionError> {
+        self.request(Method::GET, path, query, None).await
+    }
+
+    async fn post(&self, path: &str, body: Value) -> Result<Value, ConnectionError> {
+        self.request(Method::POST, path, &[], Some(body)).await
+    }
+}
+
+fn profile_from(account: &Value) -> Profile {
+    Profile {
+        id: account["id"].as_str().map(str::to_string),
+        username: account["acct"].as_str().map(str::to_string),
+        display_name: account["display_name"]
+            .as_str()
+            .filter(|name| !name.is_empty())
+            .map(str::to_string),
+        picture: account["avatar"].as_str().map(str::to_string),
+        ..Profile::default()
+    }
+}
+
+/// The oldest-first order of status ids, which are numeric strings of varying length.
+fn status_order(a: &str, b: &str) -> std::cmp::Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+fn message_from(status: &Value) -> Message {
+    let mut content = html_fragments(status["content"].as_str().unwrap_or_default());
+    for fragment in &mut content {
+        if let MessageFragment::Mention { user_id, display } = fragment {
+            let mention = status["mentions"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|mention| mention["url"].as_str() == Some(user_id.as_str()));
+            if let Some(mention) = mention {
+                *user_id = mention["id"].as_str().unwrap_or_default().to_string();
+                if let Some(acct) = mention["acct"].as_str() {
+                    *display = acct.to_string();
+                }
+            }
+        }
+    }
+    for media in status["media_attachments"].as_array().into_iter().flatten() {
+        let url = media["url"].as_str().unwrap_or_default().to_string();
+        content.push(match media["type"].as_str() {
+            Some("image") => MessageFragment::Image {
+                url,
+                mime: "image/*".to_string(),
+            },
+            Some("video") | Some("gifv") => MessageFragment::Video {
+                url,
+                mime: "video/*".to_string(),
+            },
+            Some("audio") => MessageFragment::Audio {
+                url,
+                mime: "audio/*".to_string(),
+            },
+            _ => MessageFragment::Url(url),
+        });
+    }
+    if let Some(warning) = status["spoiler_text"].as_str().filter(|s| !s.is_empty()) {
+        content = vec![
+            MessageFragment::Text(warning.to_string()),
+            MessageFragment::Spoiler(content),
+        ];
+    }
+
+    let mut extra = HashMap::new();
+    if let Some(url) = status["url"].as_str() {
+        extra.insert("url".to_string(), json!(url));
+    }
+    Message {
+        id: status["id"].as_str().map(str::to_string),
+        sender_id: status["account"]["id"].as_str().map(str::to_string),
+        content,
+        timestamp: status["created_at"]
+            .as_str()
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .map(|at| at.with_timezone(&Utc))
+            .unwrap_or_else(Utc::now),
+        message_type: MessageType::Normal,
+        status: if status["edited_at"].is_string() {
+            MessageStatus::Edited
+        } else {
+            MessageStatus::Sent
+        },
+        reactions: Vec::new(),
+        reply_to: status["in_reply_to_id"].as_str().map(str::to_string),
+        thread_id: None,
+        extra,
+    }
+}
+
+/// What a direct message needs to land in an existing conversation: who to address and which
+/// status to reply to.
+#[derive(Clone, Debug, Default)]
+struct Conversation {
+    participants: Vec<String>,
+    last_status: Option<String>,
+}
+
+/// Tracks known conversations and turns conversation payloads into connection events.
+#[derive(Clone, Debug)]
+struct MastodonMapper {
+    conversations: Arc<StdMutex<HashMap<String, Conversation>>>,
+    statuses: Arc<StdMutex<HashMap<String, String>>>,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+}
+
+impl MastodonMapper {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    /// Applies a conversation, announcing it if unseen and its last status if that is new.
+    fn conversation(&self, value: &Value) {
+        let Some(id) = value["id"].as_str() else {
+            return;
+        };
+        let accounts: Vec<&Value> = value["accounts"].as_array().into_iter().flatten().collect();
+        let last_status = value["last_status"]["id"].as_str().map(str::to_string);
+        let (is_new, previous) = {
+            let Ok(mut conversations) = self.conversations.lock() else {
+                return;
+            };
+            let is_new = !conversations.contains_key(id);
+            let entry = conversations.entry(id.to_string()).or_default();
+            entry.participants = accounts
+                .iter()
+                .filter_map(|account| account["acct"].as_str().map(str::to_string))
+                .collect();
+            let previous = std::mem::replace(&mut entry.last_status, last_status.clone());
+            (is_new, previous)
+        };
+
+        if is_new {
+            let names: Vec<&str> = accounts
+                .iter()
+                .filter_map(|account| account["acct"].as_str())
+                .collect();
+            let mut extra = HashMap::new();
+            extra.insert(
+                "participants".to_string(),
+                json!(accounts
+                    .iter()
+                    .filter_map(|account| account["id"].as_str())
+                    .collect::<Vec<_>>()),
+            );
+            self.emit(ConnectionEvent::Channel {
+                event: ChannelEvent::New {
+                    channel: Channel {
+                        id: id.to_string(),
+                        name: (!names.is_empty()).then(|| names.join(", ")),
+                        channel_type: ChannelType::Direct,
+                        topic: None,
+                        extra,
+                    },
+                },
+            });
+            self.emit(ConnectionEvent::Channel {
+                event: ChannelEvent::Join {
+                    channel_id: id.to_string(),
+                },
+            });
+        }
+        if last_status.is_some() && last_status != previous {
+            self.status(id, &value["last_status"]);
+        }
+    }
+
+    fn status(&self, conversation_id: &str, status: &Value) {
+        let message = message_from(status);
+        if let (Some(id), Ok(mut statuses)) = (&message.id, self.statuses.lock()) {
+            statuses.insert(id.clone(), conversation_id.to_string());
+        }
+        self.emit(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel(conversation_id),
+                message,
+            },
+        });
+    }
+
+    fn delete(&self, status_id: &str) {
+        let conversation = self
+            .statuses
+            .lock()
+            .ok()
+            .and_then(|mut statuses| statuses.remove(status_id));
+        if let Some(conversation_id) = conversation {
+            self.emit(ConnectionEvent::Chat {
+                event: ChatEvent::Remove {
+                    scope: Scope::channel(conversation_id),
+                    message_id: status_id.to_string(),
+                },
+            });
+        }
+    }
+
+    /// Applies one frame from the `direct` stream, whose payloads are JSON-encoded strings.
+    fn apply(&self, frame: &Value) {
+        let payload = frame["payload"].as_str().unwrap_or_default();
+        match frame["event"].as_str() {
+            Some("conversation") => match serde_json::from_str::<Value>(payload) {
+                Ok(conversation) => self.conversation(&conversation),
+                Err(e) => event!(debug, "unparsed conversation payload: {}", e),
+            },
+            Some("delete") => self.delete(payload),
+            _ => {}
+        }
+    }
+}
+
+/// A Mastodon account's direct messages, with each DM conversation as a `Direct` channel.
+/// New statuses arrive over the streaming API's `direct` stream.
+pub struct MastodonConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    api: Option<MastodonApi>,
+    account: Option<Profile>,
+    mapper: MastodonMapper,
+    max_characters: usize,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl MastodonConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        MastodonConnection {
+            auth: Vec::new(),
+            options,
+            mapper: MastodonMapper {
+                conversations: Arc::new(StdMutex::new(HashMap::new())),
+                statuses: Arc::new(StdMutex::new(HashMap::new())),
+                event_tx: event_tx.clone(),
+            },
+            event_tx,
+            event_rx: Some(event_rx),
+            api: None,
+            account: None,
+            max_characters: DEFAULT_MAX_CHARACTERS,
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    /// The logged-in account, once connected.
+    pub fn account(&self) -> Option<&Profile> {
+        self.account.as_ref()
+    }
+
+    fn api(&self) -> Result<&MastodonApi, ConnectionError> {
+        self.api.as_ref().ok_or(ConnectionError::Closed)
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn fail_auth(&self, reason: String) -> ConnectionError {
+        self.set_status(ConnectionStatus::AuthFailed {
+            reason: reason.clone(),
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed {
+                reason: reason.clone(),
+            },
+        });
+        ConnectionError::Auth(reason)
+    }
+
+    /// Posts a direct status into a conversation, addressing its participants and replying to
+    /// its latest status so the instance keeps it in the same thread.
+    async fn post_status(
+        &self,
+        scope: &Scope,
+        message: &Message,
+    ) -> Result<String, ConnectionError> {
+        let conversation_id = scope.channel_id().ok_or_else(|| {
+            ConnectionError::Unsupported("Mastodon messages need a conversation".to_string())
+        })?;
+        let conversation = self
+            .mapper
+            .conversations
+            .lock()
+            .ok()
+            .and_then(|conversations| conversations.get(conversation_id).cloned())
+            .ok_or_else(|| {
+                ConnectionError::Unsupported(format!("Unknown conversation {}", conversation_id))
+            })?;
+        let own = self
+            .account
+            .as_ref()
+            .and_then(|account| account.username.as_deref());
+        let text = plain_text(&message.content);
+        let mut status: String = conversation
+            .participants
+            .iter()
+            .filter(|acct| Some(acct.as_str()) != own)
+            .map(|acct| format!("@{}", acct))
+            .filter(|mention| !text.contains(mention.as_str()))
+            .map(|mention| mention + " ")
+            .collect();
+        status.push_str(&text);
+        let mut body = json!({ "status": status, "visibility": "direct" });
+        if let Some(reply_to) = message
+            .reply_to
+            .as_ref()
+            .or(conversation.last_status.as_ref())
+        {
+            body["in_reply_to_id"] = json!(reply_to);
+        }
+        let response = self.api()?.post("v1/statuses", body).await?;
+        Ok(response["id"].as_str().unwrap_or_default().to_string())
+    }
+
+    async fn announce_conversations(&self) -> Result<(), ConnectionError> {
+        let conversations = self
+            .api()?
+            .get(
+                "v1/conversations",
+                &[("limit", CONVERSATION_PAGE_SIZE.to_string())],
+            )
+            .await?;
+        for conversation in conversations.as_array().into_iter().flatten() {
+            self.mapper.conversation(conversation);
+        }
+        Ok(())
+    }
+}
+
+/// The `direct` stream URL on the streaming API the instance advertises, falling back to the
+/// instance itself.
+fn streaming_url(instance: &Url, info: Option<&Value>, access_token: &str) -> Url {
+    let mut url = info
+        .and_then(|info| info["urls"]["streaming_api"].as_str())
+        .and_then(|url| Url::parse(url).ok())
+        .unwrap_or_else(|| instance.clone());
+    let scheme = match url.scheme() {
+        "http" | "ws" => "ws",
+        _ => "wss",
+    };
+    let _ = url.set_scheme(scheme);
+    url.set_path("/api/v1/streaming");
+    url.query_pairs_mut()
+        .clear()
+        .append_pair("stream", "direct")
+        .append_pair("access_token", access_token);
+    url
+}
+
+impl Default for MastodonConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+#[async_trait]
+impl Connection for MastodonConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "mastodon.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let instance = field_text(&self.auth, "instance")
+            .ok_or_else(|| ConnectionError::Auth("Missing instance URL".to_string()))?;
+        let access_token = field_text(&self.auth, "access_token")
+            .ok_or_else(|| ConnectionError::Auth("Missing access token".to_string()))?;
+        let instance = if instance.contains("://") {
+            instance
+        } else {
+            format!("https://{}", instance)
+        };
+        let instance = Url::parse(&instance).map_err(|e| ConnectionError::Auth(e.to_string()))?;
+
+        self.tasks.shutdown().await;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+
+        self.api = Some(MastodonApi {
+            http: http_client(&self.options)?,
+            instance: instance.clone(),
+            access_token: access_token.clone(),
+        });
+        let account = match self.api()?.get("v1/accounts/verify_credentials", &[]).await {
+            Err(ConnectionError::Auth(reason)) => return Err(self.fail_auth(reason)),
+            other => other?,
+        };
+        let info = self.api()?.get("v1/instance", &[]).await.ok();
+        let stream_url = streaming_url(&instance, info.as_ref(), &access_token);
+        let mut socket = match connect_websocket(&stream_url, &self.options).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+
+        let profile = profile_from(&account);
+        let user_id = profile.id.clone().unwrap_or_default();
+        self.max_characters = info
+            .and_then(|info| info["configuration"]["statuses"]["max_characters"].as_u64())
+            .map_or(DEFAULT_MAX_CHARACTERS, |max| max as usize);
+        self.account = Some(profile);
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected {
+                artifact: instance.host_str().map(str::to_string),
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::User {
+            event: UserEvent::Identify { user_id },
+        });
+        self.announce_conversations().await?;
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Synced,
+        });
+
+        let mapper = self.mapper.clone();
+        let status = self.status.clone();
+        self.tasks
+            .spawn_essential("stream", self.status.clone(), async move {
+                while let Some(frame) = socket.next().await {
+                    let text = match frame {
+                        Ok(WsMessage::Text(text)) => text,
+                        Ok(WsMessage::Close(_)) | Err(_) => break,
+                        Ok(_) => continue,
+                    };
+                    match serde_json::from_str::<Value>(&text) {
+                        Ok(frame) => mapper.apply(&frame),
+                        Err(_) => event!(debug, "unparsed streaming frame {:?}", text),
+                    }
+                }
+                if let Ok(mut current) = status.lock() {
+                    if *current == ConnectionStatus::Connected {
+                        *current = ConnectionStatus::Disconnected;
+                    }
+                }
+                mapper.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Disconnected {
+                        artifact: Some("Streaming connection closed".to_string()),
+                    },
+                });
+            });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.api = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } => self.post_status(&scope, &message).await.map(|_| ()),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::Update {
+                        message_id,
+                        new_message,
+                        ..
+                    },
+            } => self
+                .api()?
+                .request(
+                    Method::PUT,
+                    &format!("v1/statuses/{}", message_id),
+                    &[],
+                    Some(json!({ "status": plain_text(&new_message.content) })),
+                )
+                .await
+                .map(|_| ()),
+            ConnectionEvent::Chat {
+                event: ChatEvent::Remove { message_id, .. },
+            } => self
+                .api()?
+                .request(
+                    Method::DELETE,
+                    &format!("v1/statuses/{}", message_id),
+                    &[],
+                    None,
+                )
+                .await
+                .map(|_| ()),
+            ConnectionEvent::Chat {
+                event: ChatEvent::ReadMarker { channel_id, .. },
+            } => self
+                .api()?
+                .post(&format!("v1/conversations/{}/read", channel_id), json!({}))
+                .await
+                .map(|_| ()),
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Leave { channel_id },
+            } => {
+                self.api()?
+                    .request(
+                        Method::DELETE,
+                        &format!("v1/conversations/{}", channel_id),
+                        &[],
+                        None,
+                    )
+                    .await?;
+                if let Ok(mut conversations) = self.mapper.conversations.lock() {
+                    conversations.remove(&channel_id);
+                }
+                let _ = self.event_tx.send(ConnectionEvent::Channel {
+                    event: ChannelEvent::Leave { channel_id },
+                });
+                Ok(())
+            }
+            _ => Err(ConnectionError::Unsupported(
+                "Event not supported over Mastodon".to_string(),
+            )),
+        }
+    }
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        let ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } = &event
+        else {
+            self.send(event).await?;
+            return Ok(SendHandle::completed(SendOutcome::Delivered {
+                message_id: None,
+            }));
+        };
+        let id = self.post_status(scope, message).await?;
+        Ok(SendHandle::completed(SendOutcome::Delivered {
+            message_id: Some(id),
+        }))
+    }
+
+    async fn fetch_profile(&mut self, user_id: &str) -> Result<Profile, ConnectionError> {
+        let account = self
+            .api()?
+            .get(&format!("v1/accounts/{}", user_id), &[])
+            .await?;
+        Ok(profile_from(&account))
+    }
+
+    async fn search_users(&mut self, query: &str) -> Result<Vec<Profile>, ConnectionError> {
+        let accounts = self
+            .api()?
+            .get("v1/accounts/search", &[("q", query.to_string())])
+            .await?;
+        Ok(accounts
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(profile_from)
+            .collect())
+    }
+
+    /// Reads a conversation's thread around its latest status, oldest first.
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        let last_status = self
+            .mapper
+            .conversations
+            .lock()
+            .ok()
+            .and_then(|conversations| conversations.get(channel_id)?.last_status.clone())
+            .ok_or_else(|| {
+                ConnectionError::Unsupported(format!("Unknown conversation {}", channel_id))
+            })?;
+        let api = self.api()?;
+        let (status_path, context_path) = (
+            format!("v1/statuses/{}", last_status),
+            format!("v1/statuses/{}/context", last_status),
+        );
+        let (latest, context) =
+            tokio::try_join!(api.get(&status_path, &[]), api.get(&context_path, &[]))?;
+        let mut statuses: Vec<&Value> = context["ancestors"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .chain(std::iter::once(&latest))
+            .chain(context["descendants"].as_array().into_iter().flatten())
+            .filter(|status| status["visibility"].as_str() == Some("direct"))
+            .collect();
+        statuses.sort_by(|a, b| {
+            status_order(
+                a["id"].as_str().unwrap_or_default(),
+                b["id"].as_str().unwrap_or_default(),
+            )
+        });
+        if let Some(before) = &before {
+            statuses.retain(|status| {
+                status_order(status["id"].as_str().unwrap_or_default(), before).is_lt()
+            });
+        }
+        let skip = statuses.len().saturating_sub(limit);
+        let messages: Vec<Message> = statuses[skip..]
+            .iter()
+            .map(|status| message_from(status))
+            .collect();
+        if let Ok(mut known) = self.mapper.statuses.lock() {
+            for id in messages.iter().filter_map(|message| message.id.clone()) {
+                known.insert(id, channel_id.to_string());
+            }
+        }
+        Ok(messages)
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        let field = |name: &str, display: &str, value: FieldValue| AuthField {
+            name: name.to_string(),
+            display: Some(display.to_string()),
+            value,
+            required: true,
+        };
+        Protocol {
+            name: "mastodon".to_string(),
+            auth: Some(vec![
+                field("instance", "Instance URL", FieldValue::Text(None)),
+                field(
+                    "access_token",
+                    "Access token with read and write scopes",
+                    FieldValue::Password(None),
+                ),
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            editing: true,
+            deletion: true,
+            history: true,
+            multiple_channels: true,
+            max_message_length: Some(self.max_characters),
+            ..Capabilities::default()
+        }
+    }
+}
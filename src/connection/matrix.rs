@@ -0,0 +1,1099 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{Method, StatusCode, Url};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{compose::plain_text, ids::IdNormalizer, trace::event},
+    AuthField, Capabilities, Channel, ChannelType, Connection, FieldValue, Message,
+    MessageFragment, MessageStatus, MessageType, Presence, Profile, Protocol, ReactionKey,
+};
+
+use super::{
+    http::http_client, ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent,
+    ConnectionOptions, Scope, SendHandle, SendOutcome, StatusEvent, Supervisor, UserEvent,
+};
+
+const CLIENT_API: &[&str] = &["_matrix", "client", "v3"];
+const SYNC_TIMEOUT_MS: u64 = 30_000;
+const SYNC_RETRY_DELAY: Duration = Duration::from_secs(5);
+const INITIAL_TIMELINE_LIMIT: usize = 20;
+
+/// An authenticated handle on a homeserver's client-server API.
+#[derive(Clone, Debug)]
+struct MatrixApi {
+    http: reqwest::Client,
+    homeserver: Url,
+    access_token: String,
+}
+
+impl MatrixApi {
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.homeserver.clone();
+        if let Ok(mut path) = url.path_segments_mut() {
+            path.pop_if_empty().extend(CLIENT_API).extend(segments);
+        }
+        url
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        segments: &[&str],
+        query: &[(&str, String)],
+        body: Option<Value>,
+    ) -> Result<Value, ConnectionError> {
+        let mut request = self
+            .http
+            .request(method, self.url(segments))
+            .bearer_auth(&self.access_token)
+            .query(query);
+        if let Some(body) = body {
+            request = request
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string());
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        let status = response.status();
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        let body: Value =
+            serde_json::from_slice(&bytes).map_err(|e| ConnectionError::Protocol(e.to_string()))?;
+        if status.is_success() {
+            return Ok(body);
+        }
+
+        let errcode = body["errcode"].as_str().unwrap_or_default();
+        let error = body["error"].as_str().unwrap_or(errcode).to_string();
+        Err(match status {
+            StatusCode::UNAUTHORIZED => ConnectionError::Auth(error),
+            StatusCode::TOO_MANY_REQUESTS => ConnectionError::RateLimited(Duration::from_millis(
+                body["retry_after_ms"].as_u64().unwrap_or(1000),
+            )),
+            _ => ConnectionError::Protocol(format!("{}: {}", errcode, error)),
+        })
+    }
+
+    async fn get(
+        &self,
+        segments: &[&str],
+        query: &[(&str, String)],
+    ) -> Result<Value, ConnectionError> {
+        self.request(Method::GET, segments, query, None).await
+    }
+
+    async fn put(&self, segments: &[&str], body: Value) -> Result<Value, ConnectionError> {
+        self.request(Method::PUT, segments, &[], Some(body)).await
+    }
+
+    async fn post(&self, segments: &[&str], body: Value) -> Result<Value, ConnectionError> {
+        self.request(Method::POST, segments, &[], Some(body)).await
+    }
+}
+
+/// Turns client-server API events into oshatori types for one logged-in user.
+#[derive(Clone, Debug)]
+struct MatrixMapper {
+    homeserver: Url,
+    user_id: String,
+}
+
+impl MatrixMapper {
+    /// Rewrites an `mxc://server/media` URI to the homeserver's download endpoint.
+    fn media_url(&self, uri: &str) -> String {
+        let Some((server, media_id)) = uri
+            .strip_prefix("mxc://")
+            .and_then(|rest| rest.split_once('/'))
+        else {
+            return uri.to_string();
+        };
+        let mut url = self.homeserver.clone();
+        if let Ok(mut path) = url.path_segments_mut() {
+            path.pop_if_empty()
+                .extend(["_matrix", "media", "v3", "download", server, media_id]);
+        }
+        url.to_string()
+    }
+
+    fn profile(&self, user_id: &str, content: &Value) -> Profile {
+        Profile {
+            id: Some(user_id.to_string()),
+            username: Some(user_id.to_string()),
+            display_name: content["displayname"].as_str().map(str::to_string),
+            picture: content["avatar_url"]
+                .as_str()
+                .map(|avatar| self.media_url(avatar)),
+            presence: Some(Presence::Online),
+            ..Profile::default()
+        }
+    }
+
+    fn fragments(&self, content: &Value) -> Vec<MessageFragment> {
+        let body = content["body"].as_str().unwrap_or_default();
+        let url = content["url"].as_str().map(|url| self.media_url(url));
+        let mime = |fallback: &str| {
+            content["info"]["mimetype"]
+                .as_str()
+                .unwrap_or(fallback)
+                .to_string()
+        };
+        match (content["msgtype"].as_str().unwrap_or_default(), url) {
+            ("m.image", Some(url)) => vec![MessageFragment::Image {
+                url,
+                mime: mime("image/*"),
+            }],
+            ("m.video", Some(url)) => vec![MessageFragment::Video {
+                url,
+                mime: mime("video/*"),
+            }],
+            ("m.audio", Some(url)) => vec![MessageFragment::Audio {
+                url,
+                mime: mime("audio/*"),
+            }],
+            ("m.file", Some(url)) => vec![MessageFragment::Url(url)],
+            _ => {
+                let body = if content["m.relates_to"]["m.in_reply_to"].is_object() {
+                    strip_reply_fallback(body)
+                } else {
+                    body
+                };
+                vec![MessageFragment::Text(body.to_string())]
+            }
+        }
+    }
+
+    /// The message an `m.room.message` event carries, ignoring edits.
+    fn message(&self, event: &Value) -> Option<Message> {
+        if event["type"] != "m.room.message"
+            || event["content"]["m.relates_to"]["rel_type"] == "m.replace"
+        {
+            return None;
+        }
+        Some(self.message_from(event, &event["content"]))
+    }
+
+    fn message_from(&self, event: &Value, content: &Value) -> Message {
+        let sender = event["sender"].as_str().unwrap_or_default();
+        let relation = &event["content"]["m.relates_to"];
+        let message_type = match content["msgtype"].as_str() {
+            Some("m.emote") => MessageType::Action,
+            Some("m.notice") => MessageType::Server,
+            _ if sender == self.user_id => MessageType::CurrentUser,
+            _ => MessageType::Normal,
+        };
+        Message {
+            id: event["event_id"].as_str().map(str::to_string),
+            sender_id: Some(sender.to_string()),
+            content: self.fragments(content),
+            timestamp: event["origin_server_ts"]
+                .as_i64()
+                .and_then(DateTime::from_timestamp_millis)
+                .unwrap_or_else(Utc::now),
+            message_type,
+            status: MessageStatus::Delivered,
+            reactions: Vec::new(),
+            reply_to: relation["m.in_reply_to"]["event_id"]
+                .as_str()
+                .map(str::to_string),
+            thread_id: (relation["rel_type"] == "m.thread")
+                .then(|| relation["event_id"].as_str().map(str::to_string))
+                .flatten(),
+            extra: HashMap::new(),
+        }
+    }
+}
+
+/// Drops the quoted `> ` lines clients prepend to replies.
+fn strip_reply_fallback(body: &str) -> &str {
+    if !body.starts_with("> ") {
+        return body;
+    }
+    body.split_once("\n\n").map_or(body, |(_, rest)| rest)
+}
+
+fn state_events(room: &Value, key: &str) -> Vec<Value> {
+    room[key]["events"].as_array().cloned().unwrap_or_default()
+}
+
+/// Sync-side state: which rooms have been announced, their channels and who is typing.
+struct SyncState {
+    mapper: MatrixMapper,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    rooms: HashMap<String, Channel>,
+    direct: HashSet<String>,
+    typing: HashMap<String, HashSet<String>>,
+}
+
+impl SyncState {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn apply(&mut self, sync: &Value) {
+        for event in sync["account_data"]["events"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            if event["type"] == "m.direct" {
+                if let Some(rooms) = event["content"].as_object() {
+                    self.direct.extend(
+                        rooms
+                            .values()
+                            .filter_map(Value::as_array)
+                            .flatten()
+                            .filter_map(Value::as_str)
+                            .map(str::to_string),
+                    );
+                }
+            }
+        }
+
+        let rooms = &sync["rooms"];
+        for (room_id, room) in rooms["join"].as_object().into_iter().flatten() {
+            let state = state_events(room, "state");
+            let timeline = state_events(room, "timeline");
+            if !self.rooms.contains_key(room_id) {
+                self.announce(room_id, state.iter().chain(&timeline));
+            }
+            for event in state.iter().chain(&timeline) {
+                self.apply_event(room_id, event);
+            }
+            for event in state_events(room, "ephemeral") {
+                if event["type"] == "m.typing" {
+                    self.apply_typing(room_id, &event["content"]);
+                }
+            }
+        }
+
+        for (room_id, room) in rooms["invite"].as_object().into_iter().flatten() {
+            let events = state_events(room, "invite_state");
+            let mut channel = self.channel(room_id, events.iter());
+            channel.channel_type = ChannelType::Direct;
+            let inviter = events
+                .iter()
+                .find(|event| {
+                    event["type"] == "m.room.member"
+                        && event["state_key"] == self.mapper.user_id.as_str()
+                })
+                .and_then(|event| event["sender"].as_str())
+                .unwrap_or_default()
+                .to_string();
+            self.emit(ConnectionEvent::Channel {
+                event: ChannelEvent::Request {
+                    channel,
+                    from: self.mapper.profile(&inviter, &Value::Null),
+                },
+            });
+        }
+
+        for room_id in rooms["leave"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(id, _)| id)
+        {
+            if self.rooms.remove(room_id).is_some() {
+                self.emit(ConnectionEvent::Channel {
+                    event: ChannelEvent::Leave {
+                        channel_id: room_id.clone(),
+                    },
+                });
+            }
+        }
+    }
+
+    fn channel<'a>(&self, room_id: &str, events: impl Iterator<Item = &'a Value>) -> Channel {
+        let mut channel = Channel {
+            id: room_id.to_string(),
+            name: None,
+            channel_type: if self.direct.contains(room_id) {
+                ChannelType::Direct
+            } else {
+                ChannelType::Group
+            },
+            topic: None,
+            extra: HashMap::new(),
+        };
+        for event in events {
+            match event["type"].as_str() {
+                Some("m.room.name") => {
+                    channel.name = event["content"]["name"].as_str().map(str::to_string)
+                }
+                Some("m.room.canonical_alias") if channel.name.is_none() => {
+                    channel.name = event["content"]["alias"].as_str().map(str::to_string)
+                }
+                Some("m.room.topic") => {
+                    channel.topic = event["content"]["topic"].as_str().map(str::to_string)
+                }
+                _ => {}
+            }
+        }
+        channel
+    }
+
+    fn announce<'a>(&mut self, room_id: &str, events: impl Iterator<Item = &'a Value>) {
+        let channel = self.channel(room_id, events);
+        self.rooms.insert(room_id.to_string(), channel.clone());
+        self.emit(ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel },
+        });
+        self.emit(ConnectionEvent::Channel {
+            event: ChannelEvent::Join {
+                channel_id: room_id.to_string(),
+            },
+        });
+    }
+
+    fn apply_event(&mut self, room_id: &str, event: &Value) {
+        let scope = Scope::channel(room_id);
+        let content = &event["content"];
+        let sender = event["sender"].as_str().unwrap_or_default().to_string();
+        let relation = &content["m.relates_to"];
+        match event["type"].as_str().unwrap_or_default() {
+            "m.room.message" if relation["rel_type"] == "m.replace" => {
+                if let Some(message_id) = relation["event_id"].as_str() {
+                    let new_content = if content["m.new_content"].is_object() {
+                        &content["m.new_content"]
+                    } else {
+                        content
+                    };
+                    let mut new_message = self.mapper.message_from(event, new_content);
+                    new_message.id = Some(message_id.to_string());
+                    new_message.status = MessageStatus::Edited;
+                    self.emit(ConnectionEvent::Chat {
+                        event: ChatEvent::Update {
+                            scope,
+                            message_id: message_id.to_string(),
+                            new_message,
+                        },
+                    });
+                }
+            }
+            "m.room.message" => {
+                if let Some(message) = self.mapper.message(event) {
+                    self.emit(ConnectionEvent::Chat {
+                        event: ChatEvent::New { scope, message },
+                    });
+                }
+            }
+            "m.room.redaction" => {
+                if let Some(message_id) = event["redacts"].as_str().or(content["redacts"].as_str())
+                {
+                    self.emit(ConnectionEvent::Chat {
+                        event: ChatEvent::Remove {
+                            scope,
+                            message_id: message_id.to_string(),
+                        },
+                    });
+                }
+            }
+            "m.reaction" => {
+                if let (Some(message_id), Some(key)) =
+                    (relation["event_id"].as_str(), relation["key"].as_str())
+                {
+                    self.emit(ConnectionEvent::Chat {
+                        event: ChatEvent::ReactionAdd {
+                            scope,
+                            message_id: message_id.to_string(),
+                            user_id: sender,
+                            key: ReactionKey::Emoji(key.to_string()),
+                        },
+                    });
+                }
+            }
+            "m.room.member" => {
+                let Some(user_id) = event["state_key"].as_str() else {
+                    return;
+                };
+                match content["membership"].as_str() {
+                    Some("join") => self.emit(ConnectionEvent::User {
+                        event: UserEvent::New {
+                            scope,
+                            user: self.mapper.profile(user_id, content),
+                        },
+                    }),
+                    Some("leave") | Some("ban") if user_id == self.mapper.user_id => {
+                        self.rooms.remove(room_id);
+                        let event = if sender == user_id {
+                            ChannelEvent::Leave {
+                                channel_id: room_id.to_string(),
+                            }
+                        } else {
+                            ChannelEvent::Kick {
+                                scope,
+                                reason: content["reason"].as_str().map(str::to_string),
+                                ban: content["membership"] == "ban",
+                                until: None,
+                            }
+                        };
+                        self.emit(ConnectionEvent::Channel { event });
+                    }
+                    Some("leave") | Some("ban") => self.emit(ConnectionEvent::User {
+                        event: UserEvent::Remove {
+                            scope,
+                            user_id: user_id.to_string(),
+                        },
+                    }),
+                    _ => {}
+                }
+            }
+            "m.room.name" => {
+                let Some(channel) = self.rooms.get_mut(room_id) else {
+                    return;
+                };
+                let name = content["name"].as_str().map(str::to_string);
+                if channel.name == name {
+                    return;
+                }
+                channel.name = name;
+                let new_channel = channel.clone();
+                self.emit(ConnectionEvent::Channel {
+                    event: ChannelEvent::Update {
+                        channel_id: room_id.to_string(),
+                        new_channel,
+                    },
+                });
+            }
+            "m.room.topic" => {
+                let topic = content["topic"]
+                    .as_str()
+                    .filter(|topic| !topic.is_empty())
+                    .map(str::to_string);
+                if let Some(channel) = self.rooms.get_mut(room_id) {
+                    if channel.topic == topic && event["state_key"].is_string() {
+                        return;
+                    }
+                    channel.topic.clone_from(&topic);
+                }
+                self.emit(ConnectionEvent::Channel {
+                    event: ChannelEvent::TopicChanged {
+                        channel_id: room_id.to_string(),
+                        topic,
+                        set_by: Some(sender),
+                    },
+                });
+            }
+            other => event!(trace, "unhandled Matrix event {}", other),
+        }
+    }
+
+    fn apply_typing(&mut self, room_id: &str, content: &Value) {
+        let now: HashSet<String> = content["user_ids"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .filter(|user_id| *user_id != self.mapper.user_id)
+            .map(str::to_string)
+            .collect();
+        let before = self.typing.insert(room_id.to_string(), now.clone());
+        let before = before.unwrap_or_default();
+        for user_id in now.difference(&before) {
+            self.emit(ConnectionEvent::User {
+                event: UserEvent::TypingStart {
+                    channel_id: room_id.to_string(),
+                    user_id: user_id.clone(),
+                },
+            });
+        }
+        for user_id in before.difference(&now) {
+            self.emit(ConnectionEvent::User {
+                event: UserEvent::TypingStop {
+                    channel_id: room_id.to_string(),
+                    user_id: user_id.clone(),
+                },
+            });
+        }
+    }
+}
+
+/// Matrix ids (`!room:server`, `#alias:server`, `@user:server`): the opaque part before the
+/// first `:` is case-sensitive, the server name after it is not.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MatrixIds;
+
+impl MatrixIds {
+    fn fold(id: &str) -> String {
+        let id = id.trim();
+        match id.split_once(':') {
+            Some((local, server)) => format!("{}:{}", local, server.to_ascii_lowercase()),
+            None => id.to_string(),
+        }
+    }
+}
+
+impl IdNormalizer for MatrixIds {
+    fn normalize_channel_id(&self, channel_id: &str) -> String {
+        Self::fold(channel_id)
+    }
+
+    fn normalize_user_id(&self, user_id: &str) -> String {
+        Self::fold(user_id)
+    }
+}
+
+/// A Matrix client using the client-server API with an access token. Rooms are channels keyed
+/// by room id, users are keyed by Matrix user id, and `mxc://` media is served through the
+/// homeserver's download endpoint.
+#[derive(Debug)]
+pub struct MatrixConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    api: Option<MatrixApi>,
+    mapper: Option<MatrixMapper>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl MatrixConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        MatrixConnection {
+            auth: Vec::new(),
+            options,
+            event_tx,
+            event_rx: Some(event_rx),
+            api: None,
+            mapper: None,
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    /// The logged-in Matrix user id, once connected.
+    pub fn user_id(&self) -> Option<&str> {
+        self.mapper.as_ref().map(|mapper| mapper.user_id.as_str())
+    }
+
+    fn api(&self) -> Result<&MatrixApi, ConnectionError> {
+        self.api.as_ref().ok_or(ConnectionError::Closed)
+    }
+
+    fn mapper(&self) -> Result<&MatrixMapper, ConnectionError> {
+        self.mapper.as_ref().ok_or(ConnectionError::Closed)
+    }
+
+    async fn send_event(
+        &self,
+        room_id: &str,
+        event_type: &str,
+        content: Value,
+    ) -> Result<String, ConnectionError> {
+        let txn_id = self.options.ids.next_id();
+        let response = self
+            .api()?
+            .put(&["rooms", room_id, "send", event_type, &txn_id], content)
+            .await?;
+        Ok(response["event_id"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string())
+    }
+
+    async fn send_message(
+        &self,
+        scope: &Scope,
+        message: &Message,
+    ) -> Result<String, ConnectionError> {
+        let room_id = room_of(scope)?;
+        let mut content = json!({
+            "msgtype": match message.message_type {
+                MessageType::Action => "m.emote",
+                _ => "m.text",
+            },
+            "body": plain_text(&message.content),
+        });
+        if let Some(thread_id) = &message.thread_id {
+            content["m.relates_to"] = json!({ "rel_type": "m.thread", "event_id": thread_id });
+        }
+        if let Some(reply_to) = &message.reply_to {
+            content["m.relates_to"]["m.in_reply_to"] = json!({ "event_id": reply_to });
+        }
+        self.send_event(room_id, "m.room.message", content).await
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+}
+
+impl Default for MatrixConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn room_of(scope: &Scope) -> Result<&str, ConnectionError> {
+    scope
+        .channel_id()
+        .ok_or_else(|| ConnectionError::Unsupported("Matrix messages without a room".to_string()))
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+async fn run_sync(api: MatrixApi, mut state: SyncState, status: Arc<StdMutex<ConnectionStatus>>) {
+    let filter = json!({ "room": { "timeline": { "limit": INITIAL_TIMELINE_LIMIT } } }).to_string();
+    let mut since: Option<String> = None;
+    loop {
+        let query = match &since {
+            Some(token) => vec![
+                ("timeout", SYNC_TIMEOUT_MS.to_string()),
+                ("since", token.clone()),
+            ],
+            None => vec![("timeout", "0".to_string()), ("filter", filter.clone())],
+        };
+        match api.get(&["sync"], &query).await {
+            Ok(sync) => {
+                state.apply(&sync);
+                if since.is_none() {
+                    state.emit(ConnectionEvent::Status {
+                        event: StatusEvent::Synced,
+                    });
+                }
+                since = sync["next_batch"].as_str().map(str::to_string).or(since);
+            }
+            Err(ConnectionError::Auth(reason)) => {
+                if let Ok(mut current) = status.lock() {
+                    *current = ConnectionStatus::AuthFailed {
+                        reason: reason.clone(),
+                    };
+                }
+                state.emit(ConnectionEvent::Status {
+                    event: StatusEvent::AuthFailed { reason },
+                });
+                return;
+            }
+            Err(e) => {
+                event!(warn, "Matrix sync failed: {}", e);
+                state.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Error {
+                        message: format!("Sync failed: {}", e),
+                    },
+                });
+                let delay = match e {
+                    ConnectionError::RateLimited(delay) => delay,
+                    _ => SYNC_RETRY_DELAY,
+                };
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Connection for MatrixConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "matrix.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let homeserver = field_text(&self.auth, "homeserver")
+            .ok_or_else(|| ConnectionError::Auth("Missing homeserver".to_string()))?;
+        let homeserver =
+            Url::parse(&homeserver).map_err(|e| ConnectionError::Auth(e.to_string()))?;
+        let access_token = field_text(&self.auth, "access_token")
+            .ok_or_else(|| ConnectionError::Auth("Missing access token".to_string()))?;
+
+        self.tasks.shutdown().await;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+
+        let api = MatrixApi {
+            http: http_client(&self.options)?,
+            homeserver: homeserver.clone(),
+            access_token,
+        };
+        let whoami = match api.get(&["account", "whoami"], &[]).await {
+            Ok(whoami) => whoami,
+            Err(ConnectionError::Auth(reason)) => {
+                self.set_status(ConnectionStatus::AuthFailed {
+                    reason: reason.clone(),
+                });
+                let _ = self.event_tx.send(ConnectionEvent::Status {
+                    event: StatusEvent::AuthFailed {
+                        reason: reason.clone(),
+                    },
+                });
+                return Err(ConnectionError::Auth(reason));
+            }
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+        let user_id = whoami["user_id"]
+            .as_str()
+            .ok_or_else(|| ConnectionError::Protocol("whoami returned no user_id".to_string()))?
+            .to_string();
+
+        let mapper = MatrixMapper {
+            homeserver,
+            user_id: user_id.clone(),
+        };
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected {
+                artifact: Some(user_id.clone()),
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::User {
+            event: UserEvent::Identify { user_id },
+        });
+
+        let state = SyncState {
+            mapper: mapper.clone(),
+            event_tx: self.event_tx.clone(),
+            rooms: HashMap::new(),
+            direct: HashSet::new(),
+            typing: HashMap::new(),
+        };
+        self.tasks
+            .spawn("sync", run_sync(api.clone(), state, self.status.clone()));
+        self.api = Some(api);
+        self.mapper = Some(mapper);
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.api = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } => self.send_message(&scope, &message).await.map(|_| ()),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::Update {
+                        scope,
+                        message_id,
+                        new_message,
+                    },
+            } => {
+                let body = plain_text(&new_message.content);
+                let content = json!({
+                    "msgtype": "m.text",
+                    "body": format!("* {}", body),
+                    "m.new_content": { "msgtype": "m.text", "body": body },
+                    "m.relates_to": { "rel_type": "m.replace", "event_id": message_id },
+                });
+                self.send_event(room_of(&scope)?, "m.room.message", content)
+                    .await
+                    .map(|_| ())
+            }
+            ConnectionEvent::Chat {
+                event: ChatEvent::Remove { scope, message_id },
+            } => {
+                let txn_id = self.options.ids.next_id();
+                self.api()?
+                    .put(
+                        &["rooms", room_of(&scope)?, "redact", &message_id, &txn_id],
+                        json!({}),
+                    )
+                    .await
+                    .map(|_| ())
+            }
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::ReactionAdd {
+                        scope,
+                        message_id,
+                        key: ReactionKey::Emoji(key),
+                        ..
+                    },
+            } => {
+                let content = json!({
+                    "m.relates_to": {
+                        "rel_type": "m.annotation",
+                        "event_id": message_id,
+                        "key": key,
+                    },
+                });
+                self.send_event(room_of(&scope)?, "m.reaction", content)
+                    .await
+                    .map(|_| ())
+            }
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::ReadMarker {
+                        channel_id,
+                        message_id,
+                        ..
+                    },
+            } => self
+                .api()?
+                .post(
+                    &["rooms", &channel_id, "receipt", "m.read", &message_id],
+                    json!({}),
+                )
+                .await
+                .map(|_| ()),
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Join { channel_id } | ChannelEvent::Accept { channel_id },
+            } => self
+                .api()?
+                .post(&["join", &channel_id], json!({}))
+                .await
+                .map(|_| ()),
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Leave { channel_id } | ChannelEvent::Decline { channel_id },
+            } => self
+                .api()?
+                .post(&["rooms", &channel_id, "leave"], json!({}))
+                .await
+                .map(|_| ()),
+            ConnectionEvent::Channel {
+                event:
+                    ChannelEvent::TopicChanged {
+                        channel_id, topic, ..
+                    },
+            } => self
+                .api()?
+                .put(
+                    &["rooms", &channel_id, "state", "m.room.topic", ""],
+                    json!({ "topic": topic.unwrap_or_default() }),
+                )
+                .await
+                .map(|_| ()),
+            ConnectionEvent::User {
+                event: UserEvent::TypingStart { channel_id, .. },
+            } => {
+                let user_id = self.mapper()?.user_id.clone();
+                self.api()?
+                    .put(
+                        &["rooms", &channel_id, "typing", &user_id],
+                        json!({ "typing": true, "timeout": SYNC_TIMEOUT_MS }),
+                    )
+                    .await
+                    .map(|_| ())
+            }
+            ConnectionEvent::User {
+                event: UserEvent::TypingStop { channel_id, .. },
+            } => {
+                let user_id = self.mapper()?.user_id.clone();
+                self.api()?
+                    .put(
+                        &["rooms", &channel_id, "typing", &user_id],
+                        json!({ "typing": false }),
+                    )
+                    .await
+                    .map(|_| ())
+            }
+            _ => Err(ConnectionError::Unsupported(
+                "Event not supported over Matrix".to_string(),
+            )),
+        }
+    }
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        let ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } = &event
+        else {
+            self.send(event).await?;
+            return Ok(SendHandle::completed(SendOutcome::Delivered {
+                message_id: None,
+            }));
+        };
+        let event_id = self.send_message(scope, message).await?;
+        Ok(SendHandle::completed(SendOutcome::Delivered {
+            message_id: Some(event_id),
+        }))
+    }
+
+    async fn fetch_profile(&mut self, user_id: &str) -> Result<Profile, ConnectionError> {
+        let content = self.api()?.get(&["profile", user_id], &[]).await?;
+        Ok(self.mapper()?.profile(user_id, &content))
+    }
+
+    async fn search_users(&mut self, query: &str) -> Result<Vec<Profile>, ConnectionError> {
+        let response = self
+            .api()?
+            .post(
+                &["user_directory", "search"],
+                json!({ "search_term": query, "limit": 20 }),
+            )
+            .await?;
+        let mapper = self.mapper()?;
+        Ok(response["results"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|result| {
+                let user_id = result["user_id"].as_str()?;
+                let mut profile = mapper.profile(user_id, &Value::Null);
+                profile.display_name = result["display_name"].as_str().map(str::to_string);
+                profile.picture = result["avatar_url"]
+                    .as_str()
+                    .map(|avatar| mapper.media_url(avatar));
+                profile.presence = None;
+                Some(profile)
+            })
+            .collect())
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        let api = self.api()?;
+        let limit_param = [("limit", limit.to_string())];
+        let events = match &before {
+            Some(event_id) => api
+                .get(&["rooms", channel_id, "context", event_id], &limit_param)
+                .await?["events_before"]
+                .take(),
+            None => api
+                .get(
+                    &["rooms", channel_id, "messages"],
+                    &[("dir", "b".to_string()), limit_param[0].clone()],
+                )
+                .await?["chunk"]
+                .take(),
+        };
+        let mapper = self.mapper()?;
+        let mut messages: Vec<Message> = events
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|event| mapper.message(event))
+            .take(limit)
+            .collect();
+        messages.reverse();
+        Ok(messages)
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        let response = self
+            .api()?
+            .post(
+                &["createRoom"],
+                json!({
+                    "is_direct": true,
+                    "invite": [user_id],
+                    "preset": "trusted_private_chat",
+                }),
+            )
+            .await?;
+        let room_id = response["room_id"].as_str().ok_or_else(|| {
+            ConnectionError::Protocol("createRoom returned no room_id".to_string())
+        })?;
+        let channel = Channel {
+            id: room_id.to_string(),
+            name: Some(user_id.to_string()),
+            channel_type: ChannelType::Direct,
+            topic: None,
+            extra: HashMap::new(),
+        };
+        self.event_tx
+            .send(ConnectionEvent::Channel {
+                event: ChannelEvent::New {
+                    channel: channel.clone(),
+                },
+            })
+            .map_err(|_| ConnectionError::Closed)?;
+        Ok(channel)
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        Protocol {
+            name: "matrix".to_string(),
+            auth: Some(vec![
+                AuthField {
+                    name: "homeserver".to_string(),
+                    display: Some("Homeserver URL".to_string()),
+                    value: FieldValue::Text(None),
+                    required: true,
+                },
+                AuthField {
+                    name: "access_token".to_string(),
+                    display: Some("Access token".to_string()),
+                    value: FieldValue::Password(None),
+                    required: true,
+                },
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            editing: true,
+            deletion: true,
+            history: true,
+            typing: true,
+            reactions: true,
+            multiple_channels: true,
+            topics: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn id_normalizer(&self) -> Arc<dyn IdNormalizer> {
+        Arc::new(MatrixIds)
+    }
+}
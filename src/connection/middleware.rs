@@ -0,0 +1,278 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{compose::strip_media, ids::IdNormalizer},
+    AuthField, Capabilities, Channel, CommandSpec, Connection, Message, MessageFragment, Profile,
+    Protocol,
+};
+
+use super::{
+    ChatEvent, ConnectionError, ConnectionEvent, PreflightReport, SendHandle, SendOutcome,
+    UserEvent,
+};
+
+pub trait EventMiddleware: Send + Sync {
+    fn inbound(&self, event: ConnectionEvent) -> Option<ConnectionEvent> {
+        Some(event)
+    }
+
+    fn outbound(&self, event: ConnectionEvent) -> Option<ConnectionEvent> {
+        Some(event)
+    }
+}
+
+/// Strips inbound media to text placeholders and drops avatar URLs, for clients on constrained
+/// links. Pair it with `ConnectionOptions::low_bandwidth` to also skip downloads at the source.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LowBandwidth;
+
+impl LowBandwidth {
+    fn lighten_user(event: UserEvent) -> UserEvent {
+        match event {
+            UserEvent::New { scope, mut user } => {
+                user.picture = None;
+                UserEvent::New { scope, user }
+            }
+            UserEvent::Update {
+                scope,
+                user_id,
+                mut new_user,
+            } => {
+                new_user.picture = None;
+                UserEvent::Update {
+                    scope,
+                    user_id,
+                    new_user,
+                }
+            }
+            UserEvent::Batch { events } => UserEvent::Batch {
+                events: events.into_iter().map(Self::lighten_user).collect(),
+            },
+            other => other,
+        }
+    }
+}
+
+impl EventMiddleware for LowBandwidth {
+    fn inbound(&self, event: ConnectionEvent) -> Option<ConnectionEvent> {
+        Some(match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, mut message },
+            } => {
+                message.content = strip_media(&message.content);
+                ConnectionEvent::Chat {
+                    event: ChatEvent::New { scope, message },
+                }
+            }
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::Update {
+                        scope,
+                        message_id,
+                        mut new_message,
+                    },
+            } => {
+                new_message.content = strip_media(&new_message.content);
+                ConnectionEvent::Chat {
+                    event: ChatEvent::Update {
+                        scope,
+                        message_id,
+                        new_message,
+                    },
+                }
+            }
+            ConnectionEvent::User { event } => ConnectionEvent::User {
+                event: Self::lighten_user(event),
+            },
+            other => other,
+        })
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MiddlewareChain {
+    middlewares: Vec<Arc<dyn EventMiddleware>>,
+}
+
+impl MiddlewareChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, middleware: impl EventMiddleware + 'static) {
+        self.middlewares.push(Arc::new(middleware));
+    }
+
+    pub fn len(&self) -> usize {
+        self.middlewares.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middlewares.is_empty()
+    }
+
+    pub fn inbound(&self, event: ConnectionEvent) -> Option<ConnectionEvent> {
+        self.middlewares
+            .iter()
+            .try_fold(event, |event, middleware| middleware.inbound(event))
+    }
+
+    pub fn outbound(&self, event: ConnectionEvent) -> Option<ConnectionEvent> {
+        self.middlewares
+            .iter()
+            .rev()
+            .try_fold(event, |event, middleware| middleware.outbound(event))
+    }
+}
+
+pub struct MiddlewareConnection<C: Connection + 'static> {
+    inner: C,
+    chain: MiddlewareChain,
+    inner_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl<C: Connection + 'static> MiddlewareConnection<C> {
+    pub fn new(mut inner: C) -> Self {
+        let inner_rx = inner.subscribe();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        MiddlewareConnection {
+            inner,
+            chain: MiddlewareChain::new(),
+            inner_rx: Some(inner_rx),
+            event_tx,
+            event_rx: Some(event_rx),
+            task: None,
+        }
+    }
+
+    pub fn with(mut self, middleware: impl EventMiddleware + 'static) -> Self {
+        self.chain.push(middleware);
+        self
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    fn spawn_forwarder(&mut self) {
+        let Some(mut rx) = self.inner_rx.take() else {
+            return;
+        };
+        let tx = self.event_tx.clone();
+        let chain = self.chain.clone();
+        self.task = Some(tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                if let Some(event) = chain.inbound(event) {
+                    let _ = tx.send(event);
+                }
+            }
+        }));
+    }
+}
+
+#[async_trait]
+impl<C: Connection + 'static> Connection for MiddlewareConnection<C> {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.inner.set_auth(auth)
+    }
+
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        self.spawn_forwarder();
+        self.inner.connect().await
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.inner.disconnect().await
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        self.spawn_forwarder();
+        match self.chain.outbound(event) {
+            Some(event) => self.inner.send(event).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        self.spawn_forwarder();
+        match self.chain.outbound(event) {
+            Some(event) => self.inner.send_tracked(event).await,
+            None => Ok(SendHandle::completed(SendOutcome::Failed(
+                "Dropped by middleware".to_string(),
+            ))),
+        }
+    }
+
+    async fn fetch_profile(&mut self, user_id: &str) -> Result<Profile, ConnectionError> {
+        self.inner.fetch_profile(user_id).await
+    }
+
+    async fn search_users(&mut self, query: &str) -> Result<Vec<Profile>, ConnectionError> {
+        self.inner.search_users(query).await
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        self.inner.fetch_history(channel_id, before, limit).await
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        self.inner.open_direct(user_id).await
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        self.inner.protocol_spec()
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.inner.status()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn commands(&self) -> Vec<CommandSpec> {
+        self.inner.commands()
+    }
+
+    fn id_normalizer(&self) -> Arc<dyn IdNormalizer> {
+        self.inner.id_normalizer()
+    }
+
+    fn render_text(&self, content: &[MessageFragment]) -> String {
+        self.inner.render_text(content)
+    }
+
+    async fn preflight(&self, check_reachability: bool) -> PreflightReport {
+        self.inner.preflight(check_reachability).await
+    }
+}
+
+impl<C: Connection + 'static> Drop for MiddlewareConnection<C> {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
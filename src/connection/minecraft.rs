@@ -0,0 +1,731 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::StreamExt;
+use regex::Regex;
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{mpsc, Mutex},
+};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use url::Url;
+
+use crate::{
+    connection::ConnectionStatus, utils::compose::plain_text, AuthField, Capabilities, Channel,
+    ChannelType, Connection, FieldValue, Message, MessageFragment, MessageStatus, MessageType,
+    Profile, Protocol,
+};
+
+use super::{
+    preflight::{probe_reachability, validate_auth},
+    transport::connect_websocket,
+    ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, ConnectionOptions, ModerationEvent,
+    PreflightReport, Scope, StatusEvent, Supervisor, UserEvent,
+};
+
+const DEFAULT_RCON_PORT: u16 = 25575;
+const DEFAULT_NAME: &str = "Rcon";
+/// The id of the one channel a server's chat is bridged into.
+pub const CHANNEL_ID: &str = "minecraft";
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const MAX_PACKET_LENGTH: usize = 1024 * 1024;
+const MAX_MESSAGE_LENGTH: usize = 256;
+
+/// The timestamp and thread prefix of a console log line, in the vanilla and Paper layouts.
+static LOG_PREFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\[[^\]]*\] \[[^\]]*/INFO\](?: \[[^\]]*\])?: |^\[[^\]]* INFO\]: ").unwrap()
+});
+
+/// RCON packet types. Login replies reuse the command type.
+mod packet {
+    pub const RESPONSE: i32 = 0;
+    pub const COMMAND: i32 = 2;
+    pub const LOGIN: i32 = 3;
+}
+
+/// A Source RCON session, as implemented by the vanilla server.
+struct Rcon {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl Rcon {
+    async fn write(&mut self, kind: i32, body: &str) -> std::io::Result<i32> {
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let id = self.next_id;
+        let mut packet = Vec::with_capacity(body.len() + 14);
+        packet.extend_from_slice(&(body.len() as i32 + 10).to_le_bytes());
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.extend_from_slice(&kind.to_le_bytes());
+        packet.extend_from_slice(body.as_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        self.stream.write_all(&packet).await?;
+        Ok(id)
+    }
+
+    async fn read(&mut self) -> std::io::Result<(i32, i32, String)> {
+        let length = self.stream.read_i32_le().await?;
+        let length = usize::try_from(length)
+            .ok()
+            .filter(|length| (10..=MAX_PACKET_LENGTH).contains(length))
+            .ok_or_else(|| std::io::Error::other("invalid packet length"))?;
+        let id = self.stream.read_i32_le().await?;
+        let kind = self.stream.read_i32_le().await?;
+        let mut body = vec![0; length - 8];
+        self.stream.read_exact(&mut body).await?;
+        body.truncate(length - 10);
+        Ok((id, kind, String::from_utf8_lossy(&body).into_owned()))
+    }
+
+    /// Logs in, returning false when the server refuses the password.
+    async fn login(&mut self, password: &str) -> std::io::Result<bool> {
+        let id = self.write(packet::LOGIN, password).await?;
+        loop {
+            let (reply, kind, _) = self.read().await?;
+            if kind == packet::COMMAND && (reply == id || reply == -1) {
+                return Ok(reply == id);
+            }
+        }
+    }
+
+    /// Runs a console command. Replies too long for one packet come back truncated, and the
+    /// fragments left over are skipped by the next command.
+    async fn command(&mut self, command: &str) -> Result<String, ConnectionError> {
+        let network = |e: std::io::Error| ConnectionError::Network(e.to_string());
+        let id = self
+            .write(packet::COMMAND, command)
+            .await
+            .map_err(network)?;
+        loop {
+            let (reply, kind, body) = self.read().await.map_err(network)?;
+            if reply == id && kind == packet::RESPONSE {
+                return Ok(body);
+            }
+        }
+    }
+}
+
+/// Splits `host[:port]`.
+fn parse_address(address: &str) -> Result<(String, u16), ConnectionError> {
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse::<u16>()
+                .map_err(|_| ConnectionError::Auth(format!("Invalid port: {}", port)))?,
+        ),
+        None => (address, DEFAULT_RCON_PORT),
+    };
+    if host.is_empty() {
+        return Err(ConnectionError::Auth(
+            "RCON address has no host".to_string(),
+        ));
+    }
+    Ok((host.to_string(), port))
+}
+
+/// Removes `§` formatting codes.
+fn strip_formatting(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Player names from the reply to `list`.
+fn parse_player_list(reply: &str) -> Vec<String> {
+    let reply = strip_formatting(reply);
+    let Some((_, names)) = reply.split_once(':') else {
+        return Vec::new();
+    };
+    names
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Reads whatever was appended to `path` since `offset`, starting over when the file shrank
+/// because the server rotated its log.
+fn read_appended(path: &Path, offset: u64) -> std::io::Result<(Vec<u8>, u64)> {
+    let mut file = std::fs::File::open(path)?;
+    let length = file.metadata()?.len();
+    let offset = if length < offset { 0 } else { offset };
+    file.seek(SeekFrom::Start(offset))?;
+    let mut appended = Vec::new();
+    file.read_to_end(&mut appended)?;
+    let offset = offset + appended.len() as u64;
+    Ok((appended, offset))
+}
+
+#[derive(Clone)]
+struct MinecraftMapper {
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    players: Arc<StdMutex<HashSet<String>>>,
+}
+
+impl MinecraftMapper {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn player(name: &str) -> Profile {
+        Profile {
+            id: Some(name.to_string()),
+            username: Some(name.to_string()),
+            display_name: None,
+            color: None,
+            picture: None,
+            presence: None,
+            role: None,
+            extra: HashMap::new(),
+        }
+    }
+
+    fn chat(&self, sender_id: Option<String>, text: &str, message_type: MessageType) {
+        self.emit(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel(CHANNEL_ID),
+                message: Message {
+                    id: None,
+                    sender_id,
+                    content: vec![MessageFragment::Text(text.to_string())],
+                    timestamp: Utc::now(),
+                    message_type,
+                    status: MessageStatus::Delivered,
+                    reactions: Vec::new(),
+                    reply_to: None,
+                    thread_id: None,
+                    extra: HashMap::new(),
+                },
+            },
+        });
+    }
+
+    fn joined(&self, name: &str) {
+        let new = self
+            .players
+            .lock()
+            .is_ok_and(|mut players| players.insert(name.to_string()));
+        if new {
+            self.emit(ConnectionEvent::User {
+                event: UserEvent::New {
+                    scope: Scope::channel(CHANNEL_ID),
+                    user: Self::player(name),
+                },
+            });
+        }
+    }
+
+    fn left(&self, name: &str) {
+        let known = self
+            .players
+            .lock()
+            .is_ok_and(|mut players| players.remove(name));
+        if known {
+            self.emit(ConnectionEvent::User {
+                event: UserEvent::Remove {
+                    scope: Scope::channel(CHANNEL_ID),
+                    user_id: name.to_string(),
+                },
+            });
+        }
+    }
+
+    /// Maps one console log line. Only chat, `/say`, `/me`, joins and leaves are picked up;
+    /// everything else the server logs is ignored.
+    fn line(&self, line: &str) {
+        let Some(found) = LOG_PREFIX.find(line) else {
+            return;
+        };
+        let text = strip_formatting(line[found.end()..].trim_end());
+        let text = text.strip_prefix("[Not Secure] ").unwrap_or(&text);
+        if let Some((name, body)) = text
+            .strip_prefix('<')
+            .and_then(|rest| rest.split_once("> "))
+        {
+            self.chat(Some(name.to_string()), body, MessageType::Normal);
+        } else if let Some((name, body)) = text
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once("] "))
+            .filter(|(name, _)| !name.contains(' ') && !name.contains('/'))
+        {
+            match name {
+                "Server" => self.chat(None, body, MessageType::Server),
+                _ => self.chat(Some(name.to_string()), body, MessageType::Normal),
+            }
+        } else if let Some((name, body)) = text
+            .strip_prefix("* ")
+            .and_then(|rest| rest.split_once(' '))
+        {
+            self.chat(Some(name.to_string()), body, MessageType::Action);
+        } else if let Some(name) = text
+            .strip_suffix(" joined the game")
+            .filter(|name| !name.contains(' '))
+        {
+            self.joined(name);
+        } else if let Some(name) = text
+            .strip_suffix(" left the game")
+            .filter(|name| !name.contains(' '))
+        {
+            self.left(name);
+        }
+    }
+}
+
+/// Where console lines are read from. A log file is followed from where it ended at connect.
+enum LogSource {
+    File(PathBuf, u64),
+    Socket(Url),
+}
+
+/// Bridges a Minecraft server's chat into the single channel [`CHANNEL_ID`]. Messages go out
+/// over RCON with `tellraw`, and chat is read back by tailing the server log (`log_path`) or
+/// from a websocket that streams console lines (`log_url`), as console relay plugins do.
+/// Messages starting with `/` are run as console commands and the reply comes back as a
+/// server message.
+pub struct MinecraftConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    rcon: Option<Arc<Mutex<Rcon>>>,
+    name: String,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl MinecraftConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        MinecraftConnection {
+            auth: Vec::new(),
+            options,
+            event_tx,
+            event_rx: Some(event_rx),
+            rcon: None,
+            name: DEFAULT_NAME.to_string(),
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn fail_auth(&self, reason: String) -> ConnectionError {
+        self.set_status(ConnectionStatus::AuthFailed {
+            reason: reason.clone(),
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed {
+                reason: reason.clone(),
+            },
+        });
+        ConnectionError::Auth(reason)
+    }
+
+    async fn command(&self, command: &str) -> Result<String, ConnectionError> {
+        let rcon = self.rcon.as_ref().ok_or(ConnectionError::Closed)?;
+        rcon.lock().await.command(command).await
+    }
+
+    fn echo(&self, message: Message, message_type: MessageType) {
+        let _ = self.event_tx.send(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel(CHANNEL_ID),
+                message: Message {
+                    sender_id: match message_type {
+                        MessageType::Server => None,
+                        _ => Some(self.name.clone()),
+                    },
+                    message_type,
+                    status: MessageStatus::Delivered,
+                    ..message
+                },
+            },
+        });
+    }
+}
+
+impl Default for MinecraftConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value)
+            | FieldValue::Password(value)
+            | FieldValue::Key(value)
+            | FieldValue::File(value) => value.clone(),
+            FieldValue::Group(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+async fn tail_file(path: PathBuf, mut offset: u64, mapper: &MinecraftMapper) {
+    let mut partial = Vec::new();
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        poll.tick().await;
+        let read = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || read_appended(&path, offset)).await
+        };
+        let Ok(Ok((appended, next))) = read else {
+            continue;
+        };
+        if next < offset {
+            partial.clear();
+        }
+        offset = next;
+        partial.extend_from_slice(&appended);
+        while let Some(end) = partial.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = partial.drain(..=end).collect();
+            mapper.line(&String::from_utf8_lossy(&line));
+        }
+    }
+}
+
+#[async_trait]
+impl Connection for MinecraftConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "minecraft.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let address = field_text(&self.auth, "rcon_address")
+            .ok_or_else(|| ConnectionError::Auth("Missing RCON address".to_string()))?;
+        let (host, port) = parse_address(&address)?;
+        let password = field_text(&self.auth, "rcon_password")
+            .ok_or_else(|| ConnectionError::Auth("Missing RCON password".to_string()))?;
+        let source = match (
+            field_text(&self.auth, "log_url"),
+            field_text(&self.auth, "log_path"),
+        ) {
+            (Some(url), _) => Some(LogSource::Socket(
+                Url::parse(&url).map_err(|e| ConnectionError::Auth(e.to_string()))?,
+            )),
+            (None, Some(path)) => {
+                let offset = std::fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+                Some(LogSource::File(PathBuf::from(path), offset))
+            }
+            (None, None) => None,
+        };
+        if self.options.proxy.is_some() {
+            return Err(ConnectionError::Unsupported(
+                "Proxies for RCON connections".to_string(),
+            ));
+        }
+        self.name = field_text(&self.auth, "name").unwrap_or_else(|| DEFAULT_NAME.to_string());
+
+        self.tasks.shutdown().await;
+        self.rcon = None;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+        let network = |e: std::io::Error| ConnectionError::Network(e.to_string());
+        let connect = TcpStream::connect((host.as_str(), port));
+        let stream = match self.options.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect)
+                .await
+                .map_err(|_| ConnectionError::Timeout)
+                .and_then(|tcp| tcp.map_err(network)),
+            None => connect.await.map_err(network),
+        };
+        let mut rcon = match stream {
+            Ok(stream) => Rcon { stream, next_id: 0 },
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+        let login = tokio::time::timeout(LOGIN_TIMEOUT, rcon.login(&password))
+            .await
+            .map_err(|_| ConnectionError::Timeout)
+            .and_then(|login| login.map_err(network));
+        match login {
+            Ok(true) => {}
+            Ok(false) => return Err(self.fail_auth("RCON password rejected".to_string())),
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        }
+        let players = match rcon.command("list").await {
+            Ok(reply) => parse_player_list(&reply),
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+        let socket = match &source {
+            Some(LogSource::Socket(url)) => match connect_websocket(url, &self.options).await {
+                Ok(socket) => Some(socket),
+                Err(e) => {
+                    self.set_status(ConnectionStatus::Disconnected);
+                    return Err(e);
+                }
+            },
+            _ => None,
+        };
+
+        let mapper = MinecraftMapper {
+            event_tx: self.event_tx.clone(),
+            players: Arc::new(StdMutex::new(HashSet::new())),
+        };
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected {
+                artifact: Some(address),
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::User {
+            event: UserEvent::Identify {
+                user_id: self.name.clone(),
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Channel {
+            event: ChannelEvent::New {
+                channel: Channel {
+                    id: CHANNEL_ID.to_string(),
+                    name: Some(host),
+                    channel_type: ChannelType::Group,
+                    topic: None,
+                    extra: HashMap::new(),
+                },
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Channel {
+            event: ChannelEvent::Join {
+                channel_id: CHANNEL_ID.to_string(),
+            },
+        });
+        for player in &players {
+            mapper.joined(player);
+        }
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Synced,
+        });
+        self.rcon = Some(Arc::new(Mutex::new(rcon)));
+
+        let status = self.status.clone();
+        match (source, socket) {
+            (Some(LogSource::Socket(_)), Some(mut socket)) => {
+                self.tasks
+                    .spawn_essential("log", self.status.clone(), async move {
+                        while let Some(frame) = socket.next().await {
+                            match frame {
+                                Ok(WsMessage::Text(text)) => {
+                                    text.lines().for_each(|line| mapper.line(line))
+                                }
+                                Ok(WsMessage::Close(_)) | Err(_) => break,
+                                Ok(_) => {}
+                            }
+                        }
+                        if let Ok(mut current) = status.lock() {
+                            if *current == ConnectionStatus::Connected {
+                                *current = ConnectionStatus::Disconnected;
+                            }
+                        }
+                        mapper.emit(ConnectionEvent::Status {
+                            event: StatusEvent::Disconnected {
+                                artifact: Some("Console stream closed".to_string()),
+                            },
+                        });
+                    });
+            }
+            (Some(LogSource::File(path, offset)), _) => {
+                self.tasks
+                    .spawn("log", async move { tail_file(path, offset, &mapper).await });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.rcon = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } => {
+                if scope.channel_id().is_some_and(|id| id != CHANNEL_ID) {
+                    return Err(ConnectionError::Unsupported(format!(
+                        "Minecraft only has the {} channel",
+                        CHANNEL_ID
+                    )));
+                }
+                let text = plain_text(&message.content);
+                if let Some(command) = text.strip_prefix('/') {
+                    let reply = self.command(command).await?;
+                    self.echo(message.clone(), MessageType::CurrentUser);
+                    if !reply.is_empty() {
+                        self.echo(
+                            Message {
+                                id: None,
+                                content: vec![MessageFragment::Text(strip_formatting(&reply))],
+                                timestamp: Utc::now(),
+                                reply_to: None,
+                                ..message
+                            },
+                            MessageType::Server,
+                        );
+                    }
+                    return Ok(());
+                }
+                let components = json!(["", format!("<{}> ", self.name), text]);
+                self.command(&format!("tellraw @a {}", components)).await?;
+                self.echo(message, MessageType::CurrentUser);
+                Ok(())
+            }
+            ConnectionEvent::Moderation { event } => {
+                let command = match event {
+                    ModerationEvent::Kick {
+                        user_id, reason, ..
+                    } => format!("kick {} {}", user_id, reason.unwrap_or_default()),
+                    ModerationEvent::Ban {
+                        user_id,
+                        reason,
+                        until: None,
+                        ..
+                    } => format!("ban {} {}", user_id, reason.unwrap_or_default()),
+                    ModerationEvent::Unban { user_id, .. } => format!("pardon {}", user_id),
+                    _ => {
+                        return Err(ConnectionError::Unsupported(
+                            "Timed bans and mutes over RCON".to_string(),
+                        ))
+                    }
+                };
+                self.command(command.trim_end()).await.map(|_| ())
+            }
+            _ => Err(ConnectionError::Unsupported(
+                "Event not supported over RCON".to_string(),
+            )),
+        }
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        let field = |name: &str, display: &str, value: FieldValue, required: bool| AuthField {
+            name: name.to_string(),
+            display: Some(display.to_string()),
+            value,
+            required,
+        };
+        Protocol {
+            name: "minecraft".to_string(),
+            auth: Some(vec![
+                field("rcon_address", "RCON address", FieldValue::Text(None), true),
+                field(
+                    "rcon_password",
+                    "RCON password",
+                    FieldValue::Password(None),
+                    true,
+                ),
+                field(
+                    "log_path",
+                    "Server log (logs/latest.log)",
+                    FieldValue::File(None),
+                    false,
+                ),
+                field(
+                    "log_url",
+                    "Console websocket URL",
+                    FieldValue::Text(None),
+                    false,
+                ),
+                field("name", "Sender name", FieldValue::Text(None), false),
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            moderation: true,
+            max_message_length: Some(MAX_MESSAGE_LENGTH),
+            ..Capabilities::default()
+        }
+    }
+
+    async fn preflight(&self, check_reachability: bool) -> PreflightReport {
+        let mut report = validate_auth(&self.protocol_spec(), &self.auth);
+        if field_text(&self.auth, "log_path").is_none()
+            && field_text(&self.auth, "log_url").is_none()
+        {
+            report.warning(
+                None,
+                "Without a log path or console URL, chat can be sent but not read",
+            );
+        }
+        let address = field_text(&self.auth, "rcon_address").and_then(|address| {
+            parse_address(&address)
+                .map_err(|e| report.error(Some("rcon_address"), e.to_string()))
+                .ok()
+        });
+        if check_reachability {
+            if let Some((host, port)) = address {
+                let timeout = self
+                    .options
+                    .connect_timeout
+                    .unwrap_or(Duration::from_secs(5));
+                report.reachability = probe_reachability(&host, port, timeout).await;
+            }
+        }
+        report
+    }
+}
@@ -1,14 +1,24 @@
-use crate::{AuthField, Connection, Protocol};
+use crate::{
+    connection::ConnectionStatus, AuthField, Capabilities, Channel, ChannelType, Connection,
+    Protocol,
+};
 use async_trait::async_trait;
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 use tokio::sync::{mpsc, Mutex};
 
-use super::ConnectionEvent;
+use super::{ChannelEvent, ConnectionError, ConnectionEvent};
 
 #[derive(Clone, Debug)]
 pub struct MockConnection {
     event_tx: mpsc::UnboundedSender<ConnectionEvent>,
     event_rx: Arc<Mutex<Option<mpsc::UnboundedReceiver<ConnectionEvent>>>>,
+    connected: Arc<AtomicBool>,
 }
 
 impl MockConnection {
@@ -17,32 +27,61 @@ impl MockConnection {
         MockConnection {
             event_tx,
             event_rx: Arc::new(Mutex::new(Some(event_rx))),
+            connected: Arc::new(AtomicBool::new(false)),
         }
     }
 }
 
+impl Default for MockConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 unsafe impl Send for MockConnection {}
 unsafe impl Sync for MockConnection {}
 
 #[async_trait]
 impl Connection for MockConnection {
-    fn set_auth(&mut self, _auth: Vec<AuthField>) -> Result<(), String> {
+    fn set_auth(&mut self, _auth: Vec<AuthField>) -> Result<(), ConnectionError> {
         Ok(())
     }
 
-    async fn connect(&mut self) -> Result<(), String> {
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        self.connected.store(true, Ordering::SeqCst);
         Ok(())
     }
 
-    async fn disconnect(&mut self) -> Result<(), String> {
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.connected.store(false, Ordering::SeqCst);
         Ok(())
     }
 
-    async fn send(&mut self, event: ConnectionEvent) -> Result<(), String> {
-        self.event_tx.send(event).map_err(|e| e.to_string())?;
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        self.event_tx
+            .send(event)
+            .map_err(|_| ConnectionError::Closed)?;
         Ok(())
     }
 
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        let channel = Channel {
+            id: format!("@direct:{}", user_id),
+            name: Some(user_id.to_string()),
+            channel_type: ChannelType::Direct,
+            topic: None,
+            extra: HashMap::new(),
+        };
+        self.event_tx
+            .send(ConnectionEvent::Channel {
+                event: ChannelEvent::New {
+                    channel: channel.clone(),
+                },
+            })
+            .map_err(|_| ConnectionError::Closed)?;
+        Ok(channel)
+    }
+
     fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
         self.event_rx
             .try_lock()
@@ -57,4 +96,28 @@ impl Connection for MockConnection {
             auth: None,
         }
     }
+
+    fn status(&self) -> ConnectionStatus {
+        if self.connected.load(Ordering::SeqCst) {
+            ConnectionStatus::Connected
+        } else {
+            ConnectionStatus::Disconnected
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            editing: true,
+            deletion: true,
+            history: false,
+            typing: true,
+            reactions: true,
+            file_upload: false,
+            multiple_channels: true,
+            asset_management: true,
+            moderation: true,
+            max_message_length: None,
+            topics: true,
+        }
+    }
 }
@@ -1,26 +1,94 @@
-use crate::{Asset, AuthField, Channel, Message, Profile, Protocol};
+use crate::{
+    utils::ids::{IdNormalizer, VerbatimIds},
+    Asset, AuthField, Capabilities, Channel, CommandSpec, Message, MessageFragment, Presence,
+    Profile, Protocol, ReactionKey,
+};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use tokio::sync::mpsc;
 
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Scope {
+    #[default]
+    Global,
+    Channel(String),
+}
+
+impl Scope {
+    pub fn channel(channel_id: impl Into<String>) -> Self {
+        Scope::Channel(channel_id.into())
+    }
+
+    pub fn channel_id(&self) -> Option<&str> {
+        match self {
+            Scope::Global => None,
+            Scope::Channel(channel_id) => Some(channel_id),
+        }
+    }
+
+    pub fn is_global(&self) -> bool {
+        matches!(self, Scope::Global)
+    }
+}
+
+impl From<Option<String>> for Scope {
+    fn from(channel_id: Option<String>) -> Self {
+        channel_id.map_or(Scope::Global, Scope::Channel)
+    }
+}
+
+impl From<Scope> for Option<String> {
+    fn from(scope: Scope) -> Self {
+        match scope {
+            Scope::Global => None,
+            Scope::Channel(channel_id) => Some(channel_id),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum ChatEvent {
     New {
-        channel_id: Option<String>,
+        scope: Scope,
         message: Message,
     },
     Update {
-        channel_id: Option<String>,
+        scope: Scope,
         message_id: String,
         new_message: Message,
     },
     Remove {
-        channel_id: Option<String>,
+        scope: Scope,
+        message_id: String,
+    },
+    ReactionAdd {
+        scope: Scope,
+        message_id: String,
+        user_id: String,
+        key: ReactionKey,
+    },
+    ReactionRemove {
+        scope: Scope,
+        message_id: String,
+        user_id: String,
+        key: ReactionKey,
+    },
+    DeliveryAck {
+        message_id: String,
+    },
+    ReadMarker {
+        channel_id: String,
+        user_id: String,
         message_id: String,
     },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum ChannelEvent {
     New {
         channel: Channel,
@@ -42,85 +110,429 @@ pub enum ChannelEvent {
         channel_id: String,
     },
     Kick {
-        channel_id: Option<String>,
+        scope: Scope,
         reason: Option<String>,
         ban: bool,
+        until: Option<DateTime<Utc>>,
     },
     Wipe {
-        channel_id: Option<String>,
+        scope: Scope,
+    },
+    TopicChanged {
+        channel_id: String,
+        topic: Option<String>,
+        set_by: Option<String>,
+    },
+    Request {
+        channel: Channel,
+        from: Profile,
+    },
+    Accept {
+        channel_id: String,
+    },
+    Decline {
+        channel_id: String,
     },
     ClearList,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ModerationEvent {
+    Kick {
+        scope: Scope,
+        user_id: String,
+        reason: Option<String>,
+    },
+    Ban {
+        scope: Scope,
+        user_id: String,
+        reason: Option<String>,
+        until: Option<DateTime<Utc>>,
+    },
+    Unban {
+        scope: Scope,
+        user_id: String,
+    },
+    Mute {
+        scope: Scope,
+        user_id: String,
+        until: Option<DateTime<Utc>>,
+    },
+    Unmute {
+        scope: Scope,
+        user_id: String,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum UserEvent {
     New {
-        channel_id: Option<String>,
+        scope: Scope,
         user: Profile,
     },
     Update {
-        channel_id: Option<String>,
+        scope: Scope,
         user_id: String,
         new_user: Profile,
     },
     Remove {
-        channel_id: Option<String>,
+        scope: Scope,
         user_id: String,
     },
     ClearList {
-        channel_id: Option<String>,
+        scope: Scope,
     },
     Identify {
         user_id: String,
     },
+    TypingStart {
+        channel_id: String,
+        user_id: String,
+    },
+    TypingStop {
+        channel_id: String,
+        user_id: String,
+    },
+    PresenceChanged {
+        user_id: String,
+        presence: Presence,
+    },
+    Batch {
+        events: Vec<UserEvent>,
+    },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum StatusEvent {
-    Ping { artifact: Option<String> },
-    Connected { artifact: Option<String> },
-    Disconnected { artifact: Option<String> },
+    Ping {
+        artifact: Option<String>,
+    },
+    Connecting,
+    Connected {
+        artifact: Option<String>,
+    },
+    Synced,
+    Reconnecting {
+        attempt: u32,
+    },
+    Disconnected {
+        artifact: Option<String>,
+    },
+    AuthFailed {
+        reason: String,
+    },
+    Error {
+        message: String,
+    },
+    Throttled {
+        retry_after_ms: u64,
+    },
+    /// The server announced a shutdown or maintenance window, starting at `scheduled_at` (or
+    /// now) and lasting until `until` when known.
+    Maintenance {
+        message: Option<String>,
+        scheduled_at: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum AssetEvent {
     New {
-        channel_id: Option<String>,
+        scope: Scope,
         asset: Asset,
     },
     Update {
-        channel_id: Option<String>,
+        scope: Scope,
         asset_id: String,
         new_asset: Asset,
     },
     Remove {
-        channel_id: Option<String>,
+        scope: Scope,
         asset_id: String,
     },
     ClearList {
-        channel_id: Option<String>,
+        scope: Scope,
     },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum ConnectionEvent {
     Chat { event: ChatEvent },
     User { event: UserEvent },
     Channel { event: ChannelEvent },
     Status { event: StatusEvent },
     Asset { event: AssetEvent },
+    Moderation { event: ModerationEvent },
 }
 
 #[async_trait]
 pub trait Connection: Send + Sync {
-    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), String>;
-    async fn connect(&mut self) -> Result<(), String>;
-    async fn disconnect(&mut self) -> Result<(), String>;
-    async fn send(&mut self, event: ConnectionEvent) -> Result<(), String>;
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError>;
+    async fn connect(&mut self) -> Result<(), ConnectionError>;
+    async fn disconnect(&mut self) -> Result<(), ConnectionError>;
+    /// Sends an outbound command. `ChatEvent::Update` edits the message with `message_id`,
+    /// replacing its content with `new_message`. Connections whose capabilities report
+    /// `editing: false` must reject it with `ConnectionError::Unsupported`. `ChatEvent::Remove`
+    /// deletes a message, under the same rule for `deletion`. `ChannelEvent::Join`, `Leave`,
+    /// `Switch` and `New` request joining, leaving, switching to and creating a channel.
+    /// `TopicChanged` sets a channel topic, rejected with `ConnectionError::Unsupported` when
+    /// capabilities report `topics: false`.
+    /// `Accept` and `Decline` answer an incoming `ChannelEvent::Request` for a direct channel.
+    /// `ModerationEvent`s kick, ban, unban, mute and unmute a user, and are rejected with
+    /// `ConnectionError::Unsupported` when capabilities report `moderation: false`.
+    /// `MessageFragment::AssetId` in outbound chat is resolved back to the protocol's text form;
+    /// unknown ids and asset types the protocol cannot transmit are rejected with
+    /// `ConnectionError::Unsupported` before anything is sent.
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError>;
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        self.send(event).await?;
+        Ok(SendHandle::completed(SendOutcome::Delivered {
+            message_id: None,
+        }))
+    }
+
+    async fn fetch_profile(&mut self, _user_id: &str) -> Result<Profile, ConnectionError> {
+        Err(ConnectionError::Unsupported("Profile lookup".to_string()))
+    }
+
+    async fn search_users(&mut self, _query: &str) -> Result<Vec<Profile>, ConnectionError> {
+        Err(ConnectionError::Unsupported("User search".to_string()))
+    }
+
+    async fn fetch_history(
+        &mut self,
+        _channel_id: &str,
+        _before: Option<String>,
+        _limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        Err(ConnectionError::Unsupported("History fetching".to_string()))
+    }
+
+    /// Opens (or reuses) a direct conversation with `user_id` and returns its channel, with
+    /// `channel_type: ChannelType::Direct`. Messages sent to that channel's scope reach the user.
+    async fn open_direct(&mut self, _user_id: &str) -> Result<Channel, ConnectionError> {
+        Err(ConnectionError::Unsupported("Direct messages".to_string()))
+    }
     fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent>;
     fn protocol_spec(&self) -> Protocol;
+
+    /// The connection's own view of its link. Implementations that don't track it report
+    /// `Disconnected`; `StateClient` still follows status through the event stream.
+    fn status(&self) -> ConnectionStatus {
+        ConnectionStatus::default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn commands(&self) -> Vec<CommandSpec> {
+        Vec::new()
+    }
+
+    fn render_text(&self, content: &[MessageFragment]) -> String {
+        crate::utils::compose::plain_text(content)
+    }
+
+    /// How this protocol compares ids. `StateClient::track_connection` keys its state by the
+    /// normalized forms, so e.g. `#Chan` and `#chan` on IRC stay one channel.
+    fn id_normalizer(&self) -> Arc<dyn IdNormalizer> {
+        Arc::new(VerbatimIds)
+    }
+
+    fn normalize_channel_id(&self, channel_id: &str) -> String {
+        self.id_normalizer().normalize_channel_id(channel_id)
+    }
+
+    fn normalize_user_id(&self, user_id: &str) -> String {
+        self.id_normalizer().normalize_user_id(user_id)
+    }
+
+    async fn preflight(&self, _check_reachability: bool) -> PreflightReport {
+        PreflightReport::default()
+    }
+}
+
+/// The page of up to `limit` messages immediately before the message with id `before`, or the
+/// newest page when `before` is `None`.
+pub(crate) fn history_page(
+    messages: &[Message],
+    before: Option<String>,
+    limit: usize,
+) -> Vec<Message> {
+    let end = match before {
+        Some(before) => messages
+            .iter()
+            .position(|m| m.id.as_ref() == Some(&before))
+            .unwrap_or(0),
+        None => messages.len(),
+    };
+    messages[end.saturating_sub(limit)..end].to_vec()
 }
 
+pub mod archive;
+pub use archive::ArchiveConnection;
+
+pub mod coalesce;
+pub use coalesce::CoalescingConnection;
+
+pub mod delivery;
+pub use delivery::{SendHandle, SendOutcome};
+
+pub mod group;
+pub use group::{ConnectionGroup, GroupMember, GroupSendResult};
+
+#[cfg(any(
+    feature = "sockchat",
+    feature = "matrix",
+    feature = "slack",
+    feature = "mastodon",
+    feature = "polling",
+    feature = "revolt",
+    feature = "rss",
+    feature = "twitch"
+))]
+pub(crate) mod http;
+
+#[cfg(feature = "email")]
+pub mod email;
+#[cfg(feature = "email")]
+pub use email::EmailConnection;
+
+#[cfg(feature = "irc")]
+pub mod irc;
+#[cfg(feature = "irc")]
+pub use irc::{IrcConnection, IrcMessage};
+
+#[cfg(feature = "mastodon")]
+pub mod mastodon;
+#[cfg(feature = "mastodon")]
+pub use mastodon::MastodonConnection;
+
+#[cfg(feature = "matrix")]
+pub mod matrix;
+#[cfg(feature = "matrix")]
+pub use matrix::MatrixConnection;
+
+#[cfg(feature = "minecraft")]
+pub mod minecraft;
+#[cfg(feature = "minecraft")]
+pub use minecraft::MinecraftConnection;
+
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "mqtt")]
+pub use mqtt::MqttConnection;
+
+#[cfg(feature = "mumble")]
+pub mod mumble;
+#[cfg(feature = "mumble")]
+pub use mumble::MumbleConnection;
+
+#[cfg(feature = "nostr")]
+pub mod nostr;
+#[cfg(feature = "nostr")]
+pub use nostr::{NostrConnection, NostrKeys};
+
+#[cfg(feature = "polling")]
+pub mod polling;
+#[cfg(feature = "polling")]
+pub use polling::{PollContext, PollPage, PollSession, PollSource, PollingConnection, SseEvent};
+
+#[cfg(feature = "revolt")]
+pub mod revolt;
+#[cfg(feature = "revolt")]
+pub use revolt::RevoltConnection;
+
+#[cfg(feature = "rocketchat")]
+pub mod rocketchat;
+#[cfg(feature = "rocketchat")]
+pub use rocketchat::RocketChatConnection;
+
+#[cfg(feature = "rss")]
+pub mod rss;
+#[cfg(feature = "rss")]
+pub use rss::RssConnection;
+
+#[cfg(feature = "slack")]
+pub mod slack;
+#[cfg(feature = "slack")]
+pub use slack::SlackConnection;
+
+#[cfg(feature = "twitch")]
+pub mod twitch;
+#[cfg(feature = "twitch")]
+pub use twitch::TwitchConnection;
+
+#[cfg(feature = "websocket")]
+pub mod websocket;
+#[cfg(feature = "websocket")]
+pub use websocket::{GenericWsConnection, JsonPath, WsMapping};
+
+pub mod envelope;
+pub use envelope::{stamp, Envelope};
+
+pub mod error;
+pub use error::ConnectionError;
+
+pub mod loop_guard;
+pub use loop_guard::{LoopGuard, LoopMode, LOOP_TAG};
+
+pub mod middleware;
+pub use middleware::{EventMiddleware, LowBandwidth, MiddlewareChain, MiddlewareConnection};
+
+pub mod options;
+pub use options::{ClientIdentity, ConnectionOptions, Proxy, ProxyKind, TlsConfig};
+
+#[cfg(any(
+    feature = "sockchat",
+    feature = "slack",
+    feature = "nostr",
+    feature = "mastodon",
+    feature = "minecraft",
+    feature = "revolt",
+    feature = "rocketchat",
+    feature = "twitch",
+    feature = "websocket"
+))]
+pub(crate) mod transport;
+
+pub mod proxy;
+pub use proxy::{Intercept, ProxyConnection};
+
+#[cfg(feature = "plugins")]
+pub mod plugin;
+#[cfg(feature = "plugins")]
+pub use plugin::{PluginDeclaration, PluginError, PluginLoader};
+
+pub mod preflight;
+pub use preflight::{PreflightIssue, PreflightReport, PreflightSeverity, Reachability};
+
+pub mod ratelimit;
+pub use ratelimit::{RateLimit, RateLimitPolicy, RateLimitedConnection, ThrottleMode};
+
+pub mod reconnect;
+pub use reconnect::{ReconnectPolicy, ReconnectingConnection};
+
+pub mod registry;
+pub use registry::ProtocolRegistry;
+
+pub mod status;
+pub use status::ConnectionStatus;
+
+pub mod supervisor;
+pub use supervisor::{RestartPolicy, Supervisor};
+
 #[cfg(feature = "mock")]
 pub mod mock;
 #[cfg(feature = "mock")]
@@ -129,4 +541,4 @@ pub use mock::MockConnection;
 #[cfg(feature = "sockchat")]
 pub mod sockchat;
 #[cfg(feature = "sockchat")]
-pub use sockchat::SockchatConnection;
+pub use sockchat::{RankRole, SockchatConnection, SockchatPool, SockchatRoles, SockchatSession};
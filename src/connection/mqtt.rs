@@ -0,0 +1,754 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+};
+
+use crate::{
+    connection::ConnectionStatus, utils::compose::plain_text, AuthField, Capabilities, Channel,
+    ChannelType, Connection, FieldValue, Message, MessageFragment, MessageStatus, MessageType,
+    Protocol,
+};
+
+use super::{
+    preflight::{probe_reachability, validate_auth},
+    ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, ConnectionOptions, PreflightReport,
+    Scope, StatusEvent, Supervisor, TlsConfig, UserEvent,
+};
+
+const CONNACK_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_PACKET_LENGTH: usize = 16 * 1024 * 1024;
+
+/// MQTT 3.1.1 control packet types, in the high nibble of the first byte.
+mod packet {
+    pub const CONNECT: u8 = 1;
+    pub const CONNACK: u8 = 2;
+    pub const PUBLISH: u8 = 3;
+    pub const PUBACK: u8 = 4;
+    pub const SUBSCRIBE: u8 = 8;
+    pub const SUBACK: u8 = 9;
+    pub const PINGREQ: u8 = 12;
+    pub const DISCONNECT: u8 = 14;
+}
+
+trait MqttStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> MqttStream for T {}
+
+/// Where to connect, parsed from `mqtt://host[:port]`, `mqtts://host[:port]` or a bare
+/// `host[:port]`, which uses TLS like `mqtts`.
+#[derive(Clone, Debug, PartialEq)]
+struct BrokerAddress {
+    host: String,
+    port: u16,
+    tls: bool,
+}
+
+impl BrokerAddress {
+    fn parse(broker: &str) -> Result<Self, ConnectionError> {
+        let (tls, rest) = match broker.split_once("://") {
+            Some(("mqtt", rest)) | Some(("tcp", rest)) => (false, rest),
+            Some(("mqtts", rest)) | Some(("ssl", rest)) => (true, rest),
+            Some((given, _)) => {
+                return Err(ConnectionError::Auth(format!(
+                    "Unknown scheme {}, expected mqtt or mqtts",
+                    given
+                )))
+            }
+            None => (true, broker),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| ConnectionError::Auth(format!("Invalid port: {}", port)))?,
+            ),
+            None => (rest, if tls { 8883 } else { 1883 }),
+        };
+        if host.is_empty() {
+            return Err(ConnectionError::Auth("Broker has no host".to_string()));
+        }
+        Ok(BrokerAddress {
+            host: host.to_string(),
+            port,
+            tls,
+        })
+    }
+}
+
+fn tls_connector(tls: &TlsConfig) -> Result<native_tls::TlsConnector, ConnectionError> {
+    let tls_error = |e: native_tls::Error| ConnectionError::Other(e.to_string());
+    let mut builder = native_tls::TlsConnector::builder();
+    builder.danger_accept_invalid_certs(tls.accept_invalid_certs);
+    for pem in &tls.root_certificates {
+        builder.add_root_certificate(native_tls::Certificate::from_pem(pem).map_err(tls_error)?);
+    }
+    if let Some(identity) = &tls.client_identity {
+        builder.identity(
+            native_tls::Identity::from_pkcs8(&identity.certificate_pem, &identity.key_pem)
+                .map_err(tls_error)?,
+        );
+    }
+    builder.build().map_err(tls_error)
+}
+
+async fn open_stream(
+    address: &BrokerAddress,
+    options: &ConnectionOptions,
+) -> Result<Box<dyn MqttStream>, ConnectionError> {
+    if options.proxy.is_some() {
+        return Err(ConnectionError::Unsupported(
+            "Proxies for MQTT connections".to_string(),
+        ));
+    }
+    let network = |e: std::io::Error| ConnectionError::Network(e.to_string());
+    let connect = TcpStream::connect((address.host.as_str(), address.port));
+    let tcp = match options.connect_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, connect)
+            .await
+            .map_err(|_| ConnectionError::Timeout)?,
+        None => connect.await,
+    }
+    .map_err(network)?;
+    if !address.tls {
+        return Ok(Box::new(tcp));
+    }
+    let connector = tokio_native_tls::TlsConnector::from(tls_connector(&options.tls)?);
+    let tls = connector
+        .connect(&address.host, tcp)
+        .await
+        .map_err(|e| ConnectionError::Network(e.to_string()))?;
+    Ok(Box::new(tls))
+}
+
+fn put_string(buffer: &mut Vec<u8>, value: &[u8]) {
+    buffer.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(value);
+}
+
+/// A control packet: the first byte, then the remaining length as a variable-length integer.
+fn frame(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut frame = vec![header];
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        frame.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    frame.extend_from_slice(body);
+    frame
+}
+
+fn connect_packet(
+    client_id: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+    keep_alive: u16,
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_string(&mut body, b"MQTT");
+    body.push(4);
+    let mut flags = 0x02;
+    if username.is_some() {
+        flags |= 0x80;
+    }
+    if password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    body.extend_from_slice(&keep_alive.to_be_bytes());
+    put_string(&mut body, client_id.as_bytes());
+    for value in [username, password].into_iter().flatten() {
+        put_string(&mut body, value.as_bytes());
+    }
+    frame(packet::CONNECT << 4, &body)
+}
+
+fn subscribe_packet(packet_id: u16, filters: &[String]) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    for filter in filters {
+        put_string(&mut body, filter.as_bytes());
+        body.push(0);
+    }
+    frame(packet::SUBSCRIBE << 4 | 0x02, &body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_string(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    frame(packet::PUBLISH << 4, &body)
+}
+
+async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<(u8, Vec<u8>)> {
+    let header = reader.read_u8().await?;
+    let mut length = 0usize;
+    for shift in 0..4 {
+        let byte = reader.read_u8().await?;
+        length |= ((byte & 0x7f) as usize) << (7 * shift);
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if shift == 3 {
+            return Err(std::io::Error::other("malformed remaining length"));
+        }
+    }
+    if length > MAX_PACKET_LENGTH {
+        return Err(std::io::Error::other("oversized packet"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    Ok((header, body))
+}
+
+/// An application message received from the broker.
+#[derive(Clone, Debug, PartialEq)]
+struct Publish {
+    topic: String,
+    packet_id: Option<u16>,
+    retain: bool,
+    payload: Vec<u8>,
+}
+
+impl Publish {
+    fn parse(header: u8, body: &[u8]) -> Option<Self> {
+        let length = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+        let topic = String::from_utf8(body.get(2..2 + length)?.to_vec()).ok()?;
+        let mut rest = &body[2 + length..];
+        let packet_id = if header & 0x06 != 0 {
+            let id = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]);
+            rest = &rest[2..];
+            Some(id)
+        } else {
+            None
+        };
+        Some(Publish {
+            topic,
+            packet_id,
+            retain: header & 0x01 != 0,
+            payload: rest.to_vec(),
+        })
+    }
+}
+
+fn has_wildcard(topic: &str) -> bool {
+    topic.contains(['+', '#'])
+}
+
+fn timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(text) => DateTime::parse_from_rfc3339(text)
+            .ok()
+            .map(|date| date.with_timezone(&Utc)),
+        Value::Number(number) => {
+            let number = number.as_f64()?;
+            let millis = if number.abs() > 1e11 {
+                number
+            } else {
+                number * 1000.0
+            };
+            Utc.timestamp_millis_opt(millis as i64).single()
+        }
+        _ => None,
+    }
+}
+
+/// Turns a payload into a message. JSON objects may carry `text`, `id`, `sender_id` and
+/// `timestamp`; any other JSON is shown as written and plain payloads as text. The parsed JSON
+/// is kept in `extra.payload` either way, for dashboards that want the raw values.
+fn payload_message(publish: &Publish, own_id: &str) -> Message {
+    let json = serde_json::from_slice::<Value>(&publish.payload).ok();
+    let field = |name: &str| {
+        json.as_ref()
+            .and_then(|json| json.get(name))
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_string())
+    };
+    let text = match &json {
+        Some(json) => field("text").unwrap_or_else(|| json.to_string()),
+        None => String::from_utf8_lossy(&publish.payload).into_owned(),
+    };
+    let sender_id = field("sender_id");
+    let mut extra = HashMap::new();
+    if let Some(json) = &json {
+        extra.insert("payload".to_string(), json.clone());
+    }
+    if publish.retain {
+        extra.insert("retained".to_string(), json!(true));
+    }
+    Message {
+        id: field("id"),
+        message_type: if sender_id.as_deref() == Some(own_id) {
+            MessageType::CurrentUser
+        } else {
+            MessageType::Normal
+        },
+        sender_id,
+        content: vec![MessageFragment::Text(text)],
+        timestamp: json
+            .as_ref()
+            .and_then(|json| json.get("timestamp"))
+            .and_then(timestamp)
+            .unwrap_or_else(Utc::now),
+        status: MessageStatus::Delivered,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra,
+    }
+}
+
+#[derive(Clone)]
+struct MqttMapper {
+    client_id: String,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    topics: Arc<StdMutex<HashSet<String>>>,
+}
+
+impl MqttMapper {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    /// Announces and joins a topic's channel the first time it is seen.
+    fn topic(&self, topic: &str) {
+        let new = self
+            .topics
+            .lock()
+            .map(|mut topics| topics.insert(topic.to_string()))
+            .unwrap_or(false);
+        if !new {
+            return;
+        }
+        self.emit(ConnectionEvent::Channel {
+            event: ChannelEvent::New {
+                channel: Channel {
+                    id: topic.to_string(),
+                    name: Some(topic.to_string()),
+                    channel_type: ChannelType::Group,
+                    topic: None,
+                    extra: HashMap::new(),
+                },
+            },
+        });
+        self.emit(ConnectionEvent::Channel {
+            event: ChannelEvent::Join {
+                channel_id: topic.to_string(),
+            },
+        });
+    }
+
+    fn publish(&self, publish: &Publish) {
+        self.topic(&publish.topic);
+        self.emit(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel(&publish.topic),
+                message: payload_message(publish, &self.client_id),
+            },
+        });
+    }
+}
+
+/// Chat over an MQTT 3.1.1 broker. Each topic is a channel: filters without wildcards are
+/// joined on connect, and topics matched by a wildcard appear as their first message arrives.
+/// Messages are published as JSON with `id`, `sender_id`, `text` and `timestamp`.
+pub struct MqttConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    packet_tx: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    mapper: Option<MqttMapper>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl MqttConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        MqttConnection {
+            auth: Vec::new(),
+            options,
+            event_tx,
+            event_rx: Some(event_rx),
+            packet_tx: None,
+            mapper: None,
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn fail_auth(&self, reason: String) -> ConnectionError {
+        self.set_status(ConnectionStatus::AuthFailed {
+            reason: reason.clone(),
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed {
+                reason: reason.clone(),
+            },
+        });
+        ConnectionError::Auth(reason)
+    }
+}
+
+impl Default for MqttConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+fn parse_topics(topics: &str) -> Vec<String> {
+    topics
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|topic| !topic.is_empty())
+        .map(|topic| topic.to_string())
+        .collect()
+}
+
+#[async_trait]
+impl Connection for MqttConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "mqtt.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let broker = field_text(&self.auth, "broker")
+            .ok_or_else(|| ConnectionError::Auth("Missing broker".to_string()))?;
+        let address = BrokerAddress::parse(&broker)?;
+        let filters = field_text(&self.auth, "topics")
+            .map(|topics| parse_topics(&topics))
+            .filter(|filters| !filters.is_empty())
+            .ok_or_else(|| ConnectionError::Auth("Missing topics".to_string()))?;
+        let client_id = field_text(&self.auth, "client_id").unwrap_or_else(|| {
+            format!(
+                "oshatori-{}",
+                &uuid::Uuid::new_v4().simple().to_string()[..8]
+            )
+        });
+        let username = field_text(&self.auth, "username");
+        // MQTT 3.1.1 only allows a password after a username.
+        let password = field_text(&self.auth, "password").filter(|_| username.is_some());
+
+        self.tasks.shutdown().await;
+        self.packet_tx = None;
+        self.mapper = None;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+        let network = |e: std::io::Error| ConnectionError::Network(e.to_string());
+        let mut stream = match open_stream(&address, &self.options).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+        // The broker drops clients silent for one and a half keep-alive periods, so leave
+        // room for a ping that is a little late.
+        let keep_alive = (self.options.ping_interval.as_secs() * 2).clamp(1, u16::MAX as u64);
+        let handshake = tokio::time::timeout(CONNACK_TIMEOUT, async {
+            stream
+                .write_all(&connect_packet(
+                    &client_id,
+                    username.as_deref(),
+                    password.as_deref(),
+                    keep_alive as u16,
+                ))
+                .await
+                .map_err(network)?;
+            let (header, body) = read_packet(&mut stream).await.map_err(network)?;
+            match (header >> 4, body.get(1)) {
+                (packet::CONNACK, Some(0)) => {}
+                (packet::CONNACK, Some(4 | 5)) => {
+                    return Err(ConnectionError::Auth(
+                        "Broker rejected the credentials".to_string(),
+                    ))
+                }
+                (packet::CONNACK, code) => {
+                    return Err(ConnectionError::Protocol(format!(
+                        "Broker refused the connection ({:?})",
+                        code
+                    )))
+                }
+                _ => return Err(ConnectionError::Protocol("Expected CONNACK".to_string())),
+            }
+            stream
+                .write_all(&subscribe_packet(1, &filters))
+                .await
+                .map_err(network)?;
+            // Retained messages may arrive before the SUBACK.
+            let mut buffered = Vec::new();
+            loop {
+                let (header, body) = read_packet(&mut stream).await.map_err(network)?;
+                match header >> 4 {
+                    packet::SUBACK => return Ok((body, buffered)),
+                    packet::PUBLISH => buffered.extend(Publish::parse(header, &body)),
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .map_err(|_| ConnectionError::Timeout)
+        .and_then(|handshake| handshake);
+        let (granted, buffered) = match handshake {
+            Err(ConnectionError::Auth(reason)) => return Err(self.fail_auth(reason)),
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+            Ok(handshake) => handshake,
+        };
+
+        let mapper = MqttMapper {
+            client_id: client_id.clone(),
+            event_tx: self.event_tx.clone(),
+            topics: Arc::new(StdMutex::new(HashSet::new())),
+        };
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected {
+                artifact: Some(address.host.clone()),
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::User {
+            event: UserEvent::Identify {
+                user_id: client_id.clone(),
+            },
+        });
+        for (filter, code) in filters.iter().zip(granted.iter().skip(2)) {
+            if *code == 0x80 {
+                mapper.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Error {
+                        message: format!("Broker refused the subscription to {}", filter),
+                    },
+                });
+            } else if !has_wildcard(filter) {
+                mapper.topic(filter);
+            }
+        }
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Synced,
+        });
+        for publish in &buffered {
+            mapper.publish(publish);
+        }
+
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let (packet_tx, mut packet_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        self.packet_tx = Some(packet_tx.clone());
+        self.mapper = Some(mapper.clone());
+        let ping_interval = self.options.ping_interval;
+        self.tasks
+            .spawn_essential("writer", self.status.clone(), async move {
+                let mut ping = tokio::time::interval(ping_interval);
+                ping.tick().await;
+                loop {
+                    let packet = tokio::select! {
+                        packet = packet_rx.recv() => match packet {
+                            Some(packet) => packet,
+                            None => break,
+                        },
+                        _ = ping.tick() => frame(packet::PINGREQ << 4, &[]),
+                    };
+                    if writer.write_all(&packet).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        let status = self.status.clone();
+        self.tasks
+            .spawn_essential("reader", self.status.clone(), async move {
+                while let Ok((header, body)) = read_packet(&mut reader).await {
+                    if header >> 4 != packet::PUBLISH {
+                        continue;
+                    }
+                    let Some(publish) = Publish::parse(header, &body) else {
+                        continue;
+                    };
+                    if let Some(packet_id) = publish.packet_id {
+                        let _ =
+                            packet_tx.send(frame(packet::PUBACK << 4, &packet_id.to_be_bytes()));
+                    }
+                    mapper.publish(&publish);
+                }
+                if let Ok(mut current) = status.lock() {
+                    if *current == ConnectionStatus::Connected {
+                        *current = ConnectionStatus::Disconnected;
+                    }
+                }
+                mapper.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Disconnected {
+                        artifact: Some("Broker closed the connection".to_string()),
+                    },
+                });
+            });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        if let Some(packet_tx) = &self.packet_tx {
+            let _ = packet_tx.send(frame(packet::DISCONNECT << 4, &[]));
+            tokio::task::yield_now().await;
+        }
+        self.tasks.shutdown().await;
+        self.packet_tx = None;
+        self.mapper = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        let ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } = event
+        else {
+            return Err(ConnectionError::Unsupported(
+                "Only new messages can be published".to_string(),
+            ));
+        };
+        let (Some(mapper), Some(packet_tx)) = (&self.mapper, &self.packet_tx) else {
+            return Err(ConnectionError::Closed);
+        };
+        let topic = scope.channel_id().ok_or_else(|| {
+            ConnectionError::Unsupported("MQTT messages need a topic".to_string())
+        })?;
+        if has_wildcard(topic) {
+            return Err(ConnectionError::Unsupported(
+                "Cannot publish to a wildcard topic".to_string(),
+            ));
+        }
+        let payload = json!({
+            "id": message.id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            "sender_id": mapper.client_id,
+            "text": plain_text(&message.content),
+            "timestamp": message.timestamp.to_rfc3339(),
+        });
+        packet_tx
+            .send(publish_packet(topic, payload.to_string().as_bytes()))
+            .map_err(|_| ConnectionError::Closed)
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        let field = |name: &str, display: &str, value, required| AuthField {
+            name: name.to_string(),
+            display: Some(display.to_string()),
+            value,
+            required,
+        };
+        Protocol {
+            name: "mqtt".to_string(),
+            auth: Some(vec![
+                field(
+                    "broker",
+                    "Broker (mqtts://host:8883)",
+                    FieldValue::Text(None),
+                    true,
+                ),
+                field(
+                    "topics",
+                    "Topic filters, separated by spaces or commas",
+                    FieldValue::Text(None),
+                    true,
+                ),
+                field("username", "Username", FieldValue::Text(None), false),
+                field("password", "Password", FieldValue::Password(None), false),
+                field("client_id", "Client id", FieldValue::Text(None), false),
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            multiple_channels: true,
+            ..Capabilities::default()
+        }
+    }
+
+    async fn preflight(&self, check_reachability: bool) -> PreflightReport {
+        let mut report = validate_auth(&self.protocol_spec(), &self.auth);
+        let address = field_text(&self.auth, "broker").and_then(|broker| {
+            BrokerAddress::parse(&broker)
+                .map_err(|e| report.error(Some("broker"), e.to_string()))
+                .ok()
+        });
+        if field_text(&self.auth, "password").is_some()
+            && field_text(&self.auth, "username").is_none()
+        {
+            report.warning(
+                Some("username"),
+                "The password is only sent along with a username",
+            );
+        }
+        if check_reachability {
+            if let Some(address) = address {
+                let timeout = self
+                    .options
+                    .connect_timeout
+                    .unwrap_or(Duration::from_secs(5));
+                report.reachability =
+                    probe_reachability(&address.host, address.port, timeout).await;
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,833 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bech32::{Bech32, Hrp};
+use chrono::{DateTime, Utc};
+use futures_util::{SinkExt, StreamExt};
+use k256::{
+    ecdh::diffie_hellman,
+    schnorr::{Signature, SigningKey, VerifyingKey},
+    PublicKey,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use url::Url;
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{compose::plain_text, trace::event},
+    AuthField, Capabilities, Channel, ChannelType, Connection, FieldValue, Message,
+    MessageFragment, MessageStatus, MessageType, Protocol,
+};
+
+use super::{
+    transport::connect_websocket, ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent,
+    ConnectionOptions, Scope, SendHandle, SendOutcome, StatusEvent, Supervisor, UserEvent,
+};
+
+const KIND_DIRECT_MESSAGE: u64 = 4;
+const KIND_CHANNEL_CREATE: u64 = 40;
+const KIND_CHANNEL_METADATA: u64 = 41;
+const KIND_CHANNEL_MESSAGE: u64 = 42;
+const DIRECT_SUBSCRIPTION: &str = "dm";
+const CHANNEL_SUBSCRIPTION: &str = "channel:";
+const SEEN_LIMIT: usize = 4096;
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+/// A secp256k1 keypair identifying a Nostr user, able to sign events and to encrypt and
+/// decrypt NIP-04 direct messages.
+#[derive(Clone)]
+pub struct NostrKeys {
+    signing: SigningKey,
+    public_key: String,
+}
+
+impl NostrKeys {
+    pub fn generate() -> Self {
+        loop {
+            let mut secret = [0u8; 32];
+            getrandom::getrandom(&mut secret).expect("no system randomness available");
+            if let Ok(signing) = SigningKey::from_bytes(&secret) {
+                return Self::from_signing(signing);
+            }
+        }
+    }
+
+    /// Parses a secret key given as 64 hex characters or as a bech32 `nsec`.
+    pub fn parse(secret: &str) -> Result<Self, ConnectionError> {
+        let bytes = decode_key(secret, "nsec")
+            .ok_or_else(|| ConnectionError::Auth("Invalid Nostr secret key".to_string()))?;
+        let signing = SigningKey::from_bytes(&bytes)
+            .map_err(|_| ConnectionError::Auth("Invalid Nostr secret key".to_string()))?;
+        Ok(Self::from_signing(signing))
+    }
+
+    fn from_signing(signing: SigningKey) -> Self {
+        let public_key = to_hex(&signing.verifying_key().to_bytes());
+        NostrKeys {
+            signing,
+            public_key,
+        }
+    }
+
+    /// The x-only public key as lowercase hex, which is also the user id.
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    pub fn npub(&self) -> String {
+        let bytes = decode_key(&self.public_key, "npub").unwrap_or_default();
+        bech32::encode::<Bech32>(Hrp::parse_unchecked("npub"), &bytes).unwrap_or_default()
+    }
+
+    pub fn secret_key(&self) -> String {
+        to_hex(&self.signing.to_bytes())
+    }
+
+    fn shared_secret(&self, peer: &str) -> Result<[u8; 32], ConnectionError> {
+        let x = decode_key(peer, "npub")
+            .ok_or_else(|| ConnectionError::Protocol(format!("Invalid public key {}", peer)))?;
+        let mut sec1 = [2u8; 33];
+        sec1[1..].copy_from_slice(&x);
+        let public = PublicKey::from_sec1_bytes(&sec1)
+            .map_err(|_| ConnectionError::Protocol(format!("Invalid public key {}", peer)))?;
+        let shared = diffie_hellman(self.signing.as_nonzero_scalar(), public.as_affine());
+        Ok((*shared.raw_secret_bytes()).into())
+    }
+
+    /// Encrypts `plaintext` for `peer` as a NIP-04 `ciphertext?iv=` payload.
+    pub fn encrypt(&self, peer: &str, plaintext: &str) -> Result<String, ConnectionError> {
+        let key = self.shared_secret(peer)?;
+        let mut iv = [0u8; 16];
+        getrandom::getrandom(&mut iv).map_err(|e| ConnectionError::Protocol(e.to_string()))?;
+        let ciphertext = Aes256CbcEnc::new(&key.into(), &iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(plaintext.as_bytes());
+        Ok(format!(
+            "{}?iv={}",
+            STANDARD.encode(ciphertext),
+            STANDARD.encode(iv)
+        ))
+    }
+
+    pub fn decrypt(&self, peer: &str, payload: &str) -> Result<String, ConnectionError> {
+        let invalid = || ConnectionError::Protocol("Malformed encrypted message".to_string());
+        let key = self.shared_secret(peer)?;
+        let (ciphertext, iv) = payload.split_once("?iv=").ok_or_else(invalid)?;
+        let ciphertext = STANDARD.decode(ciphertext).map_err(|_| invalid())?;
+        let iv: [u8; 16] = STANDARD
+            .decode(iv)
+            .ok()
+            .and_then(|iv| iv.try_into().ok())
+            .ok_or_else(invalid)?;
+        let plaintext = Aes256CbcDec::new(&key.into(), &iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&ciphertext)
+            .map_err(|_| invalid())?;
+        String::from_utf8(plaintext).map_err(|_| invalid())
+    }
+
+    /// Builds and signs an event authored by this key.
+    pub fn sign(&self, kind: u64, tags: Value, content: &str, created_at: i64) -> Value {
+        let id = event_id(&self.public_key, created_at, kind, &tags, content);
+        let mut aux = [0u8; 32];
+        let _ = getrandom::getrandom(&mut aux);
+        let sig = self
+            .signing
+            .sign_raw(&id, &aux)
+            .expect("signing a 32-byte digest cannot fail");
+        json!({
+            "id": to_hex(&id),
+            "pubkey": self.public_key,
+            "created_at": created_at,
+            "kind": kind,
+            "tags": tags,
+            "content": content,
+            "sig": to_hex(&sig.to_bytes()),
+        })
+    }
+}
+
+impl fmt::Debug for NostrKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NostrKeys")
+            .field("public_key", &self.public_key)
+            .finish_non_exhaustive()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Decodes a 32-byte key given as hex or as bech32 with the expected human-readable part.
+fn decode_key(text: &str, hrp: &str) -> Option<[u8; 32]> {
+    let text = text.trim();
+    let bytes = if text.starts_with(hrp) {
+        let (found, bytes) = bech32::decode(text).ok()?;
+        (found.as_str() == hrp).then_some(bytes)?
+    } else {
+        from_hex(text)?
+    };
+    bytes.try_into().ok()
+}
+
+fn event_id(pubkey: &str, created_at: i64, kind: u64, tags: &Value, content: &str) -> [u8; 32] {
+    let serialized = json!([0, pubkey, created_at, kind, tags, content]).to_string();
+    Sha256::digest(serialized.as_bytes()).into()
+}
+
+/// Whether `event`'s id matches its contents and its signature is valid for its pubkey.
+fn verify_event(event: &Value) -> bool {
+    let (Some(pubkey), Some(created_at), Some(kind), Some(content)) = (
+        event["pubkey"].as_str(),
+        event["created_at"].as_i64(),
+        event["kind"].as_u64(),
+        event["content"].as_str(),
+    ) else {
+        return false;
+    };
+    let id = event_id(pubkey, created_at, kind, &event["tags"], content);
+    if event["id"].as_str() != Some(to_hex(&id).as_str()) {
+        return false;
+    }
+    let key = decode_key(pubkey, "npub").and_then(|key| VerifyingKey::from_bytes(&key).ok());
+    let sig = event["sig"]
+        .as_str()
+        .and_then(from_hex)
+        .and_then(|sig| Signature::try_from(sig.as_slice()).ok());
+    match (key, sig) {
+        (Some(key), Some(sig)) => key.verify_raw(&id, &sig).is_ok(),
+        _ => false,
+    }
+}
+
+/// Values of the tags named `name`, as `(value, marker)` pairs.
+fn tags<'a>(event: &'a Value, name: &'a str) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+    event["tags"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(move |tag| tag[0].as_str() == Some(name))
+        .filter_map(|tag| Some((tag[1].as_str()?, tag[3].as_str())))
+}
+
+fn marked<'a>(event: &'a Value, marker: &str) -> Option<&'a str> {
+    tags(event, "e")
+        .find(|(_, m)| *m == Some(marker))
+        .map(|(id, _)| id)
+}
+
+fn channel_from(id: &str, metadata: &str) -> Channel {
+    let metadata: Value = serde_json::from_str(metadata).unwrap_or_default();
+    let mut extra = HashMap::new();
+    if let Some(picture) = metadata["picture"].as_str() {
+        extra.insert("picture".to_string(), json!(picture));
+    }
+    Channel {
+        id: id.to_string(),
+        name: metadata["name"].as_str().map(str::to_string),
+        channel_type: ChannelType::Group,
+        topic: metadata["about"]
+            .as_str()
+            .filter(|about| !about.is_empty())
+            .map(str::to_string),
+        extra,
+    }
+}
+
+fn subscribe_direct(public_key: &str) -> String {
+    json!([
+        "REQ",
+        DIRECT_SUBSCRIPTION,
+        { "kinds": [KIND_DIRECT_MESSAGE], "#p": [public_key] },
+        { "kinds": [KIND_DIRECT_MESSAGE], "authors": [public_key] }
+    ])
+    .to_string()
+}
+
+fn subscribe_channel(channel_id: &str) -> String {
+    json!([
+        "REQ",
+        format!("{}{}", CHANNEL_SUBSCRIPTION, channel_id),
+        { "kinds": [KIND_CHANNEL_CREATE], "ids": [channel_id] },
+        { "kinds": [KIND_CHANNEL_METADATA, KIND_CHANNEL_MESSAGE], "#e": [channel_id] }
+    ])
+    .to_string()
+}
+
+/// Turns relay messages into connection events, dropping duplicates relayed more than once.
+struct NostrMapper {
+    keys: NostrKeys,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    seen: HashSet<String>,
+    seen_order: VecDeque<String>,
+    peers: HashSet<String>,
+    creators: HashMap<String, String>,
+    synced: bool,
+}
+
+impl NostrMapper {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn remember(&mut self, id: &str) {
+        self.seen.insert(id.to_string());
+        self.seen_order.push_back(id.to_string());
+        if self.seen_order.len() > SEEN_LIMIT {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+    }
+
+    fn apply(&mut self, frame: &Value) {
+        match frame[0].as_str() {
+            Some("EVENT") => self.apply_event(&frame[2]),
+            Some("EOSE") if frame[1].as_str() == Some(DIRECT_SUBSCRIPTION) && !self.synced => {
+                self.synced = true;
+                self.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Synced,
+                });
+            }
+            Some("NOTICE") => event!(info, "relay notice: {}", frame[1]),
+            Some("OK") if frame[2].as_bool() == Some(false) => {
+                event!(warn, "relay rejected {}: {}", frame[1], frame[3])
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_event(&mut self, event: &Value) {
+        let Some(id) = event["id"].as_str() else {
+            return;
+        };
+        if self.seen.contains(id) {
+            return;
+        }
+        if !verify_event(event) {
+            event!(debug, "dropping event {} with a bad signature", id);
+            return;
+        }
+        self.remember(id);
+        let author = event["pubkey"].as_str().unwrap_or_default();
+        match event["kind"].as_u64() {
+            Some(KIND_DIRECT_MESSAGE) => self.direct_message(event, author),
+            Some(KIND_CHANNEL_CREATE) => {
+                self.creators.insert(id.to_string(), author.to_string());
+                let channel = channel_from(id, event["content"].as_str().unwrap_or_default());
+                self.emit(ConnectionEvent::Channel {
+                    event: ChannelEvent::New { channel },
+                });
+                self.emit(ConnectionEvent::Channel {
+                    event: ChannelEvent::Join {
+                        channel_id: id.to_string(),
+                    },
+                });
+            }
+            Some(KIND_CHANNEL_METADATA) => {
+                let Some(channel_id) =
+                    marked(event, "root").or(tags(event, "e").next().map(|t| t.0))
+                else {
+                    return;
+                };
+                if self.creators.get(channel_id).map(String::as_str) != Some(author) {
+                    return;
+                }
+                let new_channel =
+                    channel_from(channel_id, event["content"].as_str().unwrap_or_default());
+                self.emit(ConnectionEvent::Channel {
+                    event: ChannelEvent::Update {
+                        channel_id: channel_id.to_string(),
+                        new_channel,
+                    },
+                });
+            }
+            Some(KIND_CHANNEL_MESSAGE) => {
+                let Some(channel_id) =
+                    marked(event, "root").or(tags(event, "e").next().map(|t| t.0))
+                else {
+                    return;
+                };
+                let content = event["content"].as_str().unwrap_or_default().to_string();
+                let mut message = message_from(event, content);
+                message.reply_to = marked(event, "reply").map(str::to_string);
+                self.emit(ConnectionEvent::Chat {
+                    event: ChatEvent::New {
+                        scope: Scope::channel(channel_id),
+                        message,
+                    },
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn direct_message(&mut self, event: &Value, author: &str) {
+        let peer = if author == self.keys.public_key() {
+            match tags(event, "p").next() {
+                Some((peer, _)) => peer.to_string(),
+                None => return,
+            }
+        } else {
+            author.to_string()
+        };
+        let content = match self
+            .keys
+            .decrypt(&peer, event["content"].as_str().unwrap_or_default())
+        {
+            Ok(content) => content,
+            Err(e) => {
+                event!(debug, "undecryptable direct message from {}: {}", peer, e);
+                return;
+            }
+        };
+        if self.peers.insert(peer.clone()) {
+            self.emit(ConnectionEvent::Channel {
+                event: ChannelEvent::New {
+                    channel: direct_channel(&peer),
+                },
+            });
+            self.emit(ConnectionEvent::Channel {
+                event: ChannelEvent::Join {
+                    channel_id: peer.clone(),
+                },
+            });
+        }
+        let mut message = message_from(event, content);
+        message.reply_to = tags(event, "e").next().map(|(id, _)| id.to_string());
+        self.emit(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel(peer),
+                message,
+            },
+        });
+    }
+}
+
+fn direct_channel(peer: &str) -> Channel {
+    Channel {
+        id: peer.to_string(),
+        name: None,
+        channel_type: ChannelType::Direct,
+        topic: None,
+        extra: HashMap::new(),
+    }
+}
+
+fn message_from(event: &Value, content: String) -> Message {
+    Message {
+        id: event["id"].as_str().map(str::to_string),
+        sender_id: event["pubkey"].as_str().map(str::to_string),
+        content: vec![MessageFragment::Text(content)],
+        timestamp: event["created_at"]
+            .as_i64()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .unwrap_or_else(Utc::now),
+        message_type: MessageType::Normal,
+        status: MessageStatus::Sent,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
+    }
+}
+
+/// A Nostr client that signs with one keypair and talks to a set of relays at once. Kind 4
+/// direct messages map to one `Direct` channel per peer public key, and joined NIP-28 public
+/// channels map to `Group` channels keyed by their creation event id.
+pub struct NostrConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    keys: Option<NostrKeys>,
+    relays: Vec<mpsc::UnboundedSender<String>>,
+    channels: HashSet<String>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl NostrConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        NostrConnection {
+            auth: Vec::new(),
+            options,
+            event_tx,
+            event_rx: Some(event_rx),
+            keys: None,
+            relays: Vec::new(),
+            channels: HashSet::new(),
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    /// The signing keypair, once connected.
+    pub fn keys(&self) -> Option<&NostrKeys> {
+        self.keys.as_ref()
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn keys_or_closed(&self) -> Result<&NostrKeys, ConnectionError> {
+        self.keys.as_ref().ok_or(ConnectionError::Closed)
+    }
+
+    /// Sends `frame` to every live relay, failing only if none accepted it.
+    fn broadcast(&mut self, frame: String) -> Result<(), ConnectionError> {
+        self.relays
+            .retain(|relay| relay.send(frame.clone()).is_ok());
+        if self.relays.is_empty() {
+            return Err(ConnectionError::Closed);
+        }
+        Ok(())
+    }
+
+    fn publish(&mut self, scope: &Scope, message: &Message) -> Result<String, ConnectionError> {
+        let target = scope.channel_id().ok_or_else(|| {
+            ConnectionError::Unsupported("Nostr messages need a channel".to_string())
+        })?;
+        let keys = self.keys_or_closed()?;
+        let text = plain_text(&message.content);
+        let event = if self.channels.contains(target) {
+            let mut tags = vec![json!(["e", target, "", "root"])];
+            if let Some(reply_to) = &message.reply_to {
+                tags.push(json!(["e", reply_to, "", "reply"]));
+            }
+            keys.sign(
+                KIND_CHANNEL_MESSAGE,
+                json!(tags),
+                &text,
+                Utc::now().timestamp(),
+            )
+        } else {
+            let peer = decode_key(target, "npub")
+                .map(|key| to_hex(&key))
+                .ok_or_else(|| {
+                    ConnectionError::Unsupported(format!("Unknown channel {}", target))
+                })?;
+            let mut tags = vec![json!(["p", peer])];
+            if let Some(reply_to) = &message.reply_to {
+                tags.push(json!(["e", reply_to]));
+            }
+            keys.sign(
+                KIND_DIRECT_MESSAGE,
+                json!(tags),
+                &keys.encrypt(&peer, &text)?,
+                Utc::now().timestamp(),
+            )
+        };
+        let id = event["id"].as_str().unwrap_or_default().to_string();
+        self.broadcast(json!(["EVENT", event]).to_string())?;
+        Ok(id)
+    }
+}
+
+impl Default for NostrConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+fn split_list(value: Option<String>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[async_trait]
+impl Connection for NostrConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "nostr.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let keys = field_text(&self.auth, "secret_key")
+            .ok_or_else(|| ConnectionError::Auth("Missing secret key".to_string()))
+            .and_then(|secret| NostrKeys::parse(&secret))?;
+        let relays = split_list(field_text(&self.auth, "relays"))
+            .iter()
+            .map(|relay| Url::parse(relay).map_err(|e| ConnectionError::Auth(e.to_string())))
+            .collect::<Result<Vec<_>, _>>()?;
+        if relays.is_empty() {
+            return Err(ConnectionError::Auth("No relays configured".to_string()));
+        }
+        self.channels = split_list(field_text(&self.auth, "channels"))
+            .into_iter()
+            .collect();
+
+        self.tasks.shutdown().await;
+        self.relays.clear();
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+
+        let (frames_tx, mut frames_rx) = mpsc::unbounded_channel::<Value>();
+        let mut last_error = None;
+        for relay in relays {
+            let mut socket = match connect_websocket(&relay, &self.options).await {
+                Ok(socket) => socket,
+                Err(e) => {
+                    event!(warn, "could not reach relay {}: {}", relay, e);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel::<String>();
+            let _ = outgoing_tx.send(subscribe_direct(keys.public_key()));
+            for channel_id in &self.channels {
+                let _ = outgoing_tx.send(subscribe_channel(channel_id));
+            }
+            self.relays.push(outgoing_tx);
+            let frames_tx = frames_tx.clone();
+            self.tasks.spawn(&format!("relay {}", relay), async move {
+                loop {
+                    tokio::select! {
+                        frame = socket.next() => match frame {
+                            Some(Ok(WsMessage::Text(text))) => {
+                                let Ok(frame) = serde_json::from_str::<Value>(&text) else {
+                                    event!(debug, "unparsed relay frame {:?}", text);
+                                    continue;
+                                };
+                                if frames_tx.send(frame).is_err() {
+                                    break;
+                                }
+                            }
+                            Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                            Some(Ok(_)) => {}
+                        },
+                        Some(frame) = outgoing_rx.recv() => {
+                            if socket.send(WsMessage::Text(frame.into())).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+        drop(frames_tx);
+        if self.relays.is_empty() {
+            self.set_status(ConnectionStatus::Disconnected);
+            return Err(last_error.unwrap_or(ConnectionError::Closed));
+        }
+
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected {
+                artifact: Some(keys.npub()),
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::User {
+            event: UserEvent::Identify {
+                user_id: keys.public_key().to_string(),
+            },
+        });
+
+        let mut mapper = NostrMapper {
+            keys: keys.clone(),
+            event_tx: self.event_tx.clone(),
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+            peers: HashSet::new(),
+            creators: HashMap::new(),
+            synced: false,
+        };
+        self.keys = Some(keys);
+        let status = self.status.clone();
+        self.tasks
+            .spawn_essential("router", self.status.clone(), async move {
+                while let Some(frame) = frames_rx.recv().await {
+                    mapper.apply(&frame);
+                }
+                if let Ok(mut current) = status.lock() {
+                    if *current == ConnectionStatus::Connected {
+                        *current = ConnectionStatus::Disconnected;
+                    }
+                }
+                mapper.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Disconnected {
+                        artifact: Some("All relays closed".to_string()),
+                    },
+                });
+            });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.relays.clear();
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } => self.publish(&scope, &message).map(|_| ()),
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Join { channel_id },
+            } => {
+                self.broadcast(subscribe_channel(&channel_id))?;
+                self.channels.insert(channel_id);
+                Ok(())
+            }
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Leave { channel_id },
+            } => {
+                let subscription = format!("{}{}", CHANNEL_SUBSCRIPTION, channel_id);
+                self.broadcast(json!(["CLOSE", subscription]).to_string())?;
+                self.channels.remove(&channel_id);
+                let _ = self.event_tx.send(ConnectionEvent::Channel {
+                    event: ChannelEvent::Leave { channel_id },
+                });
+                Ok(())
+            }
+            _ => Err(ConnectionError::Unsupported(
+                "Event not supported over Nostr".to_string(),
+            )),
+        }
+    }
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        let ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } = &event
+        else {
+            self.send(event).await?;
+            return Ok(SendHandle::completed(SendOutcome::Delivered {
+                message_id: None,
+            }));
+        };
+        let id = self.publish(scope, message)?;
+        Ok(SendHandle::completed(SendOutcome::Delivered {
+            message_id: Some(id),
+        }))
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        let peer = decode_key(user_id, "npub")
+            .map(|key| to_hex(&key))
+            .ok_or_else(|| {
+                ConnectionError::Unsupported(format!("Invalid public key {}", user_id))
+            })?;
+        let channel = direct_channel(&peer);
+        self.event_tx
+            .send(ConnectionEvent::Channel {
+                event: ChannelEvent::New {
+                    channel: channel.clone(),
+                },
+            })
+            .map_err(|_| ConnectionError::Closed)?;
+        Ok(channel)
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        let field = |name: &str, display: &str, value: FieldValue, required: bool| AuthField {
+            name: name.to_string(),
+            display: Some(display.to_string()),
+            value,
+            required,
+        };
+        Protocol {
+            name: "nostr".to_string(),
+            auth: Some(vec![
+                field(
+                    "secret_key",
+                    "Secret key (nsec or hex)",
+                    FieldValue::Key(None),
+                    true,
+                ),
+                field(
+                    "relays",
+                    "Relay URLs, comma separated",
+                    FieldValue::Text(None),
+                    true,
+                ),
+                field(
+                    "channels",
+                    "NIP-28 channel ids to join",
+                    FieldValue::Text(None),
+                    false,
+                ),
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            multiple_channels: true,
+            ..Capabilities::default()
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::utils::ids::{IdGenerator, UuidGenerator};
+
+use super::supervisor::RestartPolicy;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProxyKind {
+    Http,
+    Socks5,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    pub credentials: Option<(String, String)>,
+}
+
+impl Proxy {
+    pub fn http(host: &str, port: u16) -> Self {
+        Proxy {
+            kind: ProxyKind::Http,
+            host: host.to_string(),
+            port,
+            credentials: None,
+        }
+    }
+
+    pub fn socks5(host: &str, port: u16) -> Self {
+        Proxy {
+            kind: ProxyKind::Socks5,
+            host: host.to_string(),
+            port,
+            credentials: None,
+        }
+    }
+
+    pub fn credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientIdentity {
+    pub certificate_pem: Vec<u8>,
+    pub key_pem: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TlsConfig {
+    pub root_certificates: Vec<Vec<u8>>,
+    pub client_identity: Option<ClientIdentity>,
+    pub accept_invalid_certs: bool,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn root_certificate(mut self, pem: &[u8]) -> Self {
+        self.root_certificates.push(pem.to_vec());
+        self
+    }
+
+    pub fn client_identity(mut self, certificate_pem: &[u8], key_pem: &[u8]) -> Self {
+        self.client_identity = Some(ClientIdentity {
+            certificate_pem: certificate_pem.to_vec(),
+            key_pem: key_pem.to_vec(),
+        });
+        self
+    }
+
+    pub fn danger_accept_invalid_certs(mut self) -> Self {
+        self.accept_invalid_certs = true;
+        self
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionOptions {
+    pub connect_timeout: Option<Duration>,
+    pub ping_interval: Duration,
+    pub outbound_queue: usize,
+    pub ack_timeout: Duration,
+    pub max_retries: u32,
+    pub ids: Arc<dyn IdGenerator>,
+    pub proxy: Option<Proxy>,
+    pub tls: TlsConfig,
+    pub low_bandwidth: bool,
+    pub restart_policy: RestartPolicy,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            connect_timeout: Some(Duration::from_secs(30)),
+            ping_interval: Duration::from_secs(40),
+            outbound_queue: 256,
+            ack_timeout: Duration::from_secs(10),
+            max_retries: 2,
+            ids: Arc::new(UuidGenerator),
+            proxy: None,
+            tls: TlsConfig::default(),
+            low_bandwidth: false,
+            restart_policy: RestartPolicy::default(),
+        }
+    }
+}
+
+impl ConnectionOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = interval;
+        self
+    }
+
+    pub fn ids(mut self, ids: impl IdGenerator + 'static) -> Self {
+        self.ids = Arc::new(ids);
+        self
+    }
+
+    pub fn outbound_queue(mut self, capacity: usize) -> Self {
+        self.outbound_queue = capacity.max(1);
+        self
+    }
+
+    pub fn ack_timeout(mut self, timeout: Duration) -> Self {
+        self.ack_timeout = timeout;
+        self
+    }
+
+    /// How many times a failed write is retried before the send is reported as failed. Sends
+    /// that were written but never acknowledged are not retried.
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Skips avatar and asset downloads and strips inbound media to text placeholders.
+    pub fn low_bandwidth(mut self, enabled: bool) -> Self {
+        self.low_bandwidth = enabled;
+        self
+    }
+
+    /// Controls whether background tasks that die are rebuilt. Failures are reported as
+    /// `StatusEvent::Error` regardless.
+    pub fn restart_policy(mut self, policy: RestartPolicy) -> Self {
+        self.restart_policy = policy;
+        self
+    }
+}
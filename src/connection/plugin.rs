@@ -0,0 +1,179 @@
+use std::{
+    ffi::{c_char, CStr},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use libloading::Library;
+
+use super::ProtocolRegistry;
+
+/// Bumped whenever `PluginDeclaration` or the registration contract changes.
+pub const PLUGIN_ABI_VERSION: u32 = 2;
+pub const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
+#[doc(hidden)]
+pub const CORE_VERSION_NUL: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+pub const DECLARATION_SYMBOL: &[u8] = b"OSHATORI_PLUGIN\0";
+
+/// Exported by a plugin library through `export_plugin!`. Plugins hand Rust types across the
+/// library boundary, so they must be built against the same oshatori version and toolchain as
+/// the host; the version fields let the loader reject mismatches before calling into them. The
+/// layout is fixed and the version is a C string, so both can be read from any build.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginDeclaration {
+    pub abi_version: u32,
+    /// Nul-terminated oshatori version the plugin was built against.
+    pub core_version: *const c_char,
+    pub register: fn(&mut ProtocolRegistry),
+}
+
+// The declaration only points at static, immutable data.
+unsafe impl Sync for PluginDeclaration {}
+unsafe impl Send for PluginDeclaration {}
+
+/// Declares the plugin entry point. `$register` is a `fn(&mut ProtocolRegistry)` that registers
+/// the plugin's protocols.
+#[macro_export]
+macro_rules! export_plugin {
+    ($register:expr) => {
+        #[doc(hidden)]
+        #[no_mangle]
+        pub static OSHATORI_PLUGIN: $crate::connection::plugin::PluginDeclaration =
+            $crate::connection::plugin::PluginDeclaration {
+                abi_version: $crate::connection::plugin::PLUGIN_ABI_VERSION,
+                core_version: $crate::connection::plugin::CORE_VERSION_NUL.as_ptr()
+                    as *const ::std::ffi::c_char,
+                register: $register,
+            };
+    };
+}
+
+#[derive(Debug)]
+pub enum PluginError {
+    Load { path: PathBuf, reason: String },
+    MissingDeclaration(PathBuf),
+    Incompatible { path: PathBuf, found: String },
+    Io(std::io::Error),
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::Load { path, reason } => {
+                write!(f, "failed to load plugin {}: {}", path.display(), reason)
+            }
+            PluginError::MissingDeclaration(path) => {
+                write!(f, "{} does not export a plugin declaration", path.display())
+            }
+            PluginError::Incompatible { path, found } => write!(
+                f,
+                "plugin {} was built for {}, expected ABI {} / oshatori {}",
+                path.display(),
+                found,
+                PLUGIN_ABI_VERSION,
+                CORE_VERSION
+            ),
+            PluginError::Io(e) => write!(f, "plugin discovery failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+impl From<std::io::Error> for PluginError {
+    fn from(e: std::io::Error) -> Self {
+        PluginError::Io(e)
+    }
+}
+
+/// Loads plugin libraries into a `ProtocolRegistry`. Libraries stay mapped for the rest of the
+/// process, since connections created from them can outlive the loader.
+#[derive(Debug, Default)]
+pub struct PluginLoader {
+    loaded: Vec<PathBuf>,
+}
+
+impl PluginLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn loaded(&self) -> &[PathBuf] {
+        &self.loaded
+    }
+
+    /// Loads the library at `path` and lets it register its protocols, returning the names it
+    /// added.
+    ///
+    /// # Safety
+    ///
+    /// Loading a library runs its initialisers and trusts its exported declaration. The library
+    /// must have been built with `export_plugin!` against this oshatori version and compiler.
+    pub unsafe fn load(
+        &mut self,
+        path: impl AsRef<Path>,
+        registry: &mut ProtocolRegistry,
+    ) -> Result<Vec<String>, PluginError> {
+        let path = path.as_ref().to_path_buf();
+        let library = Library::new(&path).map_err(|e| PluginError::Load {
+            path: path.clone(),
+            reason: e.to_string(),
+        })?;
+        let declaration = *library
+            .get::<*const PluginDeclaration>(DECLARATION_SYMBOL)
+            .map_err(|_| PluginError::MissingDeclaration(path.clone()))?;
+        let declaration = &*declaration;
+        let core_version = if declaration.core_version.is_null() {
+            "unknown".into()
+        } else {
+            CStr::from_ptr(declaration.core_version).to_string_lossy()
+        };
+        if declaration.abi_version != PLUGIN_ABI_VERSION || core_version != CORE_VERSION {
+            return Err(PluginError::Incompatible {
+                path,
+                found: format!(
+                    "ABI {} / oshatori {}",
+                    declaration.abi_version, core_version
+                ),
+            });
+        }
+
+        let before = registry.protocols();
+        (declaration.register)(registry);
+        std::mem::forget(library);
+        self.loaded.push(path);
+        Ok(registry
+            .protocols()
+            .into_iter()
+            .filter(|name| !before.contains(name))
+            .collect())
+    }
+
+    /// Loads every shared library in `dir`, in file name order.
+    ///
+    /// # Safety
+    ///
+    /// Every library in `dir` must satisfy the requirements of `load`.
+    pub unsafe fn load_dir(
+        &mut self,
+        dir: impl AsRef<Path>,
+        registry: &mut ProtocolRegistry,
+    ) -> Result<Vec<String>, PluginError> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path.extension().and_then(|ext| ext.to_str())
+                        == Some(std::env::consts::DLL_EXTENSION)
+            })
+            .collect();
+        paths.sort();
+
+        let mut added = Vec::new();
+        for path in paths {
+            added.extend(self.load(path, registry)?);
+        }
+        Ok(added)
+    }
+}
@@ -0,0 +1,513 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use reqwest::header::{ACCEPT, CACHE_CONTROL};
+use tokio::sync::mpsc;
+
+use crate::{
+    connection::ConnectionStatus, AuthField, Capabilities, Channel, Connection, FieldValue,
+    Message, Protocol,
+};
+
+use super::{
+    http::http_client, preflight::validate_auth, ChannelEvent, ChatEvent, ConnectionError,
+    ConnectionEvent, ConnectionOptions, PreflightReport, Scope, StatusEvent, Supervisor, UserEvent,
+};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// How many emitted message ids are remembered for deduplication.
+const SEEN_CAPACITY: usize = 10_000;
+
+/// What a [`PollSource`] needs to make requests: the shared HTTP client, built from the
+/// connection's options, and the auth fields set on the connection.
+#[derive(Clone)]
+pub struct PollContext {
+    pub http: reqwest::Client,
+    pub auth: Vec<AuthField>,
+}
+
+impl PollContext {
+    /// The value of a text, password or key field, if it is set and not empty.
+    pub fn field(&self, name: &str) -> Option<String> {
+        field_text(&self.auth, name)
+    }
+}
+
+/// The account a source logged into.
+#[derive(Clone, Debug, Default)]
+pub struct PollSession {
+    pub user_id: Option<String>,
+    pub channels: Vec<Channel>,
+}
+
+/// One batch of updates. Messages whose id was already emitted are dropped, so a page may
+/// overlap the previous one. `cursor`, when set, is passed to the next fetch.
+#[derive(Clone, Debug, Default)]
+pub struct PollPage {
+    pub messages: Vec<(Scope, Message)>,
+    pub events: Vec<ConnectionEvent>,
+    pub cursor: Option<String>,
+}
+
+/// A dispatched server-sent event.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+    pub id: Option<String>,
+}
+
+/// A REST API seen by [`PollingConnection`]. Only `open` and `fetch` are required; an API with
+/// a server-sent events endpoint also implements `event_stream` and `parse_event`, and is then
+/// only polled to catch up after the stream drops.
+#[async_trait]
+pub trait PollSource: Send + Sync + 'static {
+    fn protocol_spec(&self) -> Protocol;
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            multiple_channels: true,
+            ..Capabilities::default()
+        }
+    }
+
+    /// Checks the credentials and lists the channels to join. Called on every connect.
+    async fn open(&self, context: &PollContext) -> Result<PollSession, ConnectionError>;
+
+    /// Fetches what happened after `cursor`, or the latest messages without one.
+    async fn fetch(
+        &self,
+        context: &PollContext,
+        cursor: Option<&str>,
+    ) -> Result<PollPage, ConnectionError>;
+
+    /// The request that opens the event stream. `Last-Event-ID` is added on reconnects.
+    fn event_stream(&self, _context: &PollContext) -> Option<reqwest::RequestBuilder> {
+        None
+    }
+
+    /// Maps one server-sent event. The event id becomes the cursor unless the page sets one.
+    fn parse_event(&self, _event: &SseEvent) -> PollPage {
+        PollPage::default()
+    }
+
+    async fn send(
+        &self,
+        _context: &PollContext,
+        _event: ConnectionEvent,
+    ) -> Result<(), ConnectionError> {
+        Err(ConnectionError::Unsupported(
+            "Sending is not implemented for this source".to_string(),
+        ))
+    }
+
+    async fn fetch_history(
+        &self,
+        _context: &PollContext,
+        _channel_id: &str,
+        _before: Option<String>,
+        _limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        Err(ConnectionError::Unsupported(
+            "History is not implemented for this source".to_string(),
+        ))
+    }
+}
+
+/// Splits a `text/event-stream` body into events, across chunk boundaries.
+#[derive(Debug, Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+    pending: SseEvent,
+    has_data: bool,
+}
+
+impl SseParser {
+    fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if std::mem::take(&mut self.has_data) {
+                    events.push(std::mem::take(&mut self.pending));
+                } else {
+                    self.pending = SseEvent::default();
+                }
+                continue;
+            }
+            let (name, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match name {
+                "data" => {
+                    if self.has_data {
+                        self.pending.data.push('\n');
+                    }
+                    self.pending.data.push_str(value);
+                    self.has_data = true;
+                }
+                "event" => self.pending.event = Some(value.to_string()),
+                "id" => self.pending.id = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+/// Ids of emitted messages, forgetting the oldest past [`SEEN_CAPACITY`].
+#[derive(Debug, Default)]
+struct SeenIds {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl SeenIds {
+    /// Records `id`, returning false if it was already there.
+    fn insert(&mut self, id: &str) -> bool {
+        if !self.ids.insert(id.to_string()) {
+            return false;
+        }
+        self.order.push_back(id.to_string());
+        if self.order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[derive(Clone)]
+struct PollMapper {
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    seen: Arc<StdMutex<SeenIds>>,
+    cursor: Arc<StdMutex<Option<String>>>,
+}
+
+impl PollMapper {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn cursor(&self) -> Option<String> {
+        self.cursor.lock().ok().and_then(|cursor| cursor.clone())
+    }
+
+    fn apply(&self, page: PollPage) {
+        for (scope, message) in page.messages {
+            let fresh = match &message.id {
+                Some(id) => self.seen.lock().is_ok_and(|mut seen| seen.insert(id)),
+                None => true,
+            };
+            if fresh {
+                self.emit(ConnectionEvent::Chat {
+                    event: ChatEvent::New { scope, message },
+                });
+            }
+        }
+        for event in page.events {
+            self.emit(event);
+        }
+        if let Some(cursor) = page.cursor {
+            if let Ok(mut current) = self.cursor.lock() {
+                *current = Some(cursor);
+            }
+        }
+    }
+
+    async fn poll<S: PollSource>(&self, source: &S, context: &PollContext) {
+        let cursor = self.cursor();
+        match source.fetch(context, cursor.as_deref()).await {
+            Ok(page) => self.apply(page),
+            Err(e) => self.emit(ConnectionEvent::Status {
+                event: StatusEvent::Error {
+                    message: e.to_string(),
+                },
+            }),
+        }
+    }
+
+    /// Reads the event stream until it ends or fails.
+    async fn stream<S: PollSource>(
+        &self,
+        source: &S,
+        request: reqwest::RequestBuilder,
+    ) -> Result<(), ConnectionError> {
+        let network = |e: reqwest::Error| ConnectionError::Network(e.to_string());
+        let mut request = request
+            .header(ACCEPT, "text/event-stream")
+            .header(CACHE_CONTROL, "no-cache");
+        if let Some(cursor) = self.cursor() {
+            request = request.header("Last-Event-ID", cursor);
+        }
+        let mut response = request.send().await.map_err(network)?;
+        if !response.status().is_success() {
+            return Err(ConnectionError::Network(format!(
+                "Event stream answered {}",
+                response.status()
+            )));
+        }
+        let mut parser = SseParser::default();
+        while let Some(chunk) = response.chunk().await.map_err(network)? {
+            for event in parser.feed(&chunk) {
+                let mut page = source.parse_event(&event);
+                if page.cursor.is_none() {
+                    page.cursor = event.id.clone().filter(|id| !id.is_empty());
+                }
+                self.apply(page);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A connection to a REST API described by a [`PollSource`]. It fetches every poll interval,
+/// or follows the source's event stream when it has one, carrying the cursor from page to page
+/// and emitting each message id only once.
+pub struct PollingConnection<S: PollSource> {
+    source: Arc<S>,
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    poll_interval: Duration,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    context: Option<PollContext>,
+    mapper: PollMapper,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl<S: PollSource> PollingConnection<S> {
+    pub fn new(source: S) -> Self {
+        Self::with_options(source, ConnectionOptions::default())
+    }
+
+    pub fn with_options(source: S, options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        PollingConnection {
+            source: Arc::new(source),
+            auth: Vec::new(),
+            options,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            mapper: PollMapper {
+                event_tx: event_tx.clone(),
+                seen: Arc::new(StdMutex::new(SeenIds::default())),
+                cursor: Arc::new(StdMutex::new(None)),
+            },
+            event_tx,
+            event_rx: Some(event_rx),
+            context: None,
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    /// How often to fetch, thirty seconds by default. With an event stream, this is the delay
+    /// before catching up and reconnecting after the stream drops.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Starts from a cursor saved in an earlier session instead of the latest messages.
+    pub fn resume_from(self, cursor: impl Into<String>) -> Self {
+        if let Ok(mut current) = self.mapper.cursor.lock() {
+            *current = Some(cursor.into());
+        }
+        self
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// The cursor after the last page, to save for [`PollingConnection::resume_from`].
+    pub fn cursor(&self) -> Option<String> {
+        self.mapper.cursor()
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn fail_auth(&self, reason: String) -> ConnectionError {
+        self.set_status(ConnectionStatus::AuthFailed {
+            reason: reason.clone(),
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed {
+                reason: reason.clone(),
+            },
+        });
+        ConnectionError::Auth(reason)
+    }
+
+    fn context(&self) -> Result<&PollContext, ConnectionError> {
+        self.context.as_ref().ok_or(ConnectionError::Closed)
+    }
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+#[async_trait]
+impl<S: PollSource> Connection for PollingConnection<S> {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "polling.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.context = None;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+        let context = PollContext {
+            http: http_client(&self.options)?,
+            auth: self.auth.clone(),
+        };
+        let opened = match self.options.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.source.open(&context))
+                .await
+                .map_err(|_| ConnectionError::Timeout)
+                .and_then(|session| session),
+            None => self.source.open(&context).await,
+        };
+        let session = match opened {
+            Ok(session) => session,
+            Err(ConnectionError::Auth(reason)) => return Err(self.fail_auth(reason)),
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected { artifact: None },
+        });
+        if let Some(user_id) = session.user_id {
+            let _ = self.event_tx.send(ConnectionEvent::User {
+                event: UserEvent::Identify { user_id },
+            });
+        }
+        for channel in session.channels {
+            let channel_id = channel.id.clone();
+            let _ = self.event_tx.send(ConnectionEvent::Channel {
+                event: ChannelEvent::New { channel },
+            });
+            let _ = self.event_tx.send(ConnectionEvent::Channel {
+                event: ChannelEvent::Join { channel_id },
+            });
+        }
+        self.mapper.poll(self.source.as_ref(), &context).await;
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Synced,
+        });
+        self.context = Some(context.clone());
+
+        let source = self.source.clone();
+        let mapper = self.mapper.clone();
+        let poll_interval = self.poll_interval;
+        self.tasks
+            .spawn_essential("poll", self.status.clone(), async move {
+                let mut poll = tokio::time::interval(poll_interval);
+                poll.tick().await;
+                loop {
+                    match source.event_stream(&context) {
+                        Some(request) => {
+                            if let Err(e) = mapper.stream(source.as_ref(), request).await {
+                                mapper.emit(ConnectionEvent::Status {
+                                    event: StatusEvent::Error {
+                                        message: e.to_string(),
+                                    },
+                                });
+                            }
+                            tokio::time::sleep(poll_interval).await;
+                            mapper.poll(source.as_ref(), &context).await;
+                        }
+                        None => {
+                            poll.tick().await;
+                            mapper.poll(source.as_ref(), &context).await;
+                        }
+                    }
+                }
+            });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.context = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        self.source.send(self.context()?, event).await
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        self.source
+            .fetch_history(self.context()?, channel_id, before, limit)
+            .await
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        self.source.protocol_spec()
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.source.capabilities()
+    }
+
+    async fn preflight(&self, _check_reachability: bool) -> PreflightReport {
+        validate_auth(&self.source.protocol_spec(), &self.auth)
+    }
+}
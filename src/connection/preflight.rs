@@ -0,0 +1,136 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+
+use crate::{AuthField, FieldValue, Protocol};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum PreflightSeverity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PreflightIssue {
+    pub field: Option<String>,
+    pub severity: PreflightSeverity,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Reachability {
+    #[default]
+    NotChecked,
+    Reachable {
+        latency_ms: u64,
+    },
+    Unreachable {
+        reason: String,
+    },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PreflightReport {
+    pub issues: Vec<PreflightIssue>,
+    pub reachability: Reachability,
+}
+
+impl PreflightReport {
+    pub fn is_ok(&self) -> bool {
+        !self
+            .issues
+            .iter()
+            .any(|issue| issue.severity == PreflightSeverity::Error)
+            && !matches!(self.reachability, Reachability::Unreachable { .. })
+    }
+
+    pub fn issues_for(&self, field: &str) -> Vec<&PreflightIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.field.as_deref() == Some(field))
+            .collect()
+    }
+
+    pub fn error(&mut self, field: Option<&str>, message: impl Into<String>) {
+        self.push(field, PreflightSeverity::Error, message.into());
+    }
+
+    pub fn warning(&mut self, field: Option<&str>, message: impl Into<String>) {
+        self.push(field, PreflightSeverity::Warning, message.into());
+    }
+
+    fn push(&mut self, field: Option<&str>, severity: PreflightSeverity, message: String) {
+        self.issues.push(PreflightIssue {
+            field: field.map(str::to_string),
+            severity,
+            message,
+        });
+    }
+}
+
+pub fn validate_auth(spec: &Protocol, auth: &[AuthField]) -> PreflightReport {
+    let mut report = PreflightReport::default();
+    if let Some(expected) = &spec.auth {
+        check_fields(&mut report, expected, auth);
+    }
+    report
+}
+
+fn check_fields(report: &mut PreflightReport, expected: &[AuthField], provided: &[AuthField]) {
+    for field in expected {
+        let given = provided.iter().find(|f| f.name == field.name);
+        match (&field.value, given.map(|f| &f.value)) {
+            (FieldValue::Group(expected), Some(FieldValue::Group(provided))) => {
+                check_fields(report, expected, provided);
+            }
+            (_, value) if field.required && !value.is_some_and(has_value) => {
+                let name = field.display.as_deref().unwrap_or(&field.name);
+                report.error(Some(&field.name), format!("{} is required", name));
+            }
+            (_, Some(FieldValue::File(Some(path))))
+                if !path.trim().is_empty() && !Path::new(path).is_file() =>
+            {
+                report.error(Some(&field.name), format!("No such file: {}", path));
+            }
+            _ => {}
+        }
+    }
+    for field in provided {
+        if !expected.iter().any(|f| f.name == field.name) {
+            report.warning(Some(&field.name), "Unknown field");
+        }
+    }
+}
+
+fn has_value(value: &FieldValue) -> bool {
+    match value {
+        FieldValue::Text(value)
+        | FieldValue::Password(value)
+        | FieldValue::Key(value)
+        | FieldValue::File(value) => value.as_deref().is_some_and(|v| !v.trim().is_empty()),
+        FieldValue::Group(fields) => !fields.is_empty(),
+    }
+}
+
+pub async fn probe_reachability(host: &str, port: u16, timeout: Duration) -> Reachability {
+    let started = Instant::now();
+    match tokio::time::timeout(timeout, TcpStream::connect((host, port))).await {
+        Ok(Ok(_)) => Reachability::Reachable {
+            latency_ms: started.elapsed().as_millis().min(u128::from(u64::MAX)) as u64,
+        },
+        Ok(Err(e)) => Reachability::Unreachable {
+            reason: e.to_string(),
+        },
+        Err(_) => Reachability::Unreachable {
+            reason: format!("Timed out connecting to {}:{}", host, port),
+        },
+    }
+}
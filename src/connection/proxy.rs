@@ -0,0 +1,221 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    connection::ConnectionStatus, utils::ids::IdNormalizer, AuthField, Capabilities, Channel,
+    CommandSpec, Connection, Message, MessageFragment, Profile, Protocol,
+};
+
+use super::{
+    ConnectionError, ConnectionEvent, PreflightReport, SendHandle, SendOutcome, StatusEvent,
+};
+
+#[derive(Clone, Debug)]
+pub enum Intercept {
+    Pass(ConnectionEvent),
+    Delay(Duration, ConnectionEvent),
+    Drop,
+    Fail(ConnectionError),
+}
+
+type Hook = Arc<dyn Fn(ConnectionEvent) -> Intercept + Send + Sync>;
+
+pub struct ProxyConnection<C: Connection + 'static> {
+    inner: C,
+    inbound: Option<Hook>,
+    outbound: Option<Hook>,
+    inner_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl<C: Connection + 'static> ProxyConnection<C> {
+    pub fn new(mut inner: C) -> Self {
+        let inner_rx = inner.subscribe();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        ProxyConnection {
+            inner,
+            inbound: None,
+            outbound: None,
+            inner_rx: Some(inner_rx),
+            event_tx,
+            event_rx: Some(event_rx),
+            task: None,
+        }
+    }
+
+    pub fn on_inbound(
+        mut self,
+        hook: impl Fn(ConnectionEvent) -> Intercept + Send + Sync + 'static,
+    ) -> Self {
+        self.inbound = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn on_outbound(
+        mut self,
+        hook: impl Fn(ConnectionEvent) -> Intercept + Send + Sync + 'static,
+    ) -> Self {
+        self.outbound = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    fn spawn_forwarder(&mut self) {
+        let Some(mut rx) = self.inner_rx.take() else {
+            return;
+        };
+        let tx = self.event_tx.clone();
+        let hook = self.inbound.clone();
+        self.task = Some(tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let intercept = match &hook {
+                    Some(hook) => hook(event),
+                    None => Intercept::Pass(event),
+                };
+                let event = match intercept {
+                    Intercept::Pass(event) => event,
+                    Intercept::Delay(delay, event) => {
+                        tokio::time::sleep(delay).await;
+                        event
+                    }
+                    Intercept::Drop => continue,
+                    Intercept::Fail(e) => ConnectionEvent::Status {
+                        event: StatusEvent::Error {
+                            message: e.to_string(),
+                        },
+                    },
+                };
+                let _ = tx.send(event);
+            }
+        }));
+    }
+
+    async fn intercept_outbound(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<Option<ConnectionEvent>, ConnectionError> {
+        self.spawn_forwarder();
+        let intercept = match &self.outbound {
+            Some(hook) => hook(event),
+            None => Intercept::Pass(event),
+        };
+        match intercept {
+            Intercept::Pass(event) => Ok(Some(event)),
+            Intercept::Delay(delay, event) => {
+                tokio::time::sleep(delay).await;
+                Ok(Some(event))
+            }
+            Intercept::Drop => Ok(None),
+            Intercept::Fail(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait]
+impl<C: Connection + 'static> Connection for ProxyConnection<C> {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.inner.set_auth(auth)
+    }
+
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        self.spawn_forwarder();
+        self.inner.connect().await
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.inner.disconnect().await
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        match self.intercept_outbound(event).await? {
+            Some(event) => self.inner.send(event).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        match self.intercept_outbound(event).await? {
+            Some(event) => self.inner.send_tracked(event).await,
+            None => Ok(SendHandle::completed(SendOutcome::Failed(
+                "Dropped by proxy".to_string(),
+            ))),
+        }
+    }
+
+    async fn fetch_profile(&mut self, user_id: &str) -> Result<Profile, ConnectionError> {
+        self.inner.fetch_profile(user_id).await
+    }
+
+    async fn search_users(&mut self, query: &str) -> Result<Vec<Profile>, ConnectionError> {
+        self.inner.search_users(query).await
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        self.inner.fetch_history(channel_id, before, limit).await
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        self.inner.open_direct(user_id).await
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        self.inner.protocol_spec()
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.inner.status()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn commands(&self) -> Vec<CommandSpec> {
+        self.inner.commands()
+    }
+
+    fn id_normalizer(&self) -> Arc<dyn IdNormalizer> {
+        self.inner.id_normalizer()
+    }
+
+    fn render_text(&self, content: &[MessageFragment]) -> String {
+        self.inner.render_text(content)
+    }
+
+    async fn preflight(&self, check_reachability: bool) -> PreflightReport {
+        self.inner.preflight(check_reachability).await
+    }
+}
+
+impl<C: Connection + 'static> Drop for ProxyConnection<C> {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
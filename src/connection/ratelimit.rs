@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    connection::ConnectionStatus, utils::ids::IdNormalizer, AuthField, Capabilities, Channel,
+    CommandSpec, Connection, Message, MessageFragment, Profile, Protocol,
+};
+
+use super::{ConnectionError, ConnectionEvent, PreflightReport, SendHandle, StatusEvent};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ThrottleMode {
+    #[default]
+    Queue,
+    Reject,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+    pub mode: ThrottleMode,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            burst: 5,
+            per_second: 1.0,
+            mode: ThrottleMode::Queue,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitPolicy {
+    pub default: RateLimit,
+    pub by_protocol: HashMap<String, RateLimit>,
+}
+
+impl RateLimitPolicy {
+    pub fn limit_for(&self, protocol_name: &str) -> RateLimit {
+        self.by_protocol
+            .get(protocol_name)
+            .cloned()
+            .unwrap_or_else(|| self.default.clone())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            tokens: f64::from(limit.burst),
+            limit,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    pub(crate) fn take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.per_second).min(f64::from(self.limit.burst));
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        if self.limit.per_second <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) / self.limit.per_second,
+        ))
+    }
+}
+
+pub struct RateLimitedConnection<C: Connection + 'static> {
+    inner: C,
+    bucket: TokenBucket,
+    inner_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl<C: Connection + 'static> RateLimitedConnection<C> {
+    pub fn new(mut inner: C, policy: &RateLimitPolicy) -> Self {
+        let limit = policy.limit_for(&inner.protocol_spec().name);
+        let inner_rx = inner.subscribe();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        RateLimitedConnection {
+            inner,
+            bucket: TokenBucket::new(limit),
+            inner_rx: Some(inner_rx),
+            event_tx,
+            event_rx: Some(event_rx),
+            task: None,
+        }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    fn spawn_forwarder(&mut self) {
+        let Some(mut rx) = self.inner_rx.take() else {
+            return;
+        };
+        let tx = self.event_tx.clone();
+        self.task = Some(tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let _ = tx.send(event);
+            }
+        }));
+    }
+
+    async fn acquire(&mut self) -> Result<(), ConnectionError> {
+        self.spawn_forwarder();
+        loop {
+            let Err(wait) = self.bucket.take() else {
+                return Ok(());
+            };
+            let _ = self.event_tx.send(ConnectionEvent::Status {
+                event: StatusEvent::Throttled {
+                    retry_after_ms: wait.as_millis().min(u128::from(u64::MAX)) as u64,
+                },
+            });
+            if self.bucket.limit.mode == ThrottleMode::Reject || wait == Duration::MAX {
+                return Err(ConnectionError::RateLimited(wait));
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[async_trait]
+impl<C: Connection + 'static> Connection for RateLimitedConnection<C> {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.inner.set_auth(auth)
+    }
+
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        self.spawn_forwarder();
+        self.inner.connect().await
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.inner.disconnect().await
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        self.acquire().await?;
+        self.inner.send(event).await
+    }
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        self.acquire().await?;
+        self.inner.send_tracked(event).await
+    }
+
+    async fn fetch_profile(&mut self, user_id: &str) -> Result<Profile, ConnectionError> {
+        self.inner.fetch_profile(user_id).await
+    }
+
+    async fn search_users(&mut self, query: &str) -> Result<Vec<Profile>, ConnectionError> {
+        self.inner.search_users(query).await
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        self.inner.fetch_history(channel_id, before, limit).await
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        self.inner.open_direct(user_id).await
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        self.inner.protocol_spec()
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.inner.status()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn commands(&self) -> Vec<CommandSpec> {
+        self.inner.commands()
+    }
+
+    fn id_normalizer(&self) -> Arc<dyn IdNormalizer> {
+        self.inner.id_normalizer()
+    }
+
+    fn render_text(&self, content: &[MessageFragment]) -> String {
+        self.inner.render_text(content)
+    }
+
+    async fn preflight(&self, check_reachability: bool) -> PreflightReport {
+        self.inner.preflight(check_reachability).await
+    }
+}
+
+impl<C: Connection + 'static> Drop for RateLimitedConnection<C> {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
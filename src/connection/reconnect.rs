@@ -0,0 +1,515 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::{
+    sync::{mpsc, Mutex},
+    task::JoinHandle,
+};
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{compose::plain_text, ids::IdNormalizer, trace::event},
+    AuthField, Capabilities, Channel, CommandSpec, Connection, Message, MessageFragment, Profile,
+    Protocol,
+};
+
+use super::{
+    ChannelEvent, ConnectionError, ConnectionEvent, PreflightReport, Scope, SendHandle, StatusEvent,
+};
+
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    pub jitter: f64,
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.initial_delay.as_secs_f64() * self.multiplier.powi(attempt as i32);
+        let base = base.min(self.max_delay.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 - jitter + 2.0 * jitter * random_unit();
+        Duration::from_secs_f64((base * factor).max(0.0))
+    }
+}
+
+/// State the wrapper shares with its forwarder and reconnect tasks.
+struct Shared {
+    active: AtomicBool,
+    attempts: AtomicU32,
+    status: StdMutex<ConnectionStatus>,
+    /// Auth fields handed to `set_auth` while the inner connection was busy.
+    pending_auth: StdMutex<Option<Vec<AuthField>>>,
+    /// The inner connection's capabilities as last read, answered while it is busy.
+    capabilities: StdMutex<Capabilities>,
+}
+
+impl Shared {
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    /// Mirrors the inner connection's lifecycle events into the cached status, so `status()`
+    /// never has to wait on the inner lock.
+    fn track_status(&self, event: &StatusEvent) {
+        let status = match event {
+            StatusEvent::Connecting => ConnectionStatus::Connecting,
+            StatusEvent::Connected { .. } => ConnectionStatus::Connected,
+            StatusEvent::Disconnected { .. } => ConnectionStatus::Disconnected,
+            StatusEvent::Reconnecting { attempt } => {
+                ConnectionStatus::Reconnecting { attempt: *attempt }
+            }
+            StatusEvent::AuthFailed { reason } => ConnectionStatus::AuthFailed {
+                reason: reason.clone(),
+            },
+            _ => return,
+        };
+        self.set_status(status);
+    }
+
+    fn remember_capabilities(&self, capabilities: Capabilities) -> Capabilities {
+        if let Ok(mut current) = self.capabilities.lock() {
+            *current = capabilities.clone();
+        }
+        capabilities
+    }
+
+    /// Hands queued auth fields to the inner connection before it connects.
+    fn apply_pending_auth<C: Connection>(&self, inner: &mut C) -> Result<(), ConnectionError> {
+        match self
+            .pending_auth
+            .lock()
+            .ok()
+            .and_then(|mut auth| auth.take())
+        {
+            Some(auth) => inner.set_auth(auth),
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct ReconnectingConnection<C: Connection + 'static> {
+    inner: Arc<Mutex<C>>,
+    inner_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    protocol: Protocol,
+    commands: Vec<CommandSpec>,
+    ids: Arc<dyn IdNormalizer>,
+    policy: ReconnectPolicy,
+    shared: Arc<Shared>,
+    retry: Arc<StdMutex<Option<JoinHandle<()>>>>,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl<C: Connection + 'static> ReconnectingConnection<C> {
+    pub fn new(mut inner: C, policy: ReconnectPolicy) -> Self {
+        let inner_rx = inner.subscribe();
+        let protocol = inner.protocol_spec();
+        let capabilities = inner.capabilities();
+        let commands = inner.commands();
+        let ids = inner.id_normalizer();
+        let status = inner.status();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        ReconnectingConnection {
+            inner: Arc::new(Mutex::new(inner)),
+            inner_rx: Some(inner_rx),
+            protocol,
+            commands,
+            ids,
+            policy,
+            shared: Arc::new(Shared {
+                active: AtomicBool::new(false),
+                attempts: AtomicU32::new(0),
+                status: StdMutex::new(status),
+                pending_auth: StdMutex::new(None),
+                capabilities: StdMutex::new(capabilities),
+            }),
+            retry: Arc::new(StdMutex::new(None)),
+            event_tx,
+            event_rx: Some(event_rx),
+            task: None,
+        }
+    }
+
+    pub fn inner(&self) -> Arc<Mutex<C>> {
+        self.inner.clone()
+    }
+
+    fn spawn_forwarder(&mut self) {
+        let Some(mut rx) = self.inner_rx.take() else {
+            return;
+        };
+        let inner = self.inner.clone();
+        let policy = self.policy.clone();
+        let shared = self.shared.clone();
+        let retry = self.retry.clone();
+        let tx = self.event_tx.clone();
+        self.task = Some(tokio::spawn(async move {
+            let mut hold_until = None;
+            let mut refused = false;
+            while let Some(event) = rx.recv().await {
+                if let ConnectionEvent::Status { event } = &event {
+                    shared.track_status(event);
+                }
+                let dropped = match &event {
+                    ConnectionEvent::Status {
+                        event: StatusEvent::Disconnected { .. },
+                    } => true,
+                    ConnectionEvent::Status {
+                        event: StatusEvent::AuthFailed { .. },
+                    }
+                    | ConnectionEvent::Channel {
+                        event:
+                            ChannelEvent::Kick {
+                                scope: Scope::Global,
+                                ban: true,
+                                ..
+                            },
+                    } => {
+                        refused = true;
+                        false
+                    }
+                    ConnectionEvent::Status {
+                        event:
+                            StatusEvent::Maintenance {
+                                scheduled_at,
+                                until,
+                                ..
+                            },
+                    } => {
+                        hold_until = Some(maintenance_end(&policy, *scheduled_at, *until));
+                        false
+                    }
+                    ConnectionEvent::Status {
+                        event: StatusEvent::Connected { .. },
+                    } => {
+                        hold_until = None;
+                        refused = false;
+                        shared.attempts.store(0, Ordering::SeqCst);
+                        false
+                    }
+                    _ => false,
+                };
+                let _ = tx.send(event);
+                if !dropped || !shared.active.load(Ordering::SeqCst) {
+                    continue;
+                }
+                if refused {
+                    event!(
+                        info,
+                        "not reconnecting after the server refused the session"
+                    );
+                    shared.active.store(false, Ordering::SeqCst);
+                    continue;
+                }
+                let mut retry = retry.lock().unwrap();
+                if retry.as_ref().is_some_and(|task| !task.is_finished()) {
+                    continue;
+                }
+                *retry = Some(tokio::spawn(reconnect(
+                    inner.clone(),
+                    policy.clone(),
+                    shared.clone(),
+                    tx.clone(),
+                    hold_until.take(),
+                )));
+            }
+        }));
+    }
+}
+
+/// When a maintenance window ends: its announced end, or `max_delay` after it starts when the
+/// server gave no end.
+fn maintenance_end(
+    policy: &ReconnectPolicy,
+    scheduled_at: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+) -> DateTime<Utc> {
+    until.unwrap_or_else(|| {
+        let start = scheduled_at.unwrap_or_else(Utc::now).max(Utc::now());
+        start + chrono::Duration::from_std(policy.max_delay).unwrap_or_default()
+    })
+}
+
+/// Retries `connect` with backoff until it succeeds, the connection is closed by hand, the
+/// attempts run out, the server rejects the credentials or the configuration turns out invalid.
+/// The attempt count carries over between calls and is only reset once the inner connection
+/// reports `Connected`.
+async fn reconnect<C: Connection>(
+    inner: Arc<Mutex<C>>,
+    policy: ReconnectPolicy,
+    shared: Arc<Shared>,
+    tx: mpsc::UnboundedSender<ConnectionEvent>,
+    hold_until: Option<DateTime<Utc>>,
+) {
+    if let Some(wait) = hold_until.and_then(|until| (until - Utc::now()).to_std().ok()) {
+        event!(
+            info,
+            "holding reconnect for {:?} of announced maintenance",
+            wait
+        );
+        tokio::time::sleep(wait).await;
+    }
+    loop {
+        let attempt = shared.attempts.load(Ordering::SeqCst);
+        if let Some(max) = policy.max_attempts {
+            if attempt >= max {
+                let _ = tx.send(ConnectionEvent::Status {
+                    event: StatusEvent::Error {
+                        message: format!("Gave up reconnecting after {} attempts", attempt),
+                    },
+                });
+                shared.active.store(false, Ordering::SeqCst);
+                return;
+            }
+        }
+
+        tokio::time::sleep(policy.delay(attempt)).await;
+        if !shared.active.load(Ordering::SeqCst) {
+            return;
+        }
+        let attempt = shared.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+        shared.set_status(ConnectionStatus::Reconnecting { attempt });
+        let _ = tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Reconnecting { attempt },
+        });
+
+        let mut inner = inner.lock().await;
+        let result = match shared.apply_pending_auth(&mut *inner) {
+            Ok(()) => inner.connect().await,
+            Err(e) => Err(e),
+        };
+        shared.remember_capabilities(inner.capabilities());
+        drop(inner);
+        match result {
+            Ok(()) => return,
+            Err(ConnectionError::Auth(reason)) => {
+                event!(
+                    warn,
+                    "giving up reconnecting, credentials rejected: {}",
+                    reason
+                );
+                shared.set_status(ConnectionStatus::AuthFailed {
+                    reason: reason.clone(),
+                });
+                shared.active.store(false, Ordering::SeqCst);
+                let _ = tx.send(ConnectionEvent::Status {
+                    event: StatusEvent::AuthFailed { reason },
+                });
+                return;
+            }
+            Err(ConnectionError::Config(reason)) => {
+                event!(
+                    warn,
+                    "giving up reconnecting, invalid configuration: {}",
+                    reason
+                );
+                shared.set_status(ConnectionStatus::Disconnected);
+                shared.active.store(false, Ordering::SeqCst);
+                let _ = tx.send(ConnectionEvent::Status {
+                    event: StatusEvent::Error {
+                        message: format!("Gave up reconnecting: {}", reason),
+                    },
+                });
+                return;
+            }
+            Err(e) => {
+                event!(warn, "reconnect attempt {} failed: {}", attempt, e);
+                let _ = tx.send(ConnectionEvent::Status {
+                    event: StatusEvent::Error {
+                        message: format!("Reconnect attempt {} failed: {}", attempt, e),
+                    },
+                });
+            }
+        }
+    }
+}
+
+fn random_unit() -> f64 {
+    let mut bytes = [0; 8];
+    if getrandom::getrandom(&mut bytes).is_err() {
+        return 0.5;
+    }
+    (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[async_trait]
+impl<C: Connection + 'static> Connection for ReconnectingConnection<C> {
+    /// Applies the fields straight away when the inner connection is idle, and otherwise queues
+    /// them for the next connect or reconnect attempt.
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        match self.inner.try_lock() {
+            Ok(mut inner) => {
+                if let Ok(mut pending) = self.shared.pending_auth.lock() {
+                    pending.take();
+                }
+                inner.set_auth(auth)
+            }
+            Err(_) => {
+                if let Ok(mut pending) = self.shared.pending_auth.lock() {
+                    *pending = Some(auth);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        self.spawn_forwarder();
+        self.shared.set_status(ConnectionStatus::Connecting);
+        let mut inner = self.inner.lock().await;
+        let result = match self.shared.apply_pending_auth(&mut *inner) {
+            Ok(()) => inner.connect().await,
+            Err(e) => Err(e),
+        };
+        self.shared.remember_capabilities(inner.capabilities());
+        match result {
+            Ok(()) => self.shared.set_status(inner.status()),
+            Err(ConnectionError::Auth(reason)) => {
+                self.shared.set_status(ConnectionStatus::AuthFailed {
+                    reason: reason.clone(),
+                });
+                return Err(ConnectionError::Auth(reason));
+            }
+            Err(e) => {
+                self.shared.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        }
+        self.shared.active.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.shared.active.store(false, Ordering::SeqCst);
+        if let Some(retry) = self.retry.lock().unwrap().take() {
+            retry.abort();
+        }
+        let result = self.inner.lock().await.disconnect().await;
+        self.shared.set_status(ConnectionStatus::Disconnected);
+        result
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        self.inner.lock().await.send(event).await
+    }
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        self.inner.lock().await.send_tracked(event).await
+    }
+
+    async fn fetch_profile(&mut self, user_id: &str) -> Result<Profile, ConnectionError> {
+        self.inner.lock().await.fetch_profile(user_id).await
+    }
+
+    async fn search_users(&mut self, query: &str) -> Result<Vec<Profile>, ConnectionError> {
+        self.inner.lock().await.search_users(query).await
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        self.inner
+            .lock()
+            .await
+            .fetch_history(channel_id, before, limit)
+            .await
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        self.inner.lock().await.open_direct(user_id).await
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        self.protocol.clone()
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.shared
+            .status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or(ConnectionStatus::Disconnected)
+    }
+
+    /// Backends learn some capabilities while connecting, so this asks the inner connection each
+    /// time and only falls back to the last answer while it is busy.
+    fn capabilities(&self) -> Capabilities {
+        match self.inner.try_lock() {
+            Ok(inner) => self.shared.remember_capabilities(inner.capabilities()),
+            Err(_) => self
+                .shared
+                .capabilities
+                .lock()
+                .map(|capabilities| capabilities.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn commands(&self) -> Vec<CommandSpec> {
+        self.commands.clone()
+    }
+
+    fn id_normalizer(&self) -> Arc<dyn IdNormalizer> {
+        self.ids.clone()
+    }
+
+    fn render_text(&self, content: &[MessageFragment]) -> String {
+        self.inner
+            .try_lock()
+            .map(|inner| inner.render_text(content))
+            .unwrap_or_else(|_| plain_text(content))
+    }
+
+    async fn preflight(&self, check_reachability: bool) -> PreflightReport {
+        self.inner.lock().await.preflight(check_reachability).await
+    }
+}
+
+impl<C: Connection + 'static> Drop for ReconnectingConnection<C> {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        if let Some(retry) = self.retry.lock().unwrap().take() {
+            retry.abort();
+        }
+    }
+}
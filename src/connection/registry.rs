@@ -0,0 +1,100 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{Account, Connection};
+
+use super::ConnectionError;
+
+type Factory = Arc<dyn Fn() -> Box<dyn Connection> + Send + Sync>;
+
+/// Constructs connections by protocol name, so stored accounts can be reopened without naming
+/// concrete backend types. Names are matched case-insensitively.
+#[derive(Clone, Default)]
+pub struct ProtocolRegistry {
+    factories: HashMap<String, Factory>,
+}
+
+impl ProtocolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry holding every backend compiled into this build.
+    pub fn with_builtin() -> Self {
+        #[allow(unused_mut)]
+        let mut registry = Self::new();
+        #[cfg(feature = "email")]
+        registry.register("email", || Box::new(super::EmailConnection::new()));
+        #[cfg(feature = "irc")]
+        registry.register("irc", || Box::new(super::IrcConnection::new()));
+        #[cfg(feature = "mastodon")]
+        registry.register("mastodon", || Box::new(super::MastodonConnection::new()));
+        #[cfg(feature = "matrix")]
+        registry.register("matrix", || Box::new(super::MatrixConnection::new()));
+        #[cfg(feature = "mock")]
+        registry.register("mock", || Box::new(super::MockConnection::new()));
+        #[cfg(feature = "minecraft")]
+        registry.register("minecraft", || Box::new(super::MinecraftConnection::new()));
+        #[cfg(feature = "mqtt")]
+        registry.register("mqtt", || Box::new(super::MqttConnection::new()));
+        #[cfg(feature = "mumble")]
+        registry.register("mumble", || Box::new(super::MumbleConnection::new()));
+        #[cfg(feature = "nostr")]
+        registry.register("nostr", || Box::new(super::NostrConnection::new()));
+        #[cfg(feature = "revolt")]
+        registry.register("revolt", || Box::new(super::RevoltConnection::new()));
+        #[cfg(feature = "rocketchat")]
+        registry.register(
+            "rocketchat",
+            || Box::new(super::RocketChatConnection::new()),
+        );
+        #[cfg(feature = "rss")]
+        registry.register("rss", || Box::new(super::RssConnection::new()));
+        #[cfg(feature = "slack")]
+        registry.register("slack", || Box::new(super::SlackConnection::new()));
+        #[cfg(feature = "sockchat")]
+        registry.register("sockchat", || Box::new(super::SockchatConnection::new()));
+        #[cfg(feature = "twitch")]
+        registry.register("twitch", || Box::new(super::TwitchConnection::new()));
+        #[cfg(feature = "websocket")]
+        registry.register("websocket", || Box::new(super::GenericWsConnection::new()));
+        registry
+    }
+
+    /// Registers `factory` under `name`, replacing any factory already registered for it.
+    pub fn register(
+        &mut self,
+        name: &str,
+        factory: impl Fn() -> Box<dyn Connection> + Send + Sync + 'static,
+    ) {
+        self.factories
+            .insert(name.to_lowercase(), Arc::new(factory));
+    }
+
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.factories.remove(&name.to_lowercase()).is_some()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(&name.to_lowercase())
+    }
+
+    pub fn protocols(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.factories.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn create(&self, name: &str) -> Result<Box<dyn Connection>, ConnectionError> {
+        self.factories
+            .get(&name.to_lowercase())
+            .map(|factory| factory())
+            .ok_or_else(|| ConnectionError::Unsupported(format!("Unknown protocol {}", name)))
+    }
+
+    /// Creates a connection for `account.protocol_name` with the account's auth fields applied.
+    pub fn create_for(&self, account: &Account) -> Result<Box<dyn Connection>, ConnectionError> {
+        let mut connection = self.create(&account.protocol_name)?;
+        connection.set_auth(account.auth.clone())?;
+        Ok(connection)
+    }
+}
@@ -0,0 +1,1061 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{SinkExt, StreamExt};
+use regex::Regex;
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use url::Url;
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{compose::plain_text, trace::event},
+    Asset, AssetSource, AuthField, Capabilities, Channel, ChannelType, Connection, FieldValue,
+    Message, MessageFragment, MessageStatus, MessageType, Presence, Profile, Protocol, Reaction,
+    ReactionKey,
+};
+
+use super::{
+    http::http_client, transport::connect_websocket, AssetEvent, ChannelEvent, ChatEvent,
+    ConnectionError, ConnectionEvent, ConnectionOptions, Scope, SendHandle, SendOutcome,
+    StatusEvent, Supervisor, UserEvent,
+};
+
+const DEFAULT_API_URL: &str = "https://api.revolt.chat/";
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+/// User mentions and custom emoji references in message text.
+static TOKENS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"<@([0-9A-HJKMNP-TV-Z]{26})>|:([0-9A-HJKMNP-TV-Z]{26}):").unwrap()
+});
+/// A bare ULID, which is how custom emoji are named in reactions.
+static ULID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[0-9A-HJKMNP-TV-Z]{26}$").unwrap());
+
+/// An authenticated handle on the Revolt REST API.
+#[derive(Clone, Debug)]
+struct RevoltApi {
+    http: reqwest::Client,
+    base: Url,
+    token: String,
+    bot: bool,
+}
+
+impl RevoltApi {
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<Value>,
+    ) -> Result<Value, ConnectionError> {
+        let url = self
+            .base
+            .join(path)
+            .map_err(|e| ConnectionError::Protocol(e.to_string()))?;
+        let header = if self.bot {
+            "x-bot-token"
+        } else {
+            "x-session-token"
+        };
+        let mut request = self
+            .http
+            .request(method, url)
+            .header(header, &self.token)
+            .query(query);
+        if let Some(body) = body {
+            request = request
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string());
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        let status = response.status();
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        let body: Value = serde_json::from_slice(&bytes).unwrap_or_default();
+        if status.is_success() {
+            return Ok(body);
+        }
+
+        let error = body["type"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| status.to_string());
+        Err(match status {
+            StatusCode::UNAUTHORIZED => ConnectionError::Auth(error),
+            StatusCode::TOO_MANY_REQUESTS => ConnectionError::RateLimited(Duration::from_millis(
+                body["retry_after"].as_u64().unwrap_or(1000),
+            )),
+            _ => ConnectionError::Protocol(error),
+        })
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> Result<Value, ConnectionError> {
+        self.request(Method::GET, path, query, None).await
+    }
+}
+
+/// Turns Revolt objects into oshatori types, resolving files against the Autumn file server.
+#[derive(Clone, Debug)]
+struct RevoltMapper {
+    autumn: String,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    names: Arc<StdMutex<HashMap<String, String>>>,
+}
+
+impl RevoltMapper {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn file_url(&self, tag: &str, file: &Value) -> Option<String> {
+        let id = file["_id"].as_str()?;
+        Some(format!(
+            "{}/{}/{}",
+            self.autumn.trim_end_matches('/'),
+            tag,
+            id
+        ))
+    }
+
+    fn profile(&self, user: &Value) -> Profile {
+        let presence = match (
+            user["online"].as_bool(),
+            user["status"]["presence"].as_str(),
+        ) {
+            (Some(false), _) | (_, Some("Invisible")) => Presence::Offline,
+            (_, Some("Idle")) => Presence::Away,
+            (_, Some("Busy")) | (_, Some("Focus")) => Presence::Dnd,
+            _ => Presence::Online,
+        };
+        let mut extra = HashMap::new();
+        if let Some(text) = user["status"]["text"].as_str() {
+            extra.insert("status_text".to_string(), json!(text));
+        }
+        if user["bot"].is_object() {
+            extra.insert("bot".to_string(), json!(true));
+        }
+        Profile {
+            id: user["_id"].as_str().map(str::to_string),
+            username: user["username"].as_str().map(|username| {
+                match user["discriminator"].as_str() {
+                    Some(discriminator) => format!("{}#{}", username, discriminator),
+                    None => username.to_string(),
+                }
+            }),
+            display_name: user["display_name"].as_str().map(str::to_string),
+            picture: self.file_url("avatars", &user["avatar"]),
+            presence: Some(presence),
+            extra,
+            ..Profile::default()
+        }
+    }
+
+    fn remember(&self, profile: &Profile) {
+        let (Some(id), Some(name)) = (
+            &profile.id,
+            profile.display_name.as_ref().or(profile.username.as_ref()),
+        ) else {
+            return;
+        };
+        if let Ok(mut names) = self.names.lock() {
+            names.insert(id.clone(), name.clone());
+        }
+    }
+
+    fn name_of(&self, user_id: &str) -> Option<String> {
+        self.names.lock().ok()?.get(user_id).cloned()
+    }
+
+    fn channel(&self, value: &Value, own_id: &str) -> Option<Channel> {
+        let id = value["_id"].as_str()?.to_string();
+        let mut extra = HashMap::new();
+        if let Some(server) = value["server"].as_str() {
+            extra.insert("server".to_string(), json!(server));
+        }
+        let (name, channel_type) = match value["channel_type"].as_str()? {
+            "TextChannel" => (
+                value["name"].as_str().map(str::to_string),
+                ChannelType::Group,
+            ),
+            "Group" => (
+                value["name"].as_str().map(str::to_string),
+                ChannelType::Group,
+            ),
+            "DirectMessage" => {
+                let peer = value["recipients"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .find(|recipient| *recipient != own_id)?;
+                extra.insert("recipient".to_string(), json!(peer));
+                (
+                    Some(self.name_of(peer).unwrap_or_else(|| peer.to_string())),
+                    ChannelType::Direct,
+                )
+            }
+            _ => return None,
+        };
+        Some(Channel {
+            id,
+            name,
+            channel_type,
+            topic: value["description"]
+                .as_str()
+                .filter(|description| !description.is_empty())
+                .map(str::to_string),
+            extra,
+        })
+    }
+
+    fn emoji(&self, emoji: &Value) -> Option<Asset> {
+        let id = emoji["_id"].as_str()?;
+        let name = emoji["name"].as_str().unwrap_or(id);
+        Some(Asset::Emote {
+            id: Some(id.to_string()),
+            pattern: format!(":(?:{}|{}):", regex::escape(name), regex::escape(id)),
+            src: format!("{}/emojis/{}", self.autumn.trim_end_matches('/'), id),
+            source: AssetSource::Server,
+        })
+    }
+
+    /// Splits message text into mentions, custom emoji references and plain text.
+    fn fragments(&self, text: &str) -> Vec<MessageFragment> {
+        let mut out = Vec::new();
+        let mut last = 0;
+        for caps in TOKENS.captures_iter(text) {
+            let whole = caps.get(0).unwrap();
+            if whole.start() > last {
+                out.push(MessageFragment::Text(text[last..whole.start()].to_string()));
+            }
+            last = whole.end();
+            out.push(match (caps.get(1), caps.get(2)) {
+                (Some(user), _) => MessageFragment::Mention {
+                    user_id: user.as_str().to_string(),
+                    display: self
+                        .name_of(user.as_str())
+                        .unwrap_or_else(|| user.as_str().to_string()),
+                },
+                (_, Some(emoji)) => MessageFragment::AssetId(emoji.as_str().to_string()),
+                _ => continue,
+            });
+        }
+        if last < text.len() {
+            out.push(MessageFragment::Text(text[last..].to_string()));
+        }
+        out
+    }
+
+    /// Maps a message. A masquerade is kept as a `Profile` under `extra["masquerade"]`, so
+    /// clients can show the per-message name and avatar without touching the author's profile.
+    fn message(&self, value: &Value) -> Option<Message> {
+        let id = value["_id"].as_str()?;
+        let mut content = self.fragments(value["content"].as_str().unwrap_or_default());
+        for attachment in value["attachments"].as_array().into_iter().flatten() {
+            let Some(url) = self.file_url("attachments", attachment) else {
+                continue;
+            };
+            let mime = attachment["content_type"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            content.push(match mime.split('/').next() {
+                Some("image") => MessageFragment::Image { url, mime },
+                Some("video") => MessageFragment::Video { url, mime },
+                Some("audio") => MessageFragment::Audio { url, mime },
+                _ => MessageFragment::Url(url),
+            });
+        }
+
+        let mut extra = HashMap::new();
+        let masquerade = &value["masquerade"];
+        if masquerade.is_object() {
+            let profile = Profile {
+                id: value["author"].as_str().map(str::to_string),
+                display_name: masquerade["name"].as_str().map(str::to_string),
+                picture: masquerade["avatar"].as_str().map(str::to_string),
+                ..Profile::default()
+            };
+            extra.insert(
+                "masquerade".to_string(),
+                serde_json::to_value(profile).unwrap_or_default(),
+            );
+        }
+        let reactions = value["reactions"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(emoji, users)| Reaction {
+                key: reaction_key(emoji),
+                user_ids: users
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|user| user.as_str().map(str::to_string))
+                    .collect(),
+            })
+            .collect();
+        Some(Message {
+            id: Some(id.to_string()),
+            sender_id: value["author"].as_str().map(str::to_string),
+            content,
+            timestamp: ulid_time(id).unwrap_or_else(Utc::now),
+            message_type: MessageType::Normal,
+            status: if value["edited"].is_string() {
+                MessageStatus::Edited
+            } else {
+                MessageStatus::Sent
+            },
+            reactions,
+            reply_to: value["replies"][0].as_str().map(str::to_string),
+            thread_id: None,
+            extra,
+        })
+    }
+
+    fn apply_ready(&self, ready: &Value, own_id: &str) {
+        for user in ready["users"].as_array().into_iter().flatten() {
+            let profile = self.profile(user);
+            self.remember(&profile);
+            self.emit(ConnectionEvent::User {
+                event: UserEvent::New {
+                    scope: Scope::Global,
+                    user: profile,
+                },
+            });
+        }
+        for value in ready["channels"].as_array().into_iter().flatten() {
+            if value["active"].as_bool() == Some(false) {
+                continue;
+            }
+            let Some(channel) = self.channel(value, own_id) else {
+                continue;
+            };
+            let channel_id = channel.id.clone();
+            self.emit(ConnectionEvent::Channel {
+                event: ChannelEvent::New { channel },
+            });
+            self.emit(ConnectionEvent::Channel {
+                event: ChannelEvent::Join { channel_id },
+            });
+        }
+        for emoji in ready["emojis"].as_array().into_iter().flatten() {
+            if let Some(asset) = self.emoji(emoji) {
+                self.emit(ConnectionEvent::Asset {
+                    event: AssetEvent::New {
+                        scope: Scope::Global,
+                        asset,
+                    },
+                });
+            }
+        }
+    }
+
+    fn apply(&self, event: &Value, own_id: &str) {
+        let channel_of = |key: &str| Scope::channel(event[key].as_str().unwrap_or_default());
+        let string = |key: &str| event[key].as_str().unwrap_or_default().to_string();
+        match event["type"].as_str().unwrap_or_default() {
+            "Message" => {
+                if let Some(message) = self.message(event) {
+                    self.emit(ConnectionEvent::Chat {
+                        event: ChatEvent::New {
+                            scope: channel_of("channel"),
+                            message,
+                        },
+                    });
+                }
+            }
+            "MessageUpdate" => {
+                let mut update = event["data"].clone();
+                update["_id"] = event["id"].clone();
+                update["author"] = event["data"]["author"].clone();
+                if let Some(mut new_message) = self.message(&update) {
+                    new_message.status = MessageStatus::Edited;
+                    self.emit(ConnectionEvent::Chat {
+                        event: ChatEvent::Update {
+                            scope: channel_of("channel"),
+                            message_id: string("id"),
+                            new_message,
+                        },
+                    });
+                }
+            }
+            "MessageDelete" => self.emit(ConnectionEvent::Chat {
+                event: ChatEvent::Remove {
+                    scope: channel_of("channel"),
+                    message_id: string("id"),
+                },
+            }),
+            "MessageReact" => self.emit(ConnectionEvent::Chat {
+                event: ChatEvent::ReactionAdd {
+                    scope: channel_of("channel_id"),
+                    message_id: string("id"),
+                    user_id: string("user_id"),
+                    key: reaction_key(&string("emoji_id")),
+                },
+            }),
+            "MessageUnreact" => self.emit(ConnectionEvent::Chat {
+                event: ChatEvent::ReactionRemove {
+                    scope: channel_of("channel_id"),
+                    message_id: string("id"),
+                    user_id: string("user_id"),
+                    key: reaction_key(&string("emoji_id")),
+                },
+            }),
+            "ChannelStartTyping" => self.emit(ConnectionEvent::User {
+                event: UserEvent::TypingStart {
+                    channel_id: string("id"),
+                    user_id: string("user"),
+                },
+            }),
+            "ChannelStopTyping" => self.emit(ConnectionEvent::User {
+                event: UserEvent::TypingStop {
+                    channel_id: string("id"),
+                    user_id: string("user"),
+                },
+            }),
+            "ChannelCreate" => {
+                if let Some(channel) = self.channel(event, own_id) {
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::New { channel },
+                    });
+                }
+            }
+            "ChannelUpdate" => {
+                if let Some(description) = event["data"]["description"].as_str() {
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::TopicChanged {
+                            channel_id: string("id"),
+                            topic: Some(description.to_string()),
+                            set_by: None,
+                        },
+                    });
+                }
+            }
+            "ChannelDelete" => self.emit(ConnectionEvent::Channel {
+                event: ChannelEvent::Remove {
+                    channel_id: string("id"),
+                },
+            }),
+            "EmojiCreate" => {
+                if let Some(asset) = self.emoji(event) {
+                    self.emit(ConnectionEvent::Asset {
+                        event: AssetEvent::New {
+                            scope: Scope::Global,
+                            asset,
+                        },
+                    });
+                }
+            }
+            "EmojiDelete" => self.emit(ConnectionEvent::Asset {
+                event: AssetEvent::Remove {
+                    scope: Scope::Global,
+                    asset_id: string("id"),
+                },
+            }),
+            "UserUpdate" => {
+                let mut user = event["data"].clone();
+                user["_id"] = event["id"].clone();
+                if user["display_name"].is_null() && user["username"].is_null() {
+                    return;
+                }
+                let new_user = self.profile(&user);
+                self.remember(&new_user);
+                self.emit(ConnectionEvent::User {
+                    event: UserEvent::Update {
+                        scope: Scope::Global,
+                        user_id: string("id"),
+                        new_user,
+                    },
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Custom emoji reactions are keyed by emoji id, everything else by the unicode emoji.
+fn reaction_key(emoji: &str) -> ReactionKey {
+    if ULID.is_match(emoji) {
+        ReactionKey::AssetId(emoji.to_string())
+    } else {
+        ReactionKey::Emoji(emoji.to_string())
+    }
+}
+
+/// The creation time encoded in a ULID's leading 48 bits.
+fn ulid_time(id: &str) -> Option<DateTime<Utc>> {
+    const ALPHABET: &str = "0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+    let millis = id.get(..10)?.chars().try_fold(0i64, |acc, c| {
+        ALPHABET.find(c).map(|digit| acc * 32 + digit as i64)
+    })?;
+    DateTime::from_timestamp_millis(millis)
+}
+
+/// Renders fragments to Revolt's message syntax.
+fn render(content: &[MessageFragment]) -> String {
+    content
+        .iter()
+        .map(|fragment| match fragment {
+            MessageFragment::Mention { user_id, .. } => format!("<@{}>", user_id),
+            MessageFragment::AssetId(id) => format!(":{}:", id),
+            other => plain_text(std::slice::from_ref(other)),
+        })
+        .collect()
+}
+
+/// A Revolt bot or user account, with REST for actions and the events websocket for updates.
+pub struct RevoltConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    api: Option<RevoltApi>,
+    mapper: Option<RevoltMapper>,
+    socket_tx: Option<mpsc::UnboundedSender<Value>>,
+    user_id: Option<String>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl RevoltConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        RevoltConnection {
+            auth: Vec::new(),
+            options,
+            event_tx,
+            event_rx: Some(event_rx),
+            api: None,
+            mapper: None,
+            socket_tx: None,
+            user_id: None,
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    /// The account's user id, once connected.
+    pub fn user_id(&self) -> Option<&str> {
+        self.user_id.as_deref()
+    }
+
+    fn api(&self) -> Result<&RevoltApi, ConnectionError> {
+        self.api.as_ref().ok_or(ConnectionError::Closed)
+    }
+
+    fn mapper(&self) -> Result<&RevoltMapper, ConnectionError> {
+        self.mapper.as_ref().ok_or(ConnectionError::Closed)
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn fail_auth(&self, reason: String) -> ConnectionError {
+        self.set_status(ConnectionStatus::AuthFailed {
+            reason: reason.clone(),
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed {
+                reason: reason.clone(),
+            },
+        });
+        ConnectionError::Auth(reason)
+    }
+
+    fn send_frame(&self, frame: Value) -> Result<(), ConnectionError> {
+        self.socket_tx
+            .as_ref()
+            .and_then(|tx| tx.send(frame).ok())
+            .ok_or(ConnectionError::Closed)
+    }
+
+    async fn post_message(
+        &self,
+        scope: &Scope,
+        message: &Message,
+    ) -> Result<String, ConnectionError> {
+        let channel_id = channel_of(scope)?;
+        let mut body = json!({ "content": render(&message.content) });
+        if let Some(reply_to) = &message.reply_to {
+            body["replies"] = json!([{ "id": reply_to, "mention": false }]);
+        }
+        if let Some(masquerade) = message
+            .extra
+            .get("masquerade")
+            .and_then(|value| serde_json::from_value::<Profile>(value.clone()).ok())
+        {
+            body["masquerade"] = json!({
+                "name": masquerade.display_name,
+                "avatar": masquerade.picture,
+            });
+        }
+        let response = self
+            .api()?
+            .request(
+                Method::POST,
+                &format!("channels/{}/messages", channel_id),
+                &[],
+                Some(body),
+            )
+            .await?;
+        Ok(response["_id"].as_str().unwrap_or_default().to_string())
+    }
+}
+
+impl Default for RevoltConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn channel_of(scope: &Scope) -> Result<&str, ConnectionError> {
+    scope
+        .channel_id()
+        .ok_or_else(|| ConnectionError::Unsupported("Revolt messages need a channel".to_string()))
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+fn reaction_path(key: &ReactionKey) -> &str {
+    match key {
+        ReactionKey::Emoji(emoji) | ReactionKey::AssetId(emoji) => emoji,
+    }
+}
+
+#[async_trait]
+impl Connection for RevoltConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "revolt.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let token = field_text(&self.auth, "token")
+            .ok_or_else(|| ConnectionError::Auth("Missing token".to_string()))?;
+        let bot = field_text(&self.auth, "token_type").as_deref() != Some("session");
+        let base = field_text(&self.auth, "api_url").unwrap_or(DEFAULT_API_URL.to_string());
+        let mut base = Url::parse(&base).map_err(|e| ConnectionError::Auth(e.to_string()))?;
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+
+        self.tasks.shutdown().await;
+        self.socket_tx = None;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+
+        let api = RevoltApi {
+            http: http_client(&self.options)?,
+            base,
+            token: token.clone(),
+            bot,
+        };
+        let node = api.get("", &[]).await?;
+        let me = match api.get("users/@me", &[]).await {
+            Err(ConnectionError::Auth(reason)) => return Err(self.fail_auth(reason)),
+            other => other?,
+        };
+        let mut ws_url = node["ws"]
+            .as_str()
+            .and_then(|url| Url::parse(url).ok())
+            .ok_or_else(|| ConnectionError::Protocol("No events websocket URL".to_string()))?;
+        ws_url
+            .query_pairs_mut()
+            .append_pair("version", "1")
+            .append_pair("format", "json");
+        let mut socket = match connect_websocket(&ws_url, &self.options).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+        let authenticate = json!({ "type": "Authenticate", "token": token });
+        socket
+            .send(WsMessage::Text(authenticate.to_string().into()))
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+
+        let ready = tokio::time::timeout(READY_TIMEOUT, async {
+            while let Some(frame) = socket.next().await {
+                let text = match frame {
+                    Ok(WsMessage::Text(text)) => text,
+                    Ok(WsMessage::Close(_)) | Err(_) => break,
+                    Ok(_) => continue,
+                };
+                let Ok(event) = serde_json::from_str::<Value>(&text) else {
+                    continue;
+                };
+                match event["type"].as_str() {
+                    Some("Ready") => return Ok(event),
+                    Some("Error") => {
+                        let error = event["error"].as_str().unwrap_or("Error").to_string();
+                        return Err(ConnectionError::Auth(error));
+                    }
+                    _ => {}
+                }
+            }
+            Err(ConnectionError::Closed)
+        })
+        .await
+        .map_err(|_| ConnectionError::Timeout)
+        .and_then(|ready| ready);
+        let ready = match ready {
+            Err(ConnectionError::Auth(reason)) => return Err(self.fail_auth(reason)),
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+            Ok(ready) => ready,
+        };
+
+        let mapper = RevoltMapper {
+            autumn: node["features"]["autumn"]["url"]
+                .as_str()
+                .unwrap_or("https://autumn.revolt.chat")
+                .to_string(),
+            event_tx: self.event_tx.clone(),
+            names: Arc::new(StdMutex::new(HashMap::new())),
+        };
+        let user_id = me["_id"].as_str().unwrap_or_default().to_string();
+        self.api = Some(api);
+        self.user_id = Some(user_id.clone());
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected {
+                artifact: ws_url.host_str().map(str::to_string),
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::User {
+            event: UserEvent::Identify {
+                user_id: user_id.clone(),
+            },
+        });
+        mapper.apply_ready(&ready, &user_id);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Synced,
+        });
+
+        let (socket_tx, mut socket_rx) = mpsc::unbounded_channel::<Value>();
+        self.socket_tx = Some(socket_tx);
+        self.mapper = Some(mapper.clone());
+        let status = self.status.clone();
+        let ping_interval = self.options.ping_interval;
+        self.tasks.spawn_essential("events", self.status.clone(), async move {
+            let mut ping = tokio::time::interval(ping_interval);
+            ping.tick().await;
+            loop {
+                tokio::select! {
+                    frame = socket.next() => match frame {
+                        Some(Ok(WsMessage::Text(text))) => match serde_json::from_str(&text) {
+                            Ok(event) => mapper.apply(&event, &user_id),
+                            Err(_) => event!(debug, "unparsed Revolt event {:?}", text),
+                        },
+                        Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => {}
+                    },
+                    Some(frame) = socket_rx.recv() => {
+                        if socket.send(WsMessage::Text(frame.to_string().into())).await.is_err() {
+                            break;
+                        }
+                    }
+                    _ = ping.tick() => {
+                        let frame = json!({ "type": "Ping", "data": Utc::now().timestamp_millis() });
+                        if socket.send(WsMessage::Text(frame.to_string().into())).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            if let Ok(mut current) = status.lock() {
+                if *current == ConnectionStatus::Connected {
+                    *current = ConnectionStatus::Disconnected;
+                }
+            }
+            mapper.emit(ConnectionEvent::Status {
+                event: StatusEvent::Disconnected {
+                    artifact: Some("Events websocket closed".to_string()),
+                },
+            });
+        });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.api = None;
+        self.socket_tx = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        let (method, path, body) = match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } => return self.post_message(&scope, &message).await.map(|_| ()),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::Update {
+                        scope,
+                        message_id,
+                        new_message,
+                    },
+            } => (
+                Method::PATCH,
+                format!("channels/{}/messages/{}", channel_of(&scope)?, message_id),
+                Some(json!({ "content": render(&new_message.content) })),
+            ),
+            ConnectionEvent::Chat {
+                event: ChatEvent::Remove { scope, message_id },
+            } => (
+                Method::DELETE,
+                format!("channels/{}/messages/{}", channel_of(&scope)?, message_id),
+                None,
+            ),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::ReactionAdd {
+                        scope,
+                        message_id,
+                        key,
+                        ..
+                    },
+            } => (
+                Method::PUT,
+                format!(
+                    "channels/{}/messages/{}/reactions/{}",
+                    channel_of(&scope)?,
+                    message_id,
+                    reaction_path(&key)
+                ),
+                None,
+            ),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::ReactionRemove {
+                        scope,
+                        message_id,
+                        key,
+                        ..
+                    },
+            } => (
+                Method::DELETE,
+                format!(
+                    "channels/{}/messages/{}/reactions/{}",
+                    channel_of(&scope)?,
+                    message_id,
+                    reaction_path(&key)
+                ),
+                None,
+            ),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::ReadMarker {
+                        channel_id,
+                        message_id,
+                        ..
+                    },
+            } => (
+                Method::PUT,
+                format!("channels/{}/ack/{}", channel_id, message_id),
+                None,
+            ),
+            ConnectionEvent::Channel {
+                event:
+                    ChannelEvent::TopicChanged {
+                        channel_id, topic, ..
+                    },
+            } => (
+                Method::PATCH,
+                format!("channels/{}", channel_id),
+                Some(json!({ "description": topic.unwrap_or_default() })),
+            ),
+            ConnectionEvent::User {
+                event: UserEvent::TypingStart { channel_id, .. },
+            } => return self.send_frame(json!({ "type": "BeginTyping", "channel": channel_id })),
+            ConnectionEvent::User {
+                event: UserEvent::TypingStop { channel_id, .. },
+            } => return self.send_frame(json!({ "type": "EndTyping", "channel": channel_id })),
+            _ => {
+                return Err(ConnectionError::Unsupported(
+                    "Event not supported over Revolt".to_string(),
+                ))
+            }
+        };
+        self.api()?
+            .request(method, &path, &[], body)
+            .await
+            .map(|_| ())
+    }
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        let ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } = &event
+        else {
+            self.send(event).await?;
+            return Ok(SendHandle::completed(SendOutcome::Delivered {
+                message_id: None,
+            }));
+        };
+        let id = self.post_message(scope, message).await?;
+        Ok(SendHandle::completed(SendOutcome::Delivered {
+            message_id: Some(id),
+        }))
+    }
+
+    async fn fetch_profile(&mut self, user_id: &str) -> Result<Profile, ConnectionError> {
+        let user = self.api()?.get(&format!("users/{}", user_id), &[]).await?;
+        let mapper = self.mapper()?;
+        let profile = mapper.profile(&user);
+        mapper.remember(&profile);
+        Ok(profile)
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        let mut query = vec![
+            ("limit", limit.min(100).to_string()),
+            ("sort", "Latest".to_string()),
+        ];
+        if let Some(before) = before {
+            query.push(("before", before));
+        }
+        let messages = self
+            .api()?
+            .get(&format!("channels/{}/messages", channel_id), &query)
+            .await?;
+        let mapper = self.mapper()?;
+        let mut messages: Vec<Message> = messages
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|value| mapper.message(value))
+            .collect();
+        messages.reverse();
+        Ok(messages)
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        let value = self
+            .api()?
+            .get(&format!("users/{}/dm", user_id), &[])
+            .await?;
+        let own_id = self.user_id.clone().unwrap_or_default();
+        let channel = self
+            .mapper()?
+            .channel(&value, &own_id)
+            .ok_or_else(|| ConnectionError::Protocol("Unexpected DM channel".to_string()))?;
+        self.event_tx
+            .send(ConnectionEvent::Channel {
+                event: ChannelEvent::New {
+                    channel: channel.clone(),
+                },
+            })
+            .map_err(|_| ConnectionError::Closed)?;
+        Ok(channel)
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        let field = |name: &str, display: &str, value: FieldValue, required: bool| AuthField {
+            name: name.to_string(),
+            display: Some(display.to_string()),
+            value,
+            required,
+        };
+        Protocol {
+            name: "revolt".to_string(),
+            auth: Some(vec![
+                field(
+                    "token",
+                    "Bot or session token",
+                    FieldValue::Password(None),
+                    true,
+                ),
+                field(
+                    "token_type",
+                    "Token type: bot (default) or session",
+                    FieldValue::Text(None),
+                    false,
+                ),
+                field("api_url", "API base URL", FieldValue::Text(None), false),
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            editing: true,
+            deletion: true,
+            history: true,
+            typing: true,
+            reactions: true,
+            multiple_channels: true,
+            topics: true,
+            max_message_length: Some(MAX_MESSAGE_LENGTH),
+            ..Capabilities::default()
+        }
+    }
+}
@@ -0,0 +1,1137 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{SinkExt, StreamExt};
+use regex::Regex;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+};
+use tokio_tungstenite::{tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{compose::plain_text, trace::event},
+    AuthField, Capabilities, Channel, ChannelType, Connection, FieldValue, Message,
+    MessageFragment, MessageStatus, MessageType, Presence, Profile, Protocol, Reaction,
+    ReactionKey,
+};
+
+use super::{
+    transport::connect_websocket, ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent,
+    ConnectionOptions, Scope, SendHandle, SendOutcome, StatusEvent, Supervisor, UserEvent,
+};
+
+const DDP_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_MESSAGE_LENGTH: usize = 5000;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type Pending = Arc<StdMutex<HashMap<String, oneshot::Sender<Result<Value, ConnectionError>>>>>;
+
+/// Method calls and subscriptions over the realtime socket, matched to their results by id.
+#[derive(Clone, Debug)]
+struct Ddp {
+    frames: mpsc::UnboundedSender<Value>,
+    pending: Pending,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Ddp {
+    fn id(&self) -> String {
+        self.next_id.fetch_add(1, Ordering::Relaxed).to_string()
+    }
+
+    fn send(&self, frame: Value) -> Result<(), ConnectionError> {
+        self.frames.send(frame).map_err(|_| ConnectionError::Closed)
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, ConnectionError> {
+        let id = self.id();
+        let (tx, rx) = oneshot::channel();
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.insert(id.clone(), tx);
+        }
+        self.send(json!({ "msg": "method", "method": method, "id": id, "params": params }))?;
+        match tokio::time::timeout(DDP_TIMEOUT, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(ConnectionError::Closed),
+            Err(_) => {
+                if let Ok(mut pending) = self.pending.lock() {
+                    pending.remove(&id);
+                }
+                Err(ConnectionError::Timeout)
+            }
+        }
+    }
+
+    fn subscribe(&self, name: &str, params: Value) {
+        let _ = self.send(json!({ "msg": "sub", "id": self.id(), "name": name, "params": params }));
+    }
+
+    /// Completes the call a `result` frame answers.
+    fn resolve(&self, frame: &Value) {
+        let Some(tx) = frame["id"]
+            .as_str()
+            .and_then(|id| self.pending.lock().ok()?.remove(id))
+        else {
+            return;
+        };
+        let _ = tx.send(match &frame["error"] {
+            Value::Null => Ok(frame["result"].clone()),
+            error => Err(ddp_error(error)),
+        });
+    }
+}
+
+fn ddp_error(error: &Value) -> ConnectionError {
+    let reason = error["reason"]
+        .as_str()
+        .or(error["message"].as_str())
+        .unwrap_or("Method failed")
+        .to_string();
+    match &error["error"] {
+        code if code == "too-many-requests" => ConnectionError::RateLimited(Duration::from_millis(
+            error["details"]["timeToReset"].as_u64().unwrap_or(1000),
+        )),
+        code if code == 401 || code == 403 || code == "error-not-allowed" => {
+            ConnectionError::Auth(reason)
+        }
+        _ => ConnectionError::Protocol(reason),
+    }
+}
+
+/// Answers pings and reads frames until the result of call `id`, keeping the fields of any
+/// `users` documents the server publishes meanwhile, which is how it announces the logged in
+/// account.
+async fn await_result(
+    socket: &mut Socket,
+    id: &str,
+    users: &mut HashMap<String, Value>,
+) -> Result<Value, ConnectionError> {
+    while let Some(frame) = socket.next().await {
+        let text = match frame {
+            Ok(WsMessage::Text(text)) => text,
+            Ok(WsMessage::Close(_)) | Err(_) => break,
+            Ok(_) => continue,
+        };
+        let Ok(frame) = serde_json::from_str::<Value>(&text) else {
+            continue;
+        };
+        match frame["msg"].as_str() {
+            Some("ping") => {
+                let pong = json!({ "msg": "pong" }).to_string();
+                socket
+                    .send(WsMessage::Text(pong.into()))
+                    .await
+                    .map_err(|e| ConnectionError::Network(e.to_string()))?;
+            }
+            Some("added") if frame["collection"] == "users" => {
+                if let Some(user_id) = frame["id"].as_str() {
+                    users.insert(user_id.to_string(), frame["fields"].clone());
+                }
+            }
+            Some("result") if frame["id"] == id => {
+                return match &frame["error"] {
+                    Value::Null => Ok(frame["result"].clone()),
+                    error => Err(ddp_error(error)),
+                };
+            }
+            Some("failed") => {
+                return Err(ConnectionError::Protocol(
+                    "Server does not speak DDP version 1".to_string(),
+                ))
+            }
+            _ => {}
+        }
+    }
+    Err(ConnectionError::Closed)
+}
+
+fn timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp_millis(value["$date"].as_i64()?)
+}
+
+fn presence(status: &Value) -> Presence {
+    match (status.as_u64(), status.as_str()) {
+        (Some(1), _) | (_, Some("online")) => Presence::Online,
+        (Some(2), _) | (_, Some("away")) => Presence::Away,
+        (Some(3), _) | (_, Some("busy")) => Presence::Dnd,
+        _ => Presence::Offline,
+    }
+}
+
+/// Reaction shortcodes keep their colons, as `setReaction` expects them.
+fn reaction_name(key: &ReactionKey) -> String {
+    let (ReactionKey::Emoji(name) | ReactionKey::AssetId(name)) = key;
+    format!(":{}:", name.trim_matches(':'))
+}
+
+/// Turns realtime API documents into oshatori events, subscribing to the streams of every room
+/// it announces.
+#[derive(Clone, Debug)]
+struct RocketMapper {
+    server: Url,
+    own_id: String,
+    own_username: String,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    ddp: Ddp,
+    usernames: Arc<StdMutex<HashMap<String, String>>>,
+    seen: Arc<StdMutex<HashSet<String>>>,
+}
+
+impl RocketMapper {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn url(&self, path: &str) -> String {
+        self.server
+            .join(path.trim_start_matches('/'))
+            .map(String::from)
+            .unwrap_or_else(|_| path.to_string())
+    }
+
+    fn user_id_of(&self, username: &str) -> String {
+        self.usernames
+            .lock()
+            .ok()
+            .and_then(|usernames| {
+                usernames
+                    .iter()
+                    .find(|(_, name)| name.as_str() == username)
+                    .map(|(id, _)| id.clone())
+            })
+            .unwrap_or_else(|| username.to_string())
+    }
+
+    fn username_of(&self, user_id: &str) -> Option<String> {
+        self.usernames.lock().ok()?.get(user_id).cloned()
+    }
+
+    fn profile(&self, user_id: &str, user: &Value) -> Profile {
+        let username = user["username"].as_str();
+        let mut extra = HashMap::new();
+        if let Some(text) = user["statusText"].as_str().filter(|text| !text.is_empty()) {
+            extra.insert("status_text".to_string(), json!(text));
+        }
+        Profile {
+            id: Some(user_id.to_string()),
+            username: username.map(str::to_string),
+            display_name: user["name"].as_str().map(str::to_string),
+            picture: username.map(|username| self.url(&format!("avatar/{}", username))),
+            presence: user.get("status").map(presence),
+            extra,
+            ..Profile::default()
+        }
+    }
+
+    /// Announces a user the first time they are seen.
+    fn remember(&self, user: &Value) {
+        let (Some(user_id), Some(username)) = (user["_id"].as_str(), user["username"].as_str())
+        else {
+            return;
+        };
+        let known = match self.usernames.lock() {
+            Ok(mut usernames) => usernames
+                .insert(user_id.to_string(), username.to_string())
+                .is_some(),
+            Err(_) => return,
+        };
+        if !known {
+            self.emit(ConnectionEvent::User {
+                event: UserEvent::New {
+                    scope: Scope::Global,
+                    user: self.profile(user_id, user),
+                },
+            });
+        }
+    }
+
+    fn channel(&self, room: &Value) -> Option<Channel> {
+        let id = room["_id"].as_str()?.to_string();
+        let mut extra = HashMap::new();
+        let (name, channel_type) = match room["t"].as_str()? {
+            kind @ ("c" | "p") => {
+                extra.insert("private".to_string(), json!(kind == "p"));
+                (
+                    room["fname"].as_str().or(room["name"].as_str()),
+                    ChannelType::Group,
+                )
+            }
+            "d" => (
+                room["usernames"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .find(|username| *username != self.own_username),
+                ChannelType::Direct,
+            ),
+            _ => return None,
+        };
+        Some(Channel {
+            id,
+            name: name.map(str::to_string),
+            channel_type,
+            topic: room["topic"]
+                .as_str()
+                .filter(|topic| !topic.is_empty())
+                .map(str::to_string),
+            extra,
+        })
+    }
+
+    /// Announces and joins a room, subscribing to its messages, deletions and typing.
+    fn join(&self, channel: Channel) {
+        let room_id = channel.id.clone();
+        self.ddp
+            .subscribe("stream-room-messages", json!([room_id, false]));
+        for event in ["deleteMessage", "typing", "user-activity"] {
+            self.ddp.subscribe(
+                "stream-notify-room",
+                json!([format!("{}/{}", room_id, event), false]),
+            );
+        }
+        self.emit(ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel },
+        });
+        self.emit(ConnectionEvent::Channel {
+            event: ChannelEvent::Join {
+                channel_id: room_id,
+            },
+        });
+    }
+
+    /// Splits message text at the mentions the server resolved for it.
+    fn fragments(&self, text: &str, mentions: &Value) -> Vec<MessageFragment> {
+        let mentions: HashMap<&str, (&str, &str)> = mentions
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|mention| {
+                let username = mention["username"].as_str()?;
+                let user_id = mention["_id"].as_str()?;
+                let display = mention["name"].as_str().unwrap_or(username);
+                Some((username, (user_id, display)))
+            })
+            .filter(|(username, _)| !matches!(*username, "all" | "here"))
+            .collect();
+        if mentions.is_empty() {
+            return vec![MessageFragment::Text(text.to_string())];
+        }
+        let names: Vec<String> = mentions.keys().map(|name| regex::escape(name)).collect();
+        let pattern = Regex::new(&format!(r"@({})\b", names.join("|"))).unwrap();
+        let mut out = Vec::new();
+        let mut last = 0;
+        for caps in pattern.captures_iter(text) {
+            let (whole, username) = (caps.get(0).unwrap(), caps.get(1).unwrap());
+            let Some((user_id, display)) = mentions.get(username.as_str()) else {
+                continue;
+            };
+            if whole.start() > last {
+                out.push(MessageFragment::Text(text[last..whole.start()].to_string()));
+            }
+            out.push(MessageFragment::Mention {
+                user_id: user_id.to_string(),
+                display: display.to_string(),
+            });
+            last = whole.end();
+        }
+        if last < text.len() {
+            out.push(MessageFragment::Text(text[last..].to_string()));
+        }
+        out
+    }
+
+    fn attachment(&self, attachment: &Value) -> Option<MessageFragment> {
+        let media = |kind: &str| {
+            let url = attachment[format!("{}_url", kind)].as_str()?;
+            let mime = attachment[format!("{}_type", kind)]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            Some((self.url(url), mime))
+        };
+        if let Some((url, mime)) = media("image") {
+            Some(MessageFragment::Image { url, mime })
+        } else if let Some((url, mime)) = media("video") {
+            Some(MessageFragment::Video { url, mime })
+        } else if let Some((url, mime)) = media("audio") {
+            Some(MessageFragment::Audio { url, mime })
+        } else {
+            let link = attachment["title_link"].as_str()?;
+            Some(MessageFragment::Url(self.url(link)))
+        }
+    }
+
+    /// Maps a message. System messages (joins, topic changes and the like) come through as
+    /// `MessageType::Server` with their kind under `extra["system"]`.
+    fn message(&self, value: &Value) -> Option<Message> {
+        let id = value["_id"].as_str()?;
+        let text = value["msg"].as_str().unwrap_or_default();
+        let mut content = if text.is_empty() {
+            Vec::new()
+        } else {
+            self.fragments(text, &value["mentions"])
+        };
+        content.extend(
+            value["attachments"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|attachment| self.attachment(attachment)),
+        );
+
+        let mut extra = HashMap::new();
+        let system = value["t"].as_str().filter(|kind| !kind.is_empty());
+        if let Some(kind) = system {
+            extra.insert("system".to_string(), json!(kind));
+        }
+        if let Some(alias) = value["alias"].as_str().filter(|alias| !alias.is_empty()) {
+            extra.insert("alias".to_string(), json!(alias));
+        }
+        let reactions = value["reactions"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(name, reaction)| Reaction {
+                key: ReactionKey::Emoji(name.clone()),
+                user_ids: reaction["usernames"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .map(|username| self.user_id_of(username))
+                    .collect(),
+            })
+            .collect();
+        Some(Message {
+            id: Some(id.to_string()),
+            sender_id: value["u"]["_id"].as_str().map(str::to_string),
+            content,
+            timestamp: timestamp(&value["ts"]).unwrap_or_else(Utc::now),
+            message_type: if system.is_some() {
+                MessageType::Server
+            } else {
+                MessageType::Normal
+            },
+            status: if value["editedAt"].is_object() {
+                MessageStatus::Edited
+            } else {
+                MessageStatus::Sent
+            },
+            reactions,
+            reply_to: None,
+            thread_id: value["tmid"].as_str().map(str::to_string),
+            extra,
+        })
+    }
+
+    fn apply(&self, frame: &Value) {
+        let event_name = frame["fields"]["eventName"].as_str().unwrap_or_default();
+        let args = &frame["fields"]["args"];
+        match frame["collection"].as_str().unwrap_or_default() {
+            "stream-room-messages" => {
+                let value = &args[0];
+                let (Some(room_id), Some(message)) = (value["rid"].as_str(), self.message(value))
+                else {
+                    return;
+                };
+                self.remember(&value["u"]);
+                let message_id = message.id.clone().unwrap_or_default();
+                let seen = self
+                    .seen
+                    .lock()
+                    .is_ok_and(|mut seen| !seen.insert(message_id.clone()));
+                let scope = Scope::channel(room_id);
+                self.emit(ConnectionEvent::Chat {
+                    event: if seen {
+                        ChatEvent::Update {
+                            scope,
+                            message_id,
+                            new_message: message,
+                        }
+                    } else {
+                        ChatEvent::New { scope, message }
+                    },
+                });
+            }
+            "stream-notify-room" => {
+                let Some((room_id, kind)) = event_name.split_once('/') else {
+                    return;
+                };
+                match kind {
+                    "deleteMessage" => {
+                        if let Some(message_id) = args[0]["_id"].as_str() {
+                            self.emit(ConnectionEvent::Chat {
+                                event: ChatEvent::Remove {
+                                    scope: Scope::channel(room_id),
+                                    message_id: message_id.to_string(),
+                                },
+                            });
+                        }
+                    }
+                    "typing" | "user-activity" => {
+                        let Some(username) = args[0].as_str() else {
+                            return;
+                        };
+                        if username == self.own_username {
+                            return;
+                        }
+                        let typing = args[1].as_bool().unwrap_or_else(|| {
+                            args[1].as_array().is_some_and(|activities| {
+                                activities.contains(&json!("user-typing"))
+                            })
+                        });
+                        let (channel_id, user_id) =
+                            (room_id.to_string(), self.user_id_of(username));
+                        self.emit(ConnectionEvent::User {
+                            event: if typing {
+                                UserEvent::TypingStart {
+                                    channel_id,
+                                    user_id,
+                                }
+                            } else {
+                                UserEvent::TypingStop {
+                                    channel_id,
+                                    user_id,
+                                }
+                            },
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            "stream-notify-logged" if event_name == "user-status" => {
+                let status = &args[0];
+                let Some(user_id) = status[0].as_str() else {
+                    return;
+                };
+                if let Some(username) = status[1].as_str() {
+                    self.remember(&json!({ "_id": user_id, "username": username }));
+                }
+                self.emit(ConnectionEvent::User {
+                    event: UserEvent::PresenceChanged {
+                        user_id: user_id.to_string(),
+                        presence: presence(&status[2]),
+                    },
+                });
+            }
+            "stream-notify-user" if event_name.ends_with("/rooms-changed") => {
+                let room = &args[1];
+                match args[0].as_str() {
+                    Some("inserted") => {
+                        if let Some(channel) = self.channel(room) {
+                            self.join(channel);
+                        }
+                    }
+                    Some("updated") => {
+                        if let Some(channel) = self.channel(room) {
+                            self.emit(ConnectionEvent::Channel {
+                                event: ChannelEvent::Update {
+                                    channel_id: channel.id.clone(),
+                                    new_channel: channel,
+                                },
+                            });
+                        }
+                    }
+                    Some("removed") => {
+                        if let Some(room_id) = room["_id"].as_str() {
+                            self.emit(ConnectionEvent::Channel {
+                                event: ChannelEvent::Remove {
+                                    channel_id: room_id.to_string(),
+                                },
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Renders fragments to message text, with media fragments as attachments.
+    fn render(&self, content: &[MessageFragment]) -> (String, Vec<Value>) {
+        let mut attachments = Vec::new();
+        let text = content
+            .iter()
+            .map(|fragment| match fragment {
+                MessageFragment::Mention { user_id, display } => format!(
+                    "@{}",
+                    self.username_of(user_id).unwrap_or_else(|| display.clone())
+                ),
+                MessageFragment::AssetId(id) => format!(":{}:", id),
+                MessageFragment::Image { url, mime } => {
+                    attachments.push(json!({ "image_url": url, "image_type": mime }));
+                    String::new()
+                }
+                MessageFragment::Video { url, mime } => {
+                    attachments.push(json!({ "video_url": url, "video_type": mime }));
+                    String::new()
+                }
+                MessageFragment::Audio { url, mime } => {
+                    attachments.push(json!({ "audio_url": url, "audio_type": mime }));
+                    String::new()
+                }
+                other => plain_text(std::slice::from_ref(other)),
+            })
+            .collect();
+        (text, attachments)
+    }
+}
+
+/// A Rocket.Chat account over the realtime (DDP) websocket API. Logs in with a personal access
+/// or resume token, or a username and password.
+pub struct RocketChatConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    mapper: Option<RocketMapper>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl RocketChatConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        RocketChatConnection {
+            auth: Vec::new(),
+            options,
+            event_tx,
+            event_rx: Some(event_rx),
+            mapper: None,
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    /// The account's user id, once connected.
+    pub fn user_id(&self) -> Option<&str> {
+        self.mapper.as_ref().map(|mapper| mapper.own_id.as_str())
+    }
+
+    fn mapper(&self) -> Result<&RocketMapper, ConnectionError> {
+        self.mapper.as_ref().ok_or(ConnectionError::Closed)
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, ConnectionError> {
+        self.mapper()?.ddp.call(method, params).await
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn fail_auth(&self, reason: String) -> ConnectionError {
+        self.set_status(ConnectionStatus::AuthFailed {
+            reason: reason.clone(),
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed {
+                reason: reason.clone(),
+            },
+        });
+        ConnectionError::Auth(reason)
+    }
+
+    fn login_params(&self) -> Result<Value, ConnectionError> {
+        if let Some(token) = field_text(&self.auth, "token") {
+            return Ok(json!([{ "resume": token }]));
+        }
+        let (Some(username), Some(password)) = (
+            field_text(&self.auth, "username"),
+            field_text(&self.auth, "password"),
+        ) else {
+            return Err(ConnectionError::Auth(
+                "Missing token or username and password".to_string(),
+            ));
+        };
+        let user = if username.contains('@') {
+            json!({ "email": username })
+        } else {
+            json!({ "username": username })
+        };
+        let digest = format!("{:x}", Sha256::digest(password.as_bytes()));
+        Ok(json!([{
+            "user": user,
+            "password": { "digest": digest, "algorithm": "sha-256" },
+        }]))
+    }
+
+    async fn post_message(
+        &self,
+        scope: &Scope,
+        message: &Message,
+    ) -> Result<String, ConnectionError> {
+        let room_id = channel_of(scope)?;
+        let (text, attachments) = self.mapper()?.render(&message.content);
+        let mut params = json!({ "rid": room_id, "msg": text });
+        if !attachments.is_empty() {
+            params["attachments"] = Value::Array(attachments);
+        }
+        if let Some(thread_id) = &message.thread_id {
+            params["tmid"] = json!(thread_id);
+        }
+        let sent = self.call("sendMessage", json!([params])).await?;
+        Ok(sent["_id"].as_str().unwrap_or_default().to_string())
+    }
+}
+
+impl Default for RocketChatConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn channel_of(scope: &Scope) -> Result<&str, ConnectionError> {
+    scope
+        .channel_id()
+        .ok_or_else(|| ConnectionError::Unsupported("Rocket.Chat messages need a room".to_string()))
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+#[async_trait]
+impl Connection for RocketChatConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "rocketchat.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let server = field_text(&self.auth, "server")
+            .ok_or_else(|| ConnectionError::Auth("Missing server URL".to_string()))?;
+        let mut server = Url::parse(&server).map_err(|e| ConnectionError::Auth(e.to_string()))?;
+        if !server.path().ends_with('/') {
+            server.set_path(&format!("{}/", server.path()));
+        }
+        let mut ws_url = server
+            .join("websocket")
+            .map_err(|e| ConnectionError::Auth(e.to_string()))?;
+        let scheme = if server.scheme() == "http" {
+            "ws"
+        } else {
+            "wss"
+        };
+        ws_url
+            .set_scheme(scheme)
+            .map_err(|_| ConnectionError::Auth("Unsupported server URL".to_string()))?;
+        let login = self.login_params()?;
+
+        self.tasks.shutdown().await;
+        self.mapper = None;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+
+        let mut socket = match connect_websocket(&ws_url, &self.options).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+        let handshake = tokio::time::timeout(DDP_TIMEOUT, async {
+            let connect = json!({ "msg": "connect", "version": "1", "support": ["1"] });
+            socket
+                .send(WsMessage::Text(connect.to_string().into()))
+                .await
+                .map_err(|e| ConnectionError::Network(e.to_string()))?;
+            let call =
+                json!({ "msg": "method", "method": "login", "id": "login", "params": login });
+            socket
+                .send(WsMessage::Text(call.to_string().into()))
+                .await
+                .map_err(|e| ConnectionError::Network(e.to_string()))?;
+            let mut users = HashMap::new();
+            let session = await_result(&mut socket, "login", &mut users).await?;
+            let call =
+                json!({ "msg": "method", "method": "rooms/get", "id": "rooms", "params": [] });
+            socket
+                .send(WsMessage::Text(call.to_string().into()))
+                .await
+                .map_err(|e| ConnectionError::Network(e.to_string()))?;
+            let rooms = await_result(&mut socket, "rooms", &mut users).await?;
+            Ok((session, rooms, users))
+        })
+        .await
+        .map_err(|_| ConnectionError::Timeout)
+        .and_then(|handshake| handshake);
+        let (session, rooms, users) = match handshake {
+            Err(ConnectionError::Auth(reason)) => return Err(self.fail_auth(reason)),
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+            Ok(handshake) => handshake,
+        };
+
+        let own_id = session["id"].as_str().unwrap_or_default().to_string();
+        let own = users.get(&own_id).cloned().unwrap_or_default();
+        let own_username = own["username"]
+            .as_str()
+            .map(str::to_string)
+            .or_else(|| field_text(&self.auth, "username"))
+            .unwrap_or_else(|| own_id.clone());
+        let (frames_tx, mut frames_rx) = mpsc::unbounded_channel::<Value>();
+        let mapper = RocketMapper {
+            server,
+            own_id: own_id.clone(),
+            own_username: own_username.clone(),
+            event_tx: self.event_tx.clone(),
+            ddp: Ddp {
+                frames: frames_tx,
+                pending: Arc::new(StdMutex::new(HashMap::new())),
+                next_id: Arc::new(AtomicU64::new(1)),
+            },
+            usernames: Arc::new(StdMutex::new(HashMap::new())),
+            seen: Arc::new(StdMutex::new(HashSet::new())),
+        };
+
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected {
+                artifact: ws_url.host_str().map(str::to_string),
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::User {
+            event: UserEvent::Identify {
+                user_id: own_id.clone(),
+            },
+        });
+        let mut own_user = own;
+        own_user["_id"] = json!(own_id);
+        own_user["username"] = json!(own_username);
+        mapper.remember(&own_user);
+        mapper.ddp.subscribe(
+            "stream-notify-user",
+            json!([format!("{}/rooms-changed", own_id), false]),
+        );
+        mapper
+            .ddp
+            .subscribe("stream-notify-logged", json!(["user-status", false]));
+        let rooms = rooms
+            .get("update")
+            .and_then(Value::as_array)
+            .or(rooms.as_array())
+            .cloned()
+            .unwrap_or_default();
+        for channel in rooms.iter().filter_map(|room| mapper.channel(room)) {
+            mapper.join(channel);
+        }
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Synced,
+        });
+
+        self.mapper = Some(mapper.clone());
+        let status = self.status.clone();
+        let ping_interval = self.options.ping_interval;
+        self.tasks
+            .spawn_essential("realtime", self.status.clone(), async move {
+                let mut ping = tokio::time::interval(ping_interval);
+                ping.tick().await;
+                loop {
+                    let reply = tokio::select! {
+                        frame = socket.next() => match frame {
+                            Some(Ok(WsMessage::Text(text))) => {
+                                let Ok(frame) = serde_json::from_str::<Value>(&text) else {
+                                    event!(debug, "unparsed Rocket.Chat frame {:?}", text);
+                                    continue;
+                                };
+                                match frame["msg"].as_str() {
+                                    Some("ping") => Some(json!({ "msg": "pong" })),
+                                    Some("result") => {
+                                        mapper.ddp.resolve(&frame);
+                                        None
+                                    }
+                                    Some("changed") => {
+                                        mapper.apply(&frame);
+                                        None
+                                    }
+                                    _ => None,
+                                }
+                            }
+                            Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                            Some(Ok(_)) => None,
+                        },
+                        Some(frame) = frames_rx.recv() => Some(frame),
+                        _ = ping.tick() => Some(json!({ "msg": "ping" })),
+                    };
+                    if let Some(frame) = reply {
+                        if socket
+                            .send(WsMessage::Text(frame.to_string().into()))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                }
+                if let Ok(mut pending) = mapper.ddp.pending.lock() {
+                    pending.clear();
+                }
+                if let Ok(mut current) = status.lock() {
+                    if *current == ConnectionStatus::Connected {
+                        *current = ConnectionStatus::Disconnected;
+                    }
+                }
+                mapper.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Disconnected {
+                        artifact: Some("Realtime API closed".to_string()),
+                    },
+                });
+            });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.mapper = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        let (method, params) = match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } => return self.post_message(&scope, &message).await.map(|_| ()),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::Update {
+                        scope,
+                        message_id,
+                        new_message,
+                    },
+            } => {
+                let (text, _) = self.mapper()?.render(&new_message.content);
+                (
+                    "updateMessage",
+                    json!([{ "_id": message_id, "rid": channel_of(&scope)?, "msg": text }]),
+                )
+            }
+            ConnectionEvent::Chat {
+                event: ChatEvent::Remove { message_id, .. },
+            } => ("deleteMessage", json!([{ "_id": message_id }])),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::ReactionAdd {
+                        message_id, key, ..
+                    },
+            } => (
+                "setReaction",
+                json!([reaction_name(&key), message_id, true]),
+            ),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::ReactionRemove {
+                        message_id, key, ..
+                    },
+            } => (
+                "setReaction",
+                json!([reaction_name(&key), message_id, false]),
+            ),
+            ConnectionEvent::Chat {
+                event: ChatEvent::ReadMarker { channel_id, .. },
+            } => ("readMessages", json!([channel_id])),
+            ConnectionEvent::Channel {
+                event:
+                    ChannelEvent::TopicChanged {
+                        channel_id, topic, ..
+                    },
+            } => (
+                "saveRoomSettings",
+                json!([channel_id, "roomTopic", topic.unwrap_or_default()]),
+            ),
+            ConnectionEvent::User {
+                event: UserEvent::TypingStart { channel_id, .. },
+            } => (
+                "stream-notify-room",
+                json!([
+                    format!("{}/typing", channel_id),
+                    self.mapper()?.own_username,
+                    true
+                ]),
+            ),
+            ConnectionEvent::User {
+                event: UserEvent::TypingStop { channel_id, .. },
+            } => (
+                "stream-notify-room",
+                json!([
+                    format!("{}/typing", channel_id),
+                    self.mapper()?.own_username,
+                    false
+                ]),
+            ),
+            _ => {
+                return Err(ConnectionError::Unsupported(
+                    "Event not supported over Rocket.Chat".to_string(),
+                ))
+            }
+        };
+        self.call(method, params).await.map(|_| ())
+    }
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        let ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } = &event
+        else {
+            self.send(event).await?;
+            return Ok(SendHandle::completed(SendOutcome::Delivered {
+                message_id: None,
+            }));
+        };
+        let id = self.post_message(scope, message).await?;
+        Ok(SendHandle::completed(SendOutcome::Delivered {
+            message_id: Some(id),
+        }))
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        let before = match before {
+            Some(message_id) => {
+                let message = self.call("getSingleMessage", json!([message_id])).await?;
+                message["ts"].clone()
+            }
+            None => Value::Null,
+        };
+        let history = self
+            .call("loadHistory", json!([channel_id, before, limit, null]))
+            .await?;
+        let mapper = self.mapper()?;
+        let mut messages: Vec<Message> = history["messages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|value| {
+                mapper.remember(&value["u"]);
+                mapper.message(value)
+            })
+            .collect();
+        messages.reverse();
+        Ok(messages)
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        let mapper = self.mapper()?;
+        let username = mapper
+            .username_of(user_id)
+            .unwrap_or_else(|| user_id.to_string());
+        let room = self.call("createDirectMessage", json!([username])).await?;
+        let room_id = room["rid"]
+            .as_str()
+            .or(room["_id"].as_str())
+            .ok_or_else(|| ConnectionError::Protocol("No direct message room".to_string()))?;
+        let channel = Channel {
+            id: room_id.to_string(),
+            name: Some(username),
+            channel_type: ChannelType::Direct,
+            topic: None,
+            extra: HashMap::new(),
+        };
+        self.mapper()?.join(channel.clone());
+        Ok(channel)
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        let field = |name: &str, display: &str, value: FieldValue, required: bool| AuthField {
+            name: name.to_string(),
+            display: Some(display.to_string()),
+            value,
+            required,
+        };
+        Protocol {
+            name: "rocketchat".to_string(),
+            auth: Some(vec![
+                field("server", "Server URL", FieldValue::Text(None), true),
+                field(
+                    "username",
+                    "Username or email",
+                    FieldValue::Text(None),
+                    false,
+                ),
+                field("password", "Password", FieldValue::Password(None), false),
+                field(
+                    "token",
+                    "Personal access or resume token, instead of a password",
+                    FieldValue::Password(None),
+                    false,
+                ),
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            editing: true,
+            deletion: true,
+            history: true,
+            typing: true,
+            reactions: true,
+            multiple_channels: true,
+            topics: true,
+            max_message_length: Some(MAX_MESSAGE_LENGTH),
+            ..Capabilities::default()
+        }
+    }
+}
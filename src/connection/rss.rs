@@ -0,0 +1,589 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode, Url,
+};
+use roxmltree::{Document, Node};
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use crate::{
+    connection::ConnectionStatus, utils::html::html_fragments, AuthField, Capabilities, Channel,
+    ChannelType, Connection, FieldValue, Message, MessageFragment, MessageStatus, MessageType,
+    Protocol, TextStyle,
+};
+
+use super::{
+    http::http_client,
+    preflight::{probe_reachability, validate_auth},
+    ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, ConnectionOptions, PreflightReport,
+    Scope, StatusEvent, Supervisor,
+};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// One item of an RSS feed or entry of an Atom feed.
+#[derive(Clone, Debug, Default)]
+struct Entry {
+    id: String,
+    title: Option<String>,
+    link: Option<String>,
+    author: Option<String>,
+    summary: Vec<MessageFragment>,
+    published: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Feed {
+    title: Option<String>,
+    description: Option<String>,
+    /// Oldest first.
+    entries: Vec<Entry>,
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+/// The trimmed text of a child element, CDATA included.
+fn child_text(node: Node, name: &str) -> Option<String> {
+    let text: String = child(node, name)?
+        .children()
+        .filter_map(|text| text.text())
+        .collect();
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+fn rss_entry(item: Node) -> Entry {
+    let summary = child_text(item, "encoded").or_else(|| child_text(item, "description"));
+    let link = child_text(item, "link");
+    let title = child_text(item, "title");
+    Entry {
+        id: child_text(item, "guid")
+            .or_else(|| link.clone())
+            .or_else(|| title.clone())
+            .unwrap_or_default(),
+        author: child_text(item, "creator").or_else(|| child_text(item, "author")),
+        summary: summary
+            .map(|html| html_fragments(&html))
+            .unwrap_or_default(),
+        published: child_text(item, "pubDate")
+            .or_else(|| child_text(item, "date"))
+            .and_then(|date| {
+                DateTime::parse_from_rfc2822(&date)
+                    .or_else(|_| DateTime::parse_from_rfc3339(&date))
+                    .ok()
+            })
+            .map(|date| date.with_timezone(&Utc)),
+        title,
+        link,
+    }
+}
+
+fn atom_entry(entry: Node) -> Entry {
+    let link = children(entry, "link")
+        .find(|link| matches!(link.attribute("rel"), None | Some("alternate")))
+        .and_then(|link| link.attribute("href"))
+        .map(|href| href.to_string());
+    let summary = child(entry, "content")
+        .filter(|content| content.attribute("type") != Some("xhtml"))
+        .or_else(|| child(entry, "summary"))
+        .and_then(|summary| {
+            let text: String = summary.children().filter_map(|text| text.text()).collect();
+            let text = text.trim();
+            match summary.attribute("type") {
+                _ if text.is_empty() => None,
+                Some("html") => Some(html_fragments(text)),
+                _ => Some(vec![MessageFragment::Text(text.to_string())]),
+            }
+        });
+    let title = child_text(entry, "title");
+    Entry {
+        id: child_text(entry, "id")
+            .or_else(|| link.clone())
+            .or_else(|| title.clone())
+            .unwrap_or_default(),
+        author: child(entry, "author").and_then(|author| child_text(author, "name")),
+        summary: summary.unwrap_or_default(),
+        published: child_text(entry, "published")
+            .or_else(|| child_text(entry, "updated"))
+            .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+            .map(|date| date.with_timezone(&Utc)),
+        title,
+        link,
+    }
+}
+
+/// Reads RSS 2.0, RSS 1.0 (RDF) and Atom documents.
+fn parse_feed(xml: &str) -> Result<Feed, ConnectionError> {
+    let document = Document::parse(xml).map_err(|e| ConnectionError::Protocol(e.to_string()))?;
+    let root = document.root_element();
+    let mut feed = match root.tag_name().name() {
+        "rss" | "RDF" => {
+            let channel = child(root, "channel")
+                .ok_or_else(|| ConnectionError::Protocol("RSS has no channel".to_string()))?;
+            Feed {
+                title: child_text(channel, "title"),
+                description: child_text(channel, "description"),
+                entries: children(channel, "item")
+                    .chain(children(root, "item"))
+                    .map(rss_entry)
+                    .collect(),
+            }
+        }
+        "feed" => Feed {
+            title: child_text(root, "title"),
+            description: child_text(root, "subtitle"),
+            entries: children(root, "entry").map(atom_entry).collect(),
+        },
+        other => {
+            return Err(ConnectionError::Protocol(format!(
+                "Not a feed: <{}>",
+                other
+            )))
+        }
+    };
+    feed.entries.retain(|entry| !entry.id.is_empty());
+    // Feeds list newest first; entries without dates keep their relative order.
+    feed.entries.reverse();
+    feed.entries
+        .sort_by_key(|entry| entry.published.unwrap_or(DateTime::<Utc>::MIN_UTC));
+    Ok(feed)
+}
+
+fn entry_message(entry: &Entry) -> Message {
+    let mut content = Vec::new();
+    if let Some(title) = &entry.title {
+        content.push(MessageFragment::Styled {
+            text: title.clone(),
+            styles: vec![TextStyle::Bold],
+        });
+    }
+    if !entry.summary.is_empty() {
+        if !content.is_empty() {
+            content.push(MessageFragment::Text("\n".to_string()));
+        }
+        content.extend(entry.summary.iter().cloned());
+    }
+    let mut extra = HashMap::new();
+    if let Some(link) = &entry.link {
+        if !content.is_empty() {
+            content.push(MessageFragment::Text("\n".to_string()));
+        }
+        content.push(MessageFragment::Url(link.clone()));
+        extra.insert("link".to_string(), json!(link));
+    }
+    if let Some(author) = &entry.author {
+        extra.insert("author".to_string(), json!(author));
+    }
+    Message {
+        id: Some(entry.id.clone()),
+        sender_id: None,
+        content,
+        timestamp: entry.published.unwrap_or_else(Utc::now),
+        message_type: MessageType::Server,
+        status: MessageStatus::Delivered,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra,
+    }
+}
+
+/// What we know about one subscribed feed between polls.
+#[derive(Clone, Debug, Default)]
+struct FeedState {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    seen: HashSet<String>,
+    messages: Vec<Message>,
+}
+
+/// Polls feeds over HTTP, remembering the validators for conditional requests.
+#[derive(Clone)]
+struct FeedPoller {
+    http: reqwest::Client,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    feeds: Arc<StdMutex<HashMap<String, FeedState>>>,
+}
+
+impl FeedPoller {
+    /// Fetches a feed, or `None` when it has not changed since the last fetch.
+    async fn fetch(&self, url: &Url) -> Result<Option<Feed>, ConnectionError> {
+        let network = |e: reqwest::Error| ConnectionError::Network(e.to_string());
+        let (etag, last_modified) = self
+            .feeds
+            .lock()
+            .ok()
+            .and_then(|feeds| {
+                let state = feeds.get(url.as_str())?;
+                Some((state.etag.clone(), state.last_modified.clone()))
+            })
+            .unwrap_or_default();
+        let mut request = self.http.get(url.clone());
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request.send().await.map_err(network)?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(ConnectionError::Network(format!(
+                "{} answered {}",
+                url,
+                response.status()
+            )));
+        }
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let validators = (header(ETAG), header(LAST_MODIFIED));
+        let body = response.bytes().await.map_err(network)?;
+        let feed = parse_feed(&String::from_utf8_lossy(&body))?;
+        if let Ok(mut feeds) = self.feeds.lock() {
+            let state = feeds.entry(url.to_string()).or_default();
+            (state.etag, state.last_modified) = validators;
+        }
+        Ok(Some(feed))
+    }
+
+    /// Records a fetched feed and returns the entries not seen before. Ids that dropped out of
+    /// the feed are forgotten so the seen set stays the size of the feed.
+    fn update(&self, url: &Url, feed: &Feed) -> Vec<Message> {
+        let Ok(mut feeds) = self.feeds.lock() else {
+            return Vec::new();
+        };
+        let state = feeds.entry(url.to_string()).or_default();
+        let fresh: Vec<Message> = feed
+            .entries
+            .iter()
+            .filter(|entry| !state.seen.contains(&entry.id))
+            .map(entry_message)
+            .collect();
+        state.seen = feed.entries.iter().map(|entry| entry.id.clone()).collect();
+        state.messages = feed.entries.iter().map(entry_message).collect();
+        fresh
+    }
+
+    async fn poll(&self, url: &Url) {
+        match self.fetch(url).await {
+            Ok(Some(feed)) => {
+                for message in self.update(url, &feed) {
+                    let _ = self.event_tx.send(ConnectionEvent::Chat {
+                        event: ChatEvent::New {
+                            scope: Scope::channel(url.as_str()),
+                            message,
+                        },
+                    });
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                let _ = self.event_tx.send(ConnectionEvent::Status {
+                    event: StatusEvent::Error {
+                        message: format!("{}: {}", url, e),
+                    },
+                });
+            }
+        }
+    }
+}
+
+fn parse_feeds(feeds: &str) -> Result<Vec<Url>, ConnectionError> {
+    feeds
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|url| !url.is_empty())
+        .map(|url| {
+            Url::parse(url)
+                .map_err(|e| ConnectionError::Auth(format!("Invalid feed {}: {}", url, e)))
+        })
+        .collect()
+}
+
+/// A read-only view of RSS and Atom feeds. Each feed in the `feeds` field is a `Broadcast`
+/// channel keyed by its URL, and entries published after connecting arrive as
+/// `MessageType::Server` messages. The entries a feed held at connect are kept for history.
+pub struct RssConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    poll_interval: Duration,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    poller: Option<FeedPoller>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl RssConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        RssConnection {
+            auth: Vec::new(),
+            options,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            event_tx,
+            event_rx: Some(event_rx),
+            poller: None,
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    /// How often every feed is fetched, fifteen minutes by default.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+}
+
+impl Default for RssConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+#[async_trait]
+impl Connection for RssConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "rss.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let feeds = field_text(&self.auth, "feeds")
+            .ok_or_else(|| ConnectionError::Auth("Missing feeds".to_string()))?;
+        let urls = parse_feeds(&feeds)?;
+        if urls.is_empty() {
+            return Err(ConnectionError::Auth("Missing feeds".to_string()));
+        }
+
+        self.tasks.shutdown().await;
+        self.poller = None;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+        let poller = FeedPoller {
+            http: http_client(&self.options)?,
+            event_tx: self.event_tx.clone(),
+            feeds: Arc::new(StdMutex::new(HashMap::new())),
+        };
+        let mut fetched = Vec::new();
+        for url in &urls {
+            let feed = match self.options.connect_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, poller.fetch(url))
+                    .await
+                    .map_err(|_| ConnectionError::Timeout)
+                    .and_then(|feed| feed),
+                None => poller.fetch(url).await,
+            };
+            fetched.push(feed.map(|feed| feed.unwrap_or_default()));
+        }
+        // A feed that is down is reported and retried on the next poll, but if none of them
+        // can be read the configuration is likely wrong.
+        if fetched.iter().all(|feed| feed.is_err()) {
+            self.set_status(ConnectionStatus::Disconnected);
+            return Err(fetched.remove(0).unwrap_err());
+        }
+
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected { artifact: None },
+        });
+        for (url, feed) in urls.iter().zip(fetched) {
+            let feed = match feed {
+                Ok(feed) => feed,
+                Err(e) => {
+                    let _ = self.event_tx.send(ConnectionEvent::Status {
+                        event: StatusEvent::Error {
+                            message: format!("{}: {}", url, e),
+                        },
+                    });
+                    Feed::default()
+                }
+            };
+            poller.update(url, &feed);
+            let _ = self.event_tx.send(ConnectionEvent::Channel {
+                event: ChannelEvent::New {
+                    channel: Channel {
+                        id: url.to_string(),
+                        name: feed
+                            .title
+                            .or_else(|| url.host_str().map(|host| host.to_string())),
+                        channel_type: ChannelType::Broadcast,
+                        topic: feed.description,
+                        extra: HashMap::new(),
+                    },
+                },
+            });
+            let _ = self.event_tx.send(ConnectionEvent::Channel {
+                event: ChannelEvent::Join {
+                    channel_id: url.to_string(),
+                },
+            });
+        }
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Synced,
+        });
+        self.poller = Some(poller.clone());
+
+        let poll_interval = self.poll_interval;
+        self.tasks
+            .spawn_essential("poll", self.status.clone(), async move {
+                let mut poll = tokio::time::interval(poll_interval);
+                poll.tick().await;
+                loop {
+                    poll.tick().await;
+                    for url in &urls {
+                        poller.poll(url).await;
+                    }
+                }
+            });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.poller = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, _event: ConnectionEvent) -> Result<(), ConnectionError> {
+        Err(ConnectionError::Unsupported(
+            "Feeds are read-only".to_string(),
+        ))
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        let poller = self.poller.as_ref().ok_or(ConnectionError::Closed)?;
+        let messages = poller
+            .feeds
+            .lock()
+            .ok()
+            .and_then(|feeds| Some(feeds.get(channel_id)?.messages.clone()))
+            .ok_or_else(|| ConnectionError::Other(format!("Unknown feed {}", channel_id)))?;
+        let end = before
+            .and_then(|before| {
+                messages
+                    .iter()
+                    .position(|message| message.id.as_deref() == Some(before.as_str()))
+            })
+            .unwrap_or(messages.len());
+        Ok(messages[end.saturating_sub(limit)..end].to_vec())
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        Protocol {
+            name: "rss".to_string(),
+            auth: Some(vec![AuthField {
+                name: "feeds".to_string(),
+                display: Some("Feed URLs, separated by spaces or commas".to_string()),
+                value: FieldValue::Text(None),
+                required: true,
+            }]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            history: true,
+            multiple_channels: true,
+            ..Capabilities::default()
+        }
+    }
+
+    async fn preflight(&self, check_reachability: bool) -> PreflightReport {
+        let mut report = validate_auth(&self.protocol_spec(), &self.auth);
+        let urls = field_text(&self.auth, "feeds").and_then(|feeds| {
+            parse_feeds(&feeds)
+                .map_err(|e| report.error(Some("feeds"), e.to_string()))
+                .ok()
+        });
+        if check_reachability {
+            let first = urls.as_ref().and_then(|urls| urls.first());
+            if let Some((host, port)) =
+                first.and_then(|url| Some((url.host_str()?, url.port_or_known_default()?)))
+            {
+                let timeout = self
+                    .options
+                    .connect_timeout
+                    .unwrap_or(Duration::from_secs(5));
+                report.reachability = probe_reachability(host, port, timeout).await;
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,837 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{SinkExt, StreamExt};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use url::Url;
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{
+        mrkdwn::{parse_mrkdwn, render_mrkdwn},
+        trace::event,
+    },
+    AuthField, Capabilities, Channel, ChannelType, Connection, FieldValue, Message, MessageStatus,
+    MessageType, Presence, Profile, Protocol, ReactionKey,
+};
+
+use super::{
+    http::http_client, transport::connect_websocket, ChannelEvent, ChatEvent, ConnectionError,
+    ConnectionEvent, ConnectionOptions, Scope, SendHandle, SendOutcome, StatusEvent, Supervisor,
+    UserEvent,
+};
+
+const DEFAULT_API_URL: &str = "https://slack.com/api/";
+const CHANNEL_TYPES: &str = "public_channel,private_channel,mpim,im";
+const CHANNEL_PAGE_SIZE: usize = 200;
+
+/// A handle on the Slack Web API for one token.
+#[derive(Clone, Debug)]
+struct SlackApi {
+    http: reqwest::Client,
+    base: Url,
+    token: String,
+}
+
+impl SlackApi {
+    async fn call(&self, method: &str, body: Value) -> Result<Value, ConnectionError> {
+        let url = self
+            .base
+            .join(method)
+            .map_err(|e| ConnectionError::Other(e.to_string()))?;
+        let response = self
+            .http
+            .post(url)
+            .bearer_auth(&self.token)
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                "application/json; charset=utf-8",
+            )
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(1);
+            return Err(ConnectionError::RateLimited(Duration::from_secs(
+                retry_after,
+            )));
+        }
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        let body: Value =
+            serde_json::from_slice(&bytes).map_err(|e| ConnectionError::Protocol(e.to_string()))?;
+        if body["ok"].as_bool() == Some(true) {
+            return Ok(body);
+        }
+
+        let error = body["error"]
+            .as_str()
+            .unwrap_or("unknown_error")
+            .to_string();
+        Err(match error.as_str() {
+            "not_authed" | "invalid_auth" | "account_inactive" | "token_revoked"
+            | "token_expired" => ConnectionError::Auth(error),
+            "ratelimited" => ConnectionError::RateLimited(Duration::from_secs(1)),
+            _ => ConnectionError::Protocol(format!("{}: {}", method, error)),
+        })
+    }
+}
+
+/// Slack's message timestamps double as message ids: seconds since the epoch with a
+/// microsecond suffix.
+fn parse_ts(ts: &str) -> Option<DateTime<Utc>> {
+    let (secs, micros) = ts.split_once('.').unwrap_or((ts, "0"));
+    DateTime::from_timestamp(secs.parse().ok()?, micros.parse::<u32>().ok()? * 1000)
+}
+
+fn channel_from(value: &Value, team_id: Option<&str>) -> Option<Channel> {
+    let id = value["id"].as_str()?;
+    let channel_type =
+        if value["is_im"].as_bool() == Some(true) || value["is_mpim"].as_bool() == Some(true) {
+            ChannelType::Direct
+        } else {
+            ChannelType::Group
+        };
+    let mut extra = HashMap::new();
+    if let Some(team_id) = value["context_team_id"].as_str().or(team_id) {
+        extra.insert("team_id".to_string(), Value::String(team_id.to_string()));
+    }
+    Some(Channel {
+        id: id.to_string(),
+        name: value["name"]
+            .as_str()
+            .or(value["user"].as_str())
+            .map(str::to_string),
+        channel_type,
+        topic: value["topic"]["value"]
+            .as_str()
+            .filter(|topic| !topic.is_empty())
+            .map(str::to_string),
+        extra,
+    })
+}
+
+fn profile_from(user: &Value) -> Profile {
+    let profile = &user["profile"];
+    let display_name = profile["display_name"]
+        .as_str()
+        .filter(|name| !name.is_empty())
+        .or(profile["real_name"].as_str())
+        .map(str::to_string);
+    Profile {
+        id: user["id"].as_str().map(str::to_string),
+        username: user["name"].as_str().map(str::to_string),
+        display_name,
+        color: user["color"].as_str().and_then(|hex| {
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            Some([(value >> 16) as u8, (value >> 8) as u8, value as u8, 255])
+        }),
+        picture: profile["image_72"].as_str().map(str::to_string),
+        presence: user["presence"].as_str().map(|presence| match presence {
+            "active" => Presence::Online,
+            _ => Presence::Away,
+        }),
+        ..Profile::default()
+    }
+}
+
+/// Maps Slack message payloads onto oshatori for one bot user.
+#[derive(Clone, Debug)]
+struct SlackMapper {
+    user_id: String,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+}
+
+impl SlackMapper {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn message(&self, value: &Value) -> Option<Message> {
+        let ts = value["ts"].as_str()?;
+        let sender = value["user"]
+            .as_str()
+            .or(value["bot_id"].as_str())
+            .unwrap_or_default();
+        let message_type = match value["subtype"].as_str() {
+            Some("me_message") => MessageType::Action,
+            Some("channel_join" | "channel_leave" | "channel_topic" | "channel_name") => {
+                MessageType::Server
+            }
+            _ if sender == self.user_id => MessageType::CurrentUser,
+            _ => MessageType::Normal,
+        };
+        Some(Message {
+            id: Some(ts.to_string()),
+            sender_id: Some(sender.to_string()),
+            content: parse_mrkdwn(value["text"].as_str().unwrap_or_default()),
+            timestamp: parse_ts(ts).unwrap_or_else(Utc::now),
+            message_type,
+            status: if value["edited"].is_object() {
+                MessageStatus::Edited
+            } else {
+                MessageStatus::Delivered
+            },
+            reactions: Vec::new(),
+            reply_to: None,
+            thread_id: value["thread_ts"]
+                .as_str()
+                .filter(|thread_ts| *thread_ts != ts)
+                .map(str::to_string),
+            extra: HashMap::new(),
+        })
+    }
+
+    fn apply(&self, event: &Value) {
+        let channel_id = event["channel"]
+            .as_str()
+            .or(event["item"]["channel"].as_str())
+            .unwrap_or_default()
+            .to_string();
+        let scope = Scope::channel(channel_id.clone());
+        match event["type"].as_str().unwrap_or_default() {
+            "message" => match event["subtype"].as_str() {
+                Some("message_changed") => {
+                    let Some(new_message) = self.message(&event["message"]) else {
+                        return;
+                    };
+                    self.emit(ConnectionEvent::Chat {
+                        event: ChatEvent::Update {
+                            scope,
+                            message_id: new_message.id.clone().unwrap_or_default(),
+                            new_message,
+                        },
+                    });
+                }
+                Some("message_deleted") => {
+                    if let Some(message_id) = event["deleted_ts"].as_str() {
+                        self.emit(ConnectionEvent::Chat {
+                            event: ChatEvent::Remove {
+                                scope,
+                                message_id: message_id.to_string(),
+                            },
+                        });
+                    }
+                }
+                _ => {
+                    if let Some(message) = self.message(event) {
+                        self.emit(ConnectionEvent::Chat {
+                            event: ChatEvent::New { scope, message },
+                        });
+                    }
+                }
+            },
+            kind @ ("reaction_added" | "reaction_removed") => {
+                let (Some(message_id), Some(name), Some(user_id)) = (
+                    event["item"]["ts"].as_str(),
+                    event["reaction"].as_str(),
+                    event["user"].as_str(),
+                ) else {
+                    return;
+                };
+                let (message_id, user_id, key) = (
+                    message_id.to_string(),
+                    user_id.to_string(),
+                    ReactionKey::Emoji(name.to_string()),
+                );
+                self.emit(ConnectionEvent::Chat {
+                    event: if kind == "reaction_added" {
+                        ChatEvent::ReactionAdd {
+                            scope,
+                            message_id,
+                            user_id,
+                            key,
+                        }
+                    } else {
+                        ChatEvent::ReactionRemove {
+                            scope,
+                            message_id,
+                            user_id,
+                            key,
+                        }
+                    },
+                });
+            }
+            "member_joined_channel" => {
+                let Some(user_id) = event["user"].as_str() else {
+                    return;
+                };
+                if user_id == self.user_id {
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::Join { channel_id },
+                    });
+                    return;
+                }
+                self.emit(ConnectionEvent::User {
+                    event: UserEvent::New {
+                        scope,
+                        user: Profile {
+                            id: Some(user_id.to_string()),
+                            ..Profile::default()
+                        },
+                    },
+                });
+            }
+            "member_left_channel" => {
+                let Some(user_id) = event["user"].as_str() else {
+                    return;
+                };
+                self.emit(if user_id == self.user_id {
+                    ConnectionEvent::Channel {
+                        event: ChannelEvent::Leave { channel_id },
+                    }
+                } else {
+                    ConnectionEvent::User {
+                        event: UserEvent::Remove {
+                            scope,
+                            user_id: user_id.to_string(),
+                        },
+                    }
+                });
+            }
+            "channel_created" => {
+                if let Some(channel) = channel_from(&event["channel"], None) {
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::New { channel },
+                    });
+                }
+            }
+            "channel_rename" | "group_rename" => {
+                if let Some(new_channel) = channel_from(&event["channel"], None) {
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::Update {
+                            channel_id: new_channel.id.clone(),
+                            new_channel,
+                        },
+                    });
+                }
+            }
+            "channel_deleted" | "channel_archive" | "group_archive" => {
+                self.emit(ConnectionEvent::Channel {
+                    event: ChannelEvent::Remove { channel_id },
+                });
+            }
+            "user_change" => {
+                let user = profile_from(&event["user"]);
+                if let Some(user_id) = user.id.clone() {
+                    self.emit(ConnectionEvent::User {
+                        event: UserEvent::Update {
+                            scope: Scope::Global,
+                            user_id,
+                            new_user: user,
+                        },
+                    });
+                }
+            }
+            other => event!(trace, "unhandled Slack event {}", other),
+        }
+    }
+}
+
+/// A Slack bot over Socket Mode. Events arrive on the Socket Mode websocket opened with the
+/// app-level token, and everything outbound goes through the Web API with the bot token.
+/// Conversations are channels, DMs and group DMs are `Direct`, and thread replies carry their
+/// parent's `ts` as `thread_id`.
+#[derive(Debug)]
+pub struct SlackConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    api: Option<SlackApi>,
+    user_id: Option<String>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl SlackConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        SlackConnection {
+            auth: Vec::new(),
+            options,
+            event_tx,
+            event_rx: Some(event_rx),
+            api: None,
+            user_id: None,
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    /// The bot's Slack user id, once connected.
+    pub fn user_id(&self) -> Option<&str> {
+        self.user_id.as_deref()
+    }
+
+    fn api(&self) -> Result<&SlackApi, ConnectionError> {
+        self.api.as_ref().ok_or(ConnectionError::Closed)
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn fail_auth(&self, reason: String) -> ConnectionError {
+        self.set_status(ConnectionStatus::AuthFailed {
+            reason: reason.clone(),
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed {
+                reason: reason.clone(),
+            },
+        });
+        ConnectionError::Auth(reason)
+    }
+
+    async fn post_message(
+        &self,
+        scope: &Scope,
+        message: &Message,
+    ) -> Result<String, ConnectionError> {
+        let method = match message.message_type {
+            MessageType::Action => "chat.meMessage",
+            _ => "chat.postMessage",
+        };
+        let mut body = json!({
+            "channel": channel_of(scope)?,
+            "text": render_mrkdwn(&message.content),
+        });
+        if let Some(thread_id) = &message.thread_id {
+            body["thread_ts"] = json!(thread_id);
+        }
+        let response = self.api()?.call(method, body).await?;
+        Ok(response["ts"].as_str().unwrap_or_default().to_string())
+    }
+
+    async fn announce_channels(&self, team_id: Option<&str>) -> Result<(), ConnectionError> {
+        let mut cursor = String::new();
+        loop {
+            let response = self
+                .api()?
+                .call(
+                    "conversations.list",
+                    json!({
+                        "types": CHANNEL_TYPES,
+                        "exclude_archived": true,
+                        "limit": CHANNEL_PAGE_SIZE,
+                        "cursor": cursor,
+                    }),
+                )
+                .await?;
+            for value in response["channels"].as_array().into_iter().flatten() {
+                let Some(channel) = channel_from(value, team_id) else {
+                    continue;
+                };
+                let channel_id = channel.id.clone();
+                let _ = self.event_tx.send(ConnectionEvent::Channel {
+                    event: ChannelEvent::New { channel },
+                });
+                if value["is_member"].as_bool() == Some(true)
+                    || value["is_im"].as_bool() == Some(true)
+                {
+                    let _ = self.event_tx.send(ConnectionEvent::Channel {
+                        event: ChannelEvent::Join { channel_id },
+                    });
+                }
+            }
+            cursor = response["response_metadata"]["next_cursor"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            if cursor.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Default for SlackConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn channel_of(scope: &Scope) -> Result<&str, ConnectionError> {
+    scope.channel_id().ok_or_else(|| {
+        ConnectionError::Unsupported("Slack messages need a conversation".to_string())
+    })
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+#[async_trait]
+impl Connection for SlackConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "slack.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let app_token = field_text(&self.auth, "app_token")
+            .ok_or_else(|| ConnectionError::Auth("Missing app-level token".to_string()))?;
+        let bot_token = field_text(&self.auth, "bot_token")
+            .ok_or_else(|| ConnectionError::Auth("Missing bot token".to_string()))?;
+        let base = field_text(&self.auth, "api_url").unwrap_or(DEFAULT_API_URL.to_string());
+        let mut base = Url::parse(&base).map_err(|e| ConnectionError::Auth(e.to_string()))?;
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+
+        self.tasks.shutdown().await;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+
+        let http = http_client(&self.options)?;
+        let app = SlackApi {
+            http: http.clone(),
+            base: base.clone(),
+            token: app_token,
+        };
+        let bot = SlackApi {
+            http,
+            base,
+            token: bot_token,
+        };
+        let identity = match bot.call("auth.test", json!({})).await {
+            Err(ConnectionError::Auth(reason)) => return Err(self.fail_auth(reason)),
+            other => other?,
+        };
+        let socket_url = match app.call("apps.connections.open", json!({})).await {
+            Err(ConnectionError::Auth(reason)) => return Err(self.fail_auth(reason)),
+            other => other?,
+        };
+        let socket_url = socket_url["url"]
+            .as_str()
+            .and_then(|url| Url::parse(url).ok())
+            .ok_or_else(|| ConnectionError::Protocol("No Socket Mode URL".to_string()))?;
+        let mut socket = match connect_websocket(&socket_url, &self.options).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+
+        let user_id = identity["user_id"].as_str().unwrap_or_default().to_string();
+        let team_id = identity["team_id"].as_str().map(str::to_string);
+        self.api = Some(bot);
+        self.user_id = Some(user_id.clone());
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected {
+                artifact: identity["team"].as_str().map(str::to_string),
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::User {
+            event: UserEvent::Identify {
+                user_id: user_id.clone(),
+            },
+        });
+        self.announce_channels(team_id.as_deref()).await?;
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Synced,
+        });
+
+        let mapper = SlackMapper {
+            user_id,
+            event_tx: self.event_tx.clone(),
+        };
+        let status = self.status.clone();
+        self.tasks
+            .spawn_essential("socket", self.status.clone(), async move {
+                while let Some(frame) = socket.next().await {
+                    let text = match frame {
+                        Ok(WsMessage::Text(text)) => text,
+                        Ok(WsMessage::Close(_)) | Err(_) => break,
+                        Ok(_) => continue,
+                    };
+                    let Ok(envelope) = serde_json::from_str::<Value>(&text) else {
+                        event!(debug, "unparsed Socket Mode frame {:?}", text);
+                        continue;
+                    };
+                    if let Some(envelope_id) = envelope["envelope_id"].as_str() {
+                        let ack = json!({ "envelope_id": envelope_id }).to_string();
+                        if socket.send(WsMessage::Text(ack.into())).await.is_err() {
+                            break;
+                        }
+                    }
+                    match envelope["type"].as_str() {
+                        Some("events_api") => mapper.apply(&envelope["payload"]["event"]),
+                        Some("disconnect") => break,
+                        _ => {}
+                    }
+                }
+                if let Ok(mut current) = status.lock() {
+                    if *current == ConnectionStatus::Connected {
+                        *current = ConnectionStatus::Disconnected;
+                    }
+                }
+                mapper.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Disconnected {
+                        artifact: Some("Socket Mode connection closed".to_string()),
+                    },
+                });
+            });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.api = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        let (method, body) = match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } => return self.post_message(&scope, &message).await.map(|_| ()),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::Update {
+                        scope,
+                        message_id,
+                        new_message,
+                    },
+            } => (
+                "chat.update",
+                json!({
+                    "channel": channel_of(&scope)?,
+                    "ts": message_id,
+                    "text": render_mrkdwn(&new_message.content),
+                }),
+            ),
+            ConnectionEvent::Chat {
+                event: ChatEvent::Remove { scope, message_id },
+            } => (
+                "chat.delete",
+                json!({ "channel": channel_of(&scope)?, "ts": message_id }),
+            ),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::ReactionAdd {
+                        scope,
+                        message_id,
+                        key: ReactionKey::Emoji(name),
+                        ..
+                    },
+            } => (
+                "reactions.add",
+                json!({ "channel": channel_of(&scope)?, "timestamp": message_id, "name": name }),
+            ),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::ReactionRemove {
+                        scope,
+                        message_id,
+                        key: ReactionKey::Emoji(name),
+                        ..
+                    },
+            } => (
+                "reactions.remove",
+                json!({ "channel": channel_of(&scope)?, "timestamp": message_id, "name": name }),
+            ),
+            ConnectionEvent::Chat {
+                event:
+                    ChatEvent::ReadMarker {
+                        channel_id,
+                        message_id,
+                        ..
+                    },
+            } => (
+                "conversations.mark",
+                json!({ "channel": channel_id, "ts": message_id }),
+            ),
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Join { channel_id },
+            } => ("conversations.join", json!({ "channel": channel_id })),
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Leave { channel_id },
+            } => ("conversations.leave", json!({ "channel": channel_id })),
+            ConnectionEvent::Channel {
+                event:
+                    ChannelEvent::TopicChanged {
+                        channel_id, topic, ..
+                    },
+            } => (
+                "conversations.setTopic",
+                json!({ "channel": channel_id, "topic": topic.unwrap_or_default() }),
+            ),
+            _ => {
+                return Err(ConnectionError::Unsupported(
+                    "Event not supported over Slack".to_string(),
+                ))
+            }
+        };
+        self.api()?.call(method, body).await.map(|_| ())
+    }
+
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        let ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } = &event
+        else {
+            self.send(event).await?;
+            return Ok(SendHandle::completed(SendOutcome::Delivered {
+                message_id: None,
+            }));
+        };
+        let ts = self.post_message(scope, message).await?;
+        Ok(SendHandle::completed(SendOutcome::Delivered {
+            message_id: Some(ts),
+        }))
+    }
+
+    async fn fetch_profile(&mut self, user_id: &str) -> Result<Profile, ConnectionError> {
+        let response = self
+            .api()?
+            .call("users.info", json!({ "user": user_id }))
+            .await?;
+        Ok(profile_from(&response["user"]))
+    }
+
+    async fn fetch_history(
+        &mut self,
+        channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        let mut body = json!({ "channel": channel_id, "limit": limit });
+        if let Some(before) = before {
+            body["latest"] = json!(before);
+        }
+        let response = self.api()?.call("conversations.history", body).await?;
+        let mapper = SlackMapper {
+            user_id: self.user_id.clone().unwrap_or_default(),
+            event_tx: self.event_tx.clone(),
+        };
+        let mut messages: Vec<Message> = response["messages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|value| mapper.message(value))
+            .collect();
+        messages.reverse();
+        Ok(messages)
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        let response = self
+            .api()?
+            .call("conversations.open", json!({ "users": user_id }))
+            .await?;
+        let mut channel = channel_from(&response["channel"], None).ok_or_else(|| {
+            ConnectionError::Protocol("conversations.open returned no channel".to_string())
+        })?;
+        channel.channel_type = ChannelType::Direct;
+        channel.name.get_or_insert_with(|| user_id.to_string());
+        self.event_tx
+            .send(ConnectionEvent::Channel {
+                event: ChannelEvent::New {
+                    channel: channel.clone(),
+                },
+            })
+            .map_err(|_| ConnectionError::Closed)?;
+        Ok(channel)
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        let field = |name: &str, display: &str, value: FieldValue, required: bool| AuthField {
+            name: name.to_string(),
+            display: Some(display.to_string()),
+            value,
+            required,
+        };
+        Protocol {
+            name: "slack".to_string(),
+            auth: Some(vec![
+                field(
+                    "app_token",
+                    "App-level token (xapp-) with connections:write",
+                    FieldValue::Password(None),
+                    true,
+                ),
+                field(
+                    "bot_token",
+                    "Bot token (xoxb-)",
+                    FieldValue::Password(None),
+                    true,
+                ),
+                field("api_url", "Web API base URL", FieldValue::Text(None), false),
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            editing: true,
+            deletion: true,
+            history: true,
+            reactions: true,
+            multiple_channels: true,
+            topics: true,
+            max_message_length: Some(40_000),
+            ..Capabilities::default()
+        }
+    }
+}
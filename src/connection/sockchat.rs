@@ -1,13 +1,34 @@
 use std::str::FromStr;
 
 use crate::{
-    connection::{AssetEvent, ChannelEvent, ChatEvent, ConnectionEvent, StatusEvent, UserEvent},
-    utils::{assets::parse_assets, bbcode::parse_bbcode, color::kanii_to_rgba, html::parse_html},
-    Asset, AssetSource, AuthField, Channel, ChannelType, Connection, FieldValue, Message,
-    MessageStatus, MessageType, Profile, Protocol,
+    connection::{
+        delivery::{OutboundEntry, OutboundQueue},
+        http::http_client,
+        preflight::{probe_reachability, validate_auth},
+        ratelimit::TokenBucket,
+        supervisor::Supervisor,
+        transport::connect_websocket,
+        AssetEvent, ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, ConnectionOptions,
+        ConnectionStatus, ModerationEvent, PreflightReport, RateLimit, Scope, SendHandle,
+        SendOutcome, StatusEvent, ThrottleMode, UserEvent,
+    },
+    utils::{
+        assets::{get_id, parse_assets},
+        bbcode::{parse_bbcode, render_bbcode},
+        color::kanii_to_rgba,
+        compose::{plain_text, strip_media},
+        html::parse_html,
+        maintenance::{parse_maintenance_notice, DEFAULT_MAINTENANCE_PATTERN},
+        mentions::parse_mentions,
+        topic::parse_topic_announcement,
+        trace::event,
+    },
+    Asset, AssetSource, AuthField, Capabilities, Channel, ChannelType, CommandArg, CommandSpec,
+    Connection, FieldValue, Message, MessageStatus, MessageType, Permissions, Presence, Profile,
+    Protocol, Role,
 };
 use async_trait::async_trait;
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 use futures_util::{SinkExt, StreamExt};
 use kanii_lib::packets::{
     client::ClientPacket,
@@ -15,38 +36,465 @@ use kanii_lib::packets::{
         ChannelEventPacket, ChannelSwitchingPacket, ContextInformationPacket, JoinAuthPacket,
         ServerPacket,
     },
-    types::Sockchatable,
+    types::{DisconnectReason, MessageFlags, Sockchatable, UserPermissions},
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::Duration,
 };
-use std::sync::Arc;
-use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message as WsMessage};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex, OnceCell};
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
 use url::Url;
 
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(100);
+const PERMANENT_BAN_TIMESTAMP: i64 = 253402300799;
+const WHISPER_PREFIX: &str = "@whisper:";
+const AVATAR_CACHE_LIMIT: usize = 512;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SockchatSession {
+    pub user_id: Option<String>,
+    pub last_sequence: HashMap<String, String>,
+}
+
+impl SockchatSession {
+    fn seen(&self, channel_id: Option<&str>, sequence_id: &str) -> bool {
+        let Some(last) = channel_id.and_then(|c| self.last_sequence.get(c)) else {
+            return false;
+        };
+        match (last.parse::<u64>(), sequence_id.parse::<u64>()) {
+            (Ok(last), Ok(sequence)) => sequence <= last,
+            _ => last == sequence_id,
+        }
+    }
+
+    fn record(&mut self, channel_id: Option<&str>, sequence_id: &str) {
+        let Some(channel_id) = channel_id else {
+            return;
+        };
+        if !self.seen(Some(channel_id), sequence_id) {
+            self.last_sequence
+                .insert(channel_id.to_string(), sequence_id.to_string());
+        }
+    }
+}
+
+/// A deployment's display name and color for a sockchat permission rank.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RankRole {
+    pub name: String,
+    pub color: Option<[u8; 4]>,
+}
+
+/// Maps sockchat's numeric ranks to role names and colors. A user takes the entry for the
+/// highest configured rank not above their own.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SockchatRoles {
+    pub ranks: BTreeMap<i64, RankRole>,
+}
+
+impl SockchatRoles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rank(mut self, rank: i64, name: &str, color: Option<[u8; 4]>) -> Self {
+        self.ranks.insert(
+            rank,
+            RankRole {
+                name: name.to_string(),
+                color,
+            },
+        );
+        self
+    }
+
+    pub fn get(&self, rank: i64) -> Option<&RankRole> {
+        self.ranks.range(..=rank).next_back().map(|(_, role)| role)
+    }
+
+    /// Fills in `role`'s name and color from its rank, leaving it untouched if no entry applies.
+    pub fn apply(&self, role: &mut Role) {
+        if let Some(rank_role) = self.get(role.rank) {
+            role.name = Some(rank_role.name.clone());
+            role.color = rank_role.color;
+        }
+    }
+}
+
+/// Resources shared by `SockchatConnection`s that talk to the same server, so that several
+/// accounts fetch the emote list and each avatar once and send under one rate limit. Servers
+/// are keyed by their websocket URL; share the pool between connections with an `Arc`.
+#[derive(Debug, Default)]
+pub struct SockchatPool {
+    rate_limit: Option<RateLimit>,
+    servers: StdMutex<HashMap<String, Arc<ServerResources>>>,
+}
+
+type AvatarCell = Arc<OnceCell<Arc<[u8]>>>;
+
+#[derive(Debug)]
+struct ServerResources {
+    assets: Arc<Mutex<Vec<Asset>>>,
+    assets_loaded: Mutex<bool>,
+    avatars: StdMutex<HashMap<String, AvatarCell>>,
+    limiter: Option<(ThrottleMode, StdMutex<TokenBucket>)>,
+}
+
+impl SockchatPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits chat sends across every connection to the same server.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// The servers that connections have used this pool for.
+    pub fn servers(&self) -> Vec<String> {
+        self.servers
+            .lock()
+            .map(|servers| servers.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forgets `server`'s emote list and avatars so the next connect fetches them again.
+    pub fn invalidate(&self, server: &str) {
+        if let Ok(mut servers) = self.servers.lock() {
+            servers.remove(server);
+        }
+    }
+
+    fn server(&self, url: &Url) -> Arc<ServerResources> {
+        let mut servers = self.servers.lock().unwrap_or_else(|e| e.into_inner());
+        servers
+            .entry(url.to_string())
+            .or_insert_with(|| {
+                Arc::new(ServerResources {
+                    assets: Arc::new(Mutex::new(Vec::new())),
+                    assets_loaded: Mutex::new(false),
+                    avatars: StdMutex::new(HashMap::new()),
+                    limiter: self
+                        .rate_limit
+                        .clone()
+                        .map(|limit| (limit.mode.clone(), StdMutex::new(TokenBucket::new(limit)))),
+                })
+            })
+            .clone()
+    }
+}
+
+impl ServerResources {
+    fn avatar_cell(&self, url: &str) -> AvatarCell {
+        let mut avatars = self.avatars.lock().unwrap_or_else(|e| e.into_inner());
+        if avatars.len() >= AVATAR_CACHE_LIMIT && !avatars.contains_key(url) {
+            avatars.retain(|_, cell| !cell.initialized());
+        }
+        avatars.entry(url.to_string()).or_default().clone()
+    }
+}
+
+/// A command queued for the writer, tagged with the outbound entry it delivers, if any.
+#[derive(Clone, Debug)]
+struct Outgoing {
+    command: String,
+    entry_id: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct SockchatConnection {
     auth: Vec<AuthField>,
-    ws_tx: broadcast::Sender<WsMessage>,
+    options: ConnectionOptions,
+    ws_tx: broadcast::Sender<Outgoing>,
     event_tx: mpsc::UnboundedSender<ConnectionEvent>,
     event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
-    assets: Vec<Asset>,
-    tasks: Vec<tokio::task::JoinHandle<()>>,
+    assets: Arc<Mutex<Vec<Asset>>>,
+    users: Arc<Mutex<HashMap<String, Profile>>>,
+    outbound: Arc<OutboundQueue>,
+    moderation: Arc<Mutex<Vec<ModerationEvent>>>,
+    /// The limit the server announced on join, or 0 before it has.
+    max_message_length: Arc<AtomicUsize>,
+    session: Arc<Mutex<SockchatSession>>,
+    roles: Arc<SockchatRoles>,
+    pool: Option<Arc<SockchatPool>>,
+    server: Option<Arc<ServerResources>>,
+    http: Option<reqwest::Client>,
+    tasks: Supervisor,
+    closer: Option<tokio::task::JoinHandle<()>>,
+    closing: Arc<AtomicBool>,
+    status: Arc<StdMutex<ConnectionStatus>>,
     shutdown_tx: Option<oneshot::Sender<()>>,
 }
 
 impl SockchatConnection {
     pub fn new() -> Self {
-        let (ws_tx, _) = broadcast::channel::<WsMessage>(256);
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (ws_tx, _) = broadcast::channel::<Outgoing>(options.outbound_queue);
         let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
         SockchatConnection {
             auth: vec![],
+            options,
             ws_tx: ws_tx.clone(),
             event_tx,
             event_rx: Some(event_rx),
-            assets: Vec::new(),
-            tasks: Vec::new(),
+            assets: Arc::new(Mutex::new(Vec::new())),
+            users: Arc::new(Mutex::new(HashMap::new())),
+            outbound: Arc::new(OutboundQueue::default()),
+            moderation: Arc::new(Mutex::new(Vec::new())),
+            max_message_length: Arc::new(AtomicUsize::new(0)),
+            session: Arc::new(Mutex::new(SockchatSession::default())),
+            roles: Arc::new(SockchatRoles::default()),
+            pool: None,
+            server: None,
+            http: None,
+            tasks,
+            closer: None,
+            closing: Arc::new(AtomicBool::new(false)),
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
             shutdown_tx: None,
         }
     }
+
+    /// Sets the rank-to-role mapping applied to users from the next connect on.
+    pub fn with_roles(mut self, roles: SockchatRoles) -> Self {
+        self.roles = Arc::new(roles);
+        self
+    }
+
+    pub fn roles(&self) -> &SockchatRoles {
+        &self.roles
+    }
+
+    /// Shares emotes, avatars and the send rate limit with other connections using `pool`,
+    /// from the next connect on.
+    pub fn with_pool(mut self, pool: Arc<SockchatPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    pub fn pool(&self) -> Option<&Arc<SockchatPool>> {
+        self.pool.as_ref()
+    }
+
+    /// Downloads `user_id`'s avatar from the `pfp_url` template. With a pool, each avatar is
+    /// downloaded once per server and shared.
+    pub async fn fetch_avatar(&self, user_id: &str) -> Result<Arc<[u8]>, ConnectionError> {
+        let template = field_text(&self.auth, "pfp_url")
+            .filter(|_| !self.options.low_bandwidth)
+            .ok_or_else(|| ConnectionError::Unsupported("No avatar URL configured".to_string()))?;
+        let http = self.http.as_ref().ok_or(ConnectionError::Closed)?;
+        let url = template.replace("{uid}", user_id);
+        let download = async {
+            let response = http
+                .get(&url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| ConnectionError::Network(e.to_string()))?;
+            let bytes = response
+                .bytes()
+                .await
+                .map_err(|e| ConnectionError::Network(e.to_string()))?;
+            Ok::<_, ConnectionError>(Arc::from(bytes.as_ref()))
+        };
+        match &self.server {
+            Some(server) => server
+                .avatar_cell(&url)
+                .get_or_try_init(|| download)
+                .await
+                .cloned(),
+            None => download.await,
+        }
+    }
+
+    /// Waits for the pool's shared send rate limit, if it has one.
+    async fn throttle(&self) -> Result<(), ConnectionError> {
+        let Some((mode, bucket)) = self.server.as_ref().and_then(|s| s.limiter.as_ref()) else {
+            return Ok(());
+        };
+        loop {
+            let taken = bucket.lock().unwrap_or_else(|e| e.into_inner()).take();
+            let Err(wait) = taken else {
+                return Ok(());
+            };
+            let _ = self.event_tx.send(ConnectionEvent::Status {
+                event: StatusEvent::Throttled {
+                    retry_after_ms: wait.as_millis().min(u128::from(u64::MAX)) as u64,
+                },
+            });
+            if *mode == ThrottleMode::Reject || wait == Duration::MAX {
+                return Err(ConnectionError::RateLimited(wait));
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    pub async fn session(&self) -> SockchatSession {
+        self.session.lock().await.clone()
+    }
+
+    pub async fn resume(&mut self, session: SockchatSession) {
+        *self.session.lock().await = session;
+    }
+
+    async fn stop_tasks(&mut self) {
+        self.closing.store(true, Ordering::SeqCst);
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+        if let Some(mut closer) = self.closer.take() {
+            if tokio::time::timeout(CLOSE_TIMEOUT, &mut closer)
+                .await
+                .is_err()
+            {
+                closer.abort();
+            }
+        }
+        self.tasks.shutdown().await;
+    }
+
+    fn send_command(&self, command: String) -> Result<(), ConnectionError> {
+        self.queue_write(command, None)
+    }
+
+    fn queue_write(
+        &self,
+        command: String,
+        entry_id: Option<String>,
+    ) -> Result<(), ConnectionError> {
+        self.ws_tx
+            .send(Outgoing { command, entry_id })
+            .map(|_| ())
+            .map_err(|_| ConnectionError::Closed)
+    }
+
+    async fn send_moderation(&self, event: &ModerationEvent) -> Result<(), ConnectionError> {
+        let (scope, user_id) = match event {
+            ModerationEvent::Kick { scope, user_id, .. }
+            | ModerationEvent::Ban { scope, user_id, .. }
+            | ModerationEvent::Unban { scope, user_id }
+            | ModerationEvent::Mute { scope, user_id, .. }
+            | ModerationEvent::Unmute { scope, user_id } => (scope, user_id),
+        };
+        if !scope.is_global() {
+            return Err(ConnectionError::Unsupported(
+                "Channel-scoped moderation is not supported by sockchat".to_string(),
+            ));
+        }
+        if let ModerationEvent::Kick {
+            reason: Some(_), ..
+        }
+        | ModerationEvent::Ban {
+            reason: Some(_), ..
+        } = event
+        {
+            return Err(ConnectionError::Unsupported(
+                "Sockchat moderation commands cannot carry a reason".to_string(),
+            ));
+        }
+        let name = self
+            .users
+            .lock()
+            .await
+            .get(user_id)
+            .and_then(|user| user.username.clone())
+            .unwrap_or_else(|| user_id.clone());
+        let with_duration = |command: &str, until: &Option<DateTime<Utc>>| match until {
+            Some(until) => format!(
+                "/{} {} {}",
+                command,
+                name,
+                (*until - Utc::now()).num_seconds().max(1)
+            ),
+            None => format!("/{} {}", command, name),
+        };
+
+        self.send_command(match event {
+            ModerationEvent::Kick { .. } => format!("/kick {}", name),
+            ModerationEvent::Ban { until, .. } => with_duration("ban", until),
+            ModerationEvent::Unban { .. } => format!("/pardon {}", name),
+            ModerationEvent::Mute { until, .. } => with_duration("silence", until),
+            ModerationEvent::Unmute { .. } => format!("/unsilence {}", name),
+        })?;
+        // Reported once the server confirms it; see `confirm_moderation`.
+        self.moderation.lock().await.push(event.clone());
+        Ok(())
+    }
+
+    async fn username_of(&self, user_id: &str) -> Result<String, ConnectionError> {
+        self.users
+            .lock()
+            .await
+            .get(user_id)
+            .and_then(|user| user.username.clone())
+            .ok_or_else(|| ConnectionError::NotFound(format!("user {}", user_id)))
+    }
+
+    async fn send_chat(
+        &self,
+        scope: Scope,
+        message: Message,
+        tx: Option<oneshot::Sender<SendOutcome>>,
+    ) -> Result<(), ConnectionError> {
+        let content = resolve_assets(&message.content, &self.assets.lock().await)?;
+        let text = render_bbcode(&content);
+        if text.is_empty() {
+            return Err(ConnectionError::Unsupported(
+                "Unsupported message format".to_string(),
+            ));
+        }
+        self.throttle().await?;
+        let echo = text.trim().to_string();
+        let payload = if message.message_type == MessageType::Action {
+            format!("/me {}", text)
+        } else {
+            text
+        };
+        let payload = match scope
+            .channel_id()
+            .and_then(|id| id.strip_prefix(WHISPER_PREFIX))
+        {
+            Some(user_id) => format!("/msg {} {}", self.username_of(user_id).await?, payload),
+            None => payload,
+        };
+
+        let id = message
+            .id
+            .clone()
+            .unwrap_or_else(|| self.options.ids.next_id());
+        self.outbound
+            .push(OutboundEntry::new(id.clone(), scope, message, echo, tx))
+            .await;
+        if let Err(e) = self.queue_write(payload, Some(id.clone())) {
+            if let Some(entry) = self.outbound.remove(&id).await {
+                let _ = self.event_tx.send(entry.fail(&e.to_string()));
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+impl Default for SockchatConnection {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 unsafe impl Send for SockchatConnection {}
@@ -54,17 +502,23 @@ unsafe impl Sync for SockchatConnection {}
 
 #[async_trait]
 impl Connection for SockchatConnection {
-    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), String> {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
         self.auth = auth;
         Ok(())
     }
 
-    async fn connect(&mut self) -> Result<(), String> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "sockchat.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
         let mut url = None;
         let mut token = None;
         let mut uid = None;
         let mut pfp_url = None;
         let mut asset_api = None;
+        let mut topic_pattern = None;
+        let mut maintenance_pattern = None;
 
         for field in &self.auth {
             match field.name.as_str() {
@@ -93,92 +547,91 @@ impl Connection for SockchatConnection {
                         asset_api = Some(value);
                     }
                 }
+                "topic_pattern" => {
+                    if let FieldValue::Text(Some(value)) = field.value.clone() {
+                        topic_pattern = Some(value);
+                    }
+                }
+                "maintenance_pattern" => {
+                    if let FieldValue::Text(Some(value)) = field.value.clone() {
+                        maintenance_pattern = Some(value);
+                    }
+                }
                 _ => {}
             }
         }
 
-        let url = url.ok_or("Missing URL field")?;
-        let token = token.ok_or("Missing Token field")?;
-        let uid = uid.ok_or("Missing UID field")?;
+        if self.options.low_bandwidth {
+            pfp_url = None;
+            asset_api = None;
+        }
 
-        let url = Url::parse(&url).map_err(|e| e.to_string())?;
-        let (ws_stream, _) = connect_async(url.to_string())
-            .await
-            .map_err(|e| e.to_string())?;
+        let url = url.ok_or(ConnectionError::Auth("Missing URL field".to_string()))?;
+        let token = token.ok_or(ConnectionError::Auth("Missing Token field".to_string()))?;
+        let uid = uid.ok_or(ConnectionError::Auth("Missing UID field".to_string()))?;
+        let topic_pattern = topic_pattern
+            .map(|pattern| Regex::new(&pattern))
+            .transpose()
+            .map_err(|e| ConnectionError::Config(format!("Invalid topic pattern: {}", e)))?;
+        let maintenance_pattern = Regex::new(
+            maintenance_pattern
+                .as_deref()
+                .unwrap_or(DEFAULT_MAINTENANCE_PATTERN),
+        )
+        .map_err(|e| ConnectionError::Config(format!("Invalid maintenance pattern: {}", e)))?;
+
+        let url = Url::parse(&url).map_err(|e| ConnectionError::Auth(e.to_string()))?;
+        let http = http_client(&self.options)?;
+
+        self.stop_tasks().await;
+        self.closing = Arc::new(AtomicBool::new(false));
+
+        set_status(&self.status, ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+        let handshake = connect_websocket(&url, &self.options);
+        let ws_stream = match self.options.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, handshake)
+                .await
+                .map_err(|_| ConnectionError::Timeout),
+            None => Ok(handshake.await),
+        }
+        .and_then(|result| result)
+        .inspect_err(|e| {
+            set_status(&self.status, ConnectionStatus::Disconnected);
+            let _ = self.event_tx.send(ConnectionEvent::Status {
+                event: StatusEvent::Error {
+                    message: e.to_string(),
+                },
+            });
+        })?;
         let (write, mut read) = ws_stream.split();
 
         let tx = self.ws_tx.clone();
         let mut rx = tx.subscribe();
         let event_tx = self.event_tx.clone();
 
-        if let Some(mut api) = asset_api {
-            if api.ends_with('/') {
-                api.pop();
-            }
-            match reqwest::Client::new()
-                .get(format!("{}/{}", api, "emotes"))
-                .query(&[("fields", "uri,strings,min_rank")])
-                .send()
-                .await
-            {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        match response.text().await {
-                            Ok(text) => {
-                                if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text) {
-                                    if let Some(emotes) = json.as_array() {
-                                        for emote in emotes {
-                                            if let (Some(uri), Some(strings)) =
-                                                (emote.get("uri"), emote.get("strings"))
-                                            {
-                                                if let (Some(uri_str), Some(strings_array)) =
-                                                    (uri.as_str(), strings.as_array())
-                                                {
-                                                    let keys: Vec<String> = strings_array
-                                                        .iter()
-                                                        .filter_map(|s| {
-                                                            s.as_str().map(|s| s.to_string())
-                                                        })
-                                                        .collect();
-
-                                                    if !keys.is_empty() {
-                                                        let escaped_keys: Vec<String> = keys
-                                                            .iter()
-                                                            .map(|k| regex::escape(k))
-                                                            .collect();
-                                                        let pattern = format!(
-                                                            r":(?:{}):",
-                                                            escaped_keys.join("|")
-                                                        );
-
-                                                        let id = keys.first().cloned();
-
-                                                        let asset = Asset::Emote {
-                                                            id,
-                                                            pattern,
-                                                            src: uri_str.to_string(),
-                                                            source: AssetSource::Server,
-                                                        };
-
-                                                        self.assets.push(asset.clone());
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                dbg!(e);
-                            }
+        self.server = self.pool.as_ref().map(|pool| pool.server(&url));
+        if let Some(api) = asset_api {
+            match &self.server {
+                Some(server) => {
+                    self.assets = server.assets.clone();
+                    let mut loaded = server.assets_loaded.lock().await;
+                    if !*loaded {
+                        if let Some(emotes) = fetch_emotes(&http, &api).await {
+                            *self.assets.lock().await = emotes;
+                            *loaded = true;
                         }
                     }
                 }
-                Err(e) => {
-                    dbg!(e);
+                None => {
+                    let emotes = fetch_emotes(&http, &api).await;
+                    *self.assets.lock().await = emotes.unwrap_or_default();
                 }
             }
         }
+        self.http = Some(http);
 
         let auth_packet = ClientPacket::Authentication(
             kanii_lib::packets::client::authentication::AuthenticationPacket {
@@ -188,485 +641,772 @@ impl Connection for SockchatConnection {
         );
 
         let channel_assets = self.assets.clone();
-        let task = tokio::spawn(async move {
-            let mut current_channel: Option<String> = None;
-            let mut assets_sent = false;
-            while let Some(msg) = read.next().await {
-                if let Ok(msg) = msg {
-                    if let Ok(sockpacket) =
-                        ServerPacket::from_str(parse_html(msg.to_string()).as_str())
-                    {
-                        match sockpacket {
-                            ServerPacket::Pong(packet) => {
-                                let event = ConnectionEvent::Status {
-                                    event: StatusEvent::Ping {
-                                        artifact: Some(packet.text),
-                                    },
-                                };
-                                let _ = event_tx.send(event);
+        let low_bandwidth = self.options.low_bandwidth;
+        let users = self.users.clone();
+        let outbound = self.outbound.clone();
+        let moderation = self.moderation.clone();
+        let max_message_length = self.max_message_length.clone();
+        let session = self.session.clone();
+        let roles = self.roles.clone();
+        let closing = self.closing.clone();
+        let status = self.status.clone();
+        moderation.lock().await.clear();
+        self.tasks
+            .spawn_essential("reader", self.status.clone(), async move {
+                let mut current_channel: Option<String> = None;
+                let mut self_id: Option<String> = None;
+                let mut assets_sent = false;
+                while let Some(msg) = read.next().await {
+                    if let Ok(msg) = msg {
+                        let parsed = ServerPacket::from_str(parse_html(msg.to_string()).as_str());
+                        match &parsed {
+                            Ok(packet) => event!(trace, "received packet: {:?}", packet),
+                            Err(e) => {
+                                event!(debug, "unparsed packet {:?}: {:?}", msg.to_string(), e)
                             }
-
-                            ServerPacket::JoinAuth(packet) => match packet {
-                                JoinAuthPacket::GoodAuth {
-                                    user_id,
-                                    username,
-                                    color,
-                                    channel_name,
-                                    ..
-                                } => {
-                                    current_channel.replace(channel_name.clone());
-
+                        }
+                        if let Ok(sockpacket) = parsed {
+                            match sockpacket {
+                                ServerPacket::Pong(packet) => {
                                     let event = ConnectionEvent::Status {
-                                        event: StatusEvent::Connected { artifact: None },
+                                        event: StatusEvent::Ping {
+                                            artifact: Some(packet.text),
+                                        },
                                     };
                                     let _ = event_tx.send(event);
+                                }
 
-                                    let event = ConnectionEvent::Channel {
-                                        event: ChannelEvent::New {
-                                            channel: Channel {
-                                                id: current_channel.clone().unwrap(),
-                                                name: current_channel.clone(),
-                                                channel_type: ChannelType::Group,
+                                ServerPacket::JoinAuth(packet) => match packet {
+                                    JoinAuthPacket::GoodAuth {
+                                        user_id,
+                                        username,
+                                        color,
+                                        user_permissions,
+                                        channel_name,
+                                        max_msg_length,
+                                    } => {
+                                        self_id = Some(user_id.clone());
+                                        max_message_length.store(
+                                            usize::try_from(max_msg_length).unwrap_or(0),
+                                            Ordering::Relaxed,
+                                        );
+                                        {
+                                            let mut session = session.lock().await;
+                                            if session.user_id.as_ref() != Some(&user_id) {
+                                                *session = SockchatSession {
+                                                    user_id: Some(user_id.clone()),
+                                                    last_sequence: HashMap::new(),
+                                                };
+                                            }
+                                        }
+                                        set_status(&status, ConnectionStatus::Connected);
+                                        current_channel.replace(channel_name.clone());
+
+                                        let event = ConnectionEvent::Status {
+                                            event: StatusEvent::Connected { artifact: None },
+                                        };
+                                        let _ = event_tx.send(event);
+
+                                        let event = ConnectionEvent::Channel {
+                                            event: ChannelEvent::New {
+                                                channel: Channel {
+                                                    id: current_channel.clone().unwrap(),
+                                                    name: current_channel.clone(),
+                                                    channel_type: ChannelType::Group,
+                                                    topic: None,
+                                                    extra: HashMap::new(),
+                                                },
                                             },
-                                        },
-                                    };
-                                    let _ = event_tx.send(event);
+                                        };
+                                        let _ = event_tx.send(event);
 
-                                    let event = ConnectionEvent::Channel {
-                                        event: ChannelEvent::Join {
-                                            channel_id: current_channel.clone().unwrap(),
-                                        },
-                                    };
-                                    let _ = event_tx.send(event);
+                                        let event = ConnectionEvent::Channel {
+                                            event: ChannelEvent::Join {
+                                                channel_id: current_channel.clone().unwrap(),
+                                            },
+                                        };
+                                        let _ = event_tx.send(event);
 
-                                    let event = ConnectionEvent::Channel {
-                                        event: ChannelEvent::Switch {
-                                            channel_id: current_channel.clone().unwrap(),
-                                        },
-                                    };
-                                    let _ = event_tx.send(event);
+                                        let event = ConnectionEvent::Channel {
+                                            event: ChannelEvent::Switch {
+                                                channel_id: current_channel.clone().unwrap(),
+                                            },
+                                        };
+                                        let _ = event_tx.send(event);
 
-                                    let pic = {
+                                        let pic = pfp_url.clone().map(|pfp_format| {
+                                            pfp_format.replace("{uid}", user_id.as_str())
+                                        });
+
+                                        let event = ConnectionEvent::User {
+                                            event: UserEvent::New {
+                                                scope: current_channel.clone().into(),
+                                                user: Profile {
+                                                    id: Some(user_id.clone()),
+                                                    username: Some(username),
+                                                    display_name: None,
+                                                    color: kanii_to_rgba(color),
+                                                    picture: pic,
+                                                    presence: Some(Presence::Online),
+                                                    role: Some(sockchat_role(
+                                                        &user_permissions,
+                                                        &roles,
+                                                    )),
+                                                    extra: HashMap::new(),
+                                                },
+                                            },
+                                        };
+                                        remember_user(&users, &event).await;
+                                        let _ = event_tx.send(event);
+
+                                        let event = ConnectionEvent::User {
+                                            event: UserEvent::Identify {
+                                                user_id: user_id.clone(),
+                                            },
+                                        };
+                                        let _ = event_tx.send(event);
+
+                                        let assets = channel_assets.lock().await.clone();
+                                        if !assets_sent && !assets.is_empty() {
+                                            for asset in &assets {
+                                                let asset_event = AssetEvent::New {
+                                                    scope: current_channel.clone().into(),
+                                                    asset: asset.clone(),
+                                                };
+                                                let connection_event =
+                                                    ConnectionEvent::Asset { event: asset_event };
+                                                let _ = event_tx.send(connection_event);
+                                            }
+                                            assets_sent = true;
+                                        }
+                                    }
+                                    JoinAuthPacket::BadAuth { reason, timestamp } => {
+                                        let reason = format!("{}: {}", timestamp, reason);
+                                        set_status(
+                                            &status,
+                                            ConnectionStatus::AuthFailed {
+                                                reason: reason.clone(),
+                                            },
+                                        );
+                                        let event = ConnectionEvent::Status {
+                                            event: StatusEvent::AuthFailed { reason },
+                                        };
+                                        let _ = event_tx.send(event);
+                                    }
+                                    JoinAuthPacket::Join {
+                                        timestamp,
+                                        user_id,
+                                        username,
+                                        color,
+                                        user_permissions,
+                                        sequence_id,
+                                    } => {
+                                        let mut pic = None;
                                         if let Some(pfp_format) = pfp_url.clone() {
-                                            Some(pfp_format.replace("{uid}", user_id.as_str()))
-                                        } else {
-                                            None
+                                            pic =
+                                                Some(pfp_format.replace("{uid}", user_id.as_str()));
                                         }
-                                    };
-
-                                    let event = ConnectionEvent::User {
-                                        event: UserEvent::New {
-                                            channel_id: current_channel.clone(),
-                                            user: Profile {
-                                                id: Some(user_id.clone()),
-                                                username: Some(username),
-                                                display_name: None,
-                                                color: kanii_to_rgba(color),
-                                                picture: pic,
+                                        let event = ConnectionEvent::User {
+                                            event: UserEvent::New {
+                                                scope: current_channel.clone().into(),
+                                                user: crate::Profile {
+                                                    id: Some(user_id.clone()),
+                                                    username: Some(username.clone()),
+                                                    display_name: None,
+                                                    color: kanii_to_rgba(color),
+                                                    picture: pic,
+                                                    presence: Some(Presence::Online),
+                                                    role: Some(sockchat_role(
+                                                        &user_permissions,
+                                                        &roles,
+                                                    )),
+                                                    extra: HashMap::new(),
+                                                },
                                             },
-                                        },
-                                    };
-                                    let _ = event_tx.send(event);
+                                        };
+                                        remember_user(&users, &event).await;
+                                        let _ = event_tx.send(event);
 
-                                    let event = ConnectionEvent::User {
-                                        event: UserEvent::Identify {
-                                            user_id: user_id.clone(),
-                                        },
-                                    };
-                                    let _ = event_tx.send(event);
+                                        let join_msg = ConnectionEvent::Chat {
+                                            event: ChatEvent::New {
+                                                scope: current_channel.clone().into(),
+                                                message: Message {
+                                                    id: Some(sequence_id),
+                                                    sender_id: Some("-1".to_string()),
+                                                    content: vec![crate::MessageFragment::Text(
+                                                        format!("{} joined", username),
+                                                    )],
+                                                    timestamp: DateTime::from_timestamp_nanos(
+                                                        timestamp * 1_000_000_000,
+                                                    ),
+                                                    message_type: MessageType::Server,
+                                                    status: MessageStatus::Delivered,
+                                                    reactions: Vec::new(),
+                                                    reply_to: None,
+                                                    thread_id: None,
+                                                    extra: HashMap::new(),
+                                                },
+                                            },
+                                        };
+                                        let _ = event_tx.send(join_msg);
+                                    }
+                                },
 
-                                    if !assets_sent && !channel_assets.is_empty() {
-                                        for asset in &channel_assets {
-                                            let asset_event = AssetEvent::New {
-                                                channel_id: current_channel.clone(),
-                                                asset: asset.clone(),
+                                ServerPacket::ChatMessage(packet) => {
+                                    if packet.user_id == "-1" {
+                                        let reply = bot_reply(&packet.message);
+                                        let confirmed = match reply {
+                                            Some(("0", SILENCE_OK)) => {
+                                                confirm_moderation(&moderation, |event| {
+                                                    matches!(event, ModerationEvent::Mute { .. })
+                                                })
+                                                .await
+                                            }
+                                            Some(("0", UNSILENCE_OK)) => {
+                                                confirm_moderation(&moderation, |event| {
+                                                    matches!(event, ModerationEvent::Unmute { .. })
+                                                })
+                                                .await
+                                            }
+                                            Some(("0", PARDON_OK)) => {
+                                                confirm_moderation(&moderation, |event| {
+                                                    matches!(event, ModerationEvent::Unban { .. })
+                                                })
+                                                .await
+                                            }
+                                            _ => None,
+                                        };
+                                        if let Some(event) = confirmed {
+                                            let _ = event_tx.send(event);
+                                        }
+                                        if let Some(error) = bot_error(&packet.message) {
+                                            let refused = match reply {
+                                                Some((_, id))
+                                                    if MODERATION_ERRORS.contains(&id) =>
+                                                {
+                                                    let mut pending = moderation.lock().await;
+                                                    (!pending.is_empty()).then(|| pending.remove(0))
+                                                }
+                                                _ => None,
+                                            };
+                                            let event = match refused {
+                                                Some(_) => Some(ConnectionEvent::Status {
+                                                    event: StatusEvent::Error {
+                                                        message: format!(
+                                                            "Moderation refused: {}",
+                                                            error
+                                                        ),
+                                                    },
+                                                }),
+                                                None => outbound.fail_latest(&error).await,
                                             };
-                                            let connection_event =
-                                                ConnectionEvent::Asset { event: asset_event };
-                                            let _ = event_tx.send(connection_event);
+                                            if let Some(event) = event {
+                                                let _ = event_tx.send(event);
+                                            }
+                                        }
+                                        let text = plain_text(&parse_bbcode(&packet.message));
+                                        if let Some(notice) = parse_maintenance_notice(
+                                            &maintenance_pattern,
+                                            &text,
+                                            Utc::now(),
+                                        ) {
+                                            let _ = event_tx.send(ConnectionEvent::Status {
+                                                event: StatusEvent::Maintenance {
+                                                    message: Some(text.trim().to_string()),
+                                                    scheduled_at: notice.scheduled_at,
+                                                    until: notice.until,
+                                                },
+                                            });
                                         }
-                                        assets_sent = true;
                                     }
-                                }
-                                JoinAuthPacket::BadAuth { reason, timestamp } => {
-                                    let event = ConnectionEvent::Status {
-                                        event: StatusEvent::Disconnected {
-                                            artifact: Some(format!("{}: {}", timestamp, reason)),
-                                        },
-                                    };
-                                    let _ = event_tx.send(event);
-                                }
-                                JoinAuthPacket::Join {
-                                    timestamp,
-                                    user_id,
-                                    username,
-                                    color,
-                                    user_permissions: _,
-                                    sequence_id,
-                                } => {
-                                    let mut pic = None;
-                                    if let Some(pfp_format) = pfp_url.clone() {
-                                        pic = Some(pfp_format.replace("{uid}", user_id.as_str()));
+                                    let announcement = topic_pattern
+                                        .as_ref()
+                                        .filter(|_| packet.user_id == "-1")
+                                        .zip(current_channel.clone())
+                                        .and_then(|(pattern, channel_id)| {
+                                            let text = plain_text(&parse_bbcode(&packet.message));
+                                            parse_topic_announcement(pattern, &text)
+                                                .map(|announcement| (channel_id, announcement))
+                                        });
+                                    if let Some((channel_id, announcement)) = announcement {
+                                        session.lock().await.record(
+                                            current_channel.as_deref(),
+                                            &packet.sequence_id,
+                                        );
+                                        let _ = event_tx.send(ConnectionEvent::Channel {
+                                            event: ChannelEvent::TopicChanged {
+                                                channel_id,
+                                                topic: announcement.topic,
+                                                set_by: announcement.set_by,
+                                            },
+                                        });
+                                        continue;
                                     }
-                                    let event = ConnectionEvent::User {
-                                        event: UserEvent::New {
-                                            channel_id: current_channel.to_owned(),
-                                            user: crate::Profile {
-                                                id: Some(user_id.clone()),
-                                                username: Some(username.clone()),
-                                                display_name: None,
-                                                color: kanii_to_rgba(color),
-                                                picture: pic,
+
+                                    let parsed_content =
+                                        parse_content(&packet.message, &channel_assets, &users)
+                                            .await;
+                                    let parsed_content = if low_bandwidth {
+                                        strip_media(&parsed_content)
+                                    } else {
+                                        parsed_content
+                                    };
+
+                                    session
+                                        .lock()
+                                        .await
+                                        .record(current_channel.as_deref(), &packet.sequence_id);
+                                    let private = packet.message_flags.private;
+                                    let scope = if self_id.as_ref() == Some(&packet.user_id) {
+                                        let echo = echo_text(&packet.message);
+                                        let sent_to = outbound
+                                            .acknowledge(packet.sequence_id.clone(), |entry| {
+                                                let target = entry.scope().channel_id();
+                                                let whisper = target.is_some_and(|id| {
+                                                    id.starts_with(WHISPER_PREFIX)
+                                                });
+                                                entry.echo() == echo
+                                                    && whisper == private
+                                                    && (private
+                                                        || target.is_none()
+                                                        || target == current_channel.as_deref())
+                                            })
+                                            .await;
+                                        match sent_to {
+                                            // Our own whispers belong with the recipient.
+                                            Some(scope) if private => scope,
+                                            None if private => {
+                                                event!(
+                                                    debug,
+                                                    "dropping whisper echo {} with no pending send",
+                                                    packet.sequence_id
+                                                );
+                                                continue;
+                                            }
+                                            _ => current_channel.clone().into(),
+                                        }
+                                    } else if private && packet.user_id != "-1" {
+                                        Scope::Channel(whisper_channel_id(&packet.user_id))
+                                    } else {
+                                        current_channel.clone().into()
+                                    };
+                                    let event = ConnectionEvent::Chat {
+                                        event: ChatEvent::New {
+                                            scope,
+                                            message: Message {
+                                                id: Some(packet.sequence_id.clone()),
+                                                sender_id: Some(packet.user_id.clone()),
+                                                content: parsed_content,
+                                                timestamp: DateTime::from_timestamp_nanos(
+                                                    packet.timestamp * 1_000_000_000,
+                                                ),
+                                                message_type: message_type(
+                                                    &packet.user_id,
+                                                    &packet.message_flags,
+                                                ),
+                                                status: MessageStatus::Delivered,
+                                                reactions: Vec::new(),
+                                                reply_to: None,
+                                                thread_id: None,
+                                                extra: HashMap::new(),
                                             },
                                         },
                                     };
                                     let _ = event_tx.send(event);
+                                }
 
-                                    let join_msg = ConnectionEvent::Chat {
+                                ServerPacket::UserDisconnect(packet) => {
+                                    if matches!(packet.reason, DisconnectReason::Kick) {
+                                        let kicked = confirm_moderation(&moderation, |event| {
+                                            matches!(
+                                                event,
+                                                ModerationEvent::Kick { user_id, .. }
+                                                    | ModerationEvent::Ban { user_id, .. }
+                                                    if *user_id == packet.user_id
+                                            )
+                                        })
+                                        .await;
+                                        if let Some(event) = kicked {
+                                            let _ = event_tx.send(event);
+                                        }
+                                    }
+                                    let leave_msg = ConnectionEvent::Chat {
                                         event: ChatEvent::New {
-                                            channel_id: current_channel.clone(),
+                                            scope: current_channel.clone().into(),
                                             message: Message {
-                                                id: Some(sequence_id),
+                                                id: Some(packet.sequence_id.clone()),
                                                 sender_id: Some("-1".to_string()),
                                                 content: vec![crate::MessageFragment::Text(
-                                                    format!("{} joined", username),
+                                                    format!("{} left", packet.username),
                                                 )],
                                                 timestamp: DateTime::from_timestamp_nanos(
-                                                    timestamp * 1_000_000_000,
+                                                    packet.timestamp * 1_000_000_000,
                                                 ),
                                                 message_type: MessageType::Server,
                                                 status: MessageStatus::Delivered,
+                                                reactions: Vec::new(),
+                                                reply_to: None,
+                                                thread_id: None,
+                                                extra: HashMap::new(),
                                             },
                                         },
                                     };
-                                    let _ = event_tx.send(join_msg);
-                                }
-                            },
-
-                            ServerPacket::ChatMessage(packet) => {
-                                let content = parse_bbcode(packet.message.as_str());
-
-                                let mut parsed_content = Vec::new();
-                                for fragment in content {
-                                    match fragment {
-                                        crate::MessageFragment::Text(text) => {
-                                            let asset_parsed = parse_assets(&text, &channel_assets);
-                                            parsed_content.extend(asset_parsed);
-                                        }
-                                        other => parsed_content.push(other),
-                                    }
-                                }
-
-                                let event = ConnectionEvent::Chat {
-                                    event: ChatEvent::New {
-                                        channel_id: current_channel.clone(),
-                                        message: Message {
-                                            id: Some(packet.sequence_id),
-                                            sender_id: Some(packet.user_id.clone()),
-                                            content: parsed_content,
-                                            timestamp: DateTime::from_timestamp_nanos(
-                                                packet.timestamp * 1_000_000_000,
-                                            ),
-                                            message_type: if packet.user_id == "-1" {
-                                                MessageType::Server
-                                            } else {
-                                                MessageType::Normal
-                                            },
-                                            status: MessageStatus::Delivered,
-                                        },
-                                    },
-                                };
-                                let _ = event_tx.send(event);
-                            }
-
-                            ServerPacket::UserDisconnect(packet) => {
-                                let leave_msg = ConnectionEvent::Chat {
-                                    event: ChatEvent::New {
-                                        channel_id: current_channel.clone(),
-                                        message: Message {
-                                            id: Some(packet.sequence_id.clone()),
-                                            sender_id: Some("-1".to_string()),
-                                            content: vec![crate::MessageFragment::Text(format!(
-                                                "{} left",
-                                                packet.username
-                                            ))],
-                                            timestamp: DateTime::from_timestamp_nanos(
-                                                packet.timestamp * 1_000_000_000,
-                                            ),
-                                            message_type: MessageType::Server,
-                                            status: MessageStatus::Delivered,
-                                        },
-                                    },
-                                };
-                                let _ = event_tx.send(leave_msg);
+                                    let _ = event_tx.send(leave_msg);
 
-                                let event = ConnectionEvent::User {
-                                    event: UserEvent::Remove {
-                                        channel_id: current_channel.to_owned(),
-                                        user_id: packet.user_id,
-                                    },
-                                };
-                                let _ = event_tx.send(event);
-                            }
-
-                            ServerPacket::ChannelEvent(packet) => match packet {
-                                ChannelEventPacket::Creation {
-                                    channel_name,
-                                    is_protected: _,
-                                    is_temporary: _,
-                                } => {
-                                    let event = ConnectionEvent::Channel {
-                                        event: ChannelEvent::New {
-                                            channel: Channel {
-                                                id: channel_name,
-                                                name: None,
-                                                channel_type: ChannelType::Group,
-                                            },
-                                        },
-                                    };
-                                    let _ = event_tx.send(event);
-                                }
-                                ChannelEventPacket::Update {
-                                    channel_name,
-                                    new_name,
-                                    is_protected: _,
-                                    is_temporary: _,
-                                } => {
-                                    let event = ConnectionEvent::Channel {
-                                        event: ChannelEvent::Update {
-                                            channel_id: channel_name,
-                                            new_channel: Channel {
-                                                id: new_name,
-                                                name: None,
-                                                channel_type: ChannelType::Group,
-                                            },
-                                        },
-                                    };
-                                    let _ = event_tx.send(event);
-                                }
-                                ChannelEventPacket::Deletion { channel_name } => {
-                                    let event = ConnectionEvent::Channel {
-                                        event: ChannelEvent::Remove {
-                                            channel_id: channel_name,
-                                        },
-                                    };
-                                    let _ = event_tx.send(event);
-                                }
-                            },
-
-                            ServerPacket::ChannelSwitching(packet) => match packet {
-                                ChannelSwitchingPacket::Join {
-                                    user_id,
-                                    username,
-                                    color,
-                                    user_permissions: _,
-                                    sequence_id: _,
-                                } => {
-                                    let mut pic = None;
-                                    if let Some(pfp_format) = pfp_url.clone() {
-                                        pic = Some(pfp_format.replace("{uid}", user_id.as_str()));
-                                    }
-                                    let event = ConnectionEvent::User {
-                                        event: UserEvent::New {
-                                            channel_id: current_channel.to_owned(),
-                                            user: crate::Profile {
-                                                id: Some(user_id),
-                                                username: Some(username),
-                                                display_name: None,
-                                                color: kanii_to_rgba(color),
-                                                picture: pic,
-                                            },
-                                        },
-                                    };
-                                    let _ = event_tx.send(event);
-                                }
-                                ChannelSwitchingPacket::Departure {
-                                    user_id,
-                                    sequence_id: _,
-                                } => {
                                     let event = ConnectionEvent::User {
                                         event: UserEvent::Remove {
-                                            user_id,
-                                            channel_id: current_channel.to_owned(),
+                                            scope: current_channel.clone().into(),
+                                            user_id: packet.user_id,
                                         },
                                     };
                                     let _ = event_tx.send(event);
                                 }
-                                ChannelSwitchingPacket::ForcedSwitch { channel_name } => {
-                                    current_channel.replace(channel_name.to_owned());
-                                    let event = ConnectionEvent::Channel {
-                                        event: ChannelEvent::Switch {
-                                            channel_id: channel_name,
-                                        },
-                                    };
-                                    let _ = event_tx.send(event);
-                                }
-                            },
-
-                            ServerPacket::MessageDeletion(packet) => {
-                                let event = ConnectionEvent::Chat {
-                                    event: ChatEvent::Remove {
-                                        channel_id: current_channel.clone(),
-                                        message_id: packet.sequence_id,
-                                    },
-                                };
-                                let _ = event_tx.send(event);
-                            }
 
-                            ServerPacket::ContextInformation(packet) => match packet {
-                                ContextInformationPacket::ExistingUsers { count: _, contexts } => {
-                                    for context in contexts {
+                                ServerPacket::ChannelEvent(packet) => match packet {
+                                    ChannelEventPacket::Creation {
+                                        channel_name,
+                                        is_protected: _,
+                                        is_temporary: _,
+                                    } => {
+                                        let event = ConnectionEvent::Channel {
+                                            event: ChannelEvent::New {
+                                                channel: Channel {
+                                                    id: channel_name,
+                                                    name: None,
+                                                    channel_type: ChannelType::Group,
+                                                    topic: None,
+                                                    extra: HashMap::new(),
+                                                },
+                                            },
+                                        };
+                                        let _ = event_tx.send(event);
+                                    }
+                                    ChannelEventPacket::Update {
+                                        channel_name,
+                                        new_name,
+                                        is_protected: _,
+                                        is_temporary: _,
+                                    } => {
+                                        let event = ConnectionEvent::Channel {
+                                            event: ChannelEvent::Update {
+                                                channel_id: channel_name,
+                                                new_channel: Channel {
+                                                    id: new_name,
+                                                    name: None,
+                                                    channel_type: ChannelType::Group,
+                                                    topic: None,
+                                                    extra: HashMap::new(),
+                                                },
+                                            },
+                                        };
+                                        let _ = event_tx.send(event);
+                                    }
+                                    ChannelEventPacket::Deletion { channel_name } => {
+                                        let event = ConnectionEvent::Channel {
+                                            event: ChannelEvent::Remove {
+                                                channel_id: channel_name,
+                                            },
+                                        };
+                                        let _ = event_tx.send(event);
+                                    }
+                                },
+
+                                ServerPacket::ChannelSwitching(packet) => match packet {
+                                    ChannelSwitchingPacket::Join {
+                                        user_id,
+                                        username,
+                                        color,
+                                        user_permissions,
+                                        sequence_id: _,
+                                    } => {
                                         let mut pic = None;
                                         if let Some(pfp_format) = pfp_url.clone() {
-                                            pic = Some(
-                                                pfp_format
-                                                    .replace("{uid}", &context.user_id.as_str()),
-                                            );
+                                            pic =
+                                                Some(pfp_format.replace("{uid}", user_id.as_str()));
                                         }
                                         let event = ConnectionEvent::User {
                                             event: UserEvent::New {
-                                                channel_id: current_channel.to_owned(),
+                                                scope: current_channel.clone().into(),
                                                 user: crate::Profile {
-                                                    id: Some(context.user_id),
-                                                    username: Some(context.username),
+                                                    id: Some(user_id),
+                                                    username: Some(username),
                                                     display_name: None,
-                                                    color: kanii_to_rgba(context.color),
+                                                    color: kanii_to_rgba(color),
                                                     picture: pic,
+                                                    presence: Some(Presence::Online),
+                                                    role: Some(sockchat_role(
+                                                        &user_permissions,
+                                                        &roles,
+                                                    )),
+                                                    extra: HashMap::new(),
                                                 },
                                             },
                                         };
+                                        remember_user(&users, &event).await;
                                         let _ = event_tx.send(event);
                                     }
-                                }
-                                ContextInformationPacket::ExistingMessage {
-                                    timestamp,
-                                    user_id,
-                                    username: _,
-                                    color: _,
-                                    user_permissions: _,
-                                    message,
-                                    sequence_id,
-                                    notify: _,
-                                    message_flags: _,
-                                } => {
-                                    let event = ConnectionEvent::Chat {
-                                        event: ChatEvent::New {
-                                            channel_id: current_channel.clone(),
-                                            message: {
-                                                let content = parse_bbcode(message.as_str());
-
-                                                let mut parsed_content = Vec::new();
-                                                for fragment in content {
-                                                    match fragment {
-                                                        crate::MessageFragment::Text(text) => {
-                                                            let asset_parsed = parse_assets(
-                                                                &text,
-                                                                &channel_assets,
-                                                            );
-                                                            parsed_content.extend(asset_parsed);
-                                                        }
-                                                        other => parsed_content.push(other),
-                                                    }
-                                                }
-
-                                                Message {
-                                                    id: Some(sequence_id),
-                                                    sender_id: Some(user_id.clone()),
-                                                    content: parsed_content,
-                                                    timestamp: DateTime::from_timestamp_nanos(
-                                                        timestamp,
-                                                    ),
-                                                    message_type: if user_id == "-1" {
-                                                        MessageType::Server
-                                                    } else {
-                                                        MessageType::Normal
-                                                    },
-                                                    status: MessageStatus::Delivered,
-                                                }
+                                    ChannelSwitchingPacket::Departure {
+                                        user_id,
+                                        sequence_id: _,
+                                    } => {
+                                        let event = ConnectionEvent::User {
+                                            event: UserEvent::Remove {
+                                                user_id,
+                                                scope: current_channel.clone().into(),
+                                            },
+                                        };
+                                        let _ = event_tx.send(event);
+                                    }
+                                    ChannelSwitchingPacket::ForcedSwitch { channel_name } => {
+                                        current_channel.replace(channel_name.to_owned());
+                                        let event = ConnectionEvent::Channel {
+                                            event: ChannelEvent::Switch {
+                                                channel_id: channel_name,
                                             },
+                                        };
+                                        let _ = event_tx.send(event);
+                                    }
+                                },
+
+                                ServerPacket::MessageDeletion(packet) => {
+                                    let event = ConnectionEvent::Chat {
+                                        event: ChatEvent::Remove {
+                                            scope: current_channel.clone().into(),
+                                            message_id: packet.sequence_id,
                                         },
                                     };
                                     let _ = event_tx.send(event);
                                 }
-                                ContextInformationPacket::Channels { count: _, contexts } => {
-                                    for context in contexts {
-                                        let event = ConnectionEvent::Channel {
-                                            event: ChannelEvent::New {
-                                                channel: Channel {
-                                                    id: context.channel_name,
-                                                    name: None,
-                                                    channel_type: ChannelType::Group,
+
+                                ServerPacket::ContextInformation(packet) => match packet {
+                                    ContextInformationPacket::ExistingUsers {
+                                        count: _,
+                                        contexts,
+                                    } => {
+                                        for context in contexts {
+                                            let mut pic = None;
+                                            if let Some(pfp_format) = pfp_url.clone() {
+                                                pic = Some(
+                                                    pfp_format
+                                                        .replace("{uid}", context.user_id.as_str()),
+                                                );
+                                            }
+                                            let event = ConnectionEvent::User {
+                                                event: UserEvent::New {
+                                                    scope: current_channel.clone().into(),
+                                                    user: crate::Profile {
+                                                        id: Some(context.user_id),
+                                                        username: Some(context.username),
+                                                        display_name: None,
+                                                        color: kanii_to_rgba(context.color),
+                                                        picture: pic,
+                                                        presence: Some(Presence::Online),
+                                                        role: Some(sockchat_role(
+                                                            &context.user_permissions,
+                                                            &roles,
+                                                        )),
+                                                        extra: HashMap::new(),
+                                                    },
+                                                },
+                                            };
+                                            remember_user(&users, &event).await;
+                                            let _ = event_tx.send(event);
+                                        }
+                                    }
+                                    ContextInformationPacket::ExistingMessage {
+                                        timestamp,
+                                        user_id,
+                                        username,
+                                        color,
+                                        user_permissions,
+                                        message,
+                                        sequence_id,
+                                        notify: _,
+                                        message_flags,
+                                    } => {
+                                        // Backlog authors may have left already, so they are only
+                                        // remembered for profile lookups, never announced as present.
+                                        if user_id != "-1" {
+                                            users
+                                                .lock()
+                                                .await
+                                                .entry(user_id.clone())
+                                                .or_insert_with(|| crate::Profile {
+                                                    id: Some(user_id.clone()),
+                                                    username: Some(username),
+                                                    display_name: None,
+                                                    color: kanii_to_rgba(color),
+                                                    picture: pfp_url.as_ref().map(|pfp_format| {
+                                                        pfp_format.replace("{uid}", &user_id)
+                                                    }),
+                                                    presence: None,
+                                                    role: Some(sockchat_role(
+                                                        &user_permissions,
+                                                        &roles,
+                                                    )),
+                                                    extra: HashMap::new(),
+                                                });
+                                        }
+                                        {
+                                            let mut session = session.lock().await;
+                                            if session
+                                                .seen(current_channel.as_deref(), &sequence_id)
+                                            {
+                                                continue;
+                                            }
+                                            session
+                                                .record(current_channel.as_deref(), &sequence_id);
+                                        }
+                                        let event = ConnectionEvent::Chat {
+                                            event: ChatEvent::New {
+                                                scope: current_channel.clone().into(),
+                                                message: {
+                                                    let parsed_content = parse_content(
+                                                        &message,
+                                                        &channel_assets,
+                                                        &users,
+                                                    )
+                                                    .await;
+                                                    let parsed_content = if low_bandwidth {
+                                                        strip_media(&parsed_content)
+                                                    } else {
+                                                        parsed_content
+                                                    };
+
+                                                    Message {
+                                                        id: Some(sequence_id),
+                                                        sender_id: Some(user_id.clone()),
+                                                        content: parsed_content,
+                                                        timestamp: DateTime::from_timestamp_nanos(
+                                                            timestamp,
+                                                        ),
+                                                        message_type: message_type(
+                                                            &user_id,
+                                                            &message_flags,
+                                                        ),
+                                                        status: MessageStatus::Delivered,
+                                                        reactions: Vec::new(),
+                                                        reply_to: None,
+                                                        thread_id: None,
+                                                        extra: HashMap::new(),
+                                                    }
+                                                },
+                                            },
+                                        };
+                                        let _ = event_tx.send(event);
+                                    }
+                                    ContextInformationPacket::Channels { count: _, contexts } => {
+                                        for context in contexts {
+                                            let event = ConnectionEvent::Channel {
+                                                event: ChannelEvent::New {
+                                                    channel: Channel {
+                                                        id: context.channel_name,
+                                                        name: None,
+                                                        channel_type: ChannelType::Group,
+                                                        topic: None,
+                                                        extra: HashMap::new(),
+                                                    },
                                                 },
+                                            };
+                                            let _ = event_tx.send(event);
+                                        }
+                                    }
+                                },
+
+                                ServerPacket::ContextClearing(packet) => {
+                                    if packet.message_history {
+                                        if let Some(channel_id) = &current_channel {
+                                            session.lock().await.last_sequence.remove(channel_id);
+                                        }
+                                        let event = ConnectionEvent::Channel {
+                                            event: ChannelEvent::Wipe {
+                                                scope: current_channel.clone().into(),
+                                            },
+                                        };
+                                        let _ = event_tx.send(event);
+                                    }
+                                    if packet.user_list {
+                                        let event = ConnectionEvent::User {
+                                            event: UserEvent::ClearList {
+                                                scope: current_channel.clone().into(),
                                             },
                                         };
                                         let _ = event_tx.send(event);
                                     }
+                                    if packet.channel_list {
+                                        let event = ConnectionEvent::Channel {
+                                            event: ChannelEvent::ClearList,
+                                        };
+                                        let _ = event_tx.send(event);
+                                    }
                                 }
-                            },
 
-                            ServerPacket::ContextClearing(packet) => {
-                                if packet.message_history {
+                                ServerPacket::ForcedDisconnect(packet) => {
+                                    // The packet carries no reason text, only the ban flag and expiry.
                                     let event = ConnectionEvent::Channel {
-                                        event: ChannelEvent::Wipe {
-                                            channel_id: current_channel.clone(),
+                                        event: ChannelEvent::Kick {
+                                            scope: Scope::Global,
+                                            reason: None,
+                                            ban: packet.ban,
+                                            until: ban_expiry(packet.ban, packet.timestamp),
                                         },
                                     };
                                     let _ = event_tx.send(event);
                                 }
-                                if packet.user_list {
+
+                                ServerPacket::UserUpdate(packet) => {
+                                    let mut pic = None;
+                                    if let Some(pfp_format) = pfp_url.clone() {
+                                        pic = Some(
+                                            pfp_format.replace("{uid}", packet.user_id.as_str()),
+                                        );
+                                    }
                                     let event = ConnectionEvent::User {
-                                        event: UserEvent::ClearList {
-                                            channel_id: current_channel.to_owned(),
+                                        event: UserEvent::Update {
+                                            scope: current_channel.clone().into(),
+                                            user_id: packet.user_id.to_owned(),
+                                            new_user: Profile {
+                                                id: Some(packet.user_id),
+                                                username: Some(packet.username),
+                                                display_name: None,
+                                                color: kanii_to_rgba(packet.color),
+                                                picture: pic,
+                                                presence: Some(Presence::Online),
+                                                role: Some(sockchat_role(
+                                                    &packet.user_permissions,
+                                                    &roles,
+                                                )),
+                                                extra: HashMap::new(),
+                                            },
                                         },
                                     };
+                                    remember_user(&users, &event).await;
                                     let _ = event_tx.send(event);
                                 }
-                                if packet.channel_list {
-                                    let event = ConnectionEvent::Channel {
-                                        event: ChannelEvent::ClearList,
-                                    };
-                                    let _ = event_tx.send(event);
-                                }
-                            }
-
-                            ServerPacket::ForcedDisconnect(packet) => {
-                                let event = ConnectionEvent::Channel {
-                                    event: ChannelEvent::Kick {
-                                        channel_id: current_channel.clone(),
-                                        reason: None,
-                                        ban: packet.ban,
-                                    },
-                                };
-                                let _ = event_tx.send(event);
-                            }
-
-                            ServerPacket::UserUpdate(packet) => {
-                                let mut pic = None;
-                                if let Some(pfp_format) = pfp_url.clone() {
-                                    pic =
-                                        Some(pfp_format.replace("{uid}", &packet.user_id.as_str()));
-                                }
-                                let event = ConnectionEvent::User {
-                                    event: UserEvent::Update {
-                                        channel_id: current_channel.to_owned(),
-                                        user_id: packet.user_id.to_owned(),
-                                        new_user: Profile {
-                                            id: Some(packet.user_id),
-                                            username: Some(packet.username),
-                                            display_name: None,
-                                            color: kanii_to_rgba(packet.color),
-                                            picture: pic,
-                                        },
-                                    },
-                                };
-                                let _ = event_tx.send(event);
                             }
                         }
                     }
                 }
-            }
-        });
-        self.tasks.push(task);
+
+                event!(debug, "sockchat reader closed");
+                for entry in outbound.drain().await {
+                    let _ = event_tx.send(entry.fail("Connection closed"));
+                }
+                let auth_failed = status
+                    .lock()
+                    .is_ok_and(|s| matches!(*s, ConnectionStatus::AuthFailed { .. }));
+                if !auth_failed {
+                    set_status(&status, ConnectionStatus::Disconnected);
+                }
+                if !closing.load(Ordering::SeqCst) {
+                    let event = ConnectionEvent::Status {
+                        event: StatusEvent::Disconnected {
+                            artifact: Some("Connection closed".to_string()),
+                        },
+                    };
+                    let _ = event_tx.send(event);
+                }
+            });
 
         let write = Arc::new(Mutex::new(write));
         let _ = write
@@ -677,37 +1417,86 @@ impl Connection for SockchatConnection {
 
         let msg_uid = uid.to_owned();
         let write_clone = write.clone();
-        let task = tokio::spawn(async move {
-            loop {
-                let resp = rx.recv().await;
-                match resp {
-                    Ok(msg) => {
-                        let packet = ClientPacket::Message(
-                            kanii_lib::packets::client::message::MessagePacket {
-                                user_id: msg_uid.clone(),
-                                message: msg.to_string(),
-                            },
-                        )
-                        .to_sockstr();
-                        let _ = write_clone.lock().await.send(packet.into()).await;
-                    }
-                    Err(e) => match e {
-                        broadcast::error::RecvError::Lagged(skipped) => {
-                            eprintln!("skipped {}x WsMessage", skipped);
-                        }
-                        _ => {
-                            break;
+        let write_events = self.event_tx.clone();
+        let write_queue = self.outbound.clone();
+        let max_retries = self.options.max_retries;
+        self.tasks
+            .spawn_essential("writer", self.status.clone(), async move {
+                loop {
+                    let resp = rx.recv().await;
+                    match resp {
+                        Ok(outgoing) => {
+                            let packet = ClientPacket::Message(
+                                kanii_lib::packets::client::message::MessagePacket {
+                                    user_id: msg_uid.clone(),
+                                    message: outgoing.command,
+                                },
+                            )
+                            .to_sockstr();
+                            let mut attempt = 0;
+                            let written = loop {
+                                match write_clone.lock().await.send(packet.clone().into()).await {
+                                    Ok(()) => break Ok(()),
+                                    Err(e) if attempt < max_retries => {
+                                        attempt += 1;
+                                        event!(
+                                            debug,
+                                            "retrying sockchat write ({}): {}",
+                                            attempt,
+                                            e
+                                        );
+                                        tokio::time::sleep(WRITE_RETRY_DELAY * attempt).await;
+                                    }
+                                    Err(e) => break Err(e),
+                                }
+                            };
+                            if let Err(e) = written {
+                                event!(warn, "sockchat write failed: {}", e);
+                                let entry = match outgoing.entry_id {
+                                    Some(id) => write_queue.remove(&id).await,
+                                    None => None,
+                                };
+                                if let Some(entry) = entry {
+                                    let _ = write_events
+                                        .send(entry.fail(&format!("Write failed: {}", e)));
+                                }
+                            }
                         }
-                    },
+                        Err(e) => match e {
+                            broadcast::error::RecvError::Lagged(skipped) => {
+                                event!(warn, "writer skipped {} queued messages", skipped);
+                            }
+                            _ => {
+                                break;
+                            }
+                        },
+                    }
+                }
+            });
+
+        let ack_events = self.event_tx.clone();
+        let ack_queue = self.outbound.clone();
+        let ack_timeout = self.options.ack_timeout;
+        self.tasks.spawn_restartable("ack timeout", move || {
+            let ack_events = ack_events.clone();
+            let ack_queue = ack_queue.clone();
+            async move {
+                let mut interval =
+                    tokio::time::interval((ack_timeout / 4).max(Duration::from_millis(50)));
+                loop {
+                    interval.tick().await;
+                    for entry in ack_queue.due(ack_timeout).await {
+                        let _ = ack_events.send(entry.fail("No acknowledgment from server"));
+                    }
                 }
             }
         });
-        self.tasks.push(task);
 
         let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
         self.shutdown_tx = Some(shutdown_tx);
 
         let ping_uid = uid.to_owned();
+        let ping_interval = self.options.ping_interval;
         let task = tokio::spawn(async move {
             tokio::pin!(shutdown_rx);
             loop {
@@ -716,7 +1505,7 @@ impl Connection for SockchatConnection {
                         let _ = write.lock().await.send(WsMessage::Close(None)).await;
                         break;
                     }
-                    _ = tokio::time::sleep(std::time::Duration::from_secs(40)) => {
+                    _ = tokio::time::sleep(ping_interval) => {
                         let _ = write
                             .lock()
                             .await
@@ -732,20 +1521,17 @@ impl Connection for SockchatConnection {
                 }
             }
         });
-        self.tasks.push(task);
+        self.closer = Some(task);
 
         Ok(())
     }
 
-    async fn disconnect(&mut self) -> Result<(), String> {
-        if let Some(shutdown_tx) = self.shutdown_tx.take() {
-            let _ = shutdown_tx.send(());
-        }
-
-        for task in &self.tasks {
-            task.abort();
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.stop_tasks().await;
+        for entry in self.outbound.drain().await {
+            let _ = self.event_tx.send(entry.fail("Disconnected"));
         }
-        self.tasks.clear();
+        set_status(&self.status, ConnectionStatus::Disconnected);
 
         let event = ConnectionEvent::Status {
             event: StatusEvent::Disconnected { artifact: None },
@@ -755,31 +1541,188 @@ impl Connection for SockchatConnection {
         Ok(())
     }
 
-    async fn send(&mut self, event: ConnectionEvent) -> Result<(), String> {
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
         match event {
             ConnectionEvent::Chat {
-                event:
-                    ChatEvent::New {
-                        channel_id: _,
-                        message,
-                    },
+                event: ChatEvent::New { scope, message },
             } => {
-                let text =
-                    if let Some(crate::MessageFragment::Text(content)) = message.content.first() {
-                        content.clone()
-                    } else {
-                        return Err("Unsupported message format".to_string());
-                    };
+                self.send_chat(scope, message, None).await?;
+            }
+            ConnectionEvent::Channel {
+                event: ChannelEvent::ClearList,
+            } => {
+                return Err(ConnectionError::Unsupported(
+                    "Refreshing the channel list is not supported by sockchat".to_string(),
+                ));
+            }
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Join { channel_id } | ChannelEvent::Switch { channel_id },
+            } => {
+                self.send_command(format!("/join {}", channel_id))?;
+            }
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Leave { channel_id },
+            } => {
+                self.send_command(format!("/leave {}", channel_id))?;
+            }
+            ConnectionEvent::Channel {
+                event: ChannelEvent::New { channel },
+            } => {
+                let name = channel.name.unwrap_or(channel.id);
+                self.send_command(format!("/create {}", name))?;
+            }
+            ConnectionEvent::Asset {
+                event: AssetEvent::New { scope, asset },
+            } => {
+                let Asset::Emote {
+                    id: Some(id), src, ..
+                } = &asset
+                else {
+                    return Err(ConnectionError::Unsupported(
+                        "Only emotes with an id can be created".to_string(),
+                    ));
+                };
+
+                let api = emote_management_api(&self.auth)?;
+                let body = serde_json::json!({ "uri": src, "strings": [id] });
+                let response = http_client(&self.options)?
+                    .post(format!("{}/emotes", api.trim_end_matches('/')))
+                    .bearer_auth(field_text(&self.auth, "token").unwrap_or_default())
+                    .header("Content-Type", "application/json")
+                    .body(body.to_string())
+                    .send()
+                    .await
+                    .map_err(|e| ConnectionError::Network(e.to_string()))?;
+                if !response.status().is_success() {
+                    return Err(ConnectionError::Protocol(format!(
+                        "Emote management API returned {}",
+                        response.status()
+                    )));
+                }
 
-                if let Err(e) = self.ws_tx.send(WsMessage::Text(text.into())) {
-                    return Err(e.to_string());
+                self.assets.lock().await.push(asset.clone());
+                let _ = self.event_tx.send(ConnectionEvent::Asset {
+                    event: AssetEvent::New { scope, asset },
+                });
+            }
+            ConnectionEvent::Asset {
+                event: AssetEvent::Remove { scope, asset_id },
+            } => {
+                let api = emote_management_api(&self.auth)?;
+                let response = http_client(&self.options)?
+                    .delete(format!("{}/emotes/{}", api.trim_end_matches('/'), asset_id))
+                    .bearer_auth(field_text(&self.auth, "token").unwrap_or_default())
+                    .send()
+                    .await
+                    .map_err(|e| ConnectionError::Network(e.to_string()))?;
+                if !response.status().is_success() {
+                    return Err(ConnectionError::Protocol(format!(
+                        "Emote management API returned {}",
+                        response.status()
+                    )));
                 }
+
+                self.assets
+                    .lock()
+                    .await
+                    .retain(|a| get_id(a).as_ref() != Some(&asset_id));
+                let _ = self.event_tx.send(ConnectionEvent::Asset {
+                    event: AssetEvent::Remove { scope, asset_id },
+                });
+            }
+            ConnectionEvent::Chat {
+                event: ChatEvent::Remove { message_id, .. },
+            } => {
+                self.send_command(format!("/delete {}", message_id))?;
+            }
+            ConnectionEvent::Chat {
+                event: ChatEvent::Update { .. },
+            } => {
+                return Err(ConnectionError::Unsupported(
+                    "Message editing is not supported by sockchat".to_string(),
+                ));
             }
+            ConnectionEvent::Chat {
+                event: ChatEvent::ReactionAdd { .. } | ChatEvent::ReactionRemove { .. },
+            } => {
+                return Err(ConnectionError::Unsupported(
+                    "Reactions are not supported by sockchat".to_string(),
+                ));
+            }
+            ConnectionEvent::User {
+                event: UserEvent::TypingStart { .. } | UserEvent::TypingStop { .. },
+            } => {
+                return Err(ConnectionError::Unsupported(
+                    "Typing notifications are not supported by sockchat".to_string(),
+                ));
+            }
+            ConnectionEvent::Channel {
+                event: ChannelEvent::TopicChanged { .. },
+            } => {
+                return Err(ConnectionError::Unsupported(
+                    "Channel topics are not supported by sockchat".to_string(),
+                ));
+            }
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Accept { .. } | ChannelEvent::Decline { .. },
+            } => {
+                return Err(ConnectionError::Unsupported(
+                    "Whispers do not need to be accepted".to_string(),
+                ));
+            }
+            ConnectionEvent::Moderation { event } => self.send_moderation(&event).await?,
             _ => {}
         }
         Ok(())
     }
 
+    async fn send_tracked(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> Result<SendHandle, ConnectionError> {
+        let ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, mut message },
+        } = event
+        else {
+            self.send(event).await?;
+            return Ok(SendHandle::completed(SendOutcome::Delivered {
+                message_id: None,
+            }));
+        };
+
+        let (handle, tx) = SendHandle::with_id(self.options.ids.next_id());
+        if message.id.is_none() {
+            message.id = Some(handle.correlation_id().to_string());
+        }
+        self.send_chat(scope, message, Some(tx)).await?;
+        Ok(handle)
+    }
+
+    async fn fetch_profile(&mut self, user_id: &str) -> Result<Profile, ConnectionError> {
+        self.users
+            .lock()
+            .await
+            .get(user_id)
+            .cloned()
+            .ok_or_else(|| ConnectionError::NotFound(format!("user {}", user_id)))
+    }
+
+    async fn open_direct(&mut self, user_id: &str) -> Result<Channel, ConnectionError> {
+        let channel = Channel {
+            id: whisper_channel_id(user_id),
+            name: Some(self.username_of(user_id).await?),
+            channel_type: ChannelType::Direct,
+            topic: None,
+            extra: HashMap::new(),
+        };
+        let _ = self.event_tx.send(ConnectionEvent::Channel {
+            event: ChannelEvent::New {
+                channel: channel.clone(),
+            },
+        });
+        Ok(channel)
+    }
+
     fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
         self.event_rx
             .take()
@@ -822,7 +1765,382 @@ impl Connection for SockchatConnection {
                     value: crate::FieldValue::Text(None),
                     required: false,
                 },
+                AuthField {
+                    name: "emote_management_api".to_string(),
+                    display: Some(
+                        "URL of a server-specific emote API taking POST /emotes and DELETE /emotes/{id}"
+                            .to_string(),
+                    ),
+                    value: crate::FieldValue::Text(None),
+                    required: false,
+                },
+                AuthField {
+                    name: "topic_pattern".to_string(),
+                    display: Some("Regex matching bot topic announcements".to_string()),
+                    value: crate::FieldValue::Text(None),
+                    required: false,
+                },
+                AuthField {
+                    name: "maintenance_pattern".to_string(),
+                    display: Some("Regex matching server maintenance announcements".to_string()),
+                    value: crate::FieldValue::Text(None),
+                    required: false,
+                },
             ]),
         }
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            deletion: true,
+            multiple_channels: true,
+            asset_management: field_text(&self.auth, "emote_management_api").is_some(),
+            moderation: true,
+            max_message_length: match self.max_message_length.load(Ordering::Relaxed) {
+                0 => None,
+                limit => Some(limit),
+            },
+            ..Capabilities::default()
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn commands(&self) -> Vec<CommandSpec> {
+        [
+            ("join", &["channel", "password?"][..], "Join a channel"),
+            ("create", &["name"][..], "Create a channel"),
+            ("leave", &["channel"][..], "Leave a channel"),
+            ("delete", &["message_id"][..], "Delete a message"),
+            ("msg", &["user", "message"][..], "Send a private message"),
+            ("afk", &["reason?"][..], "Mark yourself as away"),
+            ("nick", &["name?"][..], "Change your display name"),
+            ("who", &["channel?"][..], "List users in a channel"),
+            ("kick", &["user", "duration?"][..], "Kick a user"),
+            ("ban", &["user", "duration?"][..], "Ban a user"),
+            ("pardon", &["user"][..], "Lift a ban"),
+            ("silence", &["user", "duration?"][..], "Mute a user"),
+            ("unsilence", &["user"][..], "Lift a mute"),
+        ]
+        .into_iter()
+        .map(|(name, args, description)| CommandSpec {
+            name: name.to_string(),
+            args: args
+                .iter()
+                .map(|arg| CommandArg {
+                    name: arg.trim_end_matches('?').to_string(),
+                    required: !arg.ends_with('?'),
+                })
+                .collect(),
+            description: Some(description.to_string()),
+        })
+        .collect()
+    }
+
+    fn render_text(&self, content: &[crate::MessageFragment]) -> String {
+        render_bbcode(content)
+    }
+
+    async fn preflight(&self, check_reachability: bool) -> PreflightReport {
+        let mut report = validate_auth(&self.protocol_spec(), &self.auth);
+
+        let url = field_text(&self.auth, "sockchat_url").and_then(|url| match Url::parse(&url) {
+            Ok(url) if matches!(url.scheme(), "ws" | "wss") && url.host_str().is_some() => {
+                Some(url)
+            }
+            Ok(_) => {
+                report.error(Some("sockchat_url"), "Expected a ws:// or wss:// URL");
+                None
+            }
+            Err(e) => {
+                report.error(Some("sockchat_url"), e.to_string());
+                None
+            }
+        });
+        for name in ["pfp_url", "asset_api", "emote_management_api"] {
+            let Some(value) = field_text(&self.auth, name) else {
+                continue;
+            };
+            match Url::parse(&value.replace("{uid}", "0")) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                Ok(_) => report.error(Some(name), "Expected an http:// or https:// URL"),
+                Err(e) => report.error(Some(name), e.to_string()),
+            }
+        }
+        if let Some(token) = field_text(&self.auth, "token") {
+            if token.chars().any(char::is_whitespace) {
+                report.error(Some("token"), "Token must not contain whitespace");
+            }
+        }
+        if let Some(pattern) = field_text(&self.auth, "topic_pattern") {
+            if let Err(e) = Regex::new(&pattern) {
+                report.error(Some("topic_pattern"), e.to_string());
+            }
+        }
+        if let Some(pattern) = field_text(&self.auth, "maintenance_pattern") {
+            if let Err(e) = Regex::new(&pattern) {
+                report.error(Some("maintenance_pattern"), e.to_string());
+            }
+        }
+
+        if check_reachability {
+            let target = match &self.options.proxy {
+                Some(proxy) => Some((proxy.host.clone(), proxy.port)),
+                None => url.as_ref().and_then(|url| {
+                    Some((url.host_str()?.to_string(), url.port_or_known_default()?))
+                }),
+            };
+            if let Some((host, port)) = target {
+                let timeout = self
+                    .options
+                    .connect_timeout
+                    .unwrap_or(Duration::from_secs(5));
+                report.reachability = probe_reachability(&host, port, timeout).await;
+            }
+        }
+        report
+    }
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+}
+
+async fn parse_content(
+    text: &str,
+    assets: &Mutex<Vec<Asset>>,
+    users: &Mutex<HashMap<String, Profile>>,
+) -> Vec<crate::MessageFragment> {
+    let assets = assets.lock().await.clone();
+    let users: Vec<Profile> = users.lock().await.values().cloned().collect();
+    parse_bbcode(text)
+        .into_iter()
+        .flat_map(|fragment| match fragment {
+            crate::MessageFragment::Text(text) => parse_assets(&text, &assets),
+            crate::MessageFragment::Styled { text, styles } => {
+                restyle(parse_assets(&text, &assets), &styles)
+            }
+            other => vec![other],
+        })
+        .flat_map(|fragment| match fragment {
+            crate::MessageFragment::Text(text) => parse_mentions(&text, &users),
+            crate::MessageFragment::Styled { text, styles } => {
+                restyle(parse_mentions(&text, &users), &styles)
+            }
+            other => vec![other],
+        })
+        .collect()
+}
+
+/// Undoes the escaping sockchat servers apply to echoed messages, so an echo compares equal to
+/// the text that was sent.
+fn echo_text(message: &str) -> String {
+    message
+        .replace(" <br/> ", "\n")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .trim()
+        .to_string()
+}
+
+/// Sharp-chat's informational bot replies confirming moderation commands.
+const SILENCE_OK: &str = "silok";
+const UNSILENCE_OK: &str = "usilok";
+const PARDON_OK: &str = "unban";
+
+/// Sharp-chat's bot errors for refused moderation commands.
+const MODERATION_ERRORS: &[&str] = &[
+    "kickna", "silerr", "usilerr", "silperr", "usilperr", "silself", "notban", "usernf",
+];
+
+/// Splits a bot message into its kind (`0` information, `1` error) and message id.
+fn bot_reply(message: &str) -> Option<(&str, &str)> {
+    let mut parts = message.split('\x0c');
+    Some((parts.next()?, parts.next()?))
+}
+
+/// Takes the oldest pending moderation command `confirms` accepts, as the event to report.
+async fn confirm_moderation(
+    pending: &Mutex<Vec<ModerationEvent>>,
+    confirms: impl Fn(&ModerationEvent) -> bool,
+) -> Option<ConnectionEvent> {
+    let mut pending = pending.lock().await;
+    let index = pending.iter().position(confirms)?;
+    Some(ConnectionEvent::Moderation {
+        event: pending.remove(index),
+    })
+}
+
+/// Bot messages are `<kind>\f<id>[\f<argument>...]`, where kind `1` marks an error answering
+/// the user's last command.
+fn bot_error(message: &str) -> Option<String> {
+    let mut parts = message.split('\x0c');
+    (parts.next()? == "1").then(|| parts.collect::<Vec<_>>().join(" "))
+}
+
+/// Sockchat has no emote management of its own and the Mami asset API is read-only, so creating
+/// and deleting emotes needs a server-specific endpoint configured as `emote_management_api`.
+fn emote_management_api(auth: &[AuthField]) -> Result<String, ConnectionError> {
+    field_text(auth, "emote_management_api").ok_or_else(|| {
+        ConnectionError::Unsupported(
+            "Managing emotes needs an emote_management_api endpoint".to_string(),
+        )
+    })
+}
+
+/// Fetches the server's emote list from a Mami-compatible asset API.
+async fn fetch_emotes(http: &reqwest::Client, api: &str) -> Option<Vec<Asset>> {
+    let response = http
+        .get(format!("{}/emotes", api.trim_end_matches('/')))
+        .query(&[("fields", "uri,strings,min_rank")])
+        .send()
+        .await
+        .inspect_err(|e| event!(warn, "failed to fetch emote list: {}", e))
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let text = response
+        .text()
+        .await
+        .inspect_err(|e| event!(warn, "failed to read emote list: {}", e))
+        .ok()?;
+    let json = serde_json::from_str::<serde_json::Value>(&text).ok()?;
+    let emotes = json
+        .as_array()?
+        .iter()
+        .filter_map(|emote| {
+            let uri = emote.get("uri")?.as_str()?;
+            let keys: Vec<String> = emote
+                .get("strings")?
+                .as_array()?
+                .iter()
+                .filter_map(|s| s.as_str().map(|s| s.to_string()))
+                .collect();
+            let id = keys.first().cloned()?;
+            let escaped_keys: Vec<String> = keys.iter().map(|k| regex::escape(k)).collect();
+            Some(Asset::Emote {
+                id: Some(id),
+                pattern: format!(r":(?:{}):", escaped_keys.join("|")),
+                src: uri.to_string(),
+                source: AssetSource::Server,
+            })
+        })
+        .collect();
+    Some(emotes)
+}
+
+fn resolve_assets(
+    content: &[crate::MessageFragment],
+    assets: &[Asset],
+) -> Result<Vec<crate::MessageFragment>, ConnectionError> {
+    content
+        .iter()
+        .map(|fragment| match fragment {
+            crate::MessageFragment::AssetId(id) => {
+                match assets.iter().find(|a| get_id(a).as_ref() == Some(id)) {
+                    Some(Asset::Emote { .. }) => {
+                        Ok(crate::MessageFragment::Text(format!(":{}:", id)))
+                    }
+                    Some(_) => Err(ConnectionError::Unsupported(format!(
+                        "Asset {} is not an emote",
+                        id
+                    ))),
+                    None => Err(ConnectionError::Unsupported(format!(
+                        "Unknown asset {}",
+                        id
+                    ))),
+                }
+            }
+            crate::MessageFragment::Spoiler(inner) => Ok(crate::MessageFragment::Spoiler(
+                resolve_assets(inner, assets)?,
+            )),
+            other => Ok(other.clone()),
+        })
+        .collect()
+}
+
+fn restyle(
+    fragments: Vec<crate::MessageFragment>,
+    styles: &[crate::TextStyle],
+) -> Vec<crate::MessageFragment> {
+    fragments
+        .into_iter()
+        .map(|fragment| match fragment {
+            crate::MessageFragment::Text(text) => crate::MessageFragment::Styled {
+                text,
+                styles: styles.to_vec(),
+            },
+            other => other,
+        })
+        .collect()
+}
+
+fn whisper_channel_id(user_id: &str) -> String {
+    format!("{}{}", WHISPER_PREFIX, user_id)
+}
+
+async fn remember_user(users: &Mutex<HashMap<String, Profile>>, event: &ConnectionEvent) {
+    let user = match event {
+        ConnectionEvent::User {
+            event: UserEvent::New { user, .. },
+        } => user,
+        ConnectionEvent::User {
+            event: UserEvent::Update { new_user, .. },
+        } => new_user,
+        _ => return,
+    };
+    if let Some(id) = &user.id {
+        users.lock().await.insert(id.clone(), user.clone());
+    }
+}
+
+pub(crate) fn sockchat_role(permissions: &UserPermissions, roles: &SockchatRoles) -> Role {
+    let mut role = Role {
+        rank: i64::from(permissions.rank),
+        permissions: Permissions {
+            can_moderate: permissions.can_moderate,
+            can_create_channels: permissions.channel_permissions > 0,
+            can_view_logs: permissions.can_logs,
+            can_change_nickname: permissions.can_nickname,
+        },
+        ..Role::default()
+    };
+    roles.apply(&mut role);
+    role
+}
+
+pub(crate) fn message_type(user_id: &str, flags: &MessageFlags) -> MessageType {
+    if user_id == "-1" {
+        MessageType::Server
+    } else if flags.cursive && !flags.colon {
+        MessageType::Action
+    } else {
+        MessageType::Normal
+    }
+}
+
+fn set_status(cell: &StdMutex<ConnectionStatus>, status: ConnectionStatus) {
+    if let Ok(mut current) = cell.lock() {
+        *current = status;
+    }
+}
+
+fn ban_expiry(ban: bool, timestamp: i64) -> Option<DateTime<Utc>> {
+    if !ban || timestamp <= 0 || timestamp >= PERMANENT_BAN_TIMESTAMP {
+        return None;
+    }
+    DateTime::from_timestamp(timestamp, 0)
 }
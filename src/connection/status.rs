@@ -0,0 +1,13 @@
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ConnectionStatus {
+    #[default]
+    Disconnected,
+    Connecting,
+    Connected,
+    Reconnecting {
+        attempt: u32,
+    },
+    AuthFailed {
+        reason: String,
+    },
+}
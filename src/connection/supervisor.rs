@@ -0,0 +1,180 @@
+use std::{
+    any::Any,
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use futures::FutureExt;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::utils::trace::event;
+
+use super::{ConnectionEvent, ConnectionStatus, StatusEvent};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure {
+        max_restarts: u32,
+        backoff: Duration,
+    },
+}
+
+impl RestartPolicy {
+    pub fn on_failure(max_restarts: u32, backoff: Duration) -> Self {
+        RestartPolicy::OnFailure {
+            max_restarts,
+            backoff,
+        }
+    }
+
+    fn allows(&self, restarts: u32) -> Option<Duration> {
+        match self {
+            RestartPolicy::Never => None,
+            RestartPolicy::OnFailure {
+                max_restarts,
+                backoff,
+            } => (restarts < *max_restarts).then_some(*backoff),
+        }
+    }
+}
+
+/// Owns a connection's background tasks and reports the ones that fail as `StatusEvent::Error`
+/// instead of letting them vanish. All tasks are aborted on `shutdown` or drop.
+#[derive(Debug)]
+pub struct Supervisor {
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    policy: RestartPolicy,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Supervisor {
+    pub fn new(event_tx: mpsc::UnboundedSender<ConnectionEvent>, policy: RestartPolicy) -> Self {
+        Supervisor {
+            event_tx,
+            policy,
+            tasks: Vec::new(),
+        }
+    }
+
+    pub fn policy(&self) -> &RestartPolicy {
+        &self.policy
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Runs a task that cannot be recreated. Returning is treated as normal completion; a panic
+    /// is reported.
+    pub fn spawn<F>(&mut self, name: &str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let name = name.to_string();
+        let event_tx = self.event_tx.clone();
+        self.tasks.push(tokio::spawn(async move {
+            if let Err(panic) = AssertUnwindSafe(task).catch_unwind().await {
+                report(&event_tx, &name, &panic_message(&*panic));
+            }
+        }));
+    }
+
+    /// Like `spawn`, for a task the connection cannot run without, such as its reader or writer.
+    /// A panic also marks `status` disconnected and emits `StatusEvent::Disconnected`, so
+    /// reconnect logic sees the connection drop.
+    pub fn spawn_essential<F>(
+        &mut self,
+        name: &str,
+        status: Arc<StdMutex<ConnectionStatus>>,
+        task: F,
+    ) where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let name = name.to_string();
+        let event_tx = self.event_tx.clone();
+        self.tasks.push(tokio::spawn(async move {
+            if let Err(panic) = AssertUnwindSafe(task).catch_unwind().await {
+                let reason = panic_message(&*panic);
+                report(&event_tx, &name, &reason);
+                if let Ok(mut status) = status.lock() {
+                    *status = ConnectionStatus::Disconnected;
+                }
+                let _ = event_tx.send(ConnectionEvent::Status {
+                    event: StatusEvent::Disconnected {
+                        artifact: Some(format!("{} task {}", name, reason)),
+                    },
+                });
+            }
+        }));
+    }
+
+    /// Runs a task built by `factory`, which is expected to run until shut down. Any exit, normal
+    /// or panicking, is reported and the task is rebuilt as long as the restart policy allows.
+    pub fn spawn_restartable<F, Fut>(&mut self, name: &str, factory: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let name = name.to_string();
+        let event_tx = self.event_tx.clone();
+        let policy = self.policy.clone();
+        self.tasks.push(tokio::spawn(async move {
+            let mut restarts = 0;
+            loop {
+                let reason = match AssertUnwindSafe(factory()).catch_unwind().await {
+                    Ok(()) => "exited unexpectedly".to_string(),
+                    Err(panic) => panic_message(&*panic),
+                };
+                report(&event_tx, &name, &reason);
+                let Some(backoff) = policy.allows(restarts) else {
+                    break;
+                };
+                restarts += 1;
+                tokio::time::sleep(backoff).await;
+            }
+        }));
+    }
+
+    pub async fn shutdown(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+    }
+}
+
+fn report(event_tx: &mpsc::UnboundedSender<ConnectionEvent>, name: &str, reason: &str) {
+    event!(warn, "{} task {}", name, reason);
+    let _ = event_tx.send(ConnectionEvent::Status {
+        event: StatusEvent::Error {
+            message: format!("{} task {}", name, reason),
+        },
+    });
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    let detail = panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned());
+    match detail {
+        Some(detail) => format!("panicked: {}", detail),
+        None => "panicked".to_string(),
+    }
+}
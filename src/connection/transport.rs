@@ -0,0 +1,133 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_socks::tcp::Socks5Stream;
+use tokio_tungstenite::{
+    client_async_tls_with_config, connect_async_tls_with_config,
+    tungstenite::client::IntoClientRequest, Connector, MaybeTlsStream, WebSocketStream,
+};
+use url::Url;
+
+use super::{ConnectionError, ConnectionOptions, Proxy, ProxyKind, TlsConfig};
+
+const MAX_RESPONSE_HEAD: usize = 8192;
+
+pub(crate) async fn connect_websocket(
+    url: &Url,
+    options: &ConnectionOptions,
+) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, ConnectionError> {
+    let request = url
+        .as_str()
+        .into_client_request()
+        .map_err(|e| ConnectionError::Auth(e.to_string()))?;
+    let connector = tls_connector(&options.tls)?;
+    let result = match &options.proxy {
+        Some(proxy) => {
+            let host = url
+                .host_str()
+                .ok_or_else(|| ConnectionError::Auth("URL has no host".to_string()))?;
+            let port = url
+                .port_or_known_default()
+                .ok_or_else(|| ConnectionError::Auth("URL has no port".to_string()))?;
+            let stream = tunnel(proxy, host, port).await?;
+            client_async_tls_with_config(request, stream, None, connector).await
+        }
+        None => connect_async_tls_with_config(request, None, false, connector).await,
+    };
+    result
+        .map(|(ws_stream, _)| ws_stream)
+        .map_err(|e| ConnectionError::Network(e.to_string()))
+}
+
+fn tls_connector(tls: &TlsConfig) -> Result<Option<Connector>, ConnectionError> {
+    if tls.is_default() {
+        return Ok(None);
+    }
+
+    let tls_error = |e: native_tls::Error| ConnectionError::Other(e.to_string());
+    let mut builder = native_tls::TlsConnector::builder();
+    builder.danger_accept_invalid_certs(tls.accept_invalid_certs);
+    for pem in &tls.root_certificates {
+        builder.add_root_certificate(native_tls::Certificate::from_pem(pem).map_err(tls_error)?);
+    }
+    if let Some(identity) = &tls.client_identity {
+        builder.identity(
+            native_tls::Identity::from_pkcs8(&identity.certificate_pem, &identity.key_pem)
+                .map_err(tls_error)?,
+        );
+    }
+    builder
+        .build()
+        .map(|connector| Some(Connector::NativeTls(connector)))
+        .map_err(tls_error)
+}
+
+async fn tunnel(proxy: &Proxy, host: &str, port: u16) -> Result<TcpStream, ConnectionError> {
+    let network = |e: std::io::Error| ConnectionError::Network(e.to_string());
+    let proxy_addr = (proxy.host.as_str(), proxy.port);
+
+    match proxy.kind {
+        ProxyKind::Socks5 => {
+            let stream = match &proxy.credentials {
+                Some((username, password)) => {
+                    Socks5Stream::connect_with_password(
+                        proxy_addr,
+                        (host, port),
+                        username,
+                        password,
+                    )
+                    .await
+                }
+                None => Socks5Stream::connect(proxy_addr, (host, port)).await,
+            }
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+            Ok(stream.into_inner())
+        }
+        ProxyKind::Http => {
+            let mut stream = TcpStream::connect(proxy_addr).await.map_err(network)?;
+
+            let mut request = format!(
+                "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n",
+                host = host,
+                port = port
+            );
+            if let Some((username, password)) = &proxy.credentials {
+                let token = STANDARD.encode(format!("{}:{}", username, password));
+                request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+            }
+            request.push_str("\r\n");
+            stream
+                .write_all(request.as_bytes())
+                .await
+                .map_err(network)?;
+
+            let mut head = Vec::new();
+            let mut byte = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                if stream.read(&mut byte).await.map_err(network)? == 0 {
+                    return Err(ConnectionError::Network(
+                        "Proxy closed the connection".to_string(),
+                    ));
+                }
+                head.push(byte[0]);
+                if head.len() > MAX_RESPONSE_HEAD {
+                    return Err(ConnectionError::Protocol(
+                        "Proxy response is too large".to_string(),
+                    ));
+                }
+            }
+
+            let head = String::from_utf8_lossy(&head);
+            let status = head.lines().next().unwrap_or_default();
+            match status.split_whitespace().nth(1) {
+                Some(code) if code.starts_with('2') => Ok(stream),
+                _ => Err(ConnectionError::Network(format!(
+                    "Proxy refused the tunnel: {}",
+                    status
+                ))),
+            }
+        }
+    }
+}
@@ -0,0 +1,990 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{SinkExt, StreamExt};
+use reqwest::StatusCode;
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use url::Url;
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{color::parse_css_color, compose::plain_text, ids::IdNormalizer, trace::event},
+    Asset, AssetSource, AuthField, Capabilities, Channel, ChannelType, Connection, FieldValue,
+    Message, MessageFragment, MessageStatus, MessageType, Permissions, Presence, Profile, Protocol,
+    Role,
+};
+
+use super::{
+    http::http_client,
+    irc::{IrcIds, IrcMessage},
+    transport::connect_websocket,
+    AssetEvent, ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, ConnectionOptions,
+    ModerationEvent, Scope, StatusEvent, Supervisor, UserEvent,
+};
+
+const DEFAULT_IRC_URL: &str = "wss://irc-ws.chat.twitch.tv:443";
+const DEFAULT_API_URL: &str = "https://api.twitch.tv/helix/";
+const EMOTE_CDN: &str = "https://static-cdn.jtvnw.net/emoticons/v2";
+const ANONYMOUS_LOGIN: &str = "justinfan12345";
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(15);
+const MAX_MESSAGE_LENGTH: usize = 500;
+
+/// A handle on the Helix API, used for emote lists.
+#[derive(Clone, Debug)]
+struct TwitchApi {
+    http: reqwest::Client,
+    base: Url,
+    token: String,
+    client_id: String,
+}
+
+impl TwitchApi {
+    async fn get(&self, path: &str, query: &[(&str, &str)]) -> Result<Value, ConnectionError> {
+        let url = self
+            .base
+            .join(path)
+            .map_err(|e| ConnectionError::Protocol(e.to_string()))?;
+        let response = self
+            .http
+            .get(url)
+            .bearer_auth(&self.token)
+            .header("Client-Id", &self.client_id)
+            .query(query)
+            .send()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        let status = response.status();
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        match status {
+            StatusCode::UNAUTHORIZED => Err(ConnectionError::Auth(status.to_string())),
+            status if !status.is_success() => Err(ConnectionError::Protocol(status.to_string())),
+            _ => {
+                serde_json::from_slice(&bytes).map_err(|e| ConnectionError::Protocol(e.to_string()))
+            }
+        }
+    }
+
+    /// Global emotes, or the emotes of the channel with `broadcaster_id`, as id, name and image.
+    async fn emotes(
+        &self,
+        broadcaster_id: Option<&str>,
+    ) -> Result<Vec<(String, String, String)>, ConnectionError> {
+        let response = match broadcaster_id {
+            Some(id) => self.get("chat/emotes", &[("broadcaster_id", id)]).await?,
+            None => self.get("chat/emotes/global", &[]).await?,
+        };
+        Ok(response["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|emote| {
+                let id = emote["id"].as_str()?;
+                let src = ["url_2x", "url_1x"]
+                    .iter()
+                    .find_map(|scale| emote["images"][scale].as_str())
+                    .map(str::to_string)
+                    .unwrap_or_else(|| emote_url(id));
+                Some((id.to_string(), emote["name"].as_str()?.to_string(), src))
+            })
+            .collect())
+    }
+}
+
+fn emote_url(id: &str) -> String {
+    format!("{}/{}/default/dark/1.0", EMOTE_CDN, id)
+}
+
+fn emote_asset(id: &str, name: &str, src: String) -> Asset {
+    Asset::Emote {
+        id: Some(id.to_string()),
+        pattern: format!(r"\b{}\b", regex::escape(name)),
+        src,
+        source: AssetSource::Server,
+    }
+}
+
+/// A role for the highest of a user's chat badges.
+fn badge_role(badges: &str) -> Option<Role> {
+    let (rank, name) = badges
+        .split(',')
+        .filter_map(|badge| match badge.split('/').next()? {
+            "broadcaster" => Some((5, "Broadcaster")),
+            "moderator" => Some((3, "Moderator")),
+            "vip" => Some((2, "VIP")),
+            "subscriber" | "founder" => Some((1, "Subscriber")),
+            _ => None,
+        })
+        .max_by_key(|(rank, _)| *rank)?;
+    Some(Role {
+        rank,
+        permissions: Permissions {
+            can_moderate: rank >= 3,
+            ..Permissions::default()
+        },
+        name: Some(name.to_string()),
+        color: None,
+    })
+}
+
+/// A chatter's profile from the login in the message prefix and the IRCv3 tags Twitch sends.
+pub fn twitch_profile(login: &str, tags: &HashMap<String, String>) -> Profile {
+    let tag = |name: &str| tags.get(name).filter(|value| !value.is_empty());
+    let mut extra = HashMap::new();
+    if let Some(user_id) = tag("user-id") {
+        extra.insert("twitch_id".to_string(), Value::from(user_id.as_str()));
+    }
+    if let Some(badges) = tag("badges") {
+        extra.insert(
+            "badges".to_string(),
+            badges.split(',').map(Value::from).collect(),
+        );
+    }
+    Profile {
+        id: Some(login.to_string()),
+        username: Some(login.to_string()),
+        display_name: tag("display-name").cloned(),
+        color: tag("color").and_then(|color| parse_css_color(color)),
+        presence: Some(Presence::Online),
+        role: tag("badges").and_then(|badges| badge_role(badges)),
+        extra,
+        ..Profile::default()
+    }
+}
+
+/// Splits `text` at the emote ranges of an `emotes` tag (`id:start-end,start-end/id:...`, in
+/// characters), returning the fragments and the name each emote id was written as.
+pub fn emote_fragments(text: &str, emotes: &str) -> (Vec<MessageFragment>, Vec<(String, String)>) {
+    let mut ranges: Vec<(usize, usize, &str)> = emotes
+        .split('/')
+        .filter_map(|emote| emote.split_once(':'))
+        .flat_map(|(id, positions)| {
+            positions.split(',').filter_map(move |range| {
+                let (start, end) = range.split_once('-')?;
+                Some((start.parse().ok()?, end.parse().ok()?, id))
+            })
+        })
+        .collect();
+    ranges.sort_unstable();
+
+    let chars: Vec<char> = text.chars().collect();
+    let (mut fragments, mut names, mut last) = (Vec::new(), Vec::new(), 0);
+    for (start, end, id) in ranges {
+        if start < last || end >= chars.len() || start > end {
+            continue;
+        }
+        if start > last {
+            fragments.push(MessageFragment::Text(chars[last..start].iter().collect()));
+        }
+        fragments.push(MessageFragment::AssetId(id.to_string()));
+        names.push((id.to_string(), chars[start..=end].iter().collect()));
+        last = end + 1;
+    }
+    if last < chars.len() {
+        fragments.push(MessageFragment::Text(chars[last..].iter().collect()));
+    }
+    (fragments, names)
+}
+
+fn channel_name(channel: &str) -> String {
+    let channel = channel.trim().to_ascii_lowercase();
+    if channel.starts_with('#') {
+        channel
+    } else {
+        format!("#{}", channel)
+    }
+}
+
+fn twitch_channel(name: &str, room_id: Option<&str>) -> Channel {
+    let mut extra = HashMap::new();
+    if let Some(room_id) = room_id {
+        extra.insert("room_id".to_string(), Value::from(room_id));
+    }
+    Channel {
+        id: name.to_string(),
+        name: Some(name.to_string()),
+        channel_type: ChannelType::Group,
+        topic: None,
+        extra,
+    }
+}
+
+fn server_message(id: Option<&String>, content: Vec<MessageFragment>) -> Message {
+    Message {
+        id: id.cloned(),
+        sender_id: None,
+        content,
+        timestamp: Utc::now(),
+        message_type: MessageType::Server,
+        status: MessageStatus::Delivered,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
+    }
+}
+
+/// Turns Twitch IRC lines into oshatori events for one login.
+#[derive(Clone, Debug)]
+struct TwitchMapper {
+    login: String,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    emotes: Arc<StdMutex<HashMap<String, String>>>,
+    chatters: Arc<StdMutex<HashMap<(String, String), Value>>>,
+    pending: Arc<StdMutex<Option<HashSet<String>>>>,
+}
+
+impl TwitchMapper {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn add_emotes(&self, scope: Scope, emotes: Vec<(String, String, String)>) {
+        for (id, name, src) in emotes {
+            let asset = emote_asset(&id, &name, src);
+            if let Ok(mut emotes) = self.emotes.lock() {
+                emotes.insert(id, name);
+            }
+            self.emit(ConnectionEvent::Asset {
+                event: AssetEvent::New {
+                    scope: scope.clone(),
+                    asset,
+                },
+            });
+        }
+    }
+
+    /// Registers emotes first seen in a message's tags, so their asset ids resolve.
+    fn learn_emotes(&self, names: Vec<(String, String)>) {
+        let unknown: Vec<Asset> = {
+            let Ok(mut emotes) = self.emotes.lock() else {
+                return;
+            };
+            names
+                .into_iter()
+                .filter_map(|(id, name)| {
+                    if emotes.contains_key(&id) {
+                        return None;
+                    }
+                    let asset = emote_asset(&id, &name, emote_url(&id));
+                    emotes.insert(id, name);
+                    Some(asset)
+                })
+                .collect()
+        };
+        for asset in unknown {
+            self.emit(ConnectionEvent::Asset {
+                event: AssetEvent::New {
+                    scope: Scope::Global,
+                    asset,
+                },
+            });
+        }
+    }
+
+    fn emote_name(&self, id: &str) -> Option<String> {
+        self.emotes.lock().ok()?.get(id).cloned()
+    }
+
+    /// Announces a chatter in `channel`, again whenever their tags change their profile.
+    fn chatter(&self, channel: &str, user: Profile) {
+        let Some(login) = user.id.clone() else {
+            return;
+        };
+        let value = serde_json::to_value(&user).unwrap_or_default();
+        let previous = match self.chatters.lock() {
+            Ok(mut chatters) => {
+                chatters.insert((channel.to_string(), login.clone()), value.clone())
+            }
+            Err(_) => return,
+        };
+        let scope = Scope::channel(channel);
+        let event = match previous {
+            None => UserEvent::New { scope, user },
+            Some(previous) if previous != value => UserEvent::Update {
+                scope,
+                user_id: login,
+                new_user: user,
+            },
+            Some(_) => return,
+        };
+        self.emit(ConnectionEvent::User { event });
+    }
+
+    fn forget_chatter(&self, channel: &str, login: &str) {
+        if let Ok(mut chatters) = self.chatters.lock() {
+            chatters.remove(&(channel.to_string(), login.to_string()));
+        }
+    }
+
+    /// Marks `channel` as loaded, emitting `Synced` once every configured channel is.
+    fn loaded(&self, channel: &str) {
+        let Ok(mut pending) = self.pending.lock() else {
+            return;
+        };
+        let done = pending.as_mut().is_some_and(|channels| {
+            channels.remove(channel);
+            channels.is_empty()
+        });
+        if done {
+            *pending = None;
+            self.emit(ConnectionEvent::Status {
+                event: StatusEvent::Synced,
+            });
+        }
+    }
+
+    fn content(&self, message: &IrcMessage, text: &str) -> Vec<MessageFragment> {
+        let (fragments, names) =
+            emote_fragments(text, message.tags.get("emotes").map_or("", String::as_str));
+        self.learn_emotes(names);
+        fragments
+    }
+
+    fn chat(&self, message: &IrcMessage) {
+        let (Some(channel), Some(text), Some(login)) =
+            (message.param(0), message.param(1), message.nick())
+        else {
+            return;
+        };
+        let login = login.to_ascii_lowercase();
+        self.chatter(channel, twitch_profile(&login, &message.tags));
+
+        let (text, message_type) = match text
+            .strip_prefix("\x01ACTION ")
+            .map(|action| action.trim_end_matches('\x01'))
+        {
+            Some(action) => (action, MessageType::Action),
+            None if login == self.login => (text, MessageType::CurrentUser),
+            None => (text, MessageType::Normal),
+        };
+        let timestamp = message
+            .tags
+            .get("tmi-sent-ts")
+            .and_then(|ts| ts.parse().ok())
+            .and_then(DateTime::from_timestamp_millis)
+            .unwrap_or_else(Utc::now);
+        let mut extra = HashMap::new();
+        if let Some(bits) = message.tags.get("bits") {
+            extra.insert("bits".to_string(), Value::from(bits.as_str()));
+        }
+        self.emit(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel(channel),
+                message: Message {
+                    id: message.tags.get("id").cloned(),
+                    sender_id: Some(login),
+                    content: self.content(message, text),
+                    timestamp,
+                    message_type,
+                    status: MessageStatus::Delivered,
+                    reactions: Vec::new(),
+                    reply_to: message.tags.get("reply-parent-msg-id").cloned(),
+                    thread_id: message.tags.get("reply-thread-parent-msg-id").cloned(),
+                    extra,
+                },
+            },
+        });
+    }
+
+    fn apply(&self, message: &IrcMessage) {
+        let channel = message.param(0).unwrap_or_default();
+        match message.command.as_str() {
+            "PRIVMSG" => self.chat(message),
+            "JOIN" => {
+                let Some(login) = message.nick() else {
+                    return;
+                };
+                if login.eq_ignore_ascii_case(&self.login) {
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::New {
+                            channel: twitch_channel(channel, None),
+                        },
+                    });
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::Join {
+                            channel_id: channel.to_string(),
+                        },
+                    });
+                } else {
+                    self.chatter(channel, twitch_profile(login, &HashMap::new()));
+                }
+            }
+            "PART" => {
+                let Some(login) = message.nick() else {
+                    return;
+                };
+                if login.eq_ignore_ascii_case(&self.login) {
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::Leave {
+                            channel_id: channel.to_string(),
+                        },
+                    });
+                } else {
+                    let login = login.to_ascii_lowercase();
+                    self.forget_chatter(channel, &login);
+                    self.emit(ConnectionEvent::User {
+                        event: UserEvent::Remove {
+                            scope: Scope::channel(channel),
+                            user_id: login,
+                        },
+                    });
+                }
+            }
+            "353" => {
+                let (Some(channel), Some(names)) = (message.param(2), message.param(3)) else {
+                    return;
+                };
+                for login in names.split_whitespace() {
+                    self.chatter(channel, twitch_profile(login, &HashMap::new()));
+                }
+            }
+            "ROOMSTATE" => {
+                if let Some(room_id) = message.tags.get("room-id") {
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::Update {
+                            channel_id: channel.to_string(),
+                            new_channel: twitch_channel(channel, Some(room_id)),
+                        },
+                    });
+                }
+            }
+            "GLOBALUSERSTATE" => {
+                let user = twitch_profile(&self.login, &message.tags);
+                self.emit(ConnectionEvent::User {
+                    event: UserEvent::Update {
+                        scope: Scope::Global,
+                        user_id: self.login.clone(),
+                        new_user: user,
+                    },
+                });
+            }
+            "USERSTATE" => self.chatter(channel, twitch_profile(&self.login, &message.tags)),
+            "USERNOTICE" => {
+                let mut content = Vec::new();
+                if let Some(system) = message.tags.get("system-msg") {
+                    content.push(MessageFragment::Text(system.clone()));
+                }
+                if let Some(text) = message.param(1) {
+                    if !content.is_empty() {
+                        content.push(MessageFragment::Text("\n".to_string()));
+                    }
+                    content.extend(self.content(message, text));
+                }
+                let mut notice = server_message(message.tags.get("id"), content);
+                if let Some(kind) = message.tags.get("msg-id") {
+                    notice
+                        .extra
+                        .insert("notice".to_string(), Value::from(kind.as_str()));
+                }
+                notice.sender_id = message.tags.get("login").cloned();
+                self.emit(ConnectionEvent::Chat {
+                    event: ChatEvent::New {
+                        scope: Scope::channel(channel),
+                        message: notice,
+                    },
+                });
+            }
+            "NOTICE" if channel.starts_with('#') => {
+                let text = message.param(1).unwrap_or_default();
+                self.emit(ConnectionEvent::Chat {
+                    event: ChatEvent::New {
+                        scope: Scope::channel(channel),
+                        message: server_message(
+                            None,
+                            vec![MessageFragment::Text(text.to_string())],
+                        ),
+                    },
+                });
+            }
+            "CLEARMSG" => {
+                if let Some(message_id) = message.tags.get("target-msg-id") {
+                    self.emit(ConnectionEvent::Chat {
+                        event: ChatEvent::Remove {
+                            scope: Scope::channel(channel),
+                            message_id: message_id.clone(),
+                        },
+                    });
+                }
+            }
+            "CLEARCHAT" => {
+                let scope = Scope::channel(channel);
+                let Some(login) = message.param(1) else {
+                    self.emit(ConnectionEvent::Channel {
+                        event: ChannelEvent::Wipe { scope },
+                    });
+                    return;
+                };
+                let user_id = login.to_string();
+                let event = match message
+                    .tags
+                    .get("ban-duration")
+                    .and_then(|secs| secs.parse::<i64>().ok())
+                {
+                    Some(secs) => ModerationEvent::Mute {
+                        scope,
+                        user_id,
+                        until: Some(Utc::now() + chrono::Duration::seconds(secs)),
+                    },
+                    None => ModerationEvent::Ban {
+                        scope,
+                        user_id,
+                        reason: None,
+                        until: None,
+                    },
+                };
+                self.emit(ConnectionEvent::Moderation { event });
+            }
+            _ => {}
+        }
+        if message.command == "ROOMSTATE" {
+            self.loaded(channel);
+        }
+    }
+}
+
+/// Twitch chat over IRC-over-WebSocket. Channels are keyed `#login`, chatters by their login;
+/// badges and colors from message tags fill their profiles. Without an OAuth token the
+/// connection joins anonymously and can only read.
+pub struct TwitchConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    mapper: Option<TwitchMapper>,
+    line_tx: Option<mpsc::UnboundedSender<String>>,
+    anonymous: bool,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl TwitchConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        TwitchConnection {
+            auth: Vec::new(),
+            options,
+            event_tx,
+            event_rx: Some(event_rx),
+            mapper: None,
+            line_tx: None,
+            anonymous: true,
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn fail_auth(&self, reason: String) -> ConnectionError {
+        self.set_status(ConnectionStatus::AuthFailed {
+            reason: reason.clone(),
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed {
+                reason: reason.clone(),
+            },
+        });
+        ConnectionError::Auth(reason)
+    }
+
+    fn send_line(&self, line: String) -> Result<(), ConnectionError> {
+        self.line_tx
+            .as_ref()
+            .and_then(|tx| tx.send(line).ok())
+            .ok_or(ConnectionError::Closed)
+    }
+
+    fn render(&self, content: &[MessageFragment]) -> String {
+        content
+            .iter()
+            .map(|fragment| match fragment {
+                MessageFragment::AssetId(id) => self
+                    .mapper
+                    .as_ref()
+                    .and_then(|mapper| mapper.emote_name(id))
+                    .unwrap_or_default(),
+                MessageFragment::Mention { user_id, .. } => format!("@{}", user_id),
+                other => plain_text(std::slice::from_ref(other)),
+            })
+            .collect::<String>()
+            .replace(['\r', '\n'], " ")
+    }
+}
+
+impl Default for TwitchConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+#[async_trait]
+impl Connection for TwitchConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "twitch.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let token = field_text(&self.auth, "oauth_token")
+            .map(|token| token.trim_start_matches("oauth:").to_string());
+        let login = match (&token, field_text(&self.auth, "username")) {
+            (Some(_), Some(username)) => username.to_ascii_lowercase(),
+            (Some(_), None) => {
+                return Err(ConnectionError::Auth(
+                    "A username is needed with an OAuth token".to_string(),
+                ))
+            }
+            (None, _) => ANONYMOUS_LOGIN.to_string(),
+        };
+        let url = field_text(&self.auth, "irc_url").unwrap_or(DEFAULT_IRC_URL.to_string());
+        let url = Url::parse(&url).map_err(|e| ConnectionError::Auth(e.to_string()))?;
+        let channels: Vec<String> = field_text(&self.auth, "channels")
+            .unwrap_or_default()
+            .split([',', ' '])
+            .filter(|channel| !channel.trim().is_empty())
+            .map(channel_name)
+            .collect();
+        let api = match (&token, field_text(&self.auth, "client_id")) {
+            (Some(token), Some(client_id)) => {
+                let base = field_text(&self.auth, "api_url").unwrap_or(DEFAULT_API_URL.to_string());
+                let mut base =
+                    Url::parse(&base).map_err(|e| ConnectionError::Auth(e.to_string()))?;
+                if !base.path().ends_with('/') {
+                    base.set_path(&format!("{}/", base.path()));
+                }
+                Some(TwitchApi {
+                    http: http_client(&self.options)?,
+                    base,
+                    token: token.clone(),
+                    client_id,
+                })
+            }
+            _ => None,
+        };
+
+        self.tasks.shutdown().await;
+        self.line_tx = None;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+
+        let mut socket = match connect_websocket(&url, &self.options).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+        let pass = token.as_ref().map(|token| format!("PASS oauth:{}", token));
+        let login_lines = [
+            Some("CAP REQ :twitch.tv/tags twitch.tv/commands twitch.tv/membership".to_string()),
+            pass,
+            Some(format!("NICK {}", login)),
+        ];
+        for line in login_lines.into_iter().flatten() {
+            socket
+                .send(WsMessage::Text(line.into()))
+                .await
+                .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        }
+
+        let welcome = tokio::time::timeout(LOGIN_TIMEOUT, async {
+            while let Some(frame) = socket.next().await {
+                let text = match frame {
+                    Ok(WsMessage::Text(text)) => text,
+                    Ok(WsMessage::Close(_)) | Err(_) => break,
+                    Ok(_) => continue,
+                };
+                for message in text.lines().filter_map(IrcMessage::parse) {
+                    match message.command.as_str() {
+                        "001" => return Ok(()),
+                        "NOTICE" => {
+                            let text = message.param(1).unwrap_or_default().to_string();
+                            if text.to_ascii_lowercase().contains("auth") {
+                                return Err(ConnectionError::Auth(text));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Err(ConnectionError::Closed)
+        })
+        .await
+        .map_err(|_| ConnectionError::Timeout)
+        .and_then(|welcome| welcome);
+        match welcome {
+            Err(ConnectionError::Auth(reason)) => return Err(self.fail_auth(reason)),
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+            Ok(()) => {}
+        }
+
+        let mapper = TwitchMapper {
+            login: login.clone(),
+            event_tx: self.event_tx.clone(),
+            emotes: Arc::new(StdMutex::new(HashMap::new())),
+            chatters: Arc::new(StdMutex::new(HashMap::new())),
+            pending: Arc::new(StdMutex::new(Some(channels.iter().cloned().collect()))),
+        };
+        self.anonymous = token.is_none();
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected {
+                artifact: url.host_str().map(str::to_string),
+            },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::User {
+            event: UserEvent::Identify {
+                user_id: login.clone(),
+            },
+        });
+        if let Some(api) = &api {
+            match api.emotes(None).await {
+                Ok(assets) => mapper.add_emotes(Scope::Global, assets),
+                Err(e) => event!(warn, "could not load global Twitch emotes: {}", e),
+            }
+        }
+        if channels.is_empty() {
+            mapper.loaded("");
+        }
+
+        let (line_tx, mut line_rx) = mpsc::unbounded_channel::<String>();
+        for channel in &channels {
+            let _ = line_tx.send(format!("JOIN {}", channel));
+        }
+        self.line_tx = Some(line_tx);
+        self.mapper = Some(mapper.clone());
+        let status = self.status.clone();
+        self.tasks.spawn_essential("socket", self.status.clone(), async move {
+            let mut loaded_rooms = HashSet::new();
+            loop {
+                tokio::select! {
+                    frame = socket.next() => {
+                        let text = match frame {
+                            Some(Ok(WsMessage::Text(text))) => text,
+                            Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                            Some(Ok(_)) => continue,
+                        };
+                        let mut reconnect = false;
+                        for message in text.lines().filter_map(IrcMessage::parse) {
+                            match message.command.as_str() {
+                                "PING" => {
+                                    let pong = format!("PONG :{}", message.param(0).unwrap_or("tmi.twitch.tv"));
+                                    let _ = socket.send(WsMessage::Text(pong.into())).await;
+                                }
+                                "RECONNECT" => reconnect = true,
+                                _ => {
+                                    if let (Some(api), "ROOMSTATE", Some(room_id)) =
+                                        (&api, message.command.as_str(), message.tags.get("room-id"))
+                                    {
+                                        if loaded_rooms.insert(room_id.clone()) {
+                                            let scope = Scope::channel(message.param(0).unwrap_or_default());
+                                            match api.emotes(Some(room_id)).await {
+                                                Ok(assets) => mapper.add_emotes(scope, assets),
+                                                Err(e) => event!(warn, "could not load channel emotes: {}", e),
+                                            }
+                                        }
+                                    }
+                                    mapper.apply(&message);
+                                }
+                            }
+                        }
+                        if reconnect {
+                            break;
+                        }
+                    }
+                    Some(line) = line_rx.recv() => {
+                        if socket.send(WsMessage::Text(line.into())).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            if let Ok(mut current) = status.lock() {
+                if *current == ConnectionStatus::Connected {
+                    *current = ConnectionStatus::Disconnected;
+                }
+            }
+            mapper.emit(ConnectionEvent::Status {
+                event: StatusEvent::Disconnected {
+                    artifact: Some("Twitch chat closed".to_string()),
+                },
+            });
+        });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.line_tx = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        match event {
+            ConnectionEvent::Chat {
+                event: ChatEvent::New { scope, message },
+            } => {
+                if self.anonymous {
+                    return Err(ConnectionError::Unsupported(
+                        "Anonymous Twitch connections cannot chat".to_string(),
+                    ));
+                }
+                let channel = scope.channel_id().ok_or_else(|| {
+                    ConnectionError::Unsupported("Twitch messages need a channel".to_string())
+                })?;
+                let text = self.render(&message.content);
+                let text = match message.message_type {
+                    MessageType::Action => format!("\x01ACTION {}\x01", text),
+                    _ => text,
+                };
+                let line = IrcMessage::new("PRIVMSG", &[channel, &text]).to_line();
+                let line = match &message.reply_to {
+                    Some(parent) => format!("@reply-parent-msg-id={} {}", parent, line),
+                    None => line,
+                };
+                self.send_line(line)?;
+                let mut echo = message;
+                echo.sender_id = self.mapper.as_ref().map(|mapper| mapper.login.clone());
+                echo.message_type = match echo.message_type {
+                    MessageType::Action => MessageType::Action,
+                    _ => MessageType::CurrentUser,
+                };
+                let _ = self.event_tx.send(ConnectionEvent::Chat {
+                    event: ChatEvent::New {
+                        scope,
+                        message: echo,
+                    },
+                });
+                Ok(())
+            }
+            ConnectionEvent::Channel {
+                event:
+                    ChannelEvent::Join { channel_id }
+                    | ChannelEvent::New {
+                        channel: Channel { id: channel_id, .. },
+                    },
+            } => {
+                let channel = channel_name(&channel_id);
+                if let Some(mapper) = &self.mapper {
+                    if let Ok(mut pending) = mapper.pending.lock() {
+                        if let Some(pending) = pending.as_mut() {
+                            pending.insert(channel.clone());
+                        }
+                    }
+                }
+                self.send_line(format!("JOIN {}", channel))
+            }
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Leave { channel_id },
+            } => self.send_line(format!("PART {}", channel_name(&channel_id))),
+            _ => Err(ConnectionError::Unsupported(
+                "Event not supported over Twitch chat".to_string(),
+            )),
+        }
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        let field = |name: &str, display: &str, value: FieldValue| AuthField {
+            name: name.to_string(),
+            display: Some(display.to_string()),
+            value,
+            required: false,
+        };
+        Protocol {
+            name: "twitch".to_string(),
+            auth: Some(vec![
+                field("username", "Login name", FieldValue::Text(None)),
+                field(
+                    "oauth_token",
+                    "OAuth token (leave empty to read anonymously)",
+                    FieldValue::Password(None),
+                ),
+                field(
+                    "channels",
+                    "Channels to join, comma separated",
+                    FieldValue::Text(None),
+                ),
+                field(
+                    "client_id",
+                    "Application client id, for loading emotes",
+                    FieldValue::Text(None),
+                ),
+                field("irc_url", "Chat WebSocket URL", FieldValue::Text(None)),
+                field("api_url", "Helix API base URL", FieldValue::Text(None)),
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            multiple_channels: true,
+            max_message_length: Some(MAX_MESSAGE_LENGTH),
+            ..Capabilities::default()
+        }
+    }
+
+    fn render_text(&self, content: &[MessageFragment]) -> String {
+        self.render(content)
+    }
+
+    fn id_normalizer(&self) -> Arc<dyn IdNormalizer> {
+        Arc::new(IrcIds)
+    }
+}
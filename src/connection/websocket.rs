@@ -0,0 +1,748 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use url::Url;
+
+use crate::{
+    connection::ConnectionStatus,
+    utils::{compose::plain_text, trace::event},
+    AuthField, Capabilities, Channel, ChannelType, Connection, FieldValue, Message,
+    MessageFragment, MessageStatus, MessageType, Profile, Protocol,
+};
+
+use super::{
+    preflight::{probe_reachability, validate_auth},
+    transport::connect_websocket,
+    ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, ConnectionOptions, PreflightReport,
+    Scope, StatusEvent, Supervisor, UserEvent,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+/// A path into a JSON frame, in the subset of JSONPath made of `$`, `.field`, `['field']` and
+/// `[index]`, e.g. `$.data.author['display name']` or `$.users[0].id`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct JsonPath {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl JsonPath {
+    pub fn get<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.segments
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Field(name) => value.get(name),
+                Segment::Index(index) => value.get(index),
+            })
+            .filter(|value| !value.is_null())
+    }
+
+    /// The value at this path as text, with numbers and booleans written out.
+    fn text(&self, value: &Value) -> Option<String> {
+        match self.get(value)? {
+            Value::String(text) => Some(text.clone()),
+            Value::Number(number) => Some(number.to_string()),
+            Value::Bool(flag) => Some(flag.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = ConnectionError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| ConnectionError::Protocol(format!("Bad path {}: {}", path, why));
+        let mut rest = path
+            .strip_prefix('$')
+            .ok_or_else(|| invalid("must start with $"))?;
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                if end == 0 {
+                    return Err(invalid("empty field name"));
+                }
+                segments.push(Segment::Field(after[..end].to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix("['") {
+                let end = after.find("']").ok_or_else(|| invalid("unclosed ['"))?;
+                segments.push(Segment::Field(after[..end].to_string()));
+                rest = &after[end + 2..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(|| invalid("unclosed ["))?;
+                let index = after[..end]
+                    .parse()
+                    .map_err(|_| invalid("index is not a number"))?;
+                segments.push(Segment::Index(index));
+                rest = &after[end + 1..];
+            } else {
+                return Err(invalid("expected . or ["));
+            }
+        }
+        Ok(JsonPath {
+            source: path.to_string(),
+            segments,
+        })
+    }
+}
+
+impl TryFrom<String> for JsonPath {
+    type Error = ConnectionError;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        path.parse()
+    }
+}
+
+impl From<JsonPath> for String {
+    fn from(path: JsonPath) -> Self {
+        path.source
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Limits a rule to frames where `path` is present, or equal to `equals` when given.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WsCondition {
+    pub path: JsonPath,
+    #[serde(default)]
+    pub equals: Option<Value>,
+}
+
+impl WsCondition {
+    fn matches(&self, frame: &Value) -> bool {
+        match (self.path.get(frame), &self.equals) {
+            (Some(value), Some(equals)) => value == equals,
+            (found, None) => found.is_some(),
+            (None, Some(_)) => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WsMessageFields {
+    pub text: JsonPath,
+    #[serde(default)]
+    pub id: Option<JsonPath>,
+    #[serde(default)]
+    pub sender_id: Option<JsonPath>,
+    #[serde(default)]
+    pub channel_id: Option<JsonPath>,
+    /// Unix seconds, unix milliseconds or an RFC 3339 string.
+    #[serde(default)]
+    pub timestamp: Option<JsonPath>,
+    #[serde(default)]
+    pub reply_to: Option<JsonPath>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WsProfileFields {
+    pub id: JsonPath,
+    #[serde(default)]
+    pub username: Option<JsonPath>,
+    #[serde(default)]
+    pub display_name: Option<JsonPath>,
+    #[serde(default)]
+    pub picture: Option<JsonPath>,
+    /// The channel the user is listed in; users are global without it.
+    #[serde(default)]
+    pub channel_id: Option<JsonPath>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WsChannelFields {
+    pub id: JsonPath,
+    #[serde(default)]
+    pub name: Option<JsonPath>,
+    #[serde(default)]
+    pub topic: Option<JsonPath>,
+}
+
+/// What a matching frame turns into.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WsTarget {
+    Message(WsMessageFields),
+    Profile(WsProfileFields),
+    Channel(WsChannelFields),
+    /// The id of the logged in user, usually taken from a login reply.
+    Identify {
+        id: JsonPath,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WsRule {
+    #[serde(default)]
+    pub when: Option<WsCondition>,
+    /// Applies the rule to every element of the array at this path instead of the frame.
+    #[serde(default)]
+    pub each: Option<JsonPath>,
+    #[serde(flatten)]
+    pub target: WsTarget,
+}
+
+/// Describes how to talk to a JSON-over-WebSocket chat server. Every rule matching an incoming
+/// frame is applied, so one frame can carry both a profile and a message.
+///
+/// ```json
+/// {
+///   "hello": [{ "type": "auth", "token": "{token}" }],
+///   "rules": [
+///     { "when": { "path": "$.type", "equals": "ready" }, "identify": { "id": "$.user.id" } },
+///     {
+///       "when": { "path": "$.type", "equals": "message" },
+///       "message": { "text": "$.body", "sender_id": "$.author.id", "channel_id": "$.room" }
+///     }
+///   ],
+///   "send": { "type": "message", "room": "{channel_id}", "body": "{text}" }
+/// }
+/// ```
+///
+/// In `hello` and `send`, the placeholders `{token}`, `{channel_id}` and `{text}` are replaced
+/// inside any string.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WsMapping {
+    #[serde(default)]
+    pub hello: Vec<Value>,
+    #[serde(default)]
+    pub rules: Vec<WsRule>,
+    #[serde(default)]
+    pub send: Option<Value>,
+    /// The channel for messages that do not name one.
+    #[serde(default = "default_channel")]
+    pub default_channel: String,
+}
+
+fn default_channel() -> String {
+    "default".to_string()
+}
+
+impl Default for WsMapping {
+    fn default() -> Self {
+        WsMapping {
+            hello: Vec::new(),
+            rules: Vec::new(),
+            send: None,
+            default_channel: default_channel(),
+        }
+    }
+}
+
+impl FromStr for WsMapping {
+    type Err = ConnectionError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(spec)
+            .map_err(|e| ConnectionError::Auth(format!("Invalid mapping: {}", e)))
+    }
+}
+
+/// Replaces placeholders in every string of `template`.
+fn fill(template: &Value, values: &[(&str, &str)]) -> Value {
+    match template {
+        Value::String(text) => {
+            Value::String(values.iter().fold(text.clone(), |text, (key, value)| {
+                text.replace(&format!("{{{}}}", key), value)
+            }))
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| fill(item, values)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), fill(value, values)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    let number = match value {
+        Value::Number(number) => number.as_f64()?,
+        Value::String(text) => match text.parse::<f64>() {
+            Ok(number) => number,
+            Err(_) => {
+                return DateTime::parse_from_rfc3339(text)
+                    .ok()
+                    .map(|date| date.with_timezone(&Utc))
+            }
+        },
+        _ => return None,
+    };
+    // Anything past the year 5000 in seconds is taken to be milliseconds.
+    let millis = if number.abs() > 1e11 {
+        number
+    } else {
+        number * 1000.0
+    };
+    Utc.timestamp_millis_opt(millis as i64).single()
+}
+
+#[derive(Clone)]
+struct WsMapper {
+    mapping: Arc<WsMapping>,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    own_id: Arc<StdMutex<Option<String>>>,
+    channels: Arc<StdMutex<HashSet<String>>>,
+}
+
+impl WsMapper {
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.event_tx.send(event);
+    }
+
+    fn frame(&self, frame: &Value) {
+        for rule in &self.mapping.rules {
+            if rule.when.as_ref().is_some_and(|when| !when.matches(frame)) {
+                continue;
+            }
+            match &rule.each {
+                Some(each) => {
+                    let items = each.get(frame).and_then(|items| items.as_array());
+                    for item in items.into_iter().flatten() {
+                        self.apply(&rule.target, item);
+                    }
+                }
+                None => self.apply(&rule.target, frame),
+            }
+        }
+    }
+
+    fn apply(&self, target: &WsTarget, value: &Value) {
+        match target {
+            WsTarget::Message(fields) => self.message(fields, value),
+            WsTarget::Profile(fields) => {
+                let Some(id) = fields.id.text(value) else {
+                    return;
+                };
+                let channel_id = fields.channel_id.as_ref().and_then(|path| path.text(value));
+                if let Some(channel_id) = &channel_id {
+                    self.channel(channel_id, None, None);
+                }
+                let text =
+                    |path: &Option<JsonPath>| path.as_ref().and_then(|path| path.text(value));
+                self.emit(ConnectionEvent::User {
+                    event: UserEvent::New {
+                        scope: channel_id.into(),
+                        user: Profile {
+                            id: Some(id),
+                            username: text(&fields.username),
+                            display_name: text(&fields.display_name),
+                            color: None,
+                            picture: text(&fields.picture),
+                            presence: None,
+                            role: None,
+                            extra: HashMap::new(),
+                        },
+                    },
+                });
+            }
+            WsTarget::Channel(fields) => {
+                if let Some(id) = fields.id.text(value) {
+                    let text =
+                        |path: &Option<JsonPath>| path.as_ref().and_then(|path| path.text(value));
+                    self.channel(&id, text(&fields.name), text(&fields.topic));
+                }
+            }
+            WsTarget::Identify { id } => {
+                if let Some(user_id) = id.text(value) {
+                    if let Ok(mut own_id) = self.own_id.lock() {
+                        *own_id = Some(user_id.clone());
+                    }
+                    self.emit(ConnectionEvent::User {
+                        event: UserEvent::Identify { user_id },
+                    });
+                }
+            }
+        }
+    }
+
+    /// Announces and joins a channel the first time it is seen.
+    fn channel(&self, id: &str, name: Option<String>, topic: Option<String>) {
+        let new = self
+            .channels
+            .lock()
+            .map(|mut channels| channels.insert(id.to_string()))
+            .unwrap_or(false);
+        if !new {
+            return;
+        }
+        self.emit(ConnectionEvent::Channel {
+            event: ChannelEvent::New {
+                channel: Channel {
+                    id: id.to_string(),
+                    name,
+                    channel_type: ChannelType::Group,
+                    topic,
+                    extra: HashMap::new(),
+                },
+            },
+        });
+        self.emit(ConnectionEvent::Channel {
+            event: ChannelEvent::Join {
+                channel_id: id.to_string(),
+            },
+        });
+    }
+
+    fn message(&self, fields: &WsMessageFields, value: &Value) {
+        let Some(text) = fields.text.text(value) else {
+            return;
+        };
+        let text_at = |path: &Option<JsonPath>| path.as_ref().and_then(|path| path.text(value));
+        let channel_id =
+            text_at(&fields.channel_id).unwrap_or_else(|| self.mapping.default_channel.clone());
+        self.channel(&channel_id, None, None);
+        let sender_id = text_at(&fields.sender_id);
+        let own =
+            sender_id.is_some() && self.own_id.lock().is_ok_and(|own_id| *own_id == sender_id);
+        self.emit(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel(channel_id),
+                message: Message {
+                    id: text_at(&fields.id),
+                    sender_id,
+                    content: vec![MessageFragment::Text(text)],
+                    timestamp: fields
+                        .timestamp
+                        .as_ref()
+                        .and_then(|path| path.get(value))
+                        .and_then(timestamp)
+                        .unwrap_or_else(Utc::now),
+                    message_type: if own {
+                        MessageType::CurrentUser
+                    } else {
+                        MessageType::Normal
+                    },
+                    status: MessageStatus::Delivered,
+                    reactions: Vec::new(),
+                    reply_to: text_at(&fields.reply_to),
+                    thread_id: None,
+                    extra: HashMap::new(),
+                },
+            },
+        });
+    }
+}
+
+/// A backend for chat servers that speak JSON over a WebSocket, driven entirely by a
+/// [`WsMapping`] given in the `mapping` field or with [`GenericWsConnection::mapping`].
+pub struct GenericWsConnection {
+    auth: Vec<AuthField>,
+    options: ConnectionOptions,
+    mapping: Option<WsMapping>,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+    mapper: Option<WsMapper>,
+    frames: Option<mpsc::UnboundedSender<Value>>,
+    status: Arc<StdMutex<ConnectionStatus>>,
+    tasks: Supervisor,
+}
+
+impl GenericWsConnection {
+    pub fn new() -> Self {
+        Self::with_options(ConnectionOptions::default())
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let tasks = Supervisor::new(event_tx.clone(), options.restart_policy.clone());
+        GenericWsConnection {
+            auth: Vec::new(),
+            options,
+            mapping: None,
+            event_tx,
+            event_rx: Some(event_rx),
+            mapper: None,
+            frames: None,
+            status: Arc::new(StdMutex::new(ConnectionStatus::Disconnected)),
+            tasks,
+        }
+    }
+
+    /// The mapping to use when the `mapping` field is empty.
+    pub fn mapping(mut self, mapping: WsMapping) -> Self {
+        self.mapping = Some(mapping);
+        self
+    }
+
+    fn set_status(&self, status: ConnectionStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+    }
+
+    fn resolve_mapping(&self) -> Result<WsMapping, ConnectionError> {
+        match (field_text(&self.auth, "mapping"), &self.mapping) {
+            (Some(spec), _) => spec.parse(),
+            (None, Some(mapping)) => Ok(mapping.clone()),
+            (None, None) => Err(ConnectionError::Auth("Missing mapping".to_string())),
+        }
+    }
+}
+
+impl Default for GenericWsConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn field_text(auth: &[AuthField], name: &str) -> Option<String> {
+    auth.iter()
+        .find(|field| field.name == name)
+        .and_then(|field| match &field.value {
+            FieldValue::Text(value) | FieldValue::Password(value) | FieldValue::Key(value) => {
+                value.clone()
+            }
+            FieldValue::Group(_) | FieldValue::File(_) => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+#[async_trait]
+impl Connection for GenericWsConnection {
+    fn set_auth(&mut self, auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        self.auth = auth;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "websocket.connect", skip_all)
+    )]
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let url = field_text(&self.auth, "url")
+            .ok_or_else(|| ConnectionError::Auth("Missing URL".to_string()))?;
+        let url = Url::parse(&url).map_err(|e| ConnectionError::Auth(e.to_string()))?;
+        let mapping = Arc::new(self.resolve_mapping()?);
+        let token = field_text(&self.auth, "token").unwrap_or_default();
+
+        self.tasks.shutdown().await;
+        self.mapper = None;
+        self.frames = None;
+        self.set_status(ConnectionStatus::Connecting);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connecting,
+        });
+        let connect = connect_websocket(&url, &self.options);
+        let socket = match self.options.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect)
+                .await
+                .map_err(|_| ConnectionError::Timeout)
+                .and_then(|socket| socket),
+            None => connect.await,
+        };
+        let mut socket = match socket {
+            Ok(socket) => socket,
+            Err(e) => {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(e);
+            }
+        };
+        for hello in &mapping.hello {
+            let frame = fill(hello, &[("token", &token)]);
+            if let Err(e) = socket.send(WsMessage::Text(frame.to_string().into())).await {
+                self.set_status(ConnectionStatus::Disconnected);
+                return Err(ConnectionError::Network(e.to_string()));
+            }
+        }
+
+        self.set_status(ConnectionStatus::Connected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected { artifact: None },
+        });
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Synced,
+        });
+
+        let mapper = WsMapper {
+            mapping,
+            event_tx: self.event_tx.clone(),
+            own_id: Arc::new(StdMutex::new(None)),
+            channels: Arc::new(StdMutex::new(HashSet::new())),
+        };
+        let (frames_tx, mut frames_rx) = mpsc::unbounded_channel::<Value>();
+        self.mapper = Some(mapper.clone());
+        self.frames = Some(frames_tx);
+        let status = self.status.clone();
+        let ping_interval = self.options.ping_interval;
+        self.tasks
+            .spawn_essential("socket", self.status.clone(), async move {
+                let mut ping = tokio::time::interval(ping_interval);
+                ping.tick().await;
+                loop {
+                    let outgoing = tokio::select! {
+                        frame = socket.next() => match frame {
+                            Some(Ok(WsMessage::Text(text))) => {
+                                match serde_json::from_str::<Value>(&text) {
+                                    Ok(frame) => mapper.frame(&frame),
+                                    Err(_) => event!(debug, "unparsed frame {:?}", text),
+                                }
+                                None
+                            }
+                            Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                            Some(Ok(_)) => None,
+                        },
+                        Some(frame) = frames_rx.recv() => {
+                            Some(WsMessage::Text(frame.to_string().into()))
+                        }
+                        _ = ping.tick() => Some(WsMessage::Ping(Vec::new().into())),
+                    };
+                    if let Some(outgoing) = outgoing {
+                        if socket.send(outgoing).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                if let Ok(mut current) = status.lock() {
+                    if *current == ConnectionStatus::Connected {
+                        *current = ConnectionStatus::Disconnected;
+                    }
+                }
+                mapper.emit(ConnectionEvent::Status {
+                    event: StatusEvent::Disconnected {
+                        artifact: Some("Socket closed".to_string()),
+                    },
+                });
+            });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        self.tasks.shutdown().await;
+        self.mapper = None;
+        self.frames = None;
+        self.set_status(ConnectionStatus::Disconnected);
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        let ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } = event
+        else {
+            return Err(ConnectionError::Unsupported(
+                "Only new messages can be sent".to_string(),
+            ));
+        };
+        let (Some(mapper), Some(frames)) = (&self.mapper, &self.frames) else {
+            return Err(ConnectionError::Closed);
+        };
+        let mapping = &mapper.mapping;
+        let template = mapping.send.as_ref().ok_or_else(|| {
+            ConnectionError::Unsupported("The mapping has no send template".to_string())
+        })?;
+        let channel_id = scope
+            .channel_id()
+            .unwrap_or(&mapping.default_channel)
+            .to_string();
+        let token = field_text(&self.auth, "token").unwrap_or_default();
+        let text = plain_text(&message.content);
+        let frame = fill(
+            template,
+            &[
+                ("token", &token),
+                ("channel_id", &channel_id),
+                ("text", &text),
+            ],
+        );
+        frames.send(frame).map_err(|_| ConnectionError::Closed)
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx
+            .take()
+            .expect("subscribe can only be called once")
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        let field = |name: &str, display: &str, value, required| AuthField {
+            name: name.to_string(),
+            display: Some(display.to_string()),
+            value,
+            required,
+        };
+        Protocol {
+            name: "websocket".to_string(),
+            auth: Some(vec![
+                field("url", "WebSocket URL", FieldValue::Text(None), true),
+                field(
+                    "mapping",
+                    "Mapping spec (JSON)",
+                    FieldValue::Text(None),
+                    self.mapping.is_none(),
+                ),
+                field("token", "Token", FieldValue::Password(None), false),
+            ]),
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            multiple_channels: true,
+            ..Capabilities::default()
+        }
+    }
+
+    async fn preflight(&self, check_reachability: bool) -> PreflightReport {
+        let mut report = validate_auth(&self.protocol_spec(), &self.auth);
+        if field_text(&self.auth, "mapping").is_some() {
+            if let Err(e) = self.resolve_mapping() {
+                report.error(Some("mapping"), e.to_string());
+            }
+        }
+        let url = field_text(&self.auth, "url").and_then(|url| {
+            Url::parse(&url)
+                .map_err(|e| report.error(Some("url"), e.to_string()))
+                .ok()
+        });
+        if check_reachability {
+            if let Some((host, port)) = url
+                .as_ref()
+                .and_then(|url| Some((url.host_str()?, url.port_or_known_default()?)))
+            {
+                let timeout = self
+                    .options
+                    .connect_timeout
+                    .unwrap_or(Duration::from_secs(5));
+                report.reachability = probe_reachability(host, port, timeout).await;
+            }
+        }
+        report
+    }
+}
@@ -1,13 +1,17 @@
 use chrono::prelude::*;
 pub mod client;
 pub mod connection;
+#[cfg(feature = "schema")]
+pub mod schema;
 pub mod utils;
 pub use client::StateClient;
-pub use connection::Connection;
+pub use connection::{Connection, ConnectionError};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 pub use utils::assets;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Account {
     pub auth: Vec<AuthField>,
     pub protocol_name: String,
@@ -16,28 +20,54 @@ pub struct Account {
     pub autoconnect: bool,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Profile {
     pub id: Option<String>,
     pub username: Option<String>,
     pub display_name: Option<String>,
     pub color: Option<[u8; 4]>,
     pub picture: Option<String>,
+    #[serde(default)]
+    pub presence: Option<Presence>,
+    #[serde(default)]
+    pub role: Option<Role>,
+    #[serde(default)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
-impl Default for Profile {
-    fn default() -> Self {
-        Profile {
-            id: None,
-            username: None,
-            display_name: None,
-            color: None,
-            picture: None,
-        }
-    }
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Presence {
+    Online,
+    Away,
+    Dnd,
+    Offline,
+    Custom(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Role {
+    pub rank: i64,
+    pub permissions: Permissions,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub color: Option<[u8; 4]>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Permissions {
+    pub can_moderate: bool,
+    pub can_create_channels: bool,
+    pub can_view_logs: bool,
+    pub can_change_nickname: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Message {
     pub id: Option<String>,
     pub sender_id: Option<String>,
@@ -45,9 +75,32 @@ pub struct Message {
     pub timestamp: DateTime<Utc>,
     pub message_type: MessageType,
     pub status: MessageStatus,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    #[serde(default)]
+    pub thread_id: Option<String>,
+    #[serde(default)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ReactionKey {
+    Emoji(String),
+    AssetId(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Reaction {
+    pub key: ReactionKey,
+    pub user_ids: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum MessageStatus {
     Sent,
     Delivered,
@@ -57,24 +110,76 @@ pub enum MessageStatus {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum MessageType {
     CurrentUser,
     Normal,
     Server,
     Meta,
+    Action,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum MessageFragment {
     Text(String),
-    Image { url: String, mime: String },
-    Video { url: String, mime: String },
-    Audio { url: String, mime: String },
+    Image {
+        url: String,
+        mime: String,
+    },
+    Video {
+        url: String,
+        mime: String,
+    },
+    Audio {
+        url: String,
+        mime: String,
+    },
+    VoiceNote {
+        url: String,
+        mime: String,
+        duration_ms: Option<u64>,
+        waveform: Option<Vec<u8>>,
+        size: Option<u64>,
+    },
     Url(String),
     AssetId(String),
+    Mention {
+        user_id: String,
+        display: String,
+    },
+    Styled {
+        text: String,
+        styles: Vec<TextStyle>,
+    },
+    Spoiler(Vec<MessageFragment>),
+    Code {
+        lang: Option<String>,
+        body: String,
+    },
+}
+
+impl MessageFragment {
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            MessageFragment::Text(text) | MessageFragment::Styled { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum TextStyle {
+    Bold,
+    Italic,
+    Strike,
+    Underline,
+    Color([u8; 4]),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Asset {
     Emote {
         id: Option<String>,
@@ -103,6 +208,7 @@ pub enum Asset {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum AssetSource {
     User,
     Meta,
@@ -110,13 +216,19 @@ pub enum AssetSource {
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Channel {
     pub id: String,
     pub name: Option<String>,
     pub channel_type: ChannelType,
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum ChannelType {
     #[default]
     Group,
@@ -125,12 +237,48 @@ pub enum ChannelType {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Protocol {
     pub name: String,
     pub auth: Option<Vec<AuthField>>,
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Capabilities {
+    pub editing: bool,
+    pub deletion: bool,
+    pub history: bool,
+    pub typing: bool,
+    pub reactions: bool,
+    pub file_upload: bool,
+    pub multiple_channels: bool,
+    pub asset_management: bool,
+    #[serde(default)]
+    pub moderation: bool,
+    #[serde(default)]
+    pub max_message_length: Option<usize>,
+    #[serde(default)]
+    pub topics: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CommandArg {
+    pub name: String,
+    pub required: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CommandSpec {
+    pub name: String,
+    pub args: Vec<CommandArg>,
+    pub description: Option<String>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct AuthField {
     pub name: String,
     pub display: Option<String>,
@@ -139,8 +287,13 @@ pub struct AuthField {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum FieldValue {
     Text(Option<String>),
     Password(Option<String>),
     Group(Vec<AuthField>),
+    /// A private key, e.g. a hex or bech32 encoded secp256k1 secret.
+    Key(Option<String>),
+    /// A path to a local file, e.g. a PKCS#12 client certificate.
+    File(Option<String>),
 }
@@ -0,0 +1,185 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use schemars::schema_for;
+use serde_json::Value;
+
+use crate::{
+    client::{
+        ChannelSnapshot, Completion, ConnectionSummary, Digest, DigestPolicy, DirectRequest,
+        EscalationPolicy, MediaIgnoreList, QueryLimits, RetentionPolicy, SnapshotQuery,
+    },
+    connection::{ConnectionEvent, Envelope, PreflightReport, SendOutcome},
+    Account, Asset, Channel, ConnectionError, Message, Profile, Protocol,
+};
+
+/// Bumped whenever a checked-in schema changes in a way `breaking_changes` reports.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schemas for every public type that crosses a serialization boundary, keyed by type name.
+pub fn schemas() -> BTreeMap<&'static str, Value> {
+    let mut schemas = BTreeMap::new();
+    let mut add = |name, schema: schemars::Schema| {
+        schemas.insert(name, schema.to_value());
+    };
+    add("Account", schema_for!(Account));
+    add("Asset", schema_for!(Asset));
+    add("Channel", schema_for!(Channel));
+    add("ChannelSnapshot", schema_for!(ChannelSnapshot));
+    add("Completion", schema_for!(Completion));
+    add("ConnectionError", schema_for!(ConnectionError));
+    add("ConnectionEvent", schema_for!(ConnectionEvent));
+    add("ConnectionSummary", schema_for!(ConnectionSummary));
+    add("Digest", schema_for!(Digest));
+    add("DigestPolicy", schema_for!(DigestPolicy));
+    add("DirectRequest", schema_for!(DirectRequest));
+    add("Envelope", schema_for!(Envelope));
+    add("EscalationPolicy", schema_for!(EscalationPolicy));
+    add("MediaIgnoreList", schema_for!(MediaIgnoreList));
+    add("Message", schema_for!(Message));
+    add("PreflightReport", schema_for!(PreflightReport));
+    add("Profile", schema_for!(Profile));
+    add("Protocol", schema_for!(Protocol));
+    add("QueryLimits", schema_for!(QueryLimits));
+    add("RetentionPolicy", schema_for!(RetentionPolicy));
+    add("SendOutcome", schema_for!(SendOutcome));
+    add("SnapshotQuery", schema_for!(SnapshotQuery));
+    #[cfg(feature = "sync")]
+    add("SyncBundle", schema_for!(crate::client::SyncBundle));
+    #[cfg(feature = "sockchat")]
+    add(
+        "SockchatRoles",
+        schema_for!(crate::connection::SockchatRoles),
+    );
+    add(
+        "SockchatSession",
+        schema_for!(crate::connection::SockchatSession),
+    );
+    schemas
+}
+
+/// Writes each schema to `<dir>/<name>.json`.
+pub fn write_schemas(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (name, schema) in schemas() {
+        let json = serde_json::to_string_pretty(&schema).map_err(io::Error::other)?;
+        fs::write(dir.join(format!("{}.json", name)), json + "\n")?;
+    }
+    Ok(())
+}
+
+/// Lists changes from `old` to `new` that can break a consumer validating against or parsing
+/// with `old`: removed properties, newly required properties, removed enum values or variants
+/// and changed types. Additions that old consumers can ignore are not reported.
+pub fn breaking_changes(old: &Value, new: &Value) -> Vec<String> {
+    let mut changes = Vec::new();
+    compare(old, new, "#", &mut changes);
+    changes
+}
+
+fn compare(old: &Value, new: &Value, path: &str, changes: &mut Vec<String>) {
+    let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+        return;
+    };
+
+    if old.get("type") != new.get("type") && old.contains_key("type") {
+        changes.push(format!("{}: type changed", path));
+    }
+    if let Some(removed) = removed(old.get("enum"), new.get("enum")) {
+        changes.push(format!("{}: enum values removed: {}", path, removed));
+    }
+    if old.get("const").is_some() && old.get("const") != new.get("const") {
+        changes.push(format!("{}: constant changed", path));
+    }
+    for value in strings(new.get("required")) {
+        if !strings(old.get("required")).contains(&value) {
+            changes.push(format!("{}: {} became required", path, value));
+        }
+    }
+
+    for key in ["properties", "$defs"] {
+        let (Some(old_map), new_map) = (
+            old.get(key).and_then(Value::as_object),
+            new.get(key).and_then(Value::as_object),
+        ) else {
+            continue;
+        };
+        for (name, old_schema) in old_map {
+            let child = format!("{}/{}/{}", path, key, name);
+            match new_map.and_then(|map| map.get(name)) {
+                Some(new_schema) => compare(old_schema, new_schema, &child, changes),
+                None => changes.push(format!("{}: removed", child)),
+            }
+        }
+    }
+    for key in ["items", "additionalProperties"] {
+        if let (Some(old_schema), Some(new_schema)) = (old.get(key), new.get(key)) {
+            compare(
+                old_schema,
+                new_schema,
+                &format!("{}/{}", path, key),
+                changes,
+            );
+        }
+    }
+
+    for key in ["oneOf", "anyOf"] {
+        let Some(old_variants) = old.get(key).and_then(Value::as_array) else {
+            continue;
+        };
+        let new_variants = new.get(key).and_then(Value::as_array);
+        for old_variant in old_variants {
+            let signature = variant_signature(old_variant);
+            let child = format!("{}/{}/{}", path, key, signature);
+            match new_variants.and_then(|variants| {
+                variants
+                    .iter()
+                    .find(|variant| variant_signature(variant) == signature)
+            }) {
+                Some(new_variant) => compare(old_variant, new_variant, &child, changes),
+                None => changes.push(format!("{}: variant removed", child)),
+            }
+        }
+    }
+}
+
+fn variant_signature(variant: &Value) -> String {
+    let mut parts: Vec<String> = strings(variant.get("required"));
+    parts.extend(strings(variant.get("enum")));
+    if let Some(value) = variant.get("const") {
+        parts.push(value.to_string());
+    }
+    if let Some(reference) = variant.get("$ref").and_then(Value::as_str) {
+        parts.push(reference.to_string());
+    }
+    if parts.is_empty() {
+        if let Some(kind) = variant.get("type") {
+            parts.push(kind.to_string());
+        }
+    }
+    parts.sort();
+    parts.join("+")
+}
+
+fn removed(old: Option<&Value>, new: Option<&Value>) -> Option<String> {
+    let new = strings(new);
+    let removed: Vec<String> = strings(old)
+        .into_iter()
+        .filter(|value| !new.contains(value))
+        .collect();
+    (!removed.is_empty()).then(|| removed.join(", "))
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(Value::as_array)
+        .map(|values| {
+            values
+                .iter()
+                .map(|value| match value {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -55,7 +55,59 @@ fn get_pattern(asset: &Asset) -> String {
     }
 }
 
-fn get_id(asset: &Asset) -> Option<String> {
+/// Spells out every literal string an asset pattern accepts, expanding `(?:a|b)` groups and
+/// dropping anchors and word boundaries. Patterns using any other regex feature yield nothing.
+pub(crate) fn pattern_literals(pattern: &str) -> Vec<String> {
+    let mut chars = pattern.chars();
+    let mut literals = alternatives(&mut chars, false).unwrap_or_default();
+    literals.retain(|literal| !literal.is_empty());
+    literals.sort();
+    literals.dedup();
+    literals
+}
+
+fn alternatives(chars: &mut std::str::Chars, in_group: bool) -> Option<Vec<String>> {
+    let mut branches = Vec::new();
+    let mut current = vec![String::new()];
+    loop {
+        match chars.next() {
+            None if in_group => return None,
+            None => break,
+            Some(')') if in_group => break,
+            Some(')') => return None,
+            Some('\\') => match chars.next()? {
+                'b' | 'B' => {}
+                c if c.is_ascii_alphanumeric() => return None,
+                c => current.iter_mut().for_each(|literal| literal.push(c)),
+            },
+            Some('(') => {
+                let mut lookahead = chars.clone();
+                if lookahead.next() == Some('?') {
+                    if lookahead.next() != Some(':') {
+                        return None;
+                    }
+                    *chars = lookahead;
+                }
+                let inner = alternatives(chars, true)?;
+                current = current
+                    .iter()
+                    .flat_map(|head| inner.iter().map(move |tail| format!("{}{}", head, tail)))
+                    .collect();
+            }
+            Some('|') => {
+                branches.append(&mut current);
+                current.push(String::new());
+            }
+            Some('^') | Some('$') => {}
+            Some('.' | '*' | '+' | '?' | '[' | ']' | '{' | '}') => return None,
+            Some(c) => current.iter_mut().for_each(|literal| literal.push(c)),
+        }
+    }
+    branches.append(&mut current);
+    Some(branches)
+}
+
+pub fn get_id(asset: &Asset) -> Option<String> {
     match asset {
         Asset::Emote { id, .. } => id.clone(),
         Asset::Sticker { id, .. } => id.clone(),
@@ -1,26 +1,36 @@
-use hhkodo::{parse_frags, Frag};
+use hhkodo::{parse_frags, Frag, Param};
 
-use crate::MessageFragment;
+use crate::{MessageFragment, TextStyle};
+
+use super::color::parse_css_color;
 
 pub fn parse_bbcode(input: &str) -> Vec<MessageFragment> {
     let frags = parse_frags(input);
-    frags_to_message(&frags)
+    frags_to_message(&frags, &[])
 }
 
-fn frags_to_message(frags: &[Frag]) -> Vec<MessageFragment> {
+fn frags_to_message(frags: &[Frag], styles: &[TextStyle]) -> Vec<MessageFragment> {
     let mut out = Vec::new();
     for frag in frags {
         match frag {
             Frag::Raw(text) => {
-                if !text.is_empty() {
+                if text.is_empty() {
+                    continue;
+                }
+                if styles.is_empty() {
                     out.push(MessageFragment::Text(text.clone()));
+                } else {
+                    out.push(MessageFragment::Styled {
+                        text: text.clone(),
+                        styles: styles.to_vec(),
+                    });
                 }
             }
             Frag::Tag {
                 name,
                 val,
+                params,
                 subfrags,
-                ..
             } => {
                 let tag = name.to_lowercase();
                 match tag.as_str() {
@@ -32,7 +42,7 @@ fn frags_to_message(frags: &[Frag]) -> Vec<MessageFragment> {
                             let mime = mime_from_extension(&url);
                             out.push(MessageFragment::Image { url, mime });
                         } else {
-                            out.extend(frags_to_message(subfrags));
+                            out.extend(frags_to_message(subfrags, styles));
                         }
                     }
                     "video" => {
@@ -43,7 +53,7 @@ fn frags_to_message(frags: &[Frag]) -> Vec<MessageFragment> {
                             let mime = mime_from_extension(&url);
                             out.push(MessageFragment::Video { url, mime });
                         } else {
-                            out.extend(frags_to_message(subfrags));
+                            out.extend(frags_to_message(subfrags, styles));
                         }
                     }
                     "audio" => {
@@ -54,7 +64,25 @@ fn frags_to_message(frags: &[Frag]) -> Vec<MessageFragment> {
                             let mime = mime_from_extension(&url);
                             out.push(MessageFragment::Audio { url, mime });
                         } else {
-                            out.extend(frags_to_message(subfrags));
+                            out.extend(frags_to_message(subfrags, styles));
+                        }
+                    }
+                    "voice" => {
+                        if let Some(mut url) = extract_raw(subfrags) {
+                            if url.starts_with("//") {
+                                url = format!("https:{}", &url);
+                            }
+                            let mime = mime_from_extension(&url);
+                            let duration = param(params, "duration").or(val.as_deref());
+                            out.push(MessageFragment::VoiceNote {
+                                url,
+                                mime,
+                                duration_ms: duration.and_then(parse_duration_ms),
+                                waveform: None,
+                                size: param(params, "size").and_then(|s| s.parse().ok()),
+                            });
+                        } else {
+                            out.extend(frags_to_message(subfrags, styles));
                         }
                     }
                     "url" => {
@@ -65,13 +93,151 @@ fn frags_to_message(frags: &[Frag]) -> Vec<MessageFragment> {
                             }
                             out.push(MessageFragment::Url(href));
                         } else {
-                            out.extend(frags_to_message(subfrags));
+                            out.extend(frags_to_message(subfrags, styles));
                         }
                     }
+                    "spoiler" => {
+                        out.push(MessageFragment::Spoiler(frags_to_message(subfrags, styles)));
+                    }
+                    "code" => {
+                        out.push(MessageFragment::Code {
+                            lang: val.clone().filter(|lang| !lang.is_empty()),
+                            body: source_text(subfrags),
+                        });
+                    }
+                    "b" | "i" | "s" | "u" | "color" => {
+                        let style = match tag.as_str() {
+                            "b" => Some(TextStyle::Bold),
+                            "i" => Some(TextStyle::Italic),
+                            "s" => Some(TextStyle::Strike),
+                            "u" => Some(TextStyle::Underline),
+                            _ => val
+                                .as_deref()
+                                .and_then(parse_css_color)
+                                .map(TextStyle::Color),
+                        };
+                        let mut nested = styles.to_vec();
+                        if let Some(style) = style {
+                            if let TextStyle::Color(_) = style {
+                                nested.retain(|s| !matches!(s, TextStyle::Color(_)));
+                            }
+                            if !nested.contains(&style) {
+                                nested.push(style);
+                            }
+                        }
+                        out.extend(frags_to_message(subfrags, &nested));
+                    }
                     _ => {
-                        out.extend(frags_to_message(subfrags));
+                        out.extend(frags_to_message(subfrags, styles));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+pub fn render_bbcode(content: &[MessageFragment]) -> String {
+    let mut out = String::new();
+    for fragment in content {
+        match fragment {
+            MessageFragment::Styled { text, styles } => {
+                let tags: Vec<(String, &str)> = styles
+                    .iter()
+                    .map(|style| match style {
+                        TextStyle::Bold => ("b".to_string(), "b"),
+                        TextStyle::Italic => ("i".to_string(), "i"),
+                        TextStyle::Strike => ("s".to_string(), "s"),
+                        TextStyle::Underline => ("u".to_string(), "u"),
+                        TextStyle::Color([r, g, b, _]) => {
+                            (format!("color=#{:02x}{:02x}{:02x}", r, g, b), "color")
+                        }
+                    })
+                    .collect();
+                for (open, _) in &tags {
+                    out.push_str(&format!("[{}]", open));
+                }
+                out.push_str(text);
+                for (_, close) in tags.iter().rev() {
+                    out.push_str(&format!("[/{}]", close));
+                }
+            }
+            MessageFragment::Spoiler(inner) => {
+                out.push_str(&format!("[spoiler]{}[/spoiler]", render_bbcode(inner)))
+            }
+            MessageFragment::Code { lang, body } => match lang {
+                Some(lang) => out.push_str(&format!("[code={}]{}[/code]", lang, body)),
+                None => out.push_str(&format!("[code]{}[/code]", body)),
+            },
+            MessageFragment::Image { url, .. } => out.push_str(&format!("[img]{}[/img]", url)),
+            MessageFragment::Video { url, .. } => out.push_str(&format!("[video]{}[/video]", url)),
+            MessageFragment::Audio { url, .. } => out.push_str(&format!("[audio]{}[/audio]", url)),
+            MessageFragment::VoiceNote {
+                url,
+                duration_ms,
+                size,
+                ..
+            } => {
+                out.push_str("[voice");
+                if let Some(ms) = duration_ms {
+                    out.push_str(&format!(" duration={}", *ms as f64 / 1000.0));
+                }
+                if let Some(size) = size {
+                    out.push_str(&format!(" size={}", size));
+                }
+                out.push_str(&format!("]{}[/voice]", url));
+            }
+            other => out.push_str(&super::compose::plain_text(std::slice::from_ref(other))),
+        }
+    }
+    out
+}
+
+fn param<'a>(params: &'a [Param], key: &str) -> Option<&'a str> {
+    params.iter().find_map(|param| match param {
+        Param::Pair { key: k, val } if k.eq_ignore_ascii_case(key) => Some(val.as_str()),
+        _ => None,
+    })
+}
+
+pub fn parse_duration_ms(value: &str) -> Option<u64> {
+    let mut seconds = 0.0;
+    for part in value.trim().split(':') {
+        let part: f64 = part.parse().ok()?;
+        if !part.is_finite() || part < 0.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + part;
+    }
+    Some((seconds * 1000.0).round() as u64)
+}
+
+fn source_text(frags: &[Frag]) -> String {
+    let mut out = String::new();
+    for frag in frags {
+        match frag {
+            Frag::Raw(text) => out.push_str(text),
+            Frag::Tag {
+                name,
+                val,
+                params,
+                subfrags,
+            } => {
+                out.push('[');
+                out.push_str(name);
+                if let Some(val) = val {
+                    out.push('=');
+                    out.push_str(val);
+                }
+                for param in params {
+                    match param {
+                        Param::Free(value) => out.push_str(&format!(" {}", value)),
+                        Param::Pair { key, val } => out.push_str(&format!(" {}={}", key, val)),
                     }
                 }
+                out.push(']');
+                out.push_str(&source_text(subfrags));
+                out.push_str(&format!("[/{}]", name));
             }
         }
     }
@@ -88,7 +254,7 @@ fn extract_raw(subfrags: &[Frag]) -> Option<String> {
 }
 
 fn mime_from_extension(url: &str) -> String {
-    if let Some(ext) = url.split('.').last().map(|s| s.to_lowercase()) {
+    if let Some(ext) = url.split('.').next_back().map(|s| s.to_lowercase()) {
         match ext.as_str() {
             // images
             "png" => "image/png".into(),
@@ -104,6 +270,8 @@ fn mime_from_extension(url: &str) -> String {
             "wav" => "audio/wav".into(),
             "flac" => "audio/flac".into(),
             "oga" | "ogg" => "audio/ogg".into(),
+            "opus" => "audio/opus".into(),
+            "m4a" => "audio/mp4".into(),
             _ => default_mime(url),
         }
     } else {
@@ -1,9 +1,45 @@
 use kanii_lib::packets::types::Color;
 
 pub fn kanii_to_rgba(color: Color) -> Option<[u8; 4]> {
-    if let Ok(rgba) = color.as_rgba() {
-        Some(rgba)
-    } else {
-        None
+    color.as_rgba().ok()
+}
+
+pub fn parse_css_color(value: &str) -> Option<[u8; 4]> {
+    let value = value.trim().to_lowercase();
+    if let Some(hex) = value.strip_prefix('#') {
+        let digits: Vec<u8> = hex
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()?;
+        return match digits.as_slice() {
+            [r, g, b] => Some([r * 17, g * 17, b * 17, 255]),
+            [r1, r2, g1, g2, b1, b2] => Some([r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2, 255]),
+            [r1, r2, g1, g2, b1, b2, a1, a2] => {
+                Some([r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2, a1 * 16 + a2])
+            }
+            _ => None,
+        };
     }
+    let rgb = match value.as_str() {
+        "black" => [0, 0, 0],
+        "white" => [255, 255, 255],
+        "red" => [255, 0, 0],
+        "green" => [0, 128, 0],
+        "lime" => [0, 255, 0],
+        "blue" => [0, 0, 255],
+        "yellow" => [255, 255, 0],
+        "cyan" | "aqua" => [0, 255, 255],
+        "magenta" | "fuchsia" => [255, 0, 255],
+        "orange" => [255, 165, 0],
+        "purple" => [128, 0, 128],
+        "pink" => [255, 192, 203],
+        "gray" | "grey" => [128, 128, 128],
+        "silver" => [192, 192, 192],
+        "maroon" => [128, 0, 0],
+        "navy" => [0, 0, 128],
+        "teal" => [0, 128, 128],
+        "olive" => [128, 128, 0],
+        _ => return None,
+    };
+    Some([rgb[0], rgb[1], rgb[2], 255])
 }
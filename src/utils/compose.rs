@@ -0,0 +1,67 @@
+use crate::{Connection, MessageFragment};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompositionStats {
+    pub length: usize,
+    pub limit: Option<usize>,
+    pub remaining: Option<i64>,
+    pub parts: usize,
+}
+
+pub fn plain_text(content: &[MessageFragment]) -> String {
+    content
+        .iter()
+        .map(|fragment| match fragment {
+            MessageFragment::Text(text) | MessageFragment::Styled { text, .. } => text.clone(),
+            MessageFragment::Image { url, .. }
+            | MessageFragment::Video { url, .. }
+            | MessageFragment::Audio { url, .. }
+            | MessageFragment::VoiceNote { url, .. }
+            | MessageFragment::Url(url) => url.clone(),
+            MessageFragment::AssetId(id) => id.clone(),
+            MessageFragment::Mention { display, .. } => format!("@{}", display),
+            MessageFragment::Spoiler(inner) => plain_text(inner),
+            MessageFragment::Code { body, .. } => body.clone(),
+        })
+        .collect()
+}
+
+/// Replaces media fragments with text placeholders that keep the URL, for clients that should
+/// not fetch or render media.
+pub fn strip_media(content: &[MessageFragment]) -> Vec<MessageFragment> {
+    content
+        .iter()
+        .map(|fragment| match fragment {
+            MessageFragment::Image { url, .. } => placeholder("image", url),
+            MessageFragment::Video { url, .. } => placeholder("video", url),
+            MessageFragment::Audio { url, .. } => placeholder("audio", url),
+            MessageFragment::VoiceNote { url, .. } => placeholder("voice note", url),
+            MessageFragment::Spoiler(inner) => MessageFragment::Spoiler(strip_media(inner)),
+            other => other.clone(),
+        })
+        .collect()
+}
+
+fn placeholder(kind: &str, url: &str) -> MessageFragment {
+    MessageFragment::Text(format!("[{}: {}]", kind, url))
+}
+
+pub fn measure<C: Connection + ?Sized>(
+    connection: &C,
+    content: &[MessageFragment],
+) -> CompositionStats {
+    let length = connection.render_text(content).chars().count();
+    let limit = connection
+        .capabilities()
+        .max_message_length
+        .filter(|limit| *limit > 0);
+    CompositionStats {
+        length,
+        limit,
+        remaining: limit.map(|limit| limit as i64 - length as i64),
+        parts: match limit {
+            Some(limit) => length.div_ceil(limit),
+            None => usize::from(length > 0),
+        },
+    }
+}
@@ -1,5 +1,21 @@
+use std::sync::LazyLock;
+
 use regex::Regex;
 
+use crate::{MessageFragment, TextStyle};
+
+/// An opening or closing tag, with its raw attribute text.
+static TAG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"<(/?)([a-zA-Z][a-zA-Z0-9]*)((?:[^>"']|"[^"]*"|'[^']*')*)>"#).unwrap()
+});
+/// A quoted attribute inside a tag's attribute text.
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?:^|\s)([a-zA-Z_:][-a-zA-Z0-9_:.]*)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap()
+});
+static WHITESPACE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+").unwrap());
+static ENTITY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"&(#[xX][0-9a-fA-F]+|#[0-9]+|[a-zA-Z]+);").unwrap());
+
 pub fn parse_html(s: String) -> String {
     let re = Regex::new(r"&lt;|&gt;|\s<br/>\s").unwrap();
 
@@ -10,3 +26,209 @@ pub fn parse_html(s: String) -> String {
     })
     .to_string()
 }
+
+/// Converts an HTML fragment, such as a Mastodon status body, into message fragments.
+///
+/// Links become `Url` fragments, except links with a `mention` class, which become `Mention`s
+/// keyed by their `href`, and hashtag links, which stay text. `<pre>` and `<code>` become
+/// `Code` fragments and whitespace outside them collapses as a browser would. `<img>` becomes
+/// an `Image`, with the type of a `data:` URI or `image/*`.
+pub fn html_fragments(html: &str) -> Vec<MessageFragment> {
+    let mut parser = HtmlParser::default();
+    let mut last = 0;
+    for caps in TAG.captures_iter(html) {
+        let whole = caps.get(0).unwrap();
+        parser.text(&html[last..whole.start()]);
+        last = whole.end();
+        let name = caps[2].to_ascii_lowercase();
+        if caps[1].is_empty() {
+            parser.open(&name, &caps[3]);
+        } else {
+            parser.close(&name);
+        }
+    }
+    parser.text(&html[last..]);
+    parser.finish()
+}
+
+#[derive(Default)]
+struct HtmlParser {
+    out: Vec<MessageFragment>,
+    text: String,
+    styles: Vec<(String, TextStyle)>,
+    link: Option<(String, String, String)>,
+    code: Option<(Option<String>, String, bool)>,
+}
+
+impl HtmlParser {
+    fn text(&mut self, raw: &str) {
+        if let Some((_, body, _)) = &mut self.code {
+            body.push_str(&decode_entities(raw));
+            return;
+        }
+        let collapsed = WHITESPACE.replace_all(raw, " ");
+        let text = decode_entities(&collapsed);
+        match &mut self.link {
+            Some((_, _, label)) => label.push_str(&text),
+            None => self.text.push_str(&text),
+        }
+    }
+
+    fn open(&mut self, name: &str, attrs: &str) {
+        match name {
+            "br" => match (&mut self.code, &mut self.link) {
+                (Some((_, body, _)), _) => body.push('\n'),
+                (None, Some((_, _, label))) => label.push('\n'),
+                (None, None) => self.text.push('\n'),
+            },
+            "p" if !self.out.is_empty() || !self.text.is_empty() => self.text.push_str("\n\n"),
+            "pre" => {
+                self.flush();
+                self.code = Some((None, String::new(), true));
+            }
+            "code" => {
+                let lang = attribute(attrs, "class")
+                    .and_then(|class| class.strip_prefix("language-").map(str::to_string));
+                match &mut self.code {
+                    Some((block_lang, _, true)) => *block_lang = lang,
+                    Some(_) => {}
+                    None => {
+                        self.flush();
+                        self.code = Some((lang, String::new(), false));
+                    }
+                }
+            }
+            "img" => {
+                let Some(src) = attribute(attrs, "src") else {
+                    return;
+                };
+                let mime = src
+                    .strip_prefix("data:")
+                    .and_then(|data| data.split([';', ',']).next())
+                    .unwrap_or("image/*")
+                    .to_string();
+                self.flush();
+                self.out.push(MessageFragment::Image { url: src, mime });
+            }
+            "a" => {
+                self.link = Some((
+                    attribute(attrs, "href").unwrap_or_default(),
+                    attribute(attrs, "class").unwrap_or_default(),
+                    String::new(),
+                ));
+            }
+            _ => {
+                if let Some(style) = style_for(name) {
+                    self.flush();
+                    self.styles.push((name.to_string(), style));
+                }
+            }
+        }
+    }
+
+    fn close(&mut self, name: &str) {
+        match name {
+            "pre" | "code" => {
+                let block = name == "pre";
+                if let Some((lang, body, _)) = self.code.take_if(|(_, _, b)| *b == block) {
+                    self.out.push(MessageFragment::Code {
+                        lang,
+                        body: body.trim_end_matches('\n').to_string(),
+                    });
+                }
+            }
+            "a" => {
+                let Some((href, class, label)) = self.link.take() else {
+                    return;
+                };
+                let classes: Vec<&str> = class.split_whitespace().collect();
+                if href.is_empty() || classes.contains(&"hashtag") {
+                    self.text.push_str(&label);
+                } else if classes.contains(&"mention") {
+                    self.flush();
+                    self.out.push(MessageFragment::Mention {
+                        user_id: href,
+                        display: label.trim_start_matches('@').to_string(),
+                    });
+                } else {
+                    self.flush();
+                    self.out.push(MessageFragment::Url(href));
+                }
+            }
+            _ => {
+                if style_for(name).is_some() {
+                    self.flush();
+                    if let Some(i) = self.styles.iter().rposition(|(tag, _)| tag == name) {
+                        self.styles.remove(i);
+                    }
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.text.is_empty() {
+            return;
+        }
+        let text = std::mem::take(&mut self.text);
+        if self.styles.is_empty() {
+            self.out.push(MessageFragment::Text(text));
+        } else {
+            self.out.push(MessageFragment::Styled {
+                text,
+                styles: self.styles.iter().map(|(_, style)| style.clone()).collect(),
+            });
+        }
+    }
+
+    fn finish(mut self) -> Vec<MessageFragment> {
+        if let Some((lang, body, _)) = self.code.take() {
+            self.text.clear();
+            self.out.push(MessageFragment::Code { lang, body });
+        }
+        self.flush();
+        self.out
+    }
+}
+
+fn style_for(tag: &str) -> Option<TextStyle> {
+    match tag {
+        "b" | "strong" => Some(TextStyle::Bold),
+        "i" | "em" => Some(TextStyle::Italic),
+        "s" | "del" | "strike" => Some(TextStyle::Strike),
+        "u" | "ins" => Some(TextStyle::Underline),
+        _ => None,
+    }
+}
+
+fn attribute(attrs: &str, name: &str) -> Option<String> {
+    let caps = ATTRIBUTE
+        .captures_iter(attrs)
+        .find(|caps| caps[1].eq_ignore_ascii_case(name))?;
+    let value = caps.get(2).or(caps.get(3))?.as_str();
+    Some(decode_entities(value))
+}
+
+fn decode_entities(text: &str) -> String {
+    ENTITY
+        .replace_all(text, |caps: &regex::Captures| {
+            let name = &caps[1];
+            let decoded = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => name
+                    .strip_prefix("#x")
+                    .or_else(|| name.strip_prefix("#X"))
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| name.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            decoded.map_or_else(|| caps[0].to_string(), String::from)
+        })
+        .into_owned()
+}
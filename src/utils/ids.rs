@@ -0,0 +1,268 @@
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use uuid::Uuid;
+
+use crate::connection::{
+    AssetEvent, ChannelEvent, ChatEvent, ConnectionEvent, ModerationEvent, Scope, UserEvent,
+};
+
+pub trait IdGenerator: Debug + Send + Sync {
+    fn next_id(&self) -> String;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UuidGenerator;
+
+impl IdGenerator for UuidGenerator {
+    fn next_id(&self) -> String {
+        Uuid::new_v4().to_string()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SequenceGenerator {
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl SequenceGenerator {
+    pub fn new(prefix: &str) -> Self {
+        SequenceGenerator {
+            prefix: prefix.to_string(),
+            next: AtomicU64::new(1),
+        }
+    }
+}
+
+impl IdGenerator for SequenceGenerator {
+    fn next_id(&self) -> String {
+        format!(
+            "{}{}",
+            self.prefix,
+            self.next.fetch_add(1, Ordering::SeqCst)
+        )
+    }
+}
+
+const SNOWFLAKE_EPOCH_MS: u64 = 1_704_067_200_000;
+const SNOWFLAKE_NODE_BITS: u32 = 10;
+const SNOWFLAKE_SEQUENCE_BITS: u32 = 12;
+
+#[derive(Debug)]
+pub struct SnowflakeGenerator {
+    node_id: u64,
+    last: Mutex<(u64, u64)>,
+}
+
+impl SnowflakeGenerator {
+    pub fn new(node_id: u16) -> Self {
+        SnowflakeGenerator {
+            node_id: u64::from(node_id) & ((1 << SNOWFLAKE_NODE_BITS) - 1),
+            last: Mutex::new((0, 0)),
+        }
+    }
+}
+
+impl IdGenerator for SnowflakeGenerator {
+    fn next_id(&self) -> String {
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default()
+            .saturating_sub(SNOWFLAKE_EPOCH_MS);
+
+        let (mut millis, mut sequence) = *last;
+        if now > millis {
+            millis = now;
+            sequence = 0;
+        } else {
+            sequence = (sequence + 1) & ((1 << SNOWFLAKE_SEQUENCE_BITS) - 1);
+            if sequence == 0 {
+                millis += 1;
+            }
+        }
+        *last = (millis, sequence);
+
+        let id = (millis << (SNOWFLAKE_NODE_BITS + SNOWFLAKE_SEQUENCE_BITS))
+            | (self.node_id << SNOWFLAKE_SEQUENCE_BITS)
+            | sequence;
+        id.to_string()
+    }
+}
+
+/// Canonical forms of a protocol's channel and user ids, so ids that differ only in ways the
+/// protocol ignores (such as case on IRC) name the same channel or user. Both methods default
+/// to leaving ids untouched.
+pub trait IdNormalizer: Debug + Send + Sync {
+    fn normalize_channel_id(&self, channel_id: &str) -> String {
+        channel_id.to_string()
+    }
+
+    fn normalize_user_id(&self, user_id: &str) -> String {
+        user_id.to_string()
+    }
+}
+
+/// Compares ids byte for byte.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VerbatimIds;
+
+impl IdNormalizer for VerbatimIds {}
+
+/// Rewrites every channel and user id carried by `event` to its canonical form.
+pub fn normalize_event(event: &mut ConnectionEvent, ids: &dyn IdNormalizer) {
+    let channel = |id: &mut String| *id = ids.normalize_channel_id(id);
+    let user = |id: &mut String| *id = ids.normalize_user_id(id);
+    let scope = |scope: &mut Scope| {
+        if let Scope::Channel(id) = scope {
+            channel(id);
+        }
+    };
+    match event {
+        ConnectionEvent::Chat { event } => match event {
+            ChatEvent::New { scope: s, message } => {
+                scope(s);
+                message.sender_id.iter_mut().for_each(user);
+                for reaction in &mut message.reactions {
+                    reaction.user_ids.iter_mut().for_each(user);
+                }
+            }
+            ChatEvent::Update {
+                scope: s,
+                new_message,
+                ..
+            } => {
+                scope(s);
+                new_message.sender_id.iter_mut().for_each(user);
+            }
+            ChatEvent::Remove { scope: s, .. } => scope(s),
+            ChatEvent::ReactionAdd {
+                scope: s, user_id, ..
+            }
+            | ChatEvent::ReactionRemove {
+                scope: s, user_id, ..
+            } => {
+                scope(s);
+                user(user_id);
+            }
+            ChatEvent::DeliveryAck { .. } => {}
+            ChatEvent::ReadMarker {
+                channel_id,
+                user_id,
+                ..
+            } => {
+                channel(channel_id);
+                user(user_id);
+            }
+        },
+        ConnectionEvent::User { event } => normalize_user_event(event, ids),
+        ConnectionEvent::Channel { event } => match event {
+            ChannelEvent::New { channel: c } => channel(&mut c.id),
+            ChannelEvent::Update {
+                channel_id,
+                new_channel,
+            } => {
+                channel(channel_id);
+                channel(&mut new_channel.id);
+            }
+            ChannelEvent::Remove { channel_id }
+            | ChannelEvent::Join { channel_id }
+            | ChannelEvent::Leave { channel_id }
+            | ChannelEvent::Switch { channel_id }
+            | ChannelEvent::Accept { channel_id }
+            | ChannelEvent::Decline { channel_id } => channel(channel_id),
+            ChannelEvent::Kick { scope: s, .. } | ChannelEvent::Wipe { scope: s } => scope(s),
+            ChannelEvent::TopicChanged {
+                channel_id, set_by, ..
+            } => {
+                channel(channel_id);
+                set_by.iter_mut().for_each(user);
+            }
+            ChannelEvent::Request { channel: c, from } => {
+                channel(&mut c.id);
+                from.id.iter_mut().for_each(user);
+            }
+            ChannelEvent::ClearList => {}
+        },
+        ConnectionEvent::Status { .. } => {}
+        ConnectionEvent::Asset { event } => match event {
+            AssetEvent::New { scope: s, .. }
+            | AssetEvent::Update { scope: s, .. }
+            | AssetEvent::Remove { scope: s, .. }
+            | AssetEvent::ClearList { scope: s } => scope(s),
+        },
+        ConnectionEvent::Moderation { event } => match event {
+            ModerationEvent::Kick {
+                scope: s, user_id, ..
+            }
+            | ModerationEvent::Ban {
+                scope: s, user_id, ..
+            }
+            | ModerationEvent::Unban { scope: s, user_id }
+            | ModerationEvent::Mute {
+                scope: s, user_id, ..
+            }
+            | ModerationEvent::Unmute { scope: s, user_id } => {
+                scope(s);
+                user(user_id);
+            }
+        },
+    }
+}
+
+fn normalize_user_event(event: &mut UserEvent, ids: &dyn IdNormalizer) {
+    let channel = |id: &mut String| *id = ids.normalize_channel_id(id);
+    let user = |id: &mut String| *id = ids.normalize_user_id(id);
+    let scope = |scope: &mut Scope| {
+        if let Scope::Channel(id) = scope {
+            channel(id);
+        }
+    };
+    match event {
+        UserEvent::New { scope: s, user: u } => {
+            scope(s);
+            u.id.iter_mut().for_each(user);
+        }
+        UserEvent::Update {
+            scope: s,
+            user_id,
+            new_user,
+        } => {
+            scope(s);
+            user(user_id);
+            new_user.id.iter_mut().for_each(user);
+        }
+        UserEvent::Remove { scope: s, user_id } => {
+            scope(s);
+            user(user_id);
+        }
+        UserEvent::ClearList { scope: s } => scope(s),
+        UserEvent::Identify { user_id } | UserEvent::PresenceChanged { user_id, .. } => {
+            user(user_id)
+        }
+        UserEvent::TypingStart {
+            channel_id,
+            user_id,
+        }
+        | UserEvent::TypingStop {
+            channel_id,
+            user_id,
+        } => {
+            channel(channel_id);
+            user(user_id);
+        }
+        UserEvent::Batch { events } => {
+            for event in events {
+                normalize_user_event(event, ids);
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use chrono::{DateTime, Duration, Utc};
+use regex::Regex;
+
+/// Matches common shutdown and maintenance broadcasts, e.g. "Server restarting in 5 minutes for
+/// 30 minutes" or "Maintenance until 2024-05-06T12:00:00Z".
+pub const DEFAULT_MAINTENANCE_PATTERN: &str = r"(?i)\b(?:maintenance|shutting down|shutdown|restart(?:ing)?)\b(?:.*?\bin\s+(?P<at>\d+\s*[a-z]+))?(?:.*?\bfor\s+(?P<for>\d+\s*[a-z]+))?(?:.*?\buntil\s+(?P<until>\S+))?";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaintenanceNotice {
+    pub scheduled_at: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Parses a maintenance announcement with `pattern`. The optional `at` and `until` groups take
+/// an RFC 3339 time, a Unix timestamp or a duration from `now` such as "5 minutes"; `for` is a
+/// duration after the start, giving `until` when that group is absent.
+pub fn parse_maintenance_notice(
+    pattern: &Regex,
+    text: &str,
+    now: DateTime<Utc>,
+) -> Option<MaintenanceNotice> {
+    let captures = pattern.captures(text.trim())?;
+    let time = |name: &str| {
+        captures
+            .name(name)
+            .and_then(|m| parse_time(m.as_str(), now))
+    };
+    let scheduled_at = time("at");
+    let until = time("until").or_else(|| {
+        let length = parse_duration(captures.name("for")?.as_str())?;
+        Some(scheduled_at.unwrap_or(now) + length)
+    });
+    Some(MaintenanceNotice {
+        scheduled_at,
+        until,
+    })
+}
+
+fn parse_time(value: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let value = value.trim().trim_end_matches(['.', ',', '!']);
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return DateTime::from_timestamp(timestamp, 0);
+    }
+    parse_duration(value).map(|duration| now + duration)
+}
+
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit())?;
+    let amount: i64 = value[..split].parse().ok()?;
+    let unit = value[split..].trim().trim_end_matches(['.', ',', '!']);
+    match unit.to_ascii_lowercase().as_str() {
+        "s" | "sec" | "secs" | "second" | "seconds" => Some(Duration::seconds(amount)),
+        "m" | "min" | "mins" | "minute" | "minutes" => Some(Duration::minutes(amount)),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Some(Duration::hours(amount)),
+        "d" | "day" | "days" => Some(Duration::days(amount)),
+        _ => None,
+    }
+}
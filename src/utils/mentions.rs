@@ -0,0 +1,75 @@
+use crate::{MessageFragment, Profile};
+
+pub fn parse_mentions(text: &str, users: &[Profile]) -> Vec<MessageFragment> {
+    let mut names: Vec<(String, &Profile)> = users
+        .iter()
+        .filter(|user| user.id.is_some())
+        .flat_map(|user| {
+            [&user.username, &user.display_name]
+                .into_iter()
+                .flatten()
+                .filter(|name| !name.is_empty())
+                .map(move |name| (name.to_lowercase(), user))
+        })
+        .collect();
+    names.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+
+    let mut frags = Vec::new();
+    let mut current_text = String::new();
+    let mut rest = text;
+
+    while let Some(at) = rest.find('@') {
+        let before = &rest[..at];
+        let after = &rest[at + 1..];
+        let boundary = before
+            .chars()
+            .next_back()
+            .or_else(|| current_text.chars().next_back())
+            .is_none_or(|c| !is_word(c));
+        let found = boundary
+            .then(|| {
+                names.iter().find(|(name, _)| {
+                    after
+                        .get(..name.len())
+                        .is_some_and(|head| head.to_lowercase() == *name)
+                        && after[name.len()..]
+                            .chars()
+                            .next()
+                            .is_none_or(|c| !is_word(c))
+                })
+            })
+            .flatten();
+
+        current_text.push_str(before);
+        match found {
+            Some((name, user)) => {
+                if !current_text.is_empty() {
+                    frags.push(MessageFragment::Text(std::mem::take(&mut current_text)));
+                }
+                frags.push(MessageFragment::Mention {
+                    user_id: user.id.clone().unwrap_or_default(),
+                    display: user
+                        .display_name
+                        .clone()
+                        .or(user.username.clone())
+                        .unwrap_or_default(),
+                });
+                rest = &after[name.len()..];
+            }
+            None => {
+                current_text.push('@');
+                rest = after;
+            }
+        }
+    }
+
+    current_text.push_str(rest);
+    if !current_text.is_empty() {
+        frags.push(MessageFragment::Text(current_text));
+    }
+    frags
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
@@ -1,4 +1,12 @@
 pub mod assets;
 pub mod bbcode;
 pub mod color;
+pub mod compose;
 pub mod html;
+pub mod ids;
+pub mod maintenance;
+pub mod mentions;
+pub mod mrkdwn;
+pub mod time;
+pub mod topic;
+pub(crate) mod trace;
@@ -0,0 +1,186 @@
+use crate::{MessageFragment, TextStyle};
+
+use super::compose::plain_text;
+
+/// Parses Slack mrkdwn: `*bold*`, `_italic_`, `~strike~`, inline and fenced code, and the
+/// `<...>` forms for links, user and channel mentions and special mentions.
+pub fn parse_mrkdwn(input: &str) -> Vec<MessageFragment> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut out = Vec::new();
+    let mut styles: Vec<TextStyle> = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '`' {
+            let delimiter = if chars[i..].starts_with(&['`', '`', '`']) {
+                "```"
+            } else {
+                "`"
+            };
+            let start = i + delimiter.len();
+            if let Some(end) = find(&chars, start, delimiter) {
+                flush(&mut out, &mut text, &styles);
+                let body: String = chars[start..end].iter().collect();
+                let body = if delimiter == "```" {
+                    body.trim_matches('\n').to_string()
+                } else {
+                    body
+                };
+                out.push(MessageFragment::Code {
+                    lang: None,
+                    body: unescape(&body),
+                });
+                i = end + delimiter.len();
+                continue;
+            }
+        }
+
+        if c == '<' {
+            if let Some(end) = chars[i + 1..].iter().position(|&c| c == '>') {
+                flush(&mut out, &mut text, &styles);
+                let inner: String = chars[i + 1..i + 1 + end].iter().collect();
+                out.push(entity(&inner));
+                i += end + 2;
+                continue;
+            }
+        }
+
+        if let Some(style) = style_for(c) {
+            if styles.contains(&style) && can_close(&chars, i) {
+                flush(&mut out, &mut text, &styles);
+                styles.retain(|s| *s != style);
+                i += 1;
+                continue;
+            }
+            if !styles.contains(&style) && can_open(&chars, i) && has_closer(&chars, i) {
+                flush(&mut out, &mut text, &styles);
+                styles.push(style);
+                i += 1;
+                continue;
+            }
+        }
+
+        text.push(c);
+        i += 1;
+    }
+    flush(&mut out, &mut text, &styles);
+    out
+}
+
+/// Renders fragments back to mrkdwn, escaping `&`, `<` and `>` in plain text.
+pub fn render_mrkdwn(content: &[MessageFragment]) -> String {
+    let mut out = String::new();
+    for fragment in content {
+        match fragment {
+            MessageFragment::Text(text) => out.push_str(&escape(text)),
+            MessageFragment::Styled { text, styles } => {
+                let markers: String = styles
+                    .iter()
+                    .filter_map(|style| match style {
+                        TextStyle::Bold => Some('*'),
+                        TextStyle::Italic => Some('_'),
+                        TextStyle::Strike => Some('~'),
+                        TextStyle::Underline | TextStyle::Color(_) => None,
+                    })
+                    .collect();
+                out.push_str(&markers);
+                out.push_str(&escape(text));
+                out.extend(markers.chars().rev());
+            }
+            MessageFragment::Mention { user_id, .. } => out.push_str(&format!("<@{}>", user_id)),
+            MessageFragment::Url(url) => out.push_str(&format!("<{}>", url)),
+            MessageFragment::Code { body, .. } if body.contains('\n') => {
+                out.push_str(&format!("```\n{}\n```", escape(body)))
+            }
+            MessageFragment::Code { body, .. } => out.push_str(&format!("`{}`", escape(body))),
+            MessageFragment::Spoiler(inner) => out.push_str(&render_mrkdwn(inner)),
+            other => out.push_str(&escape(&plain_text(std::slice::from_ref(other)))),
+        }
+    }
+    out
+}
+
+fn entity(inner: &str) -> MessageFragment {
+    let (target, label) = match inner.split_once('|') {
+        Some((target, label)) => (target, Some(unescape(label))),
+        None => (inner, None),
+    };
+    if let Some(user_id) = target.strip_prefix('@') {
+        return MessageFragment::Mention {
+            user_id: user_id.to_string(),
+            display: label.unwrap_or_else(|| user_id.to_string()),
+        };
+    }
+    if let Some(channel_id) = target.strip_prefix('#') {
+        return MessageFragment::Text(format!("#{}", label.as_deref().unwrap_or(channel_id)));
+    }
+    if let Some(special) = target.strip_prefix('!') {
+        let name = special.split('^').next().unwrap_or(special);
+        return MessageFragment::Text(label.unwrap_or_else(|| format!("@{}", name)));
+    }
+    MessageFragment::Url(unescape(target))
+}
+
+fn style_for(c: char) -> Option<TextStyle> {
+    match c {
+        '*' => Some(TextStyle::Bold),
+        '_' => Some(TextStyle::Italic),
+        '~' => Some(TextStyle::Strike),
+        _ => None,
+    }
+}
+
+fn can_open(chars: &[char], i: usize) -> bool {
+    let before = i.checked_sub(1).map(|j| chars[j]);
+    let after = chars.get(i + 1);
+    before.is_none_or(|c| !c.is_alphanumeric()) && after.is_some_and(|c| !c.is_whitespace())
+}
+
+fn can_close(chars: &[char], i: usize) -> bool {
+    let before = i.checked_sub(1).map(|j| chars[j]);
+    let after = chars.get(i + 1);
+    before.is_some_and(|c| !c.is_whitespace()) && after.is_none_or(|c| !c.is_alphanumeric())
+}
+
+/// Whether the marker at `open` is closed later on the same line.
+fn has_closer(chars: &[char], open: usize) -> bool {
+    chars[open + 2..]
+        .iter()
+        .take_while(|&&c| c != '\n')
+        .enumerate()
+        .any(|(offset, &c)| c == chars[open] && can_close(chars, open + 2 + offset))
+}
+
+fn find(chars: &[char], from: usize, needle: &str) -> Option<usize> {
+    let needle: Vec<char> = needle.chars().collect();
+    (from..chars.len()).find(|&i| chars[i..].starts_with(&needle))
+}
+
+fn flush(out: &mut Vec<MessageFragment>, text: &mut String, styles: &[TextStyle]) {
+    if text.is_empty() {
+        return;
+    }
+    let text = unescape(&std::mem::take(text));
+    if styles.is_empty() {
+        out.push(MessageFragment::Text(text));
+    } else {
+        out.push(MessageFragment::Styled {
+            text,
+            styles: styles.to_vec(),
+        });
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
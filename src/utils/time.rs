@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset, Local, TimeZone, Utc};
+
+use crate::Message;
+
+pub fn relative_time(timestamp: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let delta = now.signed_duration_since(timestamp);
+    let seconds = delta.num_seconds();
+    let (amount, unit) = match seconds.unsigned_abs() {
+        0..=9 => return "just now".to_string(),
+        s @ 10..=59 => (s, "s"),
+        s @ 60..=3_599 => (s / 60, "m"),
+        s @ 3_600..=86_399 => (s / 3_600, "h"),
+        s @ 86_400..=604_799 => (s / 86_400, "d"),
+        s @ 604_800..=31_535_999 => (s / 604_800, "w"),
+        s => (s / 31_536_000, "y"),
+    };
+    if seconds < 0 {
+        format!("in {}{}", amount, unit)
+    } else {
+        format!("{}{} ago", amount, unit)
+    }
+}
+
+pub fn to_local(timestamp: DateTime<Utc>) -> DateTime<Local> {
+    timestamp.with_timezone(&Local)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ClockStyle {
+    #[default]
+    TwentyFourHour,
+    TwelveHour,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DateOrder {
+    #[default]
+    YearMonthDay,
+    DayMonthYear,
+    MonthDayYear,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TimeZoneChoice {
+    #[default]
+    Local,
+    Utc,
+    Fixed(FixedOffset),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TimestampFormat {
+    pub clock: ClockStyle,
+    pub date_order: DateOrder,
+    pub zone: TimeZoneChoice,
+    pub seconds: bool,
+}
+
+impl TimestampFormat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn for_locale(locale: &str) -> Self {
+        let region = locale
+            .split(['-', '_', '.'])
+            .nth(1)
+            .unwrap_or_default()
+            .to_ascii_uppercase();
+        let language = locale.split(['-', '_', '.']).next().unwrap_or_default();
+        let (clock, date_order) = match (language, region.as_str()) {
+            (_, "US") | (_, "PH") => (ClockStyle::TwelveHour, DateOrder::MonthDayYear),
+            (_, "CA") | (_, "AU") | (_, "IN") => (ClockStyle::TwelveHour, DateOrder::DayMonthYear),
+            ("ja" | "zh" | "ko" | "hu" | "lt" | "sv", _) => {
+                (ClockStyle::TwentyFourHour, DateOrder::YearMonthDay)
+            }
+            ("en", "") => (ClockStyle::TwelveHour, DateOrder::MonthDayYear),
+            ("" | "C" | "POSIX", _) => (ClockStyle::TwentyFourHour, DateOrder::YearMonthDay),
+            _ => (ClockStyle::TwentyFourHour, DateOrder::DayMonthYear),
+        };
+        TimestampFormat {
+            clock,
+            date_order,
+            ..Self::default()
+        }
+    }
+
+    pub fn clock(mut self, clock: ClockStyle) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn date_order(mut self, date_order: DateOrder) -> Self {
+        self.date_order = date_order;
+        self
+    }
+
+    pub fn zone(mut self, zone: TimeZoneChoice) -> Self {
+        self.zone = zone;
+        self
+    }
+
+    pub fn seconds(mut self, seconds: bool) -> Self {
+        self.seconds = seconds;
+        self
+    }
+
+    pub fn format_time(&self, timestamp: DateTime<Utc>) -> String {
+        self.render(timestamp, &self.time_pattern())
+    }
+
+    pub fn format_date(&self, timestamp: DateTime<Utc>) -> String {
+        self.render(timestamp, self.date_pattern())
+    }
+
+    pub fn format(&self, timestamp: DateTime<Utc>) -> String {
+        let pattern = format!("{} {}", self.date_pattern(), self.time_pattern());
+        self.render(timestamp, &pattern)
+    }
+
+    fn date_pattern(&self) -> &'static str {
+        match self.date_order {
+            DateOrder::YearMonthDay => "%Y-%m-%d",
+            DateOrder::DayMonthYear => "%d/%m/%Y",
+            DateOrder::MonthDayYear => "%m/%d/%Y",
+        }
+    }
+
+    fn time_pattern(&self) -> String {
+        match (self.clock, self.seconds) {
+            (ClockStyle::TwentyFourHour, false) => "%H:%M".to_string(),
+            (ClockStyle::TwentyFourHour, true) => "%H:%M:%S".to_string(),
+            (ClockStyle::TwelveHour, false) => "%-I:%M %p".to_string(),
+            (ClockStyle::TwelveHour, true) => "%-I:%M:%S %p".to_string(),
+        }
+    }
+
+    fn render(&self, timestamp: DateTime<Utc>, pattern: &str) -> String {
+        match self.zone {
+            TimeZoneChoice::Local => in_zone(timestamp, &Local, pattern),
+            TimeZoneChoice::Utc => in_zone(timestamp, &Utc, pattern),
+            TimeZoneChoice::Fixed(offset) => in_zone(timestamp, &offset, pattern),
+        }
+    }
+}
+
+fn in_zone<Tz: TimeZone>(timestamp: DateTime<Utc>, zone: &Tz, pattern: &str) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    timestamp.with_timezone(zone).format(pattern).to_string()
+}
+
+#[derive(Clone, Debug)]
+pub struct TimestampCache {
+    format: TimestampFormat,
+    capacity: usize,
+    entries: HashMap<DateTime<Utc>, String>,
+}
+
+impl TimestampCache {
+    pub fn new(format: TimestampFormat, capacity: usize) -> Self {
+        TimestampCache {
+            format,
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn format(&self) -> TimestampFormat {
+        self.format
+    }
+
+    pub fn set_format(&mut self, format: TimestampFormat) {
+        if self.format != format {
+            self.format = format;
+            self.entries.clear();
+        }
+    }
+
+    pub fn get(&mut self, timestamp: DateTime<Utc>) -> &str {
+        if !self.entries.contains_key(&timestamp) && self.entries.len() >= self.capacity {
+            self.entries.clear();
+        }
+        let format = self.format;
+        self.entries
+            .entry(timestamp)
+            .or_insert_with(|| format.format(timestamp))
+    }
+
+    pub fn message(&mut self, message: &Message) -> &str {
+        self.get(message.timestamp)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
@@ -0,0 +1,21 @@
+use regex::Regex;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TopicAnnouncement {
+    pub topic: Option<String>,
+    pub set_by: Option<String>,
+}
+
+pub fn parse_topic_announcement(pattern: &Regex, text: &str) -> Option<TopicAnnouncement> {
+    let captures = pattern.captures(text.trim())?;
+    let topic = captures
+        .name("topic")
+        .or_else(|| captures.get(1))
+        .map(|m| m.as_str().trim().to_string())
+        .filter(|topic| !topic.is_empty());
+    let set_by = captures
+        .name("user")
+        .map(|m| m.as_str().trim().to_string())
+        .filter(|user| !user.is_empty());
+    Some(TopicAnnouncement { topic, set_by })
+}
@@ -0,0 +1,21 @@
+//! Forwards `event!(level, ...)` to the matching `tracing` macro when the `tracing` feature is
+//! enabled. Without it the arguments still type-check but sit behind `if false`, so they are
+//! never evaluated.
+
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {{
+        ::tracing::$level!($($arg)+);
+    }};
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {{
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+pub(crate) use event;
@@ -0,0 +1,111 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::Utc;
+use oshatori::{
+    client::{ChannelSnapshot, StateClient},
+    connection::{ArchiveConnection, ChatEvent, ConnectionEvent, Scope},
+    Channel, ChannelType, Connection, ConnectionError, Message, MessageFragment, MessageStatus,
+    MessageType, Profile,
+};
+
+fn message(id: &str, text: &str) -> Message {
+    Message {
+        id: Some(id.to_string()),
+        sender_id: Some("1".to_string()),
+        content: vec![MessageFragment::Text(text.to_string())],
+        timestamp: Utc::now(),
+        message_type: MessageType::Normal,
+        status: MessageStatus::Delivered,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
+    }
+}
+
+fn export() -> String {
+    let snapshot = ChannelSnapshot {
+        channel: Channel {
+            id: "general".to_string(),
+            name: Some("General".to_string()),
+            channel_type: ChannelType::Group,
+            topic: None,
+            extra: HashMap::new(),
+        },
+        users: vec![Profile {
+            id: Some("1".to_string()),
+            username: Some("ann".to_string()),
+            ..Profile::default()
+        }],
+        messages: vec![message("m1", "first"), message("m2", "second")],
+        assets: Vec::new(),
+        annotations: BTreeMap::new(),
+    };
+    let later = ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("general"),
+            message: message("m3", "third"),
+        },
+    };
+    format!(
+        "{}\n\n{}\n",
+        serde_json::to_string(&snapshot).unwrap(),
+        serde_json::to_string(&later).unwrap()
+    )
+}
+
+#[tokio::test]
+async fn archive_replays_into_stateclient() {
+    let mut archive = ArchiveConnection::parse("old-logs", &export()).unwrap();
+    assert_eq!(archive.protocol_spec().name, "archive");
+    let mut rx = archive.subscribe();
+
+    let client = StateClient::new();
+    let conn_id = client.track("archive").await;
+    archive.connect().await.unwrap();
+    while let Ok(event) = rx.try_recv() {
+        client.process(&conn_id, event).await;
+    }
+
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    assert_eq!(channel.channel.name.as_deref(), Some("General"));
+    assert!(channel.users.contains_key("1"));
+    let ids: Vec<_> = channel
+        .messages
+        .iter()
+        .filter_map(|m| m.id.clone())
+        .collect();
+    assert_eq!(ids, vec!["m1", "m2", "m3"]);
+
+    let result = archive
+        .send(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel("general"),
+                message: message("m4", "hello?"),
+            },
+        })
+        .await;
+    assert!(matches!(result, Err(ConnectionError::Unsupported(_))));
+}
+
+#[tokio::test]
+async fn archive_serves_history_pages() {
+    let mut archive = ArchiveConnection::parse("old-logs", &export()).unwrap();
+    assert!(archive.capabilities().history);
+
+    let page = archive
+        .fetch_history("general", Some("m3".to_string()), 1)
+        .await
+        .unwrap();
+    assert_eq!(page[0].id.as_deref(), Some("m2"));
+    assert!(archive
+        .fetch_history("elsewhere", None, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert!(matches!(
+        ArchiveConnection::parse("broken", "{not json"),
+        Err(ConnectionError::Protocol(_))
+    ));
+}
@@ -0,0 +1,130 @@
+use oshatori::{utils::bbcode::parse_bbcode, MessageFragment, TextStyle};
+
+#[test]
+fn preserves_nested_styles() {
+    let frags = parse_bbcode("a [b]bold [i]both[/i][/b] [color=#f00]red[/color] [u]u[/u]");
+    assert_eq!(
+        frags,
+        vec![
+            MessageFragment::Text("a ".to_string()),
+            MessageFragment::Styled {
+                text: "bold ".to_string(),
+                styles: vec![TextStyle::Bold],
+            },
+            MessageFragment::Styled {
+                text: "both".to_string(),
+                styles: vec![TextStyle::Bold, TextStyle::Italic],
+            },
+            MessageFragment::Text(" ".to_string()),
+            MessageFragment::Styled {
+                text: "red".to_string(),
+                styles: vec![TextStyle::Color([255, 0, 0, 255])],
+            },
+            MessageFragment::Text(" ".to_string()),
+            MessageFragment::Styled {
+                text: "u".to_string(),
+                styles: vec![TextStyle::Underline],
+            },
+        ]
+    );
+}
+
+#[test]
+fn ignores_unparseable_colors() {
+    assert_eq!(
+        parse_bbcode("[color=nope]plain[/color]"),
+        vec![MessageFragment::Text("plain".to_string())]
+    );
+    assert_eq!(
+        parse_bbcode("[s][color=navy]x[/color][/s]"),
+        vec![MessageFragment::Styled {
+            text: "x".to_string(),
+            styles: vec![TextStyle::Strike, TextStyle::Color([0, 0, 128, 255])],
+        }]
+    );
+}
+
+#[test]
+fn renders_back_to_bbcode() {
+    use oshatori::utils::bbcode::render_bbcode;
+
+    let source = "a [b][i]both[/i][/b] [color=#ff0000]red[/color] [img]https://x/y.png[/img]";
+    assert_eq!(render_bbcode(&parse_bbcode(source)), source);
+}
+
+#[test]
+fn parses_spoilers_and_code() {
+    let frags =
+        parse_bbcode("[spoiler]hidden [b]x[/b][/spoiler][code=rust]let [b]a[/b] = 1;[/code]");
+    assert_eq!(
+        frags,
+        vec![
+            MessageFragment::Spoiler(vec![
+                MessageFragment::Text("hidden ".to_string()),
+                MessageFragment::Styled {
+                    text: "x".to_string(),
+                    styles: vec![TextStyle::Bold],
+                },
+            ]),
+            MessageFragment::Code {
+                lang: Some("rust".to_string()),
+                body: "let [b]a[/b] = 1;".to_string(),
+            },
+        ]
+    );
+    assert_eq!(
+        parse_bbcode("[code]x[/code]"),
+        vec![MessageFragment::Code {
+            lang: None,
+            body: "x".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn round_trips_spoilers_and_code() {
+    use oshatori::utils::bbcode::render_bbcode;
+
+    let source = "[spoiler]a [b]b[/b][/spoiler] [code=py]print(1)[/code] [code]x[/code]";
+    let frags = parse_bbcode(source);
+    assert_eq!(render_bbcode(&frags), source);
+
+    let json = serde_json::to_string(&frags).unwrap();
+    let back: Vec<MessageFragment> = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, frags);
+}
+
+#[test]
+fn parses_voice_notes() {
+    use oshatori::utils::bbcode::{parse_duration_ms, render_bbcode};
+
+    let frags = parse_bbcode("[voice duration=1:02.5 size=2048]https://x/y.opus[/voice]");
+    assert_eq!(
+        frags,
+        vec![MessageFragment::VoiceNote {
+            url: "https://x/y.opus".to_string(),
+            mime: "audio/opus".to_string(),
+            duration_ms: Some(62_500),
+            waveform: None,
+            size: Some(2048),
+        }]
+    );
+    assert_eq!(
+        render_bbcode(&frags),
+        "[voice duration=62.5 size=2048]https://x/y.opus[/voice]"
+    );
+    assert_eq!(
+        render_bbcode(&parse_bbcode(&render_bbcode(&frags))),
+        render_bbcode(&frags)
+    );
+
+    assert!(matches!(
+        parse_bbcode("[voice=3]https://x/y.ogg[/voice]").as_slice(),
+        [MessageFragment::VoiceNote {
+            duration_ms: Some(3000),
+            size: None,
+            ..
+        }]
+    ));
+    assert_eq!(parse_duration_ms("nope"), None);
+}
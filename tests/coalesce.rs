@@ -0,0 +1,87 @@
+#![cfg(feature = "mock")]
+
+use std::time::Duration;
+
+use oshatori::{
+    client::StateClient,
+    connection::{
+        CoalescingConnection, ConnectionEvent, MockConnection, Scope, StatusEvent, UserEvent,
+    },
+    Connection, Profile,
+};
+
+fn join(user_id: &str) -> ConnectionEvent {
+    ConnectionEvent::User {
+        event: UserEvent::New {
+            scope: Scope::channel("general"),
+            user: Profile {
+                id: Some(user_id.to_string()),
+                username: Some(user_id.to_string()),
+                ..Profile::default()
+            },
+        },
+    }
+}
+
+#[tokio::test]
+async fn batches_user_list_churn() {
+    let mut conn = CoalescingConnection::new(MockConnection::new(), Duration::from_millis(50));
+    let mut rx = conn.subscribe();
+
+    for user_id in ["a", "b", "c"] {
+        conn.send(join(user_id)).await.unwrap();
+    }
+    conn.send(ConnectionEvent::User {
+        event: UserEvent::Remove {
+            scope: Scope::channel("general"),
+            user_id: "a".to_string(),
+        },
+    })
+    .await
+    .unwrap();
+    conn.send(ConnectionEvent::Status {
+        event: StatusEvent::Ping { artifact: None },
+    })
+    .await
+    .unwrap();
+
+    let batch = rx.recv().await.unwrap();
+    let ConnectionEvent::User {
+        event: UserEvent::Batch { events },
+    } = &batch
+    else {
+        panic!("expected batch, got {:?}", batch);
+    };
+    assert_eq!(events.len(), 4);
+    assert!(matches!(
+        rx.recv().await,
+        Some(ConnectionEvent::Status {
+            event: StatusEvent::Ping { .. }
+        })
+    ));
+
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    client.process(&conn_id, batch).await;
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    let mut users: Vec<_> = channel.users.keys().cloned().collect();
+    users.sort();
+    assert_eq!(users, vec!["b", "c"]);
+}
+
+#[tokio::test]
+async fn flushes_after_window() {
+    let mut conn = CoalescingConnection::new(MockConnection::new(), Duration::from_millis(20));
+    let mut rx = conn.subscribe();
+
+    conn.send(join("a")).await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+        .await
+        .unwrap();
+    assert!(matches!(
+        event,
+        Some(ConnectionEvent::User {
+            event: UserEvent::New { .. }
+        })
+    ));
+}
@@ -0,0 +1,200 @@
+//! Helpers shared by the integration tests. Each test crate only uses some of them.
+#![allow(dead_code)]
+
+use std::{collections::HashMap, fmt, future::Future, time::Duration};
+
+use chrono::Utc;
+use oshatori::{
+    connection::{ChatEvent, ConnectionEvent, Scope},
+    AuthField, FieldValue, Message, MessageFragment, MessageStatus, MessageType,
+};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+pub fn field(name: &str, value: FieldValue) -> AuthField {
+    AuthField {
+        name: name.to_string(),
+        display: None,
+        value,
+        required: false,
+    }
+}
+
+pub fn text_field(name: &str, value: &str) -> AuthField {
+    field(name, FieldValue::Text(Some(value.to_string())))
+}
+
+pub async fn next_event(rx: &mut mpsc::UnboundedReceiver<ConnectionEvent>) -> ConnectionEvent {
+    tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("timed out waiting for an event")
+        .unwrap()
+}
+
+/// A plain text message with no id or sender, sent just now.
+pub fn message(text: &str) -> Message {
+    Message {
+        id: None,
+        sender_id: None,
+        content: vec![MessageFragment::Text(text.to_string())],
+        timestamp: Utc::now(),
+        message_type: MessageType::Normal,
+        status: MessageStatus::Sent,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
+    }
+}
+
+/// A plain text message from `sender_id`, sent just now.
+pub fn message_from(sender_id: &str, text: &str) -> Message {
+    Message {
+        sender_id: Some(sender_id.to_string()),
+        ..message(text)
+    }
+}
+
+pub fn chat_message(scope: Scope, message: Message) -> ConnectionEvent {
+    ConnectionEvent::Chat {
+        event: ChatEvent::New { scope, message },
+    }
+}
+
+pub fn chat(scope: Scope, text: &str) -> ConnectionEvent {
+    chat_message(scope, message(text))
+}
+
+pub struct Request {
+    pub line: String,
+    /// Header names are lowercased.
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn path(&self) -> &str {
+        self.line.split_whitespace().nth(1).unwrap_or_default()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Reads one HTTP/1.1 request, or `None` once the client closes the connection.
+pub async fn read_request(stream: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await.ok()? == 0 {
+        return None;
+    }
+    let mut headers = Vec::new();
+    loop {
+        let mut header = String::new();
+        stream.read_line(&mut header).await.ok()?;
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await.ok()?;
+    Some(Request {
+        line: line.trim().to_string(),
+        headers,
+        body: String::from_utf8(body).unwrap(),
+    })
+}
+
+pub struct Response {
+    pub status: &'static str,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: &'static str) -> Self {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    pub fn json(value: Value) -> Self {
+        Response::new("200 OK")
+            .header("Content-Type", "application/json")
+            .body(value.to_string())
+    }
+
+    pub fn status(mut self, status: &'static str) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<String>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP/1.1 {}\r\n", self.status)?;
+        for (name, value) in &self.headers {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        write!(
+            f,
+            "Content-Length: {}\r\n\r\n{}",
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+/// Answers every request on every accepted connection with `handler`, keeping connections alive
+/// until the client closes them.
+pub async fn serve_http<F, Fut>(listener: TcpListener, handler: F)
+where
+    F: Fn(Request) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response> + Send,
+{
+    loop {
+        let (socket, _) = listener.accept().await.unwrap();
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let mut stream = BufReader::new(socket);
+            while let Some(request) = read_request(&mut stream).await {
+                let response = handler(request).await.to_string();
+                if stream
+                    .get_mut()
+                    .write_all(response.as_bytes())
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+    }
+}
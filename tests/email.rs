@@ -0,0 +1,362 @@
+#![cfg(feature = "email")]
+
+mod common;
+
+use common::{next_event, text_field};
+
+use chrono::Utc;
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, EmailConnection, Scope,
+        StatusEvent, UserEvent,
+    },
+    ChannelType, Connection, Message, MessageFragment, MessageStatus, MessageType,
+};
+use std::{collections::HashMap, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::mpsc,
+};
+
+const LUNCH: &str = concat!(
+    "From: Ann <ann@example.com>\r\n",
+    "To: bot@example.com\r\n",
+    "Cc: Bob <bob@example.com>\r\n",
+    "Subject: Lunch\r\n",
+    "Date: Tue, 14 Nov 2023 22:13:20 +0000\r\n",
+    "Message-ID: <m1@example.com>\r\n",
+    "\r\n",
+    "Pizza?\r\n",
+);
+
+const LUNCH_REPLY: &str = concat!(
+    "From: Bob <bob@example.com>\r\n",
+    "To: Ann <ann@example.com>, bot@example.com\r\n",
+    "Subject: Re: Lunch\r\n",
+    "Message-ID: <m2@example.com>\r\n",
+    "In-Reply-To: <m1@example.com>\r\n",
+    "References: <m1@example.com>\r\n",
+    "\r\n",
+    "Sure\r\n",
+);
+
+const MENU: &str = concat!(
+    "From: Ann <ann@example.com>\r\n",
+    "To: bot@example.com, bob@example.com\r\n",
+    "Subject: Re: Re: Lunch\r\n",
+    "Message-ID: <m3@example.com>\r\n",
+    "In-Reply-To: <m2@example.com>\r\n",
+    "References: <m1@example.com> <m2@example.com>\r\n",
+    "MIME-Version: 1.0\r\n",
+    "Content-Type: text/html; charset=utf-8\r\n",
+    "\r\n",
+    "<p>See <a href=\"https://example.com/menu\">the menu</a></p>\r\n",
+);
+
+const HELLO: &str = concat!(
+    "From: carol@example.com\r\n",
+    "To: bot@example.com\r\n",
+    "Subject: Hello\r\n",
+    "Message-ID: <m4@example.com>\r\n",
+    "\r\n",
+    "hi\r\n",
+);
+
+fn fetched(sequence: u32, uid: u32, raw: &str) -> String {
+    format!(
+        "* {} FETCH (UID {} BODY[] {{{}}}\r\n{})\r\n",
+        sequence,
+        uid,
+        raw.len(),
+        raw
+    )
+}
+
+/// A fake IMAP server holding the lunch thread, which delivers two new messages on the first
+/// poll. The password is `secret`.
+async fn serve_imap(listener: TcpListener) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer.write_all(b"* OK ready\r\n").await.unwrap();
+    let mut polls = 0;
+    while let Ok(Some(line)) = lines.next_line().await {
+        let (tag, command) = line.split_once(' ').unwrap();
+        let reply = if command.starts_with("LOGIN") && !command.ends_with("\"secret\"") {
+            format!("{} NO [AUTHENTICATIONFAILED] Invalid credentials\r\n", tag)
+        } else if command.starts_with("LOGIN") {
+            format!("{} OK logged in\r\n", tag)
+        } else if command.starts_with("SELECT \"INBOX\"") {
+            format!(
+                "* 2 EXISTS\r\n* OK [UIDNEXT 3]\r\n{} OK [READ-WRITE] done\r\n",
+                tag
+            )
+        } else if command == "FETCH 1:* (UID BODY.PEEK[])" {
+            format!(
+                "{}{}{} OK done\r\n",
+                fetched(1, 1, LUNCH),
+                fetched(2, 2, LUNCH_REPLY),
+                tag
+            )
+        } else if command.starts_with("UID FETCH") {
+            polls += 1;
+            match polls {
+                1 => format!(
+                    "{}{}{} OK done\r\n",
+                    fetched(3, 3, MENU),
+                    fetched(4, 4, HELLO),
+                    tag
+                ),
+                _ => format!("{}{} OK done\r\n", fetched(4, 4, HELLO), tag),
+            }
+        } else {
+            format!("{} BAD unknown\r\n", tag)
+        };
+        writer.write_all(reply.as_bytes()).await.unwrap();
+    }
+}
+
+/// A fake SMTP server that accepts anything and reports each command and the message data.
+async fn serve_smtp(listener: TcpListener, lines_tx: mpsc::UnboundedSender<String>) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer.write_all(b"220 ready\r\n").await.unwrap();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let reply = match line.split(' ').next().unwrap() {
+            "EHLO" => "250-hello\r\n250 AUTH PLAIN\r\n",
+            "AUTH" => "235 ok\r\n",
+            "DATA" => {
+                let _ = lines_tx.send(line);
+                writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                let mut data = Vec::new();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line == "." {
+                        break;
+                    }
+                    data.push(line);
+                }
+                let _ = lines_tx.send(data.join("\n"));
+                writer.write_all(b"250 queued\r\n").await.unwrap();
+                continue;
+            }
+            "QUIT" => "221 bye\r\n",
+            _ => "250 ok\r\n",
+        };
+        let _ = lines_tx.send(line);
+        writer.write_all(reply.as_bytes()).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn email_threads_poll_and_reply() {
+    let imap = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let imap_port = imap.local_addr().unwrap().port();
+    tokio::spawn(serve_imap(imap));
+    let smtp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let smtp_port = smtp.local_addr().unwrap().port();
+    let (smtp_tx, mut smtp_lines) = mpsc::unbounded_channel();
+    tokio::spawn(serve_smtp(smtp, smtp_tx));
+
+    let mut conn = EmailConnection::new().poll_interval(Duration::from_millis(50));
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("imap_server", &format!("imap://127.0.0.1:{}", imap_port)),
+        text_field("smtp_server", &format!("smtp://127.0.0.1:{}", smtp_port)),
+        text_field("username", "Bot@example.com"),
+        text_field("password", "secret"),
+    ])
+    .unwrap();
+    conn.connect().await.unwrap();
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::Identify { user_id } } if user_id == "bot@example.com"
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel },
+        } => {
+            assert_eq!(channel.id, "m1@example.com");
+            assert_eq!(channel.name.as_deref(), Some("Lunch"));
+            assert_eq!(channel.channel_type, ChannelType::Direct);
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    match next_event(&mut rx).await {
+        ConnectionEvent::User {
+            event: UserEvent::New { scope, user },
+        } => {
+            assert_eq!(scope, Scope::channel("m1@example.com"));
+            assert_eq!(user.id.as_deref(), Some("ann@example.com"));
+            assert_eq!(user.display_name.as_deref(), Some("Ann"));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::New { user, .. } }
+            if user.id.as_deref() == Some("bob@example.com")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel("m1@example.com"));
+            assert_eq!(message.id.as_deref(), Some("m3@example.com"));
+            assert_eq!(message.reply_to.as_deref(), Some("m2@example.com"));
+            assert_eq!(message.sender_id.as_deref(), Some("ann@example.com"));
+            assert!(message.content.contains(&MessageFragment::Url(
+                "https://example.com/menu".to_string()
+            )));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::New { channel } }
+            if channel.id == "m4@example.com" && channel.name.as_deref() == Some("Hello")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::New { user, .. } }
+            if user.id.as_deref() == Some("carol@example.com")
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel("m4@example.com"));
+            assert_eq!(
+                message.content,
+                vec![MessageFragment::Text("hi".to_string())]
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+
+    conn.send(ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("m1@example.com"),
+            message: Message {
+                id: None,
+                sender_id: None,
+                content: vec![MessageFragment::Text("Count me in".to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: None,
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    })
+    .await
+    .unwrap();
+    assert_eq!(smtp_lines.recv().await.unwrap(), "EHLO example.com");
+    assert_eq!(
+        smtp_lines.recv().await.unwrap(),
+        "AUTH PLAIN AEJvdEBleGFtcGxlLmNvbQBzZWNyZXQ="
+    );
+    assert_eq!(
+        smtp_lines.recv().await.unwrap(),
+        "MAIL FROM:<bot@example.com>"
+    );
+    assert_eq!(
+        smtp_lines.recv().await.unwrap(),
+        "RCPT TO:<ann@example.com>"
+    );
+    assert_eq!(
+        smtp_lines.recv().await.unwrap(),
+        "RCPT TO:<bob@example.com>"
+    );
+    assert_eq!(smtp_lines.recv().await.unwrap(), "DATA");
+    let data = smtp_lines.recv().await.unwrap();
+    assert!(data.contains("To: ann@example.com, bob@example.com\n"));
+    assert!(data.contains("Subject: Re: Lunch\n"));
+    assert!(data.contains("In-Reply-To: <m3@example.com>\n"));
+    assert!(data.contains("References: <m1@example.com> <m2@example.com> <m3@example.com>\n"));
+    assert!(data.ends_with("Q291bnQgbWUgaW4="));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. },
+        } => {
+            assert_eq!(message.message_type, MessageType::CurrentUser);
+            assert_eq!(message.reply_to.as_deref(), Some("m3@example.com"));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+
+    let history = conn
+        .fetch_history("m1@example.com", None, 10)
+        .await
+        .unwrap();
+    let ids: Vec<_> = history
+        .iter()
+        .map(|message| message.id.clone().unwrap())
+        .collect();
+    assert_eq!(
+        &ids[..3],
+        ["m1@example.com", "m2@example.com", "m3@example.com"]
+    );
+    assert_eq!(history.len(), 4);
+    let earlier = conn
+        .fetch_history("m1@example.com", Some("m3@example.com".to_string()), 1)
+        .await
+        .unwrap();
+    assert_eq!(earlier[0].id.as_deref(), Some("m2@example.com"));
+}
+
+#[tokio::test]
+async fn email_wrong_password_fails_auth() {
+    let imap = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let imap_port = imap.local_addr().unwrap().port();
+    tokio::spawn(serve_imap(imap));
+
+    let mut conn = EmailConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("imap_server", &format!("imap://127.0.0.1:{}", imap_port)),
+        text_field("smtp_server", "smtp.example.com"),
+        text_field("username", "bot@example.com"),
+        text_field("password", "wrong"),
+    ])
+    .unwrap();
+    assert!(matches!(
+        conn.connect().await,
+        Err(ConnectionError::Auth(reason)) if reason.contains("Invalid credentials")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed { .. }
+        }
+    ));
+}
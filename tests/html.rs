@@ -0,0 +1,79 @@
+use oshatori::{utils::html::html_fragments, MessageFragment, TextStyle};
+
+fn text(s: &str) -> MessageFragment {
+    MessageFragment::Text(s.to_string())
+}
+
+#[test]
+fn converts_mastodon_status_html() {
+    let html = concat!(
+        r#"<p>Hi <span class="h-card"><a href="https://remote.example/@ann" class="u-url mention">"#,
+        r#"@<span>ann</span></a></span>, see <a href="https://example.com/a?b=1&amp;c=2" "#,
+        r#"rel="nofollow"><span class="invisible">https://</span>example.com/a</a> "#,
+        r#"<a href="https://social.example/tags/rust" class="mention hashtag">#<span>rust</span></a>"#,
+        r#"<br />it&#39;s <strong>very <em>good</em></strong></p><p>bye &amp; &lt;3</p>"#,
+    );
+    assert_eq!(
+        html_fragments(html),
+        vec![
+            text("Hi "),
+            MessageFragment::Mention {
+                user_id: "https://remote.example/@ann".to_string(),
+                display: "ann".to_string(),
+            },
+            text(", see "),
+            MessageFragment::Url("https://example.com/a?b=1&c=2".to_string()),
+            text(" #rust\nit's "),
+            MessageFragment::Styled {
+                text: "very ".to_string(),
+                styles: vec![TextStyle::Bold],
+            },
+            MessageFragment::Styled {
+                text: "good".to_string(),
+                styles: vec![TextStyle::Bold, TextStyle::Italic],
+            },
+            text("\n\nbye & <3"),
+        ]
+    );
+}
+
+#[test]
+fn keeps_code_verbatim() {
+    assert_eq!(
+        html_fragments(
+            "<p>run <code>a  &lt; b</code></p><pre><code class=\"language-rust\">fn main() {\n    x();\n}\n</code></pre>"
+        ),
+        vec![
+            text("run "),
+            MessageFragment::Code {
+                lang: None,
+                body: "a  < b".to_string(),
+            },
+            MessageFragment::Code {
+                lang: Some("rust".to_string()),
+                body: "fn main() {\n    x();\n}".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn maps_inline_images() {
+    assert_eq!(
+        html_fragments(
+            r#"look <img src="data:image/png;base64,iVBO"/> and <img src="https://x.example/a.gif">"#
+        ),
+        vec![
+            text("look "),
+            MessageFragment::Image {
+                url: "data:image/png;base64,iVBO".to_string(),
+                mime: "image/png".to_string(),
+            },
+            text(" and "),
+            MessageFragment::Image {
+                url: "https://x.example/a.gif".to_string(),
+                mime: "image/*".to_string(),
+            },
+        ]
+    );
+}
@@ -0,0 +1,317 @@
+#![cfg(feature = "irc")]
+
+mod common;
+
+use common::{next_event, text_field};
+
+use chrono::Utc;
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionEvent, IrcConnection, IrcMessage, Scope, StatusEvent,
+        UserEvent,
+    },
+    ChannelType, Connection, Message, MessageFragment, MessageStatus, MessageType,
+};
+use std::{collections::HashMap, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener,
+    },
+};
+
+async fn expect_line(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> IrcMessage {
+    let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line())
+        .await
+        .expect("timed out waiting for the client")
+        .unwrap()
+        .expect("client closed the connection");
+    IrcMessage::parse(&line).unwrap()
+}
+
+async fn serve(write: &mut OwnedWriteHalf, text: &str) {
+    for line in text.lines() {
+        write
+            .write_all(format!("{}\r\n", line.trim()).as_bytes())
+            .await
+            .unwrap();
+    }
+}
+
+#[test]
+fn irc_message_round_trips() {
+    let message = IrcMessage::parse(
+        "@time=2024-01-02T03:04:05.000Z;msgid=a\\sb :nick!user@host PRIVMSG #chan :hello there",
+    )
+    .unwrap();
+    assert_eq!(message.tags["msgid"], "a b");
+    assert_eq!(message.nick(), Some("nick"));
+    assert_eq!(message.command, "PRIVMSG");
+    assert_eq!(message.params, vec!["#chan", "hello there"]);
+    assert_eq!(
+        message.to_line(),
+        ":nick!user@host PRIVMSG #chan :hello there"
+    );
+
+    let numeric = IrcMessage::parse(":irc.example.net 001 me :Welcome").unwrap();
+    assert_eq!(numeric.nick(), None);
+    assert_eq!(IrcMessage::new("CAP", &["END"]).to_line(), "CAP END");
+    assert!(IrcMessage::parse("").is_none());
+}
+
+#[tokio::test]
+async fn irc_negotiates_and_maps_events() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let mut conn = IrcConnection::new();
+    conn.set_auth(vec![
+        text_field("server", &format!("irc://127.0.0.1:{}", port)),
+        text_field("nick", "osha"),
+        text_field("channels", "#lobby"),
+    ])
+    .unwrap();
+    let mut rx = conn.subscribe();
+    conn.connect().await.unwrap();
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+
+    let (socket, _) = listener.accept().await.unwrap();
+    let (read, mut write) = socket.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    assert_eq!(expect_line(&mut lines).await.params, vec!["LS", "302"]);
+    assert_eq!(expect_line(&mut lines).await.params, vec!["osha"]);
+    assert_eq!(
+        expect_line(&mut lines).await.params,
+        vec!["osha", "0", "*", "osha"]
+    );
+
+    serve(
+        &mut write,
+        ":irc.test CAP * LS * :multi-prefix sasl=PLAIN
+         :irc.test CAP * LS :server-time chghost",
+    )
+    .await;
+    let request = expect_line(&mut lines).await;
+    assert_eq!(request.params, vec!["REQ", "multi-prefix server-time"]);
+    serve(
+        &mut write,
+        ":irc.test CAP osha ACK :multi-prefix server-time",
+    )
+    .await;
+    assert_eq!(expect_line(&mut lines).await.params, vec!["END"]);
+
+    serve(
+        &mut write,
+        ":irc.test 433 * osha :Nickname is already in use
+         :irc.test 001 osha_ :Welcome",
+    )
+    .await;
+    assert_eq!(expect_line(&mut lines).await.params, vec!["osha_"]);
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User {
+            event: UserEvent::Identify { user_id }
+        } if user_id == "osha_"
+    ));
+    assert_eq!(expect_line(&mut lines).await.to_line(), "JOIN #lobby");
+    assert_eq!(conn.nick(), "osha_");
+    assert_eq!(conn.enabled_caps(), vec!["multi-prefix", "server-time"]);
+
+    serve(
+        &mut write,
+        ":osha_!u@h JOIN #lobby
+         :irc.test 353 osha_ = #lobby :@+mod osha_ guest
+         :irc.test 366 osha_ #lobby :End of /NAMES list.
+         :guest!u@h JOIN #lobby
+         @time=2024-05-06T07:08:09.000Z :mod!u@h PRIVMSG #lobby :\x02hello\x02 all
+         :guest!u@h PRIVMSG #lobby :\x01ACTION waves\x01
+         :guest!u@h PRIVMSG osha_ :psst
+         :guest!u@h PART #lobby
+         PING :token",
+    )
+    .await;
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel }
+        } if channel.id == "#lobby" && channel.channel_type == ChannelType::Group
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Join { channel_id }
+        } if channel_id == "#lobby"
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::User {
+            event: UserEvent::Batch { events },
+        } => {
+            assert!(matches!(events[0], UserEvent::ClearList { .. }));
+            let names: Vec<(String, i64)> = events[1..]
+                .iter()
+                .map(|event| match event {
+                    UserEvent::New { user, .. } => (
+                        user.id.clone().unwrap(),
+                        user.role.as_ref().map_or(0, |role| role.rank),
+                    ),
+                    other => panic!("unexpected {:?}", other),
+                })
+                .collect();
+            assert_eq!(
+                names,
+                vec![
+                    ("mod".to_string(), 3),
+                    ("osha_".to_string(), 0),
+                    ("guest".to_string(), 0)
+                ]
+            );
+        }
+        other => panic!("expected a NAMES batch, got {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User {
+            event: UserEvent::New { scope, user }
+        } if scope == Scope::channel("#lobby") && user.id.as_deref() == Some("guest")
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel("#lobby"));
+            assert_eq!(message.sender_id.as_deref(), Some("mod"));
+            assert_eq!(message.message_type, MessageType::Normal);
+            assert_eq!(message.timestamp.to_rfc3339(), "2024-05-06T07:08:09+00:00");
+            assert!(matches!(
+                &message.content[..],
+                [MessageFragment::Text(text)] if text == "hello all"
+            ));
+        }
+        other => panic!("expected a message, got {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. }
+        } if message.message_type == MessageType::Action
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel }
+        } if channel.id == "guest" && channel.channel_type == ChannelType::Direct
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, .. }
+        } if scope == Scope::channel("guest")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User {
+            event: UserEvent::Remove { user_id, .. }
+        } if user_id == "guest"
+    ));
+    assert_eq!(expect_line(&mut lines).await.to_line(), "PONG token");
+
+    conn.send(ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("#lobby"),
+            message: Message {
+                id: None,
+                sender_id: None,
+                content: vec![MessageFragment::Text("dances".to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Action,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: None,
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        expect_line(&mut lines).await.params,
+        vec!["#lobby", "\x01ACTION dances\x01"]
+    );
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. }
+        } if message.sender_id.as_deref() == Some("osha_")
+    ));
+
+    conn.disconnect().await.unwrap();
+    assert_eq!(expect_line(&mut lines).await.command, "QUIT");
+}
+
+#[tokio::test]
+async fn irc_ids_fold_case_in_state() {
+    let conn = IrcConnection::new();
+    assert_eq!(conn.normalize_channel_id("#Chan[1]"), "#chan{1}");
+    assert_eq!(conn.normalize_user_id("Nick\\Away"), "nick|away");
+
+    let client = oshatori::StateClient::new();
+    let conn_id = client.track_connection(&conn).await;
+    for event in [
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New {
+                channel: oshatori::Channel {
+                    id: "#Chan".to_string(),
+                    name: Some("#Chan".to_string()),
+                    channel_type: ChannelType::Group,
+                    topic: None,
+                    extra: HashMap::new(),
+                },
+            },
+        },
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Join {
+                channel_id: "#chan".to_string(),
+            },
+        },
+        ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel("#CHAN"),
+                message: Message {
+                    id: None,
+                    sender_id: Some("Alice".to_string()),
+                    content: vec![MessageFragment::Text("hi".to_string())],
+                    timestamp: Utc::now(),
+                    message_type: MessageType::Normal,
+                    status: MessageStatus::Sent,
+                    reactions: Vec::new(),
+                    reply_to: None,
+                    thread_id: None,
+                    extra: HashMap::new(),
+                },
+            },
+        },
+    ] {
+        client.process(&conn_id, event).await;
+    }
+
+    let state = client.get_connection(&conn_id).await.unwrap();
+    assert_eq!(state.channels.len(), 1);
+    let messages = client.get_messages(&conn_id, "#cHaN").await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].sender_id.as_deref(), Some("alice"));
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::chat;
+
+use std::time::Duration;
+
+use oshatori::{
+    connection::{
+        ChatEvent, ConnectionEvent, LoopGuard, LoopMode, MiddlewareConnection, MockConnection,
+        Scope, SendOutcome, LOOP_TAG,
+    },
+    Connection,
+};
+use serde_json::json;
+
+async fn rejected(conn: &mut MiddlewareConnection<MockConnection>, event: ConnectionEvent) -> bool {
+    let handle = conn.send_tracked(event).await.unwrap();
+    matches!(handle.await, SendOutcome::Failed(_))
+}
+
+#[tokio::test]
+async fn rejects_reentrant_send() {
+    let guard = LoopGuard::new(Duration::from_millis(100), LoopMode::Reject);
+    let mut conn = MiddlewareConnection::new(MockConnection::new()).with(guard);
+
+    assert!(!rejected(&mut conn, chat(Scope::channel("a"), "bridged")).await);
+    assert!(!rejected(&mut conn, chat(Scope::channel("a"), "different")).await);
+    assert!(!rejected(&mut conn, chat(Scope::channel("a"), "bridged")).await);
+    assert!(rejected(&mut conn, chat(Scope::channel("b"), "bridged")).await);
+
+    // A rejected send leaves the window where the first send put it.
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert!(rejected(&mut conn, chat(Scope::channel("b"), "bridged")).await);
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert!(!rejected(&mut conn, chat(Scope::channel("b"), "bridged")).await);
+}
+
+#[tokio::test]
+async fn tags_reentrant_send() {
+    let mut conn = MiddlewareConnection::new(MockConnection::new()).with(LoopGuard::default());
+    let mut rx = conn.subscribe();
+    conn.connect().await.unwrap();
+
+    conn.send(chat(Scope::channel("a"), "bridged"))
+        .await
+        .unwrap();
+    conn.send(chat(Scope::channel("b"), "bridged"))
+        .await
+        .unwrap();
+
+    let mut tags = Vec::new();
+    while let Ok(Some(event)) = tokio::time::timeout(Duration::from_millis(50), rx.recv()).await {
+        if let ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. },
+        } = event
+        {
+            tags.push(message.extra.get(LOOP_TAG).cloned());
+        }
+    }
+    assert_eq!(tags, vec![None, Some(json!({ "Channel": "a" }))]);
+}
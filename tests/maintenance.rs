@@ -0,0 +1,60 @@
+use chrono::{DateTime, Duration, Utc};
+use oshatori::utils::maintenance::{
+    parse_maintenance_notice, MaintenanceNotice, DEFAULT_MAINTENANCE_PATTERN,
+};
+use regex::Regex;
+
+#[test]
+fn parses_default_maintenance_notices() {
+    let pattern = Regex::new(DEFAULT_MAINTENANCE_PATTERN).unwrap();
+    let now = DateTime::from_timestamp(1_714_980_000, 0).unwrap();
+    assert_eq!(
+        parse_maintenance_notice(
+            &pattern,
+            "Server restarting in 5 minutes for 30 minutes.",
+            now
+        ),
+        Some(MaintenanceNotice {
+            scheduled_at: Some(now + Duration::minutes(5)),
+            until: Some(now + Duration::minutes(35)),
+        })
+    );
+    assert_eq!(
+        parse_maintenance_notice(
+            &pattern,
+            "Scheduled maintenance until 2024-05-06T12:00:00Z",
+            now
+        ),
+        Some(MaintenanceNotice {
+            scheduled_at: None,
+            until: Some("2024-05-06T12:00:00Z".parse::<DateTime<Utc>>().unwrap()),
+        })
+    );
+    assert_eq!(
+        parse_maintenance_notice(&pattern, "The server is shutting down", now),
+        Some(MaintenanceNotice {
+            scheduled_at: None,
+            until: None,
+        })
+    );
+    assert_eq!(
+        parse_maintenance_notice(&pattern, "ann restarted her router", now),
+        None
+    );
+}
+
+#[test]
+fn custom_patterns_use_named_groups() {
+    let pattern = Regex::new(r"^Downtime at (?P<at>\d+) until (?P<until>\d+)$").unwrap();
+    assert_eq!(
+        parse_maintenance_notice(
+            &pattern,
+            "Downtime at 1714980000 until 1714983600",
+            Utc::now()
+        ),
+        Some(MaintenanceNotice {
+            scheduled_at: DateTime::from_timestamp(1_714_980_000, 0),
+            until: DateTime::from_timestamp(1_714_983_600, 0),
+        })
+    );
+}
@@ -0,0 +1,263 @@
+#![cfg(feature = "mastodon")]
+
+mod common;
+
+use common::{field, next_event, serve_http};
+
+use chrono::Utc;
+use futures_util::SinkExt;
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionEvent, MastodonConnection, Scope, SendOutcome,
+        StatusEvent, UserEvent,
+    },
+    ChannelType, Connection, FieldValue, Message, MessageFragment, MessageStatus, MessageType,
+    TextStyle,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{Request, Response},
+    Message as WsMessage,
+};
+
+fn status(id: &str, account_id: &str, acct: &str, content: &str) -> Value {
+    json!({
+        "id": id,
+        "created_at": "2024-05-06T07:08:09.000Z",
+        "visibility": "direct",
+        "spoiler_text": "",
+        "content": content,
+        "account": { "id": account_id, "acct": acct },
+        "mentions": [{ "id": "1", "acct": "me", "url": "http://127.0.0.1/@me" }],
+        "media_attachments": [],
+        "in_reply_to_id": null,
+    })
+}
+
+fn conversation(last_status: Value) -> Value {
+    json!({
+        "id": "c1",
+        "unread": true,
+        "accounts": [{ "id": "2", "acct": "friend@remote.example" }],
+        "last_status": last_status,
+    })
+}
+
+/// A fake REST API that answers the connect-time calls and reports posted statuses on
+/// `posted`.
+async fn serve_api(listener: TcpListener, stream_port: u16, posted: mpsc::UnboundedSender<Value>) {
+    serve_http(listener, move |request: common::Request| {
+        let posted = posted.clone();
+        async move {
+            assert_eq!(request.header("authorization"), Some("Bearer token"));
+            let path = request.path().split('?').next().unwrap_or_default();
+            common::Response::json(match path {
+                "/api/v1/accounts/verify_credentials" => {
+                    json!({ "id": "1", "acct": "me", "display_name": "Me" })
+                }
+                "/api/v1/instance" => json!({
+                    "urls": { "streaming_api": format!("ws://127.0.0.1:{}", stream_port) },
+                    "configuration": { "statuses": { "max_characters": 1000 } }
+                }),
+                "/api/v1/conversations" => json!([conversation(status(
+                    "100",
+                    "2",
+                    "friend@remote.example",
+                    "<p>first</p>"
+                ))]),
+                "/api/v1/statuses" => {
+                    let _ = posted.send(serde_json::from_str(&request.body).unwrap());
+                    json!({ "id": "102" })
+                }
+                _ => json!({ "error": "Record not found" }),
+            })
+        }
+    })
+    .await
+}
+
+fn frame(event: &str, payload: String) -> WsMessage {
+    WsMessage::Text(
+        json!({ "stream": ["direct"], "event": event, "payload": payload })
+            .to_string()
+            .into(),
+    )
+}
+
+#[tokio::test]
+#[allow(clippy::result_large_err)]
+async fn mastodon_streams_direct_conversations() {
+    let api = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let api_port = api.local_addr().unwrap().port();
+    let streams = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stream_port = streams.local_addr().unwrap().port();
+    let (posted_tx, mut posted) = mpsc::unbounded_channel();
+    tokio::spawn(serve_api(api, stream_port, posted_tx));
+
+    let mut conn = MastodonConnection::new();
+    conn.set_auth(vec![
+        field(
+            "instance",
+            FieldValue::Text(Some(format!("http://127.0.0.1:{}", api_port))),
+        ),
+        field(
+            "access_token",
+            FieldValue::Password(Some("token".to_string())),
+        ),
+    ])
+    .unwrap();
+    let mut rx = conn.subscribe();
+    let (connected, (mut socket, uri)) = tokio::join!(conn.connect(), async {
+        let (socket, _) = streams.accept().await.unwrap();
+        let mut uri = String::new();
+        let socket = tokio_tungstenite::accept_hdr_async(socket, |request: &Request, response| {
+            uri = request.uri().to_string();
+            Ok::<Response, _>(response)
+        })
+        .await
+        .unwrap();
+        (socket, uri)
+    });
+    connected.unwrap();
+    assert_eq!(uri, "/api/v1/streaming?stream=direct&access_token=token");
+    assert_eq!(conn.account().unwrap().username.as_deref(), Some("me"));
+    assert_eq!(conn.capabilities().max_message_length, Some(1000));
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { artifact }
+        } if artifact.as_deref() == Some("127.0.0.1")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User {
+            event: UserEvent::Identify { user_id }
+        } if user_id == "1"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel }
+        } if channel.id == "c1"
+            && channel.channel_type == ChannelType::Direct
+            && channel.name.as_deref() == Some("friend@remote.example")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Join { channel_id }
+        } if channel_id == "c1"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message }
+        } if scope == Scope::channel("c1") && message.id.as_deref() == Some("100")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+
+    let html = concat!(
+        r#"<p><span class="h-card"><a href="http://127.0.0.1/@me" class="u-url mention">"#,
+        r#"@<span>me</span></a></span> hi <strong>there</strong></p>"#
+    );
+    socket
+        .send(frame(
+            "conversation",
+            conversation(status("101", "2", "friend@remote.example", html)).to_string(),
+        ))
+        .await
+        .unwrap();
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel("c1"));
+            assert_eq!(message.id.as_deref(), Some("101"));
+            assert_eq!(message.sender_id.as_deref(), Some("2"));
+            assert_eq!(message.timestamp.timestamp(), 1714979289);
+            assert_eq!(
+                message.content,
+                vec![
+                    MessageFragment::Mention {
+                        user_id: "1".to_string(),
+                        display: "me".to_string(),
+                    },
+                    MessageFragment::Text(" hi ".to_string()),
+                    MessageFragment::Styled {
+                        text: "there".to_string(),
+                        styles: vec![TextStyle::Bold],
+                    },
+                ]
+            );
+        }
+        other => panic!("expected a status, got {:?}", other),
+    }
+
+    socket
+        .send(frame("delete", "101".to_string()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::Remove { scope, message_id }
+        } if scope == Scope::channel("c1") && message_id == "101"
+    ));
+
+    let handle = conn
+        .send_tracked(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel("c1"),
+                message: Message {
+                    id: None,
+                    sender_id: None,
+                    content: vec![MessageFragment::Text("see you".to_string())],
+                    timestamp: Utc::now(),
+                    message_type: MessageType::Normal,
+                    status: MessageStatus::Sent,
+                    reactions: Vec::new(),
+                    reply_to: None,
+                    thread_id: None,
+                    extra: HashMap::new(),
+                },
+            },
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        handle.await,
+        SendOutcome::Delivered {
+            message_id: Some("102".to_string())
+        }
+    );
+    assert_eq!(
+        posted.recv().await.unwrap(),
+        json!({
+            "status": "@friend@remote.example see you",
+            "visibility": "direct",
+            "in_reply_to_id": "101",
+        })
+    );
+
+    socket.close(None).await.unwrap();
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { .. }
+        }
+    ));
+}
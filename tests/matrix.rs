@@ -0,0 +1,288 @@
+#![cfg(feature = "matrix")]
+
+mod common;
+
+use common::{field, next_event, serve_http, Request, Response};
+
+use chrono::Utc;
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionEvent, MatrixConnection, Scope, StatusEvent, UserEvent,
+    },
+    ChannelType, Connection, FieldValue, Message, MessageFragment, MessageStatus, MessageType,
+    ReactionKey,
+};
+use serde_json::{json, Value};
+use std::{collections::HashMap, time::Duration};
+use tokio::{net::TcpListener, sync::mpsc};
+
+fn first_sync() -> Value {
+    json!({
+        "next_batch": "s1",
+        "account_data": { "events": [
+            { "type": "m.direct", "content": { "@friend:test": ["!dm:test"] } }
+        ]},
+        "rooms": {
+            "join": {
+                "!room:test": {
+                    "state": { "events": [
+                        { "type": "m.room.name", "state_key": "", "sender": "@mod:test",
+                          "content": { "name": "Lobby" } },
+                        { "type": "m.room.topic", "state_key": "", "sender": "@mod:test",
+                          "content": { "topic": "hello" } }
+                    ]},
+                    "timeline": { "events": [
+                        { "type": "m.room.member", "state_key": "@guest:test",
+                          "sender": "@guest:test", "event_id": "$m",
+                          "content": { "membership": "join", "displayname": "Guest",
+                                       "avatar_url": "mxc://test/abc" } },
+                        { "type": "m.room.message", "sender": "@guest:test", "event_id": "$1",
+                          "origin_server_ts": 1714979289000_i64,
+                          "content": { "msgtype": "m.text", "body": "> <@me:test> hi\n\nhey",
+                                       "m.relates_to": { "m.in_reply_to": { "event_id": "$0" } } } },
+                        { "type": "m.room.message", "sender": "@guest:test", "event_id": "$2",
+                          "origin_server_ts": 1714979290000_i64,
+                          "content": { "msgtype": "m.image", "body": "cat.png",
+                                       "url": "mxc://test/cat", "info": { "mimetype": "image/png" } } },
+                        { "type": "m.room.message", "sender": "@guest:test", "event_id": "$3",
+                          "origin_server_ts": 1714979291000_i64,
+                          "content": { "msgtype": "m.text", "body": "* hey!",
+                                       "m.new_content": { "msgtype": "m.text", "body": "hey!" },
+                                       "m.relates_to": { "rel_type": "m.replace", "event_id": "$1" } } },
+                        { "type": "m.reaction", "sender": "@guest:test", "event_id": "$4",
+                          "content": { "m.relates_to": { "rel_type": "m.annotation",
+                                                         "event_id": "$2", "key": "👍" } } },
+                        { "type": "m.room.redaction", "sender": "@guest:test", "event_id": "$5",
+                          "redacts": "$2", "content": {} },
+                        { "type": "m.room.member", "state_key": "@guest:test",
+                          "sender": "@guest:test", "event_id": "$6",
+                          "content": { "membership": "leave" } }
+                    ]},
+                    "ephemeral": { "events": [
+                        { "type": "m.typing", "content": { "user_ids": ["@mod:test", "@me:test"] } }
+                    ]}
+                },
+                "!dm:test": {
+                    "state": { "events": [] },
+                    "timeline": { "events": [] }
+                }
+            },
+            "invite": {
+                "!new:test": { "invite_state": { "events": [
+                    { "type": "m.room.name", "state_key": "", "sender": "@friend:test",
+                      "content": { "name": "Secret" } },
+                    { "type": "m.room.member", "state_key": "@me:test", "sender": "@friend:test",
+                      "content": { "membership": "invite" } }
+                ]}}
+            }
+        }
+    })
+}
+
+/// A fake homeserver that answers whoami, serves `first_sync` once, holds later syncs open and
+/// reports every other request on `requests`.
+async fn serve(listener: TcpListener, requests: mpsc::UnboundedSender<(String, String)>) {
+    serve_http(listener, move |request: Request| {
+        let requests = requests.clone();
+        async move {
+            let line = request.line;
+            Response::json(if line.contains("/account/whoami") {
+                json!({ "user_id": "@me:test" })
+            } else if line.contains("/sync") && line.contains("since=") {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                json!({ "next_batch": "s2" })
+            } else if line.contains("/sync") {
+                first_sync()
+            } else {
+                let _ = requests.send((line, request.body));
+                json!({ "event_id": "$sent" })
+            })
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn matrix_syncs_and_sends() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (requests_tx, mut requests) = mpsc::unbounded_channel();
+    tokio::spawn(serve(listener, requests_tx));
+
+    let mut conn = MatrixConnection::new();
+    conn.set_auth(vec![
+        field(
+            "homeserver",
+            FieldValue::Text(Some(format!("http://127.0.0.1:{}", port))),
+        ),
+        field(
+            "access_token",
+            FieldValue::Password(Some("token".to_string())),
+        ),
+    ])
+    .unwrap();
+    let mut rx = conn.subscribe();
+    conn.connect().await.unwrap();
+    assert_eq!(conn.user_id(), Some("@me:test"));
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { artifact }
+        } if artifact.as_deref() == Some("@me:test")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User {
+            event: UserEvent::Identify { .. }
+        }
+    ));
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel }
+        } if channel.id == "!dm:test" && channel.channel_type == ChannelType::Direct
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Join { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel }
+        } if channel.id == "!room:test"
+            && channel.name.as_deref() == Some("Lobby")
+            && channel.topic.as_deref() == Some("hello")
+            && channel.channel_type == ChannelType::Group
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Join { channel_id }
+        } if channel_id == "!room:test"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User {
+            event: UserEvent::New { scope, user }
+        } if scope == Scope::channel("!room:test")
+            && user.display_name.as_deref() == Some("Guest")
+            && user.picture.as_deref()
+                == Some(format!("http://127.0.0.1:{}/_matrix/media/v3/download/test/abc", port).as_str())
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. },
+        } => {
+            assert_eq!(message.id.as_deref(), Some("$1"));
+            assert_eq!(message.reply_to.as_deref(), Some("$0"));
+            assert_eq!(message.timestamp.timestamp(), 1714979289);
+            assert!(matches!(
+                &message.content[..],
+                [MessageFragment::Text(text)] if text == "hey"
+            ));
+        }
+        other => panic!("expected a message, got {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. }
+        } if matches!(
+            &message.content[..],
+            [MessageFragment::Image { url, mime }]
+                if url.ends_with("/download/test/cat") && mime == "image/png"
+        )
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::Update { message_id, new_message, .. }
+        } if message_id == "$1" && matches!(
+            &new_message.content[..],
+            [MessageFragment::Text(text)] if text == "hey!"
+        )
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::ReactionAdd { message_id, key: ReactionKey::Emoji(key), .. }
+        } if message_id == "$2" && key == "👍"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::Remove { message_id, .. }
+        } if message_id == "$2"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User {
+            event: UserEvent::Remove { user_id, .. }
+        } if user_id == "@guest:test"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User {
+            event: UserEvent::TypingStart { user_id, .. }
+        } if user_id == "@mod:test"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Request { channel, from }
+        } if channel.name.as_deref() == Some("Secret")
+            && from.id.as_deref() == Some("@friend:test")
+    ));
+
+    conn.send(ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("!room:test"),
+            message: Message {
+                id: None,
+                sender_id: None,
+                content: vec![MessageFragment::Text("waves".to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Action,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: Some("$1".to_string()),
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    })
+    .await
+    .unwrap();
+    let (line, body) = tokio::time::timeout(Duration::from_secs(5), requests.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(line.starts_with("PUT /_matrix/client/v3/rooms/!room:test/send/m.room.message/"));
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["msgtype"], "m.emote");
+    assert_eq!(body["body"], "waves");
+    assert_eq!(body["m.relates_to"]["m.in_reply_to"]["event_id"], "$1");
+
+    conn.send(ConnectionEvent::Channel {
+        event: ChannelEvent::Accept {
+            channel_id: "!new:test".to_string(),
+        },
+    })
+    .await
+    .unwrap();
+    let (line, _) = requests.recv().await.unwrap();
+    assert!(line.starts_with("POST /_matrix/client/v3/join/!new:test"));
+
+    conn.disconnect().await.unwrap();
+}
@@ -0,0 +1,40 @@
+use oshatori::{utils::mentions::parse_mentions, MessageFragment, Profile};
+
+fn user(id: &str, username: &str) -> Profile {
+    Profile {
+        id: Some(id.to_string()),
+        username: Some(username.to_string()),
+        ..Profile::default()
+    }
+}
+
+#[test]
+fn parses_known_mentions() {
+    let users = vec![user("1", "ann"), user("2", "annabel")];
+    let frags = parse_mentions("hi @Annabel and @ann, @annie", &users);
+    assert_eq!(
+        frags,
+        vec![
+            MessageFragment::Text("hi ".to_string()),
+            MessageFragment::Mention {
+                user_id: "2".to_string(),
+                display: "annabel".to_string(),
+            },
+            MessageFragment::Text(" and ".to_string()),
+            MessageFragment::Mention {
+                user_id: "1".to_string(),
+                display: "ann".to_string(),
+            },
+            MessageFragment::Text(", @annie".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn ignores_embedded_at_signs() {
+    let users = vec![user("1", "ann")];
+    assert_eq!(
+        parse_mentions("mail ann@ann.example", &users),
+        vec![MessageFragment::Text("mail ann@ann.example".to_string())]
+    );
+}
@@ -0,0 +1,209 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::chat;
+
+use oshatori::{
+    client::StateClient,
+    connection::{
+        ChatEvent, ConnectionEvent, EventMiddleware, MiddlewareConnection, MockConnection, Scope,
+        SendOutcome,
+    },
+    Connection, MessageFragment,
+};
+
+fn text_of(event: &ConnectionEvent) -> Option<String> {
+    match event {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. },
+        } => match message.content.first() {
+            Some(MessageFragment::Text(text)) => Some(text.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+struct Censor;
+
+impl EventMiddleware for Censor {
+    fn inbound(&self, mut event: ConnectionEvent) -> Option<ConnectionEvent> {
+        if let ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. },
+        } = &mut event
+        {
+            for fragment in message.content.iter_mut() {
+                if let MessageFragment::Text(text) = fragment {
+                    *text = text.replace("heck", "****");
+                }
+            }
+        }
+        Some(event)
+    }
+}
+
+struct DropSecrets;
+
+impl EventMiddleware for DropSecrets {
+    fn inbound(&self, event: ConnectionEvent) -> Option<ConnectionEvent> {
+        match text_of(&event) {
+            Some(text) if text.contains("secret") => None,
+            _ => Some(event),
+        }
+    }
+
+    fn outbound(&self, event: ConnectionEvent) -> Option<ConnectionEvent> {
+        self.inbound(event)
+    }
+}
+
+#[tokio::test]
+async fn middleware_connection_transforms_and_drops() {
+    let mut conn = MiddlewareConnection::new(MockConnection::new())
+        .with(Censor)
+        .with(DropSecrets);
+    let mut rx = conn.subscribe();
+    conn.connect().await.unwrap();
+
+    conn.send(chat(Scope::channel("general"), "what the heck"))
+        .await
+        .unwrap();
+    conn.send(chat(Scope::channel("general"), "my secret"))
+        .await
+        .unwrap();
+    let handle = conn
+        .send_tracked(chat(Scope::channel("general"), "another secret"))
+        .await
+        .unwrap();
+    assert!(matches!(handle.await, SendOutcome::Failed(_)));
+    conn.send(chat(Scope::channel("general"), "hello"))
+        .await
+        .unwrap();
+
+    let mut texts = Vec::new();
+    while let Ok(Some(event)) =
+        tokio::time::timeout(std::time::Duration::from_millis(100), rx.recv()).await
+    {
+        if let Some(text) = text_of(&event) {
+            texts.push(text);
+        }
+    }
+    assert_eq!(texts, vec!["what the ****", "hello"]);
+}
+
+#[tokio::test]
+async fn stateclient_applies_middleware() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    client.add_middleware(DropSecrets).await;
+
+    client
+        .process(&conn_id, chat(Scope::channel("general"), "public"))
+        .await;
+    client
+        .process(&conn_id, chat(Scope::channel("general"), "secret"))
+        .await;
+
+    let messages = client.get_messages(&conn_id, "general").await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(
+        messages[0].content,
+        vec![MessageFragment::Text("public".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn stateclient_processors_apply_middleware() {
+    use oshatori::connection::stamp;
+    use tokio::sync::mpsc;
+
+    let client = StateClient::new();
+    let first = client.track("mock").await;
+    let second = client.track("mock").await;
+    client.add_middleware(DropSecrets).await;
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let processor = client.spawn_processor(first.clone(), rx);
+    tx.send(chat(Scope::channel("general"), "public")).unwrap();
+    tx.send(chat(Scope::channel("general"), "secret")).unwrap();
+    drop(tx);
+    processor.await.unwrap();
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (envelopes, stamper) = stamp(&second, rx);
+    let processor = client.spawn_envelope_processor(envelopes);
+    tx.send(chat(Scope::channel("general"), "secret")).unwrap();
+    tx.send(chat(Scope::channel("general"), "public")).unwrap();
+    drop(tx);
+    stamper.await.unwrap();
+    processor.await.unwrap();
+
+    for conn_id in [&first, &second] {
+        let messages = client.get_messages(conn_id, "general").await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0].content,
+            vec![MessageFragment::Text("public".to_string())]
+        );
+    }
+}
+
+#[tokio::test]
+async fn low_bandwidth_strips_media_and_avatars() {
+    use oshatori::{
+        connection::{LowBandwidth, UserEvent},
+        Profile,
+    };
+
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    client.add_middleware(LowBandwidth).await;
+
+    let mut event = chat(Scope::channel("general"), "look");
+    if let ConnectionEvent::Chat {
+        event: ChatEvent::New { message, .. },
+    } = &mut event
+    {
+        message.content.push(MessageFragment::Image {
+            url: "https://example.com/cat.png".to_string(),
+            mime: "image/png".to_string(),
+        });
+        message
+            .content
+            .push(MessageFragment::Spoiler(vec![MessageFragment::Video {
+                url: "https://example.com/cat.mp4".to_string(),
+                mime: "video/mp4".to_string(),
+            }]));
+    }
+    client.process(&conn_id, event).await;
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::New {
+                    scope: Scope::Global,
+                    user: Profile {
+                        id: Some("1".to_string()),
+                        picture: Some("https://example.com/1.png".to_string()),
+                        ..Profile::default()
+                    },
+                },
+            },
+        )
+        .await;
+
+    let messages = client.get_messages(&conn_id, "general").await;
+    assert_eq!(
+        messages[0].content,
+        vec![
+            MessageFragment::Text("look".to_string()),
+            MessageFragment::Text("[image: https://example.com/cat.png]".to_string()),
+            MessageFragment::Spoiler(vec![MessageFragment::Text(
+                "[video: https://example.com/cat.mp4]".to_string()
+            )]),
+        ]
+    );
+    let user = client.get_user(&conn_id, "1").await.unwrap();
+    assert_eq!(user.picture, None);
+}
@@ -0,0 +1,295 @@
+#![cfg(feature = "minecraft")]
+
+mod common;
+
+use common::{field, next_event};
+
+use chrono::Utc;
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, MinecraftConnection,
+        ModerationEvent, Scope, StatusEvent, UserEvent,
+    },
+    Connection, FieldValue, Message, MessageFragment, MessageStatus, MessageType,
+};
+use std::{collections::HashMap, io::Write};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+fn text(value: &str) -> FieldValue {
+    FieldValue::Text(Some(value.to_string()))
+}
+
+async fn write_packet(stream: &mut TcpStream, id: i32, kind: i32, body: &str) {
+    let mut packet = (body.len() as i32 + 10).to_le_bytes().to_vec();
+    packet.extend_from_slice(&id.to_le_bytes());
+    packet.extend_from_slice(&kind.to_le_bytes());
+    packet.extend_from_slice(body.as_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    stream.write_all(&packet).await.unwrap();
+}
+
+/// A fake RCON server with the password `hunter2`. It answers `list` with two players and
+/// reports every other command it runs on `commands`.
+async fn serve_rcon(listener: TcpListener, commands: mpsc::UnboundedSender<String>) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    loop {
+        let Ok(length) = stream.read_i32_le().await else {
+            return;
+        };
+        let id = stream.read_i32_le().await.unwrap();
+        let kind = stream.read_i32_le().await.unwrap();
+        let mut body = vec![0; length as usize - 8];
+        stream.read_exact(&mut body).await.unwrap();
+        body.truncate(length as usize - 10);
+        let body = String::from_utf8(body).unwrap();
+        match (kind, body.as_str()) {
+            (3, "hunter2") => write_packet(&mut stream, id, 2, "").await,
+            (3, _) => write_packet(&mut stream, -1, 2, "").await,
+            (2, "list") => {
+                let reply = "There are 2 of a max of 20 players online: Steve, §aAlex";
+                write_packet(&mut stream, id, 0, reply).await;
+            }
+            (2, "seed") => {
+                write_packet(&mut stream, id, 0, "Seed: [42]").await;
+                let _ = commands.send(body);
+            }
+            (2, _) => {
+                write_packet(&mut stream, id, 0, "").await;
+                let _ = commands.send(body);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn message(content: &str) -> Message {
+    Message {
+        id: None,
+        sender_id: None,
+        content: vec![MessageFragment::Text(content.to_string())],
+        timestamp: Utc::now(),
+        message_type: MessageType::Normal,
+        status: MessageStatus::Sent,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
+    }
+}
+
+#[tokio::test]
+async fn minecraft_tails_log_and_sends_over_rcon() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (commands_tx, mut commands) = mpsc::unbounded_channel();
+    tokio::spawn(serve_rcon(listener, commands_tx));
+    let log = std::env::temp_dir().join(format!("oshatori-minecraft-{}.log", std::process::id()));
+    std::fs::write(
+        &log,
+        "[09:00:00] [Server thread/INFO]: <Steve> before we started\n",
+    )
+    .unwrap();
+
+    let mut conn = MinecraftConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        field("rcon_address", text(&format!("127.0.0.1:{}", port))),
+        field(
+            "rcon_password",
+            FieldValue::Password(Some("hunter2".to_string())),
+        ),
+        field(
+            "log_path",
+            FieldValue::File(Some(log.to_string_lossy().into_owned())),
+        ),
+        field("name", text("Bridge")),
+    ])
+    .unwrap();
+    conn.connect().await.unwrap();
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::Identify { user_id } } if user_id == "Bridge"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::New { channel } } if channel.id == "minecraft"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::Join { channel_id } } if channel_id == "minecraft"
+    ));
+    for name in ["Steve", "Alex"] {
+        assert!(matches!(
+            next_event(&mut rx).await,
+            ConnectionEvent::User { event: UserEvent::New { user, .. } }
+                if user.id.as_deref() == Some(name)
+        ));
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+
+    let mut file = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+    write!(
+        file,
+        concat!(
+            "[09:00:01] [Server thread/INFO]: [Not Secure] <Alex> hello §cthere\n",
+            "[09:00:02] [Server thread/WARN]: <Alex> not chat\n",
+            "[09:00:03 INFO]: * Steve waves\n",
+            "[09:00:04] [Server thread/INFO]: [Server] restarting soon\n",
+            "[09:00:05] [Server thread/INFO]: Notch joined the game\n",
+            "[09:00:06] [Server thread/INFO]: Alex left the game\n",
+        )
+    )
+    .unwrap();
+    drop(file);
+
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel("minecraft"));
+            assert_eq!(message.sender_id.as_deref(), Some("Alex"));
+            assert_eq!(
+                message.content,
+                vec![MessageFragment::Text("hello there".to_string())]
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat { event: ChatEvent::New { message, .. } }
+            if message.message_type == MessageType::Action
+                && message.sender_id.as_deref() == Some("Steve")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat { event: ChatEvent::New { message, .. } }
+            if message.message_type == MessageType::Server && message.sender_id.is_none()
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::New { user, .. } }
+            if user.id.as_deref() == Some("Notch")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::Remove { user_id, .. } } if user_id == "Alex"
+    ));
+
+    conn.send(ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("minecraft"),
+            message: message("hi \"all\""),
+        },
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        commands.recv().await.unwrap(),
+        r#"tellraw @a ["","<Bridge> ","hi \"all\""]"#
+    );
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat { event: ChatEvent::New { message, .. } }
+            if message.message_type == MessageType::CurrentUser
+                && message.sender_id.as_deref() == Some("Bridge")
+    ));
+
+    conn.send(ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("minecraft"),
+            message: message("/seed"),
+        },
+    })
+    .await
+    .unwrap();
+    assert_eq!(commands.recv().await.unwrap(), "seed");
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat { event: ChatEvent::New { message, .. } }
+            if message.message_type == MessageType::CurrentUser
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. },
+        } => {
+            assert_eq!(message.message_type, MessageType::Server);
+            assert_eq!(
+                message.content,
+                vec![MessageFragment::Text("Seed: [42]".to_string())]
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+
+    conn.send(ConnectionEvent::Moderation {
+        event: ModerationEvent::Kick {
+            scope: Scope::channel("minecraft"),
+            user_id: "Notch".to_string(),
+            reason: Some("griefing".to_string()),
+        },
+    })
+    .await
+    .unwrap();
+    assert_eq!(commands.recv().await.unwrap(), "kick Notch griefing");
+
+    conn.disconnect().await.unwrap();
+    let _ = std::fs::remove_file(&log);
+}
+
+#[tokio::test]
+async fn minecraft_wrong_password_fails_auth() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (commands_tx, _commands) = mpsc::unbounded_channel();
+    tokio::spawn(serve_rcon(listener, commands_tx));
+
+    let mut conn = MinecraftConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        field("rcon_address", text(&format!("127.0.0.1:{}", port))),
+        field(
+            "rcon_password",
+            FieldValue::Password(Some("wrong".to_string())),
+        ),
+    ])
+    .unwrap();
+    assert!(matches!(
+        conn.connect().await,
+        Err(ConnectionError::Auth(_))
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed { .. }
+        }
+    ));
+}
@@ -2,11 +2,13 @@
 
 use chrono::Utc;
 use oshatori::{
-    connection::{ChatEvent, ConnectionEvent, MockConnection},
-    Connection, Message, MessageFragment, MessageStatus, MessageType,
+    connection::{ChatEvent, ConnectionEvent, ConnectionGroup, MockConnection, Scope, SendOutcome},
+    Connection, ConnectionError, Message, MessageFragment, MessageStatus, MessageType,
 };
+use std::collections::HashMap;
 
 #[tokio::test]
+#[allow(clippy::get_first, clippy::single_match, clippy::collapsible_match)]
 async fn test_mock_connection_integration() {
     let mut conn = MockConnection::new();
     let mut rx = conn.subscribe();
@@ -18,11 +20,15 @@ async fn test_mock_connection_integration() {
         timestamp: Utc::now(),
         message_type: MessageType::Normal,
         status: MessageStatus::Sent,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
     };
 
     conn.send(ConnectionEvent::Chat {
         event: ChatEvent::New {
-            channel_id: None,
+            scope: Scope::Global,
             message: test_message.clone(),
         },
     })
@@ -32,12 +38,8 @@ async fn test_mock_connection_integration() {
     let received = rx.recv().await.expect("failed to receive");
 
     if let ConnectionEvent::Chat { event } = received {
-        if let ChatEvent::New {
-            channel_id,
-            message,
-        } = event
-        {
-            assert_eq!(channel_id, None);
+        if let ChatEvent::New { scope, message } = event {
+            assert_eq!(scope, Scope::Global);
             match message.content.get(0) {
                 Some(fragment) => match fragment {
                     MessageFragment::Text(value) => {
@@ -54,3 +56,186 @@ async fn test_mock_connection_integration() {
         panic!("unexpected connection event");
     }
 }
+
+#[tokio::test]
+async fn test_mock_connection_closed() {
+    let mut conn = MockConnection::new();
+    drop(conn.subscribe());
+
+    let result = conn
+        .send(ConnectionEvent::Chat {
+            event: ChatEvent::Remove {
+                scope: Scope::Global,
+                message_id: "1".to_string(),
+            },
+        })
+        .await;
+
+    assert_eq!(result, Err(ConnectionError::Closed));
+}
+
+#[tokio::test]
+async fn test_mock_connection_status() {
+    use oshatori::client::ConnectionStatus;
+
+    let mut conn = MockConnection::new();
+    assert_eq!(conn.status(), ConnectionStatus::Disconnected);
+    conn.connect().await.unwrap();
+    assert_eq!(conn.status(), ConnectionStatus::Connected);
+    conn.disconnect().await.unwrap();
+    assert_eq!(conn.status(), ConnectionStatus::Disconnected);
+    assert!(matches!(
+        conn.search_users("anyone").await,
+        Err(ConnectionError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_mock_connection_capabilities() {
+    let conn = MockConnection::new();
+    let capabilities = conn.capabilities();
+
+    assert!(capabilities.deletion);
+    assert!(capabilities.multiple_channels);
+    assert!(!capabilities.history);
+}
+
+#[tokio::test]
+async fn test_mock_connection_send_tracked() {
+    let mut conn = MockConnection::new();
+    let mut rx = conn.subscribe();
+
+    let handle = conn
+        .send_tracked(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::Global,
+                message: Message {
+                    id: None,
+                    sender_id: None,
+                    content: vec![MessageFragment::Text("tracked".to_string())],
+                    timestamp: Utc::now(),
+                    message_type: MessageType::CurrentUser,
+                    status: MessageStatus::Sent,
+                    reactions: Vec::new(),
+                    reply_to: None,
+                    thread_id: None,
+                    extra: HashMap::new(),
+                },
+            },
+        })
+        .await
+        .unwrap();
+
+    assert!(!handle.correlation_id().is_empty());
+    assert_eq!(handle.await, SendOutcome::Delivered { message_id: None });
+    assert!(rx.recv().await.is_some());
+}
+
+#[tokio::test]
+async fn test_mock_connection_group_send() {
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    let mut first = MockConnection::new();
+    let mut first_rx = first.subscribe();
+    let mut second = MockConnection::new();
+    drop(second.subscribe());
+
+    let mut group = ConnectionGroup::new("announcements");
+    group.add("first", Scope::channel("news"), Arc::new(Mutex::new(first)));
+    group.add(
+        "second",
+        Scope::channel("news"),
+        Arc::new(Mutex::new(second)),
+    );
+
+    let results = group
+        .send(Message {
+            id: None,
+            sender_id: None,
+            content: vec![MessageFragment::Text("release!".to_string())],
+            timestamp: Utc::now(),
+            message_type: MessageType::CurrentUser,
+            status: MessageStatus::Sent,
+            reactions: Vec::new(),
+            reply_to: None,
+            thread_id: None,
+            extra: HashMap::new(),
+        })
+        .await;
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].scope, Scope::channel("news"));
+    assert_eq!(results[0].result, Ok(()));
+    assert_eq!(results[1].result, Err(ConnectionError::Closed));
+    assert!(matches!(
+        first_rx.recv().await,
+        Some(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::Channel(_),
+                ..
+            }
+        })
+    ));
+}
+
+#[tokio::test]
+async fn test_mock_connection_open_direct() {
+    use oshatori::{connection::ChannelEvent, ChannelType};
+
+    let mut conn = MockConnection::new();
+    let mut rx = conn.subscribe();
+
+    let channel = conn.open_direct("42").await.unwrap();
+    assert_eq!(channel.channel_type, ChannelType::Direct);
+    match rx.recv().await {
+        Some(ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel: announced },
+        }) => assert_eq!(announced.id, channel.id),
+        other => panic!("unexpected event: {:?}", other),
+    }
+}
+
+#[test]
+fn test_extra_metadata_roundtrip() {
+    use oshatori::{Channel, Profile};
+
+    let json = r#"{"id":"general","name":null,"channel_type":"Group"}"#;
+    let channel: Channel = serde_json::from_str(json).unwrap();
+    assert!(channel.extra.is_empty());
+
+    let mut profile = Profile::default();
+    profile.extra.insert(
+        "permalink".to_string(),
+        serde_json::json!("https://example.com/u/1"),
+    );
+    let back: Profile = serde_json::from_str(&serde_json::to_string(&profile).unwrap()).unwrap();
+    assert_eq!(back.extra, profile.extra);
+}
+
+#[test]
+fn test_profile_roles() {
+    use oshatori::{Permissions, Profile, Role};
+
+    let legacy: Profile = serde_json::from_str(
+        r#"{"id":"1","username":"ann","display_name":null,"color":null,"picture":null}"#,
+    )
+    .unwrap();
+    assert_eq!(legacy.role, None);
+
+    let profile = Profile {
+        role: Some(Role {
+            rank: 5,
+            permissions: Permissions {
+                can_moderate: true,
+                can_create_channels: true,
+                ..Permissions::default()
+            },
+            name: Some("Admin".to_string()),
+            color: None,
+        }),
+        ..legacy
+    };
+    let back: Profile = serde_json::from_str(&serde_json::to_string(&profile).unwrap()).unwrap();
+    assert_eq!(back.role, profile.role);
+}
@@ -0,0 +1,270 @@
+#![cfg(feature = "mqtt")]
+
+mod common;
+
+use common::{next_event, text_field};
+
+use chrono::Utc;
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, MqttConnection, Scope,
+        StatusEvent, UserEvent,
+    },
+    Connection, Message, MessageFragment, MessageStatus, MessageType,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+async fn read_packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+    let header = stream.read_u8().await.ok()?;
+    let (mut length, mut shift) = (0usize, 0);
+    loop {
+        let byte = stream.read_u8().await.ok()?;
+        length |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await.ok()?;
+    Some((header, body))
+}
+
+async fn write_packet(stream: &mut TcpStream, header: u8, body: &[u8]) {
+    let mut packet = vec![header, body.len() as u8];
+    packet.extend_from_slice(body);
+    stream.write_all(&packet).await.unwrap();
+}
+
+fn string(value: &str) -> Vec<u8> {
+    let mut bytes = (value.len() as u16).to_be_bytes().to_vec();
+    bytes.extend_from_slice(value.as_bytes());
+    bytes
+}
+
+fn strings(mut body: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    while body.len() >= 2 {
+        let length = u16::from_be_bytes([body[0], body[1]]) as usize;
+        strings.push(String::from_utf8(body[2..2 + length].to_vec()).unwrap());
+        body = &body[2 + length..];
+    }
+    strings
+}
+
+async fn publish(stream: &mut TcpStream, topic: &str, retain: bool, payload: &str) {
+    let mut body = string(topic);
+    body.extend_from_slice(payload.as_bytes());
+    write_packet(stream, 0x30 | retain as u8, &body).await;
+}
+
+/// A fake broker for user `bot` with password `hunter2`. After the subscription it sends a
+/// retained reading and two chat messages, then echoes anything the client publishes and
+/// reports its topic and payload on `published`.
+async fn serve(listener: TcpListener, published: mpsc::UnboundedSender<(String, Value)>) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    while let Some((header, body)) = read_packet(&mut stream).await {
+        match header >> 4 {
+            1 => {
+                // Protocol name, level, flags and keep-alive come before the client id.
+                let fields = strings(&body[10..]);
+                let accepted = fields.get(1..) == Some(&["bot".to_string(), "hunter2".to_string()]);
+                write_packet(&mut stream, 0x20, &[0, if accepted { 0 } else { 5 }]).await;
+            }
+            8 => {
+                // Grants the first two filters and refuses the third.
+                let suback = [body[0], body[1], 0, 0, 0x80];
+                write_packet(&mut stream, 0x90, &suback).await;
+                publish(&mut stream, "sensors/kitchen", true, r#"{"temp":21.5}"#).await;
+                publish(
+                    &mut stream,
+                    "chat/lobby",
+                    false,
+                    r#"{"id":"m1","sender_id":"alice","text":"hi","timestamp":"2024-01-01T00:00:00Z"}"#,
+                )
+                .await;
+                publish(&mut stream, "chat/lobby", false, "plain words").await;
+            }
+            3 => {
+                let length = u16::from_be_bytes([body[0], body[1]]) as usize;
+                let topic = String::from_utf8(body[2..2 + length].to_vec()).unwrap();
+                let payload = serde_json::from_slice(&body[2 + length..]).unwrap();
+                let _ = published.send((topic, payload));
+                write_packet(&mut stream, header, &body).await;
+            }
+            14 => return,
+            _ => {}
+        }
+    }
+}
+
+#[tokio::test]
+async fn mqtt_maps_topics_to_channels() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (published_tx, mut published) = mpsc::unbounded_channel();
+    tokio::spawn(serve(listener, published_tx));
+
+    let mut conn = MqttConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("broker", &format!("mqtt://127.0.0.1:{}", port)),
+        text_field("topics", "chat/lobby, sensors/+ forbidden/#"),
+        text_field("username", "bot"),
+        text_field("password", "hunter2"),
+        text_field("client_id", "dash"),
+    ])
+    .unwrap();
+    conn.connect().await.unwrap();
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::Identify { user_id } } if user_id == "dash"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::New { channel } } if channel.id == "chat/lobby"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::Join { channel_id } } if channel_id == "chat/lobby"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status { event: StatusEvent::Error { message } } if message.contains("forbidden/#")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::New { channel } } if channel.id == "sensors/kitchen"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::Join { channel_id } } if channel_id == "sensors/kitchen"
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel("sensors/kitchen"));
+            assert_eq!(
+                message.content,
+                vec![MessageFragment::Text(r#"{"temp":21.5}"#.to_string())]
+            );
+            assert_eq!(message.extra["payload"], json!({ "temp": 21.5 }));
+            assert_eq!(message.extra["retained"], json!(true));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel("chat/lobby"));
+            assert_eq!(message.id.as_deref(), Some("m1"));
+            assert_eq!(message.sender_id.as_deref(), Some("alice"));
+            assert_eq!(message.timestamp.to_rfc3339(), "2024-01-01T00:00:00+00:00");
+            assert_eq!(
+                message.content,
+                vec![MessageFragment::Text("hi".to_string())]
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat { event: ChatEvent::New { message, .. } }
+            if message.content == vec![MessageFragment::Text("plain words".to_string())]
+                && message.sender_id.is_none()
+    ));
+
+    conn.send(ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("chat/lobby"),
+            message: Message {
+                id: Some("m2".to_string()),
+                sender_id: None,
+                content: vec![MessageFragment::Text("hello".to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: None,
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    })
+    .await
+    .unwrap();
+    let (topic, payload) = published.recv().await.unwrap();
+    assert_eq!(topic, "chat/lobby");
+    assert_eq!(payload["id"], "m2");
+    assert_eq!(payload["sender_id"], "dash");
+    assert_eq!(payload["text"], "hello");
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat { event: ChatEvent::New { message, .. } }
+            if message.message_type == MessageType::CurrentUser
+    ));
+
+    conn.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn mqtt_wrong_password_fails_auth() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (published_tx, _published) = mpsc::unbounded_channel();
+    tokio::spawn(serve(listener, published_tx));
+
+    let mut conn = MqttConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("broker", &format!("mqtt://127.0.0.1:{}", port)),
+        text_field("topics", "chat/lobby"),
+        text_field("username", "bot"),
+        text_field("password", "wrong"),
+    ])
+    .unwrap();
+    assert!(matches!(
+        conn.connect().await,
+        Err(ConnectionError::Auth(_))
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed { .. }
+        }
+    ));
+}
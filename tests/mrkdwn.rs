@@ -0,0 +1,74 @@
+use oshatori::{
+    utils::mrkdwn::{parse_mrkdwn, render_mrkdwn},
+    MessageFragment, TextStyle,
+};
+
+#[test]
+fn parses_styles_and_entities() {
+    assert_eq!(
+        parse_mrkdwn(
+            "hi *bold _both_* <@U1|ann> in <#C1|general> <https://x.y/?a=1&amp;b|site> &lt;3 a*b*c"
+        ),
+        vec![
+            MessageFragment::Text("hi ".to_string()),
+            MessageFragment::Styled {
+                text: "bold ".to_string(),
+                styles: vec![TextStyle::Bold],
+            },
+            MessageFragment::Styled {
+                text: "both".to_string(),
+                styles: vec![TextStyle::Bold, TextStyle::Italic],
+            },
+            MessageFragment::Text(" ".to_string()),
+            MessageFragment::Mention {
+                user_id: "U1".to_string(),
+                display: "ann".to_string(),
+            },
+            MessageFragment::Text(" in ".to_string()),
+            MessageFragment::Text("#general".to_string()),
+            MessageFragment::Text(" ".to_string()),
+            MessageFragment::Url("https://x.y/?a=1&b".to_string()),
+            MessageFragment::Text(" <3 a*b*c".to_string()),
+        ]
+    );
+    assert_eq!(
+        parse_mrkdwn("<!here> ~gone~"),
+        vec![
+            MessageFragment::Text("@here".to_string()),
+            MessageFragment::Text(" ".to_string()),
+            MessageFragment::Styled {
+                text: "gone".to_string(),
+                styles: vec![TextStyle::Strike],
+            },
+        ]
+    );
+}
+
+#[test]
+fn parses_code_verbatim() {
+    assert_eq!(
+        parse_mrkdwn("run `*not bold*` or\n```\nlet x = 1 &amp;&amp; 2;\n```"),
+        vec![
+            MessageFragment::Text("run ".to_string()),
+            MessageFragment::Code {
+                lang: None,
+                body: "*not bold*".to_string(),
+            },
+            MessageFragment::Text(" or\n".to_string()),
+            MessageFragment::Code {
+                lang: None,
+                body: "let x = 1 && 2;".to_string(),
+            },
+        ]
+    );
+    assert_eq!(
+        parse_mrkdwn("* not a list *"),
+        vec![MessageFragment::Text("* not a list *".to_string())]
+    );
+}
+
+#[test]
+fn renders_back_to_mrkdwn() {
+    let source = "a *b* _i_ <@U1> <https://x.y> `c` 1 &lt; 2";
+    assert_eq!(render_mrkdwn(&parse_mrkdwn(source)), source);
+}
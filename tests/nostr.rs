@@ -0,0 +1,280 @@
+#![cfg(feature = "nostr")]
+
+mod common;
+
+use common::{field, next_event};
+
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionEvent, NostrConnection, NostrKeys, Scope, SendOutcome,
+        StatusEvent, UserEvent,
+    },
+    ChannelType, Connection, FieldValue, Message, MessageFragment, MessageStatus, MessageType,
+};
+use serde_json::{json, Value};
+use std::{collections::HashMap, time::Duration};
+use tokio::net::TcpListener;
+use tokio_tungstenite::{tungstenite::Message as WsMessage, WebSocketStream};
+
+async fn next_frame(socket: &mut WebSocketStream<tokio::net::TcpStream>) -> Value {
+    let frame = tokio::time::timeout(Duration::from_secs(5), socket.next())
+        .await
+        .expect("timed out waiting for a frame")
+        .unwrap()
+        .unwrap();
+    serde_json::from_str(frame.to_text().unwrap()).unwrap()
+}
+
+async fn relay(socket: &mut WebSocketStream<tokio::net::TcpStream>, frame: Value) {
+    socket
+        .send(WsMessage::Text(frame.to_string().into()))
+        .await
+        .unwrap();
+}
+
+fn text_message(text: &str, reply_to: Option<&str>) -> Message {
+    Message {
+        id: None,
+        sender_id: None,
+        content: vec![MessageFragment::Text(text.to_string())],
+        timestamp: Utc::now(),
+        message_type: MessageType::Normal,
+        status: MessageStatus::Sent,
+        reactions: Vec::new(),
+        reply_to: reply_to.map(str::to_string),
+        thread_id: None,
+        extra: HashMap::new(),
+    }
+}
+
+#[test]
+fn nostr_keys_round_trip_encryption() {
+    let alice = NostrKeys::generate();
+    let bob = NostrKeys::generate();
+    let payload = alice.encrypt(bob.public_key(), "meet at noon").unwrap();
+    assert!(payload.contains("?iv="));
+    assert_eq!(
+        bob.decrypt(alice.public_key(), &payload).unwrap(),
+        "meet at noon"
+    );
+    assert!(NostrKeys::generate()
+        .decrypt(alice.public_key(), &payload)
+        .is_err());
+
+    assert!(alice.npub().starts_with("npub1"));
+    let parsed = NostrKeys::parse(&alice.secret_key()).unwrap();
+    assert_eq!(parsed.public_key(), alice.public_key());
+    assert!(NostrKeys::parse("nsec1notakey").is_err());
+}
+
+#[tokio::test]
+async fn nostr_maps_direct_messages_and_public_channels() {
+    let me = NostrKeys::generate();
+    let peer = NostrKeys::generate();
+    let lobby = peer.sign(
+        40,
+        json!([]),
+        r#"{"name":"lobby","about":"chat"}"#,
+        1_700_000_000,
+    );
+    let lobby_id = lobby["id"].as_str().unwrap().to_string();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let mut conn = NostrConnection::new();
+    conn.set_auth(vec![
+        field("secret_key", FieldValue::Key(Some(me.secret_key()))),
+        field(
+            "relays",
+            FieldValue::Text(Some(format!("ws://127.0.0.1:{}", port))),
+        ),
+        field("channels", FieldValue::Text(Some(lobby_id.clone()))),
+    ])
+    .unwrap();
+    let mut rx = conn.subscribe();
+    let (connected, accepted) = tokio::join!(conn.connect(), async {
+        let (socket, _) = listener.accept().await.unwrap();
+        tokio_tungstenite::accept_async(socket).await.unwrap()
+    });
+    connected.unwrap();
+    let mut socket = accepted;
+
+    assert_eq!(
+        next_frame(&mut socket).await,
+        json!(["REQ", "dm",
+            { "kinds": [4], "#p": [me.public_key()] },
+            { "kinds": [4], "authors": [me.public_key()] }])
+    );
+    assert_eq!(
+        next_frame(&mut socket).await,
+        json!(["REQ", format!("channel:{}", lobby_id),
+            { "kinds": [40], "ids": [lobby_id] },
+            { "kinds": [41, 42], "#e": [lobby_id] }])
+    );
+
+    let dm = peer.sign(
+        4,
+        json!([["p", me.public_key()]]),
+        &peer.encrypt(me.public_key(), "hi there").unwrap(),
+        1_700_000_100,
+    );
+    let mut forged = peer.sign(
+        42,
+        json!([["e", lobby_id, "", "root"]]),
+        "real",
+        1_700_000_150,
+    );
+    forged["content"] = json!("forged");
+    relay(&mut socket, json!(["EVENT", "dm", dm])).await;
+    relay(&mut socket, json!(["EVENT", "dm", dm])).await;
+    relay(
+        &mut socket,
+        json!(["EVENT", format!("channel:{}", lobby_id), forged]),
+    )
+    .await;
+    relay(&mut socket, json!(["EOSE", "dm"])).await;
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { artifact }
+        } if artifact == Some(me.npub())
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User {
+            event: UserEvent::Identify { user_id }
+        } if user_id == me.public_key()
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel }
+        } if channel.id == peer.public_key() && channel.channel_type == ChannelType::Direct
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Join { channel_id }
+        } if channel_id == peer.public_key()
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel(peer.public_key()));
+            assert_eq!(message.id.as_deref(), dm["id"].as_str());
+            assert_eq!(message.sender_id.as_deref(), Some(peer.public_key()));
+            assert_eq!(message.timestamp.timestamp(), 1_700_000_100);
+            assert_eq!(
+                message.content,
+                vec![MessageFragment::Text("hi there".to_string())]
+            );
+        }
+        other => panic!("expected a direct message, got {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+
+    let channel_message = peer.sign(
+        42,
+        json!([["e", lobby_id, "", "root"], ["e", dm["id"], "", "reply"]]),
+        "hello lobby",
+        1_700_000_200,
+    );
+    relay(&mut socket, json!(["EVENT", "channel", lobby])).await;
+    relay(&mut socket, json!(["EVENT", "channel", channel_message])).await;
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel }
+        } if channel.id == lobby_id
+            && channel.name.as_deref() == Some("lobby")
+            && channel.topic.as_deref() == Some("chat")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Join { channel_id }
+        } if channel_id == lobby_id
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message }
+        } if scope == Scope::channel(&lobby_id)
+            && message.reply_to.as_deref() == dm["id"].as_str()
+    ));
+
+    let handle = conn
+        .send_tracked(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel(peer.public_key()),
+                message: text_message("hi back", None),
+            },
+        })
+        .await
+        .unwrap();
+    let SendOutcome::Delivered {
+        message_id: Some(sent_id),
+    } = handle.await
+    else {
+        panic!("expected a delivered message id");
+    };
+    let published = next_frame(&mut socket).await;
+    assert_eq!(published[0], "EVENT");
+    let sent = published[1].clone();
+    assert_eq!(sent["id"], json!(sent_id));
+    assert_eq!(sent["kind"], 4);
+    assert_eq!(sent["tags"], json!([["p", peer.public_key()]]));
+    assert_eq!(
+        peer.decrypt(me.public_key(), sent["content"].as_str().unwrap())
+            .unwrap(),
+        "hi back"
+    );
+    relay(&mut socket, json!(["EVENT", "dm", sent])).await;
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message }
+        } if scope == Scope::channel(peer.public_key())
+            && message.id.as_deref() == Some(sent_id.as_str())
+            && message.content == vec![MessageFragment::Text("hi back".to_string())]
+    ));
+
+    conn.send(ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel(&lobby_id),
+            message: text_message("hello all", Some("abc")),
+        },
+    })
+    .await
+    .unwrap();
+    let published = next_frame(&mut socket).await;
+    assert_eq!(published[1]["kind"], 42);
+    assert_eq!(published[1]["content"], "hello all");
+    assert_eq!(
+        published[1]["tags"],
+        json!([["e", lobby_id, "", "root"], ["e", "abc", "", "reply"]])
+    );
+
+    socket.close(None).await.unwrap();
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { .. }
+        }
+    ));
+}
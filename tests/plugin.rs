@@ -0,0 +1,47 @@
+#![cfg(feature = "plugins")]
+
+use std::ffi::CStr;
+
+use oshatori::{
+    connection::{
+        plugin::{CORE_VERSION, PLUGIN_ABI_VERSION},
+        PluginError, PluginLoader, ProtocolRegistry,
+    },
+    export_plugin,
+};
+
+export_plugin!(|_registry: &mut ProtocolRegistry| {});
+
+#[test]
+fn exported_declaration_carries_c_version() {
+    assert_eq!(OSHATORI_PLUGIN.abi_version, PLUGIN_ABI_VERSION);
+    let version = unsafe { CStr::from_ptr(OSHATORI_PLUGIN.core_version) };
+    assert_eq!(version.to_str().unwrap(), CORE_VERSION);
+}
+
+#[test]
+fn plugin_loader_reports_missing_libraries() {
+    let mut loader = PluginLoader::new();
+    let mut registry = ProtocolRegistry::new();
+
+    let result = unsafe { loader.load("/nonexistent/libmissing.so", &mut registry) };
+    assert!(matches!(result, Err(PluginError::Load { .. })));
+    assert!(loader.loaded().is_empty());
+    assert!(registry.protocols().is_empty());
+}
+
+#[test]
+fn plugin_loader_skips_non_library_files() {
+    let dir = std::env::temp_dir().join(format!("oshatori-plugins-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("README.txt"), "not a plugin").unwrap();
+
+    let mut loader = PluginLoader::new();
+    let mut registry = ProtocolRegistry::new();
+    let added = unsafe { loader.load_dir(&dir, &mut registry) }.unwrap();
+    assert!(added.is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    let result = unsafe { loader.load_dir(&dir, &mut registry) };
+    assert!(matches!(result, Err(PluginError::Io(_))));
+}
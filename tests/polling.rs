@@ -0,0 +1,354 @@
+#![cfg(feature = "polling")]
+
+mod common;
+
+use common::{next_event, read_request, Response};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, PollContext, PollPage,
+        PollSession, PollSource, PollingConnection, Scope, SseEvent, StatusEvent, UserEvent,
+    },
+    AuthField, Channel, ChannelType, Connection, FieldValue, Message, MessageFragment,
+    MessageStatus, MessageType, Protocol,
+};
+use serde_json::{json, Value};
+use std::{collections::HashMap, time::Duration};
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::mpsc,
+};
+
+fn message(value: &Value) -> (Scope, Message) {
+    (
+        Scope::channel(value["room"].as_str().unwrap()),
+        Message {
+            id: value["id"].as_str().map(|id| id.to_string()),
+            sender_id: None,
+            content: vec![MessageFragment::Text(
+                value["text"].as_str().unwrap().to_string(),
+            )],
+            timestamp: Utc::now(),
+            message_type: MessageType::Normal,
+            status: MessageStatus::Delivered,
+            reactions: Vec::new(),
+            reply_to: None,
+            thread_id: None,
+            extra: HashMap::new(),
+        },
+    )
+}
+
+/// A small REST API: `GET /me`, `GET /messages?after=<cursor>` returning
+/// `{ "messages": [...], "cursor": ... }`, and optionally `GET /events`.
+struct TestApi {
+    base: String,
+    stream: bool,
+}
+
+#[async_trait]
+impl PollSource for TestApi {
+    fn protocol_spec(&self) -> Protocol {
+        Protocol {
+            name: "test".to_string(),
+            auth: Some(vec![AuthField {
+                name: "token".to_string(),
+                display: None,
+                value: FieldValue::Password(None),
+                required: true,
+            }]),
+        }
+    }
+
+    async fn open(&self, context: &PollContext) -> Result<PollSession, ConnectionError> {
+        let token = context.field("token").unwrap_or_default();
+        let response = context
+            .http
+            .get(format!("{}/me", self.base))
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        if response.status().as_u16() == 401 {
+            return Err(ConnectionError::Auth("Bad token".to_string()));
+        }
+        Ok(PollSession {
+            user_id: Some("me".to_string()),
+            channels: vec![Channel {
+                id: "lobby".to_string(),
+                name: None,
+                channel_type: ChannelType::Group,
+                topic: None,
+                extra: HashMap::new(),
+            }],
+        })
+    }
+
+    async fn fetch(
+        &self,
+        context: &PollContext,
+        cursor: Option<&str>,
+    ) -> Result<PollPage, ConnectionError> {
+        let url = format!("{}/messages?after={}", self.base, cursor.unwrap_or(""));
+        let body = context
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ConnectionError::Network(e.to_string()))?
+            .bytes()
+            .await
+            .map_err(|e| ConnectionError::Network(e.to_string()))?;
+        let page: Value = serde_json::from_slice(&body).unwrap();
+        Ok(PollPage {
+            messages: page["messages"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(message)
+                .collect(),
+            events: Vec::new(),
+            cursor: page["cursor"].as_str().map(|cursor| cursor.to_string()),
+        })
+    }
+
+    fn event_stream(&self, context: &PollContext) -> Option<reqwest::RequestBuilder> {
+        self.stream
+            .then(|| context.http.get(format!("{}/events", self.base)))
+    }
+
+    fn parse_event(&self, event: &SseEvent) -> PollPage {
+        let data: Value = serde_json::from_str(&event.data).unwrap();
+        PollPage {
+            messages: vec![message(&data)],
+            ..PollPage::default()
+        }
+    }
+}
+
+/// A fake API for the token `good`. Message pages overlap: the page after `c1` repeats `m2`.
+/// The event stream sends `m3` twice and `m4`, then closes. Request lines are reported on
+/// `requests` with the `Last-Event-ID` header, if any.
+async fn serve(listener: TcpListener, requests: mpsc::UnboundedSender<(String, Option<String>)>) {
+    loop {
+        let (socket, _) = listener.accept().await.unwrap();
+        let requests = requests.clone();
+        tokio::spawn(async move {
+            let mut stream = BufReader::new(socket);
+            while let Some(request) = read_request(&mut stream).await {
+                let last_event_id = request.header("last-event-id").map(str::to_string);
+                let _ = requests.send((request.line.clone(), last_event_id));
+                let response = match request.path() {
+                    "/me" if request.header("authorization") == Some("Bearer good") => {
+                        Response::json(json!({ "id": "me" }))
+                    }
+                    "/me" => Response::new("401 Unauthorized"),
+                    "/messages?after=" => Response::json(json!({
+                        "messages": [
+                            { "id": "m1", "room": "lobby", "text": "one" },
+                            { "id": "m2", "room": "lobby", "text": "two" }
+                        ],
+                        "cursor": "c1"
+                    })),
+                    "/messages?after=c1" => Response::json(json!({
+                        "messages": [
+                            { "id": "m2", "room": "lobby", "text": "two" },
+                            { "id": "m3", "room": "lobby", "text": "three" }
+                        ],
+                        "cursor": "c2"
+                    })),
+                    "/events" => {
+                        let events = concat!(
+                            ": keep-alive\n\n",
+                            "id: e3\ndata: {\"id\":\"m3\",\"room\":\"lobby\",\n",
+                            "data: \"text\":\"three\"}\n\n",
+                            "id: e3\r\ndata: {\"id\":\"m3\",\"room\":\"lobby\",\"text\":\"three\"}\r\n\r\n",
+                            "event: message\nid: e4\ndata: {\"id\":\"m4\",\"room\":\"lobby\",\"text\":\"four\"}\n\n",
+                        );
+                        let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n";
+                        let _ = stream.get_mut().write_all(head.as_bytes()).await;
+                        // Split an event across writes to cover reassembly.
+                        let (first, rest) = events.split_at(40);
+                        let _ = stream.get_mut().write_all(first.as_bytes()).await;
+                        let _ = stream.get_mut().flush().await;
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        let _ = stream.get_mut().write_all(rest.as_bytes()).await;
+                        return;
+                    }
+                    _ => Response::json(json!({ "messages": [] })),
+                };
+                stream
+                    .get_mut()
+                    .write_all(response.to_string().as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+    }
+}
+
+fn token(value: &str) -> Vec<AuthField> {
+    vec![AuthField {
+        name: "token".to_string(),
+        display: None,
+        value: FieldValue::Password(Some(value.to_string())),
+        required: true,
+    }]
+}
+
+async fn expect_message(rx: &mut mpsc::UnboundedReceiver<ConnectionEvent>, id: &str) {
+    match next_event(rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel("lobby"));
+            assert_eq!(message.id.as_deref(), Some(id));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+}
+
+async fn expect_synced(rx: &mut mpsc::UnboundedReceiver<ConnectionEvent>) {
+    assert!(matches!(
+        next_event(rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(rx).await,
+        ConnectionEvent::User { event: UserEvent::Identify { user_id } } if user_id == "me"
+    ));
+    assert!(matches!(
+        next_event(rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::New { channel } } if channel.id == "lobby"
+    ));
+    assert!(matches!(
+        next_event(rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::Join { channel_id } } if channel_id == "lobby"
+    ));
+    expect_message(rx, "m1").await;
+    expect_message(rx, "m2").await;
+    assert!(matches!(
+        next_event(rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+}
+
+#[tokio::test]
+async fn polling_follows_cursor_and_skips_seen_ids() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let (requests_tx, mut requests) = mpsc::unbounded_channel();
+    tokio::spawn(serve(listener, requests_tx));
+
+    let api = TestApi {
+        base,
+        stream: false,
+    };
+    let mut conn = PollingConnection::new(api).poll_interval(Duration::from_millis(100));
+    let mut rx = conn.subscribe();
+    conn.set_auth(token("good")).unwrap();
+    conn.connect().await.unwrap();
+    expect_synced(&mut rx).await;
+    assert_eq!(conn.cursor().as_deref(), Some("c1"));
+
+    // m2 comes again in the next page and is not emitted twice.
+    expect_message(&mut rx, "m3").await;
+    loop {
+        let (request_line, _) = requests.recv().await.unwrap();
+        if request_line.starts_with("GET /messages?after=c2") {
+            break;
+        }
+    }
+    assert_eq!(conn.cursor().as_deref(), Some("c2"));
+    assert!(rx.try_recv().is_err());
+
+    assert!(matches!(
+        conn.send(ConnectionEvent::Chat {
+            event: ChatEvent::Remove {
+                scope: Scope::channel("lobby"),
+                message_id: "m1".to_string(),
+            },
+        })
+        .await,
+        Err(ConnectionError::Unsupported(_))
+    ));
+    conn.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn polling_streams_events_and_resumes_with_last_event_id() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let (requests_tx, mut requests) = mpsc::unbounded_channel();
+    tokio::spawn(serve(listener, requests_tx));
+
+    let api = TestApi { base, stream: true };
+    let mut conn = PollingConnection::new(api).poll_interval(Duration::from_millis(100));
+    let mut rx = conn.subscribe();
+    conn.set_auth(token("good")).unwrap();
+    conn.connect().await.unwrap();
+    expect_synced(&mut rx).await;
+
+    expect_message(&mut rx, "m3").await;
+    expect_message(&mut rx, "m4").await;
+
+    // After the stream closes, the source is polled with the last event id as the cursor and
+    // the stream is reopened from it.
+    let mut saw_catch_up = false;
+    loop {
+        let (request_line, last_event_id) = requests.recv().await.unwrap();
+        if request_line.starts_with("GET /messages?after=e4") {
+            saw_catch_up = true;
+        }
+        if request_line.starts_with("GET /events") && last_event_id.as_deref() == Some("e4") {
+            break;
+        }
+    }
+    assert!(saw_catch_up);
+    conn.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn polling_rejected_credentials_fail_auth() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let (requests_tx, _requests) = mpsc::unbounded_channel();
+    tokio::spawn(serve(listener, requests_tx));
+
+    let mut conn = PollingConnection::new(TestApi {
+        base,
+        stream: false,
+    });
+    let mut rx = conn.subscribe();
+    conn.set_auth(token("bad")).unwrap();
+    assert!(matches!(
+        conn.connect().await,
+        Err(ConnectionError::Auth(_))
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed { .. }
+        }
+    ));
+}
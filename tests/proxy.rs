@@ -0,0 +1,98 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::chat;
+
+use std::time::{Duration, Instant};
+
+use oshatori::{
+    connection::{
+        ChatEvent, ConnectionEvent, Intercept, MockConnection, ProxyConnection, Scope, StatusEvent,
+    },
+    Connection, ConnectionError, MessageFragment,
+};
+
+fn text_of(event: &ConnectionEvent) -> Option<&str> {
+    match event {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. },
+        } => match message.content.first() {
+            Some(MessageFragment::Text(text)) => Some(text),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn proxy_intercepts_outbound() {
+    let mut conn =
+        ProxyConnection::new(MockConnection::new()).on_outbound(|event| match text_of(&event) {
+            Some("drop") => Intercept::Drop,
+            Some("fail") => Intercept::Fail(ConnectionError::Timeout),
+            Some("slow") => Intercept::Delay(Duration::from_millis(50), event),
+            _ => Intercept::Pass(event),
+        });
+    let mut rx = conn.subscribe();
+    conn.connect().await.unwrap();
+
+    conn.send(chat(Scope::channel("general"), "drop"))
+        .await
+        .unwrap();
+    assert_eq!(
+        conn.send(chat(Scope::channel("general"), "fail")).await,
+        Err(ConnectionError::Timeout)
+    );
+    let started = Instant::now();
+    conn.send(chat(Scope::channel("general"), "slow"))
+        .await
+        .unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    conn.send(chat(Scope::channel("general"), "fast"))
+        .await
+        .unwrap();
+
+    let mut texts = Vec::new();
+    while let Ok(Some(event)) = tokio::time::timeout(Duration::from_millis(100), rx.recv()).await {
+        if let Some(text) = text_of(&event) {
+            texts.push(text.to_string());
+        }
+    }
+    assert_eq!(texts, vec!["slow", "fast"]);
+}
+
+#[tokio::test]
+async fn proxy_intercepts_inbound() {
+    let mut conn =
+        ProxyConnection::new(MockConnection::new()).on_inbound(|event| match text_of(&event) {
+            Some("drop") => Intercept::Drop,
+            Some("fail") => Intercept::Fail(ConnectionError::Closed),
+            _ => Intercept::Pass(event),
+        });
+    let mut rx = conn.subscribe();
+    conn.connect().await.unwrap();
+
+    conn.send(chat(Scope::channel("general"), "drop"))
+        .await
+        .unwrap();
+    conn.send(chat(Scope::channel("general"), "fail"))
+        .await
+        .unwrap();
+    conn.send(chat(Scope::channel("general"), "kept"))
+        .await
+        .unwrap();
+
+    let mut texts = Vec::new();
+    let mut errors = 0;
+    while let Ok(Some(event)) = tokio::time::timeout(Duration::from_millis(100), rx.recv()).await {
+        match &event {
+            ConnectionEvent::Status {
+                event: StatusEvent::Error { .. },
+            } => errors += 1,
+            _ => texts.extend(text_of(&event).map(str::to_string)),
+        }
+    }
+    assert_eq!(texts, vec!["kept"]);
+    assert_eq!(errors, 1);
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::chat;
+
+use std::time::{Duration, Instant};
+
+use oshatori::{
+    connection::{
+        ConnectionEvent, MockConnection, RateLimit, RateLimitPolicy, RateLimitedConnection, Scope,
+        StatusEvent, ThrottleMode,
+    },
+    Connection, ConnectionError,
+};
+
+fn policy(mode: ThrottleMode) -> RateLimitPolicy {
+    let mut policy = RateLimitPolicy::default();
+    policy.by_protocol.insert(
+        "Mock".to_string(),
+        RateLimit {
+            burst: 2,
+            per_second: 20.0,
+            mode,
+        },
+    );
+    policy
+}
+
+#[tokio::test]
+async fn rejects_over_limit() {
+    let mut conn = RateLimitedConnection::new(MockConnection::new(), &policy(ThrottleMode::Reject));
+    let mut rx = conn.subscribe();
+
+    conn.send(chat(Scope::Global, "one")).await.unwrap();
+    conn.send(chat(Scope::Global, "two")).await.unwrap();
+    assert!(matches!(
+        conn.send(chat(Scope::Global, "three")).await,
+        Err(ConnectionError::RateLimited(_))
+    ));
+
+    let mut throttled = false;
+    while let Ok(Some(event)) = tokio::time::timeout(Duration::from_millis(50), rx.recv()).await {
+        if let ConnectionEvent::Status {
+            event: StatusEvent::Throttled { .. },
+        } = event
+        {
+            throttled = true;
+        }
+    }
+    assert!(throttled);
+}
+
+#[tokio::test]
+async fn queues_over_limit() {
+    let mut conn = RateLimitedConnection::new(MockConnection::new(), &policy(ThrottleMode::Queue));
+    let _rx = conn.subscribe();
+
+    let started = Instant::now();
+    for i in 0..3 {
+        conn.send(chat(Scope::Global, &i.to_string()))
+            .await
+            .unwrap();
+    }
+    assert!(started.elapsed() >= Duration::from_millis(40));
+}
@@ -0,0 +1,359 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use oshatori::{
+    client::ConnectionStatus,
+    connection::{ConnectionEvent, ReconnectPolicy, ReconnectingConnection, StatusEvent},
+    AuthField, Capabilities, Connection, ConnectionError, Protocol,
+};
+use tokio::sync::mpsc;
+
+struct FlakyConnection {
+    connects: Arc<AtomicU32>,
+    failures: u32,
+    rejects_reconnects: bool,
+    event_tx: mpsc::UnboundedSender<ConnectionEvent>,
+    event_rx: Option<mpsc::UnboundedReceiver<ConnectionEvent>>,
+}
+
+impl FlakyConnection {
+    fn new(connects: Arc<AtomicU32>, failures: u32) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        FlakyConnection {
+            connects,
+            failures,
+            rejects_reconnects: false,
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+
+    fn rejecting_reconnects(self) -> Self {
+        FlakyConnection {
+            rejects_reconnects: true,
+            ..self
+        }
+    }
+}
+
+#[async_trait]
+impl Connection for FlakyConnection {
+    fn set_auth(&mut self, _auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        Ok(())
+    }
+
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        let attempt = self.connects.fetch_add(1, Ordering::SeqCst);
+        if attempt > 0 && self.rejects_reconnects {
+            return Err(ConnectionError::Auth("Token expired".to_string()));
+        }
+        if attempt > 0 && attempt <= self.failures {
+            return Err(ConnectionError::Network("unreachable".to_string()));
+        }
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Connected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        let _ = self.event_tx.send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        });
+        Ok(())
+    }
+
+    async fn send(&mut self, event: ConnectionEvent) -> Result<(), ConnectionError> {
+        self.event_tx
+            .send(event)
+            .map_err(|_| ConnectionError::Closed)
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        self.event_rx.take().unwrap()
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        Protocol {
+            name: "flaky".to_string(),
+            auth: None,
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        if self.connects.load(Ordering::SeqCst) > 0 {
+            ConnectionStatus::Connected
+        } else {
+            ConnectionStatus::Disconnected
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            max_message_length: (self.connects.load(Ordering::SeqCst) > 0).then_some(100),
+            ..Capabilities::default()
+        }
+    }
+}
+
+fn fast_policy(max_attempts: Option<u32>) -> ReconnectPolicy {
+    ReconnectPolicy {
+        initial_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(5),
+        multiplier: 2.0,
+        jitter: 0.0,
+        max_attempts,
+    }
+}
+
+#[tokio::test]
+async fn reconnects_after_drop() {
+    let connects = Arc::new(AtomicU32::new(0));
+    let mut conn =
+        ReconnectingConnection::new(FlakyConnection::new(connects.clone(), 2), fast_policy(None));
+    let mut rx = conn.subscribe();
+
+    conn.connect().await.unwrap();
+    conn.send(ConnectionEvent::Status {
+        event: StatusEvent::Disconnected { artifact: None },
+    })
+    .await
+    .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(connects.load(Ordering::SeqCst), 4);
+
+    let mut connected = 0;
+    let mut attempts = Vec::new();
+    while let Ok(event) = rx.try_recv() {
+        match event {
+            ConnectionEvent::Status {
+                event: StatusEvent::Connected { .. },
+            } => connected += 1,
+            ConnectionEvent::Status {
+                event: StatusEvent::Reconnecting { attempt },
+            } => attempts.push(attempt),
+            _ => {}
+        }
+    }
+    assert_eq!(connected, 2);
+    assert_eq!(attempts, vec![1, 2, 3]);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let connects = Arc::new(AtomicU32::new(0));
+    let mut conn = ReconnectingConnection::new(
+        FlakyConnection::new(connects.clone(), 10),
+        fast_policy(Some(3)),
+    );
+    let _rx = conn.subscribe();
+
+    conn.connect().await.unwrap();
+    conn.send(ConnectionEvent::Status {
+        event: StatusEvent::Disconnected { artifact: None },
+    })
+    .await
+    .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(connects.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn manual_disconnect_does_not_reconnect() {
+    let connects = Arc::new(AtomicU32::new(0));
+    let mut conn =
+        ReconnectingConnection::new(FlakyConnection::new(connects.clone(), 0), fast_policy(None));
+    let _rx = conn.subscribe();
+
+    conn.connect().await.unwrap();
+    conn.disconnect().await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn holds_reconnect_through_maintenance() {
+    let connects = Arc::new(AtomicU32::new(0));
+    let mut conn =
+        ReconnectingConnection::new(FlakyConnection::new(connects.clone(), 0), fast_policy(None));
+    let _rx = conn.subscribe();
+
+    conn.connect().await.unwrap();
+    conn.send(ConnectionEvent::Status {
+        event: StatusEvent::Maintenance {
+            message: Some("Restarting".to_string()),
+            scheduled_at: None,
+            until: Some(chrono::Utc::now() + chrono::Duration::milliseconds(300)),
+        },
+    })
+    .await
+    .unwrap();
+    conn.send(ConnectionEvent::Status {
+        event: StatusEvent::Disconnected { artifact: None },
+    })
+    .await
+    .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn stops_after_auth_failure() {
+    let connects = Arc::new(AtomicU32::new(0));
+    let mut conn =
+        ReconnectingConnection::new(FlakyConnection::new(connects.clone(), 0), fast_policy(None));
+    let _rx = conn.subscribe();
+
+    conn.connect().await.unwrap();
+    conn.send(ConnectionEvent::Status {
+        event: StatusEvent::AuthFailed {
+            reason: "Token expired".to_string(),
+        },
+    })
+    .await
+    .unwrap();
+    conn.send(ConnectionEvent::Status {
+        event: StatusEvent::Disconnected { artifact: None },
+    })
+    .await
+    .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn reports_credentials_rejected_on_reconnect() {
+    let connects = Arc::new(AtomicU32::new(0));
+    let mut conn = ReconnectingConnection::new(
+        FlakyConnection::new(connects.clone(), 0).rejecting_reconnects(),
+        fast_policy(None),
+    );
+    let mut rx = conn.subscribe();
+
+    conn.connect().await.unwrap();
+    conn.send(ConnectionEvent::Status {
+        event: StatusEvent::Disconnected { artifact: None },
+    })
+    .await
+    .unwrap();
+
+    loop {
+        if let ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed { reason },
+        } = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .expect("no auth failure reported")
+            .unwrap()
+        {
+            assert!(reason.contains("Token expired"));
+            break;
+        }
+    }
+    assert!(matches!(conn.status(), ConnectionStatus::AuthFailed { .. }));
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn forwards_events_during_backoff() {
+    let connects = Arc::new(AtomicU32::new(0));
+    let policy = ReconnectPolicy {
+        initial_delay: Duration::from_secs(60),
+        ..fast_policy(None)
+    };
+    let mut conn = ReconnectingConnection::new(FlakyConnection::new(connects.clone(), 0), policy);
+    let mut rx = conn.subscribe();
+
+    conn.connect().await.unwrap();
+    let inner = conn.inner();
+    for event in [
+        StatusEvent::Disconnected { artifact: None },
+        StatusEvent::Error {
+            message: "late".to_string(),
+        },
+    ] {
+        inner
+            .lock()
+            .await
+            .send(ConnectionEvent::Status { event })
+            .await
+            .unwrap();
+    }
+
+    let mut disconnects = 0;
+    loop {
+        match tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .expect("events stalled during backoff")
+            .unwrap()
+        {
+            ConnectionEvent::Status {
+                event: StatusEvent::Disconnected { .. },
+            } => disconnects += 1,
+            ConnectionEvent::Status {
+                event: StatusEvent::Error { message },
+            } if message == "late" => break,
+            _ => {}
+        }
+    }
+    assert_eq!(disconnects, 1);
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn reports_status_while_inner_is_busy() {
+    let connects = Arc::new(AtomicU32::new(0));
+    let policy = ReconnectPolicy {
+        initial_delay: Duration::from_secs(60),
+        max_delay: Duration::from_secs(60),
+        ..fast_policy(None)
+    };
+    let mut conn = ReconnectingConnection::new(FlakyConnection::new(connects.clone(), 0), policy);
+    let _rx = conn.subscribe();
+
+    conn.connect().await.unwrap();
+    let inner = conn.inner();
+    let busy = inner.lock().await;
+    assert_eq!(conn.status(), ConnectionStatus::Connected);
+    assert!(conn.set_auth(Vec::new()).is_ok());
+    drop(busy);
+
+    inner
+        .lock()
+        .await
+        .send(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact: None },
+        })
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(conn.status(), ConnectionStatus::Disconnected);
+}
+
+#[tokio::test]
+async fn reports_capabilities_learned_while_connecting() {
+    let connects = Arc::new(AtomicU32::new(0));
+    let mut conn =
+        ReconnectingConnection::new(FlakyConnection::new(connects.clone(), 0), fast_policy(None));
+    let _rx = conn.subscribe();
+    assert_eq!(conn.capabilities().max_message_length, None);
+
+    conn.connect().await.unwrap();
+    assert_eq!(conn.capabilities().max_message_length, Some(100));
+
+    let inner = conn.inner();
+    let _busy = inner.lock().await;
+    assert_eq!(conn.capabilities().max_message_length, Some(100));
+}
@@ -0,0 +1,44 @@
+#![cfg(feature = "mock")]
+
+use oshatori::{
+    connection::{MockConnection, ProtocolRegistry},
+    Account, AuthField, ConnectionError, FieldValue,
+};
+
+#[test]
+fn registry_creates_builtin_protocols() {
+    let registry = ProtocolRegistry::with_builtin();
+    assert!(registry.contains("mock"));
+    assert!(registry.protocols().contains(&"mock".to_string()));
+
+    let connection = registry.create("Mock").unwrap();
+    assert_eq!(connection.protocol_spec().name, "Mock");
+
+    assert!(matches!(
+        registry.create("carrier-pigeon"),
+        Err(ConnectionError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn registry_accepts_custom_factories() {
+    let mut registry = ProtocolRegistry::new();
+    assert!(registry.protocols().is_empty());
+
+    registry.register("loopback", || Box::new(MockConnection::new()));
+    let account = Account {
+        auth: vec![AuthField {
+            name: "token".to_string(),
+            display: None,
+            value: FieldValue::Password(Some("secret".to_string())),
+            required: false,
+        }],
+        protocol_name: "loopback".to_string(),
+        private_profile: None,
+        autoconnect: false,
+    };
+    assert!(registry.create_for(&account).is_ok());
+
+    assert!(registry.unregister("LOOPBACK"));
+    assert!(registry.create_for(&account).is_err());
+}
@@ -0,0 +1,347 @@
+#![cfg(feature = "revolt")]
+
+mod common;
+
+use common::{next_event, serve_http, text_field, Request, Response};
+
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use oshatori::{
+    connection::{
+        AssetEvent, ChannelEvent, ChatEvent, ConnectionEvent, RevoltConnection, Scope, SendOutcome,
+        StatusEvent, UserEvent,
+    },
+    Asset, ChannelType, Connection, Message, MessageFragment, MessageStatus, MessageType, Profile,
+};
+use serde_json::{json, Value};
+use std::{collections::HashMap, time::Duration};
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+const BOT: &str = "01HB0T0000000000000000000A";
+const ALICE: &str = "01HA11CE00000000000000000A";
+const CHANNEL: &str = "01HCHANNE10000000000000000";
+const EMOJI: &str = "01HEM0J100000000000000000A";
+
+/// A fake REST API that answers the connect-time calls and reports every other call on
+/// `requests` as its request line and body.
+async fn serve_api(
+    listener: TcpListener,
+    socket_port: u16,
+    requests: mpsc::UnboundedSender<(String, Value)>,
+) {
+    serve_http(listener, move |request: Request| {
+        let requests = requests.clone();
+        async move {
+            let token = request.header("x-bot-token").unwrap_or_default();
+            match request.path() {
+                "/" => Response::json(json!({
+                    "ws": format!("ws://127.0.0.1:{}/", socket_port),
+                    "features": { "autumn": { "url": "https://files.example" } }
+                })),
+                "/users/@me" if token == "bot-token" => {
+                    Response::json(json!({ "_id": BOT, "username": "bot" }))
+                }
+                "/users/@me" => {
+                    Response::json(json!({ "type": "InvalidSession" })).status("401 Unauthorized")
+                }
+                _ => {
+                    let body = serde_json::from_str(&request.body).unwrap_or_default();
+                    let _ = requests.send((request.line, body));
+                    Response::json(json!({ "_id": "01HSENT0000000000000000000" }))
+                }
+            }
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn revolt_ready_messages_and_masquerade() {
+    let api = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let api_port = api.local_addr().unwrap().port();
+    let events = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let events_port = events.local_addr().unwrap().port();
+    let (requests_tx, mut requests) = mpsc::unbounded_channel();
+    tokio::spawn(serve_api(api, events_port, requests_tx));
+
+    let (frames_tx, mut frames) = mpsc::unbounded_channel::<Value>();
+    tokio::spawn(async move {
+        let (stream, _) = events.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let authenticate = ws.next().await.unwrap().unwrap();
+        let authenticate: Value = serde_json::from_str(authenticate.to_text().unwrap()).unwrap();
+        assert_eq!(
+            authenticate,
+            json!({ "type": "Authenticate", "token": "bot-token" })
+        );
+        let ready = json!({
+            "type": "Ready",
+            "users": [{
+                "_id": ALICE, "username": "alice", "discriminator": "0001",
+                "display_name": "Alice", "avatar": { "_id": "av1" },
+                "online": true, "status": { "presence": "Idle" }
+            }],
+            "channels": [
+                { "_id": CHANNEL, "channel_type": "TextChannel", "server": "S1",
+                  "name": "general", "description": "hello" },
+                { "_id": "01HDM000000000000000000000", "channel_type": "DirectMessage",
+                  "active": true, "recipients": [BOT, ALICE] },
+                { "_id": "01HSAVED000000000000000000", "channel_type": "SavedMessages",
+                  "user": BOT }
+            ],
+            "emojis": [{ "_id": EMOJI, "name": "wave", "parent": { "type": "Server", "id": "S1" } }]
+        });
+        ws.send(WsMessage::Text(ready.to_string().into()))
+            .await
+            .unwrap();
+        let message = json!({
+            "type": "Message",
+            "_id": "01HMSG00000000000000000000",
+            "channel": CHANNEL,
+            "author": ALICE,
+            "content": format!("hi <@{}> :{}:", BOT, EMOJI),
+            "attachments": [{ "_id": "att1", "content_type": "image/png" }],
+            "replies": ["01HPREV0000000000000000000"],
+            "masquerade": { "name": "Mask", "avatar": "https://example.com/mask.png" }
+        });
+        ws.send(WsMessage::Text(message.to_string().into()))
+            .await
+            .unwrap();
+        let react = json!({
+            "type": "MessageReact", "id": "01HMSG00000000000000000000",
+            "channel_id": CHANNEL, "user_id": ALICE, "emoji_id": EMOJI
+        });
+        ws.send(WsMessage::Text(react.to_string().into()))
+            .await
+            .unwrap();
+        while let Some(Ok(frame)) = ws.next().await {
+            if let WsMessage::Text(text) = frame {
+                let frame: Value = serde_json::from_str(&text).unwrap();
+                if frame["type"] != "Ping" {
+                    let _ = frames_tx.send(frame);
+                }
+            }
+        }
+    });
+
+    let mut conn = RevoltConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("token", "bot-token"),
+        text_field("api_url", &format!("http://127.0.0.1:{}", api_port)),
+    ])
+    .unwrap();
+    conn.connect().await.unwrap();
+    assert_eq!(conn.user_id(), Some(BOT));
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::Identify { user_id } } if user_id == BOT
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::User {
+            event: UserEvent::New { user, .. },
+        } => {
+            assert_eq!(user.username.as_deref(), Some("alice#0001"));
+            assert_eq!(user.display_name.as_deref(), Some("Alice"));
+            assert_eq!(
+                user.picture.as_deref(),
+                Some("https://files.example/avatars/av1")
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    match next_event(&mut rx).await {
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel },
+        } => {
+            assert_eq!(channel.name.as_deref(), Some("general"));
+            assert_eq!(channel.topic.as_deref(), Some("hello"));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::Join { channel_id } } if channel_id == CHANNEL
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel },
+        } => {
+            assert_eq!(channel.channel_type, ChannelType::Direct);
+            assert_eq!(channel.name.as_deref(), Some("Alice"));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Join { .. }
+        }
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Asset {
+            event: AssetEvent::New { asset, .. },
+        } => match asset {
+            Asset::Emote {
+                id, pattern, src, ..
+            } => {
+                assert_eq!(id.as_deref(), Some(EMOJI));
+                assert!(regex::Regex::new(&pattern).unwrap().is_match(":wave:"));
+                assert_eq!(src, format!("https://files.example/emojis/{}", EMOJI));
+            }
+            other => panic!("unexpected asset {:?}", other),
+        },
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel(CHANNEL));
+            assert_eq!(
+                message.content,
+                vec![
+                    MessageFragment::Text("hi ".to_string()),
+                    MessageFragment::Mention {
+                        user_id: BOT.to_string(),
+                        display: BOT.to_string(),
+                    },
+                    MessageFragment::Text(" ".to_string()),
+                    MessageFragment::AssetId(EMOJI.to_string()),
+                    MessageFragment::Image {
+                        url: "https://files.example/attachments/att1".to_string(),
+                        mime: "image/png".to_string(),
+                    },
+                ]
+            );
+            assert_eq!(
+                message.reply_to.as_deref(),
+                Some("01HPREV0000000000000000000")
+            );
+            let masquerade: Profile =
+                serde_json::from_value(message.extra["masquerade"].clone()).unwrap();
+            assert_eq!(masquerade.id.as_deref(), Some(ALICE));
+            assert_eq!(masquerade.display_name.as_deref(), Some("Mask"));
+            assert_eq!(
+                masquerade.picture.as_deref(),
+                Some("https://example.com/mask.png")
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::ReactionAdd { key: oshatori::ReactionKey::AssetId(id), .. }
+        } if id == EMOJI
+    ));
+
+    let mut extra = HashMap::new();
+    extra.insert(
+        "masquerade".to_string(),
+        serde_json::to_value(Profile {
+            display_name: Some("Relay".to_string()),
+            ..Profile::default()
+        })
+        .unwrap(),
+    );
+    let handle = conn
+        .send_tracked(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel(CHANNEL),
+                message: Message {
+                    id: None,
+                    sender_id: None,
+                    content: vec![
+                        MessageFragment::Mention {
+                            user_id: ALICE.to_string(),
+                            display: "Alice".to_string(),
+                        },
+                        MessageFragment::Text(" look ".to_string()),
+                        MessageFragment::AssetId(EMOJI.to_string()),
+                    ],
+                    timestamp: Utc::now(),
+                    message_type: MessageType::CurrentUser,
+                    status: MessageStatus::Sent,
+                    reactions: Vec::new(),
+                    reply_to: None,
+                    thread_id: None,
+                    extra,
+                },
+            },
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        handle.await,
+        SendOutcome::Delivered {
+            message_id: Some("01HSENT0000000000000000000".to_string())
+        }
+    );
+    let (line, body) = requests.recv().await.unwrap();
+    assert!(line.starts_with(&format!("POST /channels/{}/messages ", CHANNEL)));
+    assert_eq!(body["content"], format!("<@{}> look :{}:", ALICE, EMOJI));
+    assert_eq!(body["masquerade"]["name"], "Relay");
+
+    conn.send(ConnectionEvent::User {
+        event: UserEvent::TypingStart {
+            channel_id: CHANNEL.to_string(),
+            user_id: BOT.to_string(),
+        },
+    })
+    .await
+    .unwrap();
+    let frame = tokio::time::timeout(Duration::from_secs(5), frames.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(frame, json!({ "type": "BeginTyping", "channel": CHANNEL }));
+}
+
+#[tokio::test]
+async fn revolt_rejected_token_fails_auth() {
+    let api = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let api_port = api.local_addr().unwrap().port();
+    let (requests_tx, _requests) = mpsc::unbounded_channel();
+    tokio::spawn(serve_api(api, 1, requests_tx));
+
+    let mut conn = RevoltConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("token", "wrong"),
+        text_field("api_url", &format!("http://127.0.0.1:{}/", api_port)),
+    ])
+    .unwrap();
+    assert!(conn.connect().await.is_err());
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status { event: StatusEvent::AuthFailed { reason } } if reason == "InvalidSession"
+    ));
+}
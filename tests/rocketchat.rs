@@ -0,0 +1,345 @@
+#![cfg(feature = "rocketchat")]
+
+mod common;
+
+use common::{next_event, text_field};
+
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, RocketChatConnection, Scope,
+        SendOutcome, StatusEvent, UserEvent,
+    },
+    ChannelType, Connection, Message, MessageFragment, MessageStatus, MessageType, Presence,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+fn changed(collection: &str, event_name: &str, args: Value) -> WsMessage {
+    let frame = json!({
+        "msg": "changed",
+        "collection": collection,
+        "id": "id",
+        "fields": { "eventName": event_name, "args": args }
+    });
+    WsMessage::Text(frame.to_string().into())
+}
+
+/// A fake realtime API that accepts the resume token `good`, and once the client subscribes to
+/// the general room pushes a message, its edit, its deletion and a presence change. Other
+/// method calls are reported on `calls` and answered with a sent message.
+async fn serve_realtime(listener: TcpListener, calls: mpsc::UnboundedSender<(String, Value)>) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+    let message = json!({
+        "_id": "m1", "rid": "GENERAL", "msg": "hey @bot look",
+        "ts": { "$date": 1_700_000_000_000i64 },
+        "u": { "_id": "u2", "username": "alice", "name": "Alice" },
+        "mentions": [{ "_id": "u1", "username": "bot", "name": "Bot" }],
+        "attachments": [{ "title": "cat.png", "image_url": "/file-upload/f1/cat.png",
+                          "image_type": "image/png" }]
+    });
+    while let Some(Ok(frame)) = ws.next().await {
+        let WsMessage::Text(text) = frame else {
+            continue;
+        };
+        let frame: Value = serde_json::from_str(&text).unwrap();
+        let reply = match (frame["msg"].as_str(), frame["method"].as_str()) {
+            (Some("connect"), _) => json!({ "msg": "connected", "session": "s1" }),
+            (Some("method"), Some("login")) if frame["params"][0]["resume"] == "good" => {
+                let added = json!({
+                    "msg": "added", "collection": "users", "id": "u1",
+                    "fields": { "username": "bot", "name": "Bot" }
+                });
+                ws.send(WsMessage::Text(added.to_string().into()))
+                    .await
+                    .unwrap();
+                json!({ "msg": "result", "id": frame["id"], "result": { "id": "u1", "token": "t" } })
+            }
+            (Some("method"), Some("login")) => json!({
+                "msg": "result", "id": frame["id"],
+                "error": { "error": 403, "reason": "You've been logged out by the server." }
+            }),
+            (Some("method"), Some("rooms/get")) => json!({
+                "msg": "result", "id": frame["id"], "result": [
+                    { "_id": "GENERAL", "t": "c", "name": "general", "topic": "welcome" },
+                    { "_id": "u1u2", "t": "d", "usernames": ["bot", "alice"] },
+                    { "_id": "L1", "t": "l", "name": "visitor" }
+                ]
+            }),
+            (Some("method"), Some(method)) => {
+                let _ = calls.send((method.to_string(), frame["params"].clone()));
+                json!({ "msg": "result", "id": frame["id"], "result": { "_id": "sent1" } })
+            }
+            (Some("sub"), _) if frame["params"][0] == "GENERAL" => {
+                ws.send(changed("stream-room-messages", "GENERAL", json!([message])))
+                    .await
+                    .unwrap();
+                let mut edited = message.clone();
+                edited["msg"] = json!("hey all");
+                edited["mentions"] = json!([]);
+                edited["editedAt"] = json!({ "$date": 1_700_000_060_000i64 });
+                ws.send(changed("stream-room-messages", "GENERAL", json!([edited])))
+                    .await
+                    .unwrap();
+                ws.send(changed(
+                    "stream-notify-room",
+                    "GENERAL/deleteMessage",
+                    json!([{ "_id": "m1" }]),
+                ))
+                .await
+                .unwrap();
+                ws.send(changed(
+                    "stream-notify-logged",
+                    "user-status",
+                    json!([["u2", "alice", 2, ""]]),
+                ))
+                .await
+                .unwrap();
+                continue;
+            }
+            _ => continue,
+        };
+        ws.send(WsMessage::Text(reply.to_string().into()))
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn rocketchat_rooms_messages_and_presence() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (calls_tx, mut calls) = mpsc::unbounded_channel();
+    tokio::spawn(serve_realtime(listener, calls_tx));
+
+    let mut conn = RocketChatConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("server", &format!("http://127.0.0.1:{}", port)),
+        text_field("token", "good"),
+    ])
+    .unwrap();
+    conn.connect().await.unwrap();
+    assert_eq!(conn.user_id(), Some("u1"));
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::Identify { user_id } } if user_id == "u1"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::New { user, .. } }
+            if user.username.as_deref() == Some("bot")
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel },
+        } => {
+            assert_eq!(channel.id, "GENERAL");
+            assert_eq!(channel.name.as_deref(), Some("general"));
+            assert_eq!(channel.topic.as_deref(), Some("welcome"));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::Join { channel_id } } if channel_id == "GENERAL"
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel },
+        } => {
+            assert_eq!(channel.channel_type, ChannelType::Direct);
+            assert_eq!(channel.name.as_deref(), Some("alice"));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Join { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+
+    match next_event(&mut rx).await {
+        ConnectionEvent::User {
+            event: UserEvent::New { user, .. },
+        } => {
+            assert_eq!(user.id.as_deref(), Some("u2"));
+            assert_eq!(user.display_name.as_deref(), Some("Alice"));
+            assert_eq!(
+                user.picture,
+                Some(format!("http://127.0.0.1:{}/avatar/alice", port))
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel("GENERAL"));
+            assert_eq!(message.sender_id.as_deref(), Some("u2"));
+            assert_eq!(message.timestamp.timestamp(), 1_700_000_000);
+            assert_eq!(
+                message.content,
+                vec![
+                    MessageFragment::Text("hey ".to_string()),
+                    MessageFragment::Mention {
+                        user_id: "u1".to_string(),
+                        display: "Bot".to_string(),
+                    },
+                    MessageFragment::Text(" look".to_string()),
+                    MessageFragment::Image {
+                        url: format!("http://127.0.0.1:{}/file-upload/f1/cat.png", port),
+                        mime: "image/png".to_string(),
+                    },
+                ]
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event:
+                ChatEvent::Update {
+                    message_id,
+                    new_message,
+                    ..
+                },
+        } => {
+            assert_eq!(message_id, "m1");
+            assert_eq!(new_message.status, MessageStatus::Edited);
+            assert_eq!(
+                new_message.content[0],
+                MessageFragment::Text("hey all".to_string())
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat { event: ChatEvent::Remove { message_id, .. } } if message_id == "m1"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User {
+            event: UserEvent::PresenceChanged { user_id, presence: Presence::Away }
+        } if user_id == "u2"
+    ));
+
+    let handle = conn
+        .send_tracked(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel("GENERAL"),
+                message: Message {
+                    id: None,
+                    sender_id: None,
+                    content: vec![
+                        MessageFragment::Mention {
+                            user_id: "u2".to_string(),
+                            display: "Alice".to_string(),
+                        },
+                        MessageFragment::Text(" nice".to_string()),
+                        MessageFragment::Image {
+                            url: "https://example.com/dog.png".to_string(),
+                            mime: "image/png".to_string(),
+                        },
+                    ],
+                    timestamp: Utc::now(),
+                    message_type: MessageType::CurrentUser,
+                    status: MessageStatus::Sent,
+                    reactions: Vec::new(),
+                    reply_to: None,
+                    thread_id: Some("m0".to_string()),
+                    extra: HashMap::new(),
+                },
+            },
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        handle.await,
+        SendOutcome::Delivered {
+            message_id: Some("sent1".to_string())
+        }
+    );
+    let (method, params) = calls.recv().await.unwrap();
+    assert_eq!(method, "sendMessage");
+    assert_eq!(
+        params[0],
+        json!({
+            "rid": "GENERAL", "msg": "@alice nice", "tmid": "m0",
+            "attachments": [{ "image_url": "https://example.com/dog.png", "image_type": "image/png" }]
+        })
+    );
+
+    conn.send(ConnectionEvent::Chat {
+        event: ChatEvent::ReactionAdd {
+            scope: Scope::channel("GENERAL"),
+            message_id: "sent1".to_string(),
+            user_id: "u1".to_string(),
+            key: oshatori::ReactionKey::Emoji("tada".to_string()),
+        },
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        calls.recv().await.unwrap(),
+        ("setReaction".to_string(), json!([":tada:", "sent1", true]))
+    );
+}
+
+#[tokio::test]
+async fn rocketchat_rejected_token_fails_auth() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (calls_tx, _calls) = mpsc::unbounded_channel();
+    tokio::spawn(serve_realtime(listener, calls_tx));
+
+    let mut conn = RocketChatConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("server", &format!("http://127.0.0.1:{}/", port)),
+        text_field("token", "expired"),
+    ])
+    .unwrap();
+    assert!(matches!(
+        conn.connect().await,
+        Err(ConnectionError::Auth(_))
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed { .. }
+        }
+    ));
+}
@@ -0,0 +1,237 @@
+#![cfg(feature = "rss")]
+
+mod common;
+
+use common::{next_event, serve_http, text_field, Request, Response};
+
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, RssConnection, Scope,
+        StatusEvent,
+    },
+    AuthField, ChannelType, Connection, MessageFragment, MessageType,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{net::TcpListener, sync::mpsc};
+
+fn feeds(value: &str) -> Vec<AuthField> {
+    vec![text_field("feeds", value)]
+}
+
+const RSS: &str = r#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <channel>
+    <title>Release notes</title>
+    <description>What shipped</description>
+    {new}
+    <item>
+      <title>1.0</title>
+      <link>https://example.com/1.0</link>
+      <guid>release-1.0</guid>
+      <pubDate>Mon, 01 Jan 2024 12:00:00 +0000</pubDate>
+      <description><![CDATA[<b>First</b> release]]></description>
+      <dc:creator>saikuru</dc:creator>
+    </item>
+  </channel>
+</rss>"#;
+
+const NEW_ITEM: &str = r#"<item>
+      <title>1.1</title>
+      <link>https://example.com/1.1</link>
+      <guid>release-1.1</guid>
+      <pubDate>Tue, 02 Jan 2024 12:00:00 +0000</pubDate>
+    </item>"#;
+
+const ATOM: &str = r#"<?xml version="1.0"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Blog</title>
+  <entry>
+    <id>tag:example.com,2024:2</id>
+    <title>Second post</title>
+    <link rel="alternate" href="https://example.com/2"/>
+    <updated>2024-01-03T00:00:00Z</updated>
+    <summary>plain text</summary>
+  </entry>
+  <entry>
+    <id>tag:example.com,2024:1</id>
+    <title>First post</title>
+    <link href="https://example.com/1"/>
+    <updated>2024-01-02T00:00:00Z</updated>
+  </entry>
+</feed>"#;
+
+/// A fake web server with an RSS feed at `/rss.xml`, which gains an item once `published` is
+/// set, and an Atom feed at `/atom.xml` that answers `304 Not Modified` once its ETag is sent
+/// back. Each request line is reported on `requests` along with whether it was conditional.
+async fn serve(
+    listener: TcpListener,
+    published: Arc<AtomicBool>,
+    requests: mpsc::UnboundedSender<(String, bool)>,
+) {
+    serve_http(listener, move |request: Request| {
+        let published = published.clone();
+        let requests = requests.clone();
+        async move {
+            let conditional = request.header("if-none-match") == Some("\"v1\"");
+            let _ = requests.send((request.line.clone(), conditional));
+            if request.line.starts_with("GET /rss.xml") {
+                let new = if published.load(Ordering::SeqCst) {
+                    NEW_ITEM
+                } else {
+                    ""
+                };
+                Response::new("200 OK")
+                    .header("Content-Type", "application/rss+xml")
+                    .body(RSS.replace("{new}", new))
+            } else if request.line.starts_with("GET /atom.xml") {
+                if conditional {
+                    Response::new("304 Not Modified")
+                } else {
+                    Response::new("200 OK").header("ETag", "\"v1\"").body(ATOM)
+                }
+            } else {
+                Response::new("404 Not Found")
+            }
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn rss_emits_new_entries_per_feed() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let published = Arc::new(AtomicBool::new(false));
+    let (requests_tx, mut requests) = mpsc::unbounded_channel();
+    tokio::spawn(serve(listener, published.clone(), requests_tx));
+    let rss_url = format!("{}/rss.xml", base);
+    let atom_url = format!("{}/atom.xml", base);
+
+    let mut conn = RssConnection::new().poll_interval(Duration::from_millis(200));
+    let mut rx = conn.subscribe();
+    conn.set_auth(feeds(&format!("{}, {}", rss_url, atom_url)))
+        .unwrap();
+    conn.connect().await.unwrap();
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { .. }
+        }
+    ));
+    for (url, name) in [(&rss_url, "Release notes"), (&atom_url, "Blog")] {
+        match next_event(&mut rx).await {
+            ConnectionEvent::Channel {
+                event: ChannelEvent::New { channel },
+            } => {
+                assert_eq!(&channel.id, url);
+                assert_eq!(channel.name.as_deref(), Some(name));
+                assert_eq!(channel.channel_type, ChannelType::Broadcast);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(matches!(
+            next_event(&mut rx).await,
+            ConnectionEvent::Channel { event: ChannelEvent::Join { channel_id } } if &channel_id == url
+        ));
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+
+    let history = conn.fetch_history(&atom_url, None, 10).await.unwrap();
+    let ids: Vec<_> = history.iter().map(|m| m.id.as_deref().unwrap()).collect();
+    assert_eq!(ids, ["tag:example.com,2024:1", "tag:example.com,2024:2"]);
+    let history = conn.fetch_history(&rss_url, None, 10).await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(
+        history[0].content,
+        vec![
+            MessageFragment::Styled {
+                text: "1.0".to_string(),
+                styles: vec![oshatori::TextStyle::Bold],
+            },
+            MessageFragment::Text("\n".to_string()),
+            MessageFragment::Styled {
+                text: "First".to_string(),
+                styles: vec![oshatori::TextStyle::Bold],
+            },
+            MessageFragment::Text(" release".to_string()),
+            MessageFragment::Text("\n".to_string()),
+            MessageFragment::Url("https://example.com/1.0".to_string()),
+        ]
+    );
+
+    published.store(true, Ordering::SeqCst);
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel(&rss_url));
+            assert_eq!(message.id.as_deref(), Some("release-1.1"));
+            assert_eq!(message.message_type, MessageType::Server);
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    // The Atom feed is unchanged, so later polls ask for it conditionally.
+    loop {
+        let (request_line, conditional) = requests.recv().await.unwrap();
+        if request_line.starts_with("GET /atom.xml") && conditional {
+            break;
+        }
+    }
+
+    assert!(matches!(
+        conn.send(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel(&rss_url),
+                message: history[0].clone(),
+            },
+        })
+        .await,
+        Err(ConnectionError::Unsupported(_))
+    ));
+    conn.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn rss_fails_when_no_feed_can_be_read() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let (requests_tx, _requests) = mpsc::unbounded_channel();
+    tokio::spawn(serve(
+        listener,
+        Arc::new(AtomicBool::new(false)),
+        requests_tx,
+    ));
+
+    let mut conn = RssConnection::new();
+    let _rx = conn.subscribe();
+    conn.set_auth(feeds(&format!("{}/missing.xml", base)))
+        .unwrap();
+    assert!(matches!(
+        conn.connect().await,
+        Err(ConnectionError::Network(_))
+    ));
+
+    conn.set_auth(feeds("not a url")).unwrap();
+    assert!(matches!(
+        conn.connect().await,
+        Err(ConnectionError::Auth(_))
+    ));
+}
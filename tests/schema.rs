@@ -0,0 +1,71 @@
+#![cfg(feature = "schema")]
+
+use std::path::Path;
+
+use oshatori::schema::{breaking_changes, schemas, write_schemas};
+use serde_json::json;
+
+/// Set `OSHATORI_BLESS_SCHEMAS=1` to rewrite the checked-in schemas after an intended change.
+#[test]
+fn schemas_match_checked_in() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("schemas");
+    if std::env::var_os("OSHATORI_BLESS_SCHEMAS").is_some() {
+        write_schemas(&dir).unwrap();
+    }
+
+    for (name, schema) in schemas() {
+        let path = dir.join(format!("{}.json", name));
+        let text = std::fs::read_to_string(&path)
+            .unwrap_or_else(|_| panic!("missing checked-in schema {}", path.display()));
+        let checked_in: serde_json::Value = serde_json::from_str(&text).unwrap();
+
+        let breaking = breaking_changes(&checked_in, &schema);
+        assert!(breaking.is_empty(), "{} broke: {:#?}", name, breaking);
+        assert_eq!(
+            checked_in, schema,
+            "{} changed; rerun with OSHATORI_BLESS_SCHEMAS=1",
+            name
+        );
+    }
+}
+
+#[test]
+fn breaking_changes_ignore_additions() {
+    let old = json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "string" },
+            "kind": { "type": "string", "enum": ["a", "b"] }
+        },
+        "required": ["id"]
+    });
+
+    let added = json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "string" },
+            "kind": { "type": "string", "enum": ["a", "b", "c"] },
+            "extra": { "type": "object" }
+        },
+        "required": ["id"]
+    });
+    assert!(breaking_changes(&old, &added).is_empty());
+
+    let broken = json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "integer" },
+            "kind": { "type": "string", "enum": ["a"] },
+            "owner": { "type": "string" }
+        },
+        "required": ["id", "owner"]
+    });
+    assert_eq!(
+        breaking_changes(&old, &broken),
+        vec![
+            "#: owner became required",
+            "#/properties/id: type changed",
+            "#/properties/kind: enum values removed: b",
+        ]
+    );
+}
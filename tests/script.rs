@@ -0,0 +1,111 @@
+#![cfg(all(feature = "scripting", feature = "mock"))]
+
+mod common;
+
+use common::{chat_message, message_from};
+
+use oshatori::{
+    client::{ScriptAction, ScriptError, ScriptHost, StateClient},
+    connection::{ChatEvent, ConnectionEvent, MockConnection, Scope},
+    Connection,
+};
+
+#[tokio::test]
+async fn script_replies_and_filters() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let mut host = ScriptHost::new();
+    host.load(
+        "bot",
+        r#"
+            fn on_message(message) {
+                if message.text == "!ping" {
+                    reply("pong from " + message.sender);
+                }
+                if message.text.contains("spam") {
+                    drop_event();
+                }
+            }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(host.scripts(), vec!["bot".to_string()]);
+
+    let actions = host
+        .handle(
+            &client,
+            &conn_id,
+            &chat_message(Scope::channel("general"), message_from("user1", "!ping")),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        actions,
+        vec![ScriptAction::Send {
+            scope: Scope::channel("general"),
+            text: "pong from user1".to_string(),
+        }]
+    );
+
+    let mut conn = MockConnection::new();
+    let mut rx = conn.subscribe();
+    assert!(host
+        .dispatch(
+            &client,
+            &conn_id,
+            &chat_message(Scope::channel("general"), message_from("user1", "!ping")),
+            &mut conn
+        )
+        .await
+        .unwrap());
+    assert!(matches!(
+        rx.recv().await,
+        Some(ConnectionEvent::Chat {
+            event: ChatEvent::New { .. }
+        })
+    ));
+    assert!(!host
+        .dispatch(
+            &client,
+            &conn_id,
+            &chat_message(Scope::channel("general"), message_from("user1", "buy spam")),
+            &mut conn
+        )
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn script_is_sandboxed() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let mut host = ScriptHost::new();
+
+    assert!(matches!(
+        host.load("broken", "fn on_message(message) {"),
+        Err(ScriptError::Compile { .. })
+    ));
+    host.load("import", r#"fn on_event(event) { import "fs" as fs; }"#)
+        .unwrap();
+    assert!(matches!(
+        host.handle(
+            &client,
+            &conn_id,
+            &chat_message(Scope::channel("general"), message_from("user1", "hi"))
+        )
+        .await,
+        Err(ScriptError::Runtime { .. })
+    ));
+    assert!(host.unload("import"));
+
+    host.load("spin", "fn on_event(event) { loop {} }").unwrap();
+    assert!(matches!(
+        host.handle(
+            &client,
+            &conn_id,
+            &chat_message(Scope::channel("general"), message_from("user1", "hi"))
+        )
+        .await,
+        Err(ScriptError::Runtime { .. })
+    ));
+}
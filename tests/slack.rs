@@ -0,0 +1,268 @@
+#![cfg(feature = "slack")]
+
+mod common;
+
+use common::{next_event, serve_http, text_field, Request, Response};
+
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionEvent, Scope, SendOutcome, SlackConnection, StatusEvent,
+        UserEvent,
+    },
+    ChannelType, Connection, Message, MessageFragment, MessageStatus, MessageType, ReactionKey,
+    TextStyle,
+};
+use serde_json::{json, Value};
+use std::{collections::HashMap, time::Duration};
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+/// A fake Web API that answers the connect-time calls and reports every other call on
+/// `requests` as its method and body.
+async fn serve_api(
+    listener: TcpListener,
+    socket_port: u16,
+    requests: mpsc::UnboundedSender<(String, Value)>,
+) {
+    serve_http(listener, move |request: Request| {
+        let requests = requests.clone();
+        async move {
+            let authorization = request.header("authorization").unwrap_or_default();
+            let method = request.path().rsplit('/').next().unwrap_or_default();
+            Response::json(match method {
+                "auth.test" => {
+                    assert_eq!(authorization, "Bearer xoxb-bot");
+                    json!({ "ok": true, "user_id": "UBOT", "team": "Team", "team_id": "T1" })
+                }
+                "apps.connections.open" => {
+                    assert_eq!(authorization, "Bearer xapp-app");
+                    json!({ "ok": true, "url": format!("ws://127.0.0.1:{}/", socket_port) })
+                }
+                "conversations.list" => json!({
+                    "ok": true,
+                    "channels": [
+                        { "id": "C1", "name": "general", "is_member": true,
+                          "topic": { "value": "hello" } },
+                        { "id": "D1", "is_im": true, "user": "U2" }
+                    ],
+                    "response_metadata": { "next_cursor": "" }
+                }),
+                "chat.delete" => json!({ "ok": false, "error": "cant_delete_message" }),
+                _ => {
+                    let body = serde_json::from_str(&request.body).unwrap();
+                    let _ = requests.send((method.to_string(), body));
+                    json!({ "ok": true, "ts": "1714979300.000100" })
+                }
+            })
+        }
+    })
+    .await
+}
+
+fn envelope(id: &str, event: Value) -> WsMessage {
+    WsMessage::Text(
+        json!({
+            "envelope_id": id,
+            "type": "events_api",
+            "payload": { "team_id": "T1", "event": event }
+        })
+        .to_string()
+        .into(),
+    )
+}
+
+#[tokio::test]
+async fn slack_socket_mode_round_trip() {
+    let api = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let api_port = api.local_addr().unwrap().port();
+    let sockets = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let socket_port = sockets.local_addr().unwrap().port();
+    let (requests_tx, mut requests) = mpsc::unbounded_channel();
+    tokio::spawn(serve_api(api, socket_port, requests_tx));
+
+    let mut conn = SlackConnection::new();
+    conn.set_auth(vec![
+        text_field("app_token", "xapp-app"),
+        text_field("bot_token", "xoxb-bot"),
+        text_field("api_url", &format!("http://127.0.0.1:{}/api", api_port)),
+    ])
+    .unwrap();
+    let mut rx = conn.subscribe();
+    let (connected, accepted) = tokio::join!(conn.connect(), async {
+        let (socket, _) = sockets.accept().await.unwrap();
+        tokio_tungstenite::accept_async(socket).await.unwrap()
+    });
+    connected.unwrap();
+    let mut socket = accepted;
+    assert_eq!(conn.user_id(), Some("UBOT"));
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { artifact }
+        } if artifact.as_deref() == Some("Team")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User {
+            event: UserEvent::Identify { user_id }
+        } if user_id == "UBOT"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel }
+        } if channel.id == "C1"
+            && channel.topic.as_deref() == Some("hello")
+            && channel.extra.get("team_id") == Some(&json!("T1"))
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Join { channel_id }
+        } if channel_id == "C1"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::New { channel }
+        } if channel.id == "D1" && channel.channel_type == ChannelType::Direct
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Join { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+
+    socket
+        .send(envelope(
+            "env-1",
+            json!({
+                "type": "message", "channel": "C1", "user": "U2", "text": "*hi* <@UBOT>",
+                "ts": "1714979289.000200", "thread_ts": "1714979000.000100"
+            }),
+        ))
+        .await
+        .unwrap();
+    let ack = tokio::time::timeout(Duration::from_secs(5), socket.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(ack.to_text().unwrap()).unwrap(),
+        json!({ "envelope_id": "env-1" })
+    );
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel("C1"));
+            assert_eq!(message.id.as_deref(), Some("1714979289.000200"));
+            assert_eq!(message.thread_id.as_deref(), Some("1714979000.000100"));
+            assert_eq!(message.timestamp.timestamp_micros(), 1714979289000200);
+            assert_eq!(
+                message.content,
+                vec![
+                    MessageFragment::Styled {
+                        text: "hi".to_string(),
+                        styles: vec![TextStyle::Bold],
+                    },
+                    MessageFragment::Text(" ".to_string()),
+                    MessageFragment::Mention {
+                        user_id: "UBOT".to_string(),
+                        display: "UBOT".to_string(),
+                    },
+                ]
+            );
+        }
+        other => panic!("expected a message, got {:?}", other),
+    }
+
+    socket
+        .send(envelope(
+            "env-2",
+            json!({
+                "type": "reaction_added", "user": "U2", "reaction": "tada",
+                "item": { "type": "message", "channel": "C1", "ts": "1714979289.000200" }
+            }),
+        ))
+        .await
+        .unwrap();
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::ReactionAdd { scope, key: ReactionKey::Emoji(name), .. }
+        } if scope == Scope::channel("C1") && name == "tada"
+    ));
+
+    let handle = conn
+        .send_tracked(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel("C1"),
+                message: Message {
+                    id: None,
+                    sender_id: None,
+                    content: vec![MessageFragment::Styled {
+                        text: "a < b".to_string(),
+                        styles: vec![TextStyle::Italic],
+                    }],
+                    timestamp: Utc::now(),
+                    message_type: MessageType::Normal,
+                    status: MessageStatus::Sent,
+                    reactions: Vec::new(),
+                    reply_to: None,
+                    thread_id: Some("1714979000.000100".to_string()),
+                    extra: HashMap::new(),
+                },
+            },
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        handle.await,
+        SendOutcome::Delivered {
+            message_id: Some("1714979300.000100".to_string())
+        }
+    );
+    let (method, body) = requests.recv().await.unwrap();
+    assert_eq!(method, "chat.postMessage");
+    assert_eq!(
+        body,
+        json!({ "channel": "C1", "text": "_a &lt; b_", "thread_ts": "1714979000.000100" })
+    );
+
+    let error = conn
+        .send(ConnectionEvent::Chat {
+            event: ChatEvent::Remove {
+                scope: Scope::channel("C1"),
+                message_id: "1714979289.000200".to_string(),
+            },
+        })
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("cant_delete_message"));
+
+    socket.close(None).await.unwrap();
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { .. }
+        }
+    ));
+}
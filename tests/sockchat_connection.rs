@@ -2,10 +2,10 @@
 
 use chrono::Utc;
 use oshatori::{
-    connection::{ChatEvent, ConnectionEvent, SockchatConnection},
+    connection::{ChatEvent, ConnectionEvent, Scope, SockchatConnection},
     Connection, Message, MessageFragment, MessageStatus, MessageType,
 };
-use std::env;
+use std::{collections::HashMap, env};
 use tokio::time::Duration;
 
 #[tokio::test]
@@ -49,11 +49,15 @@ async fn sockchat_connection() {
         timestamp: Utc::now(),
         message_type: MessageType::Normal,
         status: MessageStatus::Sent,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
     };
 
     conn.send(ConnectionEvent::Chat {
         event: ChatEvent::New {
-            channel_id: None,
+            scope: Scope::Global,
             message: test_message.clone(),
         },
     })
@@ -123,3 +127,871 @@ async fn sockchat_assets() {
 
     conn.disconnect().await.unwrap();
 }
+
+/// A fake emote management API that accepts every request and reports its request line.
+async fn emote_api() -> (String, tokio::sync::mpsc::UnboundedReceiver<String>) {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut buf = vec![0u8; 4096];
+            let read = socket.read(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..read]).to_string();
+            let _ = tx.send(request.lines().next().unwrap_or_default().to_string());
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+        }
+    });
+    (url, rx)
+}
+
+fn emote_management_auth(url: &str) -> Vec<oshatori::AuthField> {
+    vec![oshatori::AuthField {
+        name: "emote_management_api".to_string(),
+        display: None,
+        value: oshatori::FieldValue::Text(Some(url.to_string())),
+        required: false,
+    }]
+}
+
+fn wave_emote() -> oshatori::Asset {
+    oshatori::Asset::Emote {
+        id: Some("wave".to_string()),
+        pattern: ":wave:".to_string(),
+        src: "https://example.com/wave.png".to_string(),
+        source: oshatori::AssetSource::User,
+    }
+}
+
+#[tokio::test]
+async fn sockchat_emote_management_needs_an_endpoint() {
+    use oshatori::{connection::AssetEvent, ConnectionError};
+
+    let mut conn = SockchatConnection::new();
+    assert!(!conn.capabilities().asset_management);
+
+    let result = conn
+        .send(ConnectionEvent::Asset {
+            event: AssetEvent::New {
+                scope: Scope::Global,
+                asset: wave_emote(),
+            },
+        })
+        .await;
+    assert!(matches!(result, Err(ConnectionError::Unsupported(_))));
+
+    let result = conn
+        .send(ConnectionEvent::Asset {
+            event: AssetEvent::Remove {
+                scope: Scope::Global,
+                asset_id: "wave".to_string(),
+            },
+        })
+        .await;
+    assert!(matches!(result, Err(ConnectionError::Unsupported(_))));
+}
+
+#[tokio::test]
+async fn sockchat_cannot_refresh_the_channel_list() {
+    use oshatori::{connection::ChannelEvent, ConnectionError};
+
+    let mut conn = SockchatConnection::new();
+    let result = conn
+        .send(ConnectionEvent::Channel {
+            event: ChannelEvent::ClearList,
+        })
+        .await;
+    assert!(matches!(result, Err(ConnectionError::Unsupported(_))));
+}
+
+#[tokio::test]
+async fn sockchat_emote_management() {
+    use oshatori::connection::AssetEvent;
+
+    let (url, mut requests) = emote_api().await;
+    let mut conn = SockchatConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(emote_management_auth(&url)).unwrap();
+    assert!(conn.capabilities().asset_management);
+
+    conn.send(ConnectionEvent::Asset {
+        event: AssetEvent::New {
+            scope: Scope::Global,
+            asset: wave_emote(),
+        },
+    })
+    .await
+    .unwrap();
+
+    assert!(requests.recv().await.unwrap().starts_with("POST /emotes "));
+    assert!(matches!(
+        rx.recv().await,
+        Some(ConnectionEvent::Asset {
+            event: AssetEvent::New { .. }
+        })
+    ));
+
+    conn.send(ConnectionEvent::Asset {
+        event: AssetEvent::Remove {
+            scope: Scope::Global,
+            asset_id: "wave".to_string(),
+        },
+    })
+    .await
+    .unwrap();
+
+    assert!(requests
+        .recv()
+        .await
+        .unwrap()
+        .starts_with("DELETE /emotes/wave "));
+    assert!(matches!(
+        rx.recv().await,
+        Some(ConnectionEvent::Asset {
+            event: AssetEvent::Remove { .. }
+        })
+    ));
+}
+
+#[tokio::test]
+async fn sockchat_resolves_outbound_assets() {
+    use oshatori::{connection::AssetEvent, ConnectionError};
+
+    let (url, _requests) = emote_api().await;
+    let mut conn = SockchatConnection::new();
+    conn.set_auth(emote_management_auth(&url)).unwrap();
+    conn.send(ConnectionEvent::Asset {
+        event: AssetEvent::New {
+            scope: Scope::Global,
+            asset: wave_emote(),
+        },
+    })
+    .await
+    .unwrap();
+
+    let message = |id: &str| Message {
+        id: None,
+        sender_id: None,
+        content: vec![
+            MessageFragment::Text("hi ".to_string()),
+            MessageFragment::AssetId(id.to_string()),
+        ],
+        timestamp: Utc::now(),
+        message_type: MessageType::CurrentUser,
+        status: MessageStatus::Sent,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
+    };
+
+    let result = conn
+        .send(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::Global,
+                message: message("nope"),
+            },
+        })
+        .await;
+    assert!(matches!(result, Err(ConnectionError::Unsupported(_))));
+
+    let result = conn
+        .send(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::Global,
+                message: message("wave"),
+            },
+        })
+        .await;
+    assert_eq!(result, Err(ConnectionError::Closed));
+}
+
+#[tokio::test]
+async fn sockchat_open_direct_requires_known_user() {
+    use oshatori::ConnectionError;
+
+    let mut conn = SockchatConnection::new();
+    let result = conn.open_direct("2").await;
+    assert!(matches!(result, Err(ConnectionError::NotFound(_))));
+}
+
+#[tokio::test]
+async fn sockchat_has_no_user_directory() {
+    use oshatori::ConnectionError;
+
+    let mut conn = SockchatConnection::new();
+    assert!(matches!(
+        conn.search_users("alice").await,
+        Err(ConnectionError::Unsupported(_))
+    ));
+}
+
+#[tokio::test]
+async fn sockchat_has_no_history_requests() {
+    use oshatori::ConnectionError;
+
+    let mut conn = SockchatConnection::new();
+    assert!(!conn.capabilities().history);
+    let result = conn.fetch_history("lounge", None, 50).await;
+    assert!(matches!(result, Err(ConnectionError::Unsupported(_))));
+}
+
+#[tokio::test]
+async fn sockchat_rejects_edits() {
+    use oshatori::ConnectionError;
+
+    let mut conn = SockchatConnection::new();
+    assert!(!conn.capabilities().editing);
+
+    let result = conn
+        .send(ConnectionEvent::Chat {
+            event: ChatEvent::Update {
+                scope: Scope::Global,
+                message_id: "1".to_string(),
+                new_message: Message {
+                    id: Some("1".to_string()),
+                    sender_id: None,
+                    content: vec![MessageFragment::Text("edited".to_string())],
+                    timestamp: Utc::now(),
+                    message_type: MessageType::CurrentUser,
+                    status: MessageStatus::Sent,
+                    reactions: Vec::new(),
+                    reply_to: None,
+                    thread_id: None,
+                    extra: HashMap::new(),
+                },
+            },
+        })
+        .await;
+    assert!(matches!(result, Err(ConnectionError::Unsupported(_))));
+}
+
+#[tokio::test]
+async fn sockchat_channel_commands_require_connection() {
+    use oshatori::{connection::ChannelEvent, ConnectionError};
+
+    let mut conn = SockchatConnection::new();
+    let result = conn
+        .send(ConnectionEvent::Channel {
+            event: ChannelEvent::Join {
+                channel_id: "lounge".to_string(),
+            },
+        })
+        .await;
+    assert_eq!(result, Err(ConnectionError::Closed));
+}
+
+#[tokio::test]
+async fn sockchat_moderation_is_global_only() {
+    use oshatori::{connection::ModerationEvent, ConnectionError};
+
+    let mut conn = SockchatConnection::new();
+    assert!(conn.capabilities().moderation);
+
+    let result = conn
+        .send(ConnectionEvent::Moderation {
+            event: ModerationEvent::Kick {
+                scope: Scope::channel("lounge"),
+                user_id: "2".to_string(),
+                reason: None,
+            },
+        })
+        .await;
+    assert!(matches!(result, Err(ConnectionError::Unsupported(_))));
+
+    assert!(!conn.capabilities().topics);
+    let result = conn
+        .send(ConnectionEvent::Channel {
+            event: oshatori::connection::ChannelEvent::TopicChanged {
+                channel_id: "lounge".to_string(),
+                topic: Some("hi".to_string()),
+                set_by: None,
+            },
+        })
+        .await;
+    assert!(matches!(result, Err(ConnectionError::Unsupported(_))));
+
+    let result = conn
+        .send(ConnectionEvent::Moderation {
+            event: ModerationEvent::Ban {
+                scope: Scope::Global,
+                user_id: "2".to_string(),
+                reason: None,
+                until: None,
+            },
+        })
+        .await;
+    assert_eq!(result, Err(ConnectionError::Closed));
+}
+
+#[tokio::test]
+async fn sockchat_reports_moderation_once_confirmed() {
+    use futures_util::{SinkExt, StreamExt};
+    use oshatori::{
+        connection::{ModerationEvent, StatusEvent},
+        ConnectionError,
+    };
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+        while let Some(Ok(frame)) = ws.next().await {
+            let WsMessage::Text(text) = frame else {
+                continue;
+            };
+            let reply = match text.split('\t').nth(2).unwrap_or("") {
+                "" => "1\ty\t1\tme\tinherit\t0\tlounge\t2000",
+                "/kick 2" => "3\t2\tbob\tkick\t1700000000\t20",
+                "/silence 2" => "2\t1700000000\t-1\t0\x0csilok\x0cbob\t21\t10010",
+                "/pardon 2" => "2\t1700000000\t-1\t1\x0cnotban\x0cbob\t22\t10010",
+                _ => continue,
+            };
+            ws.send(WsMessage::Text(reply.into())).await.unwrap();
+        }
+    });
+
+    let mut conn = SockchatConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(sockchat_auth(&url)).unwrap();
+    conn.connect().await.unwrap();
+
+    let moderate = |event| ConnectionEvent::Moderation { event };
+    assert!(matches!(
+        conn.send(moderate(ModerationEvent::Kick {
+            scope: Scope::Global,
+            user_id: "2".to_string(),
+            reason: Some("spam".to_string()),
+        }))
+        .await,
+        Err(ConnectionError::Unsupported(_))
+    ));
+    for event in [
+        ModerationEvent::Kick {
+            scope: Scope::Global,
+            user_id: "2".to_string(),
+            reason: None,
+        },
+        ModerationEvent::Mute {
+            scope: Scope::Global,
+            user_id: "2".to_string(),
+            until: None,
+        },
+        ModerationEvent::Unban {
+            scope: Scope::Global,
+            user_id: "2".to_string(),
+        },
+    ] {
+        conn.send(moderate(event)).await.unwrap();
+    }
+
+    let mut seen = Vec::new();
+    while let Ok(Some(event)) = tokio::time::timeout(Duration::from_millis(300), rx.recv()).await {
+        match event {
+            ConnectionEvent::Moderation { event } => seen.push(format!("{:?}", event)),
+            ConnectionEvent::Status {
+                event: StatusEvent::Error { message },
+            } => seen.push(message),
+            _ => {}
+        }
+    }
+    assert_eq!(seen.len(), 3, "{:?}", seen);
+    assert!(seen[0].starts_with("Kick"));
+    assert!(seen[1].starts_with("Mute"));
+    assert!(seen[2].contains("notban"));
+    conn.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn sockchat_disconnect_emits_final_status() {
+    use oshatori::connection::StatusEvent;
+
+    let mut conn = SockchatConnection::new();
+    let mut rx = conn.subscribe();
+
+    conn.disconnect().await.unwrap();
+    assert!(matches!(
+        rx.try_recv(),
+        Ok(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { .. }
+        })
+    ));
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn sockchat_failed_send_marks_message_failed() {
+    use oshatori::ConnectionError;
+
+    let mut conn = SockchatConnection::new();
+    let mut rx = conn.subscribe();
+
+    let result = conn
+        .send_tracked(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel("lounge"),
+                message: Message {
+                    id: Some("local-1".to_string()),
+                    sender_id: None,
+                    content: vec![MessageFragment::Text("hello".to_string())],
+                    timestamp: Utc::now(),
+                    message_type: MessageType::CurrentUser,
+                    status: MessageStatus::Sent,
+                    reactions: Vec::new(),
+                    reply_to: None,
+                    thread_id: None,
+                    extra: HashMap::new(),
+                },
+            },
+        })
+        .await;
+    assert!(matches!(result, Err(ConnectionError::Closed)));
+
+    match rx.try_recv() {
+        Ok(ConnectionEvent::Chat {
+            event:
+                ChatEvent::Update {
+                    scope,
+                    message_id,
+                    new_message,
+                },
+        }) => {
+            assert_eq!(scope, Scope::channel("lounge"));
+            assert_eq!(message_id, "local-1");
+            assert_eq!(new_message.status, MessageStatus::Failed);
+        }
+        other => panic!("expected failed update, got {:?}", other),
+    }
+}
+
+/// A fake sockchat server that signs user 1 into `lounge`, waits for three messages and then
+/// echoes the second and first back out of order before answering with a bot error.
+async fn serve_echoes(listener: tokio::net::TcpListener) {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+    let mut said = Vec::new();
+    while let Some(Ok(frame)) = ws.next().await {
+        let WsMessage::Text(text) = frame else {
+            continue;
+        };
+        let parts: Vec<&str> = text.split('\t').collect();
+        let replies = match parts[0] {
+            "1" => vec!["1\ty\t1\tme\tinherit\t0\tlounge\t2000".to_string()],
+            "2" => {
+                said.push(parts[2].to_string());
+                if said.len() < 3 {
+                    continue;
+                }
+                vec![
+                    format!("2\t1700000000\t1\t{}\t11\t10010", said[1]),
+                    format!("2\t1700000000\t1\t{}\t10\t10010", said[0]),
+                    "2\t1700000000\t-1\t1\x0ccmdna\x0c/x\t12\t10010".to_string(),
+                ]
+            }
+            _ => Vec::new(),
+        };
+        for reply in replies {
+            ws.send(WsMessage::Text(reply.into())).await.unwrap();
+        }
+    }
+}
+
+fn sockchat_auth(url: &str) -> Vec<oshatori::AuthField> {
+    use oshatori::{AuthField, FieldValue};
+
+    let field = |name: &str, value: FieldValue| AuthField {
+        name: name.to_string(),
+        display: None,
+        value,
+        required: true,
+    };
+    vec![
+        field("sockchat_url", FieldValue::Text(Some(url.to_string()))),
+        field("token", FieldValue::Password(Some("token".to_string()))),
+        field("uid", FieldValue::Text(Some("1".to_string()))),
+    ]
+}
+
+fn lounge_message(text: &str) -> ConnectionEvent {
+    ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("lounge"),
+            message: Message {
+                id: None,
+                sender_id: None,
+                content: vec![MessageFragment::Text(text.to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::CurrentUser,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: None,
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    }
+}
+
+#[tokio::test]
+async fn sockchat_matches_echoes_to_sends() {
+    use oshatori::connection::SendOutcome;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    tokio::spawn(serve_echoes(listener));
+
+    let mut conn = SockchatConnection::new();
+    let _rx = conn.subscribe();
+    assert_eq!(conn.capabilities().max_message_length, None);
+    conn.set_auth(sockchat_auth(&url)).unwrap();
+    conn.connect().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(conn.capabilities().max_message_length, Some(2000));
+    let long = vec![MessageFragment::Text("x".repeat(4_001))];
+    let stats = oshatori::utils::compose::measure(&conn, &long);
+    assert_eq!(stats.remaining, Some(-2001));
+    assert_eq!(stats.parts, 3);
+
+    let first = conn.send_tracked(lounge_message("first")).await.unwrap();
+    let second = conn.send_tracked(lounge_message("second")).await.unwrap();
+    let third = conn.send_tracked(lounge_message("third")).await.unwrap();
+
+    let outcome = |handle| tokio::time::timeout(Duration::from_secs(5), handle);
+    assert_eq!(
+        outcome(first).await.unwrap(),
+        SendOutcome::Delivered {
+            message_id: Some("10".to_string())
+        }
+    );
+    assert_eq!(
+        outcome(second).await.unwrap(),
+        SendOutcome::Delivered {
+            message_id: Some("11".to_string())
+        }
+    );
+    assert!(matches!(
+        outcome(third).await.unwrap(),
+        SendOutcome::Failed(reason) if reason.contains("cmdna")
+    ));
+    conn.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn sockchat_unacknowledged_send_fails() {
+    use oshatori::connection::{ConnectionOptions, SendOutcome};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+        while let Some(Ok(_)) = futures_util::StreamExt::next(&mut ws).await {}
+    });
+
+    let options = ConnectionOptions::new().ack_timeout(Duration::from_millis(100));
+    let mut conn = SockchatConnection::with_options(options);
+    let _rx = conn.subscribe();
+    conn.set_auth(sockchat_auth(&url)).unwrap();
+    conn.connect().await.unwrap();
+
+    let handle = conn.send_tracked(lounge_message("hello")).await.unwrap();
+    assert!(matches!(
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap(),
+        SendOutcome::Failed(_)
+    ));
+    conn.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn sockchat_remembers_backlog_authors() {
+    use futures_util::{SinkExt, StreamExt};
+    use oshatori::ConnectionError;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+        while let Some(Ok(_)) = ws.next().await {
+            for reply in [
+                "1\ty\t1\tme\tinherit\t0\tlounge\t2000",
+                "7\t1\t1700000000\t2\talice\tinherit\t0\thello\t5\t0\t10010",
+            ] {
+                ws.send(WsMessage::Text(reply.into())).await.unwrap();
+            }
+        }
+    });
+
+    let mut conn = SockchatConnection::new();
+    let _rx = conn.subscribe();
+    conn.set_auth(sockchat_auth(&url)).unwrap();
+    conn.connect().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let profile = conn.fetch_profile("2").await.unwrap();
+    assert_eq!(profile.username.as_deref(), Some("alice"));
+    assert!(profile.presence.is_none());
+    assert!(matches!(
+        conn.fetch_profile("3").await,
+        Err(ConnectionError::NotFound(_))
+    ));
+    conn.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn sockchat_connects_through_http_proxy() {
+    use oshatori::{
+        client::ConnectionStatus,
+        connection::{ConnectionOptions, Proxy},
+        AuthField, ConnectionError, FieldValue,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let proxy = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0u8; 1024];
+        let read = socket.read(&mut buf).await.unwrap();
+        socket
+            .write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n")
+            .await
+            .unwrap();
+        String::from_utf8_lossy(&buf[..read]).to_string()
+    });
+
+    let options =
+        ConnectionOptions::new().proxy(Proxy::http("127.0.0.1", port).credentials("user", "pass"));
+    let mut conn = SockchatConnection::with_options(options);
+    let field = |name: &str, value: FieldValue| AuthField {
+        name: name.to_string(),
+        display: None,
+        value,
+        required: true,
+    };
+    conn.set_auth(vec![
+        field(
+            "sockchat_url",
+            FieldValue::Text(Some("wss://chat.example.com/sock".to_string())),
+        ),
+        field("token", FieldValue::Password(Some("token".to_string()))),
+        field("uid", FieldValue::Text(Some("1".to_string()))),
+    ])
+    .unwrap();
+
+    let result = conn.connect().await;
+    assert!(matches!(result, Err(ConnectionError::Network(_))));
+    assert_eq!(conn.status(), ConnectionStatus::Disconnected);
+
+    let request = proxy.await.unwrap();
+    assert!(request.starts_with("CONNECT chat.example.com:443 HTTP/1.1\r\n"));
+    assert!(request.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+}
+
+#[tokio::test]
+async fn sockchat_rejects_invalid_tls_config() {
+    use oshatori::{
+        connection::{ConnectionOptions, TlsConfig},
+        AuthField, ConnectionError, FieldValue,
+    };
+
+    let options =
+        ConnectionOptions::new().tls(TlsConfig::new().root_certificate(b"not a certificate"));
+    let mut conn = SockchatConnection::with_options(options);
+    let field = |name: &str, value: FieldValue| AuthField {
+        name: name.to_string(),
+        display: None,
+        value,
+        required: true,
+    };
+    conn.set_auth(vec![
+        field(
+            "sockchat_url",
+            FieldValue::Text(Some("wss://chat.example.com/sock".to_string())),
+        ),
+        field("token", FieldValue::Password(Some("token".to_string()))),
+        field("uid", FieldValue::Text(Some("1".to_string()))),
+    ])
+    .unwrap();
+
+    assert!(matches!(
+        conn.connect().await,
+        Err(ConnectionError::Other(_))
+    ));
+}
+
+#[tokio::test]
+async fn sockchat_session_roundtrip() {
+    use oshatori::connection::SockchatSession;
+
+    let mut conn = SockchatConnection::new();
+    assert_eq!(conn.session().await, SockchatSession::default());
+
+    let mut session = SockchatSession {
+        user_id: Some("1".to_string()),
+        ..Default::default()
+    };
+    session
+        .last_sequence
+        .insert("lounge".to_string(), "42".to_string());
+    let saved = serde_json::to_string(&session).unwrap();
+
+    conn.resume(serde_json::from_str(&saved).unwrap()).await;
+    assert_eq!(conn.session().await, session);
+}
+
+#[tokio::test]
+async fn sockchat_preflight_reports_issues() {
+    use oshatori::{
+        connection::{PreflightSeverity, Reachability},
+        AuthField, FieldValue,
+    };
+    use tokio::net::TcpListener;
+
+    let field = |name: &str, value: FieldValue| AuthField {
+        name: name.to_string(),
+        display: None,
+        value,
+        required: false,
+    };
+
+    let mut conn = SockchatConnection::new();
+    conn.set_auth(vec![
+        field(
+            "sockchat_url",
+            FieldValue::Text(Some("http://chat.example".to_string())),
+        ),
+        field("token", FieldValue::Password(Some("two words".to_string()))),
+        field("topic_pattern", FieldValue::Text(Some("(".to_string()))),
+    ])
+    .unwrap();
+    let report = conn.preflight(false).await;
+    assert!(!report.is_ok());
+    assert_eq!(report.reachability, Reachability::NotChecked);
+    for name in ["sockchat_url", "token", "uid", "topic_pattern"] {
+        let issues = report.issues_for(name);
+        assert_eq!(issues.len(), 1, "{}", name);
+        assert_eq!(issues[0].severity, PreflightSeverity::Error);
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    conn.set_auth(vec![
+        field(
+            "sockchat_url",
+            FieldValue::Text(Some(format!("ws://127.0.0.1:{}/", port))),
+        ),
+        field("token", FieldValue::Password(Some("token".to_string()))),
+        field("uid", FieldValue::Text(Some("1".to_string()))),
+    ])
+    .unwrap();
+    let report = conn.preflight(true).await;
+    assert!(report.is_ok(), "{:?}", report);
+    assert!(matches!(
+        report.reachability,
+        Reachability::Reachable { .. }
+    ));
+
+    drop(listener);
+    let report = conn.preflight(true).await;
+    assert!(matches!(
+        report.reachability,
+        Reachability::Unreachable { .. }
+    ));
+}
+
+#[tokio::test]
+async fn sockchat_rejects_invalid_patterns_as_config() {
+    use oshatori::{AuthField, ConnectionError, FieldValue};
+
+    let field = |name: &str, value: &str| AuthField {
+        name: name.to_string(),
+        display: None,
+        value: FieldValue::Text(Some(value.to_string())),
+        required: false,
+    };
+
+    let mut conn = SockchatConnection::new();
+    conn.set_auth(vec![
+        field("sockchat_url", "ws://127.0.0.1:1/"),
+        AuthField {
+            value: FieldValue::Password(Some("token".to_string())),
+            ..field("token", "")
+        },
+        field("uid", "1"),
+        field("maintenance_pattern", "("),
+    ])
+    .unwrap();
+    let result = conn.connect().await;
+    assert!(
+        matches!(result, Err(ConnectionError::Config(_))),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn sockchat_measures_composition() {
+    use oshatori::{utils::compose::measure, TextStyle};
+
+    let conn = SockchatConnection::new();
+    let content = vec![
+        MessageFragment::Styled {
+            text: "hey".to_string(),
+            styles: vec![TextStyle::Bold],
+        },
+        MessageFragment::Text(" there".to_string()),
+    ];
+    assert_eq!(conn.render_text(&content), "[b]hey[/b] there");
+
+    // The limit is only known once the server announces it on join.
+    let stats = measure(&conn, &content);
+    assert_eq!(stats.length, 16);
+    assert_eq!(stats.limit, None);
+    assert_eq!(stats.remaining, None);
+    assert_eq!(stats.parts, 1);
+}
+
+#[test]
+fn sockchat_roles_map_ranks_to_names() {
+    use oshatori::{connection::SockchatRoles, Role};
+
+    let roles = SockchatRoles::new()
+        .with_rank(1, "Member", None)
+        .with_rank(5, "Moderator", Some([0, 128, 0, 255]))
+        .with_rank(10, "Admin", Some([255, 0, 0, 255]));
+    let conn = SockchatConnection::new().with_roles(roles.clone());
+    assert_eq!(conn.roles(), &roles);
+
+    let mut role = Role {
+        rank: 7,
+        ..Role::default()
+    };
+    roles.apply(&mut role);
+    assert_eq!(role.name.as_deref(), Some("Moderator"));
+    assert_eq!(role.color, Some([0, 128, 0, 255]));
+
+    let mut guest = Role::default();
+    roles.apply(&mut guest);
+    assert_eq!(guest.name, None);
+    assert_eq!(roles.get(10).map(|role| role.name.as_str()), Some("Admin"));
+}
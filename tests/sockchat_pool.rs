@@ -0,0 +1,146 @@
+#![cfg(feature = "sockchat")]
+
+mod common;
+
+use common::chat;
+
+use oshatori::{
+    connection::{
+        ConnectionError, RateLimit, Scope, SockchatConnection, SockchatPool, ThrottleMode,
+    },
+    AuthField, Connection, FieldValue,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+/// Serves the emote list and avatars, counting requests per path.
+async fn serve_http(listener: TcpListener, hits: Arc<HashMap<&'static str, AtomicUsize>>) {
+    loop {
+        let (socket, _) = listener.accept().await.unwrap();
+        let hits = hits.clone();
+        tokio::spawn(async move {
+            let mut stream = BufReader::new(socket);
+            let mut request_line = String::new();
+            stream.read_line(&mut request_line).await.unwrap();
+            loop {
+                let mut header = String::new();
+                stream.read_line(&mut header).await.unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+            }
+            let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+            let (key, body) = if path.starts_with("/emotes") {
+                (
+                    "emotes",
+                    r#"[{"uri":"https://e.x/a.png","strings":["a"],"min_rank":0}]"#,
+                )
+            } else {
+                ("avatar", "PNG")
+            };
+            hits[key].fetch_add(1, Ordering::SeqCst);
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.get_mut().write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Accepts websocket connections and keeps them open without answering.
+async fn serve_chat(listener: TcpListener) {
+    loop {
+        let (socket, _) = listener.accept().await.unwrap();
+        tokio::spawn(async move {
+            let mut socket = tokio_tungstenite::accept_async(socket).await.unwrap();
+            while let Some(Ok(_)) = futures_util::StreamExt::next(&mut socket).await {}
+        });
+    }
+}
+
+fn text(name: &str, value: String) -> AuthField {
+    AuthField {
+        name: name.to_string(),
+        display: None,
+        value: FieldValue::Text(Some(value)),
+        required: false,
+    }
+}
+
+#[tokio::test]
+async fn sockchat_pool_shares_server_resources() {
+    let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_port = http.local_addr().unwrap().port();
+    let chat_server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let chat_port = chat_server.local_addr().unwrap().port();
+    let hits = Arc::new(HashMap::from([
+        ("emotes", AtomicUsize::new(0)),
+        ("avatar", AtomicUsize::new(0)),
+    ]));
+    tokio::spawn(serve_http(http, hits.clone()));
+    tokio::spawn(serve_chat(chat_server));
+
+    let pool = Arc::new(SockchatPool::new().with_rate_limit(RateLimit {
+        burst: 1,
+        per_second: 0.0,
+        mode: ThrottleMode::Reject,
+    }));
+    let mut connections = Vec::new();
+    for uid in ["1", "2"] {
+        let mut conn = SockchatConnection::new().with_pool(pool.clone());
+        conn.set_auth(vec![
+            text("sockchat_url", format!("ws://127.0.0.1:{}/", chat_port)),
+            AuthField {
+                name: "token".to_string(),
+                display: None,
+                value: FieldValue::Password(Some(format!("token{}", uid))),
+                required: true,
+            },
+            text("uid", uid.to_string()),
+            text("asset_api", format!("http://127.0.0.1:{}", http_port)),
+            text(
+                "pfp_url",
+                format!("http://127.0.0.1:{}/avatars/{{uid}}", http_port),
+            ),
+        ])
+        .unwrap();
+        let _rx = conn.subscribe();
+        conn.connect().await.unwrap();
+        connections.push(conn);
+    }
+    assert_eq!(hits["emotes"].load(Ordering::SeqCst), 1);
+    assert_eq!(
+        pool.servers(),
+        vec![format!("ws://127.0.0.1:{}/", chat_port)]
+    );
+
+    let first = connections[0].fetch_avatar("7").await.unwrap();
+    let second = connections[1].fetch_avatar("7").await.unwrap();
+    assert_eq!(&*first, b"PNG");
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(hits["avatar"].load(Ordering::SeqCst), 1);
+
+    connections[0]
+        .send(chat(Scope::Global, "hello"))
+        .await
+        .unwrap();
+    assert!(matches!(
+        connections[1].send(chat(Scope::Global, "hello")).await,
+        Err(ConnectionError::RateLimited(_))
+    ));
+
+    pool.invalidate(&format!("ws://127.0.0.1:{}/", chat_port));
+    connections[1].connect().await.unwrap();
+    assert_eq!(hits["emotes"].load(Ordering::SeqCst), 2);
+}
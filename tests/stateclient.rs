@@ -1,14 +1,21 @@
 #![cfg(feature = "mock")]
 
+use async_trait::async_trait;
 use chrono::Utc;
 use oshatori::{
-    client::{ConnectionStatus, StateClient},
+    client::{
+        ChannelSnapshot, ConnectionStatus, EscalationPolicy, EscalationReason, Retention,
+        RetentionPolicy, StateClient, SummaryStatus, LOBBY_CHANNEL_ID,
+    },
     connection::{
-        ChannelEvent, ChatEvent, ConnectionEvent, MockConnection, StatusEvent, UserEvent,
+        ChannelEvent, ChatEvent, ConnectionEvent, MockConnection, ModerationEvent, Scope,
+        StatusEvent, UserEvent,
     },
-    Channel, ChannelType, Connection, Message, MessageFragment, MessageStatus, MessageType,
-    Profile,
+    AuthField, Channel, ChannelType, CommandArg, CommandSpec, Connection, ConnectionError, Message,
+    MessageFragment, MessageStatus, MessageType, Presence, Profile, Protocol, ReactionKey,
 };
+use std::collections::HashMap;
+use tokio::sync::mpsc;
 
 #[tokio::test]
 async fn stateclient_basic() {
@@ -52,6 +59,65 @@ async fn stateclient_status_events() {
     assert_eq!(state.status, ConnectionStatus::Disconnected);
 }
 
+#[tokio::test]
+async fn stateclient_status_lifecycle() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let status = |event| ConnectionEvent::Status { event };
+
+    client
+        .process(&conn_id, status(StatusEvent::Connecting))
+        .await;
+    let state = client.get_connection(&conn_id).await.unwrap();
+    assert_eq!(state.status, ConnectionStatus::Connecting);
+
+    client
+        .process(&conn_id, status(StatusEvent::Reconnecting { attempt: 2 }))
+        .await;
+    client
+        .process(
+            &conn_id,
+            status(StatusEvent::Error {
+                message: "timed out".to_string(),
+            }),
+        )
+        .await;
+    let state = client.get_connection(&conn_id).await.unwrap();
+    assert_eq!(state.status, ConnectionStatus::Reconnecting { attempt: 2 });
+    assert_eq!(state.last_error.as_deref(), Some("timed out"));
+
+    client
+        .process(
+            &conn_id,
+            status(StatusEvent::AuthFailed {
+                reason: "bad token".to_string(),
+            }),
+        )
+        .await;
+    client
+        .process(
+            &conn_id,
+            status(StatusEvent::Disconnected { artifact: None }),
+        )
+        .await;
+    let state = client.get_connection(&conn_id).await.unwrap();
+    assert_eq!(
+        state.status,
+        ConnectionStatus::AuthFailed {
+            reason: "bad token".to_string()
+        }
+    );
+    let summary = client.connection_summary(&conn_id).await.unwrap();
+    assert!(matches!(summary.status, SummaryStatus::AuthFailed { .. }));
+
+    client
+        .process(&conn_id, status(StatusEvent::Connected { artifact: None }))
+        .await;
+    let state = client.get_connection(&conn_id).await.unwrap();
+    assert_eq!(state.status, ConnectionStatus::Connected);
+    assert!(state.last_error.is_none());
+}
+
 #[tokio::test]
 async fn stateclient_channel_events() {
     let client = StateClient::new();
@@ -66,6 +132,8 @@ async fn stateclient_channel_events() {
                         id: "general".to_string(),
                         name: Some("General".to_string()),
                         channel_type: ChannelType::Group,
+                        topic: None,
+                        extra: HashMap::new(),
                     },
                 },
             },
@@ -105,6 +173,8 @@ async fn stateclient_user_events() {
                         id: "general".to_string(),
                         name: None,
                         channel_type: ChannelType::Group,
+                        topic: None,
+                        extra: HashMap::new(),
                     },
                 },
             },
@@ -116,13 +186,16 @@ async fn stateclient_user_events() {
             &conn_id,
             ConnectionEvent::User {
                 event: UserEvent::New {
-                    channel_id: Some("general".to_string()),
+                    scope: Scope::channel("general"),
                     user: Profile {
                         id: Some("user1".to_string()),
                         username: Some("testuser".to_string()),
                         display_name: None,
                         color: None,
                         picture: None,
+                        presence: None,
+                        role: None,
+                        extra: HashMap::new(),
                     },
                 },
             },
@@ -138,6 +211,77 @@ async fn stateclient_user_events() {
 }
 
 #[tokio::test]
+async fn stateclient_profile_history() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let profile = |name: &str| Profile {
+        id: Some("user1".to_string()),
+        username: Some(name.to_string()),
+        ..Profile::default()
+    };
+    let said_at = |timestamp| Message {
+        id: None,
+        sender_id: Some("user1".to_string()),
+        content: Vec::new(),
+        timestamp,
+        message_type: MessageType::Normal,
+        status: MessageStatus::Delivered,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
+    };
+
+    let long_ago = Utc::now() - chrono::Duration::days(1);
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::New {
+                    scope: Scope::Global,
+                    user: profile("ann"),
+                },
+            },
+        )
+        .await;
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    let before_rename = Utc::now();
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    for name in ["anna", "anna"] {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::User {
+                    event: UserEvent::Update {
+                        scope: Scope::Global,
+                        user_id: "user1".to_string(),
+                        new_user: profile(name),
+                    },
+                },
+            )
+            .await;
+    }
+
+    let name_at = |timestamp| {
+        let client = &client;
+        let conn_id = &conn_id;
+        async move {
+            client
+                .sender_profile(conn_id, &said_at(timestamp))
+                .await
+                .and_then(|p| p.username)
+        }
+    };
+    assert_eq!(name_at(long_ago).await.as_deref(), Some("ann"));
+    assert_eq!(name_at(before_rename).await.as_deref(), Some("ann"));
+    assert_eq!(name_at(Utc::now()).await.as_deref(), Some("anna"));
+
+    let state = client.get_connection(&conn_id).await.unwrap();
+    assert_eq!(state.profile_history["user1"].len(), 2);
+}
+
+#[tokio::test]
+#[allow(clippy::redundant_field_names)]
 async fn stateclient_chat_events() {
     let client = StateClient::new();
     let conn_id = client.track("mock").await;
@@ -151,6 +295,8 @@ async fn stateclient_chat_events() {
                         id: "general".to_string(),
                         name: None,
                         channel_type: ChannelType::Group,
+                        topic: None,
+                        extra: HashMap::new(),
                     },
                 },
             },
@@ -164,6 +310,10 @@ async fn stateclient_chat_events() {
         timestamp: Utc::now(),
         message_type: MessageType::Normal,
         status: MessageStatus::Sent,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
     };
 
     client
@@ -171,7 +321,7 @@ async fn stateclient_chat_events() {
             &conn_id,
             ConnectionEvent::Chat {
                 event: ChatEvent::New {
-                    channel_id: Some("general".to_string()),
+                    scope: Scope::channel("general"),
                     message: message,
                 },
             },
@@ -187,7 +337,7 @@ async fn stateclient_chat_events() {
             &conn_id,
             ConnectionEvent::Chat {
                 event: ChatEvent::Remove {
-                    channel_id: Some("general".to_string()),
+                    scope: Scope::channel("general"),
                     message_id: "msg1".to_string(),
                 },
             },
@@ -220,3 +370,2379 @@ async fn stateclient_with_mock_connection() {
 
     handle.abort();
 }
+
+#[tokio::test]
+async fn stateclient_snapshots() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::New {
+                    scope: Scope::channel("general"),
+                    user: Profile {
+                        id: Some("user1".to_string()),
+                        username: Some("testuser".to_string()),
+                        display_name: None,
+                        color: None,
+                        picture: None,
+                        presence: None,
+                        role: None,
+                        extra: HashMap::new(),
+                    },
+                },
+            },
+        )
+        .await;
+
+    let summary = client.connection_summary(&conn_id).await.unwrap();
+    assert_eq!(summary.status, SummaryStatus::Disconnected);
+    assert_eq!(summary.channels.len(), 1);
+    assert_eq!(summary.user_count, 1);
+
+    let snapshot = client.channel_snapshot(&conn_id, "general").await.unwrap();
+    assert_eq!(snapshot.users.len(), 1);
+
+    let json = serde_json::to_string(&snapshot).unwrap();
+    let decoded: ChannelSnapshot = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.channel.id, "general");
+}
+
+#[tokio::test]
+async fn stateclient_retention_policy() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    let mut policy = RetentionPolicy::default();
+    policy
+        .by_type
+        .insert(ChannelType::Group, Retention::MaxMessages(2));
+    policy
+        .by_channel
+        .insert("archive".to_string(), Retention::KeepAll);
+    client.set_retention_policy(policy).await;
+
+    for channel_id in ["general", "archive"] {
+        for i in 0..3 {
+            client
+                .process(
+                    &conn_id,
+                    ConnectionEvent::Chat {
+                        event: ChatEvent::New {
+                            scope: Scope::channel(channel_id),
+                            message: Message {
+                                id: Some(format!("msg{}", i)),
+                                sender_id: None,
+                                content: vec![MessageFragment::Text("test".to_string())],
+                                timestamp: Utc::now(),
+                                message_type: MessageType::Normal,
+                                status: MessageStatus::Sent,
+                                reactions: Vec::new(),
+                                reply_to: None,
+                                thread_id: None,
+                                extra: HashMap::new(),
+                            },
+                        },
+                    },
+                )
+                .await;
+        }
+    }
+
+    let messages = client.get_messages(&conn_id, "general").await;
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].id, Some("msg1".to_string()));
+    assert_eq!(client.get_messages(&conn_id, "archive").await.len(), 3);
+}
+
+#[tokio::test]
+async fn stateclient_state_at() {
+    let client = StateClient::new();
+    client.enable_journal(Some(1)).await;
+    let conn_id = client.track("mock").await;
+    let before = Utc::now();
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Status {
+                event: StatusEvent::Connected { artifact: None },
+            },
+        )
+        .await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+    let connected_at = Utc::now();
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Status {
+                event: StatusEvent::Disconnected { artifact: None },
+            },
+        )
+        .await;
+
+    let state = client.state_at(&conn_id, connected_at).await.unwrap();
+    assert_eq!(state.status, ConnectionStatus::Connected);
+
+    let state = client.state_at(&conn_id, Utc::now()).await.unwrap();
+    assert_eq!(state.status, ConnectionStatus::Disconnected);
+
+    assert!(client.state_at(&conn_id, before).await.is_none());
+}
+
+#[tokio::test]
+async fn stateclient_state_at_uses_past_retention() {
+    let client = StateClient::new();
+    client.enable_journal(None).await;
+    let conn_id = client.track("mock").await;
+
+    for i in 0..3 {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::Chat {
+                    event: ChatEvent::New {
+                        scope: Scope::channel("general"),
+                        message: Message {
+                            id: Some(format!("msg{}", i)),
+                            sender_id: None,
+                            content: vec![MessageFragment::Text("test".to_string())],
+                            timestamp: Utc::now(),
+                            message_type: MessageType::Normal,
+                            status: MessageStatus::Sent,
+                            reactions: Vec::new(),
+                            reply_to: None,
+                            thread_id: None,
+                            extra: HashMap::new(),
+                        },
+                    },
+                },
+            )
+            .await;
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+    let before_policy = Utc::now();
+    tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+
+    client
+        .set_retention_policy(RetentionPolicy {
+            default: Retention::MaxMessages(1),
+            ..RetentionPolicy::default()
+        })
+        .await;
+    assert_eq!(client.get_messages(&conn_id, "general").await.len(), 1);
+
+    let state = client.state_at(&conn_id, before_policy).await.unwrap();
+    assert_eq!(state.channels["general"].messages.len(), 3);
+    let state = client.state_at(&conn_id, Utc::now()).await.unwrap();
+    assert_eq!(state.channels["general"].messages.len(), 1);
+}
+
+#[tokio::test]
+async fn stateclient_state_at_replays_bulk_operations() {
+    let client = StateClient::new();
+    client.enable_journal(Some(2)).await;
+    let conn_id = client.track("mock").await;
+    let message = |id: &str, age: i64| ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("general"),
+            message: Message {
+                id: Some(id.to_string()),
+                sender_id: None,
+                content: vec![MessageFragment::Text("test".to_string())],
+                timestamp: Utc::now() - chrono::Duration::days(age),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: None,
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    };
+
+    client.process(&conn_id, message("old", 10)).await;
+    client.process(&conn_id, message("new", 0)).await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+    let before_purge = Utc::now();
+    tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+    client
+        .purge_messages_before(Utc::now() - chrono::Duration::days(1), |_| {})
+        .await;
+
+    let ids = |state: oshatori::client::ConnectionState| -> Vec<Option<String>> {
+        state.channels["general"]
+            .messages
+            .iter()
+            .map(|message| message.id.clone())
+            .collect()
+    };
+    let state = client.state_at(&conn_id, before_purge).await.unwrap();
+    assert_eq!(ids(state).len(), 2);
+    let state = client.state_at(&conn_id, Utc::now()).await.unwrap();
+    assert_eq!(ids(state), vec![Some("new".to_string())]);
+
+    // Folding past the purge keeps it in the journal's base state.
+    client.process(&conn_id, message("later", 0)).await;
+    client.process(&conn_id, message("latest", 0)).await;
+    let state = client.state_at(&conn_id, Utc::now()).await.unwrap();
+    assert_eq!(ids(state).len(), 3);
+}
+
+#[tokio::test]
+async fn stateclient_typing_events() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::TypingStart {
+                    channel_id: "general".to_string(),
+                    user_id: "user1".to_string(),
+                },
+            },
+        )
+        .await;
+
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    assert_eq!(channel.typing_users(), vec!["user1".to_string()]);
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope: Scope::channel("general"),
+                    message: Message {
+                        id: Some("msg1".to_string()),
+                        sender_id: Some("user1".to_string()),
+                        content: vec![MessageFragment::Text("test".to_string())],
+                        timestamp: Utc::now(),
+                        message_type: MessageType::Normal,
+                        status: MessageStatus::Sent,
+                        reactions: Vec::new(),
+                        reply_to: None,
+                        thread_id: None,
+                        extra: HashMap::new(),
+                    },
+                },
+            },
+        )
+        .await;
+
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    assert!(channel.typing_users().is_empty());
+}
+
+#[tokio::test]
+async fn stateclient_presence_events() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::New {
+                    scope: Scope::channel("general"),
+                    user: Profile {
+                        id: Some("user1".to_string()),
+                        presence: Some(Presence::Online),
+                        ..Profile::default()
+                    },
+                },
+            },
+        )
+        .await;
+
+    assert_eq!(
+        client.get_presence(&conn_id, "user1").await,
+        Some(Presence::Online)
+    );
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::PresenceChanged {
+                    user_id: "user1".to_string(),
+                    presence: Presence::Custom("lunch".to_string()),
+                },
+            },
+        )
+        .await;
+
+    assert_eq!(
+        client.get_presence(&conn_id, "user1").await,
+        Some(Presence::Custom("lunch".to_string()))
+    );
+    let user = client.get_user(&conn_id, "user1").await.unwrap();
+    assert_eq!(user.presence, Some(Presence::Custom("lunch".to_string())));
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::Remove {
+                    scope: Scope::channel("general"),
+                    user_id: "user1".to_string(),
+                },
+            },
+        )
+        .await;
+    assert_eq!(client.get_presence(&conn_id, "user1").await, None);
+
+    for user_id in ["user2", "user3"] {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::User {
+                    event: UserEvent::New {
+                        scope: Scope::channel("general"),
+                        user: Profile {
+                            id: Some(user_id.to_string()),
+                            presence: Some(Presence::Away),
+                            ..Profile::default()
+                        },
+                    },
+                },
+            )
+            .await;
+    }
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Channel {
+                event: ChannelEvent::ClearList,
+            },
+        )
+        .await;
+    assert_eq!(client.get_presence(&conn_id, "user2").await, None);
+    assert_eq!(client.get_presence(&conn_id, "user3").await, None);
+}
+
+#[tokio::test]
+async fn stateclient_presence_partitions() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let join = |id: &str, presence: Presence| ConnectionEvent::User {
+        event: UserEvent::New {
+            scope: Scope::channel("general"),
+            user: Profile {
+                id: Some(id.to_string()),
+                presence: Some(presence),
+                ..Profile::default()
+            },
+        },
+    };
+
+    client
+        .process(&conn_id, join("user1", Presence::Online))
+        .await;
+    client
+        .process(&conn_id, join("user2", Presence::Away))
+        .await;
+    client
+        .process(&conn_id, join("user3", Presence::Offline))
+        .await;
+
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    assert_eq!(channel.online_count(), 2);
+    assert_eq!(channel.offline_count(), 1);
+    assert_eq!(channel.total_count(), 3);
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::PresenceChanged {
+                    user_id: "user1".to_string(),
+                    presence: Presence::Offline,
+                },
+            },
+        )
+        .await;
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::Remove {
+                    scope: Scope::channel("general"),
+                    user_id: "user2".to_string(),
+                },
+            },
+        )
+        .await;
+
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    assert_eq!(channel.online_count(), 0);
+    assert_eq!(channel.offline_count(), 2);
+    assert!(!channel.is_online("user1"));
+    assert_eq!(channel.offline_users().len(), 2);
+
+    client.process(&conn_id, join("user3", Presence::Dnd)).await;
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    assert_eq!(channel.online_count(), 1);
+    assert_eq!(channel.total_count(), 2);
+    assert_eq!(channel.online_users()[0].id.as_deref(), Some("user3"));
+}
+
+#[tokio::test]
+async fn stateclient_moderation_tracking() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let moderate = |event| ConnectionEvent::Moderation { event };
+
+    client
+        .process(
+            &conn_id,
+            moderate(ModerationEvent::Ban {
+                scope: Scope::channel("general"),
+                user_id: "user1".to_string(),
+                reason: Some("spam".to_string()),
+                until: None,
+            }),
+        )
+        .await;
+    client
+        .process(
+            &conn_id,
+            moderate(ModerationEvent::Ban {
+                scope: Scope::channel("general"),
+                user_id: "user2".to_string(),
+                reason: None,
+                until: Some(Utc::now() - chrono::Duration::minutes(1)),
+            }),
+        )
+        .await;
+    client
+        .process(
+            &conn_id,
+            moderate(ModerationEvent::Mute {
+                scope: Scope::Global,
+                user_id: "user3".to_string(),
+                until: None,
+            }),
+        )
+        .await;
+
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    assert!(channel.is_banned("user1"));
+    assert!(!channel.is_banned("user2"));
+    assert_eq!(channel.bans["user1"].channel_id.as_deref(), Some("general"));
+    let lobby = client
+        .get_channel(&conn_id, LOBBY_CHANNEL_ID)
+        .await
+        .unwrap();
+    assert!(lobby.is_muted("user3"));
+
+    client
+        .process(
+            &conn_id,
+            moderate(ModerationEvent::Unban {
+                scope: Scope::channel("general"),
+                user_id: "user1".to_string(),
+            }),
+        )
+        .await;
+    client
+        .process(
+            &conn_id,
+            moderate(ModerationEvent::Unmute {
+                scope: Scope::Global,
+                user_id: "user3".to_string(),
+            }),
+        )
+        .await;
+
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    assert!(!channel.is_banned("user1"));
+    let lobby = client
+        .get_channel(&conn_id, LOBBY_CHANNEL_ID)
+        .await
+        .unwrap();
+    assert!(!lobby.is_muted("user3"));
+}
+
+#[tokio::test]
+async fn stateclient_threads() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let post = |id: &str, thread_id: Option<&str>| ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("general"),
+            message: Message {
+                id: Some(id.to_string()),
+                sender_id: Some("user1".to_string()),
+                content: vec![MessageFragment::Text(id.to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: thread_id.map(str::to_string),
+                thread_id: thread_id.map(str::to_string),
+                extra: HashMap::new(),
+            },
+        },
+    };
+
+    client.process(&conn_id, post("root", None)).await;
+    client.process(&conn_id, post("other", None)).await;
+    client.process(&conn_id, post("child1", Some("root"))).await;
+    client.process(&conn_id, post("child2", Some("root"))).await;
+
+    let thread = client.get_thread(&conn_id, "general", "root").await;
+    let ids: Vec<_> = thread.iter().filter_map(|m| m.id.as_deref()).collect();
+    assert_eq!(ids, vec!["root", "child1", "child2"]);
+    assert_eq!(thread[1].reply_to.as_deref(), Some("root"));
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::Remove {
+                    scope: Scope::channel("general"),
+                    message_id: "child1".to_string(),
+                },
+            },
+        )
+        .await;
+    let thread = client.get_thread(&conn_id, "general", "root").await;
+    assert_eq!(thread.len(), 2);
+    assert_eq!(
+        client.get_thread(&conn_id, "general", "other").await.len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn stateclient_priority_notifications() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let mut priority = client.priority_notifications();
+    let say = |channel_id: &str, sender_id: &str, text: &str| ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel(channel_id),
+            message: Message {
+                id: None,
+                sender_id: Some(sender_id.to_string()),
+                content: vec![MessageFragment::Text(text.to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: None,
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    };
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::Identify {
+                    user_id: "me".to_string(),
+                },
+            },
+        )
+        .await;
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::New {
+                    scope: Scope::Global,
+                    user: Profile {
+                        id: Some("me".to_string()),
+                        username: Some("Alice".to_string()),
+                        ..Profile::default()
+                    },
+                },
+            },
+        )
+        .await;
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Channel {
+                event: ChannelEvent::New {
+                    channel: Channel {
+                        id: "dm".to_string(),
+                        name: None,
+                        channel_type: ChannelType::Direct,
+                        topic: None,
+                        extra: HashMap::new(),
+                    },
+                },
+            },
+        )
+        .await;
+
+    client
+        .process(&conn_id, say("general", "bob", "hi @alicea"))
+        .await;
+    client
+        .process(&conn_id, say("general", "me", "@alice"))
+        .await;
+    client
+        .process(&conn_id, say("general", "bob", "ping @ALICE!"))
+        .await;
+    client.process(&conn_id, say("dm", "bob", "psst")).await;
+
+    let first = priority.try_recv().unwrap();
+    assert_eq!(first.reason, EscalationReason::Mention);
+    assert_eq!(first.scope, Scope::channel("general"));
+    let second = priority.try_recv().unwrap();
+    assert_eq!(second.reason, EscalationReason::DirectMessage);
+    assert!(priority.try_recv().is_err());
+
+    client
+        .set_escalation_policy(EscalationPolicy::disabled())
+        .await;
+    client.process(&conn_id, say("dm", "bob", "again")).await;
+    assert!(priority.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn stateclient_processor_escalates() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let mut priority = client.priority_notifications();
+    let (tx, rx) = mpsc::unbounded_channel();
+    let processor = client.spawn_processor(conn_id.clone(), rx);
+
+    tx.send(ConnectionEvent::Channel {
+        event: ChannelEvent::New {
+            channel: Channel {
+                id: "dm".to_string(),
+                name: None,
+                channel_type: ChannelType::Direct,
+                topic: None,
+                extra: HashMap::new(),
+            },
+        },
+    })
+    .unwrap();
+    tx.send(ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("dm"),
+            message: Message {
+                id: None,
+                sender_id: Some("bob".to_string()),
+                content: vec![MessageFragment::Text("psst".to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: None,
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    })
+    .unwrap();
+    drop(tx);
+    processor.await.unwrap();
+
+    let notification = priority.try_recv().unwrap();
+    assert_eq!(notification.reason, EscalationReason::DirectMessage);
+    assert_eq!(notification.connection_id, conn_id);
+}
+
+#[tokio::test]
+async fn stateclient_reactions() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope: Scope::channel("general"),
+                    message: Message {
+                        id: Some("msg1".to_string()),
+                        sender_id: Some("user1".to_string()),
+                        content: vec![MessageFragment::Text("test".to_string())],
+                        timestamp: Utc::now(),
+                        message_type: MessageType::Normal,
+                        status: MessageStatus::Sent,
+                        reactions: Vec::new(),
+                        reply_to: None,
+                        thread_id: None,
+                        extra: HashMap::new(),
+                    },
+                },
+            },
+        )
+        .await;
+
+    for user_id in ["user1", "user2", "user2"] {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::Chat {
+                    event: ChatEvent::ReactionAdd {
+                        scope: Scope::channel("general"),
+                        message_id: "msg1".to_string(),
+                        user_id: user_id.to_string(),
+                        key: ReactionKey::Emoji("👍".to_string()),
+                    },
+                },
+            )
+            .await;
+    }
+
+    let messages = client.get_messages(&conn_id, "general").await;
+    assert_eq!(messages[0].reactions.len(), 1);
+    assert_eq!(messages[0].reactions[0].user_ids.len(), 2);
+
+    for user_id in ["user1", "user2"] {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::Chat {
+                    event: ChatEvent::ReactionRemove {
+                        scope: Scope::channel("general"),
+                        message_id: "msg1".to_string(),
+                        user_id: user_id.to_string(),
+                        key: ReactionKey::Emoji("👍".to_string()),
+                    },
+                },
+            )
+            .await;
+    }
+
+    let messages = client.get_messages(&conn_id, "general").await;
+    assert!(messages[0].reactions.is_empty());
+}
+
+#[tokio::test]
+async fn stateclient_update_keeps_reactions() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let message = Message {
+        id: Some("msg1".to_string()),
+        sender_id: Some("user1".to_string()),
+        content: vec![MessageFragment::Text("test".to_string())],
+        timestamp: Utc::now(),
+        message_type: MessageType::Normal,
+        status: MessageStatus::Sent,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
+    };
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope: Scope::channel("general"),
+                    message: message.clone(),
+                },
+            },
+        )
+        .await;
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::ReactionAdd {
+                    scope: Scope::channel("general"),
+                    message_id: "msg1".to_string(),
+                    user_id: "user2".to_string(),
+                    key: ReactionKey::Emoji("👍".to_string()),
+                },
+            },
+        )
+        .await;
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::Update {
+                    scope: Scope::channel("general"),
+                    message_id: "msg1".to_string(),
+                    new_message: Message {
+                        content: vec![MessageFragment::Text("edited".to_string())],
+                        status: MessageStatus::Edited,
+                        ..message
+                    },
+                },
+            },
+        )
+        .await;
+
+    let messages = client.get_messages(&conn_id, "general").await;
+    assert_eq!(
+        messages[0].content,
+        vec![MessageFragment::Text("edited".to_string())]
+    );
+    assert_eq!(messages[0].status, MessageStatus::Edited);
+    assert_eq!(messages[0].reactions.len(), 1);
+    assert_eq!(messages[0].reactions[0].user_ids, vec!["user2".to_string()]);
+}
+
+#[tokio::test]
+async fn stateclient_receipts() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    for id in ["msg1", "msg2"] {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::Chat {
+                    event: ChatEvent::New {
+                        scope: Scope::channel("general"),
+                        message: Message {
+                            id: Some(id.to_string()),
+                            sender_id: Some("user1".to_string()),
+                            content: vec![MessageFragment::Text("test".to_string())],
+                            timestamp: Utc::now(),
+                            message_type: MessageType::CurrentUser,
+                            status: MessageStatus::Sent,
+                            reactions: Vec::new(),
+                            reply_to: None,
+                            thread_id: None,
+                            extra: HashMap::new(),
+                        },
+                    },
+                },
+            )
+            .await;
+    }
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::DeliveryAck {
+                    message_id: "msg1".to_string(),
+                },
+            },
+        )
+        .await;
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::ReadMarker {
+                    channel_id: "general".to_string(),
+                    user_id: "user2".to_string(),
+                    message_id: "msg1".to_string(),
+                },
+            },
+        )
+        .await;
+
+    let messages = client.get_messages(&conn_id, "general").await;
+    assert_eq!(messages[0].status, MessageStatus::Delivered);
+    assert_eq!(messages[1].status, MessageStatus::Sent);
+
+    assert_eq!(
+        client.seen_by(&conn_id, "general", "msg1").await,
+        vec!["user2".to_string()]
+    );
+    assert!(client.seen_by(&conn_id, "general", "msg2").await.is_empty());
+}
+
+#[tokio::test]
+async fn stateclient_watches() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    let post = |id: &str, text: &str| ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("general"),
+            message: Message {
+                id: Some(id.to_string()),
+                sender_id: Some("user1".to_string()),
+                content: vec![MessageFragment::Text(text.to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Delivered,
+                reactions: Vec::new(),
+                reply_to: None,
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    };
+
+    client.process(&conn_id, post("msg1", "buy $ABC now")).await;
+
+    let watch_id = client
+        .add_watch(&conn_id, "general", r"\$[A-Z]+")
+        .await
+        .unwrap()
+        .unwrap();
+
+    client
+        .process(&conn_id, post("msg2", "$XYZ and $ABC"))
+        .await;
+
+    let matches = client
+        .get_watch_matches(&conn_id, "general", &watch_id)
+        .await;
+    assert_eq!(matches.len(), 3);
+    assert_eq!(matches[0].message_id, Some("msg1".to_string()));
+    assert_eq!((matches[0].start, matches[0].end), (4, 8));
+    assert_eq!(matches[2].text, "$ABC");
+
+    assert!(client.add_watch(&conn_id, "general", "(").await.is_err());
+}
+
+#[tokio::test]
+async fn stateclient_lobby() {
+    use oshatori::client::{channel_scope, scope_channel_id};
+
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    let notice = ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::Global,
+            message: Message {
+                id: Some("notice1".to_string()),
+                sender_id: None,
+                content: vec![MessageFragment::Text("Server restarting soon".to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Server,
+                status: MessageStatus::Delivered,
+                reactions: Vec::new(),
+                reply_to: None,
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    };
+    client.process(&conn_id, notice).await;
+
+    let lobby = client
+        .get_channel(&conn_id, LOBBY_CHANNEL_ID)
+        .await
+        .unwrap();
+    assert_eq!(lobby.channel.channel_type, ChannelType::Broadcast);
+    assert_eq!(lobby.messages.len(), 1);
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Channel {
+                event: ChannelEvent::ClearList,
+            },
+        )
+        .await;
+    assert_eq!(
+        client.get_messages(&conn_id, LOBBY_CHANNEL_ID).await.len(),
+        1
+    );
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::Remove {
+                    scope: Scope::Global,
+                    message_id: "notice1".to_string(),
+                },
+            },
+        )
+        .await;
+    assert!(client
+        .get_messages(&conn_id, LOBBY_CHANNEL_ID)
+        .await
+        .is_empty());
+
+    assert_eq!(scope_channel_id(&Scope::Global), LOBBY_CHANNEL_ID);
+    assert_eq!(scope_channel_id(&Scope::channel("general")), "general");
+    assert_eq!(channel_scope(LOBBY_CHANNEL_ID), Scope::Global);
+}
+
+#[tokio::test]
+async fn stateclient_bans() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    let kick = |ban: bool, until| ConnectionEvent::Channel {
+        event: ChannelEvent::Kick {
+            scope: Scope::channel("general"),
+            reason: Some("Spam".to_string()),
+            ban,
+            until,
+        },
+    };
+
+    client.process(&conn_id, kick(false, None)).await;
+    assert!(client.get_connection(&conn_id).await.unwrap().ban.is_none());
+
+    let until = Utc::now() + chrono::Duration::hours(1);
+    client.process(&conn_id, kick(true, Some(until))).await;
+    let ban = client.get_connection(&conn_id).await.unwrap().ban.unwrap();
+    assert_eq!(ban.reason, Some("Spam".to_string()));
+    assert_eq!(ban.until, Some(until));
+    assert!(ban.is_active());
+
+    client
+        .process(
+            &conn_id,
+            kick(true, Some(Utc::now() - chrono::Duration::hours(1))),
+        )
+        .await;
+    let ban = client.get_connection(&conn_id).await.unwrap().ban.unwrap();
+    assert!(!ban.is_active());
+}
+
+#[tokio::test]
+async fn stateclient_outbound_deletion() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let mut conn = MockConnection::new();
+    let mut rx = conn.subscribe();
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope: Scope::channel("general"),
+                    message: Message {
+                        id: Some("msg1".to_string()),
+                        sender_id: Some("me".to_string()),
+                        content: vec![MessageFragment::Text("oops".to_string())],
+                        timestamp: Utc::now(),
+                        message_type: MessageType::CurrentUser,
+                        status: MessageStatus::Delivered,
+                        reactions: Vec::new(),
+                        reply_to: None,
+                        thread_id: None,
+                        extra: HashMap::new(),
+                    },
+                },
+            },
+        )
+        .await;
+
+    assert!(conn.capabilities().deletion);
+    conn.send(ConnectionEvent::Chat {
+        event: ChatEvent::Remove {
+            scope: Scope::channel("general"),
+            message_id: "msg1".to_string(),
+        },
+    })
+    .await
+    .unwrap();
+
+    let echoed = rx.recv().await.unwrap();
+    client.process(&conn_id, echoed).await;
+    assert!(client.get_messages(&conn_id, "general").await.is_empty());
+}
+
+#[tokio::test]
+async fn stateclient_commands() {
+    let client = StateClient::new();
+    let first = client.track("mock").await;
+    let second = client.track("mock").await;
+
+    let command = |name: &str| CommandSpec {
+        name: name.to_string(),
+        args: vec![CommandArg {
+            name: "channel".to_string(),
+            required: true,
+        }],
+        description: None,
+    };
+    client
+        .register_commands(&first, vec![command("join"), command("leave")])
+        .await;
+    client
+        .register_commands(&second, vec![command("join")])
+        .await;
+
+    let names: Vec<String> = client
+        .get_commands(&first, "/")
+        .await
+        .into_iter()
+        .map(|c| c.name)
+        .collect();
+    assert_eq!(names, vec!["join", "leave"]);
+
+    assert_eq!(client.get_commands(&first, "/le").await.len(), 1);
+    assert_eq!(client.find_commands("jo").await.len(), 2);
+    assert!(client.find_commands("ban").await.is_empty());
+}
+
+#[tokio::test]
+async fn stateclient_completions() {
+    use oshatori::{client::CompletionKind, connection::AssetEvent, Asset, AssetSource};
+
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    let emote = |id: &str, pattern: &str| ConnectionEvent::Asset {
+        event: AssetEvent::New {
+            scope: Scope::Global,
+            asset: Asset::Emote {
+                id: Some(id.to_string()),
+                pattern: pattern.to_string(),
+                src: format!("https://example.com/{}.png", id),
+                source: AssetSource::Server,
+            },
+        },
+    };
+    client.process(&conn_id, emote("wave", ":wave:")).await;
+    client.process(&conn_id, emote("wink", ":wink:")).await;
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::New {
+                    scope: Scope::channel("general"),
+                    user: Profile {
+                        id: Some("user1".to_string()),
+                        username: Some("Walter".to_string()),
+                        ..Default::default()
+                    },
+                },
+            },
+        )
+        .await;
+
+    assert_eq!(client.complete(&conn_id, None, ":w").await.len(), 2);
+    let users = client.complete(&conn_id, Some("general"), "wa").await;
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].kind, CompletionKind::User);
+    assert!(client
+        .complete(&conn_id, Some("random"), "wa")
+        .await
+        .is_empty());
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Asset {
+                event: AssetEvent::Remove {
+                    scope: Scope::Global,
+                    asset_id: "wave".to_string(),
+                },
+            },
+        )
+        .await;
+    let emotes = client.complete(&conn_id, None, ":W").await;
+    assert_eq!(emotes.len(), 1);
+    assert_eq!(emotes[0].text, ":wink:");
+
+    client
+        .process(&conn_id, emote("smile", r":(?:smile|happy\.face):"))
+        .await;
+    client.process(&conn_id, emote("wild", r":w.*:")).await;
+    let emotes = client.complete(&conn_id, None, ":").await;
+    let texts: Vec<&str> = emotes.iter().map(|c| c.text.as_str()).collect();
+    assert_eq!(texts, vec![":happy.face:", ":smile:", ":wink:"]);
+    assert!(emotes[..2].iter().all(|c| c.id == "smile"));
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Asset {
+                event: AssetEvent::Remove {
+                    scope: Scope::Global,
+                    asset_id: "smile".to_string(),
+                },
+            },
+        )
+        .await;
+    assert!(client.complete(&conn_id, None, ":h").await.is_empty());
+}
+
+#[tokio::test]
+async fn stateclient_annotations() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope: Scope::channel("support"),
+                    message: Message {
+                        id: Some("msg1".to_string()),
+                        sender_id: Some("user1".to_string()),
+                        content: vec![MessageFragment::Text("it broke".to_string())],
+                        timestamp: Utc::now(),
+                        message_type: MessageType::Normal,
+                        status: MessageStatus::Delivered,
+                        reactions: Vec::new(),
+                        reply_to: None,
+                        thread_id: None,
+                        extra: HashMap::new(),
+                    },
+                },
+            },
+        )
+        .await;
+
+    assert!(
+        client
+            .annotate(&conn_id, "support", "msg1", "status", "triaged")
+            .await
+    );
+    assert!(
+        !client
+            .annotate(&conn_id, "support", "missing", "status", "triaged")
+            .await
+    );
+
+    let snapshot = client.channel_snapshot(&conn_id, "support").await.unwrap();
+    assert_eq!(snapshot.annotations["msg1"]["status"], "triaged");
+
+    client
+        .remove_annotation(&conn_id, "support", "msg1", "status")
+        .await;
+    assert!(client
+        .get_annotations(&conn_id, "support", "msg1")
+        .await
+        .is_empty());
+
+    client
+        .annotate(&conn_id, "support", "msg1", "status", "fixed")
+        .await;
+    client
+        .set_retention_policy(RetentionPolicy {
+            default: Retention::MaxMessages(1),
+            ..RetentionPolicy::default()
+        })
+        .await;
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope: Scope::channel("support"),
+                    message: Message {
+                        id: Some("msg2".to_string()),
+                        sender_id: Some("user1".to_string()),
+                        content: vec![MessageFragment::Text("still broken".to_string())],
+                        timestamp: Utc::now(),
+                        message_type: MessageType::Normal,
+                        status: MessageStatus::Delivered,
+                        reactions: Vec::new(),
+                        reply_to: None,
+                        thread_id: None,
+                        extra: HashMap::new(),
+                    },
+                },
+            },
+        )
+        .await;
+    assert!(client
+        .get_annotations(&conn_id, "support", "msg1")
+        .await
+        .is_empty());
+}
+
+#[tokio::test]
+async fn stateclient_wipe_forgets_message_bookkeeping() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let post = |id: &str, thread_id: Option<&str>| ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("support"),
+            message: Message {
+                id: Some(id.to_string()),
+                sender_id: Some("user1".to_string()),
+                content: vec![MessageFragment::Text(id.to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Delivered,
+                reactions: Vec::new(),
+                reply_to: thread_id.map(str::to_string),
+                thread_id: thread_id.map(str::to_string),
+                extra: HashMap::new(),
+            },
+        },
+    };
+
+    client.process(&conn_id, post("root", None)).await;
+    client.process(&conn_id, post("child", Some("root"))).await;
+    assert!(
+        client
+            .annotate(&conn_id, "support", "root", "status", "triaged")
+            .await
+    );
+    let channel = client.get_channel(&conn_id, "support").await.unwrap();
+    assert!(!channel.annotations.is_empty());
+    assert!(!channel.activity.is_empty());
+    assert!(!channel.threads.is_empty());
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Channel {
+                event: ChannelEvent::Wipe {
+                    scope: Scope::channel("support"),
+                },
+            },
+        )
+        .await;
+
+    let channel = client.get_channel(&conn_id, "support").await.unwrap();
+    assert!(channel.messages.is_empty());
+    assert!(channel.annotations.is_empty());
+    assert!(channel.activity.is_empty());
+    assert!(channel.threads.is_empty());
+    assert!(client
+        .get_thread(&conn_id, "support", "root")
+        .await
+        .is_empty());
+}
+
+#[tokio::test]
+async fn stateclient_id_generator() {
+    use oshatori::utils::ids::SequenceGenerator;
+
+    let client = StateClient::new().with_id_generator(SequenceGenerator::new("conn-"));
+    assert_eq!(client.track("mock").await, "conn-1");
+    assert_eq!(client.track("mock").await, "conn-2");
+    assert_eq!(
+        client
+            .add_watch("conn-1", "general", "hello")
+            .await
+            .unwrap(),
+        Some("conn-3".to_string())
+    );
+}
+
+struct HistoryConnection {
+    messages: Vec<Message>,
+}
+
+#[async_trait]
+impl Connection for HistoryConnection {
+    fn set_auth(&mut self, _auth: Vec<AuthField>) -> Result<(), ConnectionError> {
+        Ok(())
+    }
+
+    async fn connect(&mut self) -> Result<(), ConnectionError> {
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), ConnectionError> {
+        Ok(())
+    }
+
+    async fn send(&mut self, _event: ConnectionEvent) -> Result<(), ConnectionError> {
+        Ok(())
+    }
+
+    async fn fetch_profile(&mut self, user_id: &str) -> Result<Profile, ConnectionError> {
+        Ok(Profile {
+            id: Some(user_id.to_string()),
+            username: Some(format!("{}-name", user_id)),
+            ..Default::default()
+        })
+    }
+
+    async fn fetch_history(
+        &mut self,
+        _channel_id: &str,
+        before: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<Message>, ConnectionError> {
+        let end = before
+            .and_then(|id| self.messages.iter().position(|m| m.id == Some(id.clone())))
+            .unwrap_or(self.messages.len());
+        Ok(self.messages[end.saturating_sub(limit)..end].to_vec())
+    }
+
+    fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ConnectionEvent> {
+        mpsc::unbounded_channel().1
+    }
+
+    fn protocol_spec(&self) -> Protocol {
+        Protocol {
+            name: "History".to_string(),
+            auth: None,
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        ConnectionStatus::Connected
+    }
+}
+
+#[tokio::test]
+async fn stateclient_load_older_messages() {
+    let client = StateClient::new();
+    let conn_id = client.track("history").await;
+
+    let message = |id: usize| Message {
+        id: Some(format!("msg{}", id)),
+        sender_id: Some("user1".to_string()),
+        content: vec![MessageFragment::Text(format!("message {}", id))],
+        timestamp: Utc::now(),
+        message_type: MessageType::Normal,
+        status: MessageStatus::Delivered,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
+    };
+    let mut connection = HistoryConnection {
+        messages: (1..=5).map(message).collect(),
+    };
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope: Scope::channel("general"),
+                    message: message(5),
+                },
+            },
+        )
+        .await;
+
+    let loaded = client
+        .load_older_messages(&conn_id, "general", &mut connection, 2)
+        .await
+        .unwrap();
+    assert_eq!(loaded, 2);
+
+    let loaded = client
+        .load_older_messages(&conn_id, "general", &mut connection, 10)
+        .await
+        .unwrap();
+    assert_eq!(loaded, 2);
+
+    let ids: Vec<String> = client
+        .get_messages(&conn_id, "general")
+        .await
+        .into_iter()
+        .filter_map(|m| m.id)
+        .collect();
+    assert_eq!(ids, vec!["msg1", "msg2", "msg3", "msg4", "msg5"]);
+
+    let mut mock = MockConnection::new();
+    assert!(matches!(
+        client
+            .load_older_messages(&conn_id, "general", &mut mock, 10)
+            .await,
+        Err(ConnectionError::Unsupported(_))
+    ));
+}
+
+#[tokio::test]
+async fn stateclient_resolve_user() {
+    let client = StateClient::new();
+    let conn_id = client.track("history").await;
+    let mut connection = HistoryConnection {
+        messages: Vec::new(),
+    };
+
+    assert!(client.get_user(&conn_id, "user9").await.is_none());
+    let user = client
+        .resolve_user(&conn_id, "user9", &mut connection)
+        .await
+        .unwrap();
+    assert_eq!(user.username, Some("user9-name".to_string()));
+    assert!(client.get_user(&conn_id, "user9").await.is_some());
+
+    let mut mock = MockConnection::new();
+    assert!(matches!(
+        client.resolve_user(&conn_id, "user10", &mut mock).await,
+        Err(ConnectionError::Unsupported(_))
+    ));
+}
+
+#[tokio::test]
+async fn stateclient_trending_messages() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+
+    for message_id in ["msg1", "msg2", "msg3"] {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::Chat {
+                    event: ChatEvent::New {
+                        scope: Scope::channel("general"),
+                        message: Message {
+                            id: Some(message_id.to_string()),
+                            sender_id: Some("user1".to_string()),
+                            content: vec![MessageFragment::Text("test".to_string())],
+                            timestamp: Utc::now(),
+                            message_type: MessageType::Normal,
+                            status: MessageStatus::Sent,
+                            reactions: Vec::new(),
+                            reply_to: None,
+                            thread_id: None,
+                            extra: HashMap::new(),
+                        },
+                    },
+                },
+            )
+            .await;
+    }
+
+    let reactions = [
+        ("msg1", "user1"),
+        ("msg2", "user1"),
+        ("msg2", "user2"),
+        ("msg2", "user2"),
+        ("msg2", "user3"),
+    ];
+    for (message_id, user_id) in reactions {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::Chat {
+                    event: ChatEvent::ReactionAdd {
+                        scope: Scope::channel("general"),
+                        message_id: message_id.to_string(),
+                        user_id: user_id.to_string(),
+                        key: ReactionKey::Emoji("🔥".to_string()),
+                    },
+                },
+            )
+            .await;
+    }
+
+    let trending = client
+        .trending_messages(&conn_id, "general", chrono::Duration::minutes(5), 10)
+        .await;
+    let ranked: Vec<(Option<String>, usize)> = trending
+        .into_iter()
+        .map(|(m, count)| (m.id, count))
+        .collect();
+    assert_eq!(
+        ranked,
+        vec![(Some("msg2".to_string()), 3), (Some("msg1".to_string()), 1)]
+    );
+
+    let top = client
+        .trending_messages(&conn_id, "general", chrono::Duration::minutes(5), 1)
+        .await;
+    assert_eq!(top.len(), 1);
+    assert!(client
+        .trending_messages(&conn_id, "general", chrono::Duration::zero(), 10)
+        .await
+        .is_empty());
+}
+
+#[tokio::test]
+async fn stateclient_trending_counts_replies() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let post = |id: &str, reply_to: Option<&str>| ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("general"),
+            message: Message {
+                id: Some(id.to_string()),
+                sender_id: Some("user1".to_string()),
+                content: vec![MessageFragment::Text(id.to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: reply_to.map(str::to_string),
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    };
+
+    client.process(&conn_id, post("question", None)).await;
+    client.process(&conn_id, post("aside", None)).await;
+    client
+        .process(&conn_id, post("answer1", Some("question")))
+        .await;
+    client
+        .process(&conn_id, post("answer2", Some("question")))
+        .await;
+    client
+        .process(&conn_id, post("orphan", Some("missing")))
+        .await;
+
+    let trending = client
+        .trending_messages(&conn_id, "general", chrono::Duration::minutes(5), 10)
+        .await;
+    let ranked: Vec<(Option<String>, usize)> = trending
+        .into_iter()
+        .map(|(m, count)| (m.id, count))
+        .collect();
+    assert_eq!(ranked, vec![(Some("question".to_string()), 2)]);
+}
+
+#[tokio::test]
+async fn stateclient_bulk_operations() {
+    use oshatori::{
+        client::{BulkOperation, BulkReport},
+        connection::AssetEvent,
+        Asset, AssetSource,
+    };
+
+    let client = StateClient::new();
+    let first = client.track("mock").await;
+    let second = client.track("mock").await;
+    let now = Utc::now();
+    let message = |id: &str, text: &str, age_days: i64| Message {
+        id: Some(id.to_string()),
+        sender_id: Some("user1".to_string()),
+        content: vec![MessageFragment::Text(text.to_string())],
+        timestamp: now - chrono::Duration::days(age_days),
+        message_type: MessageType::Normal,
+        status: MessageStatus::Delivered,
+        reactions: Vec::new(),
+        reply_to: None,
+        thread_id: None,
+        extra: HashMap::new(),
+    };
+
+    for conn_id in [&first, &second] {
+        for (id, text, age) in [("old", "ancient :wave:", 10), ("new", "hi :wave:", 0)] {
+            client
+                .process(
+                    conn_id,
+                    ConnectionEvent::Chat {
+                        event: ChatEvent::New {
+                            scope: Scope::channel("general"),
+                            message: message(id, text, age),
+                        },
+                    },
+                )
+                .await;
+        }
+        client
+            .process(
+                conn_id,
+                ConnectionEvent::User {
+                    event: UserEvent::New {
+                        scope: Scope::channel("general"),
+                        user: Profile {
+                            id: Some("user1".to_string()),
+                            ..Profile::default()
+                        },
+                    },
+                },
+            )
+            .await;
+    }
+
+    let mut steps = Vec::new();
+    let report = client
+        .purge_messages_before(now - chrono::Duration::days(1), |p| steps.push(p))
+        .await;
+    assert_eq!(
+        report,
+        BulkReport {
+            connections: 2,
+            channels: 2,
+            affected: 2,
+        }
+    );
+    assert_eq!(steps.len(), 2);
+    assert!(steps
+        .iter()
+        .all(|p| p.operation == BulkOperation::PurgeMessages && p.total == 2));
+    assert_eq!(steps.last().unwrap().completed, 2);
+    let messages = client.get_messages(&first, "general").await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].id.as_deref(), Some("new"));
+
+    client
+        .process(
+            &first,
+            ConnectionEvent::Asset {
+                event: AssetEvent::New {
+                    scope: Scope::Global,
+                    asset: Asset::Emote {
+                        id: Some("wave".to_string()),
+                        pattern: ":wave:".to_string(),
+                        src: "https://example.com/wave.png".to_string(),
+                        source: AssetSource::Server,
+                    },
+                },
+            },
+        )
+        .await;
+    let report = client.reparse_assets(|_| {}).await;
+    assert_eq!(report.connections, 1);
+    assert_eq!(report.affected, 1);
+    assert_eq!(
+        client.get_messages(&first, "general").await[0].content,
+        vec![
+            MessageFragment::Text("hi ".to_string()),
+            MessageFragment::AssetId("wave".to_string()),
+        ]
+    );
+    assert_eq!(client.reparse_assets(|_| {}).await.affected, 0);
+
+    let report = client.remove_user_everywhere("user1", |_| {}).await;
+    assert_eq!(report.affected, 2);
+    assert!(client.get_user(&second, "user1").await.is_none());
+}
+
+#[tokio::test]
+async fn stateclient_topics() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let mut conn = MockConnection::new();
+    let mut rx = conn.subscribe();
+    assert!(conn.capabilities().topics);
+
+    conn.send(ConnectionEvent::Channel {
+        event: ChannelEvent::TopicChanged {
+            channel_id: "general".to_string(),
+            topic: Some("release day".to_string()),
+            set_by: Some("user1".to_string()),
+        },
+    })
+    .await
+    .unwrap();
+    client.process(&conn_id, rx.recv().await.unwrap()).await;
+
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    assert_eq!(channel.channel.topic.as_deref(), Some("release day"));
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Channel {
+                event: ChannelEvent::TopicChanged {
+                    channel_id: "general".to_string(),
+                    topic: None,
+                    set_by: None,
+                },
+            },
+        )
+        .await;
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    assert_eq!(channel.channel.topic, None);
+}
+
+#[tokio::test]
+async fn stateclient_direct_requests() {
+    use oshatori::client::DirectRequestPolicy;
+
+    fn request(user_id: &str) -> ConnectionEvent {
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Request {
+                channel: Channel {
+                    id: format!("dm-{}", user_id),
+                    name: None,
+                    channel_type: ChannelType::Direct,
+                    topic: None,
+                    extra: HashMap::new(),
+                },
+                from: Profile {
+                    id: Some(user_id.to_string()),
+                    ..Profile::default()
+                },
+            },
+        }
+    }
+
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let mut conn = MockConnection::new();
+    let mut rx = conn.subscribe();
+    let mut replies = client.auto_replies();
+
+    client.process(&conn_id, request("stranger")).await;
+    client.process(&conn_id, request("other")).await;
+    let pending = client.pending_requests(&conn_id).await;
+    assert_eq!(pending.len(), 2);
+    assert_eq!(pending[0].from.id.as_deref(), Some("stranger"));
+    assert!(client.get_channel(&conn_id, "dm-stranger").await.is_none());
+
+    client
+        .accept_request(&conn_id, "dm-stranger", &mut conn)
+        .await
+        .unwrap();
+    assert!(matches!(
+        rx.recv().await,
+        Some(ConnectionEvent::Channel {
+            event: ChannelEvent::Accept { .. }
+        })
+    ));
+    let channel = client.get_channel(&conn_id, "dm-stranger").await.unwrap();
+    assert_eq!(channel.channel.channel_type, ChannelType::Direct);
+
+    client
+        .decline_request(&conn_id, "dm-other", &mut conn)
+        .await
+        .unwrap();
+    assert!(client.pending_requests(&conn_id).await.is_empty());
+    assert!(client.get_channel(&conn_id, "dm-other").await.is_none());
+
+    client
+        .set_direct_request_policy(DirectRequestPolicy {
+            auto_accept_known: true,
+        })
+        .await;
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::User {
+                event: UserEvent::New {
+                    scope: Scope::Global,
+                    user: Profile {
+                        id: Some("friend".to_string()),
+                        ..Profile::default()
+                    },
+                },
+            },
+        )
+        .await;
+    client.process(&conn_id, request("friend")).await;
+    client.process(&conn_id, request("unknown")).await;
+
+    let (reply_conn, reply) = replies.try_recv().unwrap();
+    assert_eq!(reply_conn, conn_id);
+    assert!(matches!(
+        reply,
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Accept { channel_id }
+        } if channel_id == "dm-friend"
+    ));
+    assert!(replies.try_recv().is_err());
+    assert!(client.get_channel(&conn_id, "dm-friend").await.is_some());
+    let pending = client.pending_requests(&conn_id).await;
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].from.id.as_deref(), Some("unknown"));
+}
+
+#[tokio::test]
+async fn stateclient_processes_envelopes() {
+    use oshatori::connection::{stamp, Envelope};
+
+    let client = StateClient::new();
+    client.enable_journal(None).await;
+    let conn_id = client.track("mock").await;
+    let mut conn = MockConnection::new();
+    let (mut envelopes, _task) = stamp(&conn_id, conn.subscribe());
+
+    for channel_id in ["a", "b"] {
+        conn.send(ConnectionEvent::Channel {
+            event: ChannelEvent::Join {
+                channel_id: channel_id.to_string(),
+            },
+        })
+        .await
+        .unwrap();
+    }
+    let first = envelopes.recv().await.unwrap();
+    let second = envelopes.recv().await.unwrap();
+    assert_eq!((first.seq, second.seq), (1, 2));
+    assert_eq!(first.connection_id, conn_id);
+    assert!(first.received_at <= second.received_at);
+
+    client.process_envelope(second).await;
+    client.process_envelope(first.clone()).await;
+    assert!(client.get_channel(&conn_id, "b").await.is_some());
+    assert!(client.get_channel(&conn_id, "a").await.is_none());
+    assert_eq!(
+        client.get_connection(&conn_id).await.unwrap().last_seq,
+        Some(2)
+    );
+
+    let late = Envelope { seq: 3, ..first };
+    let received_at = late.received_at;
+    client.process_envelope(late).await;
+    assert!(client.get_channel(&conn_id, "a").await.is_some());
+    let journal = client.journal(&conn_id).await.unwrap();
+    assert_eq!(journal.entries().last().unwrap().received_at, received_at);
+}
+
+#[tokio::test]
+async fn stateclient_reparses_recent_messages_on_new_assets() {
+    use oshatori::{connection::AssetEvent, Asset, AssetSource};
+
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let mut updates = client.reparsed_messages();
+    assert_eq!(client.asset_reparse().await, None);
+    client.set_asset_reparse(Some(2)).await;
+
+    for (id, text) in [("1", ":wave:"), ("2", "plain"), ("3", "hi :wave:")] {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::Chat {
+                    event: ChatEvent::New {
+                        scope: Scope::channel("general"),
+                        message: Message {
+                            id: Some(id.to_string()),
+                            sender_id: None,
+                            content: vec![MessageFragment::Text(text.to_string())],
+                            timestamp: Utc::now(),
+                            message_type: MessageType::Normal,
+                            status: MessageStatus::Delivered,
+                            reactions: Vec::new(),
+                            reply_to: None,
+                            thread_id: None,
+                            extra: HashMap::new(),
+                        },
+                    },
+                },
+            )
+            .await;
+    }
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Asset {
+                event: AssetEvent::New {
+                    scope: Scope::Global,
+                    asset: Asset::Emote {
+                        id: Some("wave".to_string()),
+                        pattern: ":wave:".to_string(),
+                        src: "https://example.com/wave.png".to_string(),
+                        source: AssetSource::Server,
+                    },
+                },
+            },
+        )
+        .await;
+
+    let (updated_conn, event) = updates.try_recv().unwrap();
+    assert_eq!(updated_conn, conn_id);
+    match event {
+        ConnectionEvent::Chat {
+            event: ChatEvent::Update {
+                scope, message_id, ..
+            },
+        } => {
+            assert_eq!(scope, Scope::channel("general"));
+            assert_eq!(message_id, "3");
+        }
+        other => panic!("expected update, got {:?}", other),
+    }
+    assert!(updates.try_recv().is_err());
+
+    let messages = client.get_messages(&conn_id, "general").await;
+    assert_eq!(
+        messages[0].content,
+        vec![MessageFragment::Text(":wave:".to_string())]
+    );
+    assert_eq!(
+        messages[2].content,
+        vec![
+            MessageFragment::Text("hi ".to_string()),
+            MessageFragment::AssetId("wave".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn stateclient_digests_broadcast_channels() {
+    use oshatori::{client::DigestPolicy, connection::Envelope};
+    use std::time::Duration;
+
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let mut digests = client.digests();
+    client
+        .set_digest_policy(DigestPolicy::new(Duration::from_secs(60)).max_previews(2))
+        .await;
+
+    for (id, channel_type) in [
+        ("news", ChannelType::Broadcast),
+        ("general", ChannelType::Group),
+    ] {
+        let channel = Channel {
+            id: id.to_string(),
+            name: None,
+            channel_type,
+            topic: None,
+            extra: HashMap::new(),
+        };
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::Channel {
+                    event: ChannelEvent::New { channel },
+                },
+            )
+            .await;
+    }
+
+    let start = Utc::now() - chrono::Duration::minutes(10);
+    let mut seq = 0;
+    let mut announce = |channel: &str, n: i64, message_type| {
+        seq += 1;
+        let mut envelope = Envelope::new(
+            &conn_id,
+            seq,
+            ConnectionEvent::Chat {
+                event: ChatEvent::New {
+                    scope: Scope::channel(channel),
+                    message: Message {
+                        id: Some(format!("{}-{}", channel, n)),
+                        sender_id: Some("bot".to_string()),
+                        content: vec![MessageFragment::Text(format!("update {}", n))],
+                        timestamp: start,
+                        message_type,
+                        status: MessageStatus::Delivered,
+                        reactions: Vec::new(),
+                        reply_to: None,
+                        thread_id: None,
+                        extra: HashMap::new(),
+                    },
+                },
+            },
+        );
+        envelope.received_at = start + chrono::Duration::seconds(n * 20);
+        envelope
+    };
+
+    for n in 0..3 {
+        client
+            .process_envelope(announce("news", n, MessageType::Normal))
+            .await;
+        client
+            .process_envelope(announce("general", n, MessageType::Normal))
+            .await;
+    }
+    client
+        .process_envelope(announce("news", 3, MessageType::CurrentUser))
+        .await;
+    assert!(digests.try_recv().is_err());
+    assert!(client.get_digests(&conn_id, "general").await.is_empty());
+    assert_eq!(
+        client
+            .get_channel(&conn_id, "general")
+            .await
+            .unwrap()
+            .messages
+            .len(),
+        3
+    );
+    assert_eq!(
+        client
+            .get_channel(&conn_id, "news")
+            .await
+            .unwrap()
+            .messages
+            .len(),
+        4
+    );
+
+    client
+        .process_envelope(announce("news", 4, MessageType::Normal))
+        .await;
+    let closed = digests.try_recv().unwrap();
+    assert_eq!(closed.connection_id, conn_id);
+    assert_eq!(closed.digest.channel_id, "news");
+    assert_eq!(closed.digest.count, 3);
+    assert_eq!(closed.digest.previews.len(), 2);
+    assert_eq!(
+        closed.digest.ended_at - closed.digest.started_at,
+        chrono::Duration::seconds(40)
+    );
+
+    let stored = client.get_digests(&conn_id, "news").await;
+    assert_eq!(stored.len(), 2);
+    assert_eq!(stored[1].count, 1);
+
+    let flushed = client.flush_digests().await;
+    assert_eq!(flushed.len(), 1);
+    assert_eq!(digests.try_recv().unwrap().digest.count, 1);
+    let channel = client.get_channel(&conn_id, "news").await.unwrap();
+    assert!(channel.pending_digest.is_none());
+    assert_eq!(channel.digests.len(), 2);
+}
+
+#[tokio::test]
+async fn stateclient_ignores_media_per_user() {
+    use oshatori::client::MediaIgnoreList;
+
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    client
+        .set_media_ignores(MediaIgnoreList::new().with_user("spammer"))
+        .await;
+
+    let post = |sender: &str, id: &str| ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("general"),
+            message: Message {
+                id: Some(id.to_string()),
+                sender_id: Some(sender.to_string()),
+                content: vec![
+                    MessageFragment::Text("look".to_string()),
+                    MessageFragment::Image {
+                        url: "https://example.com/cat.png".to_string(),
+                        mime: "image/png".to_string(),
+                    },
+                ],
+                timestamp: Utc::now(),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Delivered,
+                reactions: Vec::new(),
+                reply_to: None,
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    };
+
+    client.process(&conn_id, post("spammer", "1")).await;
+    client.process(&conn_id, post("friend", "2")).await;
+    client.set_media_ignored("spammer", false).await;
+    client.set_media_ignored("friend", true).await;
+    client.process(&conn_id, post("spammer", "3")).await;
+    client.process(&conn_id, post("friend", "4")).await;
+
+    let channel = client.get_channel(&conn_id, "general").await.unwrap();
+    let has_image = |i: usize| {
+        channel.messages[i]
+            .content
+            .iter()
+            .any(|fragment| matches!(fragment, MessageFragment::Image { .. }))
+    };
+    assert!(!has_image(0));
+    assert!(matches!(
+        &channel.messages[0].content[1],
+        MessageFragment::Text(text) if text == "[image: https://example.com/cat.png]"
+    ));
+    assert!(has_image(1));
+    assert!(has_image(2));
+    assert!(!has_image(3));
+
+    let ignores = client.media_ignores().await;
+    assert!(ignores.contains("friend") && !ignores.contains("spammer"));
+    let saved = serde_json::to_string(&ignores).unwrap();
+    assert_eq!(
+        serde_json::from_str::<MediaIgnoreList>(&saved).unwrap(),
+        ignores
+    );
+}
+
+#[tokio::test]
+async fn stateclient_partial_snapshots() {
+    use oshatori::client::{QueryLimits, SnapshotField, SnapshotQuery};
+
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    for id in ["a", "b"] {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::User {
+                    event: UserEvent::New {
+                        scope: Scope::channel("general"),
+                        user: Profile {
+                            id: Some(id.to_string()),
+                            picture: Some(format!("https://example.com/{}.png", id)),
+                            ..Profile::default()
+                        },
+                    },
+                },
+            )
+            .await;
+    }
+    for i in 0..5 {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::Chat {
+                    event: ChatEvent::New {
+                        scope: Scope::channel("general"),
+                        message: Message {
+                            id: Some(format!("m{}", i)),
+                            sender_id: Some("a".to_string()),
+                            content: vec![MessageFragment::Text(format!("message {}", i))],
+                            timestamp: Utc::now(),
+                            message_type: MessageType::Normal,
+                            status: MessageStatus::Delivered,
+                            reactions: Vec::new(),
+                            reply_to: None,
+                            thread_id: None,
+                            extra: HashMap::new(),
+                        },
+                    },
+                },
+            )
+            .await;
+    }
+
+    let query = SnapshotQuery::new()
+        .without(SnapshotField::MessageContent)
+        .without(SnapshotField::UserPictures)
+        .max_messages(3);
+    let snapshot = client
+        .partial_snapshot(&conn_id, "general", query.clone())
+        .await
+        .unwrap();
+    let ids: Vec<_> = snapshot
+        .messages
+        .iter()
+        .map(|m| m.id.clone().unwrap())
+        .collect();
+    assert_eq!(ids, vec!["m2", "m3", "m4"]);
+    assert!(snapshot.messages.iter().all(|m| m.content.is_empty()));
+    assert_eq!(snapshot.users.len(), 2);
+    assert!(snapshot.users.iter().all(|u| u.picture.is_none()));
+
+    client
+        .set_query_limits(QueryLimits {
+            max_messages: Some(1),
+            max_users: Some(1),
+        })
+        .await;
+    let snapshot = client
+        .partial_snapshot(&conn_id, "general", query)
+        .await
+        .unwrap();
+    assert_eq!(snapshot.messages.len(), 1);
+    assert_eq!(snapshot.users.len(), 1);
+
+    let snapshot = client
+        .partial_snapshot(
+            &conn_id,
+            "general",
+            SnapshotQuery::new().without(SnapshotField::Users),
+        )
+        .await
+        .unwrap();
+    assert!(snapshot.users.is_empty());
+    assert_eq!(snapshot.messages[0].content.len(), 1);
+}
+
+#[tokio::test]
+async fn stateclient_runs_lifecycle_hooks() {
+    use oshatori::client::LifecycleHook;
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder {
+        client: Arc<StateClient>,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl LifecycleHook for Recorder {
+        async fn on_connect(&self, connection_id: &str) {
+            let status = self
+                .client
+                .get_connection(connection_id)
+                .await
+                .map(|state| state.status);
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("connect {:?}", status));
+        }
+
+        async fn on_disconnect(&self, _connection_id: &str, reason: Option<&str>) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("disconnect {:?}", reason));
+        }
+
+        async fn on_first_sync_complete(&self, _connection_id: &str) {
+            self.calls.lock().unwrap().push("synced".to_string());
+        }
+
+        async fn on_fatal_error(&self, _connection_id: &str, error: &str) {
+            self.calls.lock().unwrap().push(format!("fatal {}", error));
+        }
+    }
+
+    let client = Arc::new(StateClient::new());
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    client
+        .add_lifecycle_hook(Recorder {
+            client: client.clone(),
+            calls: calls.clone(),
+        })
+        .await;
+    let conn_id = client.track("mock").await;
+
+    for event in [
+        StatusEvent::Connecting,
+        StatusEvent::Connected { artifact: None },
+        StatusEvent::Synced,
+        StatusEvent::Error {
+            message: "hiccup".to_string(),
+        },
+        StatusEvent::Disconnected {
+            artifact: Some("bye".to_string()),
+        },
+        StatusEvent::AuthFailed {
+            reason: "bad token".to_string(),
+        },
+    ] {
+        client
+            .process(&conn_id, ConnectionEvent::Status { event })
+            .await;
+    }
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![
+            "connect Some(Connected)".to_string(),
+            "synced".to_string(),
+            "disconnect Some(\"bye\")".to_string(),
+            "fatal bad token".to_string(),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn stateclient_tracks_maintenance_until_reconnected() {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    let until = Utc::now() + chrono::Duration::minutes(10);
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Status {
+                event: StatusEvent::Maintenance {
+                    message: Some("Maintenance for 10 minutes".to_string()),
+                    scheduled_at: None,
+                    until: Some(until),
+                },
+            },
+        )
+        .await;
+
+    let maintenance = client
+        .get_connection(&conn_id)
+        .await
+        .unwrap()
+        .maintenance
+        .unwrap();
+    assert_eq!(maintenance.until, Some(until));
+    assert!(maintenance.is_pending());
+
+    client
+        .process(
+            &conn_id,
+            ConnectionEvent::Status {
+                event: StatusEvent::Connected { artifact: None },
+            },
+        )
+        .await;
+    assert!(client
+        .get_connection(&conn_id)
+        .await
+        .unwrap()
+        .maintenance
+        .is_none());
+}
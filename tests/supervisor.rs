@@ -0,0 +1,104 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use oshatori::connection::{
+    ConnectionEvent, ConnectionStatus, RestartPolicy, StatusEvent, Supervisor,
+};
+use tokio::sync::mpsc;
+
+fn error_message(event: Option<ConnectionEvent>) -> String {
+    match event {
+        Some(ConnectionEvent::Status {
+            event: StatusEvent::Error { message },
+        }) => message,
+        other => panic!("expected an error status, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn supervisor_reports_panics() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut supervisor = Supervisor::new(tx, RestartPolicy::Never);
+
+    supervisor.spawn("done", async {});
+    supervisor.spawn("reader", async { panic!("socket exploded") });
+
+    let message = error_message(rx.recv().await);
+    assert_eq!(message, "reader task panicked: socket exploded");
+
+    supervisor.shutdown().await;
+    assert!(supervisor.is_empty());
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn supervisor_disconnects_when_essential_task_panics() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut supervisor = Supervisor::new(tx, RestartPolicy::Never);
+    let status = Arc::new(Mutex::new(ConnectionStatus::Connected));
+
+    supervisor.spawn_essential("writer", status.clone(), async {});
+    supervisor.spawn_essential("reader", status.clone(), async {
+        panic!("socket exploded")
+    });
+
+    assert_eq!(
+        error_message(rx.recv().await),
+        "reader task panicked: socket exploded"
+    );
+    match rx.recv().await {
+        Some(ConnectionEvent::Status {
+            event: StatusEvent::Disconnected { artifact },
+        }) => assert_eq!(
+            artifact.as_deref(),
+            Some("reader task panicked: socket exploded")
+        ),
+        other => panic!("expected a disconnect, got {:?}", other),
+    }
+    assert_eq!(*status.lock().unwrap(), ConnectionStatus::Disconnected);
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn supervisor_restarts_within_policy() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut supervisor =
+        Supervisor::new(tx, RestartPolicy::on_failure(2, Duration::from_millis(1)));
+    let runs = Arc::new(AtomicU32::new(0));
+
+    let counter = runs.clone();
+    supervisor.spawn_restartable("refresh", move || {
+        let counter = counter.clone();
+        async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+
+    for _ in 0..3 {
+        assert_eq!(
+            error_message(rx.recv().await),
+            "refresh task exited unexpectedly"
+        );
+    }
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(runs.load(Ordering::SeqCst), 3);
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn supervisor_shutdown_is_silent() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut supervisor =
+        Supervisor::new(tx, RestartPolicy::on_failure(5, Duration::from_millis(1)));
+    supervisor.spawn_restartable("ping", std::future::pending::<()>);
+    assert_eq!(supervisor.len(), 1);
+
+    supervisor.shutdown().await;
+    drop(supervisor);
+    assert!(rx.recv().await.is_none());
+}
@@ -0,0 +1,104 @@
+#![cfg(feature = "sync")]
+
+use chrono::{Duration, Utc};
+use oshatori::{
+    client::{StateClient, SyncBundle, SyncError},
+    connection::{ChatEvent, ConnectionEvent, Scope},
+    Message, MessageFragment, MessageStatus, MessageType,
+};
+use std::collections::HashMap;
+
+async fn seeded_client() -> (StateClient, String) {
+    let client = StateClient::new();
+    let conn_id = client.track("mock").await;
+    for (id, age) in [("old", 10), ("new", 1)] {
+        client
+            .process(
+                &conn_id,
+                ConnectionEvent::Chat {
+                    event: ChatEvent::New {
+                        scope: Scope::channel("general"),
+                        message: Message {
+                            id: Some(id.to_string()),
+                            sender_id: Some("user1".to_string()),
+                            content: vec![MessageFragment::Text(id.to_string())],
+                            timestamp: Utc::now() - Duration::days(age),
+                            message_type: MessageType::Normal,
+                            status: MessageStatus::Delivered,
+                            reactions: Vec::new(),
+                            reply_to: None,
+                            thread_id: None,
+                            extra: HashMap::new(),
+                        },
+                    },
+                },
+            )
+            .await;
+    }
+    (client, conn_id)
+}
+
+#[tokio::test]
+async fn sync_bundle_roundtrip() {
+    let (source, source_id) = seeded_client().await;
+    assert!(
+        source
+            .annotate(&source_id, "general", "new", "triage", "open")
+            .await
+    );
+    let bundle = source
+        .export_bundle(&source_id, &["general"], Utc::now() - Duration::days(3))
+        .await
+        .unwrap();
+    assert_eq!(bundle.channels[0].messages.len(), 1);
+
+    for encrypt in [false, true] {
+        let sealed = bundle.seal(b"secret", encrypt).unwrap();
+        let opened = SyncBundle::open(&sealed, b"secret").unwrap();
+
+        let (target, target_id) = seeded_client().await;
+        assert_eq!(
+            target.import_bundle(&target_id, opened.clone()).await,
+            Ok(0)
+        );
+
+        let fresh = StateClient::new();
+        let fresh_id = fresh.track("mock").await;
+        assert_eq!(fresh.import_bundle(&fresh_id, opened.clone()).await, Ok(1));
+        assert_eq!(fresh.get_messages(&fresh_id, "general").await.len(), 1);
+        assert_eq!(
+            fresh
+                .get_annotations(&fresh_id, "general", "new")
+                .await
+                .get("triage"),
+            Some(&"open".to_string())
+        );
+
+        let other = fresh.track("irc").await;
+        assert!(matches!(
+            fresh.import_bundle(&other, opened).await,
+            Err(SyncError::ProtocolMismatch { .. })
+        ));
+    }
+}
+
+#[tokio::test]
+async fn sync_bundle_rejects_tampering() {
+    let (client, conn_id) = seeded_client().await;
+    let bundle = client
+        .export_bundle(&conn_id, &["general"], Utc::now() - Duration::days(30))
+        .await
+        .unwrap();
+
+    let mut sealed = bundle.seal(b"secret", false).unwrap();
+    assert_eq!(
+        SyncBundle::open(&sealed, b"other").unwrap_err(),
+        SyncError::BadSignature
+    );
+
+    sealed[5] ^= 0xff;
+    assert_eq!(
+        SyncBundle::open(&sealed, b"secret").unwrap_err(),
+        SyncError::BadSignature
+    );
+}
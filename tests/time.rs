@@ -0,0 +1,57 @@
+use chrono::{Duration, FixedOffset, TimeZone, Utc};
+use oshatori::utils::time::{
+    relative_time, ClockStyle, DateOrder, TimeZoneChoice, TimestampCache, TimestampFormat,
+};
+
+#[test]
+fn formats_relative_times() {
+    let now = Utc::now();
+    assert_eq!(relative_time(now - Duration::seconds(3), now), "just now");
+    assert_eq!(relative_time(now - Duration::seconds(45), now), "45s ago");
+    assert_eq!(relative_time(now - Duration::minutes(2), now), "2m ago");
+    assert_eq!(relative_time(now - Duration::hours(5), now), "5h ago");
+    assert_eq!(relative_time(now - Duration::days(3), now), "3d ago");
+    assert_eq!(relative_time(now - Duration::days(15), now), "2w ago");
+    assert_eq!(relative_time(now - Duration::days(800), now), "2y ago");
+    assert_eq!(relative_time(now + Duration::minutes(10), now), "in 10m");
+}
+
+#[test]
+fn formats_for_locales_and_zones() {
+    let timestamp = Utc.with_ymd_and_hms(2024, 3, 9, 15, 4, 5).unwrap();
+    let utc = TimeZoneChoice::Utc;
+
+    let us = TimestampFormat::for_locale("en_US.UTF-8").zone(utc);
+    assert_eq!(us.format(timestamp), "03/09/2024 3:04 PM");
+
+    let de = TimestampFormat::for_locale("de-DE").zone(utc).seconds(true);
+    assert_eq!(de.format(timestamp), "09/03/2024 15:04:05");
+
+    let tokyo = TimestampFormat::new()
+        .zone(TimeZoneChoice::Fixed(
+            FixedOffset::east_opt(9 * 3600).unwrap(),
+        ))
+        .clock(ClockStyle::TwentyFourHour)
+        .date_order(DateOrder::YearMonthDay);
+    assert_eq!(tokyo.format(timestamp), "2024-03-10 00:04");
+    assert_eq!(tokyo.format_date(timestamp), "2024-03-10");
+    assert_eq!(tokyo.format_time(timestamp), "00:04");
+}
+
+#[test]
+fn caches_formatted_timestamps() {
+    let format = TimestampFormat::new().zone(TimeZoneChoice::Utc);
+    let mut cache = TimestampCache::new(format, 2);
+    let first = Utc.with_ymd_and_hms(2024, 1, 1, 8, 0, 0).unwrap();
+
+    assert_eq!(cache.get(first), "2024-01-01 08:00");
+    assert_eq!(cache.get(first), "2024-01-01 08:00");
+    assert_eq!(cache.len(), 1);
+    cache.get(first + Duration::minutes(1));
+    cache.get(first + Duration::minutes(2));
+    assert!(cache.len() <= 2);
+
+    cache.set_format(format.clock(ClockStyle::TwelveHour));
+    assert!(cache.is_empty());
+    assert_eq!(cache.get(first), "2024-01-01 8:00 AM");
+}
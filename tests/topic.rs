@@ -0,0 +1,34 @@
+use oshatori::utils::topic::{parse_topic_announcement, TopicAnnouncement};
+use regex::Regex;
+
+#[test]
+fn parses_topic_announcements() {
+    let pattern = Regex::new(r"^(?P<user>\S+) set the topic to:\s*(?P<topic>.*)$").unwrap();
+    assert_eq!(
+        parse_topic_announcement(&pattern, "ann set the topic to: movie night "),
+        Some(TopicAnnouncement {
+            topic: Some("movie night".to_string()),
+            set_by: Some("ann".to_string()),
+        })
+    );
+    assert_eq!(
+        parse_topic_announcement(&pattern, "ann set the topic to: "),
+        Some(TopicAnnouncement {
+            topic: None,
+            set_by: Some("ann".to_string()),
+        })
+    );
+    assert_eq!(parse_topic_announcement(&pattern, "ann joined"), None);
+}
+
+#[test]
+fn falls_back_to_first_group() {
+    let pattern = Regex::new(r"^Topic: (.+)$").unwrap();
+    assert_eq!(
+        parse_topic_announcement(&pattern, "Topic: rust"),
+        Some(TopicAnnouncement {
+            topic: Some("rust".to_string()),
+            set_by: None,
+        })
+    );
+}
@@ -0,0 +1,282 @@
+#![cfg(feature = "twitch")]
+
+mod common;
+
+use common::{next_event, text_field};
+
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use oshatori::{
+    connection::{
+        AssetEvent, ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, ModerationEvent,
+        Scope, StatusEvent, TwitchConnection, UserEvent,
+    },
+    Asset, Connection, Message, MessageFragment, MessageStatus, MessageType,
+};
+use std::collections::HashMap;
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+/// A fake chat server that answers the login with `greeting`, then sends `script` for every
+/// JOIN and reports every other line it receives on `lines`.
+async fn serve_chat(
+    listener: TcpListener,
+    greeting: &'static str,
+    script: &'static str,
+    lines: mpsc::UnboundedSender<String>,
+) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+    while let Some(Ok(frame)) = ws.next().await {
+        let WsMessage::Text(text) = frame else {
+            continue;
+        };
+        for line in text.lines() {
+            if line.starts_with("NICK ") {
+                ws.send(WsMessage::Text(greeting.into())).await.unwrap();
+            } else if line.starts_with("JOIN ") {
+                ws.send(WsMessage::Text(script.into())).await.unwrap();
+            }
+            let _ = lines.send(line.to_string());
+        }
+    }
+}
+
+#[tokio::test]
+async fn twitch_tags_emotes_and_moderation() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (lines_tx, mut lines) = mpsc::unbounded_channel();
+    tokio::spawn(serve_chat(
+        listener,
+        ":tmi.twitch.tv 001 bot :Welcome, GLHF!\r\n",
+        concat!(
+            ":bot!bot@bot.tmi.twitch.tv JOIN #chan\r\n",
+            "@room-id=42;emote-only=0 :tmi.twitch.tv ROOMSTATE #chan\r\n",
+            "@badges=moderator/1,subscriber/12;color=#1E90FF;display-name=Alice;emotes=25:3-7;",
+            "id=m1;tmi-sent-ts=1700000000000;user-id=7 ",
+            ":alice!alice@alice.tmi.twitch.tv PRIVMSG #chan :hi Kappa!\r\n",
+            "@ban-duration=60 :tmi.twitch.tv CLEARCHAT #chan :alice\r\n",
+        ),
+        lines_tx,
+    ));
+
+    let mut conn = TwitchConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("username", "Bot"),
+        text_field("oauth_token", "secret"),
+        text_field("channels", "Chan"),
+        text_field("irc_url", &format!("ws://127.0.0.1:{}", port)),
+    ])
+    .unwrap();
+    conn.connect().await.unwrap();
+
+    assert!(lines
+        .recv()
+        .await
+        .unwrap()
+        .starts_with("CAP REQ :twitch.tv/tags"));
+    assert_eq!(lines.recv().await.unwrap(), "PASS oauth:secret");
+    assert_eq!(lines.recv().await.unwrap(), "NICK bot");
+    assert_eq!(lines.recv().await.unwrap(), "JOIN #chan");
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::Identify { user_id } } if user_id == "bot"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::New { channel } } if channel.id == "#chan"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::Join { channel_id } } if channel_id == "#chan"
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Channel {
+            event: ChannelEvent::Update { new_channel, .. },
+        } => assert_eq!(new_channel.extra["room_id"], "42"),
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+
+    match next_event(&mut rx).await {
+        ConnectionEvent::User {
+            event: UserEvent::New { scope, user },
+        } => {
+            assert_eq!(scope, Scope::channel("#chan"));
+            assert_eq!(user.id.as_deref(), Some("alice"));
+            assert_eq!(user.display_name.as_deref(), Some("Alice"));
+            assert_eq!(user.color, Some([0x1e, 0x90, 0xff, 0xff]));
+            let role = user.role.unwrap();
+            assert_eq!(role.name.as_deref(), Some("Moderator"));
+            assert!(role.permissions.can_moderate);
+            assert_eq!(user.extra["twitch_id"], "7");
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    match next_event(&mut rx).await {
+        ConnectionEvent::Asset {
+            event: AssetEvent::New { asset, .. },
+        } => match asset {
+            Asset::Emote { id, pattern, .. } => {
+                assert_eq!(id.as_deref(), Some("25"));
+                assert!(regex::Regex::new(&pattern).unwrap().is_match("Kappa"));
+            }
+            other => panic!("unexpected asset {:?}", other),
+        },
+        other => panic!("unexpected event {:?}", other),
+    }
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. },
+        } => {
+            assert_eq!(message.id.as_deref(), Some("m1"));
+            assert_eq!(message.sender_id.as_deref(), Some("alice"));
+            assert_eq!(message.timestamp.timestamp(), 1_700_000_000);
+            assert_eq!(
+                message.content,
+                vec![
+                    MessageFragment::Text("hi ".to_string()),
+                    MessageFragment::AssetId("25".to_string()),
+                    MessageFragment::Text("!".to_string()),
+                ]
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Moderation {
+            event: ModerationEvent::Mute { user_id, until: Some(_), .. }
+        } if user_id == "alice"
+    ));
+
+    conn.send(ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("#chan"),
+            message: Message {
+                id: None,
+                sender_id: None,
+                content: vec![
+                    MessageFragment::Text("back at you ".to_string()),
+                    MessageFragment::AssetId("25".to_string()),
+                ],
+                timestamp: Utc::now(),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: Some("m1".to_string()),
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        lines.recv().await.unwrap(),
+        "@reply-parent-msg-id=m1 PRIVMSG #chan :back at you Kappa"
+    );
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { message, .. }
+        } if message.message_type == MessageType::CurrentUser
+    ));
+}
+
+#[tokio::test]
+async fn twitch_anonymous_reads_only_and_bad_token_fails_auth() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (lines_tx, mut lines) = mpsc::unbounded_channel();
+    tokio::spawn(serve_chat(
+        listener,
+        ":tmi.twitch.tv 001 justinfan12345 :Welcome, GLHF!\r\n",
+        "",
+        lines_tx,
+    ));
+    let mut conn = TwitchConnection::new();
+    let _rx = conn.subscribe();
+    conn.set_auth(vec![text_field(
+        "irc_url",
+        &format!("ws://127.0.0.1:{}", port),
+    )])
+    .unwrap();
+    conn.connect().await.unwrap();
+    lines.recv().await.unwrap();
+    assert_eq!(lines.recv().await.unwrap(), "NICK justinfan12345");
+    let sent = conn
+        .send(ConnectionEvent::Chat {
+            event: ChatEvent::New {
+                scope: Scope::channel("#chan"),
+                message: Message {
+                    id: None,
+                    sender_id: None,
+                    content: vec![MessageFragment::Text("hi".to_string())],
+                    timestamp: Utc::now(),
+                    message_type: MessageType::Normal,
+                    status: MessageStatus::Sent,
+                    reactions: Vec::new(),
+                    reply_to: None,
+                    thread_id: None,
+                    extra: HashMap::new(),
+                },
+            },
+        })
+        .await;
+    assert!(matches!(sent, Err(ConnectionError::Unsupported(_))));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (lines_tx, _lines) = mpsc::unbounded_channel();
+    tokio::spawn(serve_chat(
+        listener,
+        ":tmi.twitch.tv NOTICE * :Login authentication failed\r\n",
+        "",
+        lines_tx,
+    ));
+    let mut conn = TwitchConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("username", "bot"),
+        text_field("oauth_token", "oauth:wrong"),
+        text_field("irc_url", &format!("ws://127.0.0.1:{}", port)),
+    ])
+    .unwrap();
+    assert!(matches!(
+        conn.connect().await,
+        Err(ConnectionError::Auth(_))
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::AuthFailed { .. }
+        }
+    ));
+}
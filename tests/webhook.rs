@@ -0,0 +1,78 @@
+#![cfg(feature = "webhooks")]
+
+mod common;
+
+use common::{chat_message, message_from};
+
+use oshatori::{
+    client::{event_kind, WebhookError, WebhookTemplates},
+    connection::{ConnectionEvent, Scope, StatusEvent, UserEvent},
+};
+use serde_json::json;
+
+#[test]
+fn webhook_templates_render_events() {
+    let typing = ConnectionEvent::User {
+        event: UserEvent::TypingStart {
+            channel_id: "general".to_string(),
+            user_id: "user2".to_string(),
+        },
+    };
+    let connected = ConnectionEvent::Status {
+        event: StatusEvent::Connecting,
+    };
+    assert_eq!(
+        event_kind(&chat_message(
+            Scope::channel("general"),
+            message_from("user1", "hi")
+        )),
+        "chat.new"
+    );
+    assert_eq!(event_kind(&typing), "user.typing_start");
+    assert_eq!(event_kind(&connected), "status.connecting");
+
+    let slack = WebhookTemplates::slack();
+    assert_eq!(
+        slack
+            .render(
+                "conn",
+                &chat_message(
+                    Scope::channel("general"),
+                    message_from("user1", "say \"hi\"")
+                )
+            )
+            .unwrap(),
+        Some(json!({ "text": "*user1* in general: say \"hi\"" }))
+    );
+    assert_eq!(slack.render("conn", &typing).unwrap(), None);
+
+    let templates = WebhookTemplates::new()
+        .with_template(
+            "*",
+            r#"{"kind": "{{kind}}", "connection": "{{connection_id}}", "event": {{{json event}}}}"#,
+        )
+        .unwrap();
+    assert_eq!(
+        templates.render("conn", &typing).unwrap(),
+        Some(json!({
+            "kind": "user.typing_start",
+            "connection": "conn",
+            "event": { "channel_id": "general", "user_id": "user2" },
+        }))
+    );
+
+    assert!(matches!(
+        WebhookTemplates::new().with_template("chat.new", "{{#if}"),
+        Err(WebhookError::Template { .. })
+    ));
+    let broken = WebhookTemplates::new()
+        .with_template("chat.new", "text: {{text}}")
+        .unwrap();
+    assert!(matches!(
+        broken.render(
+            "conn",
+            &chat_message(Scope::channel("general"), message_from("user1", "hi"))
+        ),
+        Err(WebhookError::InvalidJson { .. })
+    ));
+}
@@ -0,0 +1,241 @@
+#![cfg(feature = "websocket")]
+
+mod common;
+
+use common::{next_event, text_field};
+
+use chrono::{TimeZone, Utc};
+use futures_util::{SinkExt, StreamExt};
+use oshatori::{
+    connection::{
+        ChannelEvent, ChatEvent, ConnectionError, ConnectionEvent, GenericWsConnection, JsonPath,
+        Scope, StatusEvent, UserEvent,
+    },
+    Connection, Message, MessageFragment, MessageStatus, MessageType,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+const MAPPING: &str = r#"{
+    "hello": [{ "op": "auth", "token": "{token}" }],
+    "rules": [
+        { "when": { "path": "$.op", "equals": "ready" }, "identify": { "id": "$.me.id" } },
+        {
+            "when": { "path": "$.op", "equals": "room" },
+            "channel": { "id": "$.room.id", "name": "$.room['display name']" }
+        },
+        {
+            "when": { "path": "$.op", "equals": "room" },
+            "each": "$.room.members",
+            "profile": { "id": "$.id", "username": "$.nick", "channel_id": "$.room" }
+        },
+        {
+            "when": { "path": "$.op", "equals": "say" },
+            "message": {
+                "text": "$.data.body",
+                "id": "$.data.id",
+                "sender_id": "$.data.from",
+                "channel_id": "$.data.room",
+                "timestamp": "$.data.at"
+            }
+        }
+    ],
+    "send": { "op": "say", "data": { "room": "{channel_id}", "body": "{text}" } }
+}"#;
+
+/// A fake chat server that wants the token `secret`, then describes the lobby and posts a
+/// message in it. Frames the client says are echoed back from user `me` and reported on `said`.
+async fn serve(listener: TcpListener, said: mpsc::UnboundedSender<Value>) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+    while let Some(Ok(frame)) = ws.next().await {
+        let WsMessage::Text(text) = frame else {
+            continue;
+        };
+        let frame: Value = serde_json::from_str(&text).unwrap();
+        let replies = match frame["op"].as_str() {
+            Some("auth") if frame["token"] == "secret" => vec![
+                json!({ "op": "ready", "me": { "id": "me" } }),
+                json!({ "op": "room", "room": {
+                    "id": "lobby", "display name": "The Lobby",
+                    "members": [{ "id": "u1", "nick": "alice", "room": "lobby" }, { "id": "me" }]
+                } }),
+                json!({ "op": "say", "data": {
+                    "id": 1, "room": "lobby", "from": "u1", "body": "hi", "at": 1_700_000_000
+                } }),
+                json!({ "op": "say", "data": { "body": "no room" } }),
+            ],
+            Some("auth") => vec![json!({ "op": "denied" })],
+            Some("say") => {
+                let _ = said.send(frame.clone());
+                let mut echo = frame;
+                echo["data"]["from"] = json!("me");
+                vec![echo]
+            }
+            _ => Vec::new(),
+        };
+        for reply in replies {
+            ws.send(WsMessage::Text(reply.to_string().into()))
+                .await
+                .unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn websocket_maps_frames_with_the_spec() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let (said_tx, mut said) = mpsc::unbounded_channel();
+    tokio::spawn(serve(listener, said_tx));
+
+    let mut conn = GenericWsConnection::new();
+    let mut rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("url", &url),
+        text_field("mapping", MAPPING),
+        text_field("token", "secret"),
+    ])
+    .unwrap();
+    conn.connect().await.unwrap();
+
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connecting
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Connected { .. }
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Status {
+            event: StatusEvent::Synced
+        }
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::Identify { user_id } } if user_id == "me"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::New { channel } }
+            if channel.id == "lobby" && channel.name.as_deref() == Some("The Lobby")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::Join { channel_id } } if channel_id == "lobby"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::New { scope, user } }
+            if scope == Scope::channel("lobby") && user.username.as_deref() == Some("alice")
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::User { event: UserEvent::New { scope, user } }
+            if scope.is_global() && user.id.as_deref() == Some("me")
+    ));
+    match next_event(&mut rx).await {
+        ConnectionEvent::Chat {
+            event: ChatEvent::New { scope, message },
+        } => {
+            assert_eq!(scope, Scope::channel("lobby"));
+            assert_eq!(message.id.as_deref(), Some("1"));
+            assert_eq!(message.sender_id.as_deref(), Some("u1"));
+            assert_eq!(message.message_type, MessageType::Normal);
+            assert_eq!(
+                message.timestamp,
+                Utc.timestamp_opt(1_700_000_000, 0).unwrap()
+            );
+            assert_eq!(
+                message.content,
+                vec![MessageFragment::Text("hi".to_string())]
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::New { channel } } if channel.id == "default"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Channel { event: ChannelEvent::Join { channel_id } } if channel_id == "default"
+    ));
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat { event: ChatEvent::New { scope, .. } } if scope == Scope::channel("default")
+    ));
+
+    conn.send(ConnectionEvent::Chat {
+        event: ChatEvent::New {
+            scope: Scope::channel("lobby"),
+            message: Message {
+                id: None,
+                sender_id: None,
+                content: vec![MessageFragment::Text("hello \"there\"".to_string())],
+                timestamp: Utc::now(),
+                message_type: MessageType::Normal,
+                status: MessageStatus::Sent,
+                reactions: Vec::new(),
+                reply_to: None,
+                thread_id: None,
+                extra: HashMap::new(),
+            },
+        },
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        said.recv().await.unwrap(),
+        json!({ "op": "say", "data": { "room": "lobby", "body": "hello \"there\"" } })
+    );
+    assert!(matches!(
+        next_event(&mut rx).await,
+        ConnectionEvent::Chat { event: ChatEvent::New { message, .. } }
+            if message.message_type == MessageType::CurrentUser
+    ));
+
+    assert!(matches!(
+        conn.send(ConnectionEvent::Chat {
+            event: ChatEvent::Remove {
+                scope: Scope::channel("lobby"),
+                message_id: "1".to_string(),
+            },
+        })
+        .await,
+        Err(ConnectionError::Unsupported(_))
+    ));
+    conn.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn websocket_rejects_bad_mappings() {
+    assert!("$.a['b c'][2].d".parse::<JsonPath>().is_ok());
+    assert!("a.b".parse::<JsonPath>().is_err());
+    assert!("$.a[x]".parse::<JsonPath>().is_err());
+
+    let mut conn = GenericWsConnection::new();
+    let _rx = conn.subscribe();
+    conn.set_auth(vec![
+        text_field("url", "ws://127.0.0.1:1"),
+        text_field(
+            "mapping",
+            r#"{ "rules": [{ "message": { "text": "body" } }] }"#,
+        ),
+    ])
+    .unwrap();
+    assert!(matches!(
+        conn.connect().await,
+        Err(ConnectionError::Auth(_))
+    ));
+    let report = conn.preflight(false).await;
+    assert!(!report.is_ok());
+}